@@ -0,0 +1,201 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{Package, Repository};
+
+/// The licenses and vendors which packages mustn't have, for [`Repository::audit()`].
+///
+/// - `denied_licenses` - Licenses which mustn't appear in the license of a package. A license expression
+///   such as `MIT and (GPLv2+ or BSD)` matches if any of the licenses in it is denied, or if the whole
+///   expression is.
+/// - `denied_vendors` - Vendors which packages mustn't have, compared exactly.
+#[derive(Clone, Debug, Default)]
+pub struct AuditPolicy {
+    pub denied_licenses: Vec<String>,
+    pub denied_vendors: Vec<String>,
+}
+
+impl AuditPolicy {
+    pub fn deny_license(mut self, license: impl Into<String>) -> Self {
+        self.denied_licenses.push(license.into());
+        self
+    }
+
+    pub fn deny_vendor(mut self, vendor: impl Into<String>) -> Self {
+        self.denied_vendors.push(vendor.into());
+        self
+    }
+
+    /// The first of the licenses in `license` which is denied, if any.
+    fn denied_license<'a>(&self, license: &'a str) -> Option<&'a str> {
+        if self.denied_licenses.iter().any(|denied| denied == license) {
+            return Some(license);
+        }
+        license
+            .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .filter(|token| {
+                !token.is_empty()
+                    && !["and", "or", "with"].contains(&token.to_ascii_lowercase().as_str())
+            })
+            .find(|token| self.denied_licenses.iter().any(|denied| denied == token))
+    }
+}
+
+/// The problems [`Repository::audit()`] looks for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AuditCheck {
+    /// The package has no license
+    MissingLicense,
+    /// The license of the package includes a denied license
+    DeniedLicense,
+    /// The package has no vendor
+    MissingVendor,
+    /// The vendor of the package is denied
+    DeniedVendor,
+}
+
+impl AuditCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditCheck::MissingLicense => "missing-license",
+            AuditCheck::DeniedLicense => "denied-license",
+            AuditCheck::MissingVendor => "missing-vendor",
+            AuditCheck::DeniedVendor => "denied-vendor",
+        }
+    }
+}
+
+/// A problem with the license or vendor of a package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditFinding {
+    pub check: AuditCheck,
+    /// The NEVRA of the package
+    pub package: String,
+    /// The denied license or vendor, empty if it's missing
+    pub value: String,
+}
+
+impl fmt::Display for AuditFinding {
+    /// e.g. `denied-license: foo-0:1.0-1.noarch: GPLv3`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.check.as_str(), self.package)?;
+        if !self.value.is_empty() {
+            write!(f, ": {}", self.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The packages of a repository grouped by license and by vendor, and the problems found by
+/// [`Repository::audit()`], in the order of the packages.
+///
+/// Its `Display` output lists one finding per line followed by a summary. [`AuditReport::to_csv()`]
+/// exports the findings for spreadsheets and other tools.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// The NEVRAs of the packages with each license, with an empty license for packages without one
+    pub licenses: BTreeMap<String, Vec<String>>,
+    /// The NEVRAs of the packages with each vendor, with an empty vendor for packages without one
+    pub vendors: BTreeMap<String, Vec<String>>,
+    pub findings: Vec<AuditFinding>,
+}
+
+impl AuditReport {
+    /// Whether no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn with_check(&self, check: AuditCheck) -> impl Iterator<Item = &AuditFinding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.check == check)
+    }
+
+    /// The findings as CSV with the columns `check`, `package` and `value`, including a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("check,package,value\n");
+        for finding in &self.findings {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                finding.check.as_str(),
+                csv_field(&finding.package),
+                csv_field(&finding.value)
+            ));
+        }
+        csv
+    }
+
+    fn audit_package(&mut self, package: &Package, policy: &AuditPolicy) {
+        let nevra = package.nevra();
+        let mut add = |check, value: &str| {
+            self.findings.push(AuditFinding {
+                check,
+                package: nevra.clone(),
+                value: value.to_owned(),
+            })
+        };
+        let license = package.rpm_license().trim();
+        if license.is_empty() {
+            add(AuditCheck::MissingLicense, "");
+        } else if let Some(denied) = policy.denied_license(license) {
+            add(AuditCheck::DeniedLicense, denied);
+        }
+        let vendor = package.rpm_vendor().trim();
+        if vendor.is_empty() {
+            add(AuditCheck::MissingVendor, "");
+        } else if policy.denied_vendors.iter().any(|denied| denied == vendor) {
+            add(AuditCheck::DeniedVendor, vendor);
+        }
+
+        self.licenses
+            .entry(license.to_owned())
+            .or_default()
+            .push(nevra.clone());
+        self.vendors
+            .entry(vendor.to_owned())
+            .or_default()
+            .push(nevra);
+    }
+}
+
+/// Quote a CSV field if it has to be.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_owned(),
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        write!(
+            f,
+            "{} findings, {} licenses, {} vendors",
+            self.findings.len(),
+            self.licenses.len(),
+            self.vendors.len()
+        )
+    }
+}
+
+impl Repository {
+    /// Group the packages by license and vendor, and find the packages whose license or vendor is
+    /// missing or denied by `policy`.
+    pub fn audit(&self, policy: &AuditPolicy) -> AuditReport {
+        let mut report = AuditReport::default();
+        for package in self.packages().values() {
+            report.audit_package(package, policy);
+        }
+        report
+    }
+}
@@ -0,0 +1,799 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A binary serialization of a [`Repository`], which loads many times faster than parsing its XML, for
+//! services which load the same repositories over and over.
+//!
+//! The file starts with a header giving the version of the format and the checksum of the `repomd.xml` the
+//! repository was loaded from, so that a cached repository is only used for the metadata it was made from.
+//! Strings and lists are written as their length followed by their contents, and integers as little-endian.
+//! The format is only meant as a cache: it changes whenever the types it holds do, and files written by
+//! another version of this library are ignored rather than read.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::logging;
+use crate::{
+    utils, Changelog, Checksum, ChecksumType, FileType, LocalizedText, MetadataError, MetadataType,
+    Package, PackageFile, Pattern, Product, RepomdData, RepomdRecord, Repository, Requirement,
+    UnknownPackageXml, UnknownXml, UpdateCollection, UpdateCollectionModule,
+    UpdateCollectionPackage, UpdateRecord, UpdateReference, EVR,
+};
+
+const MAGIC: &[u8; 8] = b"rpmrepo\0";
+/// The version of the format, to be incremented whenever it changes.
+const FORMAT_VERSION: u32 = 1;
+
+impl Repository {
+    /// Write the repository in the binary format, as loaded from the `repomd.xml` with the checksum
+    /// `repomd_checksum`, see [`Repository::read_binary()`].
+    pub fn write_binary<W: Write>(
+        &self,
+        repomd_checksum: &Checksum,
+        writer: W,
+    ) -> Result<(), MetadataError> {
+        let mut encoder = Encoder {
+            writer: BufWriter::new(writer),
+        };
+        encoder.writer.write_all(MAGIC)?;
+        encoder.u32(FORMAT_VERSION)?;
+        encoder.checksum(repomd_checksum)?;
+
+        encoder.repomd(self.repomd())?;
+        encoder.seq(self.packages().values(), Encoder::package)?;
+        encoder.seq(self.advisories().values(), Encoder::advisory)?;
+        encoder.seq(self.products(), Encoder::product)?;
+        encoder.seq(self.patterns(), Encoder::pattern)?;
+        encoder.writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a repository written by [`Repository::write_binary()`].
+    ///
+    /// Returns `None` if it was written from a `repomd.xml` which doesn't have the checksum `expected`, i.e.
+    /// the repository has changed since, or by a version of this library with another version of the format.
+    /// Fails if it isn't a repository in the binary format at all, or it's truncated or corrupt.
+    pub fn read_binary<R: Read>(
+        mut reader: R,
+        expected: &Checksum,
+    ) -> Result<Option<Repository>, MetadataError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let mut decoder = Decoder { bytes: &bytes };
+        if decoder.take(MAGIC.len())? != MAGIC {
+            return Err(MetadataError::InvalidFieldError(
+                "binary repository header",
+                String::from_utf8_lossy(&bytes[..MAGIC.len()]).into_owned(),
+            ));
+        }
+        let version = decoder.u32()?;
+        if version != FORMAT_VERSION {
+            logging::debug!("ignoring a binary repository of format version {}", version);
+            return Ok(None);
+        }
+        if decoder.checksum()? != *expected {
+            return Ok(None);
+        }
+
+        let mut repository = Repository::new();
+        *repository.repomd_mut() = decoder.repomd()?;
+        for package in decoder.seq(Decoder::package)? {
+            repository
+                .packages_mut()
+                .insert(package.pkgid().to_owned(), package);
+        }
+        for advisory in decoder.seq(Decoder::advisory)? {
+            repository
+                .advisories_mut()
+                .insert(advisory.id.clone(), advisory);
+        }
+        *repository.products_mut() = decoder.seq(Decoder::product)?;
+        *repository.patterns_mut() = decoder.seq(Decoder::pattern)?;
+        if !decoder.bytes.is_empty() {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "{} bytes left over after the binary repository",
+                decoder.bytes.len()
+            )));
+        }
+        Ok(Some(repository))
+    }
+
+    /// Like [`Repository::load_from_directory()`], but reading the repository from the binary file at
+    /// `cache_path` if it was written from the current `repomd.xml` of the repository. Otherwise the XML is
+    /// parsed as usual, and the repository is written to `cache_path` for next time.
+    ///
+    /// A cache file which can't be read is replaced, and failing to write one isn't an error, the
+    /// repository is only loaded more slowly next time.
+    pub fn load_from_directory_cached(
+        path: &Path,
+        cache_path: &Path,
+    ) -> Result<Repository, MetadataError> {
+        let repomd = fs::read(path.join("repodata").join("repomd.xml"))?;
+        let checksum = utils::checksum_bytes(&repomd, ChecksumType::Sha256)?;
+        if cache_path.exists() {
+            let _span = logging::span!("load binary repository {}", cache_path.display());
+            match File::open(cache_path)
+                .map_err(MetadataError::from)
+                .and_then(|file| Repository::read_binary(file, &checksum))
+            {
+                Ok(Some(repository)) => return Ok(repository),
+                Ok(None) => logging::debug!("{} is out of date", cache_path.display()),
+                Err(e) => logging::debug!("ignoring {}: {}", cache_path.display(), e),
+            }
+        }
+
+        let repository = Repository::load_from_directory(path)?;
+        if let Err(e) = write_cache(&repository, &checksum, cache_path) {
+            logging::debug!("failed to write {}: {}", cache_path.display(), e);
+        }
+        Ok(repository)
+    }
+}
+
+/// Write the cache file through a temporary file, so that a cache file is always complete.
+fn write_cache(
+    repository: &Repository,
+    checksum: &Checksum,
+    cache_path: &Path,
+) -> Result<(), MetadataError> {
+    let mut tmp_path = PathBuf::from(cache_path);
+    tmp_path.set_extension("tmp");
+    let result = File::create(&tmp_path)
+        .map_err(MetadataError::from)
+        .and_then(|file| repository.write_binary(checksum, file))
+        .and_then(|_| Ok(fs::rename(&tmp_path, cache_path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+struct Encoder<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> Encoder<W> {
+    fn u8(&mut self, value: u8) -> Result<(), MetadataError> {
+        Ok(self.writer.write_all(&[value])?)
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), MetadataError> {
+        Ok(self.writer.write_all(&value.to_le_bytes())?)
+    }
+
+    fn u64(&mut self, value: u64) -> Result<(), MetadataError> {
+        Ok(self.writer.write_all(&value.to_le_bytes())?)
+    }
+
+    fn bool(&mut self, value: bool) -> Result<(), MetadataError> {
+        self.u8(value as u8)
+    }
+
+    fn len(&mut self, len: usize) -> Result<(), MetadataError> {
+        let len = u32::try_from(len).map_err(|_| {
+            MetadataError::InvalidFieldError("binary repository length", len.to_string())
+        })?;
+        self.u32(len)
+    }
+
+    fn str(&mut self, value: &str) -> Result<(), MetadataError> {
+        self.len(value.len())?;
+        Ok(self.writer.write_all(value.as_bytes())?)
+    }
+
+    fn opt_str(&mut self, value: Option<&str>) -> Result<(), MetadataError> {
+        match value {
+            Some(value) => {
+                self.u8(1)?;
+                self.str(value)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn opt_u64(&mut self, value: Option<u64>) -> Result<(), MetadataError> {
+        match value {
+            Some(value) => {
+                self.u8(1)?;
+                self.u64(value)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn seq<'a, T: 'a>(
+        &mut self,
+        items: impl IntoIterator<Item = &'a T, IntoIter = impl ExactSizeIterator<Item = &'a T>>,
+        mut f: impl FnMut(&mut Self, &'a T) -> Result<(), MetadataError>,
+    ) -> Result<(), MetadataError> {
+        let items = items.into_iter();
+        self.len(items.len())?;
+        for item in items {
+            f(self, item)?;
+        }
+        Ok(())
+    }
+
+    fn checksum(&mut self, checksum: &Checksum) -> Result<(), MetadataError> {
+        match checksum {
+            Checksum::Md5(digest) => self.tagged(0, digest),
+            Checksum::Sha1(digest) => self.tagged(1, digest),
+            Checksum::Sha224(digest) => self.tagged(2, digest),
+            Checksum::Sha256(digest) => self.tagged(3, digest),
+            Checksum::Sha384(digest) => self.tagged(4, digest),
+            Checksum::Sha512(digest) => self.tagged(5, digest),
+            Checksum::Other(name, digest) => {
+                self.tagged(6, name)?;
+                self.str(digest)
+            }
+            Checksum::Unknown(digest) => self.tagged(7, digest),
+            Checksum::Empty => self.u8(8),
+        }
+    }
+
+    fn tagged(&mut self, tag: u8, value: &str) -> Result<(), MetadataError> {
+        self.u8(tag)?;
+        self.str(value)
+    }
+
+    fn opt_checksum(&mut self, checksum: Option<&Checksum>) -> Result<(), MetadataError> {
+        match checksum {
+            Some(checksum) => {
+                self.u8(1)?;
+                self.checksum(checksum)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn evr(&mut self, evr: &EVR) -> Result<(), MetadataError> {
+        self.str(&evr.epoch)?;
+        self.str(&evr.version)?;
+        self.str(&evr.release)
+    }
+
+    fn unknown_xml(&mut self, xml: &UnknownXml) -> Result<(), MetadataError> {
+        self.seq(&xml.attributes, |encoder, (name, value)| {
+            encoder.str(name)?;
+            encoder.str(value)
+        })?;
+        self.seq(&xml.elements, |encoder, element| encoder.str(element))
+    }
+
+    fn repomd(&mut self, repomd: &RepomdData) -> Result<(), MetadataError> {
+        self.opt_str(repomd.revision())?;
+        self.seq(repomd.repo_tags(), |encoder, tag| encoder.str(tag))?;
+        self.seq(repomd.content_tags(), |encoder, tag| encoder.str(tag))?;
+        self.seq(repomd.distro_tags(), |encoder, tag| {
+            encoder.str(&tag.name)?;
+            encoder.opt_str(tag.cpeid.as_deref())
+        })?;
+        self.seq(repomd.records(), Encoder::record)
+    }
+
+    fn record(&mut self, record: &RepomdRecord) -> Result<(), MetadataError> {
+        self.str(record.metadata_type.as_str())?;
+        self.str(&record.location_href.to_string_lossy())?;
+        self.opt_str(record.location_base.as_deref())?;
+        self.u64(record.timestamp as u64)?;
+        self.opt_u64(record.size)?;
+        self.checksum(&record.checksum)?;
+        self.opt_u64(record.open_size)?;
+        self.opt_checksum(record.open_checksum.as_ref())?;
+        self.opt_u64(record.header_size)?;
+        self.opt_checksum(record.header_checksum.as_ref())?;
+        self.opt_u64(record.database_version.map(u64::from))?;
+        self.unknown_xml(&record.unknown_xml)
+    }
+
+    fn requirements(&mut self, requirements: &[Requirement]) -> Result<(), MetadataError> {
+        self.seq(requirements, |encoder, requirement| {
+            encoder.str(&requirement.name)?;
+            encoder.opt_str(requirement.flags.as_deref())?;
+            encoder.opt_str(requirement.epoch.as_deref())?;
+            encoder.opt_str(requirement.version.as_deref())?;
+            encoder.opt_str(requirement.release.as_deref())?;
+            encoder.bool(requirement.preinstall)
+        })
+    }
+
+    fn package(&mut self, package: &Package) -> Result<(), MetadataError> {
+        self.str(&package.name)?;
+        self.str(&package.arch)?;
+        self.evr(&package.evr)?;
+        self.checksum(&package.checksum)?;
+        self.str(&package.location_href)?;
+        self.opt_str(package.location_base.as_deref())?;
+        self.str(&package.summary)?;
+        self.str(&package.description)?;
+        self.str(&package.packager)?;
+        self.str(&package.url)?;
+        self.u64(package.time_file)?;
+        self.u64(package.time_build)?;
+        self.u64(package.size_package)?;
+        self.u64(package.size_installed)?;
+        self.u64(package.size_archive)?;
+
+        self.str(&package.rpm_license)?;
+        self.str(&package.rpm_vendor)?;
+        self.str(&package.rpm_group)?;
+        self.str(&package.rpm_buildhost)?;
+        self.str(&package.rpm_sourcerpm)?;
+        self.u64(package.rpm_header_range.start)?;
+        self.u64(package.rpm_header_range.end)?;
+
+        self.requirements(&package.rpm_requires)?;
+        self.requirements(&package.rpm_provides)?;
+        self.requirements(&package.rpm_conflicts)?;
+        self.requirements(&package.rpm_obsoletes)?;
+        self.requirements(&package.rpm_suggests)?;
+        self.requirements(&package.rpm_enhances)?;
+        self.requirements(&package.rpm_recommends)?;
+        self.requirements(&package.rpm_supplements)?;
+
+        let changelogs = package.load_changelogs()?;
+        self.seq(changelogs.iter(), |encoder, changelog| {
+            encoder.str(&changelog.author)?;
+            encoder.u64(changelog.timestamp)?;
+            encoder.str(&changelog.description)
+        })?;
+        let files = package.load_files();
+        self.seq(files.iter(), |encoder, file| {
+            encoder.u8(match file.filetype {
+                FileType::File => 0,
+                FileType::Dir => 1,
+                FileType::Ghost => 2,
+            })?;
+            encoder.str(&file.path)
+        })?;
+
+        match &package.unknown_xml {
+            Some(xml) => {
+                self.u8(1)?;
+                self.unknown_xml(&xml.primary)?;
+                self.unknown_xml(&xml.format)?;
+                self.unknown_xml(&xml.filelists)?;
+                self.unknown_xml(&xml.other)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn advisory(&mut self, advisory: &UpdateRecord) -> Result<(), MetadataError> {
+        self.str(&advisory.from)?;
+        self.str(&advisory.update_type)?;
+        self.str(&advisory.status)?;
+        self.str(&advisory.version)?;
+        self.str(&advisory.id)?;
+        self.str(&advisory.title)?;
+        self.opt_str(advisory.issued_date.as_deref())?;
+        self.opt_str(advisory.updated_date.as_deref())?;
+        self.str(&advisory.rights)?;
+        self.str(&advisory.release)?;
+        self.opt_str(advisory.pushcount.as_deref())?;
+        self.str(&advisory.severity)?;
+        self.str(&advisory.summary)?;
+        self.str(&advisory.description)?;
+        self.str(&advisory.solution)?;
+
+        self.seq(&advisory.references, |encoder, reference| {
+            encoder.str(&reference.href)?;
+            encoder.str(&reference.id)?;
+            encoder.str(&reference.title)?;
+            encoder.str(&reference.reftype)
+        })?;
+        self.seq(&advisory.pkglist, |encoder, collection| {
+            encoder.str(&collection.name)?;
+            encoder.opt_str(collection.shortname.as_deref())?;
+            encoder.seq(&collection.packages, Encoder::collection_package)?;
+            match &collection.module {
+                Some(module) => {
+                    encoder.u8(1)?;
+                    encoder.str(&module.name)?;
+                    encoder.str(&module.stream)?;
+                    encoder.u64(module.version)?;
+                    encoder.str(&module.context)?;
+                    encoder.str(&module.arch)
+                }
+                None => encoder.u8(0),
+            }
+        })
+    }
+
+    fn collection_package(
+        &mut self,
+        package: &UpdateCollectionPackage,
+    ) -> Result<(), MetadataError> {
+        self.str(&package.epoch)?;
+        self.str(&package.filename)?;
+        self.str(&package.name)?;
+        self.bool(package.reboot_suggested)?;
+        self.bool(package.restart_suggested)?;
+        self.bool(package.relogin_suggested)?;
+        self.str(&package.release)?;
+        self.str(&package.src)?;
+        self.str(&package.arch)?;
+        self.opt_checksum(package.checksum.as_ref())?;
+        self.str(&package.version)
+    }
+
+    fn product(&mut self, product: &Product) -> Result<(), MetadataError> {
+        self.str(&product.vendor)?;
+        self.str(&product.name)?;
+        self.evr(&product.evr)?;
+        self.str(&product.arch)?;
+        self.str(&product.summary)?;
+        self.str(&product.description)
+    }
+
+    fn localized(&mut self, texts: &[LocalizedText]) -> Result<(), MetadataError> {
+        self.seq(texts, |encoder, text| {
+            encoder.opt_str(text.lang.as_deref())?;
+            encoder.str(&text.text)
+        })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Result<(), MetadataError> {
+        self.str(&pattern.name)?;
+        self.evr(&pattern.evr)?;
+        self.str(&pattern.arch)?;
+        self.localized(&pattern.summary)?;
+        self.localized(&pattern.description)?;
+        self.localized(&pattern.category)?;
+        self.bool(pattern.uservisible)?;
+        self.opt_str(pattern.icon.as_deref())?;
+        self.opt_str(pattern.order.as_deref())?;
+
+        self.requirements(&pattern.rpm_provides)?;
+        self.requirements(&pattern.rpm_requires)?;
+        self.requirements(&pattern.rpm_conflicts)?;
+        self.requirements(&pattern.rpm_obsoletes)?;
+        self.requirements(&pattern.rpm_suggests)?;
+        self.requirements(&pattern.rpm_enhances)?;
+        self.requirements(&pattern.rpm_recommends)?;
+        self.requirements(&pattern.rpm_supplements)
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MetadataError> {
+        if len > self.bytes.len() {
+            return Err(MetadataError::InconsistentMetadataError(
+                "the binary repository is truncated".to_owned(),
+            ));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, MetadataError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, MetadataError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, MetadataError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Result<bool, MetadataError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn len(&mut self) -> Result<usize, MetadataError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, MetadataError> {
+        let len = self.len()?;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+    }
+
+    /// Whether an optional value follows.
+    fn is_some(&mut self) -> Result<bool, MetadataError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(invalid_tag(tag)),
+        }
+    }
+
+    fn opt_string(&mut self) -> Result<Option<String>, MetadataError> {
+        match self.is_some()? {
+            true => Ok(Some(self.string()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn opt_u64(&mut self) -> Result<Option<u64>, MetadataError> {
+        match self.is_some()? {
+            true => Ok(Some(self.u64()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn seq<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, MetadataError>,
+    ) -> Result<Vec<T>, MetadataError> {
+        let len = self.len()?;
+        // don't trust the length of a corrupt file with the allocation
+        let mut items = Vec::with_capacity(len.min(self.bytes.len()));
+        for _ in 0..len {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn checksum(&mut self) -> Result<Checksum, MetadataError> {
+        let checksum = match self.u8()? {
+            0 => Checksum::Md5(self.string()?),
+            1 => Checksum::Sha1(self.string()?),
+            2 => Checksum::Sha224(self.string()?),
+            3 => Checksum::Sha256(self.string()?),
+            4 => Checksum::Sha384(self.string()?),
+            5 => Checksum::Sha512(self.string()?),
+            6 => Checksum::Other(self.string()?, self.string()?),
+            7 => Checksum::Unknown(self.string()?),
+            8 => Checksum::Empty,
+            tag => return Err(invalid_tag(tag)),
+        };
+        Ok(checksum)
+    }
+
+    fn opt_checksum(&mut self) -> Result<Option<Checksum>, MetadataError> {
+        match self.is_some()? {
+            true => Ok(Some(self.checksum()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn evr(&mut self) -> Result<EVR, MetadataError> {
+        Ok(EVR::new(self.string()?, self.string()?, self.string()?))
+    }
+
+    fn unknown_xml(&mut self) -> Result<UnknownXml, MetadataError> {
+        Ok(UnknownXml {
+            attributes: self.seq(|decoder| Ok((decoder.string()?, decoder.string()?)))?,
+            elements: self.seq(Decoder::string)?,
+        })
+    }
+
+    fn repomd(&mut self) -> Result<RepomdData, MetadataError> {
+        let mut repomd = RepomdData::default();
+        if let Some(revision) = self.opt_string()? {
+            repomd.set_revision(&revision);
+        }
+        for tag in self.seq(Decoder::string)? {
+            repomd.add_repo_tag(tag);
+        }
+        for tag in self.seq(Decoder::string)? {
+            repomd.add_content_tag(tag);
+        }
+        for (name, cpeid) in self.seq(|decoder| Ok((decoder.string()?, decoder.opt_string()?)))? {
+            repomd.add_distro_tag(name, cpeid);
+        }
+        for record in self.seq(Decoder::record)? {
+            repomd.add_record(record);
+        }
+        Ok(repomd)
+    }
+
+    fn record(&mut self) -> Result<RepomdRecord, MetadataError> {
+        let mut record = RepomdRecord::default();
+        record.metadata_type = MetadataType::from(self.string()?);
+        record.location_href = PathBuf::from(self.string()?);
+        record.location_base = self.opt_string()?;
+        record.timestamp = self.u64()? as i64;
+        record.size = self.opt_u64()?;
+        record.checksum = self.checksum()?;
+        record.open_size = self.opt_u64()?;
+        record.open_checksum = self.opt_checksum()?;
+        record.header_size = self.opt_u64()?;
+        record.header_checksum = self.opt_checksum()?;
+        record.database_version = self.opt_u64()?.map(|version| version as u32);
+        record.unknown_xml = self.unknown_xml()?;
+        Ok(record)
+    }
+
+    fn requirements(&mut self) -> Result<Vec<Requirement>, MetadataError> {
+        self.seq(|decoder| {
+            Ok(Requirement {
+                name: decoder.string()?,
+                flags: decoder.opt_string()?,
+                epoch: decoder.opt_string()?,
+                version: decoder.opt_string()?,
+                release: decoder.opt_string()?,
+                preinstall: decoder.bool()?,
+            })
+        })
+    }
+
+    fn package(&mut self) -> Result<Package, MetadataError> {
+        let mut package = Package {
+            name: self.string()?,
+            arch: self.string()?,
+            evr: self.evr()?,
+            checksum: self.checksum()?,
+            location_href: self.string()?,
+            location_base: self.opt_string()?,
+            summary: self.string()?,
+            description: self.string()?,
+            packager: self.string()?,
+            url: self.string()?,
+            time_file: self.u64()?,
+            time_build: self.u64()?,
+            size_package: self.u64()?,
+            size_installed: self.u64()?,
+            size_archive: self.u64()?,
+            ..Package::default()
+        };
+
+        package.rpm_license = self.string()?;
+        package.rpm_vendor = self.string()?;
+        package.rpm_group = self.string()?;
+        package.rpm_buildhost = self.string()?;
+        package.rpm_sourcerpm = self.string()?;
+        package.rpm_header_range.start = self.u64()?;
+        package.rpm_header_range.end = self.u64()?;
+
+        package.rpm_requires = self.requirements()?;
+        package.rpm_provides = self.requirements()?;
+        package.rpm_conflicts = self.requirements()?;
+        package.rpm_obsoletes = self.requirements()?;
+        package.rpm_suggests = self.requirements()?;
+        package.rpm_enhances = self.requirements()?;
+        package.rpm_recommends = self.requirements()?;
+        package.rpm_supplements = self.requirements()?;
+
+        package.rpm_changelogs = self.seq(|decoder| {
+            Ok(Changelog {
+                author: decoder.string()?,
+                timestamp: decoder.u64()?,
+                description: decoder.string()?,
+            })
+        })?;
+        package.rpm_files = self.seq(|decoder| {
+            let filetype = match decoder.u8()? {
+                0 => FileType::File,
+                1 => FileType::Dir,
+                2 => FileType::Ghost,
+                tag => return Err(invalid_tag(tag)),
+            };
+            Ok(PackageFile {
+                filetype,
+                path: decoder.string()?,
+            })
+        })?;
+
+        if self.is_some()? {
+            package.unknown_xml = Some(Box::new(UnknownPackageXml {
+                primary: self.unknown_xml()?,
+                format: self.unknown_xml()?,
+                filelists: self.unknown_xml()?,
+                other: self.unknown_xml()?,
+            }));
+        }
+        Ok(package)
+    }
+
+    fn advisory(&mut self) -> Result<UpdateRecord, MetadataError> {
+        Ok(UpdateRecord {
+            from: self.string()?,
+            update_type: self.string()?,
+            status: self.string()?,
+            version: self.string()?,
+            id: self.string()?,
+            title: self.string()?,
+            issued_date: self.opt_string()?,
+            updated_date: self.opt_string()?,
+            rights: self.string()?,
+            release: self.string()?,
+            pushcount: self.opt_string()?,
+            severity: self.string()?,
+            summary: self.string()?,
+            description: self.string()?,
+            solution: self.string()?,
+            references: self.seq(|decoder| {
+                Ok(UpdateReference {
+                    href: decoder.string()?,
+                    id: decoder.string()?,
+                    title: decoder.string()?,
+                    reftype: decoder.string()?,
+                })
+            })?,
+            pkglist: self.seq(|decoder| {
+                Ok(UpdateCollection {
+                    name: decoder.string()?,
+                    shortname: decoder.opt_string()?,
+                    packages: decoder.seq(Decoder::collection_package)?,
+                    module: match decoder.is_some()? {
+                        true => Some(UpdateCollectionModule {
+                            name: decoder.string()?,
+                            stream: decoder.string()?,
+                            version: decoder.u64()?,
+                            context: decoder.string()?,
+                            arch: decoder.string()?,
+                        }),
+                        false => None,
+                    },
+                })
+            })?,
+        })
+    }
+
+    fn collection_package(&mut self) -> Result<UpdateCollectionPackage, MetadataError> {
+        Ok(UpdateCollectionPackage {
+            epoch: self.string()?,
+            filename: self.string()?,
+            name: self.string()?,
+            reboot_suggested: self.bool()?,
+            restart_suggested: self.bool()?,
+            relogin_suggested: self.bool()?,
+            release: self.string()?,
+            src: self.string()?,
+            arch: self.string()?,
+            checksum: self.opt_checksum()?,
+            version: self.string()?,
+        })
+    }
+
+    fn product(&mut self) -> Result<Product, MetadataError> {
+        Ok(Product {
+            vendor: self.string()?,
+            name: self.string()?,
+            evr: self.evr()?,
+            arch: self.string()?,
+            summary: self.string()?,
+            description: self.string()?,
+        })
+    }
+
+    fn localized(&mut self) -> Result<Vec<LocalizedText>, MetadataError> {
+        self.seq(|decoder| {
+            Ok(LocalizedText {
+                lang: decoder.opt_string()?,
+                text: decoder.string()?,
+            })
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, MetadataError> {
+        Ok(Pattern {
+            name: self.string()?,
+            evr: self.evr()?,
+            arch: self.string()?,
+            summary: self.localized()?,
+            description: self.localized()?,
+            category: self.localized()?,
+            uservisible: self.bool()?,
+            icon: self.opt_string()?,
+            order: self.opt_string()?,
+
+            rpm_provides: self.requirements()?,
+            rpm_requires: self.requirements()?,
+            rpm_conflicts: self.requirements()?,
+            rpm_obsoletes: self.requirements()?,
+            rpm_suggests: self.requirements()?,
+            rpm_enhances: self.requirements()?,
+            rpm_recommends: self.requirements()?,
+            rpm_supplements: self.requirements()?,
+        })
+    }
+}
+
+fn invalid_tag(tag: u8) -> MetadataError {
+    MetadataError::InvalidFieldError("binary repository tag", tag.to_string())
+}
@@ -0,0 +1,341 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Bundles for carrying repositories across air gaps: tar archives of the files of a repository, with a
+//! manifest listing their sizes and checksums.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::logging;
+use crate::storage::{LocalStorage, Storage, TarStorage, TarWriter};
+use crate::verifier::{verify_files, verify_files_in};
+use crate::{
+    utils, Checksum, ChecksumType, FileCheck, FileStatus, MetadataError, PackageIterator,
+    ParseOptions, Repository, RepositoryReader, VerifyOptions,
+};
+
+/// The name of the manifest in the root of a bundle.
+const MANIFEST: &str = "MANIFEST";
+const MANIFEST_HEADER: &str = "rpmrepo-bundle 1";
+/// The files which are written last on import, so that the repository is never seen half-written
+const REPOMD_FILES: [&str; 3] = [
+    "repodata/repomd.xml",
+    "repodata/repomd.xml.asc",
+    "repodata/repomd.xml.key",
+];
+
+/// Options for [`export_bundle()`] and [`import_bundle()`].
+///
+/// - `checksum_type` - The type of the checksums the manifest of an exported bundle lists.
+/// - `verification` - How the files of a bundle (and those of the destination which an incremental bundle
+///   relies on) are verified on import.
+#[derive(Copy, Clone, Debug)]
+pub struct BundleOptions {
+    pub checksum_type: ChecksumType,
+    pub verification: VerifyOptions,
+}
+
+impl Default for BundleOptions {
+    fn default() -> Self {
+        Self {
+            checksum_type: ChecksumType::Sha256,
+            verification: VerifyOptions::default(),
+        }
+    }
+}
+
+impl BundleOptions {
+    pub fn checksum_type(self, checksum_type: ChecksumType) -> Self {
+        Self {
+            checksum_type,
+            ..self
+        }
+    }
+
+    pub fn verification(self, options: VerifyOptions) -> Self {
+        Self {
+            verification: options,
+            ..self
+        }
+    }
+}
+
+/// The manifest of a bundle: the files it contains, with paths relative to the root of the repository.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BundleManifest {
+    /// Whether packages of an earlier snapshot were left out, in which case they're expected to be in the
+    /// destination already
+    pub incremental: bool,
+    pub files: Vec<FileCheck>,
+}
+
+impl BundleManifest {
+    /// Write the manifest as a `rpmrepo-bundle 1` line, a `full` or `incremental` line, and a
+    /// `<checksum type> <digest> <size> <path>` line for each file.
+    fn write<W: Write>(&self, mut writer: W) -> Result<(), MetadataError> {
+        writeln!(writer, "{}", MANIFEST_HEADER)?;
+        let kind = match self.incremental {
+            true => "incremental",
+            false => "full",
+        };
+        writeln!(writer, "{}", kind)?;
+        for file in &self.files {
+            let (checksum_type, digest) = file.checksum.to_values()?;
+            writeln!(
+                writer,
+                "{} {} {} {}",
+                checksum_type,
+                digest,
+                file.size.unwrap_or_default(),
+                file.path.display()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn read<R: BufRead>(reader: R) -> Result<Self, MetadataError> {
+        let invalid =
+            |line: &str| MetadataError::InvalidFieldError("bundle manifest", line.to_owned());
+        let mut lines = reader.lines();
+        match lines.next().transpose()? {
+            Some(line) if line == MANIFEST_HEADER => (),
+            line => return Err(invalid(&line.unwrap_or_default())),
+        }
+        let incremental = match lines.next().transpose()?.as_deref() {
+            Some("full") => false,
+            Some("incremental") => true,
+            line => return Err(invalid(line.unwrap_or_default())),
+        };
+        let mut files = Vec::new();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.splitn(4, ' ');
+            let (Some(checksum_type), Some(digest), Some(size), Some(path)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid(&line));
+            };
+            let size = size.parse().map_err(|_| invalid(&line))?;
+            files.push(FileCheck::new(
+                path,
+                Checksum::try_create(checksum_type, digest)?,
+                Some(size),
+            ));
+        }
+        Ok(Self { incremental, files })
+    }
+}
+
+/// Write a bundle of the repository at `path` (the directory containing `repodata/`) to `bundle`: a tar
+/// archive of its metadata files and packages, with a manifest of their sizes and checksums.
+///
+/// If `since` is given, the bundle is incremental: packages which are in `since` as well, such as a
+/// snapshot of the repository which was already carried over, are left out. Packages with a
+/// `location_base` are somewhere else, and are never included.
+pub fn export_bundle(
+    path: &Path,
+    bundle: &Path,
+    since: Option<&Repository>,
+    options: BundleOptions,
+) -> Result<BundleManifest, MetadataError> {
+    let _span = logging::span!("export {} to {}", path.display(), bundle.display());
+    let reader = RepositoryReader::new_from_directory(path)?;
+    let repomd = reader.repomd();
+
+    let mut hrefs: Vec<PathBuf> = REPOMD_FILES
+        .iter()
+        .map(PathBuf::from)
+        .filter(|href| path.join(href).is_file())
+        .collect();
+    hrefs.extend(
+        repomd
+            .records()
+            .iter()
+            .map(|record| record.location_href.clone()),
+    );
+    let packages = PackageIterator::from_repodata_selected(
+        Arc::new(LocalStorage),
+        path,
+        repomd,
+        ParseOptions::default(),
+        false,
+        false,
+    )?;
+    let mut skipped = 0;
+    for package in packages {
+        let package = package?;
+        if package.location_base().is_some() {
+            continue;
+        }
+        match since {
+            Some(since) if since.packages().contains_key(package.pkgid()) => skipped += 1,
+            _ => hrefs.push(PathBuf::from(package.location_href())),
+        }
+    }
+    let mut seen = HashSet::new();
+    hrefs.retain(|href| seen.insert(href.clone()));
+
+    let mut manifest = BundleManifest {
+        incremental: since.is_some(),
+        files: Vec::new(),
+    };
+    for href in hrefs {
+        check_href(&href)?;
+        let file = path.join(&href);
+        let size = fs::metadata(&file)?.len();
+        let checksum = utils::checksum_file(&file, options.checksum_type)?;
+        manifest
+            .files
+            .push(FileCheck::new(href, checksum, Some(size)));
+    }
+
+    let mut contents = Vec::new();
+    manifest.write(&mut contents)?;
+    let mut writer = TarWriter::new(BufWriter::new(File::create(bundle)?));
+    writer.append(MANIFEST, contents.len() as u64, &mut contents.as_slice())?;
+    for file in &manifest.files {
+        let name = file.path.to_string_lossy();
+        let size = file.size.unwrap_or_default();
+        writer.append(&name, size, &mut File::open(path.join(&file.path))?)?;
+    }
+    writer.finish()?;
+    logging::debug!(
+        "exported {} files to {}, leaving out {} packages of the snapshot",
+        manifest.files.len(),
+        bundle.display(),
+        skipped
+    );
+    Ok(manifest)
+}
+
+/// Import the bundle `bundle`, written by [`export_bundle()`], into the repository at `dest`.
+///
+/// Every file of the bundle is verified against the manifest before anything is written, and so are the
+/// packages left out of an incremental bundle, which have to be in `dest` already. The files are then
+/// put in place, `repomd.xml` last. Files of `dest` which the bundle has no newer versions of are kept.
+pub fn import_bundle(
+    bundle: &Path,
+    dest: &Path,
+    options: BundleOptions,
+) -> Result<BundleManifest, MetadataError> {
+    let _span = logging::span!("import {} to {}", bundle.display(), dest.display());
+    let storage = Arc::new(TarStorage::open(bundle)?);
+    let manifest = BundleManifest::read(BufReader::new(storage.open(Path::new(MANIFEST))?))?;
+    for file in &manifest.files {
+        check_href(&file.path)?;
+    }
+    let report = verify_files_in(&*storage, &manifest.files, options.verification);
+    if let Some((path, status)) = report.failures().next() {
+        let file = manifest
+            .files
+            .iter()
+            .find(|file| &file.path == path)
+            .unwrap();
+        return Err(failure(file, status, "the bundle"));
+    }
+
+    // the packages left out have to be in the destination already
+    let bundled: HashSet<&Path> = manifest.files.iter().map(|file| &*file.path).collect();
+    let reader = RepositoryReader::new_from_storage(storage.clone(), Path::new(""))?;
+    let missing = reader
+        .repomd()
+        .records()
+        .iter()
+        .find(|record| !bundled.contains(&*record.location_href));
+    if let Some(record) = missing {
+        return Err(MetadataError::InconsistentMetadataError(format!(
+            "{} isn't in the bundle",
+            record.location_href.display()
+        )));
+    }
+    let mut existing = Vec::new();
+    let packages = PackageIterator::from_repodata_selected(
+        storage.clone(),
+        Path::new(""),
+        reader.repomd(),
+        ParseOptions::default(),
+        false,
+        false,
+    )?;
+    for package in packages {
+        let package = package?;
+        let href = Path::new(package.location_href());
+        if package.location_base().is_none() && !bundled.contains(href) {
+            check_href(href)?;
+            existing.push(FileCheck::new(
+                dest.join(href),
+                package.checksum().clone(),
+                Some(package.size_package()),
+            ));
+        }
+    }
+    let report = verify_files(&existing, options.verification);
+    if let Some((path, status)) = report.failures().next() {
+        let file = existing.iter().find(|file| &file.path == path).unwrap();
+        return Err(failure(file, status, &dest.display().to_string()));
+    }
+
+    let (repomd_files, files): (Vec<_>, Vec<_>) = manifest
+        .files
+        .iter()
+        .partition(|file| REPOMD_FILES.iter().any(|name| file.path == Path::new(name)));
+    for file in files.into_iter().chain(repomd_files) {
+        let target = dest.join(&file.path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let part = target.with_file_name(format!(".{}.part", name));
+        io::copy(&mut storage.open(&file.path)?, &mut File::create(&part)?)?;
+        fs::rename(&part, &target)?;
+    }
+    logging::debug!(
+        "imported {} files to {}, reusing {} packages",
+        manifest.files.len(),
+        dest.display(),
+        existing.len()
+    );
+    Ok(manifest)
+}
+
+/// Refuse paths which would end up outside of the repository.
+fn check_href(href: &Path) -> Result<(), MetadataError> {
+    utils::check_href(href, "bundle path")
+}
+
+/// The error for `file` failing verification with `status`, in `location`.
+fn failure(file: &FileCheck, status: &FileStatus, location: &str) -> MetadataError {
+    let path = file.path.display();
+    match status {
+        FileStatus::ChecksumMismatch { actual } => MetadataError::ChecksumMismatchError(
+            path.to_string(),
+            file.checksum
+                .to_values()
+                .map_or_else(|_| String::new(), |(_, d)| d.to_owned()),
+            actual
+                .to_values()
+                .map_or_else(|_| String::new(), |(_, d)| d.to_owned()),
+        ),
+        FileStatus::SizeMismatch { expected, actual } => {
+            MetadataError::InconsistentMetadataError(format!(
+                "{} in {} is {} bytes, expected {}",
+                path, location, actual, expected
+            ))
+        }
+        FileStatus::Missing => MetadataError::InconsistentMetadataError(format!(
+            "{} is missing from {}",
+            path, location
+        )),
+        status => MetadataError::InconsistentMetadataError(format!(
+            "{} in {} can't be verified: {:?}",
+            path, location, status
+        )),
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::logging;
+use crate::{utils, Checksum, ChecksumType, MetadataError, MetadataExpire, RepomdXml, Repository};
+
+/// The file whose modification time is when an entry was last used.
+const USED_FILE: &str = ".used";
+/// The binary serialization of an entry's repository, see [`Repository::write_binary()`].
+const BINARY_FILE: &str = "repository.bin";
+
+/// A cache of the metadata of repositories, like `/var/cache/dnf`, for clients which download the metadata
+/// themselves.
+///
+/// Each repository is kept in a directory of its own named after its ID, which holds its `repodata/` as
+/// it was downloaded, so that it can be read like any other repository. Entries are identified by the
+/// SHA-256 checksum of their `repomd.xml`, the same as [`RepositoryProbe::checksum`](crate::RepositoryProbe),
+/// so a client can find out whether its cached metadata is current without reading anything else.
+///
+/// The repositories loaded from the cache are kept in memory as well, so that getting one again is
+/// immediate as long as it hasn't changed, and in the binary format next to their metadata, so that
+/// loading one again in another process doesn't parse its XML. [`MetadataCache::evict()`] removes the
+/// entries which haven't been used for a while, or the least recently used ones if the cache has grown
+/// too big.
+#[derive(Debug)]
+pub struct MetadataCache {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_age: Option<u64>,
+    // the repositories loaded from the cache, and the checksum of the repomd.xml they were loaded from
+    loaded: HashMap<String, (Checksum, Arc<Repository>)>,
+}
+
+impl MetadataCache {
+    /// Open the cache at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self, MetadataError> {
+        fs::create_dir_all(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            max_size: None,
+            max_age: None,
+            loaded: HashMap::new(),
+        })
+    }
+
+    /// Evict the least recently used entries once the cache is bigger than `bytes`, see
+    /// [`MetadataCache::evict()`].
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Evict the entries which haven't been used for `seconds`, see [`MetadataCache::evict()`].
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The directory of the entry of `repo_id`, containing its `repodata/`. IDs which aren't a single
+    /// file name, or which contain `..`, are refused.
+    pub fn entry_path(&self, repo_id: &str) -> Result<PathBuf, MetadataError> {
+        match is_valid_repo_id(repo_id) {
+            true => Ok(self.path.join(repo_id)),
+            false => Err(MetadataError::InvalidFieldError(
+                "repo ID",
+                repo_id.to_owned(),
+            )),
+        }
+    }
+
+    /// The IDs of the repositories in the cache, sorted.
+    pub fn repo_ids(&self) -> Result<Vec<String>, MetadataError> {
+        let mut repo_ids = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_valid_repo_id(&name) && entry.file_type()?.is_dir() {
+                repo_ids.push(name);
+            }
+        }
+        repo_ids.sort();
+        Ok(repo_ids)
+    }
+
+    /// The checksum of the cached `repomd.xml` of `repo_id`, or `None` if it isn't in the cache.
+    pub fn repomd_checksum(&self, repo_id: &str) -> Result<Option<Checksum>, MetadataError> {
+        let repomd_path = self
+            .entry_path(repo_id)?
+            .join("repodata")
+            .join("repomd.xml");
+        match fs::read(repomd_path) {
+            Ok(repomd) => Ok(Some(utils::checksum_bytes(&repomd, ChecksumType::Sha256)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Copy the metadata of the repository at `source` (the directory containing `repodata/`, e.g. where
+    /// it was just downloaded to) into the cache as `repo_id`, replacing the entry it had. Returns the
+    /// checksum of its `repomd.xml`.
+    ///
+    /// The metadata files are checked against the sizes and checksums `repomd.xml` gives them first, so
+    /// that only complete metadata is cached. Records with a `location_base` are somewhere else, and
+    /// aren't copied, and records whose `location_href` would end up outside of the entry are refused.
+    pub fn store(&mut self, repo_id: &str, source: &Path) -> Result<Checksum, MetadataError> {
+        let entry_path = self.entry_path(repo_id)?;
+        let _span = logging::span!("cache {} as {}", source.display(), repo_id);
+        let repomd = fs::read(source.join("repodata").join("repomd.xml"))?;
+        let data = RepomdXml::read_data(utils::create_xml_reader(&repomd[..]))?;
+        let records: Vec<_> = data
+            .records()
+            .iter()
+            .filter(|record| record.location_base.is_none())
+            .collect();
+        for record in &records {
+            utils::check_href(&record.location_href, "location_href")?;
+            record.verify(source)?;
+        }
+
+        let tmp_path = self.path.join(format!(".{}.tmp", repo_id));
+        if tmp_path.exists() {
+            fs::remove_dir_all(&tmp_path)?;
+        }
+        fs::create_dir_all(tmp_path.join("repodata"))?;
+        for record in records {
+            let target = tmp_path.join(&record.location_href);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(source.join(&record.location_href), target)?;
+        }
+        fs::write(tmp_path.join("repodata").join("repomd.xml"), &repomd)?;
+        touch(&tmp_path.join(USED_FILE))?;
+
+        if entry_path.exists() {
+            fs::remove_dir_all(&entry_path)?;
+        }
+        fs::rename(&tmp_path, &entry_path)?;
+        self.loaded.remove(repo_id);
+        logging::debug!(
+            "cached {} metadata files as {}",
+            data.records().len(),
+            repo_id
+        );
+        utils::checksum_bytes(&repomd, ChecksumType::Sha256)
+    }
+
+    /// The repository `repo_id` from the cache, or `None` if it isn't in the cache or the cached
+    /// `repomd.xml` doesn't have the checksum `expected` (if given), e.g. because the repository has
+    /// changed since it was cached.
+    ///
+    /// An entry which is missing some of its metadata files, or whose files don't have the sizes
+    /// `repomd.xml` gives them, is removed from the cache. The checksums of the files aren't checked
+    /// again, that's done when they're stored.
+    pub fn get(
+        &mut self,
+        repo_id: &str,
+        expected: Option<&Checksum>,
+    ) -> Result<Option<Arc<Repository>>, MetadataError> {
+        let entry_path = self.entry_path(repo_id)?;
+        let Some(checksum) = self.repomd_checksum(repo_id)? else {
+            self.loaded.remove(repo_id);
+            return Ok(None);
+        };
+        if expected.is_some_and(|expected| *expected != checksum) {
+            logging::debug!("the cached metadata of {} is out of date", repo_id);
+            return Ok(None);
+        }
+        touch(&entry_path.join(USED_FILE))?;
+        if let Some((loaded, repository)) = self.loaded.get(repo_id) {
+            if *loaded == checksum {
+                return Ok(Some(repository.clone()));
+            }
+        }
+
+        let _span = logging::span!("load {} from the cache", repo_id);
+        let repository = match load_entry(&entry_path) {
+            Ok(repository) => Arc::new(repository),
+            Err(e) => {
+                logging::debug!("removing the broken cache entry of {}: {}", repo_id, e);
+                self.remove(repo_id)?;
+                return Ok(None);
+            }
+        };
+        self.loaded
+            .insert(repo_id.to_owned(), (checksum, repository.clone()));
+        Ok(Some(repository))
+    }
+
+    /// Whether the cached metadata of `repo_id` has expired according to `expire`, counting from when it
+    /// was stored or last marked as checked with [`MetadataCache::mark_checked()`]. Metadata which isn't
+    /// in the cache has always expired.
+    pub fn is_expired(&self, repo_id: &str, expire: MetadataExpire) -> Result<bool, MetadataError> {
+        let repomd_path = self
+            .entry_path(repo_id)?
+            .join("repodata")
+            .join("repomd.xml");
+        match fs::metadata(repomd_path) {
+            Ok(metadata) => Ok(expire.is_expired_now(metadata.modified()?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Start the expiry of the cached metadata of `repo_id` over, once the repository has been found not
+    /// to have changed (see [`RepositoryProbe::cache_status()`](crate::RepositoryProbe::cache_status)).
+    pub fn mark_checked(&self, repo_id: &str) -> Result<(), MetadataError> {
+        let repomd_path = self
+            .entry_path(repo_id)?
+            .join("repodata")
+            .join("repomd.xml");
+        touch(&repomd_path)
+    }
+
+    /// Remove the entry of `repo_id`, if there is one.
+    pub fn remove(&mut self, repo_id: &str) -> Result<(), MetadataError> {
+        let entry_path = self.entry_path(repo_id)?;
+        self.loaded.remove(repo_id);
+        if entry_path.exists() {
+            fs::remove_dir_all(entry_path)?;
+        }
+        Ok(())
+    }
+
+    /// The total size of the entries, in bytes.
+    pub fn size(&self) -> Result<u64, MetadataError> {
+        let mut size = 0;
+        for repo_id in self.repo_ids()? {
+            size += dir_size(&self.entry_path(&repo_id)?)?;
+        }
+        Ok(size)
+    }
+
+    /// Remove the entries which haven't been used for longer than [`MetadataCache::max_age()`], then the
+    /// least recently used ones until the cache is no bigger than [`MetadataCache::max_size()`]. Returns the
+    /// IDs of the repositories which were removed.
+    pub fn evict(&mut self) -> Result<Vec<String>, MetadataError> {
+        let now = timestamp(SystemTime::now());
+        let mut entries = Vec::new();
+        for repo_id in self.repo_ids()? {
+            let entry_path = self.entry_path(&repo_id)?;
+            let used = match fs::metadata(entry_path.join(USED_FILE)) {
+                Ok(metadata) => timestamp(metadata.modified()?),
+                Err(_) => 0,
+            };
+            entries.push((used, dir_size(&entry_path)?, repo_id));
+        }
+        // the least recently used first
+        entries.sort();
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let mut evicted = Vec::new();
+        for (used, size, repo_id) in entries {
+            let too_old = self
+                .max_age
+                .is_some_and(|age| now.saturating_sub(used) > age);
+            let too_big = self.max_size.is_some_and(|max_size| total > max_size);
+            if too_old || too_big {
+                self.remove(&repo_id)?;
+                total -= size;
+                evicted.push(repo_id);
+            }
+        }
+        if !evicted.is_empty() {
+            logging::debug!("evicted {} from the cache", evicted.join(", "));
+        }
+        Ok(evicted)
+    }
+}
+
+/// Load the repository of a cache entry, after checking that its metadata files have the sizes
+/// `repomd.xml` gives them.
+fn load_entry(path: &Path) -> Result<Repository, MetadataError> {
+    let repomd_path = path.join("repodata").join("repomd.xml");
+    let data = RepomdXml::read_data(utils::xml_reader_from_file(&repomd_path)?)?;
+    for record in data.records() {
+        if record.location_base.is_some() {
+            continue;
+        }
+        utils::check_href(&record.location_href, "location_href")?;
+        let size = fs::metadata(path.join(&record.location_href))?.len();
+        if let Some(expected) = record.size.filter(|expected| *expected != size) {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "{} is {} bytes, but repomd.xml says {}",
+                record.location_href.display(),
+                size,
+                expected
+            )));
+        }
+    }
+    Repository::load_from_directory_cached(path, &path.join(BINARY_FILE))
+}
+
+/// Whether `repo_id` can name an entry: a single file name which isn't hidden (like the temporary
+/// directories of the entries being stored) and doesn't contain `..`.
+fn is_valid_repo_id(repo_id: &str) -> bool {
+    !repo_id.is_empty()
+        && !repo_id.starts_with('.')
+        && !repo_id.contains("..")
+        && !repo_id.contains(['/', '\\', '\0'])
+}
+
+fn dir_size(path: &Path) -> Result<u64, MetadataError> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += match metadata.is_dir() {
+            true => dir_size(&entry.path())?,
+            false => metadata.len(),
+        };
+    }
+    Ok(size)
+}
+
+/// Set the modification time of the file at `path` to now, creating it if it doesn't exist.
+fn touch(path: &Path) -> Result<(), MetadataError> {
+    File::options()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+fn timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,365 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Matching of the capabilities packages provide against dependencies, the way rpm does it.
+//!
+//! A provide satisfies a dependency on a capability of the same name if the ranges of versions they
+//! describe overlap, e.g. `foo = 1.2-1` satisfies `foo >= 1.0`, and `foo >= 2.0` satisfies `foo < 3.0`.
+//! Dependencies on a path (`/usr/bin/sh`) are also satisfied by packages containing the file, and rich
+//! dependencies (`(foo >= 1.0 or bar)`) are evaluated against what is provided.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::paths::PathIndex;
+use crate::{Package, Requirement, EVR};
+
+const LESS: u8 = 1;
+const GREATER: u8 = 1 << 1;
+const EQUAL: u8 = 1 << 2;
+
+/// The comparison operators of a version range, `None` if the flags aren't set or aren't understood.
+fn sense(flags: Option<&str>) -> Option<u8> {
+    match flags? {
+        "EQ" => Some(EQUAL),
+        "LT" => Some(LESS),
+        "LE" => Some(LESS | EQUAL),
+        "GT" => Some(GREATER),
+        "GE" => Some(GREATER | EQUAL),
+        _ => None,
+    }
+}
+
+/// The EVR of a provide or dependency, with the epoch defaulting to `0` as rpm does.
+fn evr(capability: &Requirement) -> Option<EVR> {
+    let version = capability.version.as_deref()?;
+    Some(EVR::new(
+        capability.epoch.as_deref().unwrap_or("0"),
+        version,
+        capability.release.as_deref().unwrap_or(""),
+    ))
+}
+
+/// Whether the version ranges of two capabilities of the same name overlap. A capability without a
+/// version covers every version.
+pub fn ranges_overlap(a: &Requirement, b: &Requirement) -> bool {
+    let (Some(a_sense), Some(b_sense)) = (sense(a.flags.as_deref()), sense(b.flags.as_deref()))
+    else {
+        return true;
+    };
+    let (Some(mut a_evr), Some(mut b_evr)) = (evr(a), evr(b)) else {
+        return true;
+    };
+    // a missing release matches any release
+    if a_evr.release.is_empty() || b_evr.release.is_empty() {
+        a_evr.release.clear();
+        b_evr.release.clear();
+    }
+
+    match a_evr.cmp(&b_evr) {
+        Ordering::Less => a_sense & GREATER != 0 || b_sense & LESS != 0,
+        Ordering::Greater => a_sense & LESS != 0 || b_sense & GREATER != 0,
+        Ordering::Equal => a_sense & b_sense != 0,
+    }
+}
+
+/// Whether `provide` satisfies the dependency `require`: they have the same name and their version ranges
+/// overlap. A rich dependency is satisfied if it's true with `provide` being the only capability
+/// provided.
+pub fn satisfies(provide: &Requirement, require: &Requirement) -> bool {
+    if is_rich(require) {
+        return evaluate(require, |leaf| satisfies(provide, leaf));
+    }
+    provide.name == require.name && ranges_overlap(provide, require)
+}
+
+/// Whether `package` satisfies the dependency `require` with one of its provides or, for a dependency on
+/// a path, one of its files.
+pub fn package_satisfies(package: &Package, require: &Requirement) -> bool {
+    if is_rich(require) {
+        return evaluate(require, |leaf| package_satisfies(package, leaf));
+    }
+    package
+        .provides()
+        .iter()
+        .any(|provide| satisfies(provide, require))
+        || (require.name.starts_with('/') && package.contains_file(&require.name))
+}
+
+/// Whether `require` is a rich (boolean) dependency, e.g. `(foo or bar)`.
+pub fn is_rich(require: &Requirement) -> bool {
+    require.name.starts_with('(')
+}
+
+/// Evaluate a dependency, with `provided` saying whether each of the simple dependencies it's made of is
+/// satisfied. For a simple dependency, that's `provided(require)`.
+///
+/// `with` and `without` are evaluated like `and` and `and not`, without checking that the capabilities
+/// are provided by the same package. A rich dependency which can't be parsed is never satisfied.
+pub fn evaluate(require: &Requirement, mut provided: impl FnMut(&Requirement) -> bool) -> bool {
+    if !is_rich(require) {
+        return provided(require);
+    }
+    match parse_rich(&require.name) {
+        Some(expression) => expression.eval(&mut provided),
+        None => false,
+    }
+}
+
+fn parse_rich(dependency: &str) -> Option<Rich> {
+    let mut parser = RichParser {
+        tokens: tokenize(dependency),
+        pos: 0,
+    };
+    let expression = parser.expression()?;
+    (parser.pos == parser.tokens.len()).then_some(expression)
+}
+
+/// Packages indexed by what they provide, for finding the providers of a capability quickly.
+pub struct Providers<'a> {
+    by_name: HashMap<&'a str, Vec<(&'a Package, &'a Requirement)>>,
+    by_file: PathIndex<'a, &'a Package>,
+}
+
+impl<'a> Providers<'a> {
+    pub fn new(packages: impl IntoIterator<Item = &'a Package>) -> Self {
+        let mut by_name: HashMap<&str, Vec<_>> = HashMap::new();
+        let mut by_file = PathIndex::new();
+        for package in packages {
+            for provide in package.provides() {
+                by_name
+                    .entry(provide.name.as_str())
+                    .or_default()
+                    .push((package, provide));
+            }
+            by_file.add_files(package, package);
+        }
+        Self { by_name, by_file }
+    }
+
+    /// The packages satisfying `require`, in the order they were indexed, each of them once.
+    pub fn what_provides(&self, require: &Requirement) -> Vec<&'a Package> {
+        let mut packages: Vec<&'a Package> = Vec::new();
+        let mut add = |package: &'a Package| {
+            if !packages.iter().any(|p| std::ptr::eq(*p, package)) {
+                packages.push(package);
+            }
+        };
+        if is_rich(require) {
+            let expression = parse_rich(&require.name);
+            let mut leaves = Vec::new();
+            if let Some(expression) = &expression {
+                expression.leaves(&mut leaves);
+            }
+            for leaf in leaves {
+                for package in self.what_provides(leaf) {
+                    if package_satisfies(package, require) {
+                        add(package);
+                    }
+                }
+            }
+        } else {
+            let providers = self.by_name.get(require.name.as_str());
+            for (package, provide) in providers.into_iter().flatten() {
+                if satisfies(provide, require) {
+                    add(package);
+                }
+            }
+            if require.name.starts_with('/') {
+                for package in self.by_file.get(&require.name) {
+                    add(package);
+                }
+            }
+        }
+        packages
+    }
+
+    /// Whether any of the packages satisfies `require`. Unlike [`Providers::what_provides()`], a rich
+    /// dependency may be satisfied by several packages together, e.g. `(foo and bar)`.
+    pub fn is_satisfied(&self, require: &Requirement) -> bool {
+        evaluate(require, |leaf| !self.what_provides(leaf).is_empty())
+    }
+}
+
+enum Rich {
+    Simple(Requirement),
+    And(Vec<Rich>),
+    Or(Vec<Rich>),
+    With(Vec<Rich>),
+    Without(Box<Rich>, Box<Rich>),
+    If(Box<Rich>, Box<Rich>, Option<Box<Rich>>),
+    Unless(Box<Rich>, Box<Rich>, Option<Box<Rich>>),
+}
+
+impl Rich {
+    /// The simple dependencies the expression is made of.
+    fn leaves<'a>(&'a self, leaves: &mut Vec<&'a Requirement>) {
+        match self {
+            Rich::Simple(require) => leaves.push(require),
+            Rich::And(operands) | Rich::Or(operands) | Rich::With(operands) => {
+                for operand in operands {
+                    operand.leaves(leaves);
+                }
+            }
+            Rich::Without(a, b) => {
+                a.leaves(leaves);
+                b.leaves(leaves);
+            }
+            Rich::If(a, b, c) | Rich::Unless(a, b, c) => {
+                a.leaves(leaves);
+                b.leaves(leaves);
+                if let Some(c) = c {
+                    c.leaves(leaves);
+                }
+            }
+        }
+    }
+
+    fn eval(&self, provided: &mut impl FnMut(&Requirement) -> bool) -> bool {
+        match self {
+            Rich::Simple(require) => provided(require),
+            Rich::And(operands) | Rich::With(operands) => {
+                operands.iter().all(|operand| operand.eval(provided))
+            }
+            Rich::Or(operands) => operands.iter().any(|operand| operand.eval(provided)),
+            Rich::Without(a, b) => a.eval(provided) && !b.eval(provided),
+            Rich::If(then, condition, otherwise) => match condition.eval(provided) {
+                true => then.eval(provided),
+                false => otherwise.as_ref().is_none_or(|e| e.eval(provided)),
+            },
+            Rich::Unless(then, condition, otherwise) => match condition.eval(provided) {
+                true => otherwise.as_ref().is_none_or(|e| e.eval(provided)),
+                false => then.eval(provided),
+            },
+        }
+    }
+}
+
+fn tokenize(dependency: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in dependency.char_indices() {
+        if c == '(' || c == ')' || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push(&dependency[start..index]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&dependency[index..index + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&dependency[start..]);
+    }
+    tokens
+}
+
+struct RichParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> RichParser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    /// `( operand [operator operand]... )`
+    fn expression(&mut self) -> Option<Rich> {
+        if self.next()? != "(" {
+            return None;
+        }
+        let first = self.operand()?;
+        let operator = match self.peek()? {
+            ")" => {
+                self.pos += 1;
+                return Some(first);
+            }
+            operator => operator,
+        };
+        self.pos += 1;
+        let second = self.operand()?;
+        let expression = match operator {
+            "and" | "or" | "with" => {
+                let mut operands = vec![first, second];
+                while self.peek()? == operator {
+                    self.pos += 1;
+                    operands.push(self.operand()?);
+                }
+                match operator {
+                    "and" => Rich::And(operands),
+                    "or" => Rich::Or(operands),
+                    _ => Rich::With(operands),
+                }
+            }
+            "without" => Rich::Without(Box::new(first), Box::new(second)),
+            "if" | "unless" => {
+                let otherwise = match self.peek()? {
+                    "else" => {
+                        self.pos += 1;
+                        Some(Box::new(self.operand()?))
+                    }
+                    _ => None,
+                };
+                match operator {
+                    "if" => Rich::If(Box::new(first), Box::new(second), otherwise),
+                    _ => Rich::Unless(Box::new(first), Box::new(second), otherwise),
+                }
+            }
+            _ => return None,
+        };
+        match self.next()? {
+            ")" => Some(expression),
+            _ => None,
+        }
+    }
+
+    /// A nested expression, or a simple dependency such as `foo` or `foo >= 1:2.0-1`.
+    fn operand(&mut self) -> Option<Rich> {
+        if self.peek()? == "(" {
+            return self.expression();
+        }
+        let name = self.next()?;
+        if matches!(
+            name,
+            ")" | "and" | "or" | "with" | "without" | "if" | "unless" | "else"
+        ) {
+            return None;
+        }
+        let flags = match self.peek() {
+            Some("<") => "LT",
+            Some("<=") => "LE",
+            Some("=") | Some("==") => "EQ",
+            Some(">=") => "GE",
+            Some(">") => "GT",
+            _ => {
+                return Some(Rich::Simple(Requirement {
+                    name: name.to_owned(),
+                    ..Requirement::default()
+                }))
+            }
+        };
+        self.pos += 1;
+        let (epoch, version, release) = EVR::parse_values(self.next()?);
+        let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+        Some(Rich::Simple(Requirement {
+            name: name.to_owned(),
+            flags: Some(flags.to_owned()),
+            epoch: non_empty(epoch),
+            version: Some(version.to_owned()),
+            release: non_empty(release),
+            ..Requirement::default()
+        }))
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Changelog descriptions kept out-of-line, in a buffer or a temporary file shared by the packages of a
+//! repository, rather than in a string of their own each.
+//!
+//! The descriptions are usually the bulk of `other.xml` and are rarely read, so moving them out of the
+//! packages cuts the memory a repository uses considerably, especially to a temporary file. The authors
+//! and timestamps of the changelogs stay in [`Package::changelogs()`], with empty descriptions, and
+//! [`Package::load_changelogs()`] reads the descriptions back when they're needed.
+
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{Changelog, MetadataError, Package, Repository};
+
+/// Where the descriptions of the changelogs of packages are kept, see
+/// [`LoadOptions::changelogs()`](crate::LoadOptions::changelogs).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChangelogStorage {
+    /// In the changelogs of each package, as they're parsed
+    #[default]
+    Inline,
+    /// In a single buffer in memory, which saves allocating a string for each of them
+    Buffer,
+    /// In a temporary file, removed once it's no longer used, which keeps them out of memory entirely
+    TempFile,
+}
+
+/// The store of the changelog descriptions of packages, shared by the packages whose changelogs it holds.
+#[derive(Debug)]
+pub struct ChangelogStore {
+    backing: Mutex<Backing>,
+}
+
+#[derive(Debug)]
+enum Backing {
+    Buffer(Vec<u8>),
+    File { file: File, path: PathBuf, len: u64 },
+}
+
+impl ChangelogStore {
+    /// A store for `storage`, or `None` for [`ChangelogStorage::Inline`].
+    pub fn new(storage: ChangelogStorage) -> Result<Option<Arc<Self>>, MetadataError> {
+        let backing = match storage {
+            ChangelogStorage::Inline => return Ok(None),
+            ChangelogStorage::Buffer => Backing::Buffer(Vec::new()),
+            ChangelogStorage::TempFile => {
+                static COUNTER: AtomicUsize = AtomicUsize::new(0);
+                let path = std::env::temp_dir().join(format!(
+                    "rpmrepo-changelogs-{}-{}",
+                    std::process::id(),
+                    COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                Backing::File { file, path, len: 0 }
+            }
+        };
+        Ok(Some(Arc::new(Self {
+            backing: Mutex::new(backing),
+        })))
+    }
+
+    /// The size of the descriptions in the store, in bytes.
+    pub fn size(&self) -> u64 {
+        match &*self.backing.lock().unwrap() {
+            Backing::Buffer(buffer) => buffer.len() as u64,
+            Backing::File { len, .. } => *len,
+        }
+    }
+
+    /// Append `bytes`, returning where they start.
+    fn append(&self, bytes: &[u8]) -> Result<u64, MetadataError> {
+        match &mut *self.backing.lock().unwrap() {
+            Backing::Buffer(buffer) => {
+                let offset = buffer.len() as u64;
+                buffer.extend_from_slice(bytes);
+                Ok(offset)
+            }
+            Backing::File { file, len, .. } => {
+                let offset = *len;
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(bytes)?;
+                *len += bytes.len() as u64;
+                Ok(offset)
+            }
+        }
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, MetadataError> {
+        match &mut *self.backing.lock().unwrap() {
+            Backing::Buffer(buffer) => Ok(buffer[offset as usize..offset as usize + len].to_vec()),
+            Backing::File { file, .. } => {
+                let mut bytes = vec![0; len];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl Drop for ChangelogStore {
+    fn drop(&mut self) {
+        if let Ok(Backing::File { path, .. }) = self.backing.get_mut() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Where the changelog descriptions of a package are in a [`ChangelogStore`].
+///
+/// Two of them are equal if they're the same descriptions of the same store, whatever they contain.
+#[derive(Clone, Debug)]
+pub struct StoredChangelogs {
+    store: Arc<ChangelogStore>,
+    offset: u64,
+    len: usize,
+    // the number of descriptions, those of the first changelogs of the package
+    count: usize,
+}
+
+impl StoredChangelogs {
+    /// The descriptions, in the order of the changelogs.
+    fn descriptions(&self) -> Result<Vec<String>, MetadataError> {
+        let bytes = self.store.read(self.offset, self.len)?;
+        let mut descriptions = Vec::with_capacity(self.count);
+        let mut rest = bytes.as_slice();
+        for _ in 0..self.count {
+            let truncated = || {
+                MetadataError::InconsistentMetadataError(
+                    "the changelog store is truncated".to_owned(),
+                )
+            };
+            let (len, tail) = rest.split_at_checked(4).ok_or_else(truncated)?;
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            let (description, tail) = tail.split_at_checked(len).ok_or_else(truncated)?;
+            descriptions.push(std::str::from_utf8(description)?.to_owned());
+            rest = tail;
+        }
+        Ok(descriptions)
+    }
+}
+
+impl PartialEq for StoredChangelogs {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.store, &other.store) && self.offset == other.offset
+    }
+}
+
+impl Hash for StoredChangelogs {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.offset.hash(state);
+        self.len.hash(state);
+    }
+}
+
+impl Package {
+    /// The changelogs with their descriptions, read from the [`ChangelogStore`] if they're stored
+    /// out-of-line, or else the same as [`Package::changelogs()`].
+    pub fn load_changelogs(&self) -> Result<Cow<'_, [Changelog]>, MetadataError> {
+        let Some(stored) = &self.stored_changelogs else {
+            return Ok(Cow::Borrowed(&self.rpm_changelogs));
+        };
+        let mut changelogs = self.rpm_changelogs.clone();
+        // a description which was set since the changelogs were stored is kept
+        for (changelog, description) in changelogs.iter_mut().zip(stored.descriptions()?) {
+            if changelog.description.is_empty() {
+                changelog.description = description;
+            }
+        }
+        Ok(Cow::Owned(changelogs))
+    }
+
+    /// Move the descriptions of the changelogs to `store`, leaving their authors and timestamps, see
+    /// [`Package::load_changelogs()`].
+    pub fn store_changelogs(&mut self, store: &Arc<ChangelogStore>) -> Result<(), MetadataError> {
+        self.inline_changelogs()?;
+        if self.rpm_changelogs.is_empty() {
+            return Ok(());
+        }
+        let mut bytes = Vec::new();
+        for changelog in &mut self.rpm_changelogs {
+            let description = std::mem::take(&mut changelog.description);
+            let len = u32::try_from(description.len()).map_err(|_| {
+                MetadataError::InvalidFieldError("changelog description", changelog.author.clone())
+            })?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(description.as_bytes());
+        }
+        let offset = store.append(&bytes)?;
+        self.stored_changelogs = Some(StoredChangelogs {
+            store: store.clone(),
+            offset,
+            len: bytes.len(),
+            count: self.rpm_changelogs.len(),
+        });
+        Ok(())
+    }
+
+    /// Move the descriptions of the changelogs back from the [`ChangelogStore`] they were stored in, if
+    /// they were.
+    pub fn inline_changelogs(&mut self) -> Result<(), MetadataError> {
+        if self.stored_changelogs.is_some() {
+            self.rpm_changelogs = self.load_changelogs()?.into_owned();
+            self.stored_changelogs = None;
+        }
+        Ok(())
+    }
+
+    /// Where the descriptions of the changelogs are stored, if they're stored out-of-line.
+    pub fn stored_changelogs(&self) -> Option<&StoredChangelogs> {
+        self.stored_changelogs.as_ref()
+    }
+}
+
+impl Repository {
+    /// Keep the changelog descriptions of the packages according to `storage`, moving them to a new
+    /// [`ChangelogStore`] or back into the packages for [`ChangelogStorage::Inline`].
+    pub fn store_changelogs(&mut self, storage: ChangelogStorage) -> Result<(), MetadataError> {
+        let store = ChangelogStore::new(storage)?;
+        for package in self.packages_mut().values_mut() {
+            match &store {
+                Some(store) => package.store_changelogs(store)?,
+                None => package.inline_changelogs()?,
+            }
+        }
+        Ok(())
+    }
+}
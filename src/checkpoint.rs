@@ -0,0 +1,243 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::logging;
+use crate::{
+    utils, verify_files, Checksum, ChecksumType, FileCheck, LoadOptions, MetadataError, Package,
+    ParseOptions, RepositoryReader, RepositoryWriter, VerifyOptions,
+};
+
+/// The file listing the saved batches of a checkpoint, and the checksums of their `repomd.xml`.
+const INDEX_FILE: &str = "CHECKPOINT";
+const INDEX_HEADER: &str = "rpmrepo-checkpoint 1";
+const DEFAULT_INTERVAL: usize = 1000;
+
+/// The progress of reading a large directory of RPMs, saved so that an interrupted build carries on
+/// where it left off rather than reading every RPM again, see `utils::load_rpm_directory_with_checkpoint()`
+/// (with the `read_rpm` feature).
+///
+/// A checkpoint is a directory. Every [`ScanCheckpoint::interval()`] packages, the packages read since the
+/// last save are written to it as a repository of their own (a batch), which is then listed in its
+/// `CHECKPOINT` file along with the checksum of its `repomd.xml`. A batch is only listed once it's
+/// complete, so an interruption while saving one loses no more than that batch.
+///
+/// Opening a checkpoint validates it: a batch whose `repomd.xml` or metadata files don't match their
+/// checksums is dropped along with every batch after it, and their RPMs are read again. The metadata of
+/// the repository being built isn't checkpointed, as writing it takes a fraction of the time reading the
+/// RPMs does; it's written again from the packages of the checkpoint.
+#[derive(Debug)]
+pub struct ScanCheckpoint {
+    path: PathBuf,
+    interval: usize,
+    // the name of each saved batch, and the checksum of its repomd.xml
+    batches: Vec<(String, Checksum)>,
+    // every package of the checkpoint by location_href, the latest one if an RPM was read again
+    packages: HashMap<String, Package>,
+    // the location_href of the packages added since the last save
+    pending: Vec<String>,
+}
+
+impl ScanCheckpoint {
+    /// Open the checkpoint at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self, MetadataError> {
+        let _span = logging::span!("open checkpoint {}", path.display());
+        fs::create_dir_all(path)?;
+        let index_path = path.join(INDEX_FILE);
+        let listed = if index_path.exists() {
+            read_index(&index_path).unwrap_or_else(|e| {
+                logging::debug!("starting checkpoint {} over: {}", path.display(), e);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        let mut checkpoint = Self {
+            path: path.to_owned(),
+            interval: DEFAULT_INTERVAL,
+            batches: Vec::new(),
+            packages: HashMap::new(),
+            pending: Vec::new(),
+        };
+        for (name, checksum) in listed {
+            match read_batch(&path.join(&name), &checksum) {
+                Ok(packages) => {
+                    for package in packages {
+                        checkpoint
+                            .packages
+                            .insert(package.location_href().to_owned(), package);
+                    }
+                    checkpoint.batches.push((name, checksum));
+                }
+                Err(e) => {
+                    logging::debug!("dropping batch {} and those after it: {}", name, e);
+                    break;
+                }
+            }
+        }
+
+        // remove the batches which were dropped or never finished, so that their names can be reused
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let kept = checkpoint.batches.iter().any(|(batch, _)| batch == &name);
+            if name.starts_with("batch-") && !kept {
+                fs::remove_dir_all(path.join(&name))?;
+            }
+        }
+        checkpoint.write_index()?;
+        logging::debug!(
+            "opened checkpoint {} with {} packages in {} batches",
+            path.display(),
+            checkpoint.packages.len(),
+            checkpoint.batches.len()
+        );
+        Ok(checkpoint)
+    }
+
+    /// Save the checkpoint every `packages` packages added, 1000 by default.
+    pub fn interval(mut self, packages: usize) -> Self {
+        self.interval = packages.max(1);
+        self
+    }
+
+    /// The package of the checkpoint read from the RPM at `location_href`, if there is one.
+    pub fn get(&self, location_href: &str) -> Option<&Package> {
+        self.packages.get(location_href)
+    }
+
+    /// The number of packages in the checkpoint, including those which haven't been saved yet.
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// Add a package which was read, saving the checkpoint if enough have been added since the last save.
+    pub fn add(&mut self, package: Package) -> Result<(), MetadataError> {
+        let location_href = package.location_href().to_owned();
+        self.packages.insert(location_href.clone(), package);
+        self.pending.push(location_href);
+        if self.pending.len() >= self.interval {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Save the packages added since the last save as a new batch.
+    pub fn save(&mut self) -> Result<(), MetadataError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let name = format!("batch-{:06}", self.batches.len());
+        let _span = logging::span!("save checkpoint batch {}", name);
+        let batch_path = self.path.join(&name);
+        let mut writer = RepositoryWriter::new(&batch_path, self.pending.len())?;
+        for location_href in &self.pending {
+            writer.add_package(&self.packages[location_href])?;
+        }
+        writer.finish()?;
+
+        let repomd = fs::read(batch_path.join("repodata").join("repomd.xml"))?;
+        let checksum = utils::checksum_bytes(&repomd, ChecksumType::Sha256)?;
+        self.batches.push((name, checksum));
+        self.write_index()?;
+        logging::debug!("saved {} packages to checkpoint", self.pending.len());
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Remove the checkpoint, once the build it's for is complete.
+    pub fn remove(self) -> Result<(), MetadataError> {
+        fs::remove_dir_all(&self.path)?;
+        Ok(())
+    }
+
+    /// Replace the `CHECKPOINT` file with one listing the saved batches.
+    fn write_index(&self) -> Result<(), MetadataError> {
+        let tmp_path = self.path.join(format!(".{}.tmp", INDEX_FILE));
+        let mut file = File::create(&tmp_path)?;
+        writeln!(file, "{}", INDEX_HEADER)?;
+        for (name, checksum) in &self.batches {
+            let (checksum_type, digest) = checksum.to_values()?;
+            writeln!(file, "{} {} {}", name, checksum_type, digest)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp_path, self.path.join(INDEX_FILE))?;
+        Ok(())
+    }
+}
+
+/// Read the batches listed in the `CHECKPOINT` file at `path`.
+fn read_index(path: &Path) -> Result<Vec<(String, Checksum)>, MetadataError> {
+    let invalid = |line: &str| MetadataError::InvalidFieldError("checkpoint", line.to_owned());
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    match lines.next().transpose()? {
+        Some(line) if line == INDEX_HEADER => (),
+        line => return Err(invalid(&line.unwrap_or_default())),
+    }
+    let mut batches = Vec::new();
+    for line in lines {
+        let line = line?;
+        let mut fields = line.split(' ');
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(checksum_type), Some(digest), None) => {
+                batches.push((
+                    name.to_owned(),
+                    Checksum::try_create(checksum_type, digest)?,
+                ));
+            }
+            _ => return Err(invalid(&line)),
+        }
+    }
+    Ok(batches)
+}
+
+/// Read the packages of the batch at `path`, checking its `repomd.xml` against `checksum` and its metadata
+/// files against `repomd.xml`.
+fn read_batch(path: &Path, checksum: &Checksum) -> Result<Vec<Package>, MetadataError> {
+    let repomd_path = path.join("repodata").join("repomd.xml");
+    let repomd = fs::read(&repomd_path)?;
+    let found = utils::checksum_bytes(&repomd, checksum.checksum_type())?;
+    if &found != checksum {
+        return Err(MetadataError::ChecksumMismatchError(
+            repomd_path.display().to_string(),
+            checksum.to_values()?.1.to_owned(),
+            found.to_values()?.1.to_owned(),
+        ));
+    }
+
+    // written by the checkpoint, and checked against the checksums it recorded
+    let options = LoadOptions::default().parse_options(ParseOptions::default().trusted_input(true));
+    let reader = RepositoryReader::new_from_directory_with_options(path, options)?;
+    let files: Vec<FileCheck> = reader
+        .repomd()
+        .records()
+        .iter()
+        .map(|record| {
+            FileCheck::new(
+                path.join(&record.location_href),
+                record.checksum.clone(),
+                record.size,
+            )
+        })
+        .collect();
+    let report = verify_files(&files, VerifyOptions::default());
+    if let Some((file, status)) = report.failures().next() {
+        return Err(MetadataError::InconsistentMetadataError(format!(
+            "{} is {:?}",
+            file.display(),
+            status
+        )));
+    }
+    reader.iter_packages()?.collect()
+}
@@ -1,5 +1,8 @@
 use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::MetadataError;
 
 /// A full RPM "version" specifier has 3 different components - Epoch, Version, and Release.
 ///
@@ -21,7 +24,7 @@ use std::fmt;
 /// without a caret, e.g. 0.5.0 vs 0.5.0~rc1. Including ^ in a version is used for denoting snapshots
 /// not directly associated with an upstream release and will force it to sort higher, e.g.
 /// 0.5.0 vs 0.5.0^deadbeef
-#[derive(Clone, Debug, Default, Eq, Hash)]
+#[derive(Clone, Debug, Default, Eq)]
 pub struct EVR {
     pub epoch: String,
     pub version: String,
@@ -63,6 +66,28 @@ impl EVR {
     pub fn parse(evr: &str) -> Self {
         EVR::parse_values(evr).into()
     }
+
+    /// Check that the epoch is a number (if it's set) and that the version and release are non-empty and
+    /// free of the characters RPM reserves as separators.
+    pub fn validate(&self) -> Result<(), MetadataError> {
+        let invalid =
+            |reason: &str| MetadataError::InvalidEvrError(self.to_string(), reason.to_owned());
+        if !self.epoch.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("the epoch isn't a number"));
+        }
+        for (field, value) in [("version", &self.version), ("release", &self.release)] {
+            if value.is_empty() {
+                return Err(invalid(&format!("the {} is empty", field)));
+            }
+            if let Some(c) = value
+                .chars()
+                .find(|&c| c == '-' || c == ':' || c.is_whitespace() || c.is_control())
+            {
+                return Err(invalid(&format!("the {} contains {:?}", field, c)));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl From<(&str, &str, &str)> for EVR {
@@ -74,13 +99,26 @@ impl From<(&str, &str, &str)> for EVR {
 impl PartialEq for EVR {
     fn eq(&self, other: &Self) -> bool {
         ((self.epoch == other.epoch)
-            || (self.epoch == "" && other.epoch == "0")
-            || (self.epoch == "0" && other.epoch == ""))
+            || (self.epoch.is_empty() && other.epoch == "0")
+            || (self.epoch == "0" && other.epoch.is_empty()))
             && self.version == other.version
             && self.release == other.release
     }
 }
 
+// an empty epoch is equal to "0", so it has to hash the same
+impl Hash for EVR {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.epoch.as_str() {
+            "" => "0",
+            epoch => epoch,
+        }
+        .hash(state);
+        self.version.hash(state);
+        self.release.hash(state);
+    }
+}
+
 impl fmt::Display for EVR {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if !self.epoch.is_empty() {
@@ -129,8 +167,8 @@ fn compare_version_string(version1: &str, version2: &str) -> Ordering {
         return Ordering::Equal;
     }
 
-    let mut version1_part = version1.clone();
-    let mut version2_part = version2.clone();
+    let mut version1_part = version1;
+    let mut version2_part = version2;
 
     let not_alphanumeric_tilde_or_caret =
         |c: char| !c.is_ascii_alphanumeric() && c != '~' && c != '^';
@@ -164,7 +202,7 @@ fn compare_version_string(version1: &str, version2: &str) -> Ordering {
                 true => return Ordering::Greater,
                 false => return Ordering::Less,
             },
-            (None, Some(_)) => match version1.is_empty() {
+            (None, Some(_)) => match version1_part.is_empty() {
                 true => return Ordering::Less,
                 false => return Ordering::Greater,
             },
@@ -211,7 +249,7 @@ fn compare_version_string(version1: &str, version2: &str) -> Ordering {
                     if ordering != Ordering::Equal {
                         return ordering;
                     }
-                    let ordering = prefix1.cmp(&prefix2);
+                    let ordering = prefix1.cmp(prefix2);
                     if ordering != Ordering::Equal {
                         return ordering;
                     }
@@ -229,7 +267,7 @@ fn compare_version_string(version1: &str, version2: &str) -> Ordering {
                     let (prefix2, version2) = b;
                     version1_part = version1;
                     version2_part = version2;
-                    let ordering = prefix1.cmp(&prefix2);
+                    let ordering = prefix1.cmp(prefix2);
                     if ordering != Ordering::Equal {
                         return ordering;
                     }
@@ -388,6 +426,8 @@ mod test {
         // version comparisons with tilde and caret
         assert_eq!(Ordering::Equal, compare_version_string("1.0^", "1.0^"));
         assert_eq!(Ordering::Greater, compare_version_string("1.0^", "1.0"));
+        assert_eq!(Ordering::Less, compare_version_string("1.0", "1.0^git1"));
+        assert_eq!(Ordering::Greater, compare_version_string("1.0^git1", "1.0"));
         assert_eq!(Ordering::Less, compare_version_string("1.0", "1.0git1^"));
         assert_eq!(
             Ordering::Less,
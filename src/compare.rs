@@ -0,0 +1,468 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::metadata::{METADATA_PRIMARY, METADATA_UPDATEINFO};
+use crate::{
+    MetadataError, Package, PackageFile, RepomdData, RepomdRecord, Repository, UpdateRecord, EVR,
+};
+
+/// Options for comparing repositories with [`Repository::compare()`].
+///
+/// - `ignore_file_order` - Compare the files of a package regardless of the order they're listed in.
+/// - `ignore_timestamps` - Ignore the file times of packages, and the timestamps and revision of `repomd.xml`.
+/// - `ignore_filename_prefixes` - Compare the locations of metadata files without the checksum prefix of
+///   unique filenames, e.g. `repodata/<checksum>-primary.xml.gz` as `repodata/primary.xml.gz`.
+#[derive(Copy, Clone, Debug)]
+pub struct CompareOptions {
+    pub ignore_file_order: bool,
+    pub ignore_timestamps: bool,
+    pub ignore_filename_prefixes: bool,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            ignore_file_order: true,
+            ignore_timestamps: true,
+            ignore_filename_prefixes: true,
+        }
+    }
+}
+
+impl CompareOptions {
+    pub fn ignore_file_order(self, val: bool) -> Self {
+        Self {
+            ignore_file_order: val,
+            ..self
+        }
+    }
+
+    pub fn ignore_timestamps(self, val: bool) -> Self {
+        Self {
+            ignore_timestamps: val,
+            ..self
+        }
+    }
+
+    pub fn ignore_filename_prefixes(self, val: bool) -> Self {
+        Self {
+            ignore_filename_prefixes: val,
+            ..self
+        }
+    }
+}
+
+/// How an entry differs between the repositories being compared.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DifferenceKind {
+    /// The entry is only in the repository `compare()` was called on
+    OnlyInFirst,
+    /// The entry is only in the repository it was compared with
+    OnlyInSecond,
+    /// The entry is in both, with different contents
+    Changed,
+}
+
+impl DifferenceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DifferenceKind::OnlyInFirst => "only-in-first",
+            DifferenceKind::OnlyInSecond => "only-in-second",
+            DifferenceKind::Changed => "changed",
+        }
+    }
+}
+
+/// A difference found by [`Repository::compare()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub kind: DifferenceKind,
+    /// The type of metadata, e.g. `primary` for packages and `repomd` for the records of `repomd.xml`
+    pub metadata: &'static str,
+    /// The package, advisory or record which differs
+    pub entry: String,
+    /// For changed entries, the names of the fields which differ
+    pub fields: Vec<&'static str>,
+}
+
+impl fmt::Display for Difference {
+    /// e.g. `changed: primary: foo-1.0-1.noarch: summary, requires`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.kind.as_str(),
+            self.metadata,
+            self.entry
+        )?;
+        if !self.fields.is_empty() {
+            write!(f, ": {}", self.fields.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The differences found by [`Repository::compare()`]: records of `repomd.xml` first, then packages and
+/// advisories in the order of the first repository, followed by those only in the second.
+///
+/// Its `Display` output lists one difference per line followed by a summary.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepositoryDiff {
+    pub differences: Vec<Difference>,
+}
+
+impl RepositoryDiff {
+    /// Whether the repositories have the same content.
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    fn add(&mut self, kind: DifferenceKind, metadata: &'static str, entry: String) {
+        self.differences.push(Difference {
+            kind,
+            metadata,
+            entry,
+            fields: Vec::new(),
+        });
+    }
+
+    fn add_changed(&mut self, metadata: &'static str, entry: String, fields: Vec<&'static str>) {
+        if !fields.is_empty() {
+            self.differences.push(Difference {
+                kind: DifferenceKind::Changed,
+                metadata,
+                entry,
+                fields,
+            });
+        }
+    }
+}
+
+impl fmt::Display for RepositoryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        write!(f, "{} differences", self.differences.len())
+    }
+}
+
+/// The newest versions of a package in two repositories, found by [`Repository::check_update()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageUpdate {
+    pub name: String,
+    pub arch: String,
+    /// The newest EVR in the base repository, `None` if it has no package of this name and arch
+    pub base: Option<EVR>,
+    /// The newest EVR in the candidate repository, `None` if it has no package of this name and arch
+    pub candidate: Option<EVR>,
+}
+
+impl PackageUpdate {
+    /// Whether the candidate repository has a newer version than the base repository.
+    pub fn is_upgrade(&self) -> bool {
+        match (&self.base, &self.candidate) {
+            (Some(base), Some(candidate)) => candidate > base,
+            _ => false,
+        }
+    }
+
+    /// Whether the candidate repository has an older version than the base repository.
+    pub fn is_downgrade(&self) -> bool {
+        match (&self.base, &self.candidate) {
+            (Some(base), Some(candidate)) => candidate < base,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for PackageUpdate {
+    /// e.g. `foo.x86_64: 0:1.0-1 -> 0:1.1-1`, with `-` for a missing version
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let evr = |evr: &Option<EVR>| match evr {
+            Some(evr) => evr.to_string(),
+            None => "-".to_owned(),
+        };
+        write!(
+            f,
+            "{}.{}: {} -> {}",
+            self.name,
+            self.arch,
+            evr(&self.base),
+            evr(&self.candidate)
+        )
+    }
+}
+
+/// The newest versions of each binary package in `base` and `candidate`, by name and arch.
+pub(crate) fn check_update(base: &Repository, candidate: &Repository) -> Vec<PackageUpdate> {
+    // the newest EVRs in `base` and in `candidate`
+    let mut newest: BTreeMap<(&str, &str), [Option<&EVR>; 2]> = BTreeMap::new();
+    for (index, repository) in [base, candidate].into_iter().enumerate() {
+        for package in repository.packages().values() {
+            if package.arch() == "src" || package.arch() == "nosrc" {
+                continue;
+            }
+            let evr = &mut newest.entry((package.name(), package.arch())).or_default()[index];
+            if evr.is_none_or(|evr| package.evr() > evr) {
+                *evr = Some(package.evr());
+            }
+        }
+    }
+    newest
+        .into_iter()
+        .map(|((name, arch), [base, candidate])| PackageUpdate {
+            name: name.to_owned(),
+            arch: arch.to_owned(),
+            base: base.cloned(),
+            candidate: candidate.cloned(),
+        })
+        .collect()
+}
+
+/// The names of the `$field`s which are different in `$a` and `$b`.
+macro_rules! differing_fields {
+    ($a:expr, $b:expr, [$($field:ident),* $(,)?]) => {{
+        let mut fields = Vec::new();
+        $(
+            if $a.$field != $b.$field {
+                fields.push(stringify!($field));
+            }
+        )*
+        fields
+    }};
+}
+
+pub(crate) fn compare_repositories(
+    first: &Repository,
+    second: &Repository,
+    options: CompareOptions,
+) -> Result<RepositoryDiff, MetadataError> {
+    let mut diff = RepositoryDiff::default();
+    compare_repomd(first.repomd(), second.repomd(), options, &mut diff);
+
+    for (pkgid, package) in first.packages() {
+        match second.packages().get(pkgid) {
+            Some(other) => diff.add_changed(
+                METADATA_PRIMARY,
+                package.nevra(),
+                package_differences(package, other, options)?,
+            ),
+            None => diff.add(
+                DifferenceKind::OnlyInFirst,
+                METADATA_PRIMARY,
+                package.nevra(),
+            ),
+        }
+    }
+    for (pkgid, package) in second.packages() {
+        if !first.packages().contains_key(pkgid) {
+            diff.add(
+                DifferenceKind::OnlyInSecond,
+                METADATA_PRIMARY,
+                package.nevra(),
+            );
+        }
+    }
+
+    for (id, advisory) in first.advisories() {
+        match second.advisories().get(id) {
+            Some(other) => diff.add_changed(
+                METADATA_UPDATEINFO,
+                id.clone(),
+                advisory_differences(advisory, other),
+            ),
+            None => diff.add(DifferenceKind::OnlyInFirst, METADATA_UPDATEINFO, id.clone()),
+        }
+    }
+    for id in second.advisories().keys() {
+        if !first.advisories().contains_key(id) {
+            diff.add(
+                DifferenceKind::OnlyInSecond,
+                METADATA_UPDATEINFO,
+                id.clone(),
+            );
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Compare the records of `repomd.xml` by their type and location, and the tags. The checksums and sizes of
+/// the metadata files aren't compared, since writing the same content differently changes them.
+fn compare_repomd(
+    first: &RepomdData,
+    second: &RepomdData,
+    options: CompareOptions,
+    diff: &mut RepositoryDiff,
+) {
+    const REPOMD: &str = "repomd";
+    let location = |record: &RepomdRecord| {
+        let href = record.location_href.to_string_lossy().into_owned();
+        match options.ignore_filename_prefixes {
+            true => strip_checksum_prefix(&href),
+            false => href,
+        }
+    };
+
+    let mut fields = Vec::new();
+    if !options.ignore_timestamps && first.revision() != second.revision() {
+        fields.push("revision");
+    }
+    if first.repo_tags() != second.repo_tags() {
+        fields.push("repo_tags");
+    }
+    if first.content_tags() != second.content_tags() {
+        fields.push("content_tags");
+    }
+    if first.distro_tags() != second.distro_tags() {
+        fields.push("distro_tags");
+    }
+    diff.add_changed(REPOMD, "repomd.xml".to_owned(), fields);
+
+    for record in first.records() {
+        let name = record.metadata_type.as_str().to_owned();
+        match second.get_record(&name) {
+            Some(other) => {
+                let mut fields = Vec::new();
+                if location(record) != location(other) {
+                    fields.push("location_href");
+                }
+                if record.location_base != other.location_base {
+                    fields.push("location_base");
+                }
+                if !options.ignore_timestamps && record.timestamp != other.timestamp {
+                    fields.push("timestamp");
+                }
+                diff.add_changed(REPOMD, name, fields);
+            }
+            None => diff.add(DifferenceKind::OnlyInFirst, REPOMD, name),
+        }
+    }
+    for record in second.records() {
+        let name = record.metadata_type.as_str();
+        if first.get_record(name).is_none() {
+            diff.add(DifferenceKind::OnlyInSecond, REPOMD, name.to_owned());
+        }
+    }
+}
+
+/// `repodata/primary.xml.gz` for `repodata/<hex digest>-primary.xml.gz`, other locations as they are.
+fn strip_checksum_prefix(href: &str) -> String {
+    let (dir, file_name) = match href.rsplit_once('/') {
+        Some((dir, file_name)) => (Some(dir), file_name),
+        None => (None, href),
+    };
+    let file_name = match file_name.split_once('-') {
+        Some((digest, rest))
+            if digest.len() >= 32 && digest.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            rest
+        }
+        _ => file_name,
+    };
+    match dir {
+        Some(dir) => format!("{}/{}", dir, file_name),
+        None => file_name.to_owned(),
+    }
+}
+
+fn package_differences(
+    first: &Package,
+    second: &Package,
+    options: CompareOptions,
+) -> Result<Vec<&'static str>, MetadataError> {
+    let mut fields = differing_fields!(
+        first,
+        second,
+        [
+            name,
+            arch,
+            evr,
+            checksum,
+            location_href,
+            location_base,
+            summary,
+            description,
+            packager,
+            url,
+            time_build,
+            size_package,
+            size_installed,
+            size_archive,
+            rpm_license,
+            rpm_vendor,
+            rpm_group,
+            rpm_buildhost,
+            rpm_sourcerpm,
+            rpm_header_range,
+            rpm_requires,
+            rpm_provides,
+            rpm_conflicts,
+            rpm_obsoletes,
+            rpm_suggests,
+            rpm_enhances,
+            rpm_recommends,
+            rpm_supplements,
+        ]
+    );
+    // with the descriptions of changelogs stored out-of-line
+    if first.load_changelogs()? != second.load_changelogs()? {
+        fields.push("rpm_changelogs");
+    }
+    if !options.ignore_timestamps && first.time_file != second.time_file {
+        fields.push("time_file");
+    }
+    let (first_files, second_files) = (first.load_files(), second.load_files());
+    let files_equal = match options.ignore_file_order {
+        true => {
+            first_files.len() == second_files.len()
+                && file_set(&first_files) == file_set(&second_files)
+        }
+        false => first_files == second_files,
+    };
+    if !files_equal {
+        fields.push("rpm_files");
+    }
+    Ok(fields)
+}
+
+/// The type and path of each of `files`.
+fn file_set(files: &[PackageFile]) -> BTreeSet<(&[u8], &str)> {
+    files
+        .iter()
+        .map(|file| (file.filetype.to_values(), file.path.as_str()))
+        .collect()
+}
+
+fn advisory_differences(first: &UpdateRecord, second: &UpdateRecord) -> Vec<&'static str> {
+    differing_fields!(
+        first,
+        second,
+        [
+            from,
+            update_type,
+            status,
+            version,
+            id,
+            title,
+            issued_date,
+            updated_date,
+            rights,
+            release,
+            pushcount,
+            severity,
+            summary,
+            description,
+            solution,
+            references,
+            pkglist,
+        ]
+    )
+}
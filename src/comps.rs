@@ -1,158 +1,809 @@
-<?xml encoding="UTF-8"?>
-
-<!ELEMENT comps (group+,environment+,category+,langpacks?)>
-<!ATTLIST comps xmlns CDATA #FIXED ''>
-
-<!ELEMENT group (id,name,description,default,uservisible,langonly?, packagelist)>
-<!ATTLIST group xmlns CDATA #FIXED ''>
-
-<!ELEMENT environment (id,name,description,display_order?,grouplist,optionlist)>
-<!ATTLIST environment xmlns CDATA #FIXED ''>
-
-<!ELEMENT category (id,name,description,display_order?,grouplist)>
-<!ATTLIST category xmlns CDATA #FIXED ''>
-
-<!ELEMENT default (#PCDATA)>
-<!ATTLIST default xmlns CDATA #FIXED ''>
-
-<!ELEMENT uservisible (#PCDATA)>
-<!ATTLIST uservisible xmlns CDATA #FIXED ''>
-
-<!ELEMENT langonly (#PCDATA)>
-<!ATTLIST langonly xmlns CDATA #FIXED ''>
-
-<!ELEMENT packagelist (packagereq)+>
-<!ATTLIST packagelist xmlns CDATA #FIXED ''>
-
-<!ELEMENT display_order (#PCDATA)>
-<!ATTLIST display_order xmlns CDATA #FIXED ''>
-
-<!ELEMENT grouplist (groupid)+>
-<!ATTLIST grouplist xmlns CDATA #FIXED ''>
-
-<!ELEMENT packagereq (#PCDATA)>
-<!ATTLIST packagereq xmlns CDATA #FIXED '' requires NMTOKEN #IMPLIED type NMTOKEN #REQUIRED>
-
-<!ELEMENT groupid (#PCDATA)>
-<!ATTLIST groupid xmlns CDATA #FIXED ''>
-
-<!ELEMENT id (#PCDATA)>
-<!ATTLIST id xmlns CDATA #FIXED ''>
-
-<!ELEMENT name (#PCDATA)>
-<!ATTLIST name xmlns CDATA #FIXED ''>
-
-<!ELEMENT description (#PCDATA)>
-<!ATTLIST description xmlns CDATA #FIXED ''>
-
-<!ELEMENT optionlist (groupid)+>
-<!ATTLIST optionlist xmlns CDATA #FIXED ''>
-
-
-
-
-<?xml version='1.0' encoding='UTF-8'?>
-<!DOCTYPE comps PUBLIC "-//Red Hat, Inc.//DTD Comps info//EN" "comps.dtd">
-<comps>
-  <group>
-    <id>additional-devel</id>
-    <name>Additional Development</name>
-    <description>Additional development headers and libraries for developing applications</description>
-    <default>false</default>
-    <uservisible>false</uservisible>
-    <biarchonly>true</biarchonly>
-    <langonly>fr</langonly>
-    <packagelist>
-      <packagereq type="default">alsa-lib-devel</packagereq>
-      <packagereq type="default">audit-libs-devel</packagereq>
-      <packagereq type="default">binutils-devel</packagereq>
-      <packagereq type="default">boost-devel</packagereq>
-      <packagereq type="default">bzip2-devel</packagereq>
-      <packagereq type="default">cyrus-sasl-devel</packagereq>
-    </packagelist>
-  </group>
-  <group>
-    <id>backup-client</id>
-    <name>Backup Client</name>
-    <description>Client tools for connecting to a backup server and doing backups.</description>
-    <default>true</default>
-    <uservisible>true</uservisible>
-    <packagelist>
-      <packagereq type="mandatory">amanda-client</packagereq>
-      <packagereq type="optional">bacula-client</packagereq>
-    </packagelist>
-  </group>
-  <group>
-    <id>backup-server</id>
-    <name>Backup Server</name>
-    <description>Software to centralize your infrastructure's backups.</description>
-    <default>false</default>
-    <uservisible>true</uservisible>
-    <packagelist>
-      <packagereq type="mandatory">amanda-server</packagereq>
-      <packagereq type="optional">mt-st</packagereq>
-      <packagereq type="optional">mtx</packagereq>
-    </packagelist>
-  </group>
-  <group>
-    <id>ansible-node</id>
-    <name>Ansible node</name>
-    <default>false</default>
-    <uservisible>true</uservisible>
-    <packagelist>
-      <packagereq type="mandatory">python2-dnf</packagereq>
-      <packagereq type="conditional" requires="selinux-policy">libselinux-python</packagereq>
-    </packagelist>
-  </group>
-  <group>
-    <id>d-development</id>
-    <name>D Development Tools and Libraries</name>
-    <description>These include development tools and libraries such as ldc, and geany-tag.</description>
-    <default>false</default>
-    <uservisible>true</uservisible>
-    <packagelist>
-      <packagereq type="mandatory" basearchonly="true">ldc</packagereq>
-      <packagereq type="mandatory" basearchonly="true">ldc-druntime</packagereq>
-      <packagereq type="mandatory" basearchonly="true">ldc-druntime-devel</packagereq>
-      <packagereq type="mandatory" basearchonly="true">ldc-phobos-devel</packagereq>
-      <packagereq type="mandatory">make</packagereq>
-      <packagereq type="mandatory">pkgconfig</packagereq>
-      <packagereq type="default">ctags</packagereq>
-      <packagereq type="default">indent</packagereq>
-      <packagereq type="optional">astyle</packagereq>
-      <packagereq type="optional">cmake</packagereq>
-      <packagereq type="optional">derelict-devel</packagereq>
-      <packagereq type="optional">geany</packagereq>
-      <packagereq type="optional">gl3n-devel</packagereq>
-      <packagereq type="optional">insight</packagereq>
-      <packagereq type="optional">nemiver</packagereq>
-      <packagereq type="optional">uncrustify</packagereq>
-    </packagelist>
-  </group>
-  <group>
-    <id>empty-group-1</id>
-    <name>empty group 1</name>
-    <description>empty group 1 desc</description>
-    <default>false</default>
-    <uservisible>true</uservisible>
-    <packagelist/>
-  </group>
-  <group>
-    <id>empty-group-2</id>
-    <name>empty group 2</name>
-    <description>empty group 2 desc</description>
-    <default>false</default>
-    <uservisible>true</uservisible>
-  </group>
-    <group>
-    <id>unknown-group</id>
-    <name>unknown group</name>
-    <description>unknown group desc</description>
-    <default>false</default>
-    <uservisible>true</uservisible>
-    <packagelist>
-      <packagereq type="unknown">unknown</packagereq>
-      <packagereq type="what">unknown2</packagereq>
-    </packagelist>
-  </group>
-</comps>
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::{utils, MetadataError, Repository};
+
+const TAG_GROUP: &[u8] = b"group";
+const TAG_CATEGORY: &[u8] = b"category";
+const TAG_ENVIRONMENT: &[u8] = b"environment";
+const TAG_LANGPACKS: &[u8] = b"langpacks";
+const TAG_MATCH: &[u8] = b"match";
+const TAG_ID: &[u8] = b"id";
+const TAG_NAME: &[u8] = b"name";
+const TAG_DESCRIPTION: &[u8] = b"description";
+const TAG_DEFAULT: &[u8] = b"default";
+const TAG_USERVISIBLE: &[u8] = b"uservisible";
+const TAG_DISPLAY_ORDER: &[u8] = b"display_order";
+const TAG_LANGONLY: &[u8] = b"langonly";
+const TAG_PACKAGEREQ: &[u8] = b"packagereq";
+const TAG_GROUPLIST: &[u8] = b"grouplist";
+const TAG_OPTIONLIST: &[u8] = b"optionlist";
+const TAG_GROUPID: &[u8] = b"groupid";
+
+/// The package groups of a repository, as in `comps.xml`: groups of packages to install together,
+/// categories of groups, environments (groups of groups, such as a desktop) and langpacks.
+///
+/// Groups, categories and environments are kept in the order they appear in the file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comps {
+    pub groups: Vec<CompsGroup>,
+    pub categories: Vec<CompsCategory>,
+    pub environments: Vec<CompsEnvironment>,
+    pub langpacks: Vec<Langpack>,
+}
+
+/// Whether installing a group installs one of its packages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CompsPackageType {
+    /// Always installed with the group
+    Mandatory,
+    /// Installed with the group unless left out explicitly
+    Default,
+    /// Only installed if asked for
+    Optional,
+    /// Installed with the group if the package it `requires` is installed as well
+    Conditional,
+    /// A type which isn't understood, which clients ignore
+    Unknown,
+}
+
+impl CompsPackageType {
+    pub fn parse(package_type: &str) -> Self {
+        match package_type {
+            "mandatory" => CompsPackageType::Mandatory,
+            "default" => CompsPackageType::Default,
+            "optional" => CompsPackageType::Optional,
+            "conditional" => CompsPackageType::Conditional,
+            _ => CompsPackageType::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompsPackageType::Mandatory => "mandatory",
+            CompsPackageType::Default => "default",
+            CompsPackageType::Optional => "optional",
+            CompsPackageType::Conditional => "conditional",
+            CompsPackageType::Unknown => "unknown",
+        }
+    }
+}
+
+/// A package of a group, `<packagereq>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompsPackage {
+    pub name: String,
+    pub package_type: CompsPackageType,
+    /// The package a conditional package is installed along with
+    pub requires: Option<String>,
+    /// Whether the package is only installed for the base arch of a multilib system
+    pub basearchonly: bool,
+    /// The arches the package is limited to, none if it isn't limited
+    pub arches: Vec<String>,
+}
+
+/// A group of packages, `<group>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompsGroup {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// The names in other languages, by the value of their `xml:lang`
+    pub translated_names: BTreeMap<String, String>,
+    /// The descriptions in other languages, by the value of their `xml:lang`
+    pub translated_descriptions: BTreeMap<String, String>,
+    /// Whether the group is selected by default in installers
+    pub default: bool,
+    pub uservisible: bool,
+    pub display_order: Option<u32>,
+    /// The language the group is installed for, for language support groups
+    pub langonly: Option<String>,
+    pub packages: Vec<CompsPackage>,
+}
+
+impl CompsGroup {
+    /// The packages installed with the group unless they're left out explicitly, the mandatory and the
+    /// default ones.
+    pub fn default_packages(&self) -> impl Iterator<Item = &CompsPackage> {
+        self.packages.iter().filter(|package| {
+            matches!(
+                package.package_type,
+                CompsPackageType::Mandatory | CompsPackageType::Default
+            )
+        })
+    }
+}
+
+impl Default for CompsGroup {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            description: String::new(),
+            translated_names: BTreeMap::new(),
+            translated_descriptions: BTreeMap::new(),
+            default: false,
+            uservisible: true,
+            display_order: None,
+            langonly: None,
+            packages: Vec::new(),
+        }
+    }
+}
+
+/// A category of groups shown together by installers, `<category>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompsCategory {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub translated_names: BTreeMap<String, String>,
+    pub translated_descriptions: BTreeMap<String, String>,
+    pub display_order: Option<u32>,
+    pub group_ids: Vec<String>,
+}
+
+/// A set of groups making up a kind of system, such as a workstation or a server, `<environment>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompsEnvironment {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub translated_names: BTreeMap<String, String>,
+    pub translated_descriptions: BTreeMap<String, String>,
+    pub display_order: Option<u32>,
+    /// The groups installed with the environment
+    pub group_ids: Vec<String>,
+    /// The groups which can be installed with the environment if asked for
+    pub option_ids: Vec<String>,
+}
+
+/// The translations of a package for the installed languages, `<match>` in `<langpacks>`. `install` is a
+/// pattern of the package names, with `%s` standing for the language, e.g. `hunspell-%s` for `hunspell`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Langpack {
+    pub name: String,
+    pub install: String,
+}
+
+/// The environments and groups to install, for [`Repository::resolve_groups()`].
+///
+/// - `environments` - The IDs of the environments, whose groups (but not their options) are installed.
+/// - `groups` - The IDs of any further groups, e.g. the options of the environments.
+/// - `with_optional` - Whether the optional packages of the groups are installed as well, like
+///   `dnf group install --with-optional`.
+/// - `arch` - The arch of the system, for packages which are limited to some arches. Packages aren't left
+///   out for their arch if it isn't set.
+/// - `locales` - The locales of the system, such as `de_DE.UTF-8`, which langpacks are installed for.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupSelection {
+    pub environments: Vec<String>,
+    pub groups: Vec<String>,
+    pub with_optional: bool,
+    pub arch: Option<String>,
+    pub locales: Vec<String>,
+}
+
+impl GroupSelection {
+    pub fn environment(mut self, id: impl Into<String>) -> Self {
+        self.environments.push(id.into());
+        self
+    }
+
+    pub fn group(mut self, id: impl Into<String>) -> Self {
+        self.groups.push(id.into());
+        self
+    }
+
+    pub fn with_optional(self, val: bool) -> Self {
+        Self {
+            with_optional: val,
+            ..self
+        }
+    }
+
+    pub fn arch(self, arch: impl Into<String>) -> Self {
+        Self {
+            arch: Some(arch.into()),
+            ..self
+        }
+    }
+
+    pub fn locale(mut self, locale: impl Into<String>) -> Self {
+        self.locales.push(locale.into());
+        self
+    }
+}
+
+/// The packages installed for a [`GroupSelection`], found by [`Repository::resolve_groups()`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupResolution {
+    /// The IDs of the groups installed, in the order they were selected
+    pub groups: Vec<String>,
+    /// The names of the packages installed which the repository has
+    pub packages: BTreeSet<String>,
+    /// The names of the packages of the groups which the repository doesn't have
+    pub missing_packages: BTreeSet<String>,
+    /// The IDs of the environments and groups which were selected or listed by an environment, but don't
+    /// exist
+    pub unknown: Vec<String>,
+}
+
+impl Comps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a `comps.xml` file, which may be compressed.
+    pub fn from_file(path: &Path) -> Result<Self, MetadataError> {
+        read_comps(utils::xml_reader_from_file(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, MetadataError> {
+        read_comps(utils::create_xml_reader(BufReader::new(reader)))
+    }
+
+    pub fn group(&self, id: &str) -> Option<&CompsGroup> {
+        self.groups.iter().find(|group| group.id == id)
+    }
+
+    pub fn category(&self, id: &str) -> Option<&CompsCategory> {
+        self.categories.iter().find(|category| category.id == id)
+    }
+
+    pub fn environment(&self, id: &str) -> Option<&CompsEnvironment> {
+        self.environments
+            .iter()
+            .find(|environment| environment.id == id)
+    }
+
+    /// The groups installed with the environment `id` (without its options), leaving out any which don't
+    /// exist. `None` if there's no such environment.
+    pub fn environment_groups(&self, id: &str) -> Option<Vec<&CompsGroup>> {
+        let environment = self.environment(id)?;
+        Some(
+            environment
+                .group_ids
+                .iter()
+                .filter_map(|id| self.group(id))
+                .collect(),
+        )
+    }
+
+    /// The names of the langpacks of the package `name` for `locale`, most specific first. Both the
+    /// language and the territory of the locale are tried, and then just the language, so `de_AT.UTF-8`
+    /// gives `hunspell-de_AT` and `hunspell-de` for `hunspell`.
+    pub fn langpacks_for(&self, name: &str, locale: &str) -> Vec<String> {
+        let locale = locale.split(['.', '@']).next().unwrap_or_default();
+        let mut languages = vec![locale];
+        if let Some((language, _)) = locale.split_once('_') {
+            languages.push(language);
+        }
+        self.langpacks
+            .iter()
+            .filter(|langpack| langpack.name == name)
+            .flat_map(|langpack| {
+                languages
+                    .iter()
+                    .filter(|language| !language.is_empty())
+                    .map(|language| langpack.install.replace("%s", language))
+            })
+            .collect()
+    }
+
+    /// Merge the groups, categories, environments and langpacks of `other` into these ones, like
+    /// `mergerepo_c --groupfile` does when a repository is put together from several others.
+    ///
+    /// Those with the same ID (or langpacks for the same package) are merged into one, and the rest are
+    /// added after the existing ones. Where both have a value, such as a name or the translation of a
+    /// description into the same language, the existing one is kept. A package listed by both versions
+    /// of a group is listed once, with the stronger of their types (mandatory, then default, optional
+    /// and conditional), and limited to arches only if both limit it. Groups listed by both versions of
+    /// a category or environment are listed once.
+    pub fn merge(&mut self, other: &Comps) {
+        for group in &other.groups {
+            match self.groups.iter_mut().find(|g| g.id == group.id) {
+                Some(existing) => existing.merge(group),
+                None => {
+                    let mut merged = CompsGroup {
+                        packages: Vec::new(),
+                        ..group.clone()
+                    };
+                    merged.merge(group);
+                    self.groups.push(merged);
+                }
+            }
+        }
+        for category in &other.categories {
+            match self.categories.iter_mut().find(|c| c.id == category.id) {
+                Some(existing) => existing.merge(category),
+                None => {
+                    let mut merged = CompsCategory {
+                        group_ids: Vec::new(),
+                        ..category.clone()
+                    };
+                    merged.merge(category);
+                    self.categories.push(merged);
+                }
+            }
+        }
+        for environment in &other.environments {
+            match self
+                .environments
+                .iter_mut()
+                .find(|e| e.id == environment.id)
+            {
+                Some(existing) => existing.merge(environment),
+                None => {
+                    let mut merged = CompsEnvironment {
+                        group_ids: Vec::new(),
+                        option_ids: Vec::new(),
+                        ..environment.clone()
+                    };
+                    merged.merge(environment);
+                    self.environments.push(merged);
+                }
+            }
+        }
+        for langpack in &other.langpacks {
+            if !self.langpacks.iter().any(|l| l.name == langpack.name) {
+                self.langpacks.push(langpack.clone());
+            }
+        }
+    }
+}
+
+impl CompsGroup {
+    fn merge(&mut self, other: &CompsGroup) {
+        merge_text(&mut self.name, &other.name);
+        merge_text(&mut self.description, &other.description);
+        merge_translated(&mut self.translated_names, &other.translated_names);
+        merge_translated(
+            &mut self.translated_descriptions,
+            &other.translated_descriptions,
+        );
+        self.display_order = self.display_order.or(other.display_order);
+        if self.langonly.is_none() {
+            self.langonly.clone_from(&other.langonly);
+        }
+        for package in &other.packages {
+            let Some(existing) = self.packages.iter_mut().find(|p| p.name == package.name) else {
+                self.packages.push(package.clone());
+                continue;
+            };
+            let arches = match existing.arches.is_empty() || package.arches.is_empty() {
+                true => Vec::new(),
+                false => {
+                    let mut arches = existing.arches.clone();
+                    merge_ids(&mut arches, &package.arches);
+                    arches
+                }
+            };
+            if package.package_type < existing.package_type {
+                *existing = package.clone();
+            }
+            existing.arches = arches;
+        }
+    }
+}
+
+impl CompsCategory {
+    fn merge(&mut self, other: &CompsCategory) {
+        merge_text(&mut self.name, &other.name);
+        merge_text(&mut self.description, &other.description);
+        merge_translated(&mut self.translated_names, &other.translated_names);
+        merge_translated(
+            &mut self.translated_descriptions,
+            &other.translated_descriptions,
+        );
+        self.display_order = self.display_order.or(other.display_order);
+        merge_ids(&mut self.group_ids, &other.group_ids);
+    }
+}
+
+impl CompsEnvironment {
+    fn merge(&mut self, other: &CompsEnvironment) {
+        merge_text(&mut self.name, &other.name);
+        merge_text(&mut self.description, &other.description);
+        merge_translated(&mut self.translated_names, &other.translated_names);
+        merge_translated(
+            &mut self.translated_descriptions,
+            &other.translated_descriptions,
+        );
+        self.display_order = self.display_order.or(other.display_order);
+        merge_ids(&mut self.group_ids, &other.group_ids);
+        merge_ids(&mut self.option_ids, &other.option_ids);
+    }
+}
+
+fn merge_text(text: &mut String, other: &str) {
+    if text.is_empty() {
+        *text = other.to_owned();
+    }
+}
+
+fn merge_translated(translated: &mut BTreeMap<String, String>, other: &BTreeMap<String, String>) {
+    for (lang, text) in other {
+        translated
+            .entry(lang.clone())
+            .or_insert_with(|| text.clone());
+    }
+}
+
+/// Add the IDs of `other` which aren't in `ids` yet, keeping their order.
+fn merge_ids(ids: &mut Vec<String>, other: &[String]) {
+    for id in other {
+        if !ids.contains(id) {
+            ids.push(id.clone());
+        }
+    }
+}
+
+impl Repository {
+    /// The packages installed for `selection`, the way installers flatten environments and groups into the
+    /// packages to install.
+    ///
+    /// These are the mandatory and default packages of the groups of the selected environments and of the
+    /// selected groups (and their optional packages, if selected), and the conditional packages of the
+    /// groups whose condition is among them. For each of those packages, the langpack for each of the
+    /// locales is added as well, in the most specific variant the repository has.
+    pub fn resolve_groups(&self, comps: &Comps, selection: &GroupSelection) -> GroupResolution {
+        let available: HashSet<&str> = self
+            .packages()
+            .values()
+            .filter(|package| package.arch() != "src")
+            .map(|package| package.name())
+            .collect();
+        let mut resolution = GroupResolution::default();
+
+        let mut group_ids: Vec<&str> = Vec::new();
+        for id in &selection.environments {
+            match comps.environment(id) {
+                Some(environment) => {
+                    group_ids.extend(environment.group_ids.iter().map(String::as_str))
+                }
+                None => resolution.unknown.push(id.clone()),
+            }
+        }
+        group_ids.extend(selection.groups.iter().map(String::as_str));
+        let mut groups: Vec<&CompsGroup> = Vec::new();
+        for id in group_ids {
+            if resolution.groups.iter().any(|group| group == id)
+                || resolution.unknown.iter().any(|unknown| unknown == id)
+            {
+                continue;
+            }
+            match comps.group(id) {
+                Some(group) => {
+                    resolution.groups.push(id.to_owned());
+                    groups.push(group);
+                }
+                None => resolution.unknown.push(id.to_owned()),
+            }
+        }
+
+        let mut names: BTreeSet<&str> = BTreeSet::new();
+        let mut conditionals: Vec<&CompsPackage> = Vec::new();
+        let packages = groups
+            .iter()
+            .flat_map(|group| &group.packages)
+            .filter(|package| match &selection.arch {
+                Some(arch) => package.arches.is_empty() || package.arches.contains(arch),
+                None => true,
+            });
+        for package in packages {
+            match package.package_type {
+                CompsPackageType::Mandatory | CompsPackageType::Default => {
+                    names.insert(&package.name);
+                }
+                CompsPackageType::Optional if selection.with_optional => {
+                    names.insert(&package.name);
+                }
+                CompsPackageType::Conditional => conditionals.push(package),
+                _ => (),
+            }
+        }
+        // conditions can be met by other conditional packages
+        loop {
+            let count = names.len();
+            for package in &conditionals {
+                if package
+                    .requires
+                    .as_deref()
+                    .is_some_and(|requires| names.contains(requires))
+                {
+                    names.insert(&package.name);
+                }
+            }
+            if names.len() == count {
+                break;
+            }
+        }
+
+        let mut langpacks: BTreeSet<String> = BTreeSet::new();
+        for name in &names {
+            for locale in &selection.locales {
+                let found = comps
+                    .langpacks_for(name, locale)
+                    .into_iter()
+                    .find(|langpack| available.contains(langpack.as_str()));
+                langpacks.extend(found);
+            }
+        }
+        for name in names.into_iter().map(str::to_owned).chain(langpacks) {
+            match available.contains(name.as_str()) {
+                true => resolution.packages.insert(name),
+                false => resolution.missing_packages.insert(name),
+            };
+        }
+        resolution
+    }
+}
+
+impl FromStr for Comps {
+    type Err = MetadataError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        read_comps(utils::create_xml_reader(contents.as_bytes()))
+    }
+}
+
+fn read_comps<R: BufRead>(mut reader: Reader<R>) -> Result<Comps, MetadataError> {
+    let mut comps = Comps::default();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) => match e.name() {
+                TAG_GROUP => comps.groups.push(parse_group(&mut reader)?),
+                TAG_CATEGORY => comps.categories.push(parse_category(&mut reader)?),
+                TAG_ENVIRONMENT => comps.environments.push(parse_environment(&mut reader)?),
+                TAG_LANGPACKS => parse_langpacks(&mut reader, &mut comps.langpacks)?,
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(comps)
+}
+
+/// The text of a `<name>` or `<description>`, in `text` if it's untranslated or in `translations` if it
+/// has an `xml:lang`.
+fn read_translated<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &BytesStart,
+    text: &mut String,
+    translations: &mut BTreeMap<String, String>,
+) -> Result<(), MetadataError> {
+    let lang = match tag.try_get_attribute("xml:lang")? {
+        Some(lang) => Some(lang.unescape_and_decode_value(reader)?),
+        None => None,
+    };
+    let value = reader.read_text(tag.name(), &mut Vec::new())?;
+    match lang {
+        Some(lang) => {
+            translations.insert(lang, value);
+        }
+        None => *text = value,
+    }
+    Ok(())
+}
+
+fn read_display_order<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &BytesStart,
+) -> Result<Option<u32>, MetadataError> {
+    let value = reader.read_text(tag.name(), &mut Vec::new())?;
+    match value.trim() {
+        "" => Ok(None),
+        value => Ok(Some(value.parse()?)),
+    }
+}
+
+/// The `<groupid>`s of a `<grouplist>` or `<optionlist>`.
+fn read_group_ids<R: BufRead>(
+    reader: &mut Reader<R>,
+    end: &[u8],
+) -> Result<Vec<String>, MetadataError> {
+    let mut ids = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) if e.name() == TAG_GROUPID => {
+                ids.push(reader.read_text(TAG_GROUPID, &mut Vec::new())?)
+            }
+            Event::End(e) if e.name() == end => break,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(ids)
+}
+
+fn parse_packagereq<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &BytesStart,
+) -> Result<CompsPackage, MetadataError> {
+    let attribute = |name: &str| -> Result<Option<String>, MetadataError> {
+        match tag.try_get_attribute(name)? {
+            Some(value) => Ok(Some(value.unescape_and_decode_value(reader)?)),
+            None => Ok(None),
+        }
+    };
+    let package_type = attribute("type")?.unwrap_or_default();
+    let requires = attribute("requires")?;
+    let basearchonly = attribute("basearchonly")?.is_some_and(|value| utils::parse_flag(&value));
+    let arches = attribute("arch")?
+        .map(|arches| {
+            arches
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|arch| !arch.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    let name = reader.read_text(TAG_PACKAGEREQ, &mut Vec::new())?;
+    Ok(CompsPackage {
+        name: name.trim().to_owned(),
+        package_type: CompsPackageType::parse(&package_type),
+        requires,
+        basearchonly,
+        arches,
+    })
+}
+
+fn parse_group<R: BufRead>(reader: &mut Reader<R>) -> Result<CompsGroup, MetadataError> {
+    let mut group = CompsGroup::default();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) => match e.name() {
+                TAG_ID => group.id = reader.read_text(TAG_ID, &mut Vec::new())?,
+                TAG_NAME => {
+                    read_translated(reader, &e, &mut group.name, &mut group.translated_names)?
+                }
+                TAG_DESCRIPTION => read_translated(
+                    reader,
+                    &e,
+                    &mut group.description,
+                    &mut group.translated_descriptions,
+                )?,
+                TAG_DEFAULT => {
+                    group.default =
+                        utils::parse_flag(&reader.read_text(TAG_DEFAULT, &mut Vec::new())?)
+                }
+                TAG_USERVISIBLE => {
+                    group.uservisible =
+                        utils::parse_flag(&reader.read_text(TAG_USERVISIBLE, &mut Vec::new())?)
+                }
+                TAG_DISPLAY_ORDER => group.display_order = read_display_order(reader, &e)?,
+                TAG_LANGONLY => {
+                    group.langonly = Some(reader.read_text(TAG_LANGONLY, &mut Vec::new())?)
+                }
+                TAG_PACKAGEREQ => group.packages.push(parse_packagereq(reader, &e)?),
+                _ => (),
+            },
+            Event::End(e) if e.name() == TAG_GROUP => break,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    if group.id.is_empty() {
+        return Err(MetadataError::MissingFieldError("id"));
+    }
+    Ok(group)
+}
+
+fn parse_category<R: BufRead>(reader: &mut Reader<R>) -> Result<CompsCategory, MetadataError> {
+    let mut category = CompsCategory::default();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) => match e.name() {
+                TAG_ID => category.id = reader.read_text(TAG_ID, &mut Vec::new())?,
+                TAG_NAME => read_translated(
+                    reader,
+                    &e,
+                    &mut category.name,
+                    &mut category.translated_names,
+                )?,
+                TAG_DESCRIPTION => read_translated(
+                    reader,
+                    &e,
+                    &mut category.description,
+                    &mut category.translated_descriptions,
+                )?,
+                TAG_DISPLAY_ORDER => category.display_order = read_display_order(reader, &e)?,
+                TAG_GROUPLIST => category.group_ids = read_group_ids(reader, TAG_GROUPLIST)?,
+                _ => (),
+            },
+            Event::End(e) if e.name() == TAG_CATEGORY => break,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    if category.id.is_empty() {
+        return Err(MetadataError::MissingFieldError("id"));
+    }
+    Ok(category)
+}
+
+fn parse_environment<R: BufRead>(
+    reader: &mut Reader<R>,
+) -> Result<CompsEnvironment, MetadataError> {
+    let mut environment = CompsEnvironment::default();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) => match e.name() {
+                TAG_ID => environment.id = reader.read_text(TAG_ID, &mut Vec::new())?,
+                TAG_NAME => read_translated(
+                    reader,
+                    &e,
+                    &mut environment.name,
+                    &mut environment.translated_names,
+                )?,
+                TAG_DESCRIPTION => read_translated(
+                    reader,
+                    &e,
+                    &mut environment.description,
+                    &mut environment.translated_descriptions,
+                )?,
+                TAG_DISPLAY_ORDER => environment.display_order = read_display_order(reader, &e)?,
+                TAG_GROUPLIST => environment.group_ids = read_group_ids(reader, TAG_GROUPLIST)?,
+                TAG_OPTIONLIST => environment.option_ids = read_group_ids(reader, TAG_OPTIONLIST)?,
+                _ => (),
+            },
+            Event::End(e) if e.name() == TAG_ENVIRONMENT => break,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    if environment.id.is_empty() {
+        return Err(MetadataError::MissingFieldError("id"));
+    }
+    Ok(environment)
+}
+
+fn parse_langpacks<R: BufRead>(
+    reader: &mut Reader<R>,
+    langpacks: &mut Vec<Langpack>,
+) -> Result<(), MetadataError> {
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.name() == TAG_MATCH => {
+                let attribute = |name: &'static str| -> Result<String, MetadataError> {
+                    match e.try_get_attribute(name)? {
+                        Some(value) => Ok(value.unescape_and_decode_value(reader)?),
+                        None => Err(MetadataError::MissingAttributeError(name)),
+                    }
+                };
+                let name = attribute("name")?;
+                let install = attribute("install")?;
+                langpacks.push(Langpack { name, install });
+            }
+            Event::End(e) if e.name() == TAG_LANGPACKS => break,
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(())
+}
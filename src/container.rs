@@ -0,0 +1,91 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::{InstalledSet, Repository};
+
+#[cfg(all(feature = "archive", feature = "read_rpm"))]
+pub use layer::repository_from_layer;
+
+impl Repository {
+    /// A repository of the packages installed on a system, such as a container image whose rpmdb was
+    /// exported with `rpm -qa` (see [`InstalledSet::parse()`]), so that it can be queried like any other
+    /// repository, e.g. for an SBOM or for the advisories which apply to it.
+    ///
+    /// The packages only have a name, EVR and arch, so they're keyed by their NEVRA rather than their
+    /// checksum. The repository is meant for querying, and its metadata isn't fit to be published.
+    pub fn from_installed(installed: &InstalledSet) -> Repository {
+        let mut repository = Repository::new();
+        for package in installed {
+            repository
+                .packages_mut()
+                .insert(package.nevra(), package.clone());
+        }
+        repository
+    }
+}
+
+#[cfg(all(feature = "archive", feature = "read_rpm"))]
+mod layer {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::logging;
+    use crate::storage;
+    use crate::utils::{self, FailurePolicy};
+    use crate::{MetadataError, Package, Repository};
+
+    /// Build a repository of the RPMs in a container image layer, so that its contents can be
+    /// queried like any other repository: either a tar archive of the layer (possibly compressed,
+    /// such as an OCI layer blob), or a directory a layer or a whole root filesystem was unpacked
+    /// into.
+    ///
+    /// The `location_href` of each package is the path of its RPM within the layer. Hidden files
+    /// are skipped, which includes the whiteout files marking files deleted from the layers below.
+    /// Use [`Repository::from_installed()`] for the packages which are installed in the image
+    /// instead.
+    pub fn repository_from_layer(path: &Path) -> Result<Repository, MetadataError> {
+        let _span = logging::span!("read the RPMs of layer {}", path.display());
+        let packages = if path.is_dir() {
+            utils::load_rpm_directory(path, &FailurePolicy::Abort)?.packages
+        } else {
+            // read in a single pass, as a compressed layer can't be read from the middle
+            let mut packages: Vec<Package> = Vec::new();
+            let mut rpms = HashMap::new();
+            storage::read_tar_files(path, |entry, contents| {
+                let is_rpm = entry.path.ends_with(".rpm")
+                    && !entry.path.split('/').any(|name| name.starts_with('.'));
+                match &entry.link {
+                    None if is_rpm => {
+                        let package = utils::load_rpm_reader(contents, &entry.path)?;
+                        rpms.insert(entry.path.clone(), packages.len());
+                        packages.push(package);
+                    }
+                    Some(target) if is_rpm => {
+                        if let Some(index) = rpms.get(target).copied() {
+                            let mut package = packages[index].clone();
+                            package.set_location_href(&entry.path);
+                            rpms.insert(entry.path.clone(), packages.len());
+                            packages.push(package);
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(())
+            })?;
+            logging::debug!("found {} RPMs in {}", packages.len(), path.display());
+            packages.sort_unstable_by(|a, b| a.location_href().cmp(b.location_href()));
+            packages
+        };
+
+        let mut repository = Repository::new();
+        for package in packages {
+            repository
+                .packages_mut()
+                .insert(package.pkgid().to_owned(), package);
+        }
+        Ok(repository)
+    }
+}
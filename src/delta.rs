@@ -0,0 +1,237 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::logging;
+use crate::metadata::METADATA_PRIMARY;
+use crate::{
+    utils, Checksum, CompressionType, MetadataError, Package, Pattern, Product, RepomdData,
+    RepomdXml, Repository, UpdateRecord,
+};
+
+/// The file listing what a delta removes, and the revision it applies to.
+const DELTA_FILE: &str = "DELTA";
+const DELTA_HEADER: &str = "rpmrepo-delta 1";
+/// `repomd.xml` of the revision a delta upgrades to.
+const TARGET_REPOMD: &str = "target-repomd.xml";
+
+/// The metadata changes between two revisions of a repository, see [`Repository::apply_delta()`].
+///
+/// A delta is written as a directory holding a repository of just the added packages and the added or
+/// changed advisories, a `DELTA` file listing the pkgids of the removed packages and the IDs of the
+/// removed advisories, and `repomd.xml` of the new revision. Products and patterns are small, and are
+/// carried over whole.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepositoryDelta {
+    /// The checksum of the `primary` record of the revision the delta applies to, if it has one
+    pub base: Option<Checksum>,
+    /// `repomd.xml` of the new revision
+    pub repomd: RepomdData,
+    pub added_packages: Vec<Package>,
+    /// The pkgids of the packages which were removed
+    pub removed_packages: Vec<String>,
+    /// The advisories which were added or changed
+    pub changed_advisories: Vec<UpdateRecord>,
+    /// The IDs of the advisories which were removed
+    pub removed_advisories: Vec<String>,
+    pub products: Vec<Product>,
+    pub patterns: Vec<Pattern>,
+}
+
+impl RepositoryDelta {
+    /// The changes which turn `base` into `target`. Packages are matched up by their pkgid and
+    /// advisories by their ID; a package whose metadata changed without its pkgid changing is removed
+    /// and added again.
+    pub fn between(base: &Repository, target: &Repository) -> Self {
+        let packages = |from: &Repository, to: &Repository| -> Vec<Package> {
+            to.packages()
+                .iter()
+                .filter(|(pkgid, package)| from.packages().get(*pkgid) != Some(*package))
+                .map(|(_, package)| package.clone())
+                .collect()
+        };
+        let added_packages = packages(base, target);
+        let removed_packages = packages(target, base)
+            .iter()
+            .map(|package| package.pkgid().to_owned())
+            .collect();
+        let changed_advisories = target
+            .advisories()
+            .iter()
+            .filter(|(id, advisory)| base.advisories().get(*id) != Some(*advisory))
+            .map(|(_, advisory)| advisory.clone())
+            .collect();
+        let removed_advisories = base
+            .advisories()
+            .keys()
+            .filter(|id| !target.advisories().contains_key(*id))
+            .cloned()
+            .collect();
+
+        Self {
+            base: primary_checksum(base.repomd()),
+            repomd: target.repomd().clone(),
+            added_packages,
+            removed_packages,
+            changed_advisories,
+            removed_advisories,
+            products: target.products().to_vec(),
+            patterns: target.patterns().to_vec(),
+        }
+    }
+
+    /// Whether the delta changes no packages or advisories.
+    pub fn is_empty(&self) -> bool {
+        self.added_packages.is_empty()
+            && self.removed_packages.is_empty()
+            && self.changed_advisories.is_empty()
+            && self.removed_advisories.is_empty()
+    }
+
+    /// Write the delta to the directory `path`.
+    pub fn write_to_directory(&self, path: &Path) -> Result<(), MetadataError> {
+        let _span = logging::span!("write delta to {}", path.display());
+        let mut changes = Repository::new();
+        for package in &self.added_packages {
+            changes
+                .packages_mut()
+                .insert(package.pkgid().to_owned(), package.clone());
+        }
+        for advisory in &self.changed_advisories {
+            changes
+                .advisories_mut()
+                .insert(advisory.id.clone(), advisory.clone());
+        }
+        *changes.products_mut() = self.products.clone();
+        *changes.patterns_mut() = self.patterns.clone();
+        changes.write_to_directory(path)?;
+
+        let mut file = File::create(path.join(DELTA_FILE))?;
+        writeln!(file, "{}", DELTA_HEADER)?;
+        if let Some(base) = &self.base {
+            let (checksum_type, digest) = base.to_values()?;
+            writeln!(file, "base {} {}", checksum_type, digest)?;
+        }
+        for pkgid in &self.removed_packages {
+            writeln!(file, "removed-package {}", pkgid)?;
+        }
+        for id in &self.removed_advisories {
+            writeln!(file, "removed-advisory {}", id)?;
+        }
+
+        let (_, mut writer) =
+            utils::xml_writer_for_path(&path.join(TARGET_REPOMD), CompressionType::None)?;
+        RepomdXml::write_data(&self.repomd, &mut writer)?;
+        logging::debug!(
+            "wrote delta adding {} and removing {} packages to {}",
+            self.added_packages.len(),
+            self.removed_packages.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Read a delta written by [`RepositoryDelta::write_to_directory()`] from the directory `path`.
+    pub fn read_from_directory(path: &Path) -> Result<Self, MetadataError> {
+        let _span = logging::span!("read delta from {}", path.display());
+        let changes = Repository::load_from_directory(path)?;
+        let mut delta = Self {
+            repomd: RepomdXml::read_data(utils::xml_reader_from_file(&path.join(TARGET_REPOMD))?)?,
+            added_packages: changes.packages().values().cloned().collect(),
+            changed_advisories: changes.advisories().values().cloned().collect(),
+            products: changes.products().to_vec(),
+            patterns: changes.patterns().to_vec(),
+            ..Self::default()
+        };
+
+        let invalid = |line: &str| MetadataError::InvalidFieldError("delta", line.to_owned());
+        let mut lines = BufReader::new(File::open(path.join(DELTA_FILE))?).lines();
+        match lines.next().transpose()? {
+            Some(line) if line == DELTA_HEADER => (),
+            line => return Err(invalid(&line.unwrap_or_default())),
+        }
+        for line in lines {
+            let line = line?;
+            match line.split_once(' ') {
+                Some(("base", checksum)) => {
+                    let (checksum_type, digest) =
+                        checksum.split_once(' ').ok_or_else(|| invalid(&line))?;
+                    delta.base = Some(Checksum::try_create(checksum_type, digest)?);
+                }
+                Some(("removed-package", pkgid)) => delta.removed_packages.push(pkgid.to_owned()),
+                Some(("removed-advisory", id)) => delta.removed_advisories.push(id.to_owned()),
+                _ => return Err(invalid(&line)),
+            }
+        }
+        Ok(delta)
+    }
+}
+
+/// Upgrade `repo` with `delta`, checking first that it's the revision `delta` applies to and that it has
+/// everything `delta` removes, so that `repo` is left as it was if it fails.
+pub(crate) fn apply_delta(
+    repo: &mut Repository,
+    delta: &RepositoryDelta,
+) -> Result<(), MetadataError> {
+    let _span = logging::span!("apply delta");
+    if delta.base.is_some() && primary_checksum(repo.repomd()) != delta.base {
+        return Err(MetadataError::InconsistentMetadataError(
+            "the delta doesn't apply to this revision of the repository".to_owned(),
+        ));
+    }
+    let missing = delta
+        .removed_packages
+        .iter()
+        .find(|pkgid| !repo.packages().contains_key(*pkgid))
+        .map(|pkgid| format!("package {}", pkgid))
+        .or_else(|| {
+            delta
+                .removed_advisories
+                .iter()
+                .find(|id| !repo.advisories().contains_key(*id))
+                .map(|id| format!("advisory {}", id))
+        });
+    if let Some(missing) = missing {
+        return Err(MetadataError::InconsistentMetadataError(format!(
+            "the delta removes {}, which isn't in the repository",
+            missing
+        )));
+    }
+
+    let removed: HashSet<&String> = delta.removed_packages.iter().collect();
+    repo.packages_mut()
+        .retain(|pkgid, _| !removed.contains(pkgid));
+    for package in &delta.added_packages {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    let removed: HashSet<&String> = delta.removed_advisories.iter().collect();
+    repo.advisories_mut().retain(|id, _| !removed.contains(id));
+    for advisory in &delta.changed_advisories {
+        repo.advisories_mut()
+            .insert(advisory.id.clone(), advisory.clone());
+    }
+    *repo.products_mut() = delta.products.clone();
+    *repo.patterns_mut() = delta.patterns.clone();
+    *repo.repomd_mut() = delta.repomd.clone();
+    logging::debug!(
+        "applied delta adding {} and removing {} packages",
+        delta.added_packages.len(),
+        delta.removed_packages.len()
+    );
+    Ok(())
+}
+
+/// What identifies a revision of a repository: the checksum of its `primary` record.
+fn primary_checksum(repomd: &RepomdData) -> Option<Checksum> {
+    repomd
+        .get_record(METADATA_PRIMARY)
+        .map(|record| record.checksum.clone())
+}
@@ -0,0 +1,445 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+use quick_xml::events::{BytesDecl, BytesStart, BytesText, Event};
+
+use crate::metadata::Requirement;
+use crate::paths::PathIndex;
+use crate::{capability, utils, MetadataError, Package, Repository};
+
+const GRAPHML_NS: &str = "http://graphml.graphdrawing.org/xmlns";
+
+/// The kind of relationship an edge of a [`DependencyGraph`] represents.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DependencyKind {
+    Requires,
+    Recommends,
+    Suggests,
+    Supplements,
+    Enhances,
+}
+
+impl DependencyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyKind::Requires => "requires",
+            DependencyKind::Recommends => "recommends",
+            DependencyKind::Suggests => "suggests",
+            DependencyKind::Supplements => "supplements",
+            DependencyKind::Enhances => "enhances",
+        }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        *self != DependencyKind::Requires
+    }
+}
+
+/// Options for building a [`DependencyGraph`].
+///
+/// - `include_weak_deps` - Also add edges for recommends, suggests, supplements and enhances.
+/// - `include_file_deps` - Resolve requirements on file paths (e.g. `/usr/bin/sh`) against package file lists.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DependencyGraphOptions {
+    pub include_weak_deps: bool,
+    pub include_file_deps: bool,
+}
+
+impl DependencyGraphOptions {
+    pub fn include_weak_deps(self, val: bool) -> Self {
+        Self {
+            include_weak_deps: val,
+            ..self
+        }
+    }
+
+    pub fn include_file_deps(self, val: bool) -> Self {
+        Self {
+            include_file_deps: val,
+            ..self
+        }
+    }
+}
+
+/// A directed edge from the package declaring a dependency to a package satisfying it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DependencyEdge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: DependencyKind,
+    /// The name of the capability which produced this edge
+    pub capability: String,
+}
+
+/// A directed graph of the dependency relationships between the packages of a repository.
+///
+/// Nodes are packages, identified by their index into [`DependencyGraph::nodes()`]. Edges point
+/// from the package which requires a capability to each package which provides it. Rich (boolean)
+/// dependencies are not expanded, and requirements satisfied by the package itself are omitted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DependencyGraph {
+    nodes: Vec<String>,
+    edges: Vec<DependencyEdge>,
+}
+
+impl DependencyGraph {
+    /// Build the dependency graph of all packages in a [`Repository`].
+    pub fn from_repository(repository: &Repository, options: DependencyGraphOptions) -> Self {
+        let packages: Vec<&Package> = repository.packages().values().collect();
+        Self::from_packages(&packages, options)
+    }
+
+    /// Build the dependency graph of an arbitrary set of packages.
+    pub fn from_packages(packages: &[&Package], options: DependencyGraphOptions) -> Self {
+        let mut providers: HashMap<&str, Vec<(usize, &Requirement)>> = HashMap::new();
+        let mut file_providers = PathIndex::new();
+
+        for (idx, package) in packages.iter().enumerate() {
+            for provide in package.provides() {
+                providers
+                    .entry(provide.name.as_str())
+                    .or_default()
+                    .push((idx, provide));
+            }
+            if options.include_file_deps {
+                file_providers.add_files(package, idx);
+            }
+        }
+
+        let mut edges = BTreeSet::new();
+        for (idx, package) in packages.iter().enumerate() {
+            let mut sections = vec![(DependencyKind::Requires, package.requires())];
+            if options.include_weak_deps {
+                sections.push((DependencyKind::Recommends, package.recommends()));
+                sections.push((DependencyKind::Suggests, package.suggests()));
+                sections.push((DependencyKind::Supplements, package.supplements()));
+                sections.push((DependencyKind::Enhances, package.enhances()));
+            }
+
+            for (kind, requirements) in sections {
+                for requirement in requirements {
+                    let name = requirement.name.as_str();
+                    if name.starts_with('(') {
+                        // rich dependencies are out of scope
+                        continue;
+                    }
+                    let is_file = name.starts_with('/');
+                    if is_file && !options.include_file_deps {
+                        continue;
+                    }
+
+                    let mut targets = BTreeSet::new();
+                    if let Some(candidates) = providers.get(name) {
+                        targets.extend(
+                            candidates
+                                .iter()
+                                .filter(|(_, provide)| capability::satisfies(provide, requirement))
+                                .map(|(target, _)| *target),
+                        );
+                    }
+                    if is_file {
+                        targets.extend(file_providers.get(name).iter().copied());
+                    }
+
+                    for target in targets.into_iter().filter(|&t| t != idx) {
+                        edges.insert(DependencyEdge {
+                            from: idx,
+                            to: target,
+                            kind,
+                            capability: name.to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+
+        DependencyGraph {
+            nodes: packages.iter().map(|p| p.nevra()).collect(),
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// The NEVRA of each package in the graph, indexed by node id.
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[DependencyEdge] {
+        &self.edges
+    }
+
+    /// The node id of the package with the given NEVRA.
+    pub fn node(&self, nevra: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node == nevra)
+    }
+
+    /// The packages `node` depends on with a dependency of `kind`, e.g. the packages satisfying its
+    /// requirements for [`DependencyKind::Requires`].
+    pub fn dependencies(&self, node: usize, kind: DependencyKind) -> Vec<usize> {
+        let targets: BTreeSet<usize> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.from == node && edge.kind == kind)
+            .map(|edge| edge.to)
+            .collect();
+        targets.into_iter().collect()
+    }
+
+    /// The packages which declare a dependency of `kind` satisfied by `node`.
+    pub fn dependents(&self, node: usize, kind: DependencyKind) -> Vec<usize> {
+        let sources: BTreeSet<usize> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.to == node && edge.kind == kind)
+            .map(|edge| edge.from)
+            .collect();
+        sources.into_iter().collect()
+    }
+
+    /// The packages installed along with `node` as weak dependencies, if weak dependencies are installed
+    /// (dnf's `install_weak_deps`): those satisfying its recommends, and those which supplement it.
+    ///
+    /// Weak dependencies only have edges if the graph was built with
+    /// [`DependencyGraphOptions::include_weak_deps`].
+    pub fn recommended(&self, node: usize) -> Vec<usize> {
+        self.weak_dependencies(
+            node,
+            DependencyKind::Recommends,
+            DependencyKind::Supplements,
+        )
+    }
+
+    /// The packages offered along with `node` which aren't installed automatically: those satisfying its
+    /// suggests, and those which enhance it.
+    pub fn suggested(&self, node: usize) -> Vec<usize> {
+        self.weak_dependencies(node, DependencyKind::Suggests, DependencyKind::Enhances)
+    }
+
+    /// `forward` dependencies of `node` and packages with a `reverse` dependency on it, e.g. recommends of
+    /// the package and supplements of other packages, which mean the same thing from the other side.
+    fn weak_dependencies(
+        &self,
+        node: usize,
+        forward: DependencyKind,
+        reverse: DependencyKind,
+    ) -> Vec<usize> {
+        let mut targets = self.dependencies(node, forward);
+        targets.extend(self.dependents(node, reverse));
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    /// The packages installed along with `node`, excluding itself: everything satisfying its requirements,
+    /// transitively, and with `weak_deps` also what they recommend (see [`DependencyGraph::recommended()`]).
+    ///
+    /// Every provider of a capability is included, where dnf would pick one of them.
+    pub fn install_set(&self, node: usize, weak_deps: bool) -> Vec<usize> {
+        let mut seen = BTreeSet::from([node]);
+        let mut pending = vec![node];
+        while let Some(current) = pending.pop() {
+            let mut next = self.dependencies(current, DependencyKind::Requires);
+            if weak_deps {
+                next.extend(self.recommended(current));
+            }
+            for target in next {
+                if seen.insert(target) {
+                    pending.push(target);
+                }
+            }
+        }
+        seen.remove(&node);
+        seen.into_iter().collect()
+    }
+
+    /// Find dependency cycles.
+    ///
+    /// Returns each strongly connected component containing more than one package, as a sorted
+    /// list of node ids. Only hard (`Requires`) edges are considered.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); self.nodes.len()];
+        for edge in self.edges.iter().filter(|e| !e.kind.is_weak()) {
+            adjacency[edge.from].push(edge.to);
+        }
+
+        let mut cycles: Vec<Vec<usize>> = strongly_connected_components(&adjacency)
+            .into_iter()
+            .filter(|component| component.len() > 1)
+            .map(|mut component| {
+                component.sort_unstable();
+                component
+            })
+            .collect();
+        cycles.sort();
+        cycles
+    }
+
+    /// Write the graph in the Graphviz DOT format.
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> Result<(), MetadataError> {
+        writeln!(writer, "digraph packages {{")?;
+        for (idx, nevra) in self.nodes.iter().enumerate() {
+            writeln!(writer, "  n{} [label=\"{}\"];", idx, escape_dot(nevra))?;
+        }
+        for edge in &self.edges {
+            if edge.kind.is_weak() {
+                writeln!(
+                    writer,
+                    "  n{} -> n{} [label=\"{} ({})\", style=dashed];",
+                    edge.from,
+                    edge.to,
+                    escape_dot(&edge.capability),
+                    edge.kind.as_str()
+                )?;
+            } else {
+                writeln!(
+                    writer,
+                    "  n{} -> n{} [label=\"{}\"];",
+                    edge.from,
+                    edge.to,
+                    escape_dot(&edge.capability)
+                )?;
+            }
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    /// Write the graph in the GraphML format.
+    pub fn write_graphml<W: Write + Send>(&self, writer: W) -> Result<(), MetadataError> {
+        let mut writer = utils::create_xml_writer(writer);
+
+        // <?xml version="1.0" encoding="UTF-8"?>
+        writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+
+        // <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+        let mut graphml_tag = BytesStart::borrowed_name(b"graphml");
+        graphml_tag.push_attribute(("xmlns", GRAPHML_NS));
+        writer.write_event(Event::Start(graphml_tag.to_borrowed()))?;
+
+        // <key id="nevra" for="node" attr.name="nevra" attr.type="string"/>
+        for (id, domain) in [("nevra", "node"), ("capability", "edge"), ("kind", "edge")] {
+            writer
+                .create_element(b"key")
+                .with_attribute(("id", id))
+                .with_attribute(("for", domain))
+                .with_attribute(("attr.name", id))
+                .with_attribute(("attr.type", "string"))
+                .write_empty()?;
+        }
+
+        // <graph id="packages" edgedefault="directed">
+        let mut graph_tag = BytesStart::borrowed_name(b"graph");
+        graph_tag.push_attribute(("id", "packages"));
+        graph_tag.push_attribute(("edgedefault", "directed"));
+        writer.write_event(Event::Start(graph_tag.to_borrowed()))?;
+
+        for (idx, nevra) in self.nodes.iter().enumerate() {
+            // <node id="n0"><data key="nevra">foo-0:1.0-1.noarch</data></node>
+            let node_id = format!("n{}", idx);
+            let node_tag =
+                BytesStart::borrowed_name(b"node").with_attributes([("id", node_id.as_str())]);
+            writer.write_event(Event::Start(node_tag.to_borrowed()))?;
+            writer
+                .create_element(b"data")
+                .with_attribute(("key", "nevra"))
+                .write_text_content(BytesText::from_plain_str(nevra))?;
+            writer.write_event(Event::End(node_tag.to_end()))?;
+        }
+
+        for edge in &self.edges {
+            // <edge source="n0" target="n1">...</edge>
+            let source = format!("n{}", edge.from);
+            let target = format!("n{}", edge.to);
+            let edge_tag = BytesStart::borrowed_name(b"edge")
+                .with_attributes([("source", source.as_str()), ("target", target.as_str())]);
+            writer.write_event(Event::Start(edge_tag.to_borrowed()))?;
+            writer
+                .create_element(b"data")
+                .with_attribute(("key", "capability"))
+                .write_text_content(BytesText::from_plain_str(&edge.capability))?;
+            writer
+                .create_element(b"data")
+                .with_attribute(("key", "kind"))
+                .write_text_content(BytesText::from_plain_str(edge.kind.as_str()))?;
+            writer.write_event(Event::End(edge_tag.to_end()))?;
+        }
+
+        // </graph>
+        writer.write_event(Event::End(graph_tag.to_end()))?;
+        // </graphml>
+        writer.write_event(Event::End(graphml_tag.to_end()))?;
+        // trailing newline
+        writer.write_event(Event::Text(BytesText::from_plain_str("\n")))?;
+        writer.inner().flush()?;
+
+        Ok(())
+    }
+}
+
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Tarjan's algorithm, iterative to avoid overflowing the stack on large repositories.
+fn strongly_connected_components(adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+
+    let count = adjacency.len();
+    let mut index = vec![UNVISITED; count];
+    let mut lowlink = vec![0; count];
+    let mut on_stack = vec![false; count];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next_index = 0;
+
+    for root in 0..count {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // (node, position of the next edge to explore)
+        let mut work = vec![(root, 0)];
+        while let Some(&mut (node, ref mut edge_pos)) = work.last_mut() {
+            if *edge_pos == 0 {
+                index[node] = next_index;
+                lowlink[node] = next_index;
+                next_index += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+
+            if let Some(&next) = adjacency[node].get(*edge_pos) {
+                *edge_pos += 1;
+                if index[next] == UNVISITED {
+                    work.push((next, 0));
+                } else if on_stack[next] {
+                    lowlink[node] = lowlink[node].min(index[next]);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[node]);
+            }
+            if lowlink[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+
+    components
+}
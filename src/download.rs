@@ -0,0 +1,1309 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Mirroring of remote repositories (the "reposync" use case).
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::logging;
+use crate::metadata::METADATA_PRIMARY;
+use crate::{
+    utils, verify_files, Checksum, ChecksumType, FileCheck, LoadOptions, MetadataError,
+    MetadataSelection, MetadataType, Package, ParseReport, PrimaryXml, RepomdData, RepomdRecord,
+    RepomdXml, Repository, RepositoryProbe, VerifyOptions,
+};
+
+#[cfg(feature = "tokio")]
+mod async_transport;
+mod fetch;
+mod filter;
+mod mirror;
+mod state;
+mod task;
+mod throttle;
+mod transport;
+mod upload;
+mod verify;
+mod zchunk;
+
+#[cfg(feature = "tokio")]
+pub use async_transport::{AsyncResponse, AsyncTransport, ReqwestTransport};
+use fetch::{Fetched, Fetcher};
+pub use filter::PackageFilter;
+use mirror::Mirrors;
+pub use mirror::{parse_mirrorlist, Metalink, MirrorStatus};
+use state::{SyncState, Validators};
+use task::{Progress, ProgressCallback};
+pub use task::{SyncEvent, SyncTask};
+use throttle::RateLimiter;
+pub use transport::{DefaultTransport, Method, Request, Response, Transport};
+pub use upload::{UploadReport, Uploader};
+use verify::HashingWriter;
+
+/// Directory (relative to the destination) which corrupt packages are moved to by [`MismatchPolicy::Quarantine`].
+const QUARANTINE_DIR: &str = ".quarantine";
+
+/// Upper bound for the delay between two attempts to fetch the same file.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Options for mirroring a repository.
+///
+/// - `download_packages` - Download the packages listed in primary.xml in addition to the metadata.
+/// - `verify_checksums` - Check each downloaded file against the checksum recorded in the metadata.
+/// - `resume` - Continue interrupted downloads using HTTP range requests, and remember which files have
+///   already been verified in a state file in the destination directory so they aren't checksummed again.
+/// - `retries` - How many more times to try fetching a file from a mirror after a transient failure
+///   (connection problems, timeouts, server errors) before failing over to the next mirror.
+/// - `retry_delay` - How long to wait before the first retry. The delay doubles with each further attempt.
+/// - `zchunk_deltas` - When a zchunk metadata file (e.g. `primary.xml.zck`) has changed since the last sync,
+///   download only the chunks which differ from the local copy.
+/// - `max_bytes_per_sec` - Limit the overall download rate of the `Downloader`, across all connections.
+/// - `max_bytes_per_sec_per_connection` - Limit the download rate of each individual connection.
+/// - `on_mismatch` - What to do with a package which doesn't match its size or checksum in primary.xml
+///   (from any mirror). Metadata files which don't match always fail the sync.
+/// - `verification` - How the packages already in the destination directory are verified, all at once
+///   before any are downloaded, see [`VerifyOptions`].
+#[derive(Copy, Clone, Debug)]
+pub struct DownloadOptions {
+    pub download_packages: bool,
+    pub verify_checksums: bool,
+    pub resume: bool,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub zchunk_deltas: bool,
+    pub max_bytes_per_sec: Option<u64>,
+    pub max_bytes_per_sec_per_connection: Option<u64>,
+    pub on_mismatch: MismatchPolicy,
+    pub verification: VerifyOptions,
+}
+
+/// How to handle a package which fails verification. See [`DownloadOptions::on_mismatch`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Delete the package and fail the sync
+    #[default]
+    Fail,
+    /// Delete the package, record it in [`SyncReport::failed`] and carry on
+    Delete,
+    /// Move the package to the `.quarantine` directory, record it in [`SyncReport::failed`] and carry on
+    Quarantine,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            download_packages: false,
+            verify_checksums: true,
+            resume: true,
+            retries: 2,
+            retry_delay: Duration::from_secs(1),
+            zchunk_deltas: true,
+            max_bytes_per_sec: None,
+            max_bytes_per_sec_per_connection: None,
+            on_mismatch: MismatchPolicy::Fail,
+            verification: VerifyOptions::default(),
+        }
+    }
+}
+
+impl DownloadOptions {
+    pub fn download_packages(self, val: bool) -> Self {
+        Self {
+            download_packages: val,
+            ..self
+        }
+    }
+
+    pub fn verify_checksums(self, val: bool) -> Self {
+        Self {
+            verify_checksums: val,
+            ..self
+        }
+    }
+
+    pub fn resume(self, val: bool) -> Self {
+        Self {
+            resume: val,
+            ..self
+        }
+    }
+
+    pub fn retries(self, val: u32) -> Self {
+        Self {
+            retries: val,
+            ..self
+        }
+    }
+
+    pub fn retry_delay(self, val: Duration) -> Self {
+        Self {
+            retry_delay: val,
+            ..self
+        }
+    }
+
+    pub fn zchunk_deltas(self, val: bool) -> Self {
+        Self {
+            zchunk_deltas: val,
+            ..self
+        }
+    }
+
+    pub fn max_bytes_per_sec(self, val: Option<u64>) -> Self {
+        Self {
+            max_bytes_per_sec: val,
+            ..self
+        }
+    }
+
+    pub fn max_bytes_per_sec_per_connection(self, val: Option<u64>) -> Self {
+        Self {
+            max_bytes_per_sec_per_connection: val,
+            ..self
+        }
+    }
+
+    pub fn on_mismatch(self, val: MismatchPolicy) -> Self {
+        Self {
+            on_mismatch: val,
+            ..self
+        }
+    }
+
+    pub fn verification(self, val: VerifyOptions) -> Self {
+        Self {
+            verification: val,
+            ..self
+        }
+    }
+}
+
+/// What a [`Downloader::sync_to_directory()`] call did.
+///
+/// When a sync succeeds, every file in it (`downloaded` and `skipped`) matches the size and checksum
+/// recorded in the metadata, except that the checksums of the `unverified` ones couldn't be checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// Files which were downloaded, relative to the destination directory
+    pub downloaded: Vec<PathBuf>,
+    /// Files which were already present with the expected checksum, relative to the destination directory
+    pub skipped: Vec<PathBuf>,
+    /// Packages which were not downloaded because of the [`PackageFilter`], relative to the destination directory
+    pub excluded: Vec<PathBuf>,
+    /// Packages which failed verification, see [`DownloadOptions::on_mismatch`]
+    pub failed: Vec<VerificationFailure>,
+    /// Files whose checksums are of a type which isn't supported (see [`Checksum::Other`]), so only their
+    /// size was verified, relative to the destination directory
+    pub unverified: Vec<PathBuf>,
+    /// Total number of bytes transferred
+    pub bytes_downloaded: u64,
+}
+
+/// A downloaded file which didn't match the metadata.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerificationFailure {
+    /// Location of the file, relative to the destination directory
+    pub href: PathBuf,
+    pub expected_checksum: Checksum,
+    /// `None` if the checksum wasn't computed because the size was already wrong
+    pub actual_checksum: Option<Checksum>,
+    pub expected_size: Option<u64>,
+    pub actual_size: u64,
+    /// Where the file was moved to, relative to the destination directory, if it was quarantined
+    pub quarantined_to: Option<PathBuf>,
+}
+
+/// What a [`Downloader::refresh()`] call did.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RefreshOutcome {
+    /// `repomd.xml` hasn't changed since the last sync, so nothing was downloaded
+    Unchanged,
+    /// The repository had changed and was synced again
+    Synced(SyncReport),
+}
+
+/// A freshly fetched `repomd.xml`.
+struct FetchedRepomd {
+    bytes: Vec<u8>,
+    repomd: RepomdData,
+    validators: Validators,
+}
+
+/// Downloads a remote repository into a local directory.
+///
+/// `repomd.xml` is always written last, so that an interrupted sync never leaves behind a `repomd.xml`
+/// referencing files which haven't been downloaded. Files are downloaded to a `.part` file alongside
+/// their final location and only moved into place once they've been verified. If a sync is interrupted,
+/// running it again picks up where it left off (see [`DownloadOptions::resume`]).
+///
+/// A repository can be downloaded from several mirrors. Each file is fetched from the healthiest mirror,
+/// retrying with exponential backoff on transient errors and failing over to the next mirror if that
+/// doesn't help. The health of each mirror is tracked for as long as the `Downloader` lives.
+pub struct Downloader {
+    source: MirrorSource,
+    mirrors: Mirrors,
+    repomd_checksums: Mutex<Vec<Checksum>>,
+    transport: Fetcher,
+    options: DownloadOptions,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    filter: Option<PackageFilter>,
+    metadata_types: Option<Vec<String>>,
+    progress: Option<ProgressCallback>,
+}
+
+/// Where the list of mirrors comes from.
+enum MirrorSource {
+    Fixed,
+    Mirrorlist(String),
+    Metalink(String),
+}
+
+impl Downloader {
+    /// Create a new downloader for the repository at `base_url`, i.e. the URL containing `repodata/`.
+    pub fn new(base_url: &str) -> Self {
+        Self::from_mirrors(&[base_url])
+    }
+
+    /// Create a new downloader for a repository available at several base URLs, in order of preference.
+    pub fn from_mirrors(base_urls: &[&str]) -> Self {
+        let downloader = Self::with_source(MirrorSource::Fixed);
+        downloader.mirrors.set(
+            base_urls
+                .iter()
+                .map(|url| url.trim_end_matches('/').to_owned())
+                .collect(),
+        );
+        downloader
+    }
+
+    /// Create a new downloader which takes its mirrors from the mirrorlist at `url`.
+    ///
+    /// The mirrorlist is fetched when it's first needed.
+    pub fn from_mirrorlist(url: &str) -> Self {
+        Self::with_source(MirrorSource::Mirrorlist(url.to_owned()))
+    }
+
+    /// Create a new downloader which takes its mirrors from the metalink at `url`.
+    ///
+    /// The metalink is fetched when it's first needed, and `repomd.xml` is only accepted from a mirror if
+    /// it matches one of the checksums listed in the metalink.
+    pub fn from_metalink(url: &str) -> Self {
+        Self::with_source(MirrorSource::Metalink(url.to_owned()))
+    }
+
+    fn with_source(source: MirrorSource) -> Self {
+        Downloader {
+            source,
+            mirrors: Mirrors::default(),
+            repomd_checksums: Mutex::new(Vec::new()),
+            transport: Fetcher::Blocking(Box::new(DefaultTransport::default())),
+            options: DownloadOptions::default(),
+            rate_limiter: None,
+            filter: None,
+            metadata_types: None,
+            progress: None,
+        }
+    }
+
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Fetcher::Blocking(Box::new(transport));
+        self
+    }
+
+    /// Fetch files with an [`AsyncTransport`] such as the [`ReqwestTransport`] instead, so that
+    /// [`Downloader::sync_to_directory_async()`] doesn't block any threads while waiting for the network.
+    ///
+    /// The blocking methods keep working, running the transport on a Tokio runtime of their own, so
+    /// they must not be called from within a Tokio runtime.
+    ///
+    /// Only the network is async: the downloaded files are still written and verified with blocking
+    /// file I/O.
+    #[cfg(feature = "tokio")]
+    pub fn with_async_transport<T: AsyncTransport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Fetcher::Async(Box::new(transport));
+        self
+    }
+
+    pub fn with_options(mut self, options: DownloadOptions) -> Self {
+        self.options = options;
+        self.rate_limiter = options
+            .max_bytes_per_sec
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+        self
+    }
+
+    /// Only download the packages selected by `filter` when [`DownloadOptions::download_packages`] is set.
+    pub fn with_filter(mut self, filter: PackageFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Only download the listed metadata types (e.g. `primary`, `updateinfo`) rather than all of them.
+    ///
+    /// `primary` is always downloaded if packages are being downloaded.
+    pub fn metadata_types(mut self, types: &[&str]) -> Self {
+        self.metadata_types = Some(types.iter().map(|t| (*t).to_owned()).collect());
+        self
+    }
+
+    /// Call `progress` with each [`SyncEvent`] of a sync.
+    ///
+    /// It's called on the thread doing the sync, which it should block as little as possible.
+    pub fn with_progress<F: Fn(&SyncEvent) + Send + Sync + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// The health of each mirror so far, in order of preference.
+    ///
+    /// Empty if the mirrors haven't been resolved from a mirrorlist or metalink yet.
+    pub fn mirror_status(&self) -> Vec<MirrorStatus> {
+        self.mirrors.status()
+    }
+
+    /// Fetch and parse `repomd.xml`.
+    pub fn fetch_repomd(&self) -> Result<RepomdData, MetadataError> {
+        self.block_on(async {
+            self.resolve_mirrors().await?;
+            Ok(self.fetch_repomd_bytes().await?.repomd)
+        })
+    }
+
+    /// Fetch only `repomd.xml`, for [`probe_repository()`](crate::probe_repository). Cheaper than
+    /// [`Downloader::refresh()`] when there's no local mirror to bring up to date.
+    pub fn probe(&self) -> Result<RepositoryProbe, MetadataError> {
+        self.block_on(async {
+            self.resolve_mirrors().await?;
+            RepositoryProbe::from_bytes(&self.fetch_repomd_bytes().await?.bytes)
+        })
+    }
+
+    /// Mirror the repository into `path`.
+    pub fn sync_to_directory(&self, path: &Path) -> Result<SyncReport, MetadataError> {
+        self.block_on(self.sync(path))
+    }
+
+    async fn sync(&self, path: &Path) -> Result<SyncReport, MetadataError> {
+        self.resolve_mirrors().await?;
+        let fetched = self.fetch_repomd_bytes().await?;
+        self.sync_repomd(path, fetched, None).await
+    }
+
+    /// Download the metadata selected by `options` into `path` and load it into a [`Repository`].
+    ///
+    /// Only the selected metadata files are downloaded, whatever [`Downloader::metadata_types()`] says, and
+    /// packages aren't downloaded even if [`DownloadOptions::download_packages`] is set. Metadata which is
+    /// already up to date in `path` is reused, like [`Downloader::sync_to_directory()`] does.
+    pub fn load_repository(
+        &self,
+        path: &Path,
+        options: LoadOptions,
+    ) -> Result<(Repository, ParseReport), MetadataError> {
+        self.block_on(async {
+            self.resolve_mirrors().await?;
+            let fetched = self.fetch_repomd_bytes().await?;
+            self.sync_repomd(path, fetched, Some(options.metadata))
+                .await
+        })?;
+        Repository::load_from_directory_with_options(path, options)
+    }
+
+    /// Mirror the repository into `path` in the background.
+    ///
+    /// The returned [`SyncTask`] is a future which resolves to the result of the sync, and also provides
+    /// the [`SyncEvent`]s as they happen. This allows running a sync from async code without blocking the
+    /// executor.
+    ///
+    /// With an [`AsyncTransport`] (see [`Downloader::with_async_transport()`], requires the `tokio`
+    /// feature), the sync runs as a task on the current Tokio runtime and doesn't occupy a thread while
+    /// it waits for the network. Otherwise transfers use the (blocking) [`Transport`], on the threads of
+    /// the current [`Runtime`](crate::Runtime), so syncs started when they're all busy wait for one of
+    /// them to finish rather than starting threads of their own.
+    pub fn sync_to_directory_async(self, path: &Path) -> SyncTask {
+        SyncTask::spawn(self, path)
+    }
+
+    /// Bring a mirror previously created with [`Downloader::sync_to_directory()`] up to date, doing
+    /// nothing if `repomd.xml` hasn't changed since.
+    ///
+    /// The `ETag` and `Last-Modified` headers of the last `repomd.xml` fetched are stored alongside the
+    /// mirror and sent with the request, so that checking an unchanged repository usually costs a single
+    /// `304 Not Modified` response.
+    pub fn refresh(&self, path: &Path) -> Result<RefreshOutcome, MetadataError> {
+        self.block_on(self.refresh_mirror(path))
+    }
+
+    async fn refresh_mirror(&self, path: &Path) -> Result<RefreshOutcome, MetadataError> {
+        self.resolve_mirrors().await?;
+        let local = fs::read(path.join("repodata").join("repomd.xml")).ok();
+        let validators = match &local {
+            Some(_) => Validators::load(path)?,
+            None => Validators::default(),
+        };
+
+        match self.fetch_repomd_if_changed(&validators).await? {
+            None => {
+                logging::debug!("repomd.xml not modified");
+                Ok(RefreshOutcome::Unchanged)
+            }
+            // the server doesn't support validators, or a different mirror was used
+            Some(fetched) if local.as_deref() == Some(fetched.bytes.as_slice()) => {
+                fetched.validators.save(path)?;
+                Ok(RefreshOutcome::Unchanged)
+            }
+            Some(fetched) => Ok(RefreshOutcome::Synced(
+                self.sync_repomd(path, fetched, None).await?,
+            )),
+        }
+    }
+
+    /// Download the files of `fetched` into `path`, either the ones the `Downloader` is configured for or
+    /// only the metadata files in `selection`.
+    async fn sync_repomd(
+        &self,
+        path: &Path,
+        fetched: FetchedRepomd,
+        selection: Option<MetadataSelection>,
+    ) -> Result<SyncReport, MetadataError> {
+        let download_packages = self.options.download_packages && selection.is_none();
+        let FetchedRepomd {
+            bytes: repomd_bytes,
+            repomd,
+            validators,
+        } = fetched;
+        let _span = logging::span!("sync to {}", path.display());
+        let mut session = SyncSession {
+            dest_dir: path,
+            report: SyncReport::default(),
+            state: if self.options.resume {
+                SyncState::load(path)?
+            } else {
+                SyncState::disabled()
+            },
+            verified: HashMap::new(),
+        };
+
+        let previous_repomd = local_repomd(path);
+        logging::debug!(
+            "repomd.xml lists {} metadata records",
+            repomd.records().len()
+        );
+
+        for record in repomd.records() {
+            let wanted = match (selection, &self.metadata_types) {
+                (Some(selection), _) => selection.contains_type(&record.metadata_type),
+                (None, Some(types)) => {
+                    types.iter().any(|t| record.metadata_type == t.as_str())
+                        || (download_packages && record.metadata_type == MetadataType::Primary)
+                }
+                (None, None) => true,
+            };
+            if !wanted {
+                continue;
+            }
+            let previous = previous_repomd
+                .as_ref()
+                .and_then(|repomd| repomd.get_record(record.metadata_type.as_str()));
+            if let Some(previous) = previous {
+                if self
+                    .try_zchunk_delta(&mut session, record, previous)
+                    .await?
+                {
+                    continue;
+                }
+            }
+            self.download_file(
+                &mut session,
+                record.location_base.as_deref(),
+                &record.location_href,
+                &record.checksum,
+                record.size,
+                MismatchPolicy::Fail,
+            )
+            .await?;
+        }
+
+        if download_packages {
+            let primary = repomd.get_record(METADATA_PRIMARY).ok_or_else(|| {
+                MetadataError::InconsistentMetadataError(
+                    "repomd.xml has no primary record".to_owned(),
+                )
+            })?;
+            let mut packages = read_packages(&path.join(&primary.location_href))?;
+            if let Some(filter) = &self.filter {
+                let (kept, excluded) = filter.partition(packages);
+                session.report.excluded = excluded
+                    .iter()
+                    .map(|p| PathBuf::from(p.location_href()))
+                    .collect();
+                packages = kept;
+            }
+
+            if self.options.verify_checksums {
+                self.verify_existing(&mut session, &packages);
+            }
+            let _span = logging::span!("download packages");
+            for package in packages {
+                self.download_file(
+                    &mut session,
+                    package.location_base(),
+                    Path::new(package.location_href()),
+                    package.checksum(),
+                    Some(package.size_package()),
+                    self.options.on_mismatch,
+                )
+                .await?;
+            }
+        }
+
+        let repomd_path = path.join("repodata").join("repomd.xml");
+        fs::create_dir_all(repomd_path.parent().unwrap())?;
+        fs::write(&repomd_path, &repomd_bytes)?;
+        validators.save(path)?;
+        session.state.compact()?;
+
+        let mut report = session.report;
+        report.downloaded.push(PathBuf::from("repodata/repomd.xml"));
+        report.bytes_downloaded += repomd_bytes.len() as u64;
+        self.emit(SyncEvent::FileFinished {
+            href: PathBuf::from("repodata/repomd.xml"),
+            bytes: repomd_bytes.len() as u64,
+        });
+
+        Ok(report)
+    }
+
+    /// Fetch the mirrorlist or metalink, if the mirrors come from one and haven't been fetched yet.
+    async fn resolve_mirrors(&self) -> Result<(), MetadataError> {
+        if !self.mirrors.is_empty() {
+            return Ok(());
+        }
+        let (url, mirrors) = match &self.source {
+            MirrorSource::Fixed => return Ok(()),
+            MirrorSource::Mirrorlist(url) => {
+                let bytes = self.fetch_bytes(url).await?;
+                (url, parse_mirrorlist(std::str::from_utf8(&bytes)?))
+            }
+            MirrorSource::Metalink(url) => {
+                let metalink = Metalink::parse(&self.fetch_bytes(url).await?)?;
+                *self.repomd_checksums.lock().unwrap() = metalink.repomd_checksums;
+                (url, metalink.mirrors)
+            }
+        };
+        if mirrors.is_empty() {
+            return Err(MetadataError::DownloadError(
+                url.clone(),
+                "no mirrors listed".to_owned(),
+            ));
+        }
+        logging::debug!("{} lists {} mirrors", url, mirrors.len());
+        self.mirrors.set(mirrors);
+        Ok(())
+    }
+
+    /// Fetch a small file (e.g. a mirrorlist) into memory, retrying transient failures.
+    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, MetadataError> {
+        let mut attempts = Attempts::retries(self, url);
+        loop {
+            attempts.next()?;
+            let result = match self.get(&Request::new(url)).await {
+                Ok(mut response) => response.body.read_to_end(None).await.map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            if let Some(result) = attempts.settle(result).await {
+                return result;
+            }
+        }
+    }
+
+    async fn fetch_repomd_bytes(&self) -> Result<FetchedRepomd, MetadataError> {
+        let fetched = self.fetch_repomd_if_changed(&Validators::default()).await?;
+        Ok(fetched.expect("unconditional requests are never answered with 304 Not Modified"))
+    }
+
+    /// Fetch `repomd.xml` unless it still matches `validators`, in which case `None` is returned.
+    async fn fetch_repomd_if_changed(
+        &self,
+        validators: &Validators,
+    ) -> Result<Option<FetchedRepomd>, MetadataError> {
+        let mut attempts = Attempts::mirrors(self, None);
+        loop {
+            let mirror = attempts.next()?;
+            let result = self.fetch_repomd_from(&mirror, validators).await;
+            if let Some(result) = attempts.settle(result).await {
+                return result;
+            }
+        }
+    }
+
+    async fn fetch_repomd_from(
+        &self,
+        mirror: &str,
+        validators: &Validators,
+    ) -> Result<Option<FetchedRepomd>, MetadataError> {
+        {
+            let url = format!("{}/repodata/repomd.xml", mirror);
+            let mut request = Request::new(&url);
+            if let Some(etag) = &validators.etag {
+                request = request.header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header("If-Modified-Since", last_modified);
+            }
+            let mut response = self.send(&request).await?;
+            if response.status == 304 && !validators.is_empty() {
+                return Ok(None);
+            }
+            if !response.is_success() {
+                return Err(MetadataError::HttpStatusError(url, response.status));
+            }
+            let validators = Validators {
+                etag: response.header("ETag").map(str::to_owned),
+                last_modified: response.header("Last-Modified").map(str::to_owned),
+            };
+            let bytes = response.body.read_to_end(None).await?;
+
+            let repomd_checksums = self.repomd_checksums.lock().unwrap().clone();
+            if let Some(expected) = repomd_checksums.first() {
+                let matches = repomd_checksums.iter().any(|checksum| {
+                    utils::checksum_bytes(&bytes, checksum.checksum_type())
+                        .is_ok_and(|actual| &actual == checksum)
+                });
+                if !matches {
+                    let actual = utils::checksum_bytes(&bytes, expected.checksum_type())?;
+                    return Err(MetadataError::ChecksumMismatchError(
+                        url,
+                        checksum_key(expected)?,
+                        checksum_key(&actual)?,
+                    ));
+                }
+            }
+
+            let mut repo = Repository::new();
+            repo.load_metadata_bytes::<RepomdXml>(&bytes)?;
+            let repomd = std::mem::take(repo.repomd_mut());
+            Ok(Some(FetchedRepomd {
+                bytes,
+                repomd,
+                validators,
+            }))
+        }
+    }
+
+    /// Send `request` through the transport, throttling the response body.
+    async fn send(&self, request: &Request) -> Result<Fetched, MetadataError> {
+        let mut response = self.transport.fetch(request).await?;
+        if let Some(rate) = self.options.max_bytes_per_sec_per_connection {
+            response.body.throttle(Arc::new(RateLimiter::new(rate)));
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            response.body.throttle(limiter.clone());
+        }
+        Ok(response)
+    }
+
+    async fn get(&self, request: &Request) -> Result<Fetched, MetadataError> {
+        let response = self.send(request).await?;
+        if !response.is_success() {
+            return Err(MetadataError::HttpStatusError(
+                request.url.clone(),
+                response.status,
+            ));
+        }
+        Ok(response)
+    }
+
+    async fn download_file(
+        &self,
+        session: &mut SyncSession<'_>,
+        base: Option<&str>,
+        href: &Path,
+        checksum: &Checksum,
+        size: Option<u64>,
+        on_mismatch: MismatchPolicy,
+    ) -> Result<(), MetadataError> {
+        let href_str = href.to_string_lossy();
+        // Don't let a hostile repository write outside of the destination directory
+        utils::check_href(href, "location_href")?;
+
+        let dest = session.dest_dir.join(href);
+        let key = checksum_key(checksum)?;
+        if dest.exists() {
+            let verified_before = session.state.is_complete(href, &key)
+                && size.is_none_or(|size| fs::metadata(&dest).is_ok_and(|m| m.len() == size));
+            let up_to_date = verified_before
+                || match session.verified.remove(href) {
+                    Some(ok) => ok,
+                    None => self.verify(&dest, &href_str, checksum, size).is_ok(),
+                };
+            if up_to_date {
+                logging::trace!("{} is up to date", dest.display());
+                session.state.mark_complete(href, &key)?;
+                self.note_unverified(session, href, checksum);
+                session.report.skipped.push(href.to_owned());
+                self.emit(SyncEvent::FileSkipped {
+                    href: href.to_owned(),
+                });
+                return Ok(());
+            }
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let part = part_path(&dest);
+        let mut failure = None;
+        self.emit(SyncEvent::FileStarted {
+            href: href.to_owned(),
+            size,
+        });
+        let mut attempts = Attempts::mirrors(self, base);
+        let result = loop {
+            let mirror = match attempts.next() {
+                Ok(mirror) => mirror,
+                Err(e) => break Err(e),
+            };
+            let result: Result<u64, MetadataError> = async {
+                let url = format!("{}/{}", mirror, href_str);
+                // Only resume a partial download if it was started for this same version of the file
+                let offset = match fs::metadata(&part) {
+                    Ok(m) if self.options.resume && session.state.is_partial(href, &key) => m.len(),
+                    _ => 0,
+                };
+                session.state.mark_partial(href, &key)?;
+                let checksum_type = self.checksum_type_to_verify(checksum);
+                let (count, actual_checksum) = self
+                    .fetch_to_file(&url, href, &part, offset, checksum_type)
+                    .await?;
+
+                let actual_size = fs::metadata(&part)?.len();
+                let check = check_file(&url, checksum, size, actual_checksum.as_ref(), actual_size);
+                if let Err(e) = check {
+                    let quarantined_to = match on_mismatch {
+                        MismatchPolicy::Quarantine => {
+                            Some(quarantine(session.dest_dir, href, &part)?)
+                        }
+                        _ => {
+                            fs::remove_file(&part)?;
+                            None
+                        }
+                    };
+                    failure = Some(VerificationFailure {
+                        href: href.to_owned(),
+                        expected_checksum: checksum.clone(),
+                        actual_checksum: actual_checksum
+                            .filter(|_| size.is_none_or(|s| s == actual_size)),
+                        expected_size: size,
+                        actual_size,
+                        quarantined_to,
+                    });
+                    return Err(e);
+                }
+                failure = None;
+                Ok(count)
+            }
+            .await;
+            if let Some(result) = attempts.settle(result).await {
+                break result;
+            }
+        };
+        let count = match (result, failure) {
+            (Ok(count), _) => count,
+            (Err(_), Some(failure)) if on_mismatch != MismatchPolicy::Fail => {
+                logging::debug!("{} failed verification", href_str);
+                self.emit(SyncEvent::FileFailed(failure.clone()));
+                session.report.failed.push(failure);
+                return Ok(());
+            }
+            (Err(e), _) => return Err(e),
+        };
+        fs::rename(&part, &dest)?;
+        session.state.mark_complete(href, &key)?;
+        self.note_unverified(session, href, checksum);
+
+        session.report.downloaded.push(href.to_owned());
+        session.report.bytes_downloaded += count;
+        self.emit(SyncEvent::FileFinished {
+            href: href.to_owned(),
+            bytes: count,
+        });
+        Ok(())
+    }
+
+    /// Try to assemble a new version of a zchunk metadata file from the `previous` one. Returns `false`
+    /// (after cleaning up) if that isn't possible, in which case the file should be downloaded in full.
+    async fn try_zchunk_delta(
+        &self,
+        session: &mut SyncSession<'_>,
+        record: &RepomdRecord,
+        previous: &RepomdRecord,
+    ) -> Result<bool, MetadataError> {
+        let href = &record.location_href;
+        let previous_path = session.dest_dir.join(&previous.location_href);
+        let dest = session.dest_dir.join(href);
+        let Some(header_size) = record.header_size else {
+            return Ok(false);
+        };
+        if !self.options.zchunk_deltas
+            || href.extension().is_none_or(|ext| ext != "zck")
+            || previous.location_href == *href
+            || !previous_path.exists()
+            || dest.exists()
+            || utils::check_href(href, "location_href").is_err()
+        {
+            return Ok(false);
+        }
+
+        let part = part_path(&dest);
+        self.emit(SyncEvent::FileStarted {
+            href: href.to_owned(),
+            size: record.size,
+        });
+        let mut attempts = Attempts::mirrors(self, record.location_base.as_deref());
+        let result = loop {
+            let mirror = match attempts.next() {
+                Ok(mirror) => mirror,
+                Err(e) => break Err(e),
+            };
+            let url = format!("{}/{}", mirror, href.display());
+            let result = zchunk::assemble(
+                &url,
+                &previous_path,
+                &part,
+                header_size,
+                record.header_checksum.as_ref(),
+                |offset, length| self.fetch_range(&url, offset, length),
+            )
+            .await
+            .and_then(|count| {
+                self.verify(&part, &url, &record.checksum, record.size)?;
+                Ok(count)
+            });
+            if let Some(result) = attempts.settle(result).await {
+                break result;
+            }
+        };
+        let count = match result {
+            Ok(count) => count,
+            Err(e) => {
+                logging::debug!(
+                    "falling back to a full download of {}: {}",
+                    href.display(),
+                    e
+                );
+                let _ = fs::remove_file(&part);
+                return Ok(false);
+            }
+        };
+        fs::rename(&part, &dest)?;
+        session
+            .state
+            .mark_complete(href, &checksum_key(&record.checksum)?)?;
+        self.note_unverified(session, href, &record.checksum);
+
+        session.report.downloaded.push(href.to_owned());
+        session.report.bytes_downloaded += count;
+        self.emit(SyncEvent::FileFinished {
+            href: href.to_owned(),
+            bytes: count,
+        });
+        Ok(true)
+    }
+
+    /// Fetch `length` bytes of `url` starting at `offset`.
+    async fn fetch_range(
+        &self,
+        url: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, MetadataError> {
+        let range = format!("bytes={}-{}", offset, offset + length - 1);
+        let mut response = self.get(&Request::new(url).header("Range", &range)).await?;
+        if response.status != 206 {
+            return Err(MetadataError::DownloadError(
+                url.to_owned(),
+                "the server doesn't support range requests".to_owned(),
+            ));
+        }
+        let data = response.body.read_to_end(Some(length)).await?;
+        if data.len() as u64 != length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(data)
+    }
+
+    /// Download `url` (the file at `href`) into `path`, continuing from `offset` if the server supports
+    /// range requests.
+    ///
+    /// Returns the number of bytes transferred.
+    /// If `checksum_type` is provided, the checksum of the whole file is computed along the way.
+    async fn fetch_to_file(
+        &self,
+        url: &str,
+        href: &Path,
+        path: &Path,
+        mut offset: u64,
+        checksum_type: Option<ChecksumType>,
+    ) -> Result<(u64, Option<Checksum>), MetadataError> {
+        let (mut response, resumed) = loop {
+            let mut request = Request::new(url);
+            if offset > 0 {
+                logging::debug!("resuming {} at byte {}", url, offset);
+                request = request.header("Range", &format!("bytes={}-", offset));
+            }
+
+            let response = self.send(&request).await?;
+            let resumed = offset > 0
+                && response.status == 206
+                && response
+                    .header("Content-Range")
+                    .is_some_and(|range| range.starts_with(&format!("bytes {}-", offset)));
+            if offset > 0 && !resumed && response.status != 200 {
+                // e.g. 416 if the partial file is somehow longer than the real one
+                offset = 0;
+                continue;
+            }
+            break (response, resumed);
+        };
+        if !response.is_success() {
+            return Err(MetadataError::HttpStatusError(
+                url.to_owned(),
+                response.status,
+            ));
+        }
+
+        let file = if resumed {
+            OpenOptions::new().append(true).open(path)?
+        } else {
+            File::create(path)?
+        };
+        let mut writer = HashingWriter::new(BufWriter::new(file), checksum_type);
+        if resumed && checksum_type.is_some() {
+            // the part which was downloaded before has to be hashed, too
+            let mut existing = File::open(path)?;
+            let mut buffer = [0; 8192];
+            loop {
+                let count = existing.read(&mut buffer)?;
+                if count == 0 {
+                    break;
+                }
+                writer.hash_existing(&buffer[..count]);
+            }
+        }
+        let start = if resumed { offset } else { 0 };
+        let mut progress = self
+            .progress
+            .as_ref()
+            .map(|progress| Progress::new(href, progress, start));
+        let mut buffer = vec![0; 64 * 1024];
+        let mut count = 0;
+        loop {
+            let read = response.body.read(&mut buffer).await?;
+            if let Some(progress) = &mut progress {
+                progress.advance(read);
+            }
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..read])?;
+            count += read as u64;
+        }
+        let (writer, checksum) = writer.finish();
+        writer.into_inner().map_err(|e| e.into_error())?;
+
+        Ok((count, checksum))
+    }
+
+    /// Run `future` to completion on the calling thread. With an async transport that takes a Tokio
+    /// runtime, which is started for the purpose.
+    fn block_on<T>(
+        &self,
+        future: impl Future<Output = Result<T, MetadataError>>,
+    ) -> Result<T, MetadataError> {
+        match &self.transport {
+            Fetcher::Blocking(_) => fetch::block_on(future),
+            #[cfg(feature = "tokio")]
+            Fetcher::Async(_) => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(future),
+        }
+    }
+
+    fn emit(&self, event: SyncEvent) {
+        if let Some(progress) = &self.progress {
+            progress(&event);
+        }
+    }
+
+    /// The checksum type to compute for a file expected to have `checksum`, or `None` if it's not verified.
+    fn checksum_type_to_verify(&self, checksum: &Checksum) -> Option<ChecksumType> {
+        let checksum_type = checksum.checksum_type();
+        (self.options.verify_checksums && checksum_type != ChecksumType::Unknown)
+            .then_some(checksum_type)
+    }
+
+    /// Record `href` as unverified if its checksum should have been verified, but is of an unsupported type.
+    fn note_unverified(&self, session: &mut SyncSession, href: &Path, checksum: &Checksum) {
+        if self.options.verify_checksums && matches!(checksum, Checksum::Other(..)) {
+            logging::debug!("can't verify the checksum of {}", href.display());
+            session.report.unverified.push(href.to_owned());
+        }
+    }
+
+    /// Verify the `packages` which are already in the destination directory all at once, rather than one
+    /// at a time as they come up, except those which an earlier sync verified.
+    fn verify_existing(&self, session: &mut SyncSession, packages: &[Package]) {
+        let mut hrefs = Vec::new();
+        let mut files = Vec::new();
+        for package in packages {
+            let href = Path::new(package.location_href());
+            let dest = session.dest_dir.join(href);
+            let size = package.size_package();
+            let verified_before = checksum_key(package.checksum())
+                .is_ok_and(|key| session.state.is_complete(href, &key))
+                && fs::metadata(&dest).is_ok_and(|m| m.len() == size);
+            // the others are refused by download_file()
+            let safe = utils::check_href(href, "location_href").is_ok();
+            if safe && !verified_before && dest.exists() {
+                hrefs.push(href.to_owned());
+                files.push(FileCheck::new(dest, package.checksum().clone(), Some(size)));
+            }
+        }
+        if files.is_empty() {
+            return;
+        }
+        let report = verify_files(&files, self.options.verification);
+        for (href, (_, status)) in hrefs.into_iter().zip(report.files) {
+            session.verified.insert(href, status.is_ok());
+        }
+    }
+
+    fn verify(
+        &self,
+        path: &Path,
+        url: &str,
+        checksum: &Checksum,
+        size: Option<u64>,
+    ) -> Result<(), MetadataError> {
+        let actual_size = fs::metadata(path)?.len();
+        check_file(url, checksum, size, None, actual_size)?;
+        match self.checksum_type_to_verify(checksum) {
+            Some(checksum_type) => {
+                let actual = utils::checksum_file(path, checksum_type)?;
+                check_file(url, checksum, size, Some(&actual), actual_size)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// The attempts at fetching something from the mirrors (healthiest first), retrying transient failures
+/// with backoff and failing over to the next mirror if that doesn't help.
+struct Attempts<'a> {
+    downloader: &'a Downloader,
+    mirrors: std::vec::IntoIter<String>,
+    /// The mirror being attempted, `None` once it has been given up on
+    mirror: Option<String>,
+    fail_over: bool,
+    retries: u32,
+    delay: Duration,
+    last_error: Option<MetadataError>,
+}
+
+impl<'a> Attempts<'a> {
+    /// Attempts against the mirrors of `downloader`, or only against `base` if it's provided.
+    fn mirrors(downloader: &'a Downloader, base: Option<&str>) -> Self {
+        let mirrors = match base {
+            Some(base) => vec![base.trim_end_matches('/').to_owned()],
+            None => downloader.mirrors.ordered(),
+        };
+        Self::new(downloader, mirrors, true)
+    }
+
+    /// Attempts against `url` (e.g. a mirrorlist) alone, which are retried but don't fail over.
+    fn retries(downloader: &'a Downloader, url: &str) -> Self {
+        Self::new(downloader, vec![url.to_owned()], false)
+    }
+
+    fn new(downloader: &'a Downloader, mirrors: Vec<String>, fail_over: bool) -> Self {
+        Attempts {
+            downloader,
+            mirrors: mirrors.into_iter(),
+            mirror: None,
+            fail_over,
+            retries: 0,
+            delay: downloader.options.retry_delay,
+            last_error: None,
+        }
+    }
+
+    /// The mirror to make the next attempt against, or the last error once they've all been given up on.
+    fn next(&mut self) -> Result<String, MetadataError> {
+        if self.mirror.is_none() {
+            self.mirror = Some(self.mirrors.next().ok_or_else(|| {
+                self.last_error.take().unwrap_or_else(|| {
+                    MetadataError::DownloadError(String::new(), "no mirrors available".to_owned())
+                })
+            })?);
+            self.retries = 0;
+            self.delay = self.downloader.options.retry_delay;
+        }
+        Ok(self.mirror.clone().unwrap())
+    }
+
+    /// Record the `result` of an attempt, returning it unless another attempt is worth making.
+    async fn settle<T>(
+        &mut self,
+        result: Result<T, MetadataError>,
+    ) -> Option<Result<T, MetadataError>> {
+        let downloader = self.downloader;
+        let mirror = self.mirror.as_deref().expect("an attempt was made");
+        let error = match result {
+            Ok(value) => {
+                if self.fail_over {
+                    downloader.mirrors.record_success(mirror);
+                }
+                return Some(Ok(value));
+            }
+            Err(e) => e,
+        };
+        downloader.mirrors.record_failure(mirror);
+        if self.retries < downloader.options.retries && is_transient(&error) {
+            logging::debug!("retrying in {:?} after error: {}", self.delay, error);
+            downloader.transport.pause(self.delay).await;
+            self.delay = (self.delay * 2).min(MAX_RETRY_DELAY);
+            self.retries += 1;
+            return None;
+        }
+        if self.fail_over && can_fail_over(&error) {
+            logging::debug!("giving up on mirror {}: {}", mirror, error);
+            self.last_error = Some(error);
+            self.mirror = None;
+            return None;
+        }
+        Some(Err(error))
+    }
+}
+
+/// Compare the size and (if it was computed) checksum of a file with the expected ones.
+fn check_file(
+    url: &str,
+    checksum: &Checksum,
+    size: Option<u64>,
+    actual_checksum: Option<&Checksum>,
+    actual_size: u64,
+) -> Result<(), MetadataError> {
+    if let Some(size) = size {
+        if actual_size != size {
+            return Err(MetadataError::DownloadError(
+                url.to_owned(),
+                format!("expected {} bytes, received {}", size, actual_size),
+            ));
+        }
+    }
+    if let Some(actual) = actual_checksum {
+        if actual != checksum {
+            return Err(MetadataError::ChecksumMismatchError(
+                url.to_owned(),
+                checksum.to_values()?.1.to_owned(),
+                actual.to_values()?.1.to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Read the packages listed in the primary metadata file at `path`.
+fn read_packages(path: &Path) -> Result<Vec<Package>, MetadataError> {
+    let mut reader = PrimaryXml::new_reader(utils::xml_reader_from_file(path)?);
+    reader.read_header()?;
+
+    let mut packages = Vec::new();
+    loop {
+        let mut package = None;
+        reader.read_package(&mut package)?;
+        let Some(package) = package else {
+            break;
+        };
+        packages.push(package);
+    }
+    Ok(packages)
+}
+
+/// Move the corrupt download `part` of `href` into the quarantine directory, returning its new location
+/// relative to `dest_dir`.
+fn quarantine(dest_dir: &Path, href: &Path, part: &Path) -> Result<PathBuf, MetadataError> {
+    let quarantined = Path::new(QUARANTINE_DIR).join(href);
+    let path = dest_dir.join(&quarantined);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::rename(part, &path)?;
+    Ok(quarantined)
+}
+
+/// The `repomd.xml` left behind by a previous sync into `path`, if any.
+fn local_repomd(path: &Path) -> Option<RepomdData> {
+    let mut repo = Repository::new();
+    repo.load_metadata_file::<RepomdXml>(&path.join("repodata/repomd.xml"))
+        .ok()?;
+    Some(std::mem::take(repo.repomd_mut()))
+}
+
+/// Whether retrying the same request could plausibly succeed.
+fn is_transient(error: &MetadataError) -> bool {
+    match error {
+        MetadataError::IoError(_) => true,
+        MetadataError::HttpStatusError(_, status) => {
+            *status >= 500 || *status == 408 || *status == 429
+        }
+        _ => false,
+    }
+}
+
+/// Whether another mirror could plausibly succeed where one failed.
+fn can_fail_over(error: &MetadataError) -> bool {
+    matches!(
+        error,
+        MetadataError::IoError(_)
+            | MetadataError::HttpStatusError(..)
+            | MetadataError::DownloadError(..)
+            | MetadataError::ChecksumMismatchError(..)
+            | MetadataError::XmlParseError(_)
+            | MetadataError::Utf8Error(_)
+            | MetadataError::DecompressionError(_)
+    )
+}
+
+/// Per-call state of [`Downloader::sync_to_directory()`].
+struct SyncSession<'a> {
+    dest_dir: &'a Path,
+    report: SyncReport,
+    state: SyncState,
+    /// Whether each of the files which were verified by [`Downloader::verify_existing()`] was ok
+    verified: HashMap<PathBuf, bool>,
+}
+
+fn checksum_key(checksum: &Checksum) -> Result<String, MetadataError> {
+    match checksum {
+        Checksum::Empty => Ok("none".to_owned()),
+        _ => {
+            let (checksum_type, value) = checksum.to_values()?;
+            Ok(format!("{}:{}", checksum_type, value))
+        }
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
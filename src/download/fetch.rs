@@ -0,0 +1,185 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::pin;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::throttle::RateLimiter;
+#[cfg(feature = "tokio")]
+use super::AsyncTransport;
+use super::{Request, Transport};
+use crate::MetadataError;
+
+/// The transport of a [`Downloader`](super::Downloader).
+///
+/// The sync itself is written as async code. With a blocking [`Transport`] every future it awaits is
+/// ready immediately, so it can be driven to completion on the calling thread.
+pub(crate) enum Fetcher {
+    Blocking(Box<dyn Transport>),
+    #[cfg(feature = "tokio")]
+    Async(Box<dyn AsyncTransport>),
+}
+
+impl Fetcher {
+    #[cfg(feature = "tokio")]
+    pub(crate) fn is_async(&self) -> bool {
+        !matches!(self, Fetcher::Blocking(_))
+    }
+
+    pub(crate) async fn fetch(&self, request: &Request) -> Result<Fetched, MetadataError> {
+        match self {
+            Fetcher::Blocking(transport) => {
+                let response = transport.fetch(request)?;
+                Ok(Fetched {
+                    status: response.status,
+                    headers: response.headers,
+                    body: Body::new(BodyReader::Blocking(response.body)),
+                })
+            }
+            #[cfg(feature = "tokio")]
+            Fetcher::Async(transport) => {
+                let response = transport.fetch(request).await?;
+                Ok(Fetched {
+                    status: response.status,
+                    headers: response.headers,
+                    body: Body::new(BodyReader::Async(response.body)),
+                })
+            }
+        }
+    }
+
+    /// Wait for `delay`, without blocking the executor if the transport is async.
+    pub(crate) async fn pause(&self, delay: Duration) {
+        match self {
+            Fetcher::Blocking(_) => thread::sleep(delay),
+            #[cfg(feature = "tokio")]
+            Fetcher::Async(_) => tokio::time::sleep(delay).await,
+        }
+    }
+}
+
+/// Wakes up the thread running [`block_on()`].
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run `future` to completion on the calling thread, parking it while the future is pending.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+            return value;
+        }
+        thread::park();
+    }
+}
+
+/// A response from either kind of transport.
+pub(crate) struct Fetched {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Body,
+}
+
+impl Fetched {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+enum BodyReader {
+    Blocking(Box<dyn Read + Send>),
+    #[cfg(feature = "tokio")]
+    Async(Pin<Box<dyn AsyncRead + Send>>),
+}
+
+/// The body of a [`Fetched`] response, read only as quickly as its rate limiters allow.
+pub(crate) struct Body {
+    reader: BodyReader,
+    limiters: Vec<Arc<RateLimiter>>,
+}
+
+impl Body {
+    fn new(reader: BodyReader) -> Self {
+        Body {
+            reader,
+            limiters: Vec::new(),
+        }
+    }
+
+    pub(crate) fn throttle(&mut self, limiter: Arc<RateLimiter>) {
+        self.limiters.push(limiter);
+    }
+
+    pub(crate) async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self
+            .limiters
+            .iter()
+            .map(|l| l.max_read())
+            .fold(buf.len(), usize::min);
+        let count = match &mut self.reader {
+            BodyReader::Blocking(reader) => reader.read(&mut buf[..len])?,
+            #[cfg(feature = "tokio")]
+            BodyReader::Async(reader) => reader.read(&mut buf[..len]).await?,
+        };
+        let delay = self
+            .limiters
+            .iter()
+            .map(|l| l.consume(count))
+            .max()
+            .unwrap_or_default();
+        if !delay.is_zero() {
+            match self.reader {
+                BodyReader::Blocking(_) => thread::sleep(delay),
+                #[cfg(feature = "tokio")]
+                BodyReader::Async(_) => tokio::time::sleep(delay).await,
+            }
+        }
+        Ok(count)
+    }
+
+    /// Read the rest of the body, or only up to `limit` bytes of it.
+    pub(crate) async fn read_to_end(&mut self, limit: Option<u64>) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let remaining = limit.map_or(buffer.len() as u64, |l| l - data.len() as u64);
+            let len = buffer.len().min(remaining as usize);
+            if len == 0 {
+                break;
+            }
+            let count = self.read(&mut buffer[..len]).await?;
+            if count == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..count]);
+        }
+        Ok(data)
+    }
+}
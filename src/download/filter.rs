@@ -0,0 +1,128 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use crate::utils::glob_match;
+use crate::Package;
+
+/// Selects which packages a [`Downloader`](crate::Downloader) fetches.
+///
+/// - `newest_only` - Only keep the newest version of each package name and architecture.
+/// - `arches` - Only keep packages built for one of these architectures. `noarch` and `src` have to be
+///   listed explicitly if they're wanted.
+/// - `exclude_debuginfo` - Skip `-debuginfo` and `-debugsource` packages.
+/// - `exclude_source` - Skip source packages.
+/// - `excludes` - Skip packages whose name or NEVRA matches one of these glob patterns (`*`, `?`, `[...]`).
+///
+/// The metadata is always mirrored unmodified, so it still lists the packages which were filtered out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PackageFilter {
+    pub newest_only: bool,
+    pub arches: Option<Vec<String>>,
+    pub exclude_debuginfo: bool,
+    pub exclude_source: bool,
+    pub excludes: Vec<String>,
+}
+
+impl PackageFilter {
+    pub fn newest_only(self, val: bool) -> Self {
+        Self {
+            newest_only: val,
+            ..self
+        }
+    }
+
+    pub fn arches(self, arches: &[&str]) -> Self {
+        Self {
+            arches: Some(arches.iter().map(|a| (*a).to_owned()).collect()),
+            ..self
+        }
+    }
+
+    pub fn exclude_debuginfo(self, val: bool) -> Self {
+        Self {
+            exclude_debuginfo: val,
+            ..self
+        }
+    }
+
+    pub fn exclude_source(self, val: bool) -> Self {
+        Self {
+            exclude_source: val,
+            ..self
+        }
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.excludes.push(pattern.to_owned());
+        self
+    }
+
+    /// Whether `package` passes the filters which don't depend on the other packages.
+    pub fn matches(&self, package: &Package) -> bool {
+        let name = package.name();
+        let arch = package.arch();
+        if self
+            .arches
+            .as_ref()
+            .is_some_and(|arches| !arches.iter().any(|a| a == arch))
+        {
+            return false;
+        }
+        if self.exclude_source && (arch == "src" || arch == "nosrc") {
+            return false;
+        }
+        if self.exclude_debuginfo
+            && (name.ends_with("-debuginfo")
+                || name.ends_with("-debugsource")
+                || name.contains("-debuginfo-"))
+        {
+            return false;
+        }
+        let nevra = package.nevra();
+        !self
+            .excludes
+            .iter()
+            .any(|pattern| glob_match(pattern, name) || glob_match(pattern, &nevra))
+    }
+
+    /// Filter `packages`, preserving their order.
+    pub fn apply(&self, packages: Vec<Package>) -> Vec<Package> {
+        self.partition(packages).0
+    }
+
+    /// Split `packages` into those which pass the filter and those which don't, preserving their order.
+    pub(crate) fn partition(&self, packages: Vec<Package>) -> (Vec<Package>, Vec<Package>) {
+        let (kept, mut excluded): (Vec<Package>, Vec<Package>) =
+            packages.into_iter().partition(|p| self.matches(p));
+        if !self.newest_only {
+            return (kept, excluded);
+        }
+
+        let mut newest: HashMap<(&str, &str), usize> = HashMap::new();
+        for (idx, package) in kept.iter().enumerate() {
+            newest
+                .entry((package.name(), package.arch()))
+                .and_modify(|best| {
+                    if package.evr() > kept[*best].evr() {
+                        *best = idx;
+                    }
+                })
+                .or_insert(idx);
+        }
+        let mut is_newest = vec![false; kept.len()];
+        for idx in newest.into_values() {
+            is_newest[idx] = true;
+        }
+        let (newest, older): (Vec<_>, Vec<_>) = kept
+            .into_iter()
+            .zip(is_newest)
+            .partition(|(_, is_newest)| *is_newest);
+        excluded.extend(older.into_iter().map(|(p, _)| p));
+        (newest.into_iter().map(|(p, _)| p).collect(), excluded)
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::Mutex;
+
+use quick_xml::events::Event;
+
+use crate::{utils, Checksum, ChecksumType, MetadataError};
+
+const TAG_FILE: &[u8] = b"file";
+const TAG_HASH: &[u8] = b"hash";
+const TAG_URL: &[u8] = b"url";
+
+const REPOMD_SUFFIX: &str = "repodata/repomd.xml";
+
+/// The parts of a metalink document (as served by e.g. Fedora's MirrorManager) relevant to a repository.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metalink {
+    /// Base URLs of the mirrors, most preferred first
+    pub mirrors: Vec<String>,
+    /// Checksums a valid `repomd.xml` may have. Besides the current one, this includes the alternates
+    /// listed for recent revisions, which mirrors which are a little behind may still be serving.
+    pub repomd_checksums: Vec<Checksum>,
+}
+
+impl Metalink {
+    /// Parse the `repomd.xml` entry of a metalink document.
+    pub fn parse(bytes: &[u8]) -> Result<Self, MetadataError> {
+        let mut reader = utils::create_xml_reader(bytes);
+        let mut buf = Vec::new();
+        let mut text_buf = Vec::new();
+
+        let mut metalink = Metalink::default();
+        let mut mirrors = Vec::new();
+        let mut in_repomd = false;
+
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(e) if e.name() == TAG_FILE => {
+                    in_repomd = match e.try_get_attribute("name")? {
+                        Some(name) => name.value.as_ref() == b"repomd.xml",
+                        None => false,
+                    };
+                }
+                Event::End(e) if e.name() == TAG_FILE => in_repomd = false,
+                Event::Start(e) if in_repomd && e.name() == TAG_HASH => {
+                    let hash_type = e
+                        .try_get_attribute("type")?
+                        .ok_or(MetadataError::MissingAttributeError("type"))?;
+                    let value = reader.read_text(e.name(), &mut text_buf)?;
+                    // skip hash types we can't verify
+                    if let Ok(checksum) =
+                        Checksum::try_create(hash_type.value.as_ref(), value.as_bytes())
+                    {
+                        if checksum.checksum_type() != ChecksumType::Unknown
+                            && !metalink.repomd_checksums.contains(&checksum)
+                        {
+                            metalink.repomd_checksums.push(checksum);
+                        }
+                    }
+                }
+                Event::Start(e) if in_repomd && e.name() == TAG_URL => {
+                    let preference = match e.try_get_attribute("preference")? {
+                        Some(p) => std::str::from_utf8(&p.value)?.parse::<u32>().unwrap_or(0),
+                        None => 0,
+                    };
+                    let url = reader.read_text(e.name(), &mut text_buf)?;
+                    mirrors.push((preference, mirror_base(&url)));
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+            text_buf.clear();
+        }
+
+        // stable, so mirrors of equal preference stay in document order
+        mirrors.sort_by_key(|m| std::cmp::Reverse(m.0));
+        metalink.mirrors = mirrors.into_iter().map(|(_, url)| url).collect();
+        Ok(metalink)
+    }
+}
+
+/// Parse a mirrorlist, i.e. a list of base URLs with one per line.
+pub fn parse_mirrorlist(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(mirror_base)
+        .collect()
+}
+
+/// Strip a trailing `repodata/repomd.xml` (which some mirror lists include) from a URL.
+fn mirror_base(url: &str) -> String {
+    let url = url.trim();
+    url.strip_suffix(REPOMD_SUFFIX)
+        .unwrap_or(url)
+        .trim_end_matches('/')
+        .to_owned()
+}
+
+/// How a mirror has been doing over the lifetime of a [`Downloader`](crate::Downloader).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MirrorStatus {
+    pub url: String,
+    /// Number of files successfully fetched from this mirror
+    pub successes: u64,
+    /// Number of failed attempts to fetch a file from this mirror
+    pub failures: u64,
+    /// Number of failed attempts since the last success
+    pub consecutive_failures: u64,
+}
+
+/// The mirrors of a session, tracking their health.
+#[derive(Debug, Default)]
+pub(crate) struct Mirrors {
+    mirrors: Mutex<Vec<MirrorStatus>>,
+}
+
+impl Mirrors {
+    pub(crate) fn set(&self, urls: Vec<String>) {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        *mirrors = urls
+            .into_iter()
+            .map(|url| MirrorStatus {
+                url,
+                ..MirrorStatus::default()
+            })
+            .collect();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mirrors.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn status(&self) -> Vec<MirrorStatus> {
+        self.mirrors.lock().unwrap().clone()
+    }
+
+    /// The mirrors in the order they should be tried: those which are currently failing go last.
+    pub(crate) fn ordered(&self) -> Vec<String> {
+        let mut mirrors = self.status();
+        mirrors.sort_by_key(|m| m.consecutive_failures);
+        mirrors.into_iter().map(|m| m.url).collect()
+    }
+
+    pub(crate) fn record_success(&self, url: &str) {
+        if let Some(mirror) = self
+            .mirrors
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|m| m.url == url)
+        {
+            mirror.successes += 1;
+            mirror.consecutive_failures = 0;
+        }
+    }
+
+    pub(crate) fn record_failure(&self, url: &str) {
+        if let Some(mirror) = self
+            .mirrors
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|m| m.url == url)
+        {
+            mirror.failures += 1;
+            mirror.consecutive_failures += 1;
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the state file kept in the root of the destination directory.
+const STATE_FILENAME: &str = ".sync_state";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    checksum: String,
+    complete: bool,
+}
+
+/// Remembers, across runs, which files of a sync have been fully downloaded and verified and which
+/// `.part` files belong to which version of a file.
+///
+/// The state file is an append-only log with one `<complete|partial> <checksum> <href>` line per event,
+/// so that an interrupted process loses at most the line being written. It is compacted at the end of
+/// each successful sync.
+pub(crate) struct SyncState {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+    log: Option<File>,
+}
+
+impl SyncState {
+    /// A state which is never persisted.
+    pub(crate) fn disabled() -> Self {
+        SyncState {
+            path: PathBuf::new(),
+            entries: HashMap::new(),
+            log: None,
+        }
+    }
+
+    pub(crate) fn load(dest_dir: &Path) -> io::Result<Self> {
+        let path = dest_dir.join(STATE_FILENAME);
+        let mut entries = HashMap::new();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let mut fields = line.splitn(3, ' ');
+                    let (Some(status), Some(checksum), Some(href)) =
+                        (fields.next(), fields.next(), fields.next())
+                    else {
+                        // most likely a line truncated by an interruption
+                        continue;
+                    };
+                    let entry = Entry {
+                        checksum: checksum.to_owned(),
+                        complete: status == "complete",
+                    };
+                    entries.insert(PathBuf::from(href), entry);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        fs::create_dir_all(dest_dir)?;
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(SyncState {
+            path,
+            entries,
+            log: Some(log),
+        })
+    }
+
+    /// Whether `href` was previously downloaded and verified against `checksum`.
+    pub(crate) fn is_complete(&self, href: &Path, checksum: &str) -> bool {
+        self.entries
+            .get(href)
+            .is_some_and(|e| e.complete && e.checksum == checksum)
+    }
+
+    /// Whether a partial download of `href` was started for the file with `checksum`.
+    pub(crate) fn is_partial(&self, href: &Path, checksum: &str) -> bool {
+        self.entries
+            .get(href)
+            .is_some_and(|e| !e.complete && e.checksum == checksum)
+    }
+
+    pub(crate) fn mark_partial(&mut self, href: &Path, checksum: &str) -> io::Result<()> {
+        self.record(href, checksum, false)
+    }
+
+    pub(crate) fn mark_complete(&mut self, href: &Path, checksum: &str) -> io::Result<()> {
+        self.record(href, checksum, true)
+    }
+
+    fn record(&mut self, href: &Path, checksum: &str, complete: bool) -> io::Result<()> {
+        let entry = Entry {
+            checksum: checksum.to_owned(),
+            complete,
+        };
+        if self.entries.get(href) == Some(&entry) {
+            return Ok(());
+        }
+        if let Some(log) = self.log.as_mut() {
+            let status = if complete { "complete" } else { "partial" };
+            writeln!(log, "{} {} {}", status, checksum, href.display())?;
+            log.flush()?;
+        }
+        self.entries.insert(href.to_owned(), entry);
+        Ok(())
+    }
+
+    /// Rewrite the log so that it only contains the latest entry for each file.
+    pub(crate) fn compact(&mut self) -> io::Result<()> {
+        if self.log.is_none() {
+            return Ok(());
+        }
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        for (href, entry) in entries {
+            let status = if entry.complete {
+                "complete"
+            } else {
+                "partial"
+            };
+            writeln!(tmp, "{} {} {}", status, entry.checksum, href.display())?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.log = Some(OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+}
+
+/// Name of the file remembering the HTTP cache validators of the last `repomd.xml` fetched.
+const VALIDATORS_FILENAME: &str = ".repomd_validators";
+
+/// The `ETag` and `Last-Modified` headers a `repomd.xml` was served with, which can be sent back to the
+/// server to only fetch it again if it has changed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Load the validators stored in `dest_dir`. They're stored as `<header>: <value>` lines.
+    pub(crate) fn load(dest_dir: &Path) -> io::Result<Self> {
+        let mut validators = Validators::default();
+        let contents = match fs::read_to_string(dest_dir.join(VALIDATORS_FILENAME)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(validators),
+            Err(e) => return Err(e),
+        };
+        for line in contents.lines() {
+            match line.split_once(": ") {
+                Some(("ETag", value)) => validators.etag = Some(value.to_owned()),
+                Some(("Last-Modified", value)) => validators.last_modified = Some(value.to_owned()),
+                _ => (),
+            }
+        }
+        Ok(validators)
+    }
+
+    pub(crate) fn save(&self, dest_dir: &Path) -> io::Result<()> {
+        let path = dest_dir.join(VALIDATORS_FILENAME);
+        if self.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut contents = String::new();
+        if let Some(etag) = &self.etag {
+            contents.push_str(&format!("ETag: {}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            contents.push_str(&format!("Last-Modified: {}\n", last_modified));
+        }
+        fs::write(path, contents)
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::{Downloader, SyncReport, VerificationFailure};
+use crate::{runtime, MetadataError};
+
+/// Report [`SyncEvent::FileProgress`] at most once per this many bytes.
+const PROGRESS_BYTES: u64 = 256 * 1024;
+
+/// Something which happened during a sync, see [`Downloader::with_progress()`].
+///
+/// Paths are relative to the destination directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyncEvent {
+    /// A file is about to be downloaded. `size` is the size recorded in the metadata, if any.
+    FileStarted { href: PathBuf, size: Option<u64> },
+    /// Part of a file has been downloaded. `bytes` counts the whole file so far, including any part of it
+    /// downloaded by an earlier, interrupted sync.
+    FileProgress { href: PathBuf, bytes: u64 },
+    /// A file has been downloaded and verified. `bytes` is the number of bytes transferred.
+    FileFinished { href: PathBuf, bytes: u64 },
+    /// A file was already present and up to date
+    FileSkipped { href: PathBuf },
+    /// A package failed verification and was skipped, see [`DownloadOptions::on_mismatch`](super::DownloadOptions::on_mismatch)
+    FileFailed(VerificationFailure),
+}
+
+pub(crate) type ProgressCallback = Arc<dyn Fn(&SyncEvent) + Send + Sync>;
+
+/// Reports [`SyncEvent::FileProgress`] for the data of a file as it's downloaded.
+pub(crate) struct Progress<'a> {
+    href: &'a Path,
+    progress: &'a ProgressCallback,
+    bytes: u64,
+    reported: u64,
+}
+
+impl<'a> Progress<'a> {
+    /// Start at `offset`, the size of the part of the file downloaded before.
+    pub(crate) fn new(href: &'a Path, progress: &'a ProgressCallback, offset: u64) -> Self {
+        Progress {
+            href,
+            progress,
+            bytes: offset,
+            reported: offset,
+        }
+    }
+
+    /// Account for `count` more bytes having been downloaded, `0` meaning the end of the file.
+    pub(crate) fn advance(&mut self, count: usize) {
+        self.bytes += count as u64;
+        if self.bytes - self.reported >= PROGRESS_BYTES
+            || (count == 0 && self.bytes > self.reported)
+        {
+            self.reported = self.bytes;
+            (self.progress)(&SyncEvent::FileProgress {
+                href: self.href.to_owned(),
+                bytes: self.bytes,
+            });
+        }
+    }
+}
+
+/// A sync running in the background, see [`Downloader::sync_to_directory_async()`].
+///
+/// Awaiting it yields the result of the sync. Polling it again once it has yielded the result returns an
+/// error rather than panicking. Progress events can be polled with [`SyncTask::poll_event()`] in the
+/// meantime, which has the signature of `Stream::poll_next()`, so e.g. `futures::stream::poll_fn()` turns
+/// it into a stream.
+///
+/// The events wait in a queue until they're polled, with the progress of each file coalesced into its
+/// latest [`SyncEvent::FileProgress`], so the queue doesn't grow with the size of the files if the events
+/// aren't polled.
+pub struct SyncTask {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    events: VecDeque<SyncEvent>,
+    /// The number of events which were taken from the queue
+    taken: u64,
+    /// The position of the queued [`SyncEvent::FileProgress`] of each file, counting the taken events
+    progress: HashMap<PathBuf, u64>,
+    result: Option<Result<SyncReport, MetadataError>>,
+    finished: bool,
+    result_waker: Option<Waker>,
+    event_waker: Option<Waker>,
+}
+
+impl Shared {
+    fn push(&mut self, event: &SyncEvent) {
+        match event {
+            SyncEvent::FileProgress { href, .. } => {
+                let position = self.taken + self.events.len() as u64;
+                match self.progress.insert(href.clone(), position) {
+                    Some(queued) if queued >= self.taken => {
+                        self.events[(queued - self.taken) as usize] = event.clone();
+                        self.progress.insert(href.clone(), queued);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+            // a file which is downloaded again starts its progress over
+            SyncEvent::FileStarted { href, .. } | SyncEvent::FileFinished { href, .. } => {
+                self.progress.remove(href);
+            }
+            _ => {}
+        }
+        self.events.push_back(event.clone());
+    }
+
+    fn pop(&mut self) -> Option<SyncEvent> {
+        let event = self.events.pop_front()?;
+        if let SyncEvent::FileProgress { href, .. } = &event {
+            if self.progress.get(href) == Some(&self.taken) {
+                self.progress.remove(href);
+            }
+        }
+        self.taken += 1;
+        Some(event)
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.event_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl SyncTask {
+    pub(crate) fn spawn(mut downloader: Downloader, path: &Path) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let events = shared.clone();
+        let progress = downloader.progress.take();
+        downloader.progress = Some(Arc::new(move |event: &SyncEvent| {
+            if let Some(progress) = &progress {
+                progress(event);
+            }
+            let mut shared = events.lock().unwrap();
+            shared.push(event);
+            shared.wake();
+        }));
+
+        let result = shared.clone();
+        let path: PathBuf = path.to_owned();
+        // an async transport doesn't need a thread of its own, unless there's no Tokio runtime to run on
+        #[cfg(feature = "tokio")]
+        if downloader.transport.is_async() {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let report = downloader.sync(&path).await;
+                    finish(&result, report);
+                });
+                return SyncTask { shared };
+            }
+        }
+        runtime::current_runtime().spawn(Box::new(move || {
+            let report = downloader.sync_to_directory(&path);
+            finish(&result, report);
+        }));
+
+        SyncTask { shared }
+    }
+
+    /// Poll for the next progress event. Returns `Ready(None)` once the sync has finished and all events
+    /// have been consumed.
+    pub fn poll_event(&self, cx: &mut Context<'_>) -> Poll<Option<SyncEvent>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.pop() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if shared.finished => Poll::Ready(None),
+            None => {
+                shared.event_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Hand the result of the sync to the [`SyncTask`].
+fn finish(shared: &Mutex<Shared>, report: Result<SyncReport, MetadataError>) {
+    let mut shared = shared.lock().unwrap();
+    shared.result = Some(report);
+    shared.finished = true;
+    shared.wake();
+    if let Some(waker) = shared.result_waker.take() {
+        waker.wake();
+    }
+}
+
+impl Future for SyncTask {
+    type Output = Result<SyncReport, MetadataError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if shared.finished => Poll::Ready(Err(io::Error::other(
+                "the result of the sync was already taken",
+            )
+            .into())),
+            None => {
+                shared.result_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the rate at which bytes are transferred, across however many responses share it.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+    /// The time at which all the bytes consumed so far may have been transferred
+    next_free: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Account for `bytes` having been transferred, returning how long to wait to stay within the rate.
+    pub(crate) fn consume(&self, bytes: usize) -> Duration {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let now = Instant::now();
+        let mut next_free = self.next_free.lock().unwrap();
+        // idle time doesn't accumulate into a burst allowance
+        *next_free = (*next_free).max(now) + cost;
+        *next_free - now
+    }
+
+    /// The largest read which keeps the transfer smooth rather than bursty.
+    pub(crate) fn max_read(&self) -> usize {
+        (self.bytes_per_sec / 10).max(1) as usize
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use super::{is_transient, DefaultTransport, Request, Transport, MAX_RETRY_DELAY};
+use crate::logging;
+use crate::MetadataError;
+
+/// What an [`Uploader::upload_directory()`] call did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UploadReport {
+    /// Files which were uploaded, relative to the directory, in the order they were uploaded
+    pub uploaded: Vec<PathBuf>,
+    /// Total number of bytes uploaded
+    pub bytes_uploaded: u64,
+}
+
+/// Where the files are uploaded to.
+enum UploadTarget {
+    Base(String),
+    Urls(Box<dyn Fn(&Path) -> String + Send + Sync>),
+}
+
+/// Publishes a repository (e.g. one written by [`Repository::write_to_directory()`](crate::Repository::write_to_directory))
+/// by uploading its files with HTTP `PUT` requests, as accepted by WebDAV servers, artifact repositories
+/// and S3 (using presigned URLs).
+///
+/// Packages are uploaded first, then the metadata files, and `repomd.xml` last of all. A client reading
+/// the repository while it's being published therefore either sees the old `repomd.xml`, or a new one
+/// whose files are all in place. Old files aren't removed, so clients holding on to the old `repomd.xml`
+/// can keep using it.
+///
+/// ```no_run
+/// use rpmrepo_metadata::Uploader;
+///
+/// let uploader = Uploader::new("https://artifacts.example.com/repos/myrepo")
+///     .header("Authorization", "Bearer 1234");
+/// uploader.upload_directory("./myrepo".as_ref()).unwrap();
+/// ```
+pub struct Uploader {
+    target: UploadTarget,
+    transport: Box<dyn Transport>,
+    headers: Vec<(String, String)>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Uploader {
+    /// Upload each file to its path relative to `base_url`.
+    pub fn new(base_url: &str) -> Self {
+        Self::with_target(UploadTarget::Base(
+            base_url.trim_end_matches('/').to_owned(),
+        ))
+    }
+
+    /// Upload each file to the URL returned by `url_for` for its path (relative to the directory), e.g.
+    /// a presigned S3 URL.
+    pub fn with_urls<F: Fn(&Path) -> String + Send + Sync + 'static>(url_for: F) -> Self {
+        Self::with_target(UploadTarget::Urls(Box::new(url_for)))
+    }
+
+    fn with_target(target: UploadTarget) -> Self {
+        Uploader {
+            target,
+            transport: Box::new(DefaultTransport::default()),
+            headers: Vec::new(),
+            retries: 2,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// Send an additional header with each request, e.g. `Authorization`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// How many more times to try uploading a file after a transient failure, waiting `delay` before the
+    /// first retry and twice as long before each further one.
+    pub fn retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Upload the repository in `path`.
+    ///
+    /// Hidden files (such as the state files of a [`Downloader`](crate::Downloader)) are skipped.
+    pub fn upload_directory(&self, path: &Path) -> Result<UploadReport, MetadataError> {
+        let _span = logging::span!("upload of {}", path.display());
+        let mut files = Vec::new();
+        list_files(path, Path::new(""), &mut files)?;
+        files.sort_by_key(|href| (upload_stage(href), href.clone()));
+        logging::debug!("uploading {} files", files.len());
+
+        let mut report = UploadReport::default();
+        for href in files {
+            let url = match &self.target {
+                UploadTarget::Base(base) => format!("{}/{}", base, encode_path(&href)),
+                UploadTarget::Urls(url_for) => url_for(&href),
+            };
+            let file = path.join(&href);
+            let mut request = Request::put(&url, file.clone());
+            request.headers = self.headers.clone();
+            self.with_retries(|| {
+                let response = self.transport.fetch(&request)?;
+                if !response.is_success() {
+                    return Err(MetadataError::HttpStatusError(url.clone(), response.status));
+                }
+                Ok(())
+            })?;
+            logging::trace!("uploaded {}", url);
+            report.bytes_uploaded += fs::metadata(&file)?.len();
+            report.uploaded.push(href);
+        }
+        Ok(report)
+    }
+
+    fn with_retries(
+        &self,
+        mut op: impl FnMut() -> Result<(), MetadataError>,
+    ) -> Result<(), MetadataError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    logging::debug!("retrying in {:?} after error: {}", delay, e);
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Collect the paths (relative to `root`) of the files under `root.join(dir)`, skipping hidden ones.
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), MetadataError> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let href = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(root, &href, files)?;
+        } else {
+            files.push(href);
+        }
+    }
+    Ok(())
+}
+
+/// The order files are uploaded in: packages, metadata, the signature of `repomd.xml`, `repomd.xml`.
+fn upload_stage(href: &Path) -> u8 {
+    let repodata = Path::new("repodata");
+    if href == repodata.join("repomd.xml") {
+        3
+    } else if href.parent() == Some(repodata)
+        && href
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("repomd.xml"))
+    {
+        2
+    } else if href.starts_with(repodata) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Join the components of `href` with `/`, percent-encoding characters which aren't allowed in a URL path.
+fn encode_path(href: &Path) -> String {
+    let mut encoded = String::new();
+    for (i, component) in href.iter().enumerate() {
+        if i > 0 {
+            encoded.push('/');
+        }
+        for byte in component.to_string_lossy().bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~+@:!$&'()*,;=".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{self, Write};
+
+use crate::hasher::{self, Hasher};
+use crate::{utils, Checksum, ChecksumType};
+
+/// Computes a checksum of everything written through it.
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<(ChecksumType, Box<dyn Hasher>)>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Hash with `checksum_type`, or don't hash at all if it's `None` or `Unknown`.
+    pub(crate) fn new(inner: W, checksum_type: Option<ChecksumType>) -> Self {
+        HashingWriter {
+            inner,
+            hasher: checksum_type.and_then(|checksum_type| {
+                hasher::new_hasher(checksum_type).map(|hasher| (checksum_type, hasher))
+            }),
+        }
+    }
+
+    /// Feed data which is already part of the output (e.g. the start of a resumed download) to the hasher.
+    pub(crate) fn hash_existing(&mut self, data: &[u8]) {
+        if let Some((_, hasher)) = self.hasher.as_mut() {
+            hasher.update(data);
+        }
+    }
+
+    /// Returns the inner writer and the checksum, if one was computed.
+    pub(crate) fn finish(self) -> (W, Option<Checksum>) {
+        let checksum = self.hasher.map(|(checksum_type, hasher)| {
+            utils::checksum_from_digest(checksum_type, hex::encode(hasher.finalize()))
+        });
+        (self.inner, checksum)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.hash_existing(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Delta downloads of [zchunk](https://github.com/zchunk/zchunk/blob/main/zchunk_format.txt) files.
+//!
+//! A zchunk file is a header listing the checksum and length of each independently compressed chunk,
+//! followed by the chunks. Chunks which haven't changed since a previous version of the file can be
+//! copied from that version, so that only the header and the new chunks need to be downloaded. The
+//! chunks never need to be decompressed for this.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::logging;
+use crate::zchunk::ZchunkHeader;
+use crate::{Checksum, MetadataError};
+
+/// Assemble a zchunk file at `dest` using the chunks it has in common with the `previous` version of it.
+///
+/// `fetch_range(offset, length)` fetches part of the new file. `header_size` and `header_checksum` are the
+/// values recorded in `repomd.xml`. Returns the number of bytes fetched.
+pub(crate) async fn assemble<F>(
+    url: &str,
+    previous: &Path,
+    dest: &Path,
+    header_size: u64,
+    header_checksum: Option<&Checksum>,
+    mut fetch_range: impl FnMut(u64, u64) -> F,
+) -> Result<u64, MetadataError>
+where
+    F: Future<Output = Result<Vec<u8>, MetadataError>>,
+{
+    let invalid = |reason: &str| MetadataError::DownloadError(url.to_owned(), reason.to_owned());
+
+    let header_bytes = fetch_range(0, header_size).await?;
+    let header = ZchunkHeader::parse(&header_bytes)
+        .filter(|header| header.len == header_bytes.len())
+        .ok_or_else(|| invalid("unsupported or invalid zchunk header"))?;
+    let expected_checksum = match header_checksum {
+        Some(checksum) => checksum.to_values()?.1.to_owned(),
+        None => hex::encode(&header.checksum),
+    };
+    if header.actual_checksum != header.checksum
+        || hex::encode(&header.actual_checksum) != expected_checksum
+    {
+        return Err(MetadataError::ChecksumMismatchError(
+            format!("{} (zchunk header)", url),
+            expected_checksum,
+            hex::encode(&header.actual_checksum),
+        ));
+    }
+
+    let previous_bytes = std::fs::read(previous)?;
+    let mut known = HashMap::new();
+    if let Some(previous_header) = ZchunkHeader::parse(&previous_bytes) {
+        if previous_header.chunk_checksum_type == header.chunk_checksum_type {
+            for chunk in &previous_header.chunks {
+                if let Some(data) = previous_bytes.get(chunk.offset..chunk.offset + chunk.length) {
+                    known.insert(chunk.checksum.clone(), data);
+                }
+            }
+        }
+    }
+
+    // Fetch the missing chunks, merging adjacent ones into a single request
+    let mut missing: Vec<(usize, usize)> = Vec::new();
+    for chunk in &header.chunks {
+        let reusable = known
+            .get(&chunk.checksum)
+            .is_some_and(|data| data.len() == chunk.length && header.chunk_matches(chunk, data));
+        if reusable || chunk.length == 0 {
+            continue;
+        }
+        match missing.last_mut() {
+            Some((start, length)) if *start + *length == chunk.offset => *length += chunk.length,
+            _ => missing.push((chunk.offset, chunk.length)),
+        }
+    }
+    let mut fetched = HashMap::new();
+    let mut count = header_size;
+    for (start, length) in missing {
+        let data = fetch_range(start as u64, length as u64).await?;
+        count += length as u64;
+        fetched.insert(start, data);
+    }
+    logging::debug!(
+        "fetched {} bytes of {} using zchunk deltas",
+        count,
+        header
+            .chunks
+            .last()
+            .map_or(header.len, |c| c.offset + c.length)
+    );
+
+    let mut writer = BufWriter::new(File::create(dest)?);
+    writer.write_all(&header_bytes)?;
+    let mut range: Option<(usize, &[u8])> = None;
+    for chunk in header.chunks.iter().filter(|c| c.length > 0) {
+        if let Some(data) = fetched.get(&chunk.offset) {
+            range = Some((chunk.offset, data.as_slice()));
+        }
+        let data = match range {
+            Some((start, data)) if chunk.offset >= start && chunk.offset - start < data.len() => {
+                &data[chunk.offset - start..chunk.offset - start + chunk.length]
+            }
+            _ => known[&chunk.checksum],
+        };
+        if !header.chunk_matches(chunk, data) {
+            return Err(MetadataError::ChecksumMismatchError(
+                format!("{} (chunk at offset {})", url, chunk.offset),
+                hex::encode(&chunk.checksum),
+                hex::encode(header.chunk_checksum_type.digest(&[data])),
+            ));
+        }
+        writer.write_all(data)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?;
+
+    Ok(count)
+}
@@ -0,0 +1,119 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Draft advisories for the packages which are new in a repository, made from their changelogs.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use crate::{utils, Changelog, MetadataError, Package, Repository, UpdateCollection, UpdateRecord};
+
+/// One draft for each build (i.e. source RPM) with packages in `current` which aren't in `previous`.
+pub(crate) fn draft_advisories(
+    previous: &Repository,
+    current: &Repository,
+) -> Result<Vec<UpdateRecord>, MetadataError> {
+    // the time of the newest changelog entry of each package in the previous snapshot
+    let mut previous_changelogs: HashMap<&str, u64> = HashMap::new();
+    for package in previous.packages().values() {
+        let newest = package.changelogs().iter().map(|c| c.timestamp).max();
+        let entry = previous_changelogs.entry(package.name()).or_default();
+        *entry = (*entry).max(newest.unwrap_or_default());
+    }
+
+    let mut builds: IndexMap<&str, Vec<&Package>> = IndexMap::new();
+    for package in current.packages().values() {
+        if previous.packages().contains_key(package.pkgid()) {
+            continue;
+        }
+        let build = match package.rpm_sourcerpm.as_str() {
+            "" => package.location_href(),
+            sourcerpm => sourcerpm,
+        };
+        builds.entry(build).or_default().push(package);
+    }
+
+    builds
+        .into_iter()
+        .filter_map(|(build, packages)| {
+            draft_advisory(build, &packages, &previous_changelogs).transpose()
+        })
+        .collect()
+}
+
+/// The draft for the `packages` built from `build`. `None` if none of them have changelog entries since
+/// their previous versions, e.g. because they were only rebuilt.
+fn draft_advisory(
+    build: &str,
+    packages: &[&Package],
+    previous_changelogs: &HashMap<&str, u64>,
+) -> Result<Option<UpdateRecord>, MetadataError> {
+    let updated = packages
+        .iter()
+        .any(|package| previous_changelogs.contains_key(package.name()));
+
+    // the descriptions of changelogs stored out-of-line are needed for the title and description
+    let changelogs: Vec<Cow<[Changelog]>> = packages
+        .iter()
+        .map(|package| package.load_changelogs())
+        .collect::<Result<_, _>>()?;
+
+    // Subpackages share their changelog, so it's enough to take the entries of each package once
+    let mut entries: Vec<&Changelog> = Vec::new();
+    for (package, changelogs) in packages.iter().zip(&changelogs) {
+        let new_entries: Vec<&Changelog> = match previous_changelogs.get(package.name()) {
+            Some(since) => changelogs.iter().filter(|c| c.timestamp > *since).collect(),
+            // a package without a previous version is described by its latest entry
+            None => changelogs
+                .iter()
+                .max_by_key(|c| c.timestamp)
+                .into_iter()
+                .collect(),
+        };
+        for entry in new_entries {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries.sort_by_key(|c| std::cmp::Reverse(c.timestamp));
+    let Some(latest) = entries.first() else {
+        return Ok(None);
+    };
+
+    let build = build.strip_suffix(".rpm").unwrap_or(build);
+    let build = build.strip_suffix(".src").unwrap_or(build);
+    let title = latest
+        .description
+        .lines()
+        .map(|line| line.trim().trim_start_matches('-').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or(build);
+    let issued = packages.iter().map(|p| p.time_build()).max().unwrap_or(0);
+    let description = entries
+        .iter()
+        .map(|c| format!("* {}\n{}", c.author, c.description.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    Ok(Some(UpdateRecord {
+        id: format!("DRAFT-{}", build),
+        title: title.to_owned(),
+        update_type: if updated { "bugfix" } else { "newpackage" }.to_owned(),
+        status: "final".to_owned(),
+        version: "1".to_owned(),
+        issued_date: Some(utils::format_timestamp(issued)),
+        summary: format!("{} update", build),
+        description,
+        pkglist: vec![UpdateCollection {
+            packages: packages.iter().map(|p| (*p).into()).collect(),
+            ..UpdateCollection::default()
+        }],
+        ..UpdateRecord::default()
+    }))
+}
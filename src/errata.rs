@@ -0,0 +1,708 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Convert between [`UpdateRecord`]s and vendor advisory formats.
+//!
+//! Two JSON formats can be imported:
+//!
+//! - CSAF 2.0 advisories, as published at e.g. <https://access.redhat.com/security/data/csaf/v2/advisories/>
+//! - The CVRF-as-JSON documents served by the Red Hat security data API
+//!   (`https://access.redhat.com/hydra/rest/securitydata/cvrf/<id>.json`)
+//!
+//! Packages are grouped into one [`UpdateCollection`] per product the advisory applies to, and only
+//! binary and source RPMs are imported. The resulting records can be inserted into
+//! [`Repository::advisories_mut()`](crate::Repository::advisories_mut) and written out as updateinfo.xml.
+//!
+//! In the other direction, advisories can be exported as CSAF 2.0 security advisories or VEX documents.
+//! Each collection becomes a product, each package a product version which is a component of it, and
+//! each CVE reference a vulnerability which is fixed in all of those product versions.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::{
+    MetadataError, UpdateCollection, UpdateCollectionPackage, UpdateRecord, UpdateReference,
+};
+
+/// Read a CSAF 2.0 advisory.
+pub fn read_csaf_advisory<R: Read>(reader: R) -> Result<UpdateRecord, MetadataError> {
+    let root: Value = serde_json::from_reader(reader)?;
+    csaf_to_updaterecord(&root)
+}
+
+/// Read an advisory from the CVRF JSON served by the Red Hat security data API.
+pub fn read_cvrf_advisory<R: Read>(reader: R) -> Result<UpdateRecord, MetadataError> {
+    let root: Value = serde_json::from_reader(reader)?;
+    cvrf_to_updaterecord(&root)
+}
+
+/// The CSAF profile of an exported document.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CsafProfile {
+    /// `csaf_security_advisory`
+    SecurityAdvisory,
+    /// `csaf_vex`
+    Vex,
+}
+
+impl CsafProfile {
+    pub fn category(&self) -> &'static str {
+        match self {
+            CsafProfile::SecurityAdvisory => "csaf_security_advisory",
+            CsafProfile::Vex => "csaf_vex",
+        }
+    }
+}
+
+/// Write an advisory as a CSAF 2.0 document of the given profile.
+pub fn write_csaf_advisory<W: Write>(
+    record: &UpdateRecord,
+    profile: CsafProfile,
+    writer: W,
+) -> Result<(), MetadataError> {
+    let document = updaterecord_to_csaf(record, profile);
+    serde_json::to_writer_pretty(writer, &document)?;
+    Ok(())
+}
+
+fn csaf_to_updaterecord(root: &Value) -> Result<UpdateRecord, MetadataError> {
+    let document = root
+        .get("document")
+        .ok_or(MetadataError::MissingFieldError("document"))?;
+    let tracking = document
+        .get("tracking")
+        .ok_or(MetadataError::MissingFieldError("tracking"))?;
+
+    let id = required_str(tracking, "id")?;
+    let mut record = UpdateRecord {
+        id: id.to_owned(),
+        title: required_str(document, "title")?.to_owned(),
+        update_type: update_type(str_at(document, &["category"]), id).to_owned(),
+        status: str_at(tracking, &["status"]).to_lowercase(),
+        version: str_at(tracking, &["version"]).to_owned(),
+        issued_date: date_at(tracking, "initial_release_date"),
+        updated_date: date_at(tracking, "current_release_date"),
+        from: first_nonempty(&[
+            str_at(document, &["publisher", "contact_details"]),
+            str_at(document, &["publisher", "name"]),
+        ]),
+        rights: str_at(document, &["distribution", "text"]).to_owned(),
+        severity: str_at(document, &["aggregate_severity", "text"]).to_owned(),
+        ..UpdateRecord::default()
+    };
+
+    for note in list_at(document, "notes") {
+        match str_at(note, &["category"]) {
+            "summary" if record.summary.is_empty() => {
+                record.summary = str_at(note, &["text"]).to_owned()
+            }
+            "general" if record.description.is_empty() => {
+                record.description = str_at(note, &["text"]).to_owned()
+            }
+            _ => (),
+        }
+    }
+
+    for reference in list_at(document, "references") {
+        record.references.push(document_reference(
+            &record.id,
+            str_at(reference, &["category"]) == "self",
+            str_at(reference, &["url"]),
+            str_at(reference, &["summary"]),
+        ));
+    }
+
+    for vulnerability in list_at(root, "vulnerabilities") {
+        if let Some(cve) = vulnerability.get("cve").and_then(Value::as_str) {
+            record.references.push(cve_reference(cve, vulnerability));
+        }
+        for id in list_at(vulnerability, "ids") {
+            let bug = str_at(id, &["text"]);
+            let known = record
+                .references
+                .iter()
+                .any(|r| r.reftype == "bugzilla" && r.id == bug);
+            if str_at(id, &["system_name"]).contains("Bugzilla") && !known {
+                record.references.push(bugzilla_reference(bug, ""));
+            }
+        }
+        if record.solution.is_empty() {
+            if let Some(fix) = list_at(vulnerability, "remediations")
+                .into_iter()
+                .find(|r| str_at(r, &["category"]) == "vendor_fix")
+            {
+                record.solution = str_at(fix, &["details"]).to_owned();
+            }
+        }
+    }
+
+    let tree = root.get("product_tree").unwrap_or(&Value::Null);
+    let mut products = BTreeMap::new();
+    let mut packages = BTreeMap::new();
+    collect_csaf_branches(tree, &mut products, &mut packages);
+
+    let relationships = list_at(tree, "relationships")
+        .into_iter()
+        .map(|r| {
+            (
+                str_at(r, &["relates_to_product_reference"]),
+                str_at(r, &["product_reference"]),
+            )
+        })
+        .collect::<Vec<_>>();
+    record.pkglist = build_collections(&products, &packages, &relationships);
+
+    Ok(record)
+}
+
+fn cvrf_to_updaterecord(root: &Value) -> Result<UpdateRecord, MetadataError> {
+    let cvrf = root.get("cvrf").unwrap_or(root);
+    let tracking = cvrf
+        .get("document_tracking")
+        .ok_or(MetadataError::MissingFieldError("document_tracking"))?;
+
+    let id = str_at(tracking, &["identification", "id"]);
+    if id.is_empty() {
+        return Err(MetadataError::MissingFieldError("id"));
+    }
+    let mut record = UpdateRecord {
+        id: id.to_owned(),
+        title: required_str(cvrf, "document_title")?.to_owned(),
+        update_type: update_type(str_at(cvrf, &["document_type"]), id).to_owned(),
+        status: str_at(tracking, &["status"]).to_lowercase(),
+        version: str_at(tracking, &["version"]).to_owned(),
+        issued_date: date_at(tracking, "initial_release_date"),
+        updated_date: date_at(tracking, "current_release_date"),
+        from: first_nonempty(&[
+            str_at(cvrf, &["document_publisher", "contact_details"]),
+            str_at(cvrf, &["document_publisher", "issuing_authority"]),
+        ]),
+        rights: first_nonempty(&[
+            str_at(cvrf, &["document_distribution"]),
+            str_at(cvrf, &["document_distribution", "content"]),
+        ]),
+        severity: first_nonempty(&[
+            str_at(cvrf, &["aggregate_severity", "content"]),
+            str_at(cvrf, &["aggregate_severity"]),
+        ]),
+        ..UpdateRecord::default()
+    };
+
+    let notes = cvrf.get("document_notes").unwrap_or(&Value::Null);
+    for note in list_at(notes, "note") {
+        match str_at(note, &["type"]) {
+            "Summary" if record.summary.is_empty() => {
+                record.summary = str_at(note, &["content"]).to_owned()
+            }
+            "General" if record.description.is_empty() => {
+                record.description = str_at(note, &["content"]).to_owned()
+            }
+            _ => (),
+        }
+    }
+
+    let references = cvrf.get("document_references").unwrap_or(&Value::Null);
+    for reference in list_at(references, "reference") {
+        record.references.push(document_reference(
+            &record.id,
+            str_at(reference, &["type"]) == "Self",
+            str_at(reference, &["url"]),
+            str_at(reference, &["description"]),
+        ));
+    }
+
+    for vulnerability in list_at(cvrf, "vulnerability") {
+        if let Some(cve) = vulnerability.get("cve").and_then(Value::as_str) {
+            record.references.push(cve_reference(cve, &Value::Null));
+        }
+        if record.solution.is_empty() {
+            let remediations = vulnerability.get("remediations").unwrap_or(&Value::Null);
+            if let Some(fix) = list_at(remediations, "remediation")
+                .into_iter()
+                .find(|r| str_at(r, &["type"]) == "Vendor Fix")
+            {
+                record.solution = str_at(fix, &["description"]).to_owned();
+            }
+        }
+    }
+
+    let tree = cvrf.get("product_tree").unwrap_or(&Value::Null);
+    let mut products = BTreeMap::new();
+    let mut packages = BTreeMap::new();
+    collect_cvrf_branches(tree, &mut products, &mut packages);
+
+    let relationships = list_at(tree, "relationship")
+        .into_iter()
+        .map(|r| {
+            (
+                str_at(r, &["relates_to_product_reference"]),
+                str_at(r, &["product_reference"]),
+            )
+        })
+        .collect::<Vec<_>>();
+    record.pkglist = build_collections(&products, &packages, &relationships);
+
+    Ok(record)
+}
+
+fn updaterecord_to_csaf(record: &UpdateRecord, profile: CsafProfile) -> Value {
+    let mut product_names = Vec::new();
+    let mut product_versions = Vec::new();
+    let mut relationships = Vec::new();
+    let mut fixed = Vec::new();
+
+    for (idx, collection) in record.pkglist.iter().enumerate() {
+        let shortname = collection.shortname.as_deref().unwrap_or_default();
+        let product_id = match (shortname, collection.name.as_str()) {
+            ("", "") => format!("collection-{}", idx),
+            ("", name) => name.to_owned(),
+            (shortname, _) => shortname.to_owned(),
+        };
+        let product_name = if collection.name.is_empty() {
+            product_id.clone()
+        } else {
+            collection.name.clone()
+        };
+        product_names.push(json!({
+            "category": "product_name",
+            "name": product_name,
+            "product": { "name": product_name, "product_id": product_id },
+        }));
+
+        for package in &collection.packages {
+            let epoch = if package.epoch.is_empty() {
+                "0"
+            } else {
+                &package.epoch
+            };
+            let nevra = format!(
+                "{}-{}:{}-{}.{}",
+                package.name, epoch, package.version, package.release, package.arch
+            );
+            let purl = format!(
+                "pkg:rpm/{}@{}-{}?arch={}&epoch={}",
+                package.name, package.version, package.release, package.arch, epoch
+            );
+            let version_branch = json!({
+                "category": "product_version",
+                "name": nevra,
+                "product": {
+                    "name": nevra,
+                    "product_id": nevra,
+                    "product_identification_helper": { "purl": purl },
+                },
+            });
+            if !product_versions.contains(&version_branch) {
+                product_versions.push(version_branch);
+            }
+
+            let component_id = format!("{}:{}", product_id, nevra);
+            relationships.push(json!({
+                "category": "default_component_of",
+                "full_product_name": {
+                    "name": format!("{} as a component of {}", nevra, product_name),
+                    "product_id": component_id,
+                },
+                "product_reference": nevra,
+                "relates_to_product_reference": product_id,
+            }));
+            fixed.push(component_id);
+        }
+    }
+
+    let mut references = Vec::new();
+    let mut vulnerabilities = Vec::new();
+    for reference in &record.references {
+        match reference.reftype.as_str() {
+            "cve" => {
+                let cve = if reference.id.is_empty() {
+                    &reference.title
+                } else {
+                    &reference.id
+                };
+                let mut vulnerability = json!({
+                    "cve": cve,
+                    "product_status": { "fixed": fixed },
+                    "remediations": [{
+                        "category": "vendor_fix",
+                        "details": first_nonempty(&[&record.solution, &record.title]),
+                        "product_ids": fixed,
+                    }],
+                });
+                if !reference.href.is_empty() {
+                    vulnerability["references"] = json!([{
+                        "category": "self",
+                        "summary": cve,
+                        "url": reference.href,
+                    }]);
+                }
+                if !record.severity.is_empty() {
+                    vulnerability["threats"] = json!([{
+                        "category": "impact",
+                        "details": record.severity,
+                    }]);
+                }
+                vulnerabilities.push(vulnerability);
+            }
+            reftype if !reference.href.is_empty() => references.push(json!({
+                "category": if reftype == "self" { "self" } else { "external" },
+                "summary": first_nonempty(&[&reference.title, &reference.id, &reference.href]),
+                "url": reference.href,
+            })),
+            _ => (),
+        }
+    }
+
+    let mut notes = Vec::new();
+    if !record.summary.is_empty() {
+        notes.push(json!({ "category": "summary", "title": "Topic", "text": record.summary }));
+    }
+    if !record.description.is_empty() {
+        notes
+            .push(json!({ "category": "general", "title": "Details", "text": record.description }));
+    }
+
+    let initial_date = record.issued_date.as_deref().map(csaf_date);
+    let current_date = record
+        .updated_date
+        .as_deref()
+        .map(csaf_date)
+        .or(initial_date.clone());
+    let version = if record.version.is_empty() {
+        "1"
+    } else {
+        &record.version
+    };
+    let status = match record.status.as_str() {
+        "final" | "stable" | "" => "final",
+        "testing" => "interim",
+        _ => "draft",
+    };
+
+    let mut document = json!({
+        "category": profile.category(),
+        "csaf_version": "2.0",
+        "title": record.title,
+        "publisher": {
+            "category": "vendor",
+            "name": record.from,
+        },
+        "tracking": {
+            "id": record.id,
+            "status": status,
+            "version": version,
+            "initial_release_date": initial_date,
+            "current_release_date": current_date,
+            "revision_history": [{
+                "date": current_date,
+                "number": version,
+                "summary": "Exported from updateinfo",
+            }],
+        },
+    });
+    if !record.rights.is_empty() {
+        document["distribution"] = json!({ "text": record.rights });
+    }
+    if !record.severity.is_empty() {
+        document["aggregate_severity"] = json!({ "text": record.severity });
+    }
+    if !notes.is_empty() {
+        document["notes"] = Value::Array(notes);
+    }
+    if !references.is_empty() {
+        document["references"] = Value::Array(references);
+    }
+
+    let mut branches = product_names;
+    branches.extend(product_versions);
+
+    json!({
+        "document": document,
+        "product_tree": {
+            "branches": branches,
+            "relationships": relationships,
+        },
+        "vulnerabilities": vulnerabilities,
+    })
+}
+
+/// Convert a `YYYY-MM-DD HH:MM:SS` updateinfo date into an ISO 8601 timestamp.
+fn csaf_date(date: &str) -> String {
+    match date.split_once(' ') {
+        Some((day, time)) => format!("{}T{}Z", day, time),
+        None if date.len() == 10 => format!("{}T00:00:00Z", date),
+        None => date.to_owned(),
+    }
+}
+
+/// Walk the CSAF product tree, recording product names and RPM packages by product ID.
+fn collect_csaf_branches<'a>(
+    node: &'a Value,
+    products: &mut BTreeMap<&'a str, String>,
+    packages: &mut BTreeMap<&'a str, UpdateCollectionPackage>,
+) {
+    for branch in list_at(node, "branches") {
+        let name = str_at(branch, &["name"]);
+        let id = str_at(branch, &["product", "product_id"]);
+        match str_at(branch, &["category"]) {
+            "product_name" if !id.is_empty() => {
+                products.insert(id, name.to_owned());
+            }
+            "product_version" if !id.is_empty() => {
+                let purl = str_at(
+                    branch,
+                    &["product", "product_identification_helper", "purl"],
+                );
+                let package = if purl.is_empty() {
+                    package_from_nevra(name)
+                } else {
+                    package_from_purl(purl)
+                };
+                if let Some(package) = package {
+                    packages.insert(id, package);
+                }
+            }
+            _ => (),
+        }
+        collect_csaf_branches(branch, products, packages);
+    }
+}
+
+/// Walk the CVRF product tree, recording product names and RPM packages by product ID.
+fn collect_cvrf_branches<'a>(
+    node: &'a Value,
+    products: &mut BTreeMap<&'a str, String>,
+    packages: &mut BTreeMap<&'a str, UpdateCollectionPackage>,
+) {
+    for branch in list_at(node, "branch") {
+        let id = str_at(branch, &["full_product_name", "product_id"]);
+        match str_at(branch, &["type"]) {
+            "Product Name" if !id.is_empty() => {
+                let name = first_nonempty(&[
+                    str_at(branch, &["full_product_name", "content"]),
+                    str_at(branch, &["name"]),
+                ]);
+                products.insert(id, name);
+            }
+            "Product Version" if !id.is_empty() => {
+                if let Some(package) = package_from_nevra(str_at(branch, &["name"])) {
+                    packages.insert(id, package);
+                }
+            }
+            _ => (),
+        }
+        collect_cvrf_branches(branch, products, packages);
+    }
+}
+
+/// Group packages into one collection per product, following the "component of" relationships.
+///
+/// Packages which aren't related to any known product end up in a single collection of their own.
+fn build_collections(
+    products: &BTreeMap<&str, String>,
+    packages: &BTreeMap<&str, UpdateCollectionPackage>,
+    relationships: &[(&str, &str)],
+) -> Vec<UpdateCollection> {
+    let mut collections: Vec<UpdateCollection> = Vec::new();
+    let mut seen = BTreeSet::new();
+
+    for (product_id, package_id) in relationships {
+        let Some(package) = packages.get(package_id) else {
+            continue;
+        };
+        seen.insert(*package_id);
+
+        let collection = match collections
+            .iter_mut()
+            .position(|c| c.shortname.as_deref() == Some(&**product_id))
+        {
+            Some(idx) => &mut collections[idx],
+            None => {
+                collections.push(UpdateCollection {
+                    name: products
+                        .get(product_id)
+                        .map_or(product_id.to_string(), |name| name.clone()),
+                    shortname: Some(product_id.to_string()),
+                    ..UpdateCollection::default()
+                });
+                collections.last_mut().unwrap()
+            }
+        };
+        if !collection.packages.contains(package) {
+            collection.packages.push(package.clone());
+        }
+    }
+
+    let unrelated = packages
+        .iter()
+        .filter(|(id, _)| !seen.contains(*id))
+        .map(|(_, package)| package.clone())
+        .collect::<Vec<_>>();
+    if !unrelated.is_empty() {
+        collections.push(UpdateCollection {
+            packages: unrelated,
+            ..UpdateCollection::default()
+        });
+    }
+
+    collections
+}
+
+/// Parse a package from a `pkg:rpm/<namespace>/<name>@<version>-<release>?arch=<arch>&epoch=<epoch>` purl.
+fn package_from_purl(purl: &str) -> Option<UpdateCollectionPackage> {
+    let path = purl.strip_prefix("pkg:rpm/")?;
+    let (path, qualifiers) = path.split_once('?').unwrap_or((path, ""));
+    let (path, vr) = path.split_once('@')?;
+    let name = path.rsplit('/').next()?;
+    let (version, release) = vr.rsplit_once('-')?;
+
+    let mut arch = "";
+    let mut epoch = "0";
+    for qualifier in qualifiers.split('&') {
+        match qualifier.split_once('=') {
+            Some(("arch", val)) => arch = val,
+            Some(("epoch", val)) => epoch = val,
+            _ => (),
+        }
+    }
+    if arch.is_empty() {
+        return None;
+    }
+
+    Some(collection_package(name, epoch, version, release, arch))
+}
+
+/// Parse a package from a `<name>-[<epoch>:]<version>-<release>.<arch>` string.
+fn package_from_nevra(nevra: &str) -> Option<UpdateCollectionPackage> {
+    let nevra = nevra.strip_suffix(".rpm").unwrap_or(nevra);
+    let (nevr, arch) = nevra.rsplit_once('.')?;
+    let (nev, release) = nevr.rsplit_once('-')?;
+    let (name, ev) = nev.rsplit_once('-')?;
+    let (epoch, version) = ev.split_once(':').unwrap_or(("0", ev));
+    if name.is_empty() || version.is_empty() || release.is_empty() {
+        return None;
+    }
+
+    Some(collection_package(name, epoch, version, release, arch))
+}
+
+fn collection_package(
+    name: &str,
+    epoch: &str,
+    version: &str,
+    release: &str,
+    arch: &str,
+) -> UpdateCollectionPackage {
+    UpdateCollectionPackage {
+        name: name.to_owned(),
+        epoch: epoch.to_owned(),
+        version: version.to_owned(),
+        release: release.to_owned(),
+        arch: arch.to_owned(),
+        filename: format!("{}-{}-{}.{}.rpm", name, version, release, arch),
+        ..UpdateCollectionPackage::default()
+    }
+}
+
+/// Classify a document-level reference, recognizing links to Bugzilla.
+fn document_reference(record_id: &str, is_self: bool, url: &str, title: &str) -> UpdateReference {
+    if is_self {
+        UpdateReference {
+            href: url.to_owned(),
+            id: record_id.to_owned(),
+            title: title.to_owned(),
+            reftype: "self".to_owned(),
+        }
+    } else if let Some((_, bug)) = url.split_once("show_bug.cgi?id=") {
+        bugzilla_reference(bug, title)
+    } else {
+        UpdateReference {
+            href: url.to_owned(),
+            id: String::new(),
+            title: title.to_owned(),
+            reftype: "other".to_owned(),
+        }
+    }
+}
+
+fn cve_reference(cve: &str, vulnerability: &Value) -> UpdateReference {
+    let href = list_at(vulnerability, "references")
+        .into_iter()
+        .find(|r| str_at(r, &["category"]) == "self")
+        .map(|r| str_at(r, &["url"]).to_owned())
+        .unwrap_or_else(|| format!("https://access.redhat.com/security/cve/{}", cve));
+
+    UpdateReference {
+        href,
+        id: cve.to_owned(),
+        title: cve.to_owned(),
+        reftype: "cve".to_owned(),
+    }
+}
+
+fn bugzilla_reference(id: &str, title: &str) -> UpdateReference {
+    UpdateReference {
+        href: format!("https://bugzilla.redhat.com/show_bug.cgi?id={}", id),
+        id: id.to_owned(),
+        title: title.to_owned(),
+        reftype: "bugzilla".to_owned(),
+    }
+}
+
+/// Map the document category onto an updateinfo type, falling back to the advisory ID prefix.
+fn update_type(category: &str, id: &str) -> &'static str {
+    let category = category.to_lowercase();
+    if category.contains("security") || id.starts_with("RHSA") {
+        "security"
+    } else if category.contains("bug") || id.starts_with("RHBA") {
+        "bugfix"
+    } else if category.contains("enhancement") || id.starts_with("RHEA") {
+        "enhancement"
+    } else {
+        "security"
+    }
+}
+
+/// Convert an ISO 8601 timestamp into the `YYYY-MM-DD HH:MM:SS` form used by updateinfo.xml.
+fn date_at(value: &Value, key: &str) -> Option<String> {
+    let date = value.get(key)?.as_str()?;
+    let date = date.get(..19).unwrap_or(date);
+    Some(date.replacen('T', " ", 1))
+}
+
+fn required_str<'a>(value: &'a Value, key: &'static str) -> Result<&'a str, MetadataError> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or(MetadataError::MissingFieldError(key))
+}
+
+fn str_at<'a>(value: &'a Value, path: &[&str]) -> &'a str {
+    path.iter()
+        .try_fold(value, |value, key| value.get(key))
+        .and_then(Value::as_str)
+        .unwrap_or("")
+}
+
+fn first_nonempty(values: &[&str]) -> String {
+    values
+        .iter()
+        .find(|v| !v.is_empty())
+        .copied()
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// The JSON documents are converted from XML, so a list with one element is often just the element.
+fn list_at<'a>(value: &'a Value, key: &str) -> Vec<&'a Value> {
+    match value.get(key) {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(value) => vec![value],
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The freshness logic of dnf, for clients which keep their own cache of a repository's metadata.
+//!
+//! Cached metadata is used as it is until it expires (see [`MetadataExpire`]). Once it has, the client
+//! fetches `repomd.xml` and compares it with the cached one using [`RepositoryProbe::cache_status()`],
+//! downloading only the metadata files which changed, if any, and starting the expiry over.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{MetadataError, MetadataType, RepositoryProbe};
+
+/// How long cached metadata is used before checking whether the repository has changed, like the
+/// `metadata_expire` option of dnf. The default is 48 hours, as in dnf.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MetadataExpire {
+    /// The metadata never expires, it's only refreshed when asked to
+    Never,
+    /// The metadata expires this many seconds after it was last checked. 0 means it always has.
+    After(u64),
+}
+
+impl Default for MetadataExpire {
+    fn default() -> Self {
+        MetadataExpire::After(48 * 60 * 60)
+    }
+}
+
+impl MetadataExpire {
+    /// Whether metadata which was last checked against the repository at `checked` has expired at `now`,
+    /// both as Unix timestamps. Metadata checked in the future (according to a clock which was wrong at
+    /// the time) has expired.
+    pub fn is_expired(&self, checked: u64, now: u64) -> bool {
+        self.expires_in(checked, now) == Some(0)
+    }
+
+    /// How many seconds are left at `now` until metadata which was last checked at `checked` expires, 0 if
+    /// it has, or `None` if it never does.
+    pub fn expires_in(&self, checked: u64, now: u64) -> Option<u64> {
+        match self {
+            MetadataExpire::Never => None,
+            MetadataExpire::After(_) if checked > now => Some(0),
+            MetadataExpire::After(seconds) => Some(seconds.saturating_sub(now - checked)),
+        }
+    }
+
+    /// Like [`MetadataExpire::is_expired()`], for metadata checked at `checked` (e.g. the modification time
+    /// of the cached `repomd.xml`) as of now.
+    pub fn is_expired_now(&self, checked: SystemTime) -> bool {
+        let timestamp =
+            |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        self.is_expired(timestamp(checked), timestamp(SystemTime::now()))
+    }
+}
+
+impl FromStr for MetadataExpire {
+    type Err = MetadataError;
+
+    /// Parse the value of `metadata_expire` as dnf does: `never` or `-1`, or a number of seconds with an
+    /// optional `s`, `m`, `h` or `d` suffix for seconds, minutes, hours or days, e.g. `90m`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value == "never" || value == "-1" {
+            return Ok(MetadataExpire::Never);
+        }
+        let (number, unit) = match value.char_indices().last() {
+            Some((idx, 's')) => (&value[..idx], 1),
+            Some((idx, 'm')) => (&value[..idx], 60),
+            Some((idx, 'h')) => (&value[..idx], 60 * 60),
+            Some((idx, 'd')) => (&value[..idx], 24 * 60 * 60),
+            _ => (value, 1),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .map(MetadataExpire::After)
+            .ok_or_else(|| MetadataError::InvalidFieldError("metadata_expire", value.to_owned()))
+    }
+}
+
+impl fmt::Display for MetadataExpire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataExpire::Never => f.write_str("never"),
+            MetadataExpire::After(seconds) => write!(f, "{}", seconds),
+        }
+    }
+}
+
+/// How the `repomd.xml` of a repository compares with the cached one, see
+/// [`RepositoryProbe::cache_status()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The repository hasn't changed, the cached metadata is current again
+    Unchanged,
+    /// The repository is newer than the cache
+    Updated {
+        /// The types of metadata which are new or whose files changed, which are the only ones that
+        /// need downloading
+        changed: Vec<MetadataType>,
+    },
+    /// The repository is older than the cache, as when a mirror hasn't caught up yet. The cached
+    /// metadata should be kept, and perhaps another mirror tried.
+    Outdated,
+}
+
+impl RepositoryProbe {
+    /// Compare the `repomd.xml` of the repository with `cached`, the probe of the cached one, to find out
+    /// whether the cached metadata needs refreshing.
+    ///
+    /// Which one is newer is decided by the revisions if they're both numbers (`createrepo_c` uses the
+    /// time the repository was generated by default), or else by the newest timestamps of the records.
+    pub fn cache_status(&self, cached: &RepositoryProbe) -> CacheStatus {
+        if !self.has_changed_since(cached) {
+            return CacheStatus::Unchanged;
+        }
+        let revision = |probe: &RepositoryProbe| {
+            probe
+                .revision
+                .as_deref()
+                .and_then(|revision| revision.parse::<u64>().ok())
+        };
+        let outdated = match (revision(self), revision(cached)) {
+            (Some(revision), Some(cached)) => revision < cached,
+            _ => self.timestamp < cached.timestamp,
+        };
+        if outdated {
+            return CacheStatus::Outdated;
+        }
+
+        let changed: Vec<MetadataType> = self
+            .records
+            .iter()
+            .filter(|record| match cached.record(&record.metadata_type) {
+                Some(previous) => previous.checksum != record.checksum,
+                None => true,
+            })
+            .map(|record| record.metadata_type.clone())
+            .collect();
+        // e.g. only the tags or the order of the records changed
+        if changed.is_empty() && self.revision == cached.revision {
+            return CacheStatus::Unchanged;
+        }
+        CacheStatus::Updated { changed }
+    }
+}
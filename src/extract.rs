@@ -0,0 +1,63 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::path::Path;
+
+use crate::logging;
+use crate::{MetadataError, PublishReport, Repository, RepositoryOptions, RepositoryWriter};
+
+/// Create a new repository at `destination` with only the packages of the repository at `source` with
+/// the given NEVRAs and their dependencies, e.g. a minimal repository to build an image from a kickstart
+/// or a lockfile. See [`Repository::extract()`] for what is kept.
+///
+/// The RPMs are copied to the same `location_href` under `destination`, except for those with a
+/// `location_base`, which are left where they are. The metadata is written fresh according to `options`.
+/// The metadata files which aren't generated from the packages (such as `group` and `modules`) refer to
+/// packages which may have been left out, and aren't copied.
+pub fn extract_repository(
+    source: &Path,
+    destination: &Path,
+    nevras: &[&str],
+    options: RepositoryOptions,
+) -> Result<PublishReport, MetadataError> {
+    let _span = logging::span!(
+        "extract {} packages of {} into {}",
+        nevras.len(),
+        source.display(),
+        destination.display()
+    );
+    let extracted = Repository::load_from_directory(source)?.extract(nevras)?;
+
+    for package in extracted.packages().values() {
+        if package.location_base().is_some() {
+            continue;
+        }
+        let href = package.location_href();
+        let target = destination.join(href);
+        logging::debug!("copying {}", href);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(source.join(href), &target)?;
+    }
+
+    let mut writer =
+        RepositoryWriter::new_with_options(destination, extracted.packages().len(), options)?;
+    for package in extracted.packages().values() {
+        writer.add_package(package)?;
+    }
+    for advisory in extracted.advisories().values() {
+        writer.add_advisory(advisory)?;
+    }
+    for product in extracted.products() {
+        writer.add_product(product);
+    }
+    for pattern in extracted.patterns() {
+        writer.add_pattern(pattern);
+    }
+    writer.finish()
+}
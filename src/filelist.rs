@@ -12,9 +12,10 @@ use quick_xml::{Reader, Writer};
 use crate::Checksum;
 
 use super::metadata::{
-    FileType, FilelistsXml, Package, PackageFile, RpmMetadata, XML_NS_FILELISTS,
+    FileType, FilelistsXml, Package, PackageFile, ParseContext, ParseError, ParseMode,
+    ParseWarning, RpmMetadata, XmlStyle, XML_NS_FILELISTS,
 };
-use super::{MetadataError, Repository, EVR};
+use super::{utils, MetadataError, Repository, EVR};
 
 const TAG_FILELISTS: &[u8] = b"filelists";
 const TAG_PACKAGE: &[u8] = b"package";
@@ -35,7 +36,7 @@ impl RpmMetadata for FilelistsXml {
         let mut package = None;
         loop {
             reader.read_package(&mut package)?;
-            if package == None {
+            if package.is_none() {
                 break;
             }
             let pkgid = package.as_ref().unwrap().pkgid().to_owned();
@@ -65,19 +66,31 @@ impl RpmMetadata for FilelistsXml {
 
 impl FilelistsXml {
     pub fn new_writer<W: Write>(writer: quick_xml::Writer<W>) -> FilelistsXmlWriter<W> {
-        FilelistsXmlWriter { writer }
+        FilelistsXmlWriter {
+            writer,
+            style: XmlStyle::Standard,
+        }
     }
 
     pub fn new_reader<R: BufRead>(reader: quick_xml::Reader<R>) -> FilelistsXmlReader<R> {
-        FilelistsXmlReader { reader }
+        FilelistsXmlReader {
+            reader,
+            context: ParseContext::new("filelists", "filelists"),
+        }
     }
 }
 
 pub struct FilelistsXmlWriter<W: Write> {
     writer: Writer<W>,
+    style: XmlStyle,
 }
 
 impl<W: Write> FilelistsXmlWriter<W> {
+    /// Set how the metadata is laid out and escaped. Must be set before the header is written.
+    pub fn set_style(&mut self, style: XmlStyle) {
+        self.style = style;
+    }
+
     pub fn write_header(&mut self, num_pkgs: usize) -> Result<(), MetadataError> {
         // <?xml version="1.0" encoding="UTF-8"?>
         self.writer
@@ -87,19 +100,29 @@ impl<W: Write> FilelistsXmlWriter<W> {
         let mut filelists_tag = BytesStart::borrowed_name(TAG_FILELISTS);
         filelists_tag.push_attribute(("xmlns", XML_NS_FILELISTS));
         filelists_tag.push_attribute(("packages", num_pkgs.to_string().as_str()));
-        self.writer
-            .write_event(Event::Start(filelists_tag.to_borrowed()))?;
+        match self.style {
+            XmlStyle::Standard | XmlStyle::Legacy => self
+                .writer
+                .write_event(Event::Start(filelists_tag.to_borrowed()))?,
+            XmlStyle::CreaterepoC => {
+                utils::write_unindented_start(&mut self.writer, &filelists_tag)?
+            }
+        }
 
         Ok(())
     }
 
     pub fn write_package(&mut self, package: &Package) -> Result<(), MetadataError> {
+        let style = self.style;
         // <package pkgid="a2d3bce512f79b0bc840ca7912a86bbc0016cf06d5c363ffbb6fd5e1ef03de1b" name="fontconfig" arch="x86_64">
         let mut package_tag = BytesStart::borrowed_name(TAG_PACKAGE);
         let pkgid = package.pkgid();
-        package_tag.push_attribute(("pkgid", pkgid));
-        package_tag.push_attribute(("name", package.name()));
-        package_tag.push_attribute(("arch", package.arch()));
+        package_tag.push_attribute(utils::package_attribute(style, "pkgid", pkgid));
+        package_tag.push_attribute(utils::package_attribute(style, "name", package.name()));
+        package_tag.push_attribute(utils::package_attribute(style, "arch", package.arch()));
+        if let Some(unknown) = package.unknown_xml() {
+            unknown.filelists.push_attributes(&mut package_tag);
+        }
         self.writer
             .write_event(Event::Start(package_tag.to_borrowed()))?;
 
@@ -107,16 +130,20 @@ impl<W: Write> FilelistsXmlWriter<W> {
         let (epoch, version, release) = package.evr().values();
         self.writer
             .create_element(TAG_VERSION)
-            .with_attribute(("epoch", epoch))
-            .with_attribute(("ver", version))
-            .with_attribute(("rel", release))
+            .with_attribute(utils::package_attribute(style, "epoch", epoch))
+            .with_attribute(utils::package_attribute(style, "ver", version))
+            .with_attribute(utils::package_attribute(style, "rel", release))
             .write_empty()?;
 
         // <file type="dir">/etc/fonts/conf.avail</file>
         package
-            .files()
+            .load_files()
             .iter()
-            .try_for_each(|f| write_file_element(&mut self.writer, f))?;
+            .try_for_each(|f| write_file_element(&mut self.writer, f, style))?;
+
+        if let Some(unknown) = package.unknown_xml() {
+            unknown.filelists.write_elements(&mut self.writer)?;
+        }
 
         // </package>
         self.writer.write_event(Event::End(package_tag.to_end()))?;
@@ -148,43 +175,91 @@ impl<W: Write> FilelistsXmlWriter<W> {
 pub(crate) fn write_file_element<W: Write>(
     writer: &mut Writer<W>,
     file: &PackageFile,
+    style: XmlStyle,
 ) -> Result<(), MetadataError> {
     let mut file_tag = BytesStart::borrowed_name(TAG_FILE);
     if file.filetype != FileType::File {
-        file_tag.push_attribute(("type".as_bytes(), file.filetype.to_values()));
+        file_tag.push_attribute(utils::package_attribute(
+            style,
+            "type",
+            file.filetype.to_values(),
+        ));
     }
     writer.write_event(Event::Start(file_tag.to_borrowed()))?;
-    writer.write_event(Event::Text(BytesText::from_plain_str(&file.path)))?;
+    writer.write_event(Event::Text(utils::text(style, &file.path)))?;
     writer.write_event(Event::End(file_tag.to_end()))?;
     Ok(())
 }
 
 pub struct FilelistsXmlReader<R: BufRead> {
     reader: Reader<R>,
+    context: ParseContext,
 }
 
 impl<R: BufRead> FilelistsXmlReader<R> {
     pub fn read_header(&mut self) -> Result<usize, MetadataError> {
-        parse_header(&mut self.reader)
+        parse_header(&mut self.reader, &mut self.context)
     }
 
     pub fn read_package(&mut self, package: &mut Option<Package>) -> Result<(), MetadataError> {
-        parse_package(package, &mut self.reader)
+        // an entry can only be skipped when it's read into a new package
+        let new_package = package.is_none();
+        loop {
+            self.context.next_entry();
+            match parse_package(package, &mut self.reader, &mut self.context) {
+                Err(e) if new_package => {
+                    if let Err(e) = self.context.recover(&mut self.reader, TAG_PACKAGE, e) {
+                        return Err(self.context.locate(&self.reader, e, package.as_ref()));
+                    }
+                    *package = None;
+                }
+                Err(e) => return Err(self.context.locate(&self.reader, e, package.as_ref())),
+                result => return result,
+            }
+        }
+    }
+
+    /// Set how metadata which doesn't follow the spec is dealt with. Strict by default.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.context.mode = mode;
+    }
+
+    pub fn parse_mode(&self) -> ParseMode {
+        self.context.mode
+    }
+
+    /// Set whether the elements and attributes of packages which aren't understood are kept, so that
+    /// they're written out again. Off by default.
+    pub fn set_preserve_unknown(&mut self, preserve: bool) {
+        self.context.preserve_unknown = preserve;
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        self.context.take_warnings()
+    }
+
+    /// Take the errors of the entries skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.context.take_errors()
     }
 }
 
 // <?xml version="1.0" encoding="UTF-8"?>
 // <filelists xmlns="http://linux.duke.edu/metadata/filelists" packages="35">
-fn parse_header<R: BufRead>(reader: &mut Reader<R>) -> Result<usize, MetadataError> {
+fn parse_header<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+) -> Result<usize, MetadataError> {
     let mut buf = Vec::new();
 
     // TODO: get rid of this buffer
     loop {
         match reader.read_event(&mut buf)? {
             Event::Decl(_) => (),
-            Event::Start(e) if e.name().as_ref() == TAG_FILELISTS => {
-                let count = e.try_get_attribute("packages")?.unwrap().value;
-                return Ok(std::str::from_utf8(&count)?.parse()?);
+            Event::Start(e) if e.name() == TAG_FILELISTS => {
+                context.start_root(reader, &e)?;
+                return context.numeric_attribute(reader, &e, "packages");
             }
             _ => return Err(MetadataError::MissingHeaderError),
         }
@@ -200,30 +275,33 @@ fn parse_header<R: BufRead>(reader: &mut Reader<R>) -> Result<usize, MetadataErr
 pub fn parse_package<R: BufRead>(
     package: &mut Option<Package>,
     reader: &mut Reader<R>,
+    context: &mut ParseContext,
 ) -> Result<(), MetadataError> {
     let mut buf = Vec::with_capacity(128);
 
     loop {
         match reader.read_event(&mut buf)? {
-            Event::End(e) if e.name().as_ref() == TAG_PACKAGE => break,
+            Event::End(e) if e.name() == TAG_PACKAGE => break,
 
-            Event::Start(e) => match e.name().as_ref() {
+            Event::Start(e) => match e.name() {
                 TAG_PACKAGE => {
                     let pkgid = e
                         .try_get_attribute("pkgid")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("pkgid"))?
-                        .unescape_and_decode_value(reader)?;
-                    let name = e
-                        .try_get_attribute("name")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("name"))?
-                        .unescape_and_decode_value(reader)?;
-                    let arch = e
-                        .try_get_attribute("arch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("arch"))?
+                        .ok_or(MetadataError::MissingAttributeError("pkgid"))?
                         .unescape_and_decode_value(reader)?;
+                    context.start_entry(reader, &e);
+                    let name = context.attribute(reader, &e, "name", "")?;
+                    context.entry = Some(name.clone());
+                    let arch = context.attribute(reader, &e, "arch", "")?;
 
                     if let Some(pkg) = package {
-                        assert!(pkg.pkgid() == &pkgid); // TODO err instead of assert
+                        if pkg.pkgid() != pkgid {
+                            return Err(MetadataError::InconsistentMetadataError(format!(
+                                "filelists.xml lists package {} where primary.xml lists {}",
+                                pkgid,
+                                pkg.pkgid()
+                            )));
+                        }
                     } else {
                         let mut pkg = Package::default();
                         pkg.set_name(&name)
@@ -231,19 +309,38 @@ pub fn parse_package<R: BufRead>(
                             .set_checksum(Checksum::Unknown(pkgid));
                         *package = Some(pkg);
                     };
+
+                    let attributes =
+                        context.unknown_attributes(reader, &e, &["pkgid", "name", "arch"])?;
+                    if !attributes.is_empty() {
+                        package
+                            .as_mut()
+                            .unwrap()
+                            .unknown_xml_mut()
+                            .filelists
+                            .attributes = attributes;
+                    }
                 }
                 TAG_VERSION => {
-                    package.as_mut().unwrap().set_evr(parse_evr(reader, &e)?);
+                    package
+                        .as_mut()
+                        .unwrap()
+                        .set_evr(parse_evr(reader, &e, context)?);
                 }
                 TAG_FILE => {
-                    let file = parse_file(reader, &e)?;
+                    let file = parse_file(reader, &e, context)?;
                     // TODO: temporary PackageFile?
                     package
                         .as_mut()
                         .unwrap()
                         .add_file(file.filetype, &file.path);
                 }
-                _ => (),
+                _ => {
+                    if let Some(xml) = context.unknown_element(reader, &e)? {
+                        let unknown = package.as_mut().unwrap().unknown_xml_mut();
+                        unknown.filelists.elements.push(xml);
+                    }
+                }
             },
             Event::Eof => break,
             _ => (),
@@ -258,19 +355,11 @@ pub fn parse_package<R: BufRead>(
 pub fn parse_evr<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &mut ParseContext,
 ) -> Result<EVR, MetadataError> {
-    let epoch = open_tag
-        .try_get_attribute("epoch")?
-        .ok_or_else(|| MetadataError::MissingAttributeError("epoch"))?
-        .unescape_and_decode_value(reader)?;
-    let version = open_tag
-        .try_get_attribute("ver")?
-        .ok_or_else(|| MetadataError::MissingAttributeError("ver"))?
-        .unescape_and_decode_value(reader)?;
-    let release = open_tag
-        .try_get_attribute("rel")?
-        .ok_or_else(|| MetadataError::MissingAttributeError("rel"))?
-        .unescape_and_decode_value(reader)?;
+    let epoch = context.attribute(reader, open_tag, "epoch", "0")?;
+    let version = context.attribute(reader, open_tag, "ver", "")?;
+    let release = context.attribute(reader, open_tag, "rel", "")?;
 
     Ok(EVR::new(epoch, version, release))
 }
@@ -279,12 +368,17 @@ pub fn parse_evr<R: BufRead>(
 pub fn parse_file<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &mut ParseContext,
 ) -> Result<PackageFile, MetadataError> {
-    let mut file = PackageFile::default();
-    file.path = reader.read_text(open_tag.name(), &mut Vec::with_capacity(128))?;
+    let mut file = PackageFile {
+        path: reader.read_text(open_tag.name(), &mut Vec::with_capacity(128))?,
+        ..PackageFile::default()
+    };
 
     if let Some(filetype) = open_tag.try_get_attribute("type")? {
-        file.filetype = FileType::try_create(filetype.value.as_ref())?;
+        file.filetype = context.tolerate(FileType::try_create(filetype.value.as_ref()), || {
+            FileType::File
+        })?;
     }
 
     Ok(file)
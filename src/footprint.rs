@@ -0,0 +1,284 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::{size_of, size_of_val};
+use std::sync::Arc;
+
+use crate::{
+    Changelog, LocalizedText, Package, PackageFile, Pattern, RepomdRecord, Repository, Requirement,
+    UnknownPackageXml, UnknownXml, UpdateCollection, UpdateCollectionPackage, UpdateRecord,
+    UpdateReference,
+};
+
+/// The approximate memory used by a [`Repository`] in bytes, by what it's used for, see
+/// [`Repository::memory_footprint()`].
+///
+/// The sizes count the allocated capacity of strings and lists rather than their length, but not the
+/// overhead of the allocator, so the memory actually used is somewhat higher.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// The package structs themselves and the map of packages by pkgid
+    pub packages: usize,
+    /// The strings of the packages: names, EVRs, checksums, locations, summaries, descriptions etc.
+    pub strings: usize,
+    /// The requirements of the packages (provides, requires, conflicts etc.)
+    pub requirements: usize,
+    /// The file lists of the packages, from `filelists.xml`
+    pub files: usize,
+    /// The changelogs of the packages, from `other.xml`
+    pub changelogs: usize,
+    /// The advisories, from `updateinfo.xml`
+    pub advisories: usize,
+    /// Everything else: the records of `repomd.xml`, products and patterns
+    pub other: usize,
+}
+
+impl MemoryFootprint {
+    /// The total of every component.
+    pub fn total(&self) -> usize {
+        self.packages
+            + self.strings
+            + self.requirements
+            + self.files
+            + self.changelogs
+            + self.advisories
+            + self.other
+    }
+}
+
+impl fmt::Display for MemoryFootprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let components = [
+            ("packages", self.packages),
+            ("strings", self.strings),
+            ("requirements", self.requirements),
+            ("files", self.files),
+            ("changelogs", self.changelogs),
+            ("advisories", self.advisories),
+            ("other", self.other),
+        ];
+        for (name, bytes) in components {
+            writeln!(f, "{:<14}{:>12} bytes", name, bytes)?;
+        }
+        write!(f, "{:<14}{:>12} bytes", "total", self.total())
+    }
+}
+
+impl Repository {
+    /// An approximate breakdown of the memory used by the repository, e.g. to find out how much leaving
+    /// out the file lists or changelogs when loading it would save (see
+    /// [`Repository::load_from_directory_with_options()`]).
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        // each entry of an IndexMap is a hash and the key and value, plus an index in the hash table
+        let entry_size = |value_size| size_of::<usize>() * 2 + size_of::<String>() + value_size;
+        let mut footprint = MemoryFootprint {
+            packages: self.packages().capacity() * entry_size(size_of::<Package>()),
+            advisories: self.advisories().capacity() * entry_size(size_of::<UpdateRecord>()),
+            ..MemoryFootprint::default()
+        };
+        // the tables of stored files are shared by the packages, so each of them is counted once
+        let mut tables = HashSet::new();
+        for (pkgid, package) in self.packages() {
+            footprint.strings += pkgid.capacity();
+            add_package(&mut footprint, package);
+            if let Some(stored) = package.stored_files() {
+                if tables.insert(Arc::as_ptr(stored.table())) {
+                    footprint.files += stored.table().memory_footprint();
+                }
+            }
+        }
+
+        for (id, advisory) in self.advisories() {
+            footprint.advisories += id.capacity() + advisory_size(advisory);
+        }
+
+        let repomd = self.repomd();
+        footprint.other += repomd.records().capacity() * size_of::<RepomdRecord>();
+        for record in repomd.records() {
+            footprint.other += record.location_href.capacity()
+                + record.location_base.as_ref().map_or(0, String::capacity)
+                + unknown_xml_size(&record.unknown_xml);
+        }
+        for tag in repomd.repo_tags().iter().chain(repomd.content_tags()) {
+            footprint.other += size_of::<String>() + tag.capacity();
+        }
+        footprint.other += size_of_val(self.products());
+        for product in self.products() {
+            footprint.other += product.vendor.capacity()
+                + product.name.capacity()
+                + product.evr.epoch.capacity()
+                + product.evr.version.capacity()
+                + product.evr.release.capacity()
+                + product.arch.capacity()
+                + product.summary.capacity()
+                + product.description.capacity();
+        }
+        footprint.other += size_of_val(self.patterns());
+        for pattern in self.patterns() {
+            footprint.other += pattern_size(pattern);
+        }
+        footprint
+    }
+}
+
+fn add_package(footprint: &mut MemoryFootprint, package: &Package) {
+    let optional = |value: &Option<String>| value.as_ref().map_or(0, String::capacity);
+    let (_, digest) = package.checksum.to_values().unwrap_or_default();
+    footprint.strings += package.name.capacity()
+        + package.arch.capacity()
+        + package.evr.epoch.capacity()
+        + package.evr.version.capacity()
+        + package.evr.release.capacity()
+        + digest.len()
+        + package.location_href.capacity()
+        + optional(&package.location_base)
+        + package.summary.capacity()
+        + package.description.capacity()
+        + package.packager.capacity()
+        + package.url.capacity()
+        + package.rpm_license.capacity()
+        + package.rpm_vendor.capacity()
+        + package.rpm_group.capacity()
+        + package.rpm_buildhost.capacity()
+        + package.rpm_sourcerpm.capacity();
+    if let Some(xml) = &package.unknown_xml {
+        footprint.strings += size_of::<UnknownPackageXml>()
+            + unknown_xml_size(&xml.primary)
+            + unknown_xml_size(&xml.format)
+            + unknown_xml_size(&xml.filelists)
+            + unknown_xml_size(&xml.other);
+    }
+
+    for requirements in [
+        &package.rpm_requires,
+        &package.rpm_provides,
+        &package.rpm_conflicts,
+        &package.rpm_obsoletes,
+        &package.rpm_suggests,
+        &package.rpm_enhances,
+        &package.rpm_recommends,
+        &package.rpm_supplements,
+    ] {
+        footprint.requirements += requirements_size(requirements);
+    }
+
+    footprint.files += package.rpm_files.capacity() * size_of::<PackageFile>();
+    for file in &package.rpm_files {
+        footprint.files += file.path.capacity();
+    }
+    if let Some(stored) = &package.stored_files {
+        footprint.files += stored.memory_footprint();
+    }
+    footprint.changelogs += package.rpm_changelogs.capacity() * size_of::<Changelog>();
+    for changelog in &package.rpm_changelogs {
+        footprint.changelogs += changelog.author.capacity() + changelog.description.capacity();
+    }
+}
+
+fn requirements_size(requirements: &Vec<Requirement>) -> usize {
+    let optional = |value: &Option<String>| value.as_ref().map_or(0, String::capacity);
+    requirements.capacity() * size_of::<Requirement>()
+        + requirements
+            .iter()
+            .map(|requirement| {
+                requirement.name.capacity()
+                    + optional(&requirement.flags)
+                    + optional(&requirement.epoch)
+                    + optional(&requirement.version)
+                    + optional(&requirement.release)
+            })
+            .sum::<usize>()
+}
+
+fn advisory_size(advisory: &UpdateRecord) -> usize {
+    let optional = |value: &Option<String>| value.as_ref().map_or(0, String::capacity);
+    let mut size = advisory.from.capacity()
+        + advisory.update_type.capacity()
+        + advisory.status.capacity()
+        + advisory.version.capacity()
+        + advisory.id.capacity()
+        + advisory.title.capacity()
+        + optional(&advisory.issued_date)
+        + optional(&advisory.updated_date)
+        + advisory.rights.capacity()
+        + advisory.release.capacity()
+        + optional(&advisory.pushcount)
+        + advisory.severity.capacity()
+        + advisory.summary.capacity()
+        + advisory.description.capacity()
+        + advisory.solution.capacity();
+    size += advisory.references.capacity() * size_of::<UpdateReference>();
+    for reference in &advisory.references {
+        size += reference.href.capacity()
+            + reference.id.capacity()
+            + reference.title.capacity()
+            + reference.reftype.capacity();
+    }
+    size += advisory.pkglist.capacity() * size_of::<UpdateCollection>();
+    for collection in &advisory.pkglist {
+        size += collection.name.capacity() + optional(&collection.shortname);
+        size += collection.packages.capacity() * size_of::<UpdateCollectionPackage>();
+        for package in &collection.packages {
+            size += package.epoch.capacity()
+                + package.filename.capacity()
+                + package.name.capacity()
+                + package.release.capacity()
+                + package.src.capacity()
+                + package.arch.capacity()
+                + package.version.capacity();
+        }
+        if let Some(module) = &collection.module {
+            size += module.name.capacity()
+                + module.stream.capacity()
+                + module.context.capacity()
+                + module.arch.capacity();
+        }
+    }
+    size
+}
+
+fn pattern_size(pattern: &Pattern) -> usize {
+    let optional = |value: &Option<String>| value.as_ref().map_or(0, String::capacity);
+    let mut size = pattern.name.capacity()
+        + pattern.evr.epoch.capacity()
+        + pattern.evr.version.capacity()
+        + pattern.evr.release.capacity()
+        + pattern.arch.capacity()
+        + optional(&pattern.icon)
+        + optional(&pattern.order);
+    for texts in [&pattern.summary, &pattern.description, &pattern.category] {
+        size += texts.capacity() * size_of::<LocalizedText>();
+        for text in texts {
+            size += optional(&text.lang) + text.text.capacity();
+        }
+    }
+    for requirements in [
+        &pattern.rpm_provides,
+        &pattern.rpm_requires,
+        &pattern.rpm_conflicts,
+        &pattern.rpm_obsoletes,
+        &pattern.rpm_suggests,
+        &pattern.rpm_enhances,
+        &pattern.rpm_recommends,
+        &pattern.rpm_supplements,
+    ] {
+        size += requirements_size(requirements);
+    }
+    size
+}
+
+fn unknown_xml_size(xml: &UnknownXml) -> usize {
+    xml.attributes.capacity() * size_of::<(String, String)>()
+        + xml
+            .attributes
+            .iter()
+            .map(|(name, value)| name.capacity() + value.capacity())
+            .sum::<usize>()
+        + xml.elements.capacity() * size_of::<String>()
+        + xml.elements.iter().map(String::capacity).sum::<usize>()
+}
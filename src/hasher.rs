@@ -0,0 +1,79 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::{Arc, RwLock};
+
+use crate::ChecksumType;
+
+/// Computes a checksum of the data passed to it, a piece at a time.
+pub trait Hasher: Send {
+    fn update(&mut self, data: &[u8]);
+
+    /// The digest of all of the data, as raw bytes rather than hex.
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+/// Creates the [`Hasher`]s every checksum is computed with, see [`set_hash_provider()`].
+///
+/// Implement this to plug in a different implementation, e.g. a hardware-accelerated or FIPS-certified
+/// one, or one which hands the work off to another device.
+pub trait HashProvider: Send + Sync {
+    /// A hasher for `checksum_type`. Types the provider returns `None` for are hashed by the
+    /// [`DefaultHashProvider`].
+    fn hasher(&self, checksum_type: ChecksumType) -> Option<Box<dyn Hasher>>;
+}
+
+/// The built-in implementations of MD5, SHA-1 and SHA-2, from the RustCrypto crates.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DefaultHashProvider;
+
+impl HashProvider for DefaultHashProvider {
+    fn hasher(&self, checksum_type: ChecksumType) -> Option<Box<dyn Hasher>> {
+        match checksum_type {
+            ChecksumType::Md5 => Some(Box::<DigestHasher<md5::Md5>>::default()),
+            ChecksumType::Sha1 => Some(Box::<DigestHasher<sha1::Sha1>>::default()),
+            ChecksumType::Sha224 => Some(Box::<DigestHasher<sha2::Sha224>>::default()),
+            ChecksumType::Sha256 => Some(Box::<DigestHasher<sha2::Sha256>>::default()),
+            ChecksumType::Sha384 => Some(Box::<DigestHasher<sha2::Sha384>>::default()),
+            ChecksumType::Sha512 => Some(Box::<DigestHasher<sha2::Sha512>>::default()),
+            ChecksumType::Unknown => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct DigestHasher<D>(D);
+
+impl<D: digest::Digest + Send> Hasher for DigestHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        digest::Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+static HASH_PROVIDER: RwLock<Option<Arc<dyn HashProvider>>> = RwLock::new(None);
+
+/// Compute every checksum with hashers from `provider` from now on, in the whole process: those of
+/// packages and metadata files, those verified while downloading and those inside zchunk files.
+pub fn set_hash_provider<P: HashProvider + 'static>(provider: P) {
+    *HASH_PROVIDER.write().unwrap() = Some(Arc::new(provider));
+}
+
+/// Go back to computing checksums with the [`DefaultHashProvider`].
+pub fn reset_hash_provider() {
+    *HASH_PROVIDER.write().unwrap() = None;
+}
+
+/// A hasher for `checksum_type` from the current provider, or `None` for [`ChecksumType::Unknown`].
+pub(crate) fn new_hasher(checksum_type: ChecksumType) -> Option<Box<dyn Hasher>> {
+    let provider = HASH_PROVIDER.read().unwrap().clone();
+    provider
+        .and_then(|provider| provider.hasher(checksum_type))
+        .or_else(|| DefaultHashProvider.hasher(checksum_type))
+}
@@ -0,0 +1,47 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use crate::{MetadataError, Package, RepomdData, RepomdRecord};
+
+/// Called at the key points of generating a repository, e.g. to write an audit log, update a database
+/// or purge a CDN as part of the same pipeline. See [`RepositoryWriter::with_hooks()`].
+///
+/// Every method does nothing by default, so only the ones of interest need to be implemented. An error
+/// returned by a hook stops the generation of the repository and is returned to the caller.
+///
+/// [`RepositoryWriter::with_hooks()`]: crate::RepositoryWriter::with_hooks
+pub trait RepositoryHooks: Send + Sync {
+    /// An RPM at `path` was read into `package`, see `utils::load_rpm_directory_with_hooks()`
+    /// (with the `read_rpm` feature).
+    fn package_scanned(&self, _path: &Path, _package: &Package) -> Result<(), MetadataError> {
+        Ok(())
+    }
+
+    /// `package` was written to the package metadata.
+    fn package_written(&self, _package: &Package) -> Result<(), MetadataError> {
+        Ok(())
+    }
+
+    /// A metadata file was finished, and is now at `path` under its final name.
+    fn metadata_file_finished(
+        &self,
+        _path: &Path,
+        _record: &RepomdRecord,
+    ) -> Result<(), MetadataError> {
+        Ok(())
+    }
+
+    /// `repomd.xml` of the repository at `path` was written, so the new metadata is live.
+    fn repository_published(
+        &self,
+        _path: &Path,
+        _repomd: &RepomdData,
+    ) -> Result<(), MetadataError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::path::Path;
+
+use crate::utils::split_nevra;
+use crate::{MetadataError, Package, EVR};
+
+/// The packages installed on a system, as listed by `rpm -qa`, for checking which updates and advisories
+/// apply to it with e.g. [`UpdateRecord::applies_to()`](crate::UpdateRecord::applies_to) and
+/// [`Repository::security_updates()`](crate::Repository::security_updates).
+///
+/// The packages only have a name, EVR and arch. An `&InstalledSet` can be passed wherever installed
+/// packages are expected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstalledSet {
+    packages: Vec<Package>,
+}
+
+impl InstalledSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse an inventory of installed packages with one package per line, either as a NEVRA such as
+    /// `bash-0:5.2.15-3.fc38.x86_64` (the epoch is optional, as in the output of `rpm -qa`), or as the
+    /// whitespace-separated fields of `rpm -qa --qf '%{NAME} %{EPOCH} %{VERSION} %{RELEASE} %{ARCH}\n'`,
+    /// with or without the epoch. An epoch of `(none)` is taken to be 0.
+    ///
+    /// Empty lines and lines starting with `#` are skipped, as are the `gpg-pubkey` pseudo-packages rpm
+    /// keeps the imported keys in.
+    pub fn parse(inventory: &str) -> Result<Self, MetadataError> {
+        let mut installed = InstalledSet::new();
+        for line in inventory.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with("gpg-pubkey") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match fields[..] {
+                [nevra] => split_nevra(nevra),
+                [name, version, release, arch] => Some((name, "", version, release, arch)),
+                [name, epoch, version, release, arch] => {
+                    Some((name, epoch, version, release, arch))
+                }
+                _ => None,
+            };
+            let invalid = || MetadataError::InvalidFieldError("installed package", line.to_owned());
+            let (name, epoch, version, release, arch) = parsed.ok_or_else(invalid)?;
+            if name.is_empty() || arch.is_empty() {
+                return Err(invalid());
+            }
+            let epoch = match epoch {
+                "" | "(none)" => "0",
+                epoch => epoch,
+            };
+            let evr = EVR::new(epoch, version, release);
+            evr.validate()?;
+            installed.insert(Package {
+                name: name.to_owned(),
+                arch: arch.to_owned(),
+                evr,
+                ..Package::default()
+            });
+        }
+        Ok(installed)
+    }
+
+    /// Parse the inventory in the file at `path`, see [`InstalledSet::parse()`].
+    pub fn from_file(path: &Path) -> Result<Self, MetadataError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn insert(&mut self, package: Package) -> &mut Self {
+        self.packages.push(package);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Package> {
+        self.packages.iter()
+    }
+
+    /// The installed packages named `name`, of which there can be several, e.g. for kernels or multilib
+    /// packages.
+    pub fn get<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Package> {
+        self.packages
+            .iter()
+            .filter(move |package| package.name() == name)
+    }
+}
+
+impl<'a> IntoIterator for &'a InstalledSet {
+    type Item = &'a Package;
+    type IntoIter = std::slice::Iter<'a, Package>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.packages.iter()
+    }
+}
@@ -4,27 +4,142 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod audit;
+mod binary;
+mod cache;
+pub mod capability;
+mod changelogs;
+mod checkpoint;
 mod common;
+mod compare;
+mod comps;
+mod container;
+mod delta;
+mod depgraph;
+mod drafts;
+mod expire;
+mod extract;
 mod filelist;
+mod footprint;
+mod hasher;
+mod hooks;
+mod installed;
+mod logging;
+mod manifest;
 mod metadata;
+mod modules;
 mod other;
 mod package;
+mod paths;
 mod primary;
+mod probe;
+mod recompress;
 mod repomd;
+mod reposet;
 mod repository;
+mod runtime;
+mod scheduler;
+mod scrub;
+mod security;
+#[cfg(feature = "read_rpm")]
+mod signatures;
+mod storage;
+mod suse;
+mod transcode;
 mod updateinfo;
 pub mod utils;
+mod validate;
+mod verifier;
+mod zchunk;
+
+#[cfg(feature = "archive")]
+mod bundle;
+#[cfg(feature = "download")]
+mod download;
+#[cfg(feature = "errata")]
+pub mod errata;
+#[cfg(feature = "sbom")]
+pub mod sbom;
+#[cfg(feature = "search")]
+pub mod search;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 #[cfg(feature = "python_ext")]
 mod python_ext;
 
-pub use common::EVR;
+pub use audit::{AuditCheck, AuditFinding, AuditPolicy, AuditReport};
+#[cfg(feature = "archive")]
+pub use bundle::{export_bundle, import_bundle, BundleManifest, BundleOptions};
+pub use cache::MetadataCache;
+pub use changelogs::{ChangelogStorage, ChangelogStore, StoredChangelogs};
+pub use checkpoint::ScanCheckpoint;
+pub use common::{rpmvercmp, EVR};
+pub use compare::{CompareOptions, Difference, DifferenceKind, PackageUpdate, RepositoryDiff};
+pub use comps::{
+    Comps, CompsCategory, CompsEnvironment, CompsGroup, CompsPackage, CompsPackageType,
+    GroupResolution, GroupSelection, Langpack,
+};
+#[cfg(all(feature = "archive", feature = "read_rpm"))]
+pub use container::repository_from_layer;
+pub use delta::RepositoryDelta;
+pub use depgraph::{DependencyEdge, DependencyGraph, DependencyGraphOptions, DependencyKind};
+#[cfg(feature = "download")]
+pub use download::{
+    parse_mirrorlist, DefaultTransport, DownloadOptions, Downloader, Metalink, Method,
+    MirrorStatus, MismatchPolicy, PackageFilter, RefreshOutcome, Request, Response, SyncEvent,
+    SyncReport, SyncTask, Transport, UploadReport, Uploader, VerificationFailure,
+};
+#[cfg(feature = "tokio")]
+pub use download::{AsyncResponse, AsyncTransport, ReqwestTransport};
+pub use expire::{CacheStatus, MetadataExpire};
+pub use extract::extract_repository;
+pub use footprint::MemoryFootprint;
+pub use hasher::{
+    reset_hash_provider, set_hash_provider, DefaultHashProvider, HashProvider, Hasher,
+};
+pub use hooks::RepositoryHooks;
+pub use installed::InstalledSet;
+pub use manifest::{ManifestItem, ManifestResolution, PackageManifest};
 pub use metadata::{
-    Changelog, Checksum, ChecksumType, CompressionType, FileType, FilelistsXml, MetadataError,
-    OtherXml, Package, PackageFile, PrimaryXml, RepomdData, RepomdRecord, RepomdXml, Requirement,
-    UpdateCollection, UpdateCollectionModule, UpdateCollectionPackage, UpdateRecord,
-    UpdateReference, UpdateinfoXml,
+    AttributeOrder, Changelog, Checksum, ChecksumType, CompressionType, DecompressionError,
+    ElementPolicy, FallbackEncoding, FileType, FilelistsXml, InvalidCharPolicy, LineEnding,
+    LocalizedText, MetadataError, MetadataType, OtherXml, Package, PackageBuilder, PackageFile,
+    ParseError, ParseLocation, ParseMode, ParseOptions, ParseReport, ParseWarning, Pattern,
+    PatternsXml, PrimaryElements, PrimaryXml, Product, ProductsXml, RecordOrder, RepomdData,
+    RepomdRecord, RepomdXml, Requirement, UnknownPackageXml, UnknownXml, UpdateCollection,
+    UpdateCollectionModule, UpdateCollectionPackage, UpdateRecord, UpdateRecordBuilder,
+    UpdateReference, UpdateinfoXml, XmlFormat, XmlStyle,
 };
+pub use modules::{ModuleDefaults, ModuleDocument, ModuleObsoletes, ModuleStream, Modules};
 pub use package::PackageIterator;
-pub use repository::{Repository, RepositoryOptions, RepositoryReader, RepositoryWriter};
+pub use paths::{FileStorage, PathTable, StoredFiles};
+pub use primary::{PackageEvent, PackageField};
+pub use probe::{probe_repository, RepoChange, RepoMonitor, RepositoryProbe};
+pub use recompress::{recompress_repository, RecompressOptions};
+pub use reposet::{RepoSet, DEFAULT_PRIORITY};
+pub use repository::{
+    ChangelogMatch, DuplicateAdvisoryPolicy, LoadOptions, MetadataSelection, PublishReport,
+    Repository, RepositoryOptions, RepositoryReader, RepositoryWriter, UpdateinfoSplit,
+};
+pub use runtime::{current_runtime, reset_runtime, set_runtime, Runtime};
+pub use scheduler::{
+    JobContext, JobId, JobInfo, JobPriority, JobStatus, Scheduler, SchedulerOptions,
+};
+pub use scrub::{scrub_repository, ScrubAction, ScrubOptions};
+pub use security::{AdvisorySeverity, SecurityFeed, SecurityUpdate, SecurityUpdatePackage};
+#[cfg(feature = "read_rpm")]
+pub use signatures::{SignatureReport, SignatureStatus};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteWriter, DATABASE_VERSION};
+#[cfg(feature = "archive")]
+pub use storage::{open_archive, IsoStorage, TarStorage};
+pub use storage::{LocalStorage, Storage};
+pub use transcode::{transcode_metadata_file, MetadataFormat};
 pub use updateinfo::UpdateinfoXmlReader;
+pub use validate::{Severity, ValidationCheck, ValidationIssue, ValidationReport};
+pub use verifier::{
+    verify_files, verify_files_in, FileCheck, FileStatus, VerificationReport, VerifyOptions,
+};
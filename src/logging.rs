@@ -0,0 +1,105 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Internal diagnostics, compiled in only with the `logging` feature.
+//!
+//! Events are emitted through the `log` facade so that embedding services can route them to
+//! whatever backend they already use. Without the feature all of this compiles away to nothing: the
+//! messages aren't formatted and spans aren't timed.
+
+#[cfg(feature = "logging")]
+use std::time::Instant;
+
+/// How many packages are processed between two progress events.
+pub(crate) const PROGRESS_INTERVAL: usize = 10_000;
+
+#[cfg(feature = "logging")]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        log::debug!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+#[cfg(feature = "logging")]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        log::trace!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! trace {
+    ($($arg:tt)+) => {{
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Start a [`Span`] named by the format arguments, e.g. `span!("read {}", path.display())`.
+#[cfg(feature = "logging")]
+macro_rules! span {
+    ($($arg:tt)+) => {
+        $crate::logging::Span::new(format!($($arg)+))
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! span {
+    ($($arg:tt)+) => {{
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+        $crate::logging::Span {}
+    }};
+}
+
+pub(crate) use debug;
+pub(crate) use span;
+pub(crate) use trace;
+
+/// A timed phase of work, see [`span!`]. Logs when it starts and how long it took when dropped.
+pub(crate) struct Span {
+    #[cfg(feature = "logging")]
+    name: String,
+    #[cfg(feature = "logging")]
+    start: Instant,
+}
+
+#[cfg(feature = "logging")]
+impl Span {
+    pub(crate) fn new(name: String) -> Self {
+        let span = Span {
+            name,
+            start: Instant::now(),
+        };
+        trace!("{}: started", span.name);
+        span
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        #[cfg(feature = "logging")]
+        debug!("{}: finished in {:?}", self.name, self.start.elapsed());
+    }
+}
+
+/// Emit a progress event every [`PROGRESS_INTERVAL`] items.
+pub(crate) fn progress(what: &str, done: usize, total: usize) {
+    if done > 0 && done.is_multiple_of(PROGRESS_INTERVAL) {
+        debug!("{}: {} of {} packages", what, done, total);
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::utils::glob_match;
+use crate::{Comps, GroupSelection, MetadataError, Package, Repository};
+
+/// An entry of a [`PackageManifest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestItem {
+    /// A package name or a glob pattern (`*`, `?`, `[...]`) of them, optionally with the arch, the version
+    /// or the whole NEVRA, e.g. `vim-*`, `glibc.i686` or `bash-5.2.15-3.fc38.x86_64`
+    Package(String),
+    /// `@id`, a group of the comps, along with its optional packages with `@id --optional`
+    Group { id: String, optional: bool },
+    /// `@^id`, an environment of the comps
+    Environment(String),
+}
+
+/// A list of the packages to install, such as the `%packages` section of a kickstart file or the
+/// manifest of an image build, to be resolved against a repository with
+/// [`Repository::resolve_manifest()`].
+///
+/// Manifests have one entry per line: a package, a glob pattern of packages, `@group` or
+/// `@^environment`. Entries starting with `-` are excluded instead, and `#` starts a comment.
+///
+/// - `include` - The packages, groups and environments to install.
+/// - `exclude` - The packages, groups and environments not to install, even if something included
+///   them.
+/// - `include_core` - Whether the `core` group is installed as well, if the comps has one. Kickstart
+///   files install it unless `%packages` has `--nocore`.
+/// - `ignore_missing` - Whether entries which the repository doesn't have are skipped rather than an
+///   error, like `%packages --ignoremissing`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageManifest {
+    pub include: Vec<ManifestItem>,
+    pub exclude: Vec<ManifestItem>,
+    pub include_core: bool,
+    pub ignore_missing: bool,
+}
+
+impl PackageManifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the `%packages` sections of a kickstart file. Everything else in it is ignored.
+    pub fn from_kickstart(kickstart: &str) -> Result<Self, MetadataError> {
+        let mut manifest = PackageManifest {
+            include_core: true,
+            ..PackageManifest::default()
+        };
+        let mut found = false;
+        let mut in_packages = false;
+        for line in kickstart.lines().map(str::trim) {
+            if let Some(section) = line.strip_prefix('%') {
+                let mut words = section.split_whitespace();
+                in_packages = words.next() == Some("packages");
+                if in_packages {
+                    found = true;
+                    for option in words {
+                        match option {
+                            "--nocore" => manifest.include_core = false,
+                            "--ignoremissing" => manifest.ignore_missing = true,
+                            // options about how the packages are installed, rather than which
+                            _ => (),
+                        }
+                    }
+                }
+            } else if in_packages {
+                manifest.add_line(line)?;
+            }
+        }
+        if !found {
+            return Err(MetadataError::InconsistentMetadataError(
+                "the kickstart has no %packages section".to_owned(),
+            ));
+        }
+        Ok(manifest)
+    }
+
+    pub fn include(mut self, item: ManifestItem) -> Self {
+        self.include.push(item);
+        self
+    }
+
+    pub fn exclude(mut self, item: ManifestItem) -> Self {
+        self.exclude.push(item);
+        self
+    }
+
+    pub fn include_core(self, val: bool) -> Self {
+        Self {
+            include_core: val,
+            ..self
+        }
+    }
+
+    pub fn ignore_missing(self, val: bool) -> Self {
+        Self {
+            ignore_missing: val,
+            ..self
+        }
+    }
+
+    fn add_line(&mut self, line: &str) -> Result<(), MetadataError> {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        let (excluded, entry) = match entry.strip_prefix('-') {
+            Some(entry) => (true, entry),
+            None => (false, entry),
+        };
+        let mut words = entry.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(());
+        };
+        let mut optional = false;
+        for option in words {
+            match option {
+                "--optional" if name.starts_with('@') => optional = true,
+                _ => {
+                    return Err(MetadataError::InvalidFieldError(
+                        "manifest entry",
+                        line.to_owned(),
+                    ))
+                }
+            }
+        }
+        let item = match name.strip_prefix('@') {
+            Some(id) => match id.strip_prefix('^') {
+                Some(id) => ManifestItem::Environment(id.to_owned()),
+                None => ManifestItem::Group {
+                    id: id.to_owned(),
+                    optional,
+                },
+            },
+            None => ManifestItem::Package(name.to_owned()),
+        };
+        match excluded {
+            true => self.exclude.push(item),
+            false => self.include.push(item),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PackageManifest {
+    type Err = MetadataError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut manifest = PackageManifest::default();
+        for line in contents.lines() {
+            manifest.add_line(line)?;
+        }
+        Ok(manifest)
+    }
+}
+
+/// The packages a [`PackageManifest`] selects, found by [`Repository::resolve_manifest()`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManifestResolution {
+    /// The NEVRAs of the packages, in the order of the repository, e.g. for
+    /// [`Repository::extract()`]
+    pub nevras: Vec<String>,
+    /// The entries which matched nothing (as they're written, e.g. `@core` or `vim-*`), and the
+    /// packages of the groups which the repository doesn't have
+    pub missing: Vec<String>,
+}
+
+impl Repository {
+    /// Resolve `manifest` into the packages it selects: the newest version (for each arch) of the packages
+    /// matching each entry, and the packages of the groups and environments as
+    /// [`Repository::resolve_groups()`] finds them in `comps`, less those which are excluded. If `arch` is
+    /// set, only packages of that arch and `noarch` are selected. Source packages never are.
+    ///
+    /// Entries which match nothing are an error, unless the manifest ignores them, in which case they're
+    /// listed in [`ManifestResolution::missing`]. The dependencies of the packages aren't added, see
+    /// [`Repository::dependency_closure()`] for that.
+    pub fn resolve_manifest(
+        &self,
+        manifest: &PackageManifest,
+        comps: Option<&Comps>,
+        arch: Option<&str>,
+    ) -> Result<ManifestResolution, MetadataError> {
+        let candidates: Vec<(&str, &Package)> = self
+            .packages()
+            .iter()
+            .filter(|(_, package)| package.arch() != "src")
+            .filter(|(_, package)| match arch {
+                Some(arch) => package.arch() == arch || package.arch() == "noarch",
+                None => true,
+            })
+            .map(|(pkgid, package)| (pkgid.as_str(), package))
+            .collect();
+        let mut missing = Vec::new();
+
+        let mut include = manifest.include.clone();
+        if manifest.include_core && comps.is_some_and(|comps| comps.group("core").is_some()) {
+            include.push(ManifestItem::Group {
+                id: "core".to_owned(),
+                optional: false,
+            });
+        }
+        let mut selected: HashSet<&str> = HashSet::new();
+        for (pattern, matches) in select(self, &candidates, &include, comps, arch, &mut missing) {
+            if matches.is_empty() {
+                missing.push(pattern);
+                continue;
+            }
+            // only the newest version of each package the pattern matches
+            let mut newest: HashMap<(&str, &str), (&str, &Package)> = HashMap::new();
+            for (pkgid, package) in matches {
+                let current = newest
+                    .entry((package.name(), package.arch()))
+                    .or_insert((pkgid, package));
+                if package.evr() > current.1.evr() {
+                    *current = (pkgid, package);
+                }
+            }
+            selected.extend(newest.into_values().map(|(pkgid, _)| pkgid));
+        }
+        let mut ignored = Vec::new();
+        for (_, matches) in select(
+            self,
+            &candidates,
+            &manifest.exclude,
+            comps,
+            arch,
+            &mut ignored,
+        ) {
+            for (pkgid, _) in matches {
+                selected.remove(pkgid);
+            }
+        }
+
+        if !missing.is_empty() && !manifest.ignore_missing {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "the repository doesn't have {}",
+                missing.join(", ")
+            )));
+        }
+        let nevras = candidates
+            .iter()
+            .filter(|(pkgid, _)| selected.contains(pkgid))
+            .map(|(_, package)| package.nevra())
+            .collect();
+        Ok(ManifestResolution { nevras, missing })
+    }
+}
+
+/// The packages of `candidates` matching each entry of `items`, along with the entry as it's written.
+/// The packages of the groups which the repository doesn't have and the groups which don't exist are
+/// added to `missing`.
+fn select<'a>(
+    repository: &Repository,
+    candidates: &[(&'a str, &'a Package)],
+    items: &[ManifestItem],
+    comps: Option<&Comps>,
+    arch: Option<&str>,
+    missing: &mut Vec<String>,
+) -> Vec<(String, Vec<(&'a str, &'a Package)>)> {
+    let mut selected = Vec::new();
+    for item in items {
+        let (pattern, id, selection) = match item {
+            ManifestItem::Package(pattern) => {
+                let matches = candidates
+                    .iter()
+                    .filter(|(_, package)| matches_package(pattern, package))
+                    .copied()
+                    .collect();
+                selected.push((pattern.clone(), matches));
+                continue;
+            }
+            ManifestItem::Group { id, optional } => (
+                format!("@{}", id),
+                id,
+                GroupSelection::default().group(id).with_optional(*optional),
+            ),
+            ManifestItem::Environment(id) => (
+                format!("@^{}", id),
+                id,
+                GroupSelection::default().environment(id),
+            ),
+        };
+        let resolution = match (comps, arch) {
+            (Some(comps), Some(arch)) => repository.resolve_groups(comps, &selection.arch(arch)),
+            (Some(comps), None) => repository.resolve_groups(comps, &selection),
+            (None, _) => {
+                selected.push((pattern, Vec::new()));
+                continue;
+            }
+        };
+        if resolution.unknown.contains(id) {
+            selected.push((pattern, Vec::new()));
+            continue;
+        }
+        missing.extend(resolution.unknown.iter().map(|id| format!("@{}", id)));
+        missing.extend(resolution.missing_packages.iter().cloned());
+        let names: BTreeSet<&str> = resolution.packages.iter().map(String::as_str).collect();
+        let matches = candidates
+            .iter()
+            .filter(|(_, package)| names.contains(package.name()))
+            .copied()
+            .collect();
+        selected.push((pattern, matches));
+    }
+    selected
+}
+
+/// Whether `pattern` matches the name of `package`, or its name along with its arch, its version or its
+/// whole NEVRA.
+fn matches_package(pattern: &str, package: &Package) -> bool {
+    let (name, evr, arch) = (package.name(), package.evr(), package.arch());
+    [
+        name.to_owned(),
+        format!("{}.{}", name, arch),
+        format!("{}-{}", name, evr.version),
+        format!("{}-{}-{}", name, evr.version, evr.release),
+        package.nvra(),
+        package.nevra(),
+    ]
+    .iter()
+    .any(|text| glob_match(pattern, text))
+}
@@ -5,26 +5,29 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::convert::TryInto;
-use std::io::{BufRead, Write};
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io::{BufRead, Read, Write};
 use std::os::unix::prelude::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 // use bitflags;
-use niffler;
-use quick_xml;
+use quick_xml::events::BytesStart;
 use quick_xml::{Reader, Writer};
-#[cfg(feature = "read_rpm")]
-use rpm;
 use thiserror::Error;
 
-use crate::{utils, Repository, EVR};
+use crate::storage::LocalStorage;
+use crate::zchunk::ZchunkHeader;
+use crate::{capability, logging, utils, Repository, StoredChangelogs, StoredFiles, EVR};
 
 pub struct RepomdXml;
 pub struct PrimaryXml;
 pub struct FilelistsXml;
 pub struct OtherXml;
 pub struct UpdateinfoXml;
+pub struct ProductsXml;
+pub struct PatternsXml;
 
 pub const METADATA_PRIMARY: &str = "primary";
 pub const METADATA_FILELISTS: &str = "filelists";
@@ -37,23 +40,32 @@ pub const METADATA_UPDATEINFO: &str = "updateinfo";
 // TODO: probably this can / should be broken up better rather than being a kitchen sink
 #[derive(Error, Debug)]
 pub enum MetadataError {
+    /// An error of the `rpm` crate, as a string because some of them hold errors which can't be sent
+    /// between threads
     #[cfg(feature = "read_rpm")]
+    #[error("{0}")]
+    RpmReadError(String),
     #[error(transparent)]
-    RpmReadError(#[from] rpm::Error),
+    XmlParseError(quick_xml::Error),
+    #[cfg(any(feature = "errata", feature = "sbom"))]
     #[error(transparent)]
-    XmlParseError(#[from] quick_xml::Error),
+    JsonParseError(#[from] serde_json::Error),
     #[error(transparent)]
     Utf8Error(#[from] std::str::Utf8Error),
     #[error(transparent)]
-    IoError(#[from] std::io::Error),
+    IoError(std::io::Error),
     #[error(transparent)]
     IntFieldParseError(#[from] std::num::ParseIntError),
     #[error(transparent)]
     UnsupportedCompressionTypeError(#[from] niffler::Error),
+    #[error("Failed to decompress metadata: {0}")]
+    DecompressionError(#[from] DecompressionError),
     #[error("Checksum type {0} is not supported")]
     UnsupportedChecksumTypeError(String),
     #[error("\"{0}\" is not a valid checksum of type \"{1:?}\"")]
     InvalidChecksumError(String, ChecksumType),
+    #[error("\"{1}\" is not a valid {0}")]
+    InvalidFieldError(&'static str, String),
     #[error("\"{0}\" is not a valid flag value")]
     InvalidFlagsError(String),
     #[error("\"{0}\" is not a valid EVR string: {1}")]
@@ -62,12 +74,551 @@ pub enum MetadataError {
     InconsistentMetadataError(String),
     #[error("Missing metadata field: {0}")]
     MissingFieldError(&'static str),
+    #[error("Missing metadata fields: {}", .0.join(", "))]
+    MissingFieldsError(Vec<&'static str>),
     #[error("Missing metadata attribute: {0}")]
     MissingAttributeError(&'static str),
     #[error("Unknown metadata attribute: {0}")]
     UnknownAttributeError(String),
     #[error("Missing metadata header")]
     MissingHeaderError,
+    #[error("Failed to parse {location}: {source}")]
+    MetadataParseError {
+        location: Box<ParseLocation>,
+        source: Box<MetadataError>,
+    },
+    #[cfg(feature = "download")]
+    #[error("Failed to download {0}: {1}")]
+    DownloadError(String, String),
+    #[cfg(feature = "download")]
+    #[error("Failed to download {0}: HTTP status {1}")]
+    HttpStatusError(String, u16),
+    #[error("Checksum mismatch for {0}: expected {1}, found {2}")]
+    ChecksumMismatchError(String, String, String),
+    #[error("Failed to parse YAML at line {0}: {1}")]
+    YamlParseError(usize, String),
+    #[cfg(feature = "read_rpm")]
+    #[error("Failed to read the payload of {0}: {1}")]
+    PayloadError(String, String),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+}
+
+/// Why a compressed metadata file couldn't be decompressed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DecompressionError {
+    /// The file ended in the middle of a compressed stream, e.g. because the download was cut short
+    #[error("the compressed file is truncated")]
+    Truncated,
+    /// The compressed data is damaged
+    #[error("the compressed file is corrupt: {0}")]
+    Corrupt(String),
+    /// The compressed stream is followed by data which isn't part of it, see
+    /// [`ParseOptions::ignore_trailing_data`]
+    #[error("the compressed stream is followed by trailing data")]
+    TrailingData,
+}
+
+impl From<DecompressionError> for std::io::Error {
+    fn from(error: DecompressionError) -> Self {
+        let kind = match error {
+            DecompressionError::Truncated => std::io::ErrorKind::UnexpectedEof,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+// Decompression errors (and the errors of files which don't match their checksums) have to pass through
+// `Read` (and the XML reader) as I/O errors, so they're unwrapped again here.
+#[cfg(feature = "read_rpm")]
+impl From<rpm::Error> for MetadataError {
+    fn from(error: rpm::Error) -> Self {
+        MetadataError::RpmReadError(error.to_string())
+    }
+}
+
+impl From<std::io::Error> for MetadataError {
+    fn from(error: std::io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<DecompressionError>())
+        {
+            let inner = error.into_inner().unwrap();
+            return MetadataError::DecompressionError(*inner.downcast().unwrap());
+        }
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<MetadataError>())
+        {
+            return *error.into_inner().unwrap().downcast().unwrap();
+        }
+        MetadataError::IoError(error)
+    }
+}
+
+impl From<quick_xml::Error> for MetadataError {
+    fn from(error: quick_xml::Error) -> Self {
+        match error {
+            quick_xml::Error::Io(e)
+                if e.get_ref().is_some_and(|inner| {
+                    inner.is::<DecompressionError>() || inner.is::<MetadataError>()
+                }) =>
+            {
+                e.into()
+            }
+            e => MetadataError::XmlParseError(e),
+        }
+    }
+}
+
+impl MetadataError {
+    /// Where in a metadata file the error occurred, if it's known.
+    pub fn location(&self) -> Option<&ParseLocation> {
+        match self {
+            Self::MetadataParseError { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without the [`ParseLocation`].
+    pub fn root_cause(&self) -> &MetadataError {
+        match self {
+            Self::MetadataParseError { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
+
+    /// Fill in the line number of the location by counting the lines up to its offset in the metadata
+    /// file, which is opened again with `open`.
+    pub(crate) fn with_line_from<R: Read>(
+        mut self,
+        open: impl FnOnce() -> Result<R, MetadataError>,
+    ) -> Self {
+        if let Self::MetadataParseError { location, .. } = &mut self {
+            if location.line.is_none() {
+                location.line = open()
+                    .and_then(|data| Ok(utils::line_at_offset(data, location.offset)?))
+                    .ok();
+            }
+        }
+        self
+    }
+}
+
+/// Where in a metadata file a [`MetadataError::MetadataParseError`] occurred.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseLocation {
+    /// The type of metadata, e.g. `primary`
+    pub metadata: &'static str,
+    /// Byte offset in the (decompressed) metadata file
+    pub offset: usize,
+    /// Line number in the metadata file, if it could be read again to count the lines
+    pub line: Option<usize>,
+    /// The enclosing elements, e.g. `metadata/package/format/rpm:requires`
+    pub path: String,
+    /// The NEVRA of the package (or the ID of the advisory) being parsed, if it's known
+    pub entry: Option<String>,
+}
+
+impl fmt::Display for ParseLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.xml", self.metadata)?;
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+        write!(f, " (byte {}) in <{}>", self.offset, self.path)?;
+        if let Some(entry) = &self.entry {
+            write!(f, " of {}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// How to deal with metadata which doesn't follow the spec.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first deviation from the spec
+    #[default]
+    Strict,
+    /// Substitute defaults for missing or invalid values and carry on, recording a [`ParseWarning`] for each
+    Lenient,
+    /// Skip entries (packages, advisories) which can't be parsed or don't follow the spec and carry on,
+    /// recording a [`ParseError`] for each
+    Recover,
+}
+
+/// How metadata files are parsed.
+///
+/// - `mode` - How to deal with metadata which doesn't follow the spec.
+/// - `preserve_unknown` - Keep the elements and attributes of packages and `repomd.xml` records which
+///   aren't understood (e.g. ones added by other tools), so that they are written out again.
+/// - `invalid_chars` - What to do with characters which aren't allowed in XML, see [`InvalidCharPolicy`].
+/// - `fallback_encoding` - The encoding of text which isn't valid UTF-8, see [`FallbackEncoding`]. If
+///   it's not set, such text is dealt with according to `invalid_chars`.
+/// - `ignore_trailing_data` - Ignore anything following the last gzip stream of a file (such as the
+///   zero padding added by some mirrors) rather than failing with [`DecompressionError::TrailingData`].
+/// - `trusted_input` - The metadata is known to be valid UTF-8 without any characters which aren't allowed
+///   in XML, e.g. because it was just written by this crate, so it's parsed as it is rather than being
+///   checked and copied first, which makes reading it noticeably faster. `invalid_chars` and
+///   `fallback_encoding` are ignored. Text which isn't valid UTF-8 still fails to parse, but characters
+///   which aren't allowed in XML are read as they are.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+    pub preserve_unknown: bool,
+    pub invalid_chars: InvalidCharPolicy,
+    pub fallback_encoding: Option<FallbackEncoding>,
+    pub ignore_trailing_data: bool,
+    pub trusted_input: bool,
+}
+
+impl ParseOptions {
+    pub fn mode(self, mode: ParseMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    pub fn preserve_unknown(self, val: bool) -> Self {
+        Self {
+            preserve_unknown: val,
+            ..self
+        }
+    }
+
+    pub fn invalid_chars(self, policy: InvalidCharPolicy) -> Self {
+        Self {
+            invalid_chars: policy,
+            ..self
+        }
+    }
+
+    pub fn fallback_encoding(self, encoding: Option<FallbackEncoding>) -> Self {
+        Self {
+            fallback_encoding: encoding,
+            ..self
+        }
+    }
+
+    pub fn ignore_trailing_data(self, val: bool) -> Self {
+        Self {
+            ignore_trailing_data: val,
+            ..self
+        }
+    }
+
+    pub fn trusted_input(self, val: bool) -> Self {
+        Self {
+            trusted_input: val,
+            ..self
+        }
+    }
+}
+
+/// A deviation from the spec which was tolerated because of [`ParseMode::Lenient`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// The type of metadata, e.g. `primary`
+    pub metadata: &'static str,
+    /// The package (or advisory) the problem was found in, if it's known
+    pub entry: Option<String>,
+    pub message: String,
+}
+
+/// An entry which was skipped because of [`ParseMode::Recover`].
+#[derive(Debug)]
+pub struct ParseError {
+    /// The type of metadata, e.g. `primary`
+    pub metadata: &'static str,
+    /// The package (or advisory) which was skipped, if its name was read before the error
+    pub entry: Option<String>,
+    /// Byte offset of the start of the entry in the (decompressed) metadata file
+    pub offset: usize,
+    pub error: MetadataError,
+}
+
+/// The problems found while reading metadata with [`ParseMode::Lenient`] or [`ParseMode::Recover`].
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub warnings: Vec<ParseWarning>,
+    pub errors: Vec<ParseError>,
+}
+
+impl ParseReport {
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// Tracks the parse mode of a metadata reader, and the problems recorded in lenient and recover mode.
+#[derive(Debug)]
+pub(crate) struct ParseContext {
+    pub(crate) mode: ParseMode,
+    metadata: &'static str,
+    /// The package (or advisory) currently being parsed
+    pub(crate) entry: Option<String>,
+    /// Byte offset of the entry currently being parsed, once its start tag has been read
+    pub(crate) offset: Option<usize>,
+    /// The root element of the file
+    root: &'static str,
+    /// The elements enclosing the current position, below the root element
+    sections: Vec<String>,
+    /// Whether XML which isn't understood is kept
+    pub(crate) preserve_unknown: bool,
+    /// The namespace prefixes declared on the root element
+    namespaces: Vec<(String, String)>,
+    warnings: Vec<ParseWarning>,
+    errors: Vec<ParseError>,
+}
+
+impl ParseContext {
+    pub(crate) fn new(metadata: &'static str, root: &'static str) -> Self {
+        ParseContext {
+            mode: ParseMode::Strict,
+            metadata,
+            entry: None,
+            offset: None,
+            root,
+            sections: Vec::new(),
+            preserve_unknown: false,
+            namespaces: Vec::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Note the namespaces declared on the root element `tag`.
+    pub(crate) fn start_root<R: BufRead>(
+        &mut self,
+        reader: &Reader<R>,
+        tag: &BytesStart,
+    ) -> Result<(), MetadataError> {
+        self.namespaces = utils::namespace_declarations(reader, tag)?;
+        Ok(())
+    }
+
+    /// The attributes of `tag` other than the `known` ones, if XML which isn't understood is kept.
+    pub(crate) fn unknown_attributes<R: BufRead>(
+        &self,
+        reader: &Reader<R>,
+        tag: &BytesStart,
+        known: &[&str],
+    ) -> Result<Vec<(String, String)>, MetadataError> {
+        let mut attributes = Vec::new();
+        if !self.preserve_unknown {
+            return Ok(attributes);
+        }
+        for attr in tag.attributes() {
+            let attr = attr.map_err(quick_xml::Error::from)?;
+            let key = std::str::from_utf8(attr.key)?;
+            if !known.contains(&key) {
+                attributes.push((key.to_owned(), attr.unescape_and_decode_value(reader)?));
+            }
+        }
+        // the namespaces of the attributes are declared on the root element, which isn't preserved
+        for (prefix, uri) in &self.namespaces {
+            let declaration = format!("xmlns:{}", prefix);
+            let used = attributes
+                .iter()
+                .any(|(key, _)| key.split_once(':').map(|(p, _)| p) == Some(prefix.as_str()));
+            if used && !attributes.iter().any(|(key, _)| *key == declaration) {
+                attributes.push((declaration, uri.clone()));
+            }
+        }
+        Ok(attributes)
+    }
+
+    /// Read the element `tag`, which isn't understood, if XML which isn't understood is kept. Otherwise
+    /// nothing is read.
+    pub(crate) fn unknown_element<R: BufRead>(
+        &self,
+        reader: &mut Reader<R>,
+        tag: &BytesStart,
+    ) -> Result<Option<String>, MetadataError> {
+        if !self.preserve_unknown {
+            return Ok(None);
+        }
+        utils::read_element_xml(reader, tag, &self.namespaces).map(Some)
+    }
+
+    /// Prepare for reading the next entry.
+    pub(crate) fn next_entry(&mut self) {
+        self.offset = None;
+        self.sections.clear();
+    }
+
+    /// Note the start of an entry, whose start tag `tag` was just read.
+    pub(crate) fn start_entry<R: BufRead>(&mut self, reader: &Reader<R>, tag: &BytesStart) {
+        self.entry = None;
+        // the position is just past the '>'
+        self.offset = Some(reader.buffer_position().saturating_sub(tag.len() + 2));
+        self.sections.clear();
+        self.enter(tag.name());
+    }
+
+    /// Note that the element `name`, which contains further elements, was entered.
+    pub(crate) fn enter(&mut self, name: &[u8]) {
+        self.sections
+            .push(String::from_utf8_lossy(name).into_owned());
+    }
+
+    /// Note that the element last entered was left.
+    pub(crate) fn leave(&mut self) {
+        self.sections.pop();
+    }
+
+    /// Wrap `error` with the current position of `reader`. The entry is described by the NEVRA of `package`
+    /// if enough of it is known.
+    pub(crate) fn locate<R: BufRead>(
+        &self,
+        reader: &Reader<R>,
+        error: MetadataError,
+        package: Option<&Package>,
+    ) -> MetadataError {
+        if let MetadataError::MetadataParseError { .. } = error {
+            return error;
+        }
+        let entry = match package {
+            Some(package) if !package.name().is_empty() && !package.version().is_empty() => {
+                Some(package.nevra_short())
+            }
+            _ => self.entry.clone(),
+        };
+        let mut path = self.root.to_owned();
+        for section in &self.sections {
+            path.push('/');
+            path.push_str(section);
+        }
+        MetadataError::MetadataParseError {
+            location: Box::new(ParseLocation {
+                metadata: self.metadata,
+                offset: reader.buffer_position(),
+                line: None,
+                path,
+                entry,
+            }),
+            source: Box::new(error),
+        }
+    }
+
+    /// In recover mode, record `error` and skip the rest of the entry it occurred in, up to `</end_tag>`.
+    /// Otherwise, or if the error occurred outside of an entry or left the XML unreadable, return it.
+    pub(crate) fn recover<R: BufRead>(
+        &mut self,
+        reader: &mut Reader<R>,
+        end_tag: &[u8],
+        error: MetadataError,
+    ) -> Result<(), MetadataError> {
+        let offset = match self.offset.take() {
+            Some(offset) if self.mode == ParseMode::Recover && is_recoverable(&error) => offset,
+            _ => return Err(error),
+        };
+        logging::debug!(
+            "{}: skipping entry at offset {}: {}",
+            self.metadata,
+            offset,
+            error
+        );
+        self.errors.push(ParseError {
+            metadata: self.metadata,
+            entry: self.entry.take(),
+            offset,
+            error,
+        });
+        reader.read_to_end(end_tag, &mut Vec::new())?;
+        Ok(())
+    }
+
+    /// Fail with `error` in strict and recover mode, otherwise record it as a warning.
+    pub(crate) fn deviation(&mut self, error: MetadataError) -> Result<(), MetadataError> {
+        match self.mode {
+            ParseMode::Strict | ParseMode::Recover => Err(error),
+            ParseMode::Lenient => {
+                self.warn(error.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn warn(&mut self, message: String) {
+        logging::debug!("{}: {}", self.metadata, message);
+        self.warnings.push(ParseWarning {
+            metadata: self.metadata,
+            entry: self.entry.clone(),
+            message,
+        });
+    }
+
+    /// Unwrap `result`, substituting `default` for an error in lenient mode.
+    pub(crate) fn tolerate<T>(
+        &mut self,
+        result: Result<T, MetadataError>,
+        default: impl FnOnce() -> T,
+    ) -> Result<T, MetadataError> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(e) => self.deviation(e).map(|_| default()),
+        }
+    }
+
+    /// The value of a required attribute of `tag`, or `default` if it's missing in lenient mode.
+    pub(crate) fn attribute<R: BufRead>(
+        &mut self,
+        reader: &Reader<R>,
+        tag: &BytesStart,
+        name: &'static str,
+        default: &str,
+    ) -> Result<String, MetadataError> {
+        match tag.try_get_attribute(name)? {
+            Some(value) => Ok(value.unescape_and_decode_value(reader)?),
+            None => self
+                .deviation(MetadataError::MissingAttributeError(name))
+                .map(|_| default.to_owned()),
+        }
+    }
+
+    /// The value of a required numeric attribute of `tag`, or zero if it's missing or invalid in lenient mode.
+    pub(crate) fn numeric_attribute<R: BufRead, T>(
+        &mut self,
+        reader: &Reader<R>,
+        tag: &BytesStart,
+        name: &'static str,
+    ) -> Result<T, MetadataError>
+    where
+        T: FromStr<Err = std::num::ParseIntError> + Default,
+    {
+        let value = match tag.try_get_attribute(name)? {
+            Some(value) => value
+                .unescape_and_decode_value(reader)?
+                .parse()
+                .map_err(MetadataError::from),
+            None => Err(MetadataError::MissingAttributeError(name)),
+        };
+        self.tolerate(value, T::default)
+    }
+
+    pub(crate) fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub(crate) fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+}
+
+/// Whether the rest of the file can still be read after `error` occurred within an entry.
+fn is_recoverable(error: &MetadataError) -> bool {
+    match error {
+        MetadataError::XmlParseError(e) => matches!(
+            e,
+            quick_xml::Error::Utf8(_)
+                | quick_xml::Error::TextNotFound
+                | quick_xml::Error::InvalidAttr(_)
+                | quick_xml::Error::EscapeError(_)
+        ),
+        MetadataError::IoError(_) | MetadataError::UnsupportedCompressionTypeError(_) => false,
+        _ => true,
+    }
 }
 
 // #[derive(Error, Debug)]
@@ -85,6 +636,8 @@ pub const XML_NS_OTHER: &str = "http://linux.duke.edu/metadata/other";
 pub const XML_NS_REPO: &str = "http://linux.duke.edu/metadata/repo";
 /// Namespace for rpm (used in primary.xml and repomd.xml)
 pub const XML_NS_RPM: &str = "http://linux.duke.edu/metadata/rpm";
+/// Default namespace for patterns.xml
+pub const XML_NS_PATTERN: &str = "http://novell.com/package/metadata/suse/pattern";
 
 pub trait RpmMetadata {
     fn filename() -> &'static str;
@@ -112,7 +665,7 @@ pub enum CompressionType {
 }
 
 impl CompressionType {
-    pub fn to_file_extension(&self) -> &str {
+    pub fn to_file_extension(&self) -> &'static str {
         match self {
             CompressionType::None => "",
             CompressionType::Gzip => ".gz",
@@ -138,6 +691,213 @@ impl TryInto<CompressionType> for &str {
     }
 }
 
+/// How metadata is laid out and escaped when it's written.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum XmlStyle {
+    #[default]
+    Standard,
+    /// Match the output of createrepo_c byte for byte, so that tools which compare metadata files don't
+    /// see any changes after switching from createrepo_c. Differences from [`XmlStyle::Standard`]:
+    ///
+    /// - packages aren't indented in primary.xml, filelists.xml and other.xml
+    /// - quotes aren't escaped in text, and apostrophes aren't escaped at all
+    /// - carriage returns are written as character references, as are newlines and tabs in attributes
+    /// - non-ASCII characters in the attributes of packages are written as character references
+    /// - `xml:base` is written for package and `repomd.xml` record locations
+    /// - advisories leave out empty elements and attributes, and give dates as `date` attributes
+    /// - `repomd.xml` records are sorted the way createrepo_c sorts them
+    CreaterepoC,
+    /// Metadata which the yum and createrepo shipped with EL5 / EL6 understand, and which round-trips the
+    /// metadata of repositories they created. Differences from [`XmlStyle::Standard`]:
+    ///
+    /// - SHA-1 checksums are labelled `sha` rather than `sha1`
+    /// - `repomd.xml` has no `<revision>` unless one was set
+    Legacy,
+}
+
+/// Whether an optional element of the packages in `primary.xml` is written, see [`PrimaryElements`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ElementPolicy {
+    /// Write the element even if it's empty, as createrepo_c does
+    #[default]
+    Always,
+    /// Write the element unless it's empty, or for `rpm:header-range`, unless both offsets are 0
+    IfPresent,
+    /// Never write the element
+    Omit,
+}
+
+/// Which of the optional elements of the packages in `primary.xml` are written. By default they all
+/// are, as createrepo_c writes them, but some tools choke on e.g. the header range of packages which
+/// weren't read from an RPM.
+///
+/// - `header_range` - `<rpm:header-range>`, the offsets of the header in the RPM.
+/// - `buildhost` - `<rpm:buildhost>`.
+/// - `packager` - `<packager>`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrimaryElements {
+    pub header_range: ElementPolicy,
+    pub buildhost: ElementPolicy,
+    pub packager: ElementPolicy,
+}
+
+impl PrimaryElements {
+    pub fn header_range(self, policy: ElementPolicy) -> Self {
+        Self {
+            header_range: policy,
+            ..self
+        }
+    }
+
+    pub fn buildhost(self, policy: ElementPolicy) -> Self {
+        Self {
+            buildhost: policy,
+            ..self
+        }
+    }
+
+    pub fn packager(self, policy: ElementPolicy) -> Self {
+        Self {
+            packager: policy,
+            ..self
+        }
+    }
+}
+
+impl ElementPolicy {
+    /// Whether an element is written, given whether it's empty.
+    pub(crate) fn writes(self, empty: bool) -> bool {
+        match self {
+            ElementPolicy::Always => true,
+            ElementPolicy::IfPresent => !empty,
+            ElementPolicy::Omit => false,
+        }
+    }
+}
+
+/// The line endings of metadata files, see [`XmlFormat`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+/// The order the attributes of elements are written in, see [`XmlFormat`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AttributeOrder {
+    /// The order createrepo_c writes them in, e.g. `epoch`, `ver`, `rel`
+    #[default]
+    Standard,
+    /// Sorted by name, after any namespace declarations
+    Alphabetical,
+}
+
+/// How metadata files are indented and laid out. The default is the layout createrepo_c writes.
+///
+/// - `indent_char` - The character elements are indented with, usually `b' '` or `b'\t'`.
+/// - `indent_width` - How many of `indent_char` each level of elements is indented by.
+/// - `line_ending` - Whether lines end in `\n` or `\r\n`.
+/// - `attribute_order` - The order the attributes of elements are written in, see [`AttributeOrder`].
+/// - `compact` - Write the elements without any indentation or line breaks between them, for the
+///   smallest files. Overrides `indent_char` and `indent_width`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct XmlFormat {
+    pub indent_char: u8,
+    pub indent_width: usize,
+    pub line_ending: LineEnding,
+    pub attribute_order: AttributeOrder,
+    pub compact: bool,
+}
+
+impl Default for XmlFormat {
+    fn default() -> Self {
+        Self {
+            indent_char: b' ',
+            indent_width: 2,
+            line_ending: LineEnding::Lf,
+            attribute_order: AttributeOrder::Standard,
+            compact: false,
+        }
+    }
+}
+
+impl XmlFormat {
+    pub fn indent(self, indent_char: u8, indent_width: usize) -> Self {
+        Self {
+            indent_char,
+            indent_width,
+            ..self
+        }
+    }
+
+    pub fn line_ending(self, line_ending: LineEnding) -> Self {
+        Self {
+            line_ending,
+            ..self
+        }
+    }
+
+    pub fn attribute_order(self, order: AttributeOrder) -> Self {
+        Self {
+            attribute_order: order,
+            ..self
+        }
+    }
+
+    pub fn compact(self, val: bool) -> Self {
+        Self {
+            compact: val,
+            ..self
+        }
+    }
+}
+
+/// What to do with characters which aren't allowed in XML 1.0: control characters other than tab, newline
+/// and carriage return, U+FFFE and U+FFFF, and (when reading) bytes which aren't valid UTF-8. Packages
+/// sometimes have such characters in their changelogs or descriptions, and most XML parsers (including
+/// the one used by dnf) refuse to read metadata which contains them.
+///
+/// By default they're an error, so that metadata is never changed without asking. Stripping or
+/// replacing them has to be opted into, e.g. to clean up a repository which dnf can't read.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InvalidCharPolicy {
+    /// Leave them out
+    Strip,
+    /// Replace each of them with U+FFFD (the replacement character)
+    Replace,
+    /// Fail with an I/O error of kind [`std::io::ErrorKind::InvalidData`]
+    #[default]
+    Error,
+}
+
+/// The encoding to decode bytes which aren't valid UTF-8 with, rather than treating them as invalid
+/// characters. Some ancient repositories have Latin-1 text in the packagers or changelogs of packages.
+/// The text is valid UTF-8 once it's read, so writing the metadata out again cleans it up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FallbackEncoding {
+    /// ISO-8859-1
+    Latin1,
+    /// Windows-1252, the superset of Latin-1 which has printable characters in place of the C1 controls
+    Windows1252,
+}
+
+impl FallbackEncoding {
+    /// The character encoded as `byte`.
+    pub(crate) fn decode(&self, byte: u8) -> char {
+        // 0x80 - 0x9F, with the undefined ones mapped to the C1 controls like browsers do
+        const WINDOWS_1252: [char; 32] = [
+            '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž',
+            '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ',
+            '\u{9D}', 'ž', 'Ÿ',
+        ];
+        match (self, byte) {
+            (FallbackEncoding::Windows1252, 0x80..=0x9f) => WINDOWS_1252[byte as usize - 0x80],
+            _ => byte as char,
+        }
+    }
+}
+
 // impl Ord for Package {
 //     #[inline]
 //     fn cmp(&self, other: &Package) -> Ordering {
@@ -193,6 +953,14 @@ pub struct Package {
 
     pub rpm_changelogs: Vec<Changelog>,
     pub rpm_files: Vec<PackageFile>,
+
+    /// Where the descriptions of the changelogs are kept, if they're stored out-of-line
+    pub stored_changelogs: Option<StoredChangelogs>,
+    /// Where the files are kept, if they're stored in a [`PathTable`](crate::PathTable)
+    pub stored_files: Option<StoredFiles>,
+
+    /// XML which wasn't understood, if it was preserved
+    pub unknown_xml: Option<Box<UnknownPackageXml>>,
 }
 
 impl Package {
@@ -228,7 +996,7 @@ impl Package {
     }
 
     pub fn set_epoch(&mut self, epoch: u32) -> &mut Self {
-        self.evr.epoch = epoch.to_string().into();
+        self.evr.epoch = epoch.to_string();
         self
     }
 
@@ -237,7 +1005,7 @@ impl Package {
     }
 
     pub fn set_version(&mut self, version: impl Into<String>) -> &mut Self {
-        self.evr.version = version.into().into();
+        self.evr.version = version.into();
         self
     }
 
@@ -246,7 +1014,7 @@ impl Package {
     }
 
     pub fn set_release(&mut self, release: impl Into<String>) -> &mut Self {
-        self.evr.release = release.into().into();
+        self.evr.release = release.into();
         self
     }
 
@@ -300,6 +1068,116 @@ impl Package {
             self.name, self.evr.epoch, self.evr.version, self.evr.release, self.arch
         )
     }
+
+    /// The file name an RPM of this package is normally given, e.g. `foo-1.0-1.noarch.rpm`.
+    pub fn canonical_filename(&self) -> String {
+        format!("{}.rpm", self.nvra())
+    }
+
+    /// Change the name of the package, updating what's derived from it: the provides of the package
+    /// itself (`foo = 1.0-1` and `foo(x86-64) = 1.0-1`), and the location_href if the file is named
+    /// after the package. [`Package::set_name()`] only changes the name.
+    pub fn rename(&mut self, name: impl Into<String>) -> &mut Self {
+        let old_name = std::mem::replace(&mut self.name, name.into());
+        let old_evr = self.evr.clone();
+        self.relink(&old_name, &old_evr)
+    }
+
+    /// Change the EVR of the package, updating what's derived from it like [`Package::rename()`] does.
+    /// [`Package::set_evr()`] only changes the EVR.
+    pub fn change_evr(&mut self, evr: EVR) -> &mut Self {
+        let old_evr = self.evr.clone();
+        self.set_evr(evr);
+        let old_name = self.name.clone();
+        self.relink(&old_name, &old_evr)
+    }
+
+    /// Name the file in the location_href after the package, keeping the directory it's in.
+    pub fn recompute_location_href(&mut self) -> &mut Self {
+        let filename = self.canonical_filename();
+        self.location_href = match self.location_href.rfind('/') {
+            Some(pos) => format!("{}{}", &self.location_href[..=pos], filename),
+            None => filename,
+        };
+        self
+    }
+
+    /// The provides rpmbuild gives every package of itself: `name = epoch:version-release`, and for
+    /// packages built for an architecture it knows the instruction set of, `name(isa)` as well, e.g.
+    /// `foo(x86-64) = 0:1.0-1`.
+    pub fn self_provides(&self) -> Vec<Requirement> {
+        let epoch = match self.evr.epoch.as_str() {
+            "" => "0",
+            epoch => epoch,
+        };
+        let provide = |name: String| Requirement {
+            name,
+            flags: Some("EQ".to_owned()),
+            epoch: Some(epoch.to_owned()),
+            version: Some(self.evr.version.clone()),
+            release: Some(self.evr.release.clone()),
+            ..Requirement::default()
+        };
+        let mut provides = vec![provide(self.name.clone())];
+        if let Some(isa) = isa(&self.arch) {
+            provides.push(provide(format!("{}({})", self.name, isa)));
+        }
+        provides
+    }
+
+    /// Provides of the files of the package in `bin` and `sbin` directories (e.g. `/usr/bin/foo`), which
+    /// are listed in primary.xml so that dependencies on them can be resolved without the filelists.
+    pub fn file_provides(&self) -> Vec<Requirement> {
+        self.load_files()
+            .iter()
+            .filter(|file| file.filetype != FileType::Dir)
+            .filter(|file| {
+                let parent = file.path.rsplit('/').nth(1);
+                matches!(parent, Some("bin" | "sbin"))
+            })
+            .map(|file| Requirement {
+                name: file.path.clone(),
+                ..Requirement::default()
+            })
+            .collect()
+    }
+
+    /// Bring the self-provides and location_href which matched the old name and EVR of the package in
+    /// line with the current ones.
+    fn relink(&mut self, old_name: &str, old_evr: &EVR) -> &mut Self {
+        let epoch = match self.evr.epoch.as_str() {
+            "" => "0",
+            epoch => epoch,
+        };
+        for provide in &mut self.rpm_provides {
+            let Some(suffix) = provide.name.strip_prefix(old_name) else {
+                continue;
+            };
+            let is_self_provide = (suffix.is_empty()
+                || (suffix.starts_with('(') && suffix.ends_with(')')))
+                && provide.flags.as_deref() == Some("EQ")
+                && EVR::new(
+                    provide.epoch.as_deref().unwrap_or_default(),
+                    provide.version.as_deref().unwrap_or_default(),
+                    provide.release.as_deref().unwrap_or_default(),
+                ) == *old_evr;
+            if is_self_provide {
+                provide.name = format!("{}{}", self.name, suffix);
+                provide.epoch = Some(epoch.to_owned());
+                provide.version = Some(self.evr.version.clone());
+                provide.release = Some(self.evr.release.clone());
+            }
+        }
+
+        let old_filename = format!(
+            "{}-{}-{}.{}.rpm",
+            old_name, old_evr.version, old_evr.release, self.arch
+        );
+        if self.location_href.rsplit('/').next() == Some(old_filename.as_str()) {
+            self.recompute_location_href();
+        }
+        self
+    }
     // TODO: signature
     pub fn set_checksum(&mut self, checksum: Checksum) -> &mut Self {
         self.checksum = checksum;
@@ -312,7 +1190,7 @@ impl Package {
 
     pub fn pkgid(&self) -> &str {
         // TODO: better way to do this
-        &self.checksum.to_values().unwrap().1
+        self.checksum.to_values().unwrap().1
     }
 
     pub fn set_location_href(&mut self, location_href: impl Into<String>) -> &mut Self {
@@ -325,12 +1203,12 @@ impl Package {
     }
 
     pub fn set_location_base(&mut self, location_base: Option<impl Into<String>>) -> &mut Self {
-        self.location_base = location_base.and_then(|a| Some(a.into()));
+        self.location_base = location_base.map(|a| a.into());
         self
     }
 
     pub fn location_base(&self) -> Option<&str> {
-        self.location_base.as_ref().and_then(|a| Some(a.as_ref()))
+        self.location_base.as_ref().map(|a| a.as_ref())
     }
 
     pub fn set_summary(&mut self, summary: impl Into<String>) -> &mut Self {
@@ -551,9 +1429,12 @@ impl Package {
 
     pub fn set_files(&mut self, files: Vec<PackageFile>) -> &mut Self {
         self.rpm_files = files;
+        self.stored_files = None;
         self
     }
 
+    /// The files of the package, empty if they're stored in a [`PathTable`](crate::PathTable), see
+    /// [`Package::load_files()`].
     pub fn files(&self) -> &[PackageFile] {
         &self.rpm_files
     }
@@ -569,61 +1450,433 @@ impl Package {
 
     pub fn set_changelogs(&mut self, changelogs: Vec<Changelog>) -> &mut Self {
         self.rpm_changelogs = changelogs;
+        self.stored_changelogs = None;
         self
     }
 
+    /// The changelogs, without their descriptions if they're stored out-of-line, see
+    /// [`Package::load_changelogs()`].
     pub fn changelogs(&self) -> &[Changelog] {
         &self.rpm_changelogs
     }
-}
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum ChecksumType {
-    Md5,
-    Sha1,
-    Sha224,
-    Sha256,
-    Sha384,
-    Sha512,
-    Unknown,
-}
+    pub fn set_unknown_xml(&mut self, unknown_xml: Option<UnknownPackageXml>) -> &mut Self {
+        self.unknown_xml = unknown_xml.map(Box::new);
+        self
+    }
 
-impl Default for ChecksumType {
-    fn default() -> Self {
-        ChecksumType::Sha256
+    /// The XML of the package which wasn't understood, kept because of [`ParseOptions::preserve_unknown`].
+    pub fn unknown_xml(&self) -> Option<&UnknownPackageXml> {
+        self.unknown_xml.as_deref()
+    }
+
+    pub fn unknown_xml_mut(&mut self) -> &mut UnknownPackageXml {
+        self.unknown_xml.get_or_insert_with(Default::default)
+    }
+
+    /// Start building a package with a [`PackageBuilder`].
+    pub fn builder() -> PackageBuilder {
+        PackageBuilder::default()
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum Checksum {
-    Md5(String),
-    Sha1(String),
-    Sha224(String),
-    Sha256(String),
-    Sha384(String),
-    Sha512(String),
-    Unknown(String),
-    Empty,
+/// Builds a [`Package`], making sure that the fields every package needs are set.
+///
+/// The name, arch, EVR (at least the version), checksum and location_href are required, and
+/// [`PackageBuilder::build()`] fails with [`MetadataError::MissingFieldsError`] listing each of them
+/// which wasn't set (or was set to an empty value). Everything else defaults to being empty or zero,
+/// except for the epoch, which defaults to `0`.
+///
+/// The provides which rpmbuild would add implicitly can be added by [`PackageBuilder::build()`] too, see
+/// [`PackageBuilder::self_provides()`] and [`PackageBuilder::file_provides()`].
+#[derive(Clone, Debug, Default)]
+pub struct PackageBuilder {
+    package: Package,
+    self_provides: bool,
+    file_provides: bool,
 }
 
-impl Default for Checksum {
-    fn default() -> Self {
-        Checksum::Empty
+impl PackageBuilder {
+    pub fn new() -> Self {
+        Self::default()
     }
-}
 
-impl Hash for Checksum {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        match self {
-            Self::Md5(hash) => format!("md5:{}", hash).hash(state),
-            Self::Sha1(hash) => format!("sha1:{}", hash).hash(state),
-            Self::Sha224(hash) => format!("sha224:{}", hash).hash(state),
-            Self::Sha256(hash) => format!("sha256:{}", hash).hash(state),
-            Self::Sha384(hash) => format!("sha384:{}", hash).hash(state),
-            Self::Sha512(hash) => format!("sha512:{}", hash).hash(state),
-            // TODO: adjust this representation. Currently these exist because of reuse of these enums
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.package.set_name(name);
+        self
+    }
+
+    pub fn arch(mut self, arch: impl Into<String>) -> Self {
+        self.package.set_arch(arch);
+        self
+    }
+
+    pub fn evr(mut self, evr: EVR) -> Self {
+        self.package.set_evr(evr);
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.package.set_checksum(checksum);
+        self
+    }
+
+    pub fn location_href(mut self, location_href: impl Into<String>) -> Self {
+        self.package.set_location_href(location_href);
+        self
+    }
+
+    pub fn location_base(mut self, location_base: impl Into<String>) -> Self {
+        self.package.set_location_base(Some(location_base));
+        self
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.package.set_summary(summary);
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.package.set_description(description);
+        self
+    }
+
+    pub fn packager(mut self, packager: impl Into<String>) -> Self {
+        self.package.set_packager(packager);
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.package.set_url(url);
+        self
+    }
+
+    pub fn time_file(mut self, time_file: u64) -> Self {
+        self.package.set_time_file(time_file);
+        self
+    }
+
+    pub fn time_build(mut self, time_build: u64) -> Self {
+        self.package.set_time_build(time_build);
+        self
+    }
+
+    pub fn size_package(mut self, size_package: u64) -> Self {
+        self.package.set_size_package(size_package);
+        self
+    }
+
+    pub fn size_installed(mut self, size_installed: u64) -> Self {
+        self.package.set_size_installed(size_installed);
+        self
+    }
+
+    pub fn size_archive(mut self, size_archive: u64) -> Self {
+        self.package.set_size_archive(size_archive);
+        self
+    }
+
+    pub fn rpm_license(mut self, license: impl Into<String>) -> Self {
+        self.package.set_rpm_license(license);
+        self
+    }
+
+    pub fn rpm_vendor(mut self, vendor: impl Into<String>) -> Self {
+        self.package.set_rpm_vendor(vendor);
+        self
+    }
+
+    pub fn rpm_group(mut self, group: impl Into<String>) -> Self {
+        self.package.set_rpm_group(group);
+        self
+    }
+
+    pub fn rpm_buildhost(mut self, rpm_buildhost: impl Into<String>) -> Self {
+        self.package.set_rpm_buildhost(rpm_buildhost);
+        self
+    }
+
+    pub fn rpm_sourcerpm(mut self, rpm_sourcerpm: impl Into<String>) -> Self {
+        self.package.set_rpm_sourcerpm(rpm_sourcerpm);
+        self
+    }
+
+    pub fn rpm_header_range(mut self, start: u64, end: u64) -> Self {
+        self.package.set_rpm_header_range(start, end);
+        self
+    }
+
+    pub fn requires(mut self, requires: Vec<Requirement>) -> Self {
+        self.package.set_requires(requires);
+        self
+    }
+
+    pub fn provides(mut self, provides: Vec<Requirement>) -> Self {
+        self.package.set_provides(provides);
+        self
+    }
+
+    pub fn conflicts(mut self, conflicts: Vec<Requirement>) -> Self {
+        self.package.set_conflicts(conflicts);
+        self
+    }
+
+    pub fn obsoletes(mut self, obsoletes: Vec<Requirement>) -> Self {
+        self.package.set_obsoletes(obsoletes);
+        self
+    }
+
+    pub fn suggests(mut self, suggests: Vec<Requirement>) -> Self {
+        self.package.set_suggests(suggests);
+        self
+    }
+
+    pub fn enhances(mut self, enhances: Vec<Requirement>) -> Self {
+        self.package.set_enhances(enhances);
+        self
+    }
+
+    pub fn recommends(mut self, recommends: Vec<Requirement>) -> Self {
+        self.package.set_recommends(recommends);
+        self
+    }
+
+    pub fn supplements(mut self, supplements: Vec<Requirement>) -> Self {
+        self.package.set_supplements(supplements);
+        self
+    }
+
+    pub fn files(mut self, files: Vec<PackageFile>) -> Self {
+        self.package.set_files(files);
+        self
+    }
+
+    pub fn changelogs(mut self, changelogs: Vec<Changelog>) -> Self {
+        self.package.set_changelogs(changelogs);
+        self
+    }
+
+    /// Whether to add the [`Package::self_provides()`] which aren't provided already. Off by default.
+    pub fn self_provides(mut self, val: bool) -> Self {
+        self.self_provides = val;
+        self
+    }
+
+    /// Whether to add the [`Package::file_provides()`] which aren't provided already. Off by default.
+    pub fn file_provides(mut self, val: bool) -> Self {
+        self.file_provides = val;
+        self
+    }
+
+    /// Check that the required fields are set and that the EVR is valid, and return the package.
+    pub fn build(self) -> Result<Package, MetadataError> {
+        let mut package = self.package;
+        let missing: Vec<&'static str> = [
+            ("name", package.name.is_empty()),
+            ("arch", package.arch.is_empty()),
+            ("evr", package.evr.version.is_empty()),
+            ("checksum", matches!(package.checksum, Checksum::Empty)),
+            ("location_href", package.location_href.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(field, missing)| missing.then_some(field))
+        .collect();
+        if !missing.is_empty() {
+            return Err(MetadataError::MissingFieldsError(missing));
+        }
+
+        if package.evr.epoch.is_empty() {
+            package.set_epoch(0);
+        }
+        package.evr.validate()?;
+
+        let mut implicit = Vec::new();
+        if self.self_provides {
+            implicit.extend(package.self_provides());
+        }
+        if self.file_provides {
+            implicit.extend(package.file_provides());
+        }
+        for provide in implicit {
+            if !package.rpm_provides.contains(&provide) {
+                package.rpm_provides.push(provide);
+            }
+        }
+        Ok(package)
+    }
+}
+
+/// The instruction set rpm uses in the arch-specific provides of packages built for `arch`, e.g. `x86-64`
+/// for `x86_64`. `None` for `noarch` and architectures it doesn't have one for.
+fn isa(arch: &str) -> Option<&'static str> {
+    let isa = match arch {
+        "x86_64" => "x86-64",
+        "i386" | "i486" | "i586" | "i686" | "athlon" | "pentium3" | "pentium4" => "x86-32",
+        "aarch64" => "aarch-64",
+        "armv7hl" | "armv7hnl" | "armhfp" => "arm-32",
+        "ppc64" | "ppc64le" | "ppc64p7" => "ppc-64",
+        "ppc" => "ppc-32",
+        "s390x" => "s390-64",
+        "s390" => "s390-32",
+        "riscv64" => "riscv-64",
+        "loongarch64" => "loongarch-64",
+        "mips64el" | "mips64" => "mips-64",
+        "sparc64" | "sparc64v" => "sparc-64",
+        "alpha" | "alphaev6" => "alpha-64",
+        _ => return None,
+    };
+    Some(isa)
+}
+
+/// Attributes and elements which weren't understood, so that they can be written out again.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct UnknownXml {
+    /// Attributes of the element, as `(name, value)` pairs
+    pub attributes: Vec<(String, String)>,
+    /// Child elements, each serialized as XML
+    pub elements: Vec<String>,
+}
+
+impl UnknownXml {
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty() && self.elements.is_empty()
+    }
+
+    /// The value of the attribute `name`, as it's written, e.g. `pulp:origin`.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of the attribute `name` of the XML namespace `namespace`, whichever prefix the namespace
+    /// was declared with.
+    pub fn namespaced_attribute(&self, namespace: &str, name: &str) -> Option<&str> {
+        let prefix = self.prefix_of(namespace)?;
+        self.attribute(&format!("{}:{}", prefix, name))
+    }
+
+    /// Set the attribute `name` of the XML namespace `namespace`, declaring the namespace with `prefix`
+    /// unless it's declared already.
+    pub fn set_namespaced_attribute(
+        &mut self,
+        prefix: &str,
+        namespace: &str,
+        name: &str,
+        value: impl Into<String>,
+    ) {
+        let prefix = match self.prefix_of(namespace) {
+            Some(declared) => declared.to_owned(),
+            None => {
+                self.attributes
+                    .push((format!("xmlns:{}", prefix), namespace.to_owned()));
+                prefix.to_owned()
+            }
+        };
+        let key = format!("{}:{}", prefix, name);
+        let value = value.into();
+        match self.attributes.iter_mut().find(|(other, _)| *other == key) {
+            Some((_, old)) => *old = value,
+            None => self.attributes.push((key, value)),
+        }
+    }
+
+    /// The elements named `name` of the XML namespace `namespace`, each serialized as XML.
+    pub fn namespaced_elements<'a>(
+        &'a self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.elements
+            .iter()
+            .filter(move |element| {
+                utils::element_namespace(element)
+                    .is_some_and(|(uri, local)| uri.as_deref() == Some(namespace) && local == name)
+            })
+            .map(String::as_str)
+    }
+
+    /// The prefix the namespace `namespace` is declared with on the element.
+    fn prefix_of(&self, namespace: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(_, value)| value == namespace)
+            .and_then(|(key, _)| key.strip_prefix("xmlns:"))
+    }
+
+    pub(crate) fn push_attributes(&self, tag: &mut BytesStart) {
+        for (name, value) in &self.attributes {
+            tag.push_attribute((name.as_str(), value.as_str()));
+        }
+    }
+
+    pub(crate) fn write_elements<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+    ) -> Result<(), MetadataError> {
+        for element in &self.elements {
+            utils::write_element_xml(writer, element)?;
+        }
+        Ok(())
+    }
+}
+
+/// The XML of a package which wasn't understood, by where it was found.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct UnknownPackageXml {
+    /// `<package>` in primary.xml
+    pub primary: UnknownXml,
+    /// `<format>` in primary.xml
+    pub format: UnknownXml,
+    /// `<package>` in filelists.xml
+    pub filelists: UnknownXml,
+    /// `<package>` in other.xml
+    pub other: UnknownXml,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ChecksumType {
+    Md5,
+    Sha1,
+    Sha224,
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum Checksum {
+    Md5(String),
+    Sha1(String),
+    Sha224(String),
+    Sha256(String),
+    Sha384(String),
+    Sha512(String),
+    /// A checksum of a type which isn't known to this library, such as one introduced after it was written,
+    /// as the name of the type and the hex digest. It's read and written like the others, but can't be
+    /// verified.
+    Other(String, String),
+    Unknown(String),
+    #[default]
+    Empty,
+}
+
+impl Hash for Checksum {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Md5(hash) => format!("md5:{}", hash).hash(state),
+            Self::Sha1(hash) => format!("sha1:{}", hash).hash(state),
+            Self::Sha224(hash) => format!("sha224:{}", hash).hash(state),
+            Self::Sha256(hash) => format!("sha256:{}", hash).hash(state),
+            Self::Sha384(hash) => format!("sha384:{}", hash).hash(state),
+            Self::Sha512(hash) => format!("sha512:{}", hash).hash(state),
+            Self::Other(name, hash) => format!("{}:{}", name, hash).hash(state),
+            // TODO: adjust this representation. Currently these exist because of reuse of these enums
             // to represent intermediate parsing states, but those probably ought to be pulled out somehow
-            Self::Unknown(hash) => unimplemented!(),
+            Self::Unknown(_hash) => unimplemented!(),
             Self::Empty => unimplemented!(),
         }
     }
@@ -714,15 +1967,28 @@ impl Checksum {
                     Ok(Checksum::Sha512(digest))
                 }
             }
-            _ => {
-                return Err(MetadataError::UnsupportedChecksumTypeError(bytes_to_str(
-                    checksum_type.as_ref(),
-                )))
-            }
+            name if is_checksum_type_name(name) && is_hex_digest(checksum.as_ref()) => Ok(
+                Checksum::Other(bytes_to_str(name), bytes_to_str(checksum.as_ref())),
+            ),
+            _ => Err(MetadataError::UnsupportedChecksumTypeError(bytes_to_str(
+                checksum_type.as_ref(),
+            ))),
+        }
+    }
+
+    pub fn checksum_type(&self) -> ChecksumType {
+        match self {
+            Checksum::Md5(_) => ChecksumType::Md5,
+            Checksum::Sha1(_) => ChecksumType::Sha1,
+            Checksum::Sha224(_) => ChecksumType::Sha224,
+            Checksum::Sha256(_) => ChecksumType::Sha256,
+            Checksum::Sha384(_) => ChecksumType::Sha384,
+            Checksum::Sha512(_) => ChecksumType::Sha512,
+            Checksum::Other(..) | Checksum::Unknown(_) | Checksum::Empty => ChecksumType::Unknown,
         }
     }
 
-    pub fn to_values<'a>(&'a self) -> Result<(&str, &'a str), MetadataError> {
+    pub fn to_values(&self) -> Result<(&str, &str), MetadataError> {
         let values = match self {
             Checksum::Md5(c) => ("md5", c.as_str()),
             Checksum::Sha1(c) => ("sha1", c.as_str()),
@@ -730,6 +1996,7 @@ impl Checksum {
             Checksum::Sha256(c) => ("sha256", c.as_str()),
             Checksum::Sha384(c) => ("sha384", c.as_str()),
             Checksum::Sha512(c) => ("sha512", c.as_str()),
+            Checksum::Other(name, c) => (name.as_str(), c.as_str()),
             Checksum::Unknown(c) => ("unknown", c.as_str()), // TODO: need to fix this - if filelists is loaded w/o metadata the pkgid is known but the type is not
             Checksum::Empty => panic!("Cannot take value of empty checksum"),
         };
@@ -737,6 +2004,19 @@ impl Checksum {
     }
 }
 
+/// Whether `name` looks like the name of a checksum type, e.g. `sha3-256` or `blake2b`.
+fn is_checksum_type_name(name: &[u8]) -> bool {
+    name.first().is_some_and(u8::is_ascii_lowercase)
+        && name
+            .iter()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == b'-' || *c == b'_')
+}
+
+/// Whether `digest` looks like a hex digest at least as long as an MD5 one.
+fn is_hex_digest(digest: &[u8]) -> bool {
+    digest.len() >= 32 && digest.len().is_multiple_of(2) && digest.iter().all(u8::is_ascii_hexdigit)
+}
+
 #[derive(Clone, Debug, Default, Hash, PartialEq)]
 pub struct Changelog {
     pub author: String,
@@ -792,15 +2072,16 @@ impl TryFrom<&str> for RequirementType {
             "EQ" => RequirementType::EQ,
             "LE" => RequirementType::LE,
             "GE" => RequirementType::GE,
-            t @ _ => return Err(MetadataError::InvalidFlagsError(t.to_owned())),
+            t => return Err(MetadataError::InvalidFlagsError(t.to_owned())),
         };
 
         Ok(reqtype)
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Default)]
 pub enum FileType {
+    #[default]
     File,
     Dir,
     Ghost,
@@ -827,32 +2108,153 @@ impl FileType {
     }
 }
 
-impl Default for FileType {
-    fn default() -> Self {
-        FileType::File
-    }
-}
-
 #[derive(Clone, Debug, Default, Hash, PartialEq)]
 pub struct PackageFile {
     pub filetype: FileType,
     pub path: String,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// The type of a metadata file, as given by the `type` of its record in `repomd.xml`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum MetadataType {
     Primary,
     Filelists,
     Other,
 
-    // PrimaryZck,
-    // FilelistsZck,
-    // OtherZck,
+    PrimaryZck,
+    FilelistsZck,
+    OtherZck,
 
-    // PrimaryDb,
-    // FilelistsDb,
-    // OtherDb,
-    Unknown,
+    PrimaryDb,
+    FilelistsDb,
+    OtherDb,
+
+    Updateinfo,
+    UpdateinfoZck,
+
+    /// The package groups (comps) of the repository
+    Group,
+    GroupGz,
+    GroupZck,
+
+    Modules,
+
+    /// The AppStream data of the desktop applications of a repository, which isn't made from the packages
+    Appstream,
+    /// The icons of the applications in the AppStream data, as a tarball
+    AppstreamIcons,
+
+    /// The products a SUSE repository belongs to
+    Products,
+    /// The patterns (sets of packages to install together) of a SUSE repository
+    Patterns,
+
+    /// Any other type, such as `prestodelta` or one only a particular tool knows about
+    Custom(String),
+}
+
+impl MetadataType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MetadataType::Primary => METADATA_PRIMARY,
+            MetadataType::Filelists => METADATA_FILELISTS,
+            MetadataType::Other => METADATA_OTHER,
+            MetadataType::PrimaryZck => "primary_zck",
+            MetadataType::FilelistsZck => "filelists_zck",
+            MetadataType::OtherZck => "other_zck",
+            MetadataType::PrimaryDb => "primary_db",
+            MetadataType::FilelistsDb => "filelists_db",
+            MetadataType::OtherDb => "other_db",
+            MetadataType::Updateinfo => METADATA_UPDATEINFO,
+            MetadataType::UpdateinfoZck => "updateinfo_zck",
+            MetadataType::Group => "group",
+            MetadataType::GroupGz => "group_gz",
+            MetadataType::GroupZck => "group_zck",
+            MetadataType::Modules => "modules",
+            MetadataType::Appstream => "appstream",
+            MetadataType::AppstreamIcons => "appstream-icons",
+            MetadataType::Products => "products",
+            MetadataType::Patterns => "patterns",
+            MetadataType::Custom(name) => name,
+        }
+    }
+
+    /// Whether this is the sqlite database version of another type of metadata.
+    pub fn is_database(&self) -> bool {
+        matches!(
+            self,
+            MetadataType::PrimaryDb | MetadataType::FilelistsDb | MetadataType::OtherDb
+        )
+    }
+
+    /// Whether this is the zchunk compressed version of another type of metadata.
+    pub fn is_zchunk(&self) -> bool {
+        matches!(
+            self,
+            MetadataType::PrimaryZck
+                | MetadataType::FilelistsZck
+                | MetadataType::OtherZck
+                | MetadataType::UpdateinfoZck
+                | MetadataType::GroupZck
+        )
+    }
+
+    /// The type of metadata this is a version of, e.g. [`MetadataType::Primary`] for
+    /// [`MetadataType::PrimaryDb`] and [`MetadataType::PrimaryZck`].
+    pub fn base(&self) -> MetadataType {
+        match self {
+            MetadataType::PrimaryZck | MetadataType::PrimaryDb => MetadataType::Primary,
+            MetadataType::FilelistsZck | MetadataType::FilelistsDb => MetadataType::Filelists,
+            MetadataType::OtherZck | MetadataType::OtherDb => MetadataType::Other,
+            MetadataType::UpdateinfoZck => MetadataType::Updateinfo,
+            MetadataType::GroupGz | MetadataType::GroupZck => MetadataType::Group,
+            other => other.clone(),
+        }
+    }
+
+    /// The extension of the file before any compression suffix, e.g. `xml` or `sqlite`.
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            _ if self.is_database() => "sqlite",
+            MetadataType::Modules => "yaml",
+            MetadataType::AppstreamIcons => "tar",
+            _ => "xml",
+        }
+    }
+
+    /// The suffix a file of this type has when it's compressed with `compression`. Zchunk and `group_gz`
+    /// files have the same suffix whatever `compression` is, and `group` files are never compressed.
+    pub fn compression_suffix(&self, compression: CompressionType) -> &'static str {
+        match self {
+            _ if self.is_zchunk() => ".zck",
+            MetadataType::GroupGz => CompressionType::Gzip.to_file_extension(),
+            MetadataType::Group => CompressionType::None.to_file_extension(),
+            _ => compression.to_file_extension(),
+        }
+    }
+
+    /// The name of the file in `repodata/`, e.g. `primary.xml.gz`, `primary.sqlite.bz2` or `comps.xml`,
+    /// without the checksum prefix some repositories use.
+    pub fn file_name(&self, compression: CompressionType) -> String {
+        let base = self.base();
+        let stem = match &base {
+            MetadataType::Group => "comps",
+            other => other.as_str(),
+        };
+        format!(
+            "{}.{}{}",
+            stem,
+            self.file_extension(),
+            self.compression_suffix(compression)
+        )
+    }
+}
+
+// Records created with `RepomdRecord::default()` have no type until one is assigned
+impl Default for MetadataType {
+    fn default() -> Self {
+        MetadataType::Custom(String::new())
+    }
 }
 
 impl From<&str> for MetadataType {
@@ -861,16 +2263,48 @@ impl From<&str> for MetadataType {
             METADATA_PRIMARY => MetadataType::Primary,
             METADATA_FILELISTS => MetadataType::Filelists,
             METADATA_OTHER => MetadataType::Other,
+            "primary_zck" => MetadataType::PrimaryZck,
+            "filelists_zck" => MetadataType::FilelistsZck,
+            "other_zck" => MetadataType::OtherZck,
+            "primary_db" => MetadataType::PrimaryDb,
+            "filelists_db" => MetadataType::FilelistsDb,
+            "other_db" => MetadataType::OtherDb,
+            METADATA_UPDATEINFO => MetadataType::Updateinfo,
+            "updateinfo_zck" => MetadataType::UpdateinfoZck,
+            "group" => MetadataType::Group,
+            "group_gz" => MetadataType::GroupGz,
+            "group_zck" => MetadataType::GroupZck,
+            "modules" => MetadataType::Modules,
+            "appstream" => MetadataType::Appstream,
+            "appstream-icons" => MetadataType::AppstreamIcons,
+            "products" => MetadataType::Products,
+            "patterns" => MetadataType::Patterns,
+            other => MetadataType::Custom(other.to_owned()),
+        }
+    }
+}
 
-            // METADATA_PRIMARY_DB => MetadataType::PrimaryDb,
-            // METADATA_FILELISTS_DB => MetadataType::FilelistsDb,
-            // METADATA_OTHER_DB => MetadataType::OtherDb,
+impl From<String> for MetadataType {
+    fn from(name: String) -> Self {
+        MetadataType::from(name.as_str())
+    }
+}
 
-            // METADATA_PRIMARY_ZCK => MetadataType::PrimaryZck,
-            // METADATA_FILELISTS_ZCK => MetadataType::FilelistsZck,
-            // METADATA_OTHER_ZCK => MetadataType::OtherZck,
-            _ => MetadataType::Unknown,
-        }
+impl fmt::Display for MetadataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for MetadataType {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for MetadataType {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
     }
 }
 
@@ -905,7 +2339,7 @@ impl RepomdData {
     pub fn get_record(&self, rectype: &str) -> Option<&RepomdRecord> {
         self.metadata_files
             .iter()
-            .find(|r| &r.metadata_name == rectype)
+            .find(|r| r.metadata_type == rectype)
     }
 
     pub fn records(&self) -> &Vec<RepomdRecord> {
@@ -916,9 +2350,9 @@ impl RepomdData {
     //     &self.metadata_files
     // }
 
-    // pub fn records_mut(&self) -> &mut BTreeMap<String, RepomdRecord> {
-    //     &mut self.metadata_files
-    // }
+    pub fn records_mut(&mut self) -> &mut Vec<RepomdRecord> {
+        &mut self.metadata_files
+    }
 
     // pub fn remove_record(&mut self, rectype: &str) {
     //     self.metadata_files.retain(|r| &r.mdtype != rectype);
@@ -957,23 +2391,40 @@ impl RepomdData {
         self.revision.as_deref()
     }
 
+    /// Keep only the records for which `f` returns true, e.g. to leave the sqlite databases out of a
+    /// minimal repository.
+    pub fn retain_records(&mut self, f: impl FnMut(&RepomdRecord) -> bool) {
+        self.metadata_files.retain(f);
+    }
+
     pub fn sort_records(&mut self) {
+        self.sort_records_by(RecordOrder::CreaterepoC);
+    }
+
+    /// Sort the records in `order`. The sort is stable, records which are equal in `order` keep the
+    /// order they were added in.
+    pub fn sort_records_by(&mut self, order: RecordOrder) {
         fn value(item: &RepomdRecord) -> u32 {
-            let mdtype = MetadataType::from(item.metadata_name.as_str());
-            match mdtype {
+            match item.metadata_type {
                 MetadataType::Primary => 1,
                 MetadataType::Filelists => 2,
                 MetadataType::Other => 3,
-                // MetadataType::PrimaryDb => 4,
-                // MetadataType::FilelistsDb => 5,
-                // MetadataType::OtherDb => 6,
-                // MetadataType::PrimaryZck => 7,
-                // MetadataType::FilelistsZck => 8,
-                // MetadataType::OtherZck => 9,
-                MetadataType::Unknown => 10,
+                MetadataType::PrimaryDb => 4,
+                MetadataType::FilelistsDb => 5,
+                MetadataType::OtherDb => 6,
+                MetadataType::PrimaryZck => 7,
+                MetadataType::FilelistsZck => 8,
+                MetadataType::OtherZck => 9,
+                _ => 10,
             }
         }
-        self.metadata_files.sort_by(|a, b| value(a).cmp(&value(b)));
+        match order {
+            RecordOrder::CreaterepoC => self.metadata_files.sort_by_key(value),
+            RecordOrder::Added => (),
+            RecordOrder::Alphabetical => self
+                .metadata_files
+                .sort_by(|a, b| a.metadata_type.as_str().cmp(b.metadata_type.as_str())),
+        }
     }
 
     // TODO error handling
@@ -993,12 +2444,26 @@ impl RepomdData {
     }
 }
 
+/// The order of the records in `repomd.xml`, see [`RepomdData::sort_records_by()`]. Some clients, like
+/// old versions of yum, expect the records of the package metadata to come first.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RecordOrder {
+    /// The order createrepo_c writes them in: `primary`, `filelists` and `other`, then their sqlite
+    /// databases, then their zchunk versions, then everything else in the order they were added
+    #[default]
+    CreaterepoC,
+    /// The order the records were added in
+    Added,
+    /// Ordered by the name of their type
+    Alphabetical,
+}
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct RepomdRecord {
     base_path: Option<PathBuf>,
 
     /// Record type
-    pub metadata_name: String,
+    pub metadata_type: MetadataType,
     /// Relative location of the file in a repository
     pub location_href: PathBuf,
     /// URL at which the location_href is relative - if it is not the current one
@@ -1022,30 +2487,198 @@ pub struct RepomdRecord {
 
     /// Database version (used only for sqlite databases like primary.sqlite etc.)
     pub database_version: Option<u32>,
+
+    /// XML of the record which wasn't understood, if it was preserved
+    pub unknown_xml: UnknownXml,
 }
 
 impl RepomdRecord {
     pub fn new(
-        name: &str,
+        name: impl Into<MetadataType>,
         href: &Path,
         base: &Path,
         checksum_type: ChecksumType,
     ) -> Result<Self, MetadataError> {
-        let mut record = RepomdRecord::default();
-        record.metadata_name = name.to_owned();
-        record.location_href = {
-            // let href = href
-            //     .strip_prefix(href.ancestors().nth(2).unwrap())
-            //     .unwrap()
-            //     .to_owned();
-            assert!(href.starts_with("repodata/"));
-            href.to_owned()
+        // let href = href
+        //     .strip_prefix(href.ancestors().nth(2).unwrap())
+        //     .unwrap()
+        //     .to_owned();
+        assert!(href.starts_with("repodata/"));
+        let mut record = RepomdRecord {
+            metadata_type: name.into(),
+            location_href: href.to_owned(),
+            base_path: Some(base.to_owned()),
+            ..RepomdRecord::default()
         };
-        record.base_path = Some(base.to_owned());
         record.fill(checksum_type)?;
         Ok(record)
     }
 
+    /// The value of the attribute `name` of the XML namespace `namespace` which another tool added to the
+    /// record, e.g. Pulp. Such attributes are only kept if `repomd.xml` was read with
+    /// [`ParseOptions::preserve_unknown`], see [`UnknownXml::namespaced_attribute()`].
+    pub fn extension_attribute(&self, namespace: &str, name: &str) -> Option<&str> {
+        self.unknown_xml.namespaced_attribute(namespace, name)
+    }
+
+    /// Add an attribute of the XML namespace `namespace` to the record, declared with `prefix` unless the
+    /// namespace is declared already, which is written out along with the record.
+    pub fn set_extension_attribute(
+        &mut self,
+        prefix: &str,
+        namespace: &str,
+        name: &str,
+        value: impl Into<String>,
+    ) {
+        self.unknown_xml
+            .set_namespaced_attribute(prefix, namespace, name, value)
+    }
+
+    /// The elements named `name` of the XML namespace `namespace` which another tool added to the record,
+    /// each serialized as XML. Like extension attributes, they're only kept if `repomd.xml` was read with
+    /// [`ParseOptions::preserve_unknown`].
+    pub fn extension_elements<'a>(
+        &'a self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.unknown_xml.namespaced_elements(namespace, name)
+    }
+
+    /// Create the record of the metadata file at `path`, which belongs in the `repodata/` directory of a
+    /// repository. The size, checksums and timestamp are computed from the file, looking through its
+    /// compression (including zchunk) for the open size and checksum.
+    pub fn from_file(
+        name: impl Into<MetadataType>,
+        path: &Path,
+        checksum_type: ChecksumType,
+    ) -> Result<Self, MetadataError> {
+        let file_name = path.file_name().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} isn't a file", path.display()),
+            )
+        })?;
+        let file_metadata = path.metadata()?;
+        let mut record = RepomdRecord {
+            metadata_type: name.into(),
+            location_href: Path::new("repodata").join(file_name),
+            base_path: path
+                .parent()
+                .filter(|dir| dir.ends_with("repodata"))
+                .and_then(Path::parent)
+                .map(Path::to_owned),
+            timestamp: file_metadata.mtime(),
+            size: Some(file_metadata.size()),
+            checksum: utils::checksum_file(path, checksum_type)?,
+            ..RepomdRecord::default()
+        };
+        let zchunk_header = ZchunkHeader::read_from(std::fs::File::open(path)?)?;
+        let contents = std::io::BufReader::new(std::fs::File::open(path)?);
+        record.fill_contents(zchunk_header, Box::new(contents), checksum_type)?;
+        Ok(record)
+    }
+
+    /// Create the record of a metadata file which will be stored at `href`, from its contents. They're
+    /// read into memory, and the timestamp is the current time.
+    pub fn from_reader<R: Read>(
+        name: impl Into<MetadataType>,
+        href: &Path,
+        mut reader: R,
+        checksum_type: ChecksumType,
+    ) -> Result<Self, MetadataError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let mut record = RepomdRecord {
+            metadata_type: name.into(),
+            location_href: href.to_owned(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64),
+            size: Some(bytes.len() as u64),
+            checksum: utils::checksum_bytes(&bytes, checksum_type)?,
+            ..RepomdRecord::default()
+        };
+        let zchunk_header = ZchunkHeader::read_from(bytes.as_slice())?;
+        record.fill_contents(
+            zchunk_header,
+            Box::new(std::io::Cursor::new(bytes)),
+            checksum_type,
+        )?;
+        Ok(record)
+    }
+
+    /// Recompute the size, checksums and timestamp from the file of the record in the repository at `base`,
+    /// e.g. after the file was replaced by a new version with the same name.
+    pub fn refresh(
+        &mut self,
+        base: &Path,
+        checksum_type: ChecksumType,
+    ) -> Result<(), MetadataError> {
+        let current = RepomdRecord::from_file(
+            self.metadata_type.clone(),
+            &base.join(&self.location_href),
+            checksum_type,
+        )?;
+        self.timestamp = current.timestamp;
+        self.size = current.size;
+        self.checksum = current.checksum;
+        self.open_size = current.open_size;
+        self.open_checksum = current.open_checksum;
+        self.header_size = current.header_size;
+        self.header_checksum = current.header_checksum;
+        Ok(())
+    }
+
+    /// Check that the file of the record in the repository at `base` has the size and checksums the record
+    /// says it has, including the open checksum of its decompressed contents if the record has one.
+    pub fn verify(&self, base: &Path) -> Result<(), MetadataError> {
+        let path = base.join(&self.location_href);
+        let href = self.location_href.display().to_string();
+        let size = path.metadata()?.size();
+        if let Some(expected) = self.size.filter(|expected| *expected != size) {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "{} is {} bytes, but repomd.xml says {}",
+                href, size, expected
+            )));
+        }
+
+        let mismatch = |expected: &Checksum, actual: &Checksum| -> Result<_, MetadataError> {
+            Ok(MetadataError::ChecksumMismatchError(
+                href.clone(),
+                expected.to_values()?.1.to_owned(),
+                actual.to_values()?.1.to_owned(),
+            ))
+        };
+        let actual = utils::checksum_file(&path, self.checksum.checksum_type())?;
+        if actual != self.checksum {
+            return Err(mismatch(&self.checksum, &actual)?);
+        }
+        if let Some(expected) = &self.open_checksum {
+            match utils::checksum_inner_file(&path, expected.checksum_type())? {
+                Some(actual) if actual != *expected => return Err(mismatch(expected, &actual)?),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the file of the record in the repository at `base` for reading, decompressed if it's
+    /// compressed, e.g. for metadata types this crate doesn't parse itself.
+    ///
+    /// The file is checked against the size and checksum the record gives it, and its decompressed
+    /// contents against the open size and checksum, as it's read. Once the end is reached, a read fails
+    /// with an I/O error (which converts into the [`MetadataError::ChecksumMismatchError`] or
+    /// [`MetadataError::InconsistentMetadataError`]) if they don't match, so the contents mustn't be
+    /// trusted until all of them have been read. Opening it fails with
+    /// [`MetadataError::UnsupportedChecksumTypeError`] if either checksum is of a type which can't be
+    /// computed. See [`RepositoryReader::open_record()`](crate::RepositoryReader::open_record) for
+    /// repositories in other storage.
+    pub fn open(&self, base: &Path) -> Result<Box<dyn BufRead + Send>, MetadataError> {
+        utils::verified_record_reader(&LocalStorage, base, self, false)
+    }
+
     pub fn fill(&mut self, checksum_type: ChecksumType) -> Result<(), MetadataError> {
         let file_path = self
             .base_path
@@ -1061,6 +2694,26 @@ impl RepomdRecord {
 
         Ok(())
     }
+
+    /// Fill in what's known about the contents of a (possibly compressed) file: its decompressed size and
+    /// checksum, and the size and checksum of its header if it's a zchunk file.
+    fn fill_contents(
+        &mut self,
+        zchunk_header: Option<ZchunkHeader>,
+        contents: Box<dyn Read + Send>,
+        checksum_type: ChecksumType,
+    ) -> Result<(), MetadataError> {
+        (self.open_size, self.open_checksum) =
+            match utils::measure_decompressed(contents, checksum_type)? {
+                Some((size, checksum)) => (Some(size), Some(checksum)),
+                None => (None, None),
+            };
+        if let Some(header) = zchunk_header {
+            self.header_size = Some(header.len as u64);
+            self.header_checksum = Some(header.header_checksum());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
@@ -1086,10 +2739,223 @@ pub struct UpdateRecord {
     pub pkglist: Vec<UpdateCollection>,
 }
 
+impl UpdateRecord {
+    /// The packages of every collection in the pkglist, in the order they appear
+    pub fn packages(&self) -> impl Iterator<Item = &UpdateCollectionPackage> {
+        self.pkglist
+            .iter()
+            .flat_map(|collection| collection.packages.iter())
+    }
+
+    /// Whether the advisory applies to a system with the `installed` packages: whether it updates any of
+    /// them, with a newer version of a package of the same name and architecture.
+    pub fn applies_to<'a>(&self, installed: impl IntoIterator<Item = &'a Package>) -> bool {
+        let installed: Vec<&Package> = installed.into_iter().collect();
+        self.packages()
+            .filter(|update| update.arch != "src")
+            .any(|update| {
+                let older = Requirement {
+                    name: update.name.clone(),
+                    flags: Some("LT".to_owned()),
+                    epoch: Some(update.epoch.clone()),
+                    version: Some(update.version.clone()),
+                    release: Some(update.release.clone()),
+                    ..Requirement::default()
+                };
+                installed.iter().any(|package| {
+                    package.arch == update.arch
+                        && capability::satisfies(&package.self_provides()[0], &older)
+                })
+            })
+    }
+
+    /// Start writing an advisory with an [`UpdateRecordBuilder`].
+    pub fn builder() -> UpdateRecordBuilder {
+        UpdateRecordBuilder::default()
+    }
+}
+
+/// The severities advisories are given by Fedora, RHEL and SUSE, which dnf can filter on. They're
+/// compared case-insensitively, as SUSE writes them in lowercase.
+const ADVISORY_SEVERITIES: &[&str] = &["None", "Low", "Moderate", "Important", "Critical"];
+
+/// Builds an [`UpdateRecord`], making sure that it's complete enough for dnf to make use of it.
+///
+/// The id, title, from, type and issued date are required, and [`UpdateRecordBuilder::build()`] fails
+/// with [`MetadataError::MissingFieldsError`] listing each of them which wasn't set. It also checks that
+/// the dates are in a format dnf understands (`YYYY-MM-DD HH:MM:SS`, `YYYY-MM-DD` or a Unix timestamp) and
+/// that the severity, if there is one, is one of `None`, `Low`, `Moderate`, `Important` or `Critical`.
+///
+/// The status defaults to `final`, the version to `1`, and the updated date to the issued date.
+#[derive(Clone, Debug, Default)]
+pub struct UpdateRecordBuilder {
+    record: UpdateRecord,
+}
+
+impl UpdateRecordBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.record.id = id.into();
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.record.title = title.into();
+        self
+    }
+
+    /// The contact address of whoever issued the advisory
+    pub fn from(mut self, from: impl Into<String>) -> Self {
+        self.record.from = from.into();
+        self
+    }
+
+    /// `security`, `bugfix`, `enhancement` or `newpackage`
+    pub fn update_type(mut self, update_type: impl Into<String>) -> Self {
+        self.record.update_type = update_type.into();
+        self
+    }
+
+    pub fn status(mut self, status: impl Into<String>) -> Self {
+        self.record.status = status.into();
+        self
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.record.version = version.into();
+        self
+    }
+
+    pub fn issued_date(mut self, issued_date: impl Into<String>) -> Self {
+        self.record.issued_date = Some(issued_date.into());
+        self
+    }
+
+    pub fn updated_date(mut self, updated_date: impl Into<String>) -> Self {
+        self.record.updated_date = Some(updated_date.into());
+        self
+    }
+
+    pub fn rights(mut self, rights: impl Into<String>) -> Self {
+        self.record.rights = rights.into();
+        self
+    }
+
+    pub fn release(mut self, release: impl Into<String>) -> Self {
+        self.record.release = release.into();
+        self
+    }
+
+    pub fn pushcount(mut self, pushcount: impl Into<String>) -> Self {
+        self.record.pushcount = Some(pushcount.into());
+        self
+    }
+
+    pub fn severity(mut self, severity: impl Into<String>) -> Self {
+        self.record.severity = severity.into();
+        self
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.record.summary = summary.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.record.description = description.into();
+        self
+    }
+
+    pub fn solution(mut self, solution: impl Into<String>) -> Self {
+        self.record.solution = solution.into();
+        self
+    }
+
+    pub fn reference(mut self, reference: UpdateReference) -> Self {
+        self.record.references.push(reference);
+        self
+    }
+
+    pub fn collection(mut self, collection: UpdateCollection) -> Self {
+        self.record.pkglist.push(collection);
+        self
+    }
+
+    /// Add a collection of `packages` to the pkglist, e.g. `("F35", "Fedora 35", ...)`.
+    pub fn packages<'a>(
+        self,
+        shortname: impl Into<String>,
+        name: impl Into<String>,
+        packages: impl IntoIterator<Item = &'a Package>,
+    ) -> Self {
+        self.collection(UpdateCollection {
+            name: name.into(),
+            shortname: Some(shortname.into()),
+            packages: packages.into_iter().map(Into::into).collect(),
+            module: None,
+        })
+    }
+
+    /// Check that the required fields are set and that the dates and severity are valid, and return the
+    /// advisory.
+    pub fn build(self) -> Result<UpdateRecord, MetadataError> {
+        let mut record = self.record;
+        let missing: Vec<&'static str> = [
+            ("id", record.id.is_empty()),
+            ("title", record.title.is_empty()),
+            ("from", record.from.is_empty()),
+            ("update_type", record.update_type.is_empty()),
+            ("issued_date", record.issued_date.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(field, missing)| missing.then_some(field))
+        .collect();
+        if !missing.is_empty() {
+            return Err(MetadataError::MissingFieldsError(missing));
+        }
+
+        if record.updated_date.is_none() {
+            record.updated_date = record.issued_date.clone();
+        }
+        for (field, date) in [
+            ("issued_date", &record.issued_date),
+            ("updated_date", &record.updated_date),
+        ] {
+            if let Some(date) = date
+                .as_deref()
+                .filter(|d| !utils::is_valid_advisory_date(d))
+            {
+                return Err(MetadataError::InvalidFieldError(field, date.to_owned()));
+            }
+        }
+        if !record.severity.is_empty()
+            && !ADVISORY_SEVERITIES
+                .iter()
+                .any(|severity| severity.eq_ignore_ascii_case(&record.severity))
+        {
+            return Err(MetadataError::InvalidFieldError(
+                "severity",
+                record.severity.clone(),
+            ));
+        }
+        if record.status.is_empty() {
+            record.status = "final".to_owned();
+        }
+        if record.version.is_empty() {
+            record.version = "1".to_owned();
+        }
+        Ok(record)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct UpdateCollection {
     pub name: String,
-    pub shortname: String,
+    /// The `short` attribute, which not every producer sets
+    pub shortname: Option<String>,
     pub packages: Vec<UpdateCollectionPackage>,
     pub module: Option<UpdateCollectionModule>,
 }
@@ -1117,6 +2983,30 @@ pub struct UpdateCollectionPackage {
     pub version: String,
 }
 
+impl From<&Package> for UpdateCollectionPackage {
+    fn from(package: &Package) -> Self {
+        UpdateCollectionPackage {
+            epoch: match package.evr.epoch.as_str() {
+                "" => "0".to_owned(),
+                epoch => epoch.to_owned(),
+            },
+            filename: package
+                .location_href
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+            name: package.name.clone(),
+            release: package.evr.release.clone(),
+            src: package.rpm_sourcerpm.clone(),
+            arch: package.arch.clone(),
+            checksum: Some(package.checksum.clone()).filter(|c| !matches!(c, Checksum::Empty)),
+            version: package.evr.version.clone(),
+            ..UpdateCollectionPackage::default()
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct UpdateCollectionModule {
     pub name: String,
@@ -1125,3 +3015,85 @@ pub struct UpdateCollectionModule {
     pub context: String,
     pub arch: String,
 }
+
+/// A product of a SUSE repository, from `products.xml`, e.g. SUSE Linux Enterprise Server 15 SP4.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Product {
+    pub vendor: String,
+    pub name: String,
+    pub evr: EVR,
+    pub arch: String,
+    pub summary: String,
+    pub description: String,
+}
+
+/// A pattern of a SUSE repository, from `patterns.xml`: a set of packages to install together, given
+/// by its requirements, much like a comps group.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Pattern {
+    pub name: String,
+    pub evr: EVR,
+    pub arch: String,
+    /// The summary, and its translations
+    pub summary: Vec<LocalizedText>,
+    /// The description, and its translations
+    pub description: Vec<LocalizedText>,
+    /// The category the pattern is listed under, and its translations
+    pub category: Vec<LocalizedText>,
+    /// Whether installers show the pattern to users
+    pub uservisible: bool,
+    pub icon: Option<String>,
+    /// Where installers list the pattern among the others, a number as a string
+    pub order: Option<String>,
+
+    pub rpm_provides: Vec<Requirement>,
+    pub rpm_requires: Vec<Requirement>,
+    pub rpm_conflicts: Vec<Requirement>,
+    pub rpm_obsoletes: Vec<Requirement>,
+    pub rpm_suggests: Vec<Requirement>,
+    pub rpm_enhances: Vec<Requirement>,
+    pub rpm_recommends: Vec<Requirement>,
+    pub rpm_supplements: Vec<Requirement>,
+}
+
+impl Pattern {
+    /// The untranslated summary.
+    pub fn summary(&self) -> &str {
+        LocalizedText::untranslated(&self.summary)
+    }
+
+    /// The untranslated description.
+    pub fn description(&self) -> &str {
+        LocalizedText::untranslated(&self.description)
+    }
+
+    /// The untranslated category.
+    pub fn category(&self) -> &str {
+        LocalizedText::untranslated(&self.category)
+    }
+}
+
+/// Text in the language `lang`, or untranslated if `lang` isn't set.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct LocalizedText {
+    pub lang: Option<String>,
+    pub text: String,
+}
+
+impl LocalizedText {
+    pub fn new(lang: Option<&str>, text: &str) -> Self {
+        Self {
+            lang: lang.map(str::to_owned),
+            text: text.to_owned(),
+        }
+    }
+
+    /// The text of `texts` without a language, or in English if there's none, or `""` if neither is there.
+    fn untranslated(texts: &[LocalizedText]) -> &str {
+        texts
+            .iter()
+            .find(|t| t.lang.is_none())
+            .or_else(|| texts.iter().find(|t| t.lang.as_deref() == Some("en")))
+            .map_or("", |t| t.text.as_str())
+    }
+}
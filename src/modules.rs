@@ -0,0 +1,809 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{utils, MetadataError};
+
+const DOCUMENT_STREAM: &str = "modulemd";
+const DOCUMENT_DEFAULTS: &str = "modulemd-defaults";
+const DOCUMENT_OBSOLETES: &str = "modulemd-obsoletes";
+
+/// The documents of a `modules.yaml` file, in the order they appear in it.
+///
+/// Module streams are kept as they were read, with accessors for the fields identifying them. Defaults and
+/// obsoletes are modelled completely and written from their fields, and documents of any other type are
+/// kept verbatim.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Modules {
+    documents: Vec<ModuleDocument>,
+}
+
+/// A document of a `modules.yaml` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleDocument {
+    /// `modulemd`, a stream of a module
+    Stream(ModuleStream),
+    /// `modulemd-defaults`, the default stream and profiles of a module
+    Defaults(ModuleDefaults),
+    /// `modulemd-obsoletes`, the end of life of a module stream and what replaces it
+    Obsoletes(ModuleObsoletes),
+    /// Any other type of document, e.g. `modulemd-translations`
+    Unknown {
+        /// The value of `document:`
+        document: String,
+        /// The document as it was read, without the `---` and `...` markers
+        yaml: String,
+    },
+}
+
+/// A `modulemd` document, describing a build of a module stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleStream {
+    name: String,
+    stream: String,
+    version: u64,
+    context: String,
+    arch: String,
+    summary: String,
+    rpm_artifacts: Vec<String>,
+    yaml: String,
+}
+
+impl ModuleStream {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stream(&self) -> &str {
+        &self.stream
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    /// The NEVRAs of the binary and source packages built for the stream.
+    pub fn rpm_artifacts(&self) -> &[String] {
+        &self.rpm_artifacts
+    }
+
+    /// The document as it was read, without the `---` and `...` markers.
+    pub fn yaml(&self) -> &str {
+        &self.yaml
+    }
+
+    /// `name:stream:version:context:arch`
+    pub fn nsvca(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}",
+            self.name, self.stream, self.version, self.context, self.arch
+        )
+    }
+}
+
+/// A `modulemd-defaults` document (version 1).
+///
+/// - `module` - The module the defaults apply to.
+/// - `stream` - The stream enabled by default, if any.
+/// - `profiles` - The profiles installed by default, by stream.
+/// - `modified` - When the defaults were last changed, as `YYYYMMDDHHMM`. Used to pick between defaults of
+///   the same module from different repositories.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleDefaults {
+    pub module: String,
+    pub stream: Option<String>,
+    pub profiles: BTreeMap<String, Vec<String>>,
+    pub modified: Option<u64>,
+}
+
+/// A `modulemd-obsoletes` document (version 1).
+///
+/// - `module` and `stream` - The module stream which is obsoleted.
+/// - `context` - Only builds of the stream with this context are obsoleted, if it's set.
+/// - `modified` - When the document was last changed, e.g. `2022-01-24T08:54Z`.
+/// - `reset` - Whether this cancels the previous obsoletes of the stream instead.
+/// - `message` - Why the stream is obsoleted, for users.
+/// - `eol_date` - When the stream reaches its end of life, if it's not already obsoleted.
+/// - `obsoleted_by` - The module and stream replacing it, if any.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleObsoletes {
+    pub module: String,
+    pub stream: String,
+    pub context: Option<String>,
+    pub modified: String,
+    pub reset: bool,
+    pub message: String,
+    pub eol_date: Option<String>,
+    pub obsoleted_by: Option<(String, String)>,
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a `modules.yaml` file, which may be compressed.
+    pub fn from_file(path: &Path) -> Result<Self, MetadataError> {
+        Self::from_reader(utils::reader_from_file(path)?)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, MetadataError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        contents.parse()
+    }
+
+    pub fn documents(&self) -> &[ModuleDocument] {
+        &self.documents
+    }
+
+    pub fn documents_mut(&mut self) -> &mut Vec<ModuleDocument> {
+        &mut self.documents
+    }
+
+    pub fn streams(&self) -> impl Iterator<Item = &ModuleStream> {
+        self.documents.iter().filter_map(|document| match document {
+            ModuleDocument::Stream(stream) => Some(stream),
+            _ => None,
+        })
+    }
+
+    pub fn defaults(&self) -> impl Iterator<Item = &ModuleDefaults> {
+        self.documents.iter().filter_map(|document| match document {
+            ModuleDocument::Defaults(defaults) => Some(defaults),
+            _ => None,
+        })
+    }
+
+    pub fn obsoletes(&self) -> impl Iterator<Item = &ModuleObsoletes> {
+        self.documents.iter().filter_map(|document| match document {
+            ModuleDocument::Obsoletes(obsoletes) => Some(obsoletes),
+            _ => None,
+        })
+    }
+
+    /// The defaults of `module`. If there are several, the most recently modified ones.
+    pub fn defaults_of(&self, module: &str) -> Option<&ModuleDefaults> {
+        self.defaults()
+            .filter(|defaults| defaults.module == module)
+            .max_by_key(|defaults| defaults.modified)
+    }
+
+    /// The stream of `module` which is enabled by default, if it has one.
+    pub fn default_stream(&self, module: &str) -> Option<&str> {
+        self.defaults_of(module)?.stream.as_deref()
+    }
+
+    /// The profiles of `stream` of `module` which are installed by default, if there are defaults for it.
+    pub fn default_profiles(&self, module: &str, stream: &str) -> Option<&[String]> {
+        self.defaults_of(module)?
+            .profiles
+            .get(stream)
+            .map(Vec::as_slice)
+    }
+
+    /// The most recently modified obsoletes of `stream` of `module`, for builds with `context` or for all
+    /// builds of the stream. `None` if there are none, or if the most recent one resets them.
+    pub fn obsoletes_of(
+        &self,
+        module: &str,
+        stream: &str,
+        context: Option<&str>,
+    ) -> Option<&ModuleObsoletes> {
+        self.obsoletes()
+            .filter(|obsoletes| {
+                obsoletes.module == module
+                    && obsoletes.stream == stream
+                    && (obsoletes.context.is_none() || obsoletes.context.as_deref() == context)
+            })
+            .max_by(|a, b| a.modified.cmp(&b.modified))
+            .filter(|obsoletes| !obsoletes.reset)
+    }
+
+    /// Write the documents as a `modules.yaml` file.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), MetadataError> {
+        writer.write_all(self.to_yaml_string().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn to_yaml_string(&self) -> String {
+        let mut out = String::new();
+        for document in &self.documents {
+            out.push_str("---\n");
+            match document {
+                ModuleDocument::Stream(stream) => out.push_str(&stream.yaml),
+                ModuleDocument::Unknown { yaml, .. } => out.push_str(yaml),
+                ModuleDocument::Defaults(defaults) => write_defaults(&mut out, defaults),
+                ModuleDocument::Obsoletes(obsoletes) => write_obsoletes(&mut out, obsoletes),
+            }
+            if !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str("...\n");
+        }
+        out
+    }
+}
+
+impl FromStr for Modules {
+    type Err = MetadataError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut documents = Vec::new();
+        for (first_line, yaml) in split_documents(contents) {
+            let root = YamlParser::new(&yaml, first_line).parse()?;
+            let document = match root.get("document").and_then(Yaml::as_str) {
+                Some(document) => document.to_owned(),
+                None => continue,
+            };
+            let data = root.get("data").unwrap_or(&Yaml::Null);
+            documents.push(match &*document {
+                DOCUMENT_STREAM => ModuleDocument::Stream(parse_stream(data, yaml)?),
+                DOCUMENT_DEFAULTS => ModuleDocument::Defaults(parse_defaults(data)?),
+                DOCUMENT_OBSOLETES => ModuleDocument::Obsoletes(parse_obsoletes(data)?),
+                _ => ModuleDocument::Unknown { document, yaml },
+            });
+        }
+        Ok(Self { documents })
+    }
+}
+
+/// Split the contents of a `modules.yaml` file into its documents, with the number of the line each starts at.
+fn split_documents(contents: &str) -> Vec<(usize, String)> {
+    let mut documents = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (number, line) in contents.lines().enumerate() {
+        let marker = line.trim_end();
+        if marker == "---" || marker.starts_with("--- ") || marker == "..." {
+            documents.extend(current.take());
+            if marker != "..." {
+                current = Some((number + 2, String::new()));
+            }
+            continue;
+        }
+        let (_, yaml) = current.get_or_insert_with(|| (number + 1, String::new()));
+        yaml.push_str(line);
+        yaml.push('\n');
+    }
+    documents.extend(current);
+    documents.retain(|(_, yaml)| !yaml.trim().is_empty());
+    documents
+}
+
+fn required<'a>(data: &'a Yaml, field: &'static str) -> Result<&'a str, MetadataError> {
+    data.get(field)
+        .and_then(Yaml::as_str)
+        .ok_or(MetadataError::MissingFieldError(field))
+}
+
+fn optional(data: &Yaml, field: &str) -> Option<String> {
+    data.get(field).and_then(Yaml::as_str).map(str::to_owned)
+}
+
+fn string_list(data: Option<&Yaml>) -> Vec<String> {
+    match data {
+        Some(Yaml::Seq(items)) => items
+            .iter()
+            .filter_map(Yaml::as_str)
+            .map(str::to_owned)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_stream(data: &Yaml, yaml: String) -> Result<ModuleStream, MetadataError> {
+    // streams built from scratch may not have been assigned a version or context yet
+    let version = match data.get("version").and_then(Yaml::as_str) {
+        Some(version) => version.parse()?,
+        None => 0,
+    };
+    Ok(ModuleStream {
+        name: required(data, "name")?.to_owned(),
+        stream: required(data, "stream")?.to_owned(),
+        version,
+        context: optional(data, "context").unwrap_or_default(),
+        arch: optional(data, "arch").unwrap_or_default(),
+        summary: optional(data, "summary").unwrap_or_default(),
+        rpm_artifacts: string_list(data.get("artifacts").and_then(|a| a.get("rpms"))),
+        yaml,
+    })
+}
+
+fn parse_defaults(data: &Yaml) -> Result<ModuleDefaults, MetadataError> {
+    let mut profiles = BTreeMap::new();
+    if let Some(Yaml::Map(entries)) = data.get("profiles") {
+        for (stream, names) in entries {
+            profiles.insert(stream.clone(), string_list(Some(names)));
+        }
+    }
+    let modified = match data.get("modified").and_then(Yaml::as_str) {
+        Some(modified) => Some(modified.parse()?),
+        None => None,
+    };
+    Ok(ModuleDefaults {
+        module: required(data, "module")?.to_owned(),
+        stream: optional(data, "stream"),
+        profiles,
+        modified,
+    })
+}
+
+fn parse_obsoletes(data: &Yaml) -> Result<ModuleObsoletes, MetadataError> {
+    let obsoleted_by = match data.get("obsoleted_by") {
+        Some(by) => Some((
+            required(by, "module")?.to_owned(),
+            required(by, "stream")?.to_owned(),
+        )),
+        None => None,
+    };
+    let reset = match data.get("reset").and_then(Yaml::as_str) {
+        Some("true") => true,
+        Some("false") | None => false,
+        Some(other) => return Err(MetadataError::InvalidFieldError("reset", other.to_owned())),
+    };
+    Ok(ModuleObsoletes {
+        module: required(data, "module")?.to_owned(),
+        stream: required(data, "stream")?.to_owned(),
+        context: optional(data, "context"),
+        modified: required(data, "modified")?.to_owned(),
+        reset,
+        message: optional(data, "message").unwrap_or_default(),
+        eol_date: optional(data, "eol_date"),
+        obsoleted_by,
+    })
+}
+
+fn write_defaults(out: &mut String, defaults: &ModuleDefaults) {
+    out.push_str("document: modulemd-defaults\nversion: 1\ndata:\n");
+    out.push_str(&format!("  module: {}\n", scalar(&defaults.module)));
+    if let Some(modified) = defaults.modified {
+        out.push_str(&format!("  modified: {}\n", modified));
+    }
+    if let Some(stream) = &defaults.stream {
+        out.push_str(&format!("  stream: {}\n", scalar(stream)));
+    }
+    if !defaults.profiles.is_empty() {
+        out.push_str("  profiles:\n");
+        for (stream, names) in &defaults.profiles {
+            let names: Vec<String> = names.iter().map(|name| scalar(name)).collect();
+            out.push_str(&format!("    {}: [{}]\n", scalar(stream), names.join(", ")));
+        }
+    }
+}
+
+fn write_obsoletes(out: &mut String, obsoletes: &ModuleObsoletes) {
+    out.push_str("document: modulemd-obsoletes\nversion: 1\ndata:\n");
+    out.push_str(&format!("  modified: {}\n", scalar(&obsoletes.modified)));
+    out.push_str(&format!("  module: {}\n", scalar(&obsoletes.module)));
+    out.push_str(&format!("  stream: {}\n", scalar(&obsoletes.stream)));
+    if let Some(context) = &obsoletes.context {
+        out.push_str(&format!("  context: {}\n", scalar(context)));
+    }
+    if obsoletes.reset {
+        out.push_str("  reset: true\n");
+    }
+    if let Some(eol_date) = &obsoletes.eol_date {
+        out.push_str(&format!("  eol_date: {}\n", scalar(eol_date)));
+    }
+    out.push_str(&format!("  message: {}\n", scalar(&obsoletes.message)));
+    if let Some((module, stream)) = &obsoletes.obsoleted_by {
+        out.push_str("  obsoleted_by:\n");
+        out.push_str(&format!("    module: {}\n", scalar(module)));
+        out.push_str(&format!("    stream: {}\n", scalar(stream)));
+    }
+}
+
+/// `value` as a YAML scalar, quoted unless it would be read back as the same string without quotes.
+fn scalar(value: &str) -> String {
+    let plain = value.starts_with(|c: char| c.is_ascii_alphabetic())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+/:@ ".contains(c))
+        && !value.contains(": ")
+        && !value.ends_with([':', ' '])
+        && !matches!(
+            &*value.to_ascii_lowercase(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "null"
+        );
+    if plain {
+        return value.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The subset of YAML used by modulemd documents: block mappings and sequences, flow sequences of scalars,
+/// quoted, plain and block scalars. Scalars are kept as strings, without resolving their type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Yaml {
+    Null,
+    Scalar(String),
+    Seq(Vec<Yaml>),
+    Map(Vec<(String, Yaml)>),
+}
+
+impl Yaml {
+    fn get(&self, key: &str) -> Option<&Yaml> {
+        match self {
+            Yaml::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Yaml::Scalar(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Line<'a> {
+    number: usize,
+    /// The index of the line in `YamlParser::raw`
+    index: usize,
+    indent: usize,
+    content: &'a str,
+}
+
+struct YamlParser<'a> {
+    raw: Vec<&'a str>,
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> YamlParser<'a> {
+    fn new(yaml: &'a str, first_line: usize) -> Self {
+        let raw: Vec<&str> = yaml.lines().collect();
+        let lines = raw
+            .iter()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let content = strip_comment(line).trim_end();
+                let trimmed = content.trim_start();
+                if trimmed.is_empty() {
+                    return None;
+                }
+                Some(Line {
+                    number: first_line + index,
+                    index,
+                    indent: content.len() - trimmed.len(),
+                    content: trimmed,
+                })
+            })
+            .collect();
+        Self { raw, lines, pos: 0 }
+    }
+
+    fn parse(mut self) -> Result<Yaml, MetadataError> {
+        if self.lines.is_empty() {
+            return Ok(Yaml::Null);
+        }
+        let indent = self.lines[0].indent;
+        let root = self.node(indent)?;
+        match self.lines.get(self.pos) {
+            Some(line) => Err(self.error(line.number, "unexpected indentation")),
+            None => Ok(root),
+        }
+    }
+
+    fn error(&self, line: usize, message: &str) -> MetadataError {
+        MetadataError::YamlParseError(line, message.to_owned())
+    }
+
+    fn node(&mut self, indent: usize) -> Result<Yaml, MetadataError> {
+        match is_seq_item(self.lines[self.pos].content) {
+            true => self.seq(indent),
+            false => self.map(indent),
+        }
+    }
+
+    /// The value of a key or sequence item whose line ends after the `:` or `-`.
+    fn nested(&mut self, indent: usize, seq_allowed: bool) -> Result<Yaml, MetadataError> {
+        match self.lines.get(self.pos) {
+            Some(next) if next.indent > indent => {
+                let indent = next.indent;
+                self.node(indent)
+            }
+            // sequences of mappings are often not indented more than their key
+            Some(next) if seq_allowed && next.indent == indent && is_seq_item(next.content) => {
+                self.seq(indent)
+            }
+            _ => Ok(Yaml::Null),
+        }
+    }
+
+    fn seq(&mut self, indent: usize) -> Result<Yaml, MetadataError> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent != indent || !is_seq_item(line.content) {
+                break;
+            }
+            let item = line.content[1..].trim_start();
+            if item.is_empty() {
+                self.pos += 1;
+                items.push(self.nested(indent, false)?);
+            } else if split_key(item).is_some() {
+                // `- key: value` starts a mapping indented to where `key` is
+                let offset = line.content.len() - item.len();
+                let line = &mut self.lines[self.pos];
+                line.indent += offset;
+                line.content = item;
+                let indent = line.indent;
+                items.push(self.map(indent)?);
+            } else {
+                self.pos += 1;
+                items.push(self.inline(item, self.lines[self.pos - 1].number, indent)?);
+            }
+        }
+        Ok(Yaml::Seq(items))
+    }
+
+    fn map(&mut self, indent: usize) -> Result<Yaml, MetadataError> {
+        let mut entries = Vec::new();
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.indent < indent || (line.indent == indent && is_seq_item(line.content)) {
+                break;
+            }
+            let number = line.number;
+            if line.indent > indent {
+                return Err(self.error(number, "unexpected indentation"));
+            }
+            let (key, rest) =
+                split_key(line.content).ok_or_else(|| self.error(number, "expected a key"))?;
+            let key = unquote(key).map_err(|message| self.error(number, message))?;
+            self.pos += 1;
+            let value = if rest.is_empty() {
+                self.nested(indent, true)?
+            } else if rest.starts_with(['|', '>']) {
+                self.block_scalar(rest, indent)
+            } else {
+                self.inline(rest, number, indent)?
+            };
+            entries.push((key, value));
+        }
+        Ok(Yaml::Map(entries))
+    }
+
+    /// A value on the same line as its key or `-`, which is continued on any following lines indented more
+    /// than `indent`.
+    fn inline(&mut self, value: &str, number: usize, indent: usize) -> Result<Yaml, MetadataError> {
+        if let Some(items) = value.strip_prefix('[') {
+            let items = items
+                .strip_suffix(']')
+                .ok_or_else(|| self.error(number, "unterminated flow sequence"))?;
+            return split_flow(items)
+                .into_iter()
+                .map(|item| unquote(item).map(Yaml::Scalar))
+                .collect::<Result<_, _>>()
+                .map(Yaml::Seq)
+                .map_err(|message| self.error(number, message));
+        }
+        if value == "{}" {
+            return Ok(Yaml::Map(Vec::new()));
+        }
+        let mut value = value.to_owned();
+        while let Some(next) = self.lines.get(self.pos) {
+            if next.indent <= indent {
+                break;
+            }
+            // a plain scalar can't contain `: `, so this is a mapping where there can't be one
+            if split_key(next.content).is_some() {
+                return Err(self.error(next.number, "unexpected indentation"));
+            }
+            value.push(' ');
+            value.push_str(next.content);
+            self.pos += 1;
+        }
+        match &*value {
+            "~" | "null" => Ok(Yaml::Null),
+            _ => unquote(&value)
+                .map(Yaml::Scalar)
+                .map_err(|message| self.error(number, message)),
+        }
+    }
+
+    /// A `|` (literal) or `>` (folded) block scalar, with an optional `-` or `+` chomping indicator.
+    fn block_scalar(&mut self, header: &str, indent: usize) -> Yaml {
+        let literal = header.starts_with('|');
+        let chomping = header[1..].trim();
+        // the raw lines, including the blank ones, up to the next line which isn't part of the scalar
+        let start = self.lines[self.pos - 1].index + 1;
+        while self
+            .lines
+            .get(self.pos)
+            .is_some_and(|line| line.indent > indent)
+        {
+            self.pos += 1;
+        }
+        let end = self
+            .lines
+            .get(self.pos)
+            .map_or(self.raw.len(), |line| line.index);
+        let body = &self.raw[start..end];
+        let block_indent = body
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        let lines: Vec<&str> = body
+            .iter()
+            .map(|line| {
+                line.get(block_indent..)
+                    .unwrap_or("")
+                    .trim_end_matches('\r')
+            })
+            .collect();
+
+        let mut text = String::new();
+        // whether the last non-empty line was more indented than the block, whose line breaks aren't folded
+        let mut previous_indented = false;
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                let previous = lines[index - 1];
+                let indented = line.starts_with(' ');
+                if literal || previous_indented || (!line.is_empty() && indented) {
+                    text.push('\n');
+                } else if line.is_empty() {
+                    // an empty line is a line break of its own, and the one before it is folded away
+                    // unless the empty lines are followed by a more indented line or the end of the block
+                    let next = lines[index..].iter().find(|line| !line.is_empty());
+                    if !previous.is_empty() && next.is_some_and(|line| !line.starts_with(' ')) {
+                        continue;
+                    }
+                    text.push('\n');
+                } else if previous.is_empty() {
+                    text.push('\n');
+                } else {
+                    text.push(' ');
+                }
+            }
+            if !line.is_empty() {
+                previous_indented = line.starts_with(' ');
+            }
+            text.push_str(line);
+        }
+        match chomping {
+            "-" => text.truncate(text.trim_end_matches('\n').len()),
+            "+" => text.push('\n'),
+            _ => {
+                text.truncate(text.trim_end_matches('\n').len());
+                text.push('\n');
+            }
+        }
+        Yaml::Scalar(text)
+    }
+}
+
+fn is_seq_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// `content` with any comment removed. A `#` starts a comment at the start of a line or after whitespace,
+/// outside of quotes.
+fn strip_comment(content: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in content.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if (c == '"' || c == '\'')
+                && (previous == ' ' || previous == '[' || previous == ',') =>
+            {
+                quote = Some(c)
+            }
+            None if c == '#' && previous.is_whitespace() => return &content[..index],
+            None => (),
+        }
+        previous = c;
+    }
+    content
+}
+
+/// The key and the rest of a `key: value` or `key:` line.
+fn split_key(content: &str) -> Option<(&str, &str)> {
+    if content.starts_with(['"', '\'']) {
+        let quote = content.chars().next().unwrap();
+        let end = content[1..].find(quote)? + 1;
+        let rest = content[end + 1..].strip_prefix(':')?;
+        return match rest.is_empty() || rest.starts_with(' ') {
+            true => Some((&content[..=end], rest.trim())),
+            false => None,
+        };
+    }
+    if content.starts_with(['[', '{']) {
+        return None;
+    }
+    match content.find(": ") {
+        Some(index) => Some((&content[..index], content[index + 2..].trim())),
+        None => content.strip_suffix(':').map(|key| (key, "")),
+    }
+}
+
+/// The items of a flow sequence, without the brackets.
+fn split_flow(items: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in items.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ',' => {
+                result.push(items[start..index].trim());
+                start = index + 1;
+            }
+            None => (),
+        }
+    }
+    let last = items[start..].trim();
+    if !last.is_empty() {
+        result.push(last);
+    }
+    result
+}
+
+/// The value of a plain, single-quoted or double-quoted scalar.
+fn unquote(value: &str) -> Result<String, &'static str> {
+    let value = value.trim();
+    if let Some(inner) = value.strip_prefix('\'') {
+        let inner = inner
+            .strip_suffix('\'')
+            .ok_or("unterminated quoted scalar")?;
+        return Ok(inner.replace("''", "'"));
+    }
+    if let Some(inner) = value.strip_prefix('"') {
+        let inner = inner
+            .strip_suffix('"')
+            .ok_or("unterminated quoted scalar")?;
+        let mut result = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('0') => result.push('\0'),
+                Some(c @ ('"' | '\\' | '/' | ' ')) => result.push(c),
+                _ => return Err("unsupported escape sequence"),
+            }
+        }
+        return Ok(result);
+    }
+    Ok(value.to_owned())
+}
@@ -12,8 +12,11 @@ use quick_xml::{Reader, Writer};
 
 use crate::Checksum;
 
-use super::metadata::{Changelog, OtherXml, Package, RpmMetadata, XML_NS_OTHER};
-use super::{MetadataError, Repository, EVR};
+use super::metadata::{
+    Changelog, OtherXml, Package, ParseContext, ParseError, ParseMode, ParseWarning, RpmMetadata,
+    XmlStyle, XML_NS_OTHER,
+};
+use super::{utils, MetadataError, Repository, EVR};
 
 const TAG_OTHERDATA: &[u8] = b"otherdata";
 const TAG_PACKAGE: &[u8] = b"package";
@@ -34,7 +37,7 @@ impl RpmMetadata for OtherXml {
         let mut package = None;
         loop {
             reader.read_package(&mut package)?;
-            if package == None {
+            if package.is_none() {
                 break;
             }
             let pkgid = package.as_ref().unwrap().pkgid().to_owned();
@@ -67,19 +70,31 @@ impl RpmMetadata for OtherXml {
 
 impl OtherXml {
     pub fn new_writer<W: Write>(writer: quick_xml::Writer<W>) -> OtherXmlWriter<W> {
-        OtherXmlWriter { writer }
+        OtherXmlWriter {
+            writer,
+            style: XmlStyle::Standard,
+        }
     }
 
     pub fn new_reader<R: BufRead>(reader: quick_xml::Reader<R>) -> OtherXmlReader<R> {
-        OtherXmlReader { reader }
+        OtherXmlReader {
+            reader,
+            context: ParseContext::new("other", "otherdata"),
+        }
     }
 }
 
 pub struct OtherXmlWriter<W: Write> {
     writer: Writer<W>,
+    style: XmlStyle,
 }
 
 impl<W: Write> OtherXmlWriter<W> {
+    /// Set how the metadata is laid out and escaped. Must be set before the header is written.
+    pub fn set_style(&mut self, style: XmlStyle) {
+        self.style = style;
+    }
+
     pub fn write_header(&mut self, num_pkgs: usize) -> Result<(), MetadataError> {
         // <?xml version="1.0" encoding="UTF-8"?>
         self.writer
@@ -89,18 +104,27 @@ impl<W: Write> OtherXmlWriter<W> {
         let mut other_tag = BytesStart::borrowed_name(TAG_OTHERDATA);
         other_tag.push_attribute(("xmlns", XML_NS_OTHER));
         other_tag.push_attribute(("packages", num_pkgs.to_string().as_str()));
-        self.writer.write_event(Event::Start(other_tag))?;
+        match self.style {
+            XmlStyle::Standard | XmlStyle::Legacy => {
+                self.writer.write_event(Event::Start(other_tag))?
+            }
+            XmlStyle::CreaterepoC => utils::write_unindented_start(&mut self.writer, &other_tag)?,
+        }
 
         Ok(())
     }
 
     pub fn write_package(&mut self, package: &Package) -> Result<(), MetadataError> {
+        let style = self.style;
         // <package pkgid="6a915b6e1ad740994aa9688d70a67ff2b6b72e0ced668794aeb27b2d0f2e237b" name="fontconfig" arch="x86_64">
         let mut package_tag = BytesStart::borrowed_name(TAG_PACKAGE);
         let (_, pkgid) = package.checksum().to_values()?;
-        package_tag.push_attribute(("pkgid", pkgid));
-        package_tag.push_attribute(("name", package.name()));
-        package_tag.push_attribute(("arch", package.arch()));
+        package_tag.push_attribute(utils::package_attribute(style, "pkgid", pkgid));
+        package_tag.push_attribute(utils::package_attribute(style, "name", package.name()));
+        package_tag.push_attribute(utils::package_attribute(style, "arch", package.arch()));
+        if let Some(unknown) = package.unknown_xml() {
+            unknown.other.push_attributes(&mut package_tag);
+        }
         self.writer
             .write_event(Event::Start(package_tag.to_borrowed()))?;
 
@@ -108,20 +132,35 @@ impl<W: Write> OtherXmlWriter<W> {
         // <version epoch="0" ver="2.8.0" rel="5.el6"/>
         self.writer
             .create_element(TAG_VERSION)
-            .with_attribute(("epoch", epoch))
-            .with_attribute(("ver", version))
-            .with_attribute(("rel", release))
+            .with_attribute(utils::package_attribute(style, "epoch", epoch))
+            .with_attribute(utils::package_attribute(style, "ver", version))
+            .with_attribute(utils::package_attribute(style, "rel", release))
             .write_empty()?;
 
-        for changelog in package.changelogs() {
+        for changelog in package.load_changelogs()?.iter() {
             //  <changelog author="dalley &lt;dalley@redhat.com&gt; - 2.7.2-1" date="1251720000">- Update to 2.7.2</changelog>
             self.writer
                 .create_element(TAG_CHANGELOG)
-                .with_attribute(("author", changelog.author.as_str()))
-                .with_attribute(("date", changelog.timestamp.to_string().as_str()))
-                .write_text_content(BytesText::from_escaped(partial_escape(
-                    &changelog.description.as_bytes(),
-                )))?;
+                .with_attribute(utils::package_attribute(
+                    style,
+                    "author",
+                    changelog.author.as_str(),
+                ))
+                .with_attribute(utils::package_attribute(
+                    style,
+                    "date",
+                    changelog.timestamp.to_string().as_str(),
+                ))
+                .write_text_content(match style {
+                    XmlStyle::Standard | XmlStyle::Legacy => {
+                        BytesText::from_escaped(partial_escape(changelog.description.as_bytes()))
+                    }
+                    XmlStyle::CreaterepoC => utils::text(style, &changelog.description),
+                })?;
+        }
+
+        if let Some(unknown) = package.unknown_xml() {
+            unknown.other.write_elements(&mut self.writer)?;
         }
 
         // </package>
@@ -152,30 +191,73 @@ impl<W: Write> OtherXmlWriter<W> {
 
 pub struct OtherXmlReader<R: BufRead> {
     reader: Reader<R>,
+    context: ParseContext,
 }
 
 impl<R: BufRead> OtherXmlReader<R> {
     pub fn read_header(&mut self) -> Result<usize, MetadataError> {
-        parse_header(&mut self.reader)
+        parse_header(&mut self.reader, &mut self.context)
     }
 
     pub fn read_package(&mut self, package: &mut Option<Package>) -> Result<(), MetadataError> {
-        parse_package(package, &mut self.reader)
+        // an entry can only be skipped when it's read into a new package
+        let new_package = package.is_none();
+        loop {
+            self.context.next_entry();
+            match parse_package(package, &mut self.reader, &mut self.context) {
+                Err(e) if new_package => {
+                    if let Err(e) = self.context.recover(&mut self.reader, TAG_PACKAGE, e) {
+                        return Err(self.context.locate(&self.reader, e, package.as_ref()));
+                    }
+                    *package = None;
+                }
+                Err(e) => return Err(self.context.locate(&self.reader, e, package.as_ref())),
+                result => return result,
+            }
+        }
+    }
+
+    /// Set how metadata which doesn't follow the spec is dealt with. Strict by default.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.context.mode = mode;
+    }
+
+    pub fn parse_mode(&self) -> ParseMode {
+        self.context.mode
+    }
+
+    /// Set whether the elements and attributes of packages which aren't understood are kept, so that
+    /// they're written out again. Off by default.
+    pub fn set_preserve_unknown(&mut self, preserve: bool) {
+        self.context.preserve_unknown = preserve;
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        self.context.take_warnings()
+    }
+
+    /// Take the errors of the entries skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.context.take_errors()
     }
 }
 
 // <?xml version="1.0" encoding="UTF-8"?>
 // <otherdata xmlns="http://linux.duke.edu/metadata/other" packages="35">
-fn parse_header<R: BufRead>(reader: &mut Reader<R>) -> Result<usize, MetadataError> {
+fn parse_header<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+) -> Result<usize, MetadataError> {
     let mut buf = Vec::new();
 
     // TODO: get rid of this buffer
     loop {
         match reader.read_event(&mut buf)? {
             Event::Decl(_) => (),
-            Event::Start(e) if e.name().as_ref() == TAG_OTHERDATA => {
-                let count = e.try_get_attribute("packages")?.unwrap().value;
-                return Ok(std::str::from_utf8(&count)?.parse()?);
+            Event::Start(e) if e.name() == TAG_OTHERDATA => {
+                context.start_root(reader, &e)?;
+                return context.numeric_attribute(reader, &e, "packages");
             }
             _ => return Err(MetadataError::MissingHeaderError),
         }
@@ -191,30 +273,33 @@ fn parse_header<R: BufRead>(reader: &mut Reader<R>) -> Result<usize, MetadataErr
 pub fn parse_package<R: BufRead>(
     package: &mut Option<Package>,
     reader: &mut Reader<R>,
+    context: &mut ParseContext,
 ) -> Result<(), MetadataError> {
     let mut buf = Vec::with_capacity(128);
 
     // TODO: get rid of unwraps, various branches could happen in wrong order
     loop {
         match reader.read_event(&mut buf)? {
-            Event::End(e) if e.name().as_ref() == TAG_PACKAGE => break,
-            Event::Start(e) => match e.name().as_ref() {
+            Event::End(e) if e.name() == TAG_PACKAGE => break,
+            Event::Start(e) => match e.name() {
                 TAG_PACKAGE => {
                     let pkgid = e
                         .try_get_attribute("pkgid")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("pkgid"))?
-                        .unescape_and_decode_value(reader)?;
-                    let name = e
-                        .try_get_attribute("name")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("name"))?
-                        .unescape_and_decode_value(reader)?;
-                    let arch = e
-                        .try_get_attribute("arch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("arch"))?
+                        .ok_or(MetadataError::MissingAttributeError("pkgid"))?
                         .unescape_and_decode_value(reader)?;
+                    context.start_entry(reader, &e);
+                    let name = context.attribute(reader, &e, "name", "")?;
+                    context.entry = Some(name.clone());
+                    let arch = context.attribute(reader, &e, "arch", "")?;
 
                     if let Some(pkg) = package {
-                        assert!(pkg.pkgid() == &pkgid); // TODO err instead of assert
+                        if pkg.pkgid() != pkgid {
+                            return Err(MetadataError::InconsistentMetadataError(format!(
+                                "other.xml lists package {} where primary.xml lists {}",
+                                pkgid,
+                                pkg.pkgid()
+                            )));
+                        }
                     } else {
                         let mut pkg = Package::default();
                         pkg.set_name(&name)
@@ -222,12 +307,21 @@ pub fn parse_package<R: BufRead>(
                             .set_checksum(Checksum::Unknown(pkgid));
                         *package = Some(pkg);
                     };
+
+                    let attributes =
+                        context.unknown_attributes(reader, &e, &["pkgid", "name", "arch"])?;
+                    if !attributes.is_empty() {
+                        package.as_mut().unwrap().unknown_xml_mut().other.attributes = attributes;
+                    }
                 }
                 TAG_VERSION => {
-                    package.as_mut().unwrap().set_evr(parse_evr(reader, &e)?);
+                    package
+                        .as_mut()
+                        .unwrap()
+                        .set_evr(parse_evr(reader, &e, context)?);
                 }
                 TAG_CHANGELOG => {
-                    let changelog = parse_changelog(reader, &e)?;
+                    let changelog = parse_changelog(reader, &e, context)?;
                     // TODO: Temporary changelog?
                     package.as_mut().unwrap().add_changelog(
                         &changelog.author,
@@ -235,7 +329,12 @@ pub fn parse_package<R: BufRead>(
                         changelog.timestamp,
                     );
                 }
-                _ => (),
+                _ => {
+                    if let Some(xml) = context.unknown_element(reader, &e)? {
+                        let unknown = package.as_mut().unwrap().unknown_xml_mut();
+                        unknown.other.elements.push(xml);
+                    }
+                }
             },
             Event::Eof => break,
             _ => (),
@@ -250,19 +349,11 @@ pub fn parse_package<R: BufRead>(
 pub fn parse_evr<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &mut ParseContext,
 ) -> Result<EVR, MetadataError> {
-    let epoch = open_tag
-        .try_get_attribute("epoch")?
-        .unwrap()
-        .unescape_and_decode_value(reader)?;
-    let version = open_tag
-        .try_get_attribute("ver")?
-        .unwrap()
-        .unescape_and_decode_value(reader)?;
-    let release = open_tag
-        .try_get_attribute("rel")?
-        .unwrap()
-        .unescape_and_decode_value(reader)?;
+    let epoch = context.attribute(reader, open_tag, "epoch", "0")?;
+    let version = context.attribute(reader, open_tag, "ver", "")?;
+    let release = context.attribute(reader, open_tag, "rel", "")?;
 
     Ok(EVR::new(epoch, version, release))
 }
@@ -271,20 +362,13 @@ pub fn parse_evr<R: BufRead>(
 pub fn parse_changelog<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &mut ParseContext,
 ) -> Result<Changelog, MetadataError> {
-    let mut changelog = Changelog::default();
-
-    changelog.author = open_tag
-        .try_get_attribute("author")?
-        .unwrap()
-        .unescape_and_decode_value(reader)?;
-    changelog.timestamp = open_tag
-        .try_get_attribute("date")?
-        .unwrap()
-        .unescape_and_decode_value(reader)?
-        .parse()?;
-
-    changelog.description = reader.read_text(open_tag.name(), &mut Vec::with_capacity(128))?;
+    let changelog = Changelog {
+        author: context.attribute(reader, open_tag, "author", "")?,
+        timestamp: context.numeric_attribute(reader, open_tag, "date")?,
+        description: reader.read_text(open_tag.name(), &mut Vec::with_capacity(128))?,
+    };
 
     Ok(changelog)
 }
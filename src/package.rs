@@ -4,22 +4,35 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::collections::HashMap;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::filelist::FilelistsXmlReader;
-use crate::metadata::{METADATA_FILELISTS, METADATA_OTHER, METADATA_PRIMARY};
+use crate::logging;
+use crate::metadata::{
+    ParseError, ParseMode, ParseOptions, ParseWarning, METADATA_FILELISTS, METADATA_OTHER,
+    METADATA_PRIMARY,
+};
 use crate::other::OtherXmlReader;
 use crate::primary::PrimaryXmlReader;
+use crate::storage::{LocalStorage, Storage};
 use crate::{utils, RepomdData};
 use crate::{FilelistsXml, MetadataError, OtherXml, Package, PrimaryXml};
 
 #[cfg(feature = "read_rpm")]
 pub mod rpm_parsing {
-    use std::fs::File;
+    use std::fmt;
+    use std::fs::{self, File};
+    use std::io::{self, Read};
+    use std::sync::Arc;
     use std::time::SystemTime;
 
-    use crate::{Changelog, ChecksumType, PackageFile, Requirement, EVR};
+    use crate::runtime;
+    use crate::{
+        Changelog, ChecksumType, PackageFile, RepositoryHooks, Requirement, ScanCheckpoint, EVR,
+    };
 
     use super::*;
     use rpm;
@@ -114,10 +127,53 @@ pub mod rpm_parsing {
     // todo: location_href, location_base
     // todo: checksum type
     pub fn load_rpm_package(path: &str) -> Result<Package, MetadataError> {
-        let file = File::open(&path)?;
+        let file = File::open(path)?;
         let file_metadata = file.metadata()?;
 
-        let pkg = rpm::PackageMetadata::parse(&mut BufReader::new(&file))?;
+        let mut pkg_metadata = read_rpm_header(&mut BufReader::new(&file))?;
+
+        pkg_metadata.set_checksum(utils::checksum_file(Path::new(path), ChecksumType::Sha256)?);
+        pkg_metadata.set_location_href(path);
+
+        let file_size = file_metadata.len();
+        let unix_timestamp = file_metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        pkg_metadata.set_size_package(file_size);
+        pkg_metadata.set_time_file(unix_timestamp);
+
+        Ok(pkg_metadata)
+    }
+
+    /// Like [`load_rpm_package()`], for an RPM which is already in memory, e.g. one read from an archive.
+    /// The package is given `location_href`, and a file timestamp of 0.
+    pub fn load_rpm_bytes(bytes: &[u8], location_href: &str) -> Result<Package, MetadataError> {
+        load_rpm_reader(bytes, location_href)
+    }
+
+    /// Like [`load_rpm_bytes()`], for an RPM which is read from `reader` as it's parsed, e.g. an entry
+    /// of a compressed archive. All of it is read, as the checksum and size are of the whole RPM.
+    pub fn load_rpm_reader(
+        reader: impl io::Read,
+        location_href: &str,
+    ) -> Result<Package, MetadataError> {
+        let mut reader = BufReader::new(utils::HashingReader::new(reader, ChecksumType::Sha256));
+        let mut pkg_metadata = read_rpm_header(&mut reader)?;
+        io::copy(&mut reader, &mut io::sink())?;
+        let (size, checksum) = reader.into_inner().finish();
+        pkg_metadata.set_checksum(checksum);
+        pkg_metadata.set_location_href(location_href);
+        pkg_metadata.set_size_package(size);
+        Ok(pkg_metadata)
+    }
+
+    /// Read the package from the headers of an RPM, leaving out what depends on the RPM file: its
+    /// checksum, location, size and timestamp.
+    fn read_rpm_header(reader: &mut impl io::BufRead) -> Result<Package, MetadataError> {
+        let pkg = rpm::PackageMetadata::parse(reader)?;
 
         let mut pkg_metadata = Package::default();
 
@@ -187,7 +243,7 @@ pub mod rpm_parsing {
         for f in pkg.get_changelog_entries()?.into_iter() {
             changelogs.push(f.into())
         }
-        changelogs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        changelogs.sort_by_key(|a| a.timestamp);
         pkg_metadata.set_changelogs(changelogs);
 
         // todo: filter files
@@ -197,43 +253,532 @@ pub mod rpm_parsing {
         }
         pkg_metadata.set_files(files);
 
-        pkg_metadata.set_checksum(utils::checksum_file(Path::new(path), ChecksumType::Sha256)?);
-        pkg_metadata.set_location_href(path);
+        let offsets = pkg.get_package_segment_offsets();
+        pkg_metadata.set_rpm_header_range(offsets.header, offsets.payload);
 
-        let file_size = file_metadata.len();
-        let unix_timestamp = file_metadata
+        Ok(pkg_metadata)
+    }
+
+    /// How many RPMs are read at once before the packages are handed on, e.g. to the checkpoint.
+    const SCAN_CHUNK: usize = 64;
+
+    /// Called with the path of an RPM which couldn't be read, and the error.
+    pub type QuarantineCallback =
+        Arc<dyn Fn(&Path, &MetadataError) -> Result<(), MetadataError> + Send + Sync>;
+
+    /// What [`load_rpm_directory()`] does with an RPM which can't be read.
+    #[derive(Clone, Default)]
+    pub enum FailurePolicy {
+        /// Stop and return the error
+        #[default]
+        Abort,
+        /// Leave the package out and carry on, recording it in [`RpmDirectoryReport::failures`]
+        Skip,
+        /// Like `Skip`, but pass the RPM to the callback first, e.g. to move it into a quarantine directory.
+        /// An error returned by the callback stops the scan.
+        Quarantine(QuarantineCallback),
+    }
+
+    impl fmt::Debug for FailurePolicy {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                FailurePolicy::Abort => write!(f, "Abort"),
+                FailurePolicy::Skip => write!(f, "Skip"),
+                FailurePolicy::Quarantine(_) => write!(f, "Quarantine(..)"),
+            }
+        }
+    }
+
+    /// An RPM which was left out by [`load_rpm_directory()`].
+    #[derive(Debug)]
+    pub struct PackageFailure {
+        /// The path of the RPM, relative to the directory
+        pub path: PathBuf,
+        pub error: MetadataError,
+    }
+
+    /// The packages read by [`load_rpm_directory()`], and the RPMs which had to be left out.
+    #[derive(Debug, Default)]
+    pub struct RpmDirectoryReport {
+        pub packages: Vec<Package>,
+        pub failures: Vec<PackageFailure>,
+    }
+
+    /// Load the RPMs (`*.rpm`) in the directory `path` and its subdirectories, in order of their paths.
+    ///
+    /// The `location_href` of each package is its path relative to `path`. RPMs which can't be read are
+    /// dealt with according to `policy`, so that one corrupt RPM needn't abort the build of a large
+    /// repository. Hidden files and directories are skipped.
+    ///
+    /// Several RPMs are read at once, on threads drawn from the current [`Runtime`](crate::Runtime).
+    pub fn load_rpm_directory(
+        path: &Path,
+        policy: &FailurePolicy,
+    ) -> Result<RpmDirectoryReport, MetadataError> {
+        struct NoHooks;
+        impl RepositoryHooks for NoHooks {}
+        load_rpm_directory_with_hooks(path, policy, &NoHooks)
+    }
+
+    /// Like [`load_rpm_directory()`], calling [`RepositoryHooks::package_scanned()`] with each package read.
+    pub fn load_rpm_directory_with_hooks(
+        path: &Path,
+        policy: &FailurePolicy,
+        hooks: &dyn RepositoryHooks,
+    ) -> Result<RpmDirectoryReport, MetadataError> {
+        scan_rpm_directory(path, policy, hooks, None)
+    }
+
+    /// Like [`load_rpm_directory()`], recording the packages read in `checkpoint` as it goes, and taking
+    /// the packages of the RPMs which were already read from it rather than reading them again.
+    ///
+    /// The package of an RPM is taken from the checkpoint only if the size and modification time of the
+    /// RPM haven't changed since. The checkpoint is saved before returning.
+    pub fn load_rpm_directory_with_checkpoint(
+        path: &Path,
+        policy: &FailurePolicy,
+        checkpoint: &mut ScanCheckpoint,
+    ) -> Result<RpmDirectoryReport, MetadataError> {
+        struct NoHooks;
+        impl RepositoryHooks for NoHooks {}
+        let report = scan_rpm_directory(path, policy, &NoHooks, Some(&mut *checkpoint))?;
+        checkpoint.save()?;
+        Ok(report)
+    }
+
+    fn scan_rpm_directory(
+        path: &Path,
+        policy: &FailurePolicy,
+        hooks: &dyn RepositoryHooks,
+        mut checkpoint: Option<&mut ScanCheckpoint>,
+    ) -> Result<RpmDirectoryReport, MetadataError> {
+        let mut rpms = Vec::new();
+        find_rpms(path, Path::new(""), &mut rpms)?;
+        rpms.sort();
+        logging::debug!("found {} RPMs in {}", rpms.len(), path.display());
+
+        let mut report = RpmDirectoryReport::default();
+        for chunk in rpms.chunks(SCAN_CHUNK) {
+            // the packages of the RPMs which are in the checkpoint, the others are read
+            let mut saved = Vec::with_capacity(chunk.len());
+            for href in chunk {
+                saved.push(match &checkpoint {
+                    Some(checkpoint) => saved_package(checkpoint, &path.join(href), href)?,
+                    None => None,
+                });
+            }
+            let unsaved: Vec<&PathBuf> = chunk
+                .iter()
+                .zip(&saved)
+                .filter(|(_, saved)| saved.is_none())
+                .map(|(href, _)| href)
+                .collect();
+            let mut read = runtime::map_concurrently(&unsaved, 0, |href| {
+                load_rpm_package(&path.join(href).to_string_lossy())
+            })
+            .into_iter();
+
+            for (href, saved) in chunk.iter().zip(saved) {
+                if let Some(package) = saved {
+                    report.packages.push(package);
+                    continue;
+                }
+                let rpm_path = path.join(href);
+                let error = match read.next().unwrap() {
+                    Ok(mut package) => {
+                        package.set_location_href(href.to_string_lossy());
+                        hooks.package_scanned(&rpm_path, &package)?;
+                        if let Some(checkpoint) = &mut checkpoint {
+                            checkpoint.add(package.clone())?;
+                        }
+                        report.packages.push(package);
+                        continue;
+                    }
+                    Err(e) => e,
+                };
+                logging::debug!("failed to read {}: {}", rpm_path.display(), error);
+                match policy {
+                    FailurePolicy::Abort => return Err(error),
+                    FailurePolicy::Skip => (),
+                    FailurePolicy::Quarantine(quarantine) => quarantine(&rpm_path, &error)?,
+                }
+                report.failures.push(PackageFailure {
+                    path: href.clone(),
+                    error,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// The package of the RPM at `rpm_path` from `checkpoint`, if the RPM hasn't changed since it was read.
+    fn saved_package(
+        checkpoint: &ScanCheckpoint,
+        rpm_path: &Path,
+        href: &Path,
+    ) -> Result<Option<Package>, MetadataError> {
+        let file_metadata = fs::metadata(rpm_path)?;
+        let modified = file_metadata
             .modified()?
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        Ok(checkpoint
+            .get(&href.to_string_lossy())
+            .filter(|package| {
+                package.size_package() == file_metadata.len() && package.time_file() == modified
+            })
+            .cloned())
+    }
 
-        pkg_metadata.set_size_package(file_size);
-        pkg_metadata.set_time_file(unix_timestamp);
+    /// Collect the paths (relative to `root`) of the RPMs under `root.join(dir)`, skipping hidden ones.
+    fn find_rpms(root: &Path, dir: &Path, rpms: &mut Vec<PathBuf>) -> Result<(), MetadataError> {
+        for entry in fs::read_dir(root.join(dir))? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let href = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                find_rpms(root, &href, rpms)?;
+            } else if href.extension().is_some_and(|ext| ext == "rpm") {
+                rpms.push(href);
+            }
+        }
+        Ok(())
+    }
 
-        let offsets = pkg.get_package_segment_offsets();
-        pkg_metadata.set_rpm_header_range(offsets.header, offsets.payload);
+    /// A file, directory or symlink in the payload of an RPM.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PayloadEntry {
+        /// The absolute path the file is installed to, e.g. `/etc/foo.conf`
+        pub path: String,
+        /// The type and permissions of the file, as in `st_mode`
+        pub mode: u32,
+        /// The size of the contents of the entry. For a symlink the contents are the target, and only the
+        /// last of several hardlinks to a file carries the contents.
+        pub size: u64,
+    }
 
-        Ok(pkg_metadata)
+    impl PayloadEntry {
+        pub fn is_file(&self) -> bool {
+            self.mode & 0o170000 == 0o100000
+        }
+
+        pub fn is_dir(&self) -> bool {
+            self.mode & 0o170000 == 0o040000
+        }
+
+        pub fn is_symlink(&self) -> bool {
+            self.mode & 0o170000 == 0o120000
+        }
+    }
+
+    /// The payload of an RPM, read one entry at a time without unpacking the rest of the archive.
+    ///
+    /// The payload is decompressed as it's read. After [`PayloadReader::next_entry()`], the contents of
+    /// that entry are read from the `PayloadReader` itself; whatever isn't read is skipped by the next call.
+    pub struct PayloadReader {
+        path: PathBuf,
+        reader: Box<dyn io::Read + Send>,
+        /// The unread contents of the current entry
+        remaining: u64,
+        /// The padding after the contents of the current entry
+        padding: u64,
+        finished: bool,
+    }
+
+    const CPIO_HEADER_LEN: usize = 110;
+    const CPIO_TRAILER: &str = "TRAILER!!!";
+
+    impl PayloadReader {
+        /// Open the RPM at `path`, skipping its headers.
+        pub fn open(path: &Path) -> Result<Self, MetadataError> {
+            Ok(Self::open_with_metadata(path)?.1)
+        }
+
+        fn open_with_metadata(
+            path: &Path,
+        ) -> Result<(rpm::PackageMetadata, PayloadReader), MetadataError> {
+            let mut reader = BufReader::new(File::open(path)?);
+            let metadata = rpm::PackageMetadata::parse(&mut reader)?;
+            let (reader, _) = niffler::send::get_reader(Box::new(reader))?;
+            let payload = PayloadReader {
+                path: path.to_owned(),
+                reader,
+                remaining: 0,
+                padding: 0,
+                finished: false,
+            };
+            Ok((metadata, payload))
+        }
+
+        fn error(&self, message: impl Into<String>) -> MetadataError {
+            MetadataError::PayloadError(self.path.display().to_string(), message.into())
+        }
+
+        /// Move on to the next entry of the payload, `None` once the end of the archive is reached.
+        pub fn next_entry(&mut self) -> Result<Option<PayloadEntry>, MetadataError> {
+            if self.finished {
+                return Ok(None);
+            }
+            let skip = self.remaining + self.padding;
+            if io::copy(&mut (&mut self.reader).take(skip), &mut io::sink())? != skip {
+                return Err(self.error("the payload is truncated"));
+            }
+            self.remaining = 0;
+            self.padding = 0;
+
+            // the "new ASCII" format of cpio, which rpm uses for packages without files larger than 4 GiB
+            let mut header = [0u8; CPIO_HEADER_LEN];
+            self.reader.read_exact(&mut header)?;
+            if &header[..6] != b"070701" && &header[..6] != b"070702" {
+                return Err(self.error("the payload isn't a cpio archive of a supported format"));
+            }
+            let field = |index: usize| -> Result<u64, MetadataError> {
+                let start = 6 + 8 * index;
+                let value = std::str::from_utf8(&header[start..start + 8])?;
+                Ok(u64::from_str_radix(value, 16)?)
+            };
+            let mode = field(1)? as u32;
+            let size = field(6)?;
+            let name_size = field(11)? as usize;
+
+            let mut name = vec![0u8; name_size];
+            self.reader.read_exact(&mut name)?;
+            if name.pop() != Some(0) {
+                return Err(self.error("an entry has an invalid name"));
+            }
+            let name_padding = (4 - (CPIO_HEADER_LEN + name_size) % 4) % 4;
+            self.reader.read_exact(&mut [0u8; 3][..name_padding])?;
+            let name = String::from_utf8(name).map_err(|e| e.utf8_error())?;
+            if name == CPIO_TRAILER {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            self.remaining = size;
+            self.padding = (4 - size % 4) % 4;
+            let path = format!("/{}", name.trim_start_matches('.').trim_start_matches('/'));
+            Ok(Some(PayloadEntry { path, mode, size }))
+        }
+    }
+
+    impl io::Read for PayloadReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = (buf.len() as u64).min(self.remaining) as usize;
+            if len == 0 {
+                return Ok(0);
+            }
+            let read = self.reader.read(&mut buf[..len])?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.remaining -= read as u64;
+            Ok(read)
+        }
+    }
+
+    impl Package {
+        /// Open the RPM of the package, found at `location_href` under `repo_root`, for reading its
+        /// payload.
+        ///
+        /// The name, EVR and arch in the headers of the RPM must match those of the package, so that a
+        /// different build which happens to have the same filename isn't read by mistake.
+        pub fn open_rpm(&self, repo_root: &Path) -> Result<PayloadReader, MetadataError> {
+            let path = repo_root.join(self.location_href());
+            let (metadata, payload) = PayloadReader::open_with_metadata(&path)?;
+            let arch = match metadata.is_source_package() {
+                true => "src",
+                false => metadata.get_arch()?,
+            };
+            let matches = metadata.get_name()? == self.name()
+                && metadata.get_epoch().unwrap_or(0).to_string() == self.evr().epoch()
+                && metadata.get_version()? == self.evr().version()
+                && metadata.get_release()? == self.evr().release()
+                && arch == self.arch();
+            if !matches {
+                return Err(MetadataError::InconsistentMetadataError(format!(
+                    "{} is not an RPM of {}",
+                    path.display(),
+                    self.nevra()
+                )));
+            }
+            Ok(payload)
+        }
+
+        /// Read the contents of the regular files of the payload whose paths are in `paths`, in the order
+        /// they're stored in the RPM. Paths which aren't regular files of the payload are left out, and
+        /// reading stops as soon as every file has been found.
+        pub fn extract_files(
+            &self,
+            repo_root: &Path,
+            paths: &[&str],
+        ) -> Result<Vec<(PayloadEntry, Vec<u8>)>, MetadataError> {
+            let mut payload = self.open_rpm(repo_root)?;
+            let mut extracted = Vec::new();
+            while extracted.len() < paths.len() {
+                let Some(entry) = payload.next_entry()? else {
+                    break;
+                };
+                if !entry.is_file() || !paths.contains(&entry.path.as_str()) {
+                    continue;
+                }
+                let mut contents = Vec::with_capacity(entry.size as usize);
+                payload.read_to_end(&mut contents)?;
+                extracted.push((entry, contents));
+            }
+            Ok(extracted)
+        }
+
+        /// Like [`Package::extract_files()`], but write the files under `destination` (keeping the
+        /// directories they're in, e.g. `/etc/foo.conf` becomes `destination/etc/foo.conf`), returning the
+        /// paths written.
+        pub fn extract_files_to(
+            &self,
+            repo_root: &Path,
+            paths: &[&str],
+            destination: &Path,
+        ) -> Result<Vec<PathBuf>, MetadataError> {
+            let mut written = Vec::new();
+            for (entry, contents) in self.extract_files(repo_root, paths)? {
+                let relative = Path::new(entry.path.trim_start_matches('/'));
+                if !relative
+                    .components()
+                    .all(|c| matches!(c, std::path::Component::Normal(_)))
+                {
+                    return Err(MetadataError::InvalidFieldError("payload path", entry.path));
+                }
+                let target = destination.join(relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&target, contents)?;
+                written.push(target);
+            }
+            Ok(written)
+        }
     }
 }
 
 pub struct PackageIterator {
     primary_xml: PrimaryXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
-    filelists_xml: FilelistsXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
-    other_xml: OtherXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
+    /// `None` if the files of the packages aren't read
+    filelists_xml: Option<FilelistsXmlReader<BufReader<Box<dyn std::io::Read + Send>>>>,
+    /// `None` if the changelogs of the packages aren't read
+    other_xml: Option<OtherXmlReader<BufReader<Box<dyn std::io::Read + Send>>>>,
 
     num_packages: usize,
     num_remaining: usize,
     in_progress_package: Option<Package>,
+
+    mode: ParseMode,
+    filelists_ahead: ReadAhead,
+    other_ahead: ReadAhead,
+    warnings: Vec<ParseWarning>,
+    errors: Vec<ParseError>,
+    /// The paths of the metadata files by type, if they were opened by the iterator
+    paths: Vec<(&'static str, PathBuf)>,
+    /// Where the metadata files were opened from
+    storage: Arc<dyn Storage>,
+}
+
+/// Entries of filelists.xml or other.xml which were read ahead while looking for a package that they
+/// list in a different order than primary.xml does. Only used in [`ParseMode::Lenient`] and
+/// [`ParseMode::Recover`].
+#[derive(Default)]
+struct ReadAhead {
+    /// The entries read ahead, and whether they were read while looking for an entry that was found later
+    /// in the file
+    entries: HashMap<String, (Package, bool)>,
+    reordered: bool,
+}
+
+impl ReadAhead {
+    /// Find the entry for `pkgid`, reading further entries with `read` until it turns up.
+    fn find(
+        &mut self,
+        pkgid: &str,
+        mut read: impl FnMut(&mut Option<Package>) -> Result<(), MetadataError>,
+    ) -> Result<Option<Package>, MetadataError> {
+        if let Some((entry, displaced)) = self.entries.remove(pkgid) {
+            // entries read ahead because of a missing (or skipped) entry don't mean the order differs
+            self.reordered |= displaced;
+            return Ok(Some(entry));
+        }
+        let mut read_ahead = Vec::new();
+        loop {
+            let mut entry = None;
+            read(&mut entry)?;
+            match entry {
+                None => return Ok(None),
+                Some(entry) if entry.pkgid() == pkgid => {
+                    for pkgid in read_ahead {
+                        if let Some((_, displaced)) = self.entries.get_mut(&pkgid) {
+                            *displaced = true;
+                        }
+                    }
+                    return Ok(Some(entry));
+                }
+                Some(entry) => {
+                    read_ahead.push(entry.pkgid().to_owned());
+                    self.entries
+                        .insert(entry.pkgid().to_owned(), (entry, false));
+                }
+            }
+        }
+    }
 }
 
 impl PackageIterator {
     pub fn from_repodata(base: &Path, repomd: &RepomdData) -> Result<Self, MetadataError> {
-        let primary_path = base.join(&repomd.get_record(METADATA_PRIMARY).unwrap().location_href);
-        let filelists_path =
-            base.join(&repomd.get_record(METADATA_FILELISTS).unwrap().location_href);
-        let other_path = base.join(&repomd.get_record(METADATA_OTHER).unwrap().location_href);
-        Self::from_files(&primary_path, &filelists_path, &other_path)
+        Self::from_repodata_with_mode(base, repomd, ParseMode::Strict)
+    }
+
+    pub fn from_repodata_with_mode(
+        base: &Path,
+        repomd: &RepomdData,
+        mode: ParseMode,
+    ) -> Result<Self, MetadataError> {
+        Self::from_repodata_with_options(base, repomd, ParseOptions::default().mode(mode))
+    }
+
+    pub fn from_repodata_with_options(
+        base: &Path,
+        repomd: &RepomdData,
+        options: ParseOptions,
+    ) -> Result<Self, MetadataError> {
+        Self::from_repodata_selected(Arc::new(LocalStorage), base, repomd, options, true, true)
+    }
+
+    /// Like [`PackageIterator::from_repodata_with_options()`], but the files and changelogs of the
+    /// packages are only read if `filelists` and `other` are set (and the repository has them), and the
+    /// metadata files are read from `storage`.
+    pub(crate) fn from_repodata_selected(
+        storage: Arc<dyn Storage>,
+        base: &Path,
+        repomd: &RepomdData,
+        options: ParseOptions,
+        filelists: bool,
+        other: bool,
+    ) -> Result<Self, MetadataError> {
+        let path_of = |metadata| {
+            repomd
+                .get_record(metadata)
+                .map(|record| base.join(&record.location_href))
+        };
+        let primary_path = path_of(METADATA_PRIMARY).ok_or_else(|| {
+            MetadataError::InconsistentMetadataError("repomd.xml has no primary record".to_owned())
+        })?;
+        let filelists_path = path_of(METADATA_FILELISTS).filter(|_| filelists);
+        let other_path = path_of(METADATA_OTHER).filter(|_| other);
+        Self::from_optional_files(
+            storage,
+            &primary_path,
+            filelists_path.as_deref(),
+            other_path.as_deref(),
+            options,
+        )
     }
 
     pub fn from_files(
@@ -241,18 +786,100 @@ impl PackageIterator {
         filelists_path: &Path,
         other_path: &Path,
     ) -> Result<Self, MetadataError> {
-        let primary_xml = PrimaryXml::new_reader(utils::xml_reader_from_file(primary_path)?);
-        let filelists_xml = FilelistsXml::new_reader(utils::xml_reader_from_file(filelists_path)?);
-        let other_xml = OtherXml::new_reader(utils::xml_reader_from_file(other_path)?);
+        Self::from_files_with_mode(primary_path, filelists_path, other_path, ParseMode::Strict)
+    }
+
+    pub fn from_files_with_mode(
+        primary_path: &Path,
+        filelists_path: &Path,
+        other_path: &Path,
+        mode: ParseMode,
+    ) -> Result<Self, MetadataError> {
+        let options = ParseOptions::default().mode(mode);
+        Self::from_files_with_options(primary_path, filelists_path, other_path, options)
+    }
+
+    pub fn from_files_with_options(
+        primary_path: &Path,
+        filelists_path: &Path,
+        other_path: &Path,
+        options: ParseOptions,
+    ) -> Result<Self, MetadataError> {
+        Self::from_optional_files(
+            Arc::new(LocalStorage),
+            primary_path,
+            Some(filelists_path),
+            Some(other_path),
+            options,
+        )
+    }
+
+    /// Create an iterator over the packages of `primary_path` in `storage`, with their files and
+    /// changelogs if `filelists_path` and `other_path` are given.
+    fn from_optional_files(
+        storage: Arc<dyn Storage>,
+        primary_path: &Path,
+        filelists_path: Option<&Path>,
+        other_path: Option<&Path>,
+        options: ParseOptions,
+    ) -> Result<Self, MetadataError> {
+        let mut paths = vec![("primary", primary_path.to_owned())];
+        paths.extend(filelists_path.map(|path| ("filelists", path.to_owned())));
+        paths.extend(other_path.map(|path| ("other", path.to_owned())));
+        logging::debug!(
+            "reading packages from {}",
+            paths
+                .iter()
+                .map(|(_, path)| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let open = |path| utils::filtered_xml_reader_from_storage(&*storage, path, options);
+        let mut primary_xml = PrimaryXml::new_reader(open(primary_path)?);
+        primary_xml.set_parse_mode(options.mode);
+        primary_xml.set_preserve_unknown(options.preserve_unknown);
+        let filelists_xml = match filelists_path {
+            Some(path) => {
+                let mut filelists_xml = FilelistsXml::new_reader(open(path)?);
+                filelists_xml.set_parse_mode(options.mode);
+                filelists_xml.set_preserve_unknown(options.preserve_unknown);
+                Some(filelists_xml)
+            }
+            None => None,
+        };
+        let other_xml = match other_path {
+            Some(path) => {
+                let mut other_xml = OtherXml::new_reader(open(path)?);
+                other_xml.set_parse_mode(options.mode);
+                other_xml.set_preserve_unknown(options.preserve_unknown);
+                Some(other_xml)
+            }
+            None => None,
+        };
 
-        Self::from_readers(primary_xml, filelists_xml, other_xml)
+        let mut iterator = Self::new(primary_xml, filelists_xml, other_xml)?;
+        iterator.paths = paths;
+        iterator.storage = storage;
+        Ok(iterator)
     }
 
+    /// Create an iterator over the packages of already opened metadata files. The [`ParseMode`] of
+    /// `primary_xml` applies to the iterator as a whole.
     pub fn from_readers(
         primary_xml: PrimaryXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
         filelists_xml: FilelistsXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
         other_xml: OtherXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
     ) -> Result<Self, MetadataError> {
+        Self::new(primary_xml, Some(filelists_xml), Some(other_xml))
+    }
+
+    fn new(
+        primary_xml: PrimaryXmlReader<BufReader<Box<dyn std::io::Read + Send>>>,
+        filelists_xml: Option<FilelistsXmlReader<BufReader<Box<dyn std::io::Read + Send>>>>,
+        other_xml: Option<OtherXmlReader<BufReader<Box<dyn std::io::Read + Send>>>>,
+    ) -> Result<Self, MetadataError> {
+        let primary_xml_mode = primary_xml.parse_mode();
         let mut parser = Self {
             primary_xml,
             filelists_xml,
@@ -260,6 +887,13 @@ impl PackageIterator {
             num_packages: 0,
             num_remaining: 0,
             in_progress_package: None,
+            mode: primary_xml_mode,
+            filelists_ahead: ReadAhead::default(),
+            other_ahead: ReadAhead::default(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            paths: Vec::new(),
+            storage: Arc::new(LocalStorage),
         };
         parser.parse_headers()?;
 
@@ -268,36 +902,60 @@ impl PackageIterator {
 
     fn parse_headers(&mut self) -> Result<(), MetadataError> {
         let primary_pkg_count = self.primary_xml.read_header()?;
-        let filelists_pkg_count = self.filelists_xml.read_header()?;
-        let other_pkg_count = self.other_xml.read_header()?;
+        let filelists_pkg_count = match &mut self.filelists_xml {
+            Some(filelists_xml) => Some(filelists_xml.read_header()?),
+            None => None,
+        };
+        let other_pkg_count = match &mut self.other_xml {
+            Some(other_xml) => Some(other_xml.read_header()?),
+            None => None,
+        };
 
-        if primary_pkg_count != filelists_pkg_count || primary_pkg_count != other_pkg_count {
-            return Err(MetadataError::InconsistentMetadataError(
-                "Metadata package counts don't match".to_owned(),
-            ));
+        if [filelists_pkg_count, other_pkg_count]
+            .iter()
+            .flatten()
+            .any(|count| *count != primary_pkg_count)
+        {
+            let message = "Metadata package counts don't match".to_owned();
+            match self.mode {
+                ParseMode::Strict => {
+                    return Err(MetadataError::InconsistentMetadataError(message));
+                }
+                ParseMode::Lenient | ParseMode::Recover => self.warn("primary", None, message),
+            }
         }
 
-        assert_eq!(primary_pkg_count, filelists_pkg_count);
-        assert_eq!(primary_pkg_count, other_pkg_count);
         self.num_packages = primary_pkg_count;
         self.num_remaining = self.num_packages;
+        logging::debug!("metadata headers declare {} packages", self.num_packages);
 
         Ok(())
     }
 
     pub fn parse_package(&mut self) -> Result<Option<Package>, MetadataError> {
-        self.primary_xml
-            .read_package(&mut self.in_progress_package)?;
-        self.filelists_xml
-            .read_package(&mut self.in_progress_package)?;
-        self.other_xml.read_package(&mut self.in_progress_package)?;
-
-        let package = self.in_progress_package.take();
+        let package = self.read_package().map_err(|e| {
+            let path = e.location().and_then(|l| {
+                self.paths
+                    .iter()
+                    .find(|(metadata, _)| *metadata == l.metadata)
+            });
+            match path {
+                Some((_, path)) => {
+                    e.with_line_from(|| utils::reader_from_storage(&*self.storage, path))
+                }
+                None => e,
+            }
+        })?;
 
         // TODO: re-enable this with actual error handling instead of panics - RHEL6 for example will fail
         // because the header lies about the number of packages
-        if let Some(_) = package {
-            self.num_remaining -= 1;
+        if package.is_some() {
+            self.num_remaining = self.num_remaining.saturating_sub(1);
+            logging::progress(
+                "read",
+                self.num_packages - self.num_remaining,
+                self.num_packages,
+            );
             // self.num_remaining = self
             //     .num_remaining
             //     .checked_sub(1)
@@ -312,6 +970,173 @@ impl PackageIterator {
         Ok(package)
     }
 
+    fn read_package(&mut self) -> Result<Option<Package>, MetadataError> {
+        match self.mode {
+            ParseMode::Strict => {
+                self.primary_xml
+                    .read_package(&mut self.in_progress_package)?;
+                if let Some(filelists_xml) = &mut self.filelists_xml {
+                    filelists_xml.read_package(&mut self.in_progress_package)?;
+                }
+                if let Some(other_xml) = &mut self.other_xml {
+                    other_xml.read_package(&mut self.in_progress_package)?;
+                }
+                Ok(self.in_progress_package.take())
+            }
+            ParseMode::Lenient | ParseMode::Recover => self.parse_package_matched(),
+        }
+    }
+
+    /// Read the next package, matching up the entries of filelists.xml and other.xml by pkgid rather than
+    /// relying on all three files listing the packages in the same order.
+    fn parse_package_matched(&mut self) -> Result<Option<Package>, MetadataError> {
+        loop {
+            let mut package = None;
+            self.primary_xml.read_package(&mut package)?;
+            match package {
+                Some(package) => {
+                    if let Some(package) = self.complete_package(package)? {
+                        return Ok(Some(package));
+                    }
+                }
+                None => {
+                    self.unmatched_entries();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Warn about the entries of filelists.xml and other.xml which were read ahead but never matched.
+    fn unmatched_entries(&mut self) {
+        for (metadata, ahead) in [
+            ("filelists", &mut self.filelists_ahead),
+            ("other", &mut self.other_ahead),
+        ] {
+            if !ahead.entries.is_empty() {
+                let message = format!(
+                    "{} entries don't belong to any package in primary.xml",
+                    ahead.entries.len()
+                );
+                ahead.entries.clear();
+                self.warnings.push(ParseWarning {
+                    metadata,
+                    entry: None,
+                    message,
+                });
+            }
+        }
+    }
+
+    /// Add the files and changelogs of `package` from the entries of filelists.xml and other.xml with the
+    /// same pkgid. Returns `None` if the package is skipped because of [`ParseMode::Recover`].
+    fn complete_package(&mut self, mut package: Package) -> Result<Option<Package>, MetadataError> {
+        let pkgid = package.pkgid().to_owned();
+        let mut complete = true;
+
+        if let Some(filelists_xml) = &mut self.filelists_xml {
+            let was_reordered = self.filelists_ahead.reordered;
+            match self
+                .filelists_ahead
+                .find(&pkgid, |entry| filelists_xml.read_package(entry))?
+            {
+                Some(entry) => {
+                    package.set_files(entry.files().to_vec());
+                    if let Some(unknown) = entry.unknown_xml() {
+                        package.unknown_xml_mut().filelists = unknown.filelists.clone();
+                    }
+                }
+                None => complete &= self.missing_entry("filelists", &package),
+            }
+            if self.filelists_ahead.reordered && !was_reordered {
+                let message = "packages are listed in a different order than in primary.xml";
+                self.warn("filelists", None, message.to_owned());
+            }
+        }
+
+        if let Some(other_xml) = &mut self.other_xml {
+            let was_reordered = self.other_ahead.reordered;
+            match self
+                .other_ahead
+                .find(&pkgid, |entry| other_xml.read_package(entry))?
+            {
+                Some(entry) => {
+                    package.set_changelogs(entry.changelogs().to_vec());
+                    if let Some(unknown) = entry.unknown_xml() {
+                        package.unknown_xml_mut().other = unknown.other.clone();
+                    }
+                }
+                None => complete &= self.missing_entry("other", &package),
+            }
+            if self.other_ahead.reordered && !was_reordered {
+                let message = "packages are listed in a different order than in primary.xml";
+                self.warn("other", None, message.to_owned());
+            }
+        }
+
+        Ok(complete.then_some(package))
+    }
+
+    /// Deal with `metadata` having no entry for `package`: a warning in lenient mode, and the package is
+    /// skipped in recover mode. Returns whether the package is kept.
+    fn missing_entry(&mut self, metadata: &'static str, package: &Package) -> bool {
+        if self.mode != ParseMode::Recover {
+            self.warn(
+                metadata,
+                Some(package),
+                "no entry for this package".to_owned(),
+            );
+            return true;
+        }
+        let error = MetadataError::InconsistentMetadataError(format!(
+            "{}.xml has no entry for this package",
+            metadata
+        ));
+        logging::debug!("primary: skipping {}: {}", package.name(), error);
+        self.errors.push(ParseError {
+            metadata: "primary",
+            entry: Some(package.name().to_owned()),
+            offset: self.primary_xml.entry_offset(),
+            error,
+        });
+        false
+    }
+
+    fn warn(&mut self, metadata: &'static str, package: Option<&Package>, message: String) {
+        logging::debug!("{}: {}", metadata, message);
+        self.warnings.push(ParseWarning {
+            metadata,
+            entry: package.map(|p| p.name().to_owned()),
+            message,
+        });
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.extend(self.primary_xml.take_warnings());
+        if let Some(filelists_xml) = &mut self.filelists_xml {
+            warnings.extend(filelists_xml.take_warnings());
+        }
+        if let Some(other_xml) = &mut self.other_xml {
+            warnings.extend(other_xml.take_warnings());
+        }
+        warnings
+    }
+
+    /// Take the errors of the entries skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        let mut errors = self.primary_xml.take_errors();
+        if let Some(filelists_xml) = &mut self.filelists_xml {
+            errors.extend(filelists_xml.take_errors());
+        }
+        if let Some(other_xml) = &mut self.other_xml {
+            errors.extend(other_xml.take_errors());
+        }
+        errors.append(&mut self.errors);
+        errors
+    }
+
     pub fn remaining_packages(&self) -> usize {
         self.num_remaining
     }
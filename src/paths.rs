@@ -0,0 +1,398 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compact storage of file paths, for repositories whose file lists take up most of their memory.
+//!
+//! Most files share long directories with many others (`/usr/lib/python3.12/site-packages/...`), so
+//! rather than a string of its own, each path is kept as its directory in a [`PathTable`], a trie of
+//! directories shared by every package, plus its name. [`Package::files()`] is empty for packages whose
+//! files are stored this way, [`Package::load_files()`] puts the paths back together.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::{Arc, RwLock};
+
+use indexmap::{Equivalent, IndexMap, IndexSet};
+
+use crate::{FileType, MetadataError, Package, PackageFile, Repository};
+
+/// How the file lists of packages are kept, see [`LoadOptions::files()`](crate::LoadOptions::files).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FileStorage {
+    /// A [`PackageFile`] with the full path of each file, as they're parsed
+    #[default]
+    Inline,
+    /// The directories of the files in a [`PathTable`] shared by the packages, and only the names of the
+    /// files in each package
+    Compact,
+}
+
+/// A trie of the directories of file paths, shared by the packages whose files are stored in it.
+///
+/// Each directory is the name of its last component and the directory it's in, identified by an index.
+/// 0 is the empty directory of paths without any `/`.
+#[derive(Default)]
+pub struct PathTable {
+    dirs: RwLock<IndexSet<(u32, Box<str>)>>,
+}
+
+/// A directory of a [`PathTable`] to look up, without allocating a key.
+#[derive(Hash)]
+struct DirKey<'a>(u32, &'a str);
+
+impl Equivalent<(u32, Box<str>)> for DirKey<'_> {
+    fn equivalent(&self, key: &(u32, Box<str>)) -> bool {
+        self.0 == key.0 && self.1 == &*key.1
+    }
+}
+
+impl Equivalent<(u32, &str)> for DirKey<'_> {
+    fn equivalent(&self, key: &(u32, &str)) -> bool {
+        self.0 == key.0 && self.1 == key.1
+    }
+}
+
+impl PathTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of directories.
+    pub fn len(&self) -> usize {
+        self.dirs.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The approximate memory used by the table, in bytes.
+    pub fn memory_footprint(&self) -> usize {
+        let dirs = self.dirs.read().unwrap();
+        // each entry of an IndexSet is a hash and the value, plus an index in the hash table
+        dirs.capacity() * (size_of::<usize>() * 2 + size_of::<(u32, Box<str>)>())
+            + dirs.iter().map(|(_, name)| name.len()).sum::<usize>()
+    }
+
+    /// The directory `dir`, added if it isn't in the table yet.
+    fn intern(&self, dir: &str) -> u32 {
+        if let Some(id) = self.find(dir) {
+            return id;
+        }
+        let mut dirs = self.dirs.write().unwrap();
+        let mut parent = 0;
+        for name in dir.split('/') {
+            parent = match dirs.get_index_of(&DirKey(parent, name)) {
+                Some(idx) => idx as u32 + 1,
+                None => {
+                    let (idx, _) = dirs.insert_full((parent, name.into()));
+                    u32::try_from(idx + 1).expect("more than u32::MAX directories")
+                }
+            };
+        }
+        parent
+    }
+
+    /// The directory `dir`, if it's in the table.
+    fn find(&self, dir: &str) -> Option<u32> {
+        let dirs = self.dirs.read().unwrap();
+        let mut parent = 0;
+        for name in dir.split('/') {
+            parent = dirs.get_index_of(&DirKey(parent, name))? as u32 + 1;
+        }
+        Some(parent)
+    }
+
+    /// Append the path of the directory `id` to `path`.
+    fn push_dir(&self, id: u32, path: &mut String) {
+        let dirs = self.dirs.read().unwrap();
+        let mut names = Vec::new();
+        let mut id = id;
+        while id != 0 {
+            let (parent, name) = &dirs[id as usize - 1];
+            names.push(&**name);
+            id = *parent;
+        }
+        for (idx, name) in names.iter().rev().enumerate() {
+            if idx > 0 {
+                path.push('/');
+            }
+            path.push_str(name);
+        }
+    }
+}
+
+impl fmt::Debug for PathTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathTable")
+            .field("dirs", &self.len())
+            .finish()
+    }
+}
+
+/// Split `path` into its directory and its name, `None` for the directory of a path without any `/`.
+fn split_path(path: &str) -> (Option<&str>, &str) {
+    match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    }
+}
+
+/// The files of a package, stored in a [`PathTable`].
+///
+/// Two of them are equal if they have the same files, whichever tables they're stored in.
+#[derive(Clone, Debug)]
+pub struct StoredFiles {
+    table: Arc<PathTable>,
+    // for each file, its directory, where its name ends in `names`, and its type
+    entries: Box<[(u32, u32, FileType)]>,
+    names: Box<str>,
+}
+
+impl StoredFiles {
+    fn new(files: &[PackageFile], table: &Arc<PathTable>) -> Result<Self, MetadataError> {
+        let mut entries = Vec::with_capacity(files.len());
+        let mut names = String::new();
+        // the files of a package are usually listed directory by directory
+        let mut last_dir: Option<(&str, u32)> = None;
+        for file in files {
+            let (dir, name) = split_path(&file.path);
+            let dir_id = match (dir, last_dir) {
+                (None, _) => 0,
+                (Some(dir), Some((last, id))) if dir == last => id,
+                (Some(dir), _) => {
+                    let id = table.intern(dir);
+                    last_dir = Some((dir, id));
+                    id
+                }
+            };
+            names.push_str(name);
+            let end = u32::try_from(names.len())
+                .map_err(|_| MetadataError::InvalidFieldError("file list", file.path.clone()))?;
+            entries.push((dir_id, end, file.filetype));
+        }
+        Ok(Self {
+            table: table.clone(),
+            entries: entries.into_boxed_slice(),
+            names: names.into_boxed_str(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The directory, name and type of each file.
+    fn iter(&self) -> impl Iterator<Item = (u32, &str, FileType)> {
+        let mut start = 0;
+        self.entries.iter().map(move |(dir, end, filetype)| {
+            let name = &self.names[start..*end as usize];
+            start = *end as usize;
+            (*dir, name, *filetype)
+        })
+    }
+
+    /// The files with their full paths.
+    pub fn to_vec(&self) -> Vec<PackageFile> {
+        let mut files = Vec::with_capacity(self.entries.len());
+        let mut last_dir: Option<(u32, String)> = None;
+        for (dir, name, filetype) in self.iter() {
+            let mut path = match &last_dir {
+                _ if dir == 0 => String::new(),
+                Some((last, path)) if *last == dir => path.clone(),
+                _ => {
+                    let mut path = String::new();
+                    self.table.push_dir(dir, &mut path);
+                    path.push('/');
+                    last_dir = Some((dir, path.clone()));
+                    path
+                }
+            };
+            path.push_str(name);
+            files.push(PackageFile { filetype, path });
+        }
+        files
+    }
+
+    /// Whether one of the files has the path `path`.
+    pub fn contains(&self, path: &str) -> bool {
+        let (dir, name) = split_path(path);
+        let dir = match dir {
+            Some(dir) => match self.table.find(dir) {
+                Some(id) => id,
+                None => return false,
+            },
+            None => 0,
+        };
+        self.iter()
+            .any(|(file_dir, file_name, _)| file_dir == dir && file_name == name)
+    }
+
+    /// The approximate memory used by the files, not counting the table, in bytes.
+    pub fn memory_footprint(&self) -> usize {
+        self.entries.len() * size_of::<(u32, u32, FileType)>() + self.names.len()
+    }
+
+    /// The table the directories are stored in.
+    pub fn table(&self) -> &Arc<PathTable> {
+        &self.table
+    }
+}
+
+impl PartialEq for StoredFiles {
+    fn eq(&self, other: &Self) -> bool {
+        if self.names != other.names || self.entries.len() != other.entries.len() {
+            return false;
+        }
+        match Arc::ptr_eq(&self.table, &other.table) {
+            true => self.entries == other.entries,
+            false => self.to_vec() == other.to_vec(),
+        }
+    }
+}
+
+impl Hash for StoredFiles {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.names.hash(state);
+        for (_, _, filetype) in self.entries.iter() {
+            filetype.hash(state);
+        }
+    }
+}
+
+impl Package {
+    /// The files of the package with their full paths, put back together if they're stored in a
+    /// [`PathTable`], or else the same as [`Package::files()`].
+    pub fn load_files(&self) -> Cow<'_, [PackageFile]> {
+        let Some(stored) = &self.stored_files else {
+            return Cow::Borrowed(&self.rpm_files);
+        };
+        let mut files = stored.to_vec();
+        // files which were added since the others were stored
+        files.extend(self.rpm_files.iter().cloned());
+        Cow::Owned(files)
+    }
+
+    /// Whether one of the files of the package has the path `path`, wherever they're stored.
+    pub fn contains_file(&self, path: &str) -> bool {
+        self.rpm_files.iter().any(|file| file.path == path)
+            || self
+                .stored_files
+                .as_ref()
+                .is_some_and(|stored| stored.contains(path))
+    }
+
+    /// Store the files of the package in `table`, leaving [`Package::files()`] empty, see
+    /// [`Package::load_files()`].
+    pub fn store_files(&mut self, table: &Arc<PathTable>) -> Result<(), MetadataError> {
+        self.inline_files();
+        if self.rpm_files.is_empty() {
+            return Ok(());
+        }
+        self.stored_files = Some(StoredFiles::new(&self.rpm_files, table)?);
+        self.rpm_files = Vec::new();
+        Ok(())
+    }
+
+    /// Move the files of the package back from the [`PathTable`] they were stored in, if they were.
+    pub fn inline_files(&mut self) {
+        if self.stored_files.is_some() {
+            self.rpm_files = self.load_files().into_owned();
+            self.stored_files = None;
+        }
+    }
+
+    /// How the files are stored, if they're stored in a [`PathTable`].
+    pub fn stored_files(&self) -> Option<&StoredFiles> {
+        self.stored_files.as_ref()
+    }
+}
+
+impl Repository {
+    /// Keep the files of the packages according to `storage`, moving them to a new [`PathTable`] or
+    /// back into the packages for [`FileStorage::Inline`].
+    pub fn store_files(&mut self, storage: FileStorage) -> Result<(), MetadataError> {
+        let table = Arc::new(PathTable::new());
+        for package in self.packages_mut().values_mut() {
+            match storage {
+                FileStorage::Compact => package.store_files(&table)?,
+                FileStorage::Inline => package.inline_files(),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An index of file paths for looking up whatever `V` has them (e.g. the packages with a file), keyed by
+/// directory and name so that it doesn't need a string for each path, whether the files are stored
+/// inline or in a [`PathTable`].
+pub(crate) struct PathIndex<'a, V> {
+    dirs: PathTable,
+    entries: IndexMap<(u32, &'a str), Vec<V>>,
+    // the directories of the tables of stored files, by the address of the table, as they are in `dirs`
+    translated: HashMap<(usize, u32), u32>,
+}
+
+impl<'a, V: Copy> PathIndex<'a, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            dirs: PathTable::new(),
+            entries: IndexMap::new(),
+            translated: HashMap::new(),
+        }
+    }
+
+    /// Add the files of `package`, which `value` has.
+    pub(crate) fn add_files(&mut self, package: &'a Package, value: V) {
+        for file in &package.rpm_files {
+            let (dir, name) = split_path(&file.path);
+            let dir = match dir {
+                Some(dir) => self.dirs.intern(dir),
+                None => 0,
+            };
+            self.entries.entry((dir, name)).or_default().push(value);
+        }
+        if let Some(stored) = &package.stored_files {
+            let address = Arc::as_ptr(&stored.table) as usize;
+            for (dir, name, _) in stored.iter() {
+                let dir = match self.translated.get(&(address, dir)) {
+                    Some(translated) => *translated,
+                    None if dir == 0 => 0,
+                    None => {
+                        let mut path = String::new();
+                        stored.table.push_dir(dir, &mut path);
+                        let translated = self.dirs.intern(&path);
+                        self.translated.insert((address, dir), translated);
+                        translated
+                    }
+                };
+                self.entries.entry((dir, name)).or_default().push(value);
+            }
+        }
+    }
+
+    /// Whatever has the file `path`.
+    pub(crate) fn get(&self, path: &str) -> &[V] {
+        let (dir, name) = split_path(path);
+        let dir = match dir {
+            Some(dir) => match self.dirs.find(dir) {
+                Some(id) => id,
+                None => return &[],
+            },
+            None => 0,
+        };
+        self.entries
+            .get(&DirKey(dir, name))
+            .map_or(&[], |values| values.as_slice())
+    }
+}
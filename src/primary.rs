@@ -11,10 +11,11 @@ use quick_xml::{Reader, Writer};
 
 use super::filelist;
 use super::metadata::{
-    Checksum, MetadataError, Package, PrimaryXml, Requirement, RpmMetadata, XML_NS_COMMON,
-    XML_NS_RPM,
+    Checksum, MetadataError, Package, PackageFile, ParseContext, ParseError, ParseMode,
+    ParseWarning, PrimaryElements, PrimaryXml, Requirement, RpmMetadata, UnknownPackageXml,
+    XmlStyle, XML_NS_COMMON, XML_NS_RPM,
 };
-use super::{PackageFile, Repository, EVR};
+use super::{utils, Repository, EVR};
 
 const TAG_METADATA: &[u8] = b"metadata";
 const TAG_PACKAGE: &[u8] = b"package";
@@ -64,7 +65,7 @@ impl RpmMetadata for PrimaryXml {
         let mut package = None;
         loop {
             reader.read_package(&mut package)?;
-            if package == None {
+            if package.is_none() {
                 break;
             }
             let pkgid = package.as_ref().unwrap().pkgid().to_owned();
@@ -90,333 +91,564 @@ impl RpmMetadata for PrimaryXml {
 
 impl PrimaryXml {
     pub fn new_writer<W: Write>(writer: quick_xml::Writer<W>) -> PrimaryXmlWriter<W> {
-        PrimaryXmlWriter { writer }
+        PrimaryXmlWriter {
+            writer,
+            style: XmlStyle::Standard,
+            elements: PrimaryElements::default(),
+        }
     }
 
     pub fn new_reader<R: BufRead>(reader: quick_xml::Reader<R>) -> PrimaryXmlReader<R> {
-        PrimaryXmlReader { reader }
+        PrimaryXmlReader {
+            reader,
+            context: ParseContext::new("primary", "metadata"),
+            events: EventState::default(),
+        }
     }
 }
 
 pub struct PrimaryXmlReader<R: BufRead> {
     reader: Reader<R>,
+    context: ParseContext,
+    events: EventState,
 }
 
 impl<R: BufRead> PrimaryXmlReader<R> {
     pub fn read_header(&mut self) -> Result<usize, MetadataError> {
-        parse_header(&mut self.reader)
+        parse_header(&mut self.reader, &mut self.context)
     }
 
+    /// Read the next package into `package`, which must be `None` since primary.xml is where packages
+    /// start out, or leave it `None` at the end of the file.
     pub fn read_package(&mut self, package: &mut Option<Package>) -> Result<(), MetadataError> {
-        parse_package(&mut self.reader, package)
+        self.read_package_with(package, false)
+    }
+
+    /// Like [`PrimaryXmlReader::read_package()`], but the package also gets the files primary.xml lists,
+    /// which are otherwise left to be read from filelists.xml.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn read_package_with_files(
+        &mut self,
+        package: &mut Option<Package>,
+    ) -> Result<(), MetadataError> {
+        self.read_package_with(package, true)
+    }
+
+    fn read_package_with(
+        &mut self,
+        package: &mut Option<Package>,
+        files: bool,
+    ) -> Result<(), MetadataError> {
+        if package.is_some() {
+            return Err(already_started());
+        }
+        loop {
+            self.context.next_entry();
+            match parse_package(&mut self.reader, package, &mut self.context, files) {
+                Err(e) => {
+                    if let Err(e) = self.context.recover(&mut self.reader, TAG_PACKAGE, e) {
+                        return Err(self.context.locate(&self.reader, e, package.as_ref()));
+                    }
+                    *package = None;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read the next step of the packages, or `None` at the end of the file. Unlike
+    /// [`PrimaryXmlReader::read_package()`] no [`Package`] is built, so that e.g. only the license and
+    /// size of each package can be extracted without holding on to the rest.
+    ///
+    /// In [`ParseMode::Recover`], the rest of a package which can't be parsed is skipped without a
+    /// [`PackageEvent::End`], and the next event is the [`PackageEvent::Start`] of the next package.
+    pub fn read_event(&mut self) -> Result<Option<PackageEvent>, MetadataError> {
+        loop {
+            if self.events.position == EventPosition::Between {
+                self.context.next_entry();
+            }
+            match next_event(&mut self.reader, &mut self.context, &mut self.events) {
+                Err(e) => {
+                    if let Err(e) = self.context.recover(&mut self.reader, TAG_PACKAGE, e) {
+                        return Err(self.context.locate(&self.reader, e, None));
+                    }
+                    self.events.position = EventPosition::Between;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Set how metadata which doesn't follow the spec is dealt with. Strict by default.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.context.mode = mode;
+    }
+
+    pub fn parse_mode(&self) -> ParseMode {
+        self.context.mode
+    }
+
+    /// Set whether the elements and attributes of packages which aren't understood are kept, so that
+    /// they're written out again. Off by default.
+    pub fn set_preserve_unknown(&mut self, preserve: bool) {
+        self.context.preserve_unknown = preserve;
+    }
+
+    /// Byte offset of the last package read.
+    pub(crate) fn entry_offset(&self) -> usize {
+        self.context.offset.unwrap_or_default()
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        self.context.take_warnings()
+    }
+
+    /// Take the errors of the entries skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.context.take_errors()
     }
 }
 
 // <?xml version="1.0" encoding="UTF-8"?>
 // <metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="35">
-fn parse_header<R: BufRead>(reader: &mut Reader<R>) -> Result<usize, MetadataError> {
+fn parse_header<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+) -> Result<usize, MetadataError> {
     let mut buf = Vec::new();
 
     // TODO: get rid of this buffer
     loop {
         match reader.read_event(&mut buf)? {
             Event::Decl(_) => (),
-            Event::Start(e) if e.name().as_ref() == TAG_METADATA => {
-                let count = e.try_get_attribute("packages")?.unwrap().value;
-                return Ok(std::str::from_utf8(&count)?.parse()?);
+            Event::Start(e) if e.name() == TAG_METADATA => {
+                context.start_root(reader, &e)?;
+                return context.numeric_attribute(reader, &e, "packages");
             }
             _ => return Err(MetadataError::MissingHeaderError),
         }
     }
 }
 
+/// A step of reading a package from `primary.xml`, see [`PrimaryXmlReader::read_event()`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackageEvent {
+    /// A `<package>` was started
+    Start,
+    /// A value of the package was read
+    Field(PackageField),
+    /// The `<package>` was finished
+    End,
+}
+
+/// A value of a package in `primary.xml`, as read by [`PrimaryXmlReader::read_event()`].
+///
+/// The fields are read in the order they appear in, and fields which are missing aren't read at all.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackageField {
+    Name(String),
+    Evr(EVR),
+    Checksum(Checksum),
+    Arch(String),
+    Summary(String),
+    Description(String),
+    Packager(String),
+    Url(String),
+    Time {
+        file: u64,
+        build: u64,
+    },
+    Size {
+        package: u64,
+        installed: u64,
+        archive: u64,
+    },
+    Location {
+        href: String,
+        base: Option<String>,
+    },
+    License(String),
+    Vendor(String),
+    Group(String),
+    Buildhost(String),
+    Sourcerpm(String),
+    HeaderRange {
+        start: u64,
+        end: u64,
+    },
+    Provides(Vec<Requirement>),
+    Requires(Vec<Requirement>),
+    Conflicts(Vec<Requirement>),
+    Obsoletes(Vec<Requirement>),
+    Suggests(Vec<Requirement>),
+    Enhances(Vec<Requirement>),
+    Recommends(Vec<Requirement>),
+    Supplements(Vec<Requirement>),
+    /// The attributes of `<package>` which aren't understood, if they're kept
+    UnknownAttributes(Vec<(String, String)>),
+    /// An element of `<package>` which isn't understood, if they're kept
+    UnknownElement(String),
+    /// An element of `<format>` which isn't understood, if they're kept
+    UnknownFormatElement(String),
+}
+
+impl PackageField {
+    /// Set the value on `package`.
+    pub fn apply(self, package: &mut Package) {
+        match self {
+            PackageField::Name(name) => {
+                package.set_name(name);
+            }
+            PackageField::Evr(evr) => {
+                package.set_evr(evr);
+            }
+            PackageField::Checksum(checksum) => {
+                package.set_checksum(checksum);
+            }
+            PackageField::Arch(arch) => {
+                package.set_arch(arch);
+            }
+            PackageField::Summary(summary) => {
+                package.set_summary(summary);
+            }
+            PackageField::Description(description) => {
+                package.set_description(description);
+            }
+            PackageField::Packager(packager) => {
+                package.set_packager(packager);
+            }
+            PackageField::Url(url) => {
+                package.set_url(url);
+            }
+            PackageField::Time { file, build } => {
+                package.set_time_file(file).set_time_build(build);
+            }
+            PackageField::Size {
+                package: size,
+                installed,
+                archive,
+            } => {
+                package
+                    .set_size_package(size)
+                    .set_size_installed(installed)
+                    .set_size_archive(archive);
+            }
+            PackageField::Location { href, base } => {
+                if base.is_some() {
+                    package.set_location_base(base);
+                }
+                package.set_location_href(&href);
+            }
+            PackageField::License(license) => {
+                package.set_rpm_license(license);
+            }
+            PackageField::Vendor(vendor) => {
+                package.set_rpm_vendor(vendor);
+            }
+            PackageField::Group(group) => {
+                package.set_rpm_group(group);
+            }
+            PackageField::Buildhost(buildhost) => {
+                package.set_rpm_buildhost(buildhost);
+            }
+            PackageField::Sourcerpm(sourcerpm) => {
+                package.set_rpm_sourcerpm(sourcerpm);
+            }
+            PackageField::HeaderRange { start, end } => {
+                package.set_rpm_header_range(start, end);
+            }
+            PackageField::Provides(list) => {
+                package.set_provides(list);
+            }
+            PackageField::Requires(list) => {
+                package.set_requires(list);
+            }
+            PackageField::Conflicts(list) => {
+                package.set_conflicts(list);
+            }
+            PackageField::Obsoletes(list) => {
+                package.set_obsoletes(list);
+            }
+            PackageField::Suggests(list) => {
+                package.set_suggests(list);
+            }
+            PackageField::Enhances(list) => {
+                package.set_enhances(list);
+            }
+            PackageField::Recommends(list) => {
+                package.set_recommends(list);
+            }
+            PackageField::Supplements(list) => {
+                package.set_supplements(list);
+            }
+            PackageField::UnknownAttributes(attributes) => {
+                package.unknown_xml_mut().primary.attributes = attributes;
+            }
+            PackageField::UnknownElement(xml) => {
+                package.unknown_xml_mut().primary.elements.push(xml);
+            }
+            PackageField::UnknownFormatElement(xml) => {
+                package.unknown_xml_mut().format.elements.push(xml);
+            }
+        }
+    }
+}
+
+/// Where the reading of package events is at.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum EventPosition {
+    #[default]
+    Between,
+    Package,
+    Format,
+}
+
+/// What's kept between [`PackageEvent`]s.
+#[derive(Debug, Default)]
+struct EventState {
+    position: EventPosition,
+    // the event following the one which was just returned
+    pending: Option<PackageEvent>,
+    buf: Vec<u8>,
+    text_buf: Vec<u8>,
+    // the files of the package, if they're read from primary.xml
+    files: Option<Vec<PackageFile>>,
+}
+
+/// Read the next package into `package`, with the files primary.xml lists if `files` is set.
 pub fn parse_package<R: BufRead>(
     reader: &mut Reader<R>,
     package: &mut Option<Package>,
+    context: &mut ParseContext,
+    files: bool,
 ) -> Result<(), MetadataError> {
-    let mut buf = Vec::with_capacity(512);
-    let mut text_buf = Vec::with_capacity(512);
+    let mut state = EventState {
+        files: files.then(Vec::new),
+        ..EventState::default()
+    };
+    loop {
+        match next_event(reader, context, &mut state)? {
+            Some(PackageEvent::Start) => {
+                if package.is_some() {
+                    return Err(already_started());
+                }
+                *package = Some(Package::default());
+            }
+            Some(PackageEvent::Field(field)) => field.apply(package.as_mut().unwrap()),
+            Some(PackageEvent::End) => {
+                if let Some(files) = state.files.take() {
+                    package.as_mut().unwrap().set_files(files);
+                }
+                break;
+            }
+            None => break,
+        }
+    }
 
+    // package.parse_state |= ParseState::PRIMARY;
+    Ok(())
+}
+
+/// The error for reading a package of primary.xml into one which was already read from somewhere else.
+fn already_started() -> MetadataError {
+    MetadataError::InconsistentMetadataError(
+        "packages must be read from primary.xml before the other metadata".to_owned(),
+    )
+}
+
+/// Read up to the next [`PackageEvent`], or `None` at the end of the file.
+fn next_event<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+    state: &mut EventState,
+) -> Result<Option<PackageEvent>, MetadataError> {
+    use EventPosition::*;
+
+    if let Some(event) = state.pending.take() {
+        return Ok(Some(event));
+    }
+    let text_buf = &mut state.text_buf;
     loop {
-        match reader.read_event(&mut buf)? {
-            Event::End(e) if e.name().as_ref() == TAG_PACKAGE => break,
-            Event::Start(e) => match e.name().as_ref() {
-                TAG_PACKAGE => {
-                    let ptype = e
-                        .try_get_attribute(b"type")?
-                        .unwrap()
-                        .unescape_and_decode_value(reader)?;
-
-                    assert_eq!(&ptype, "rpm"); // TODO: better error handling
-
-                    if let Some(_pkg) = package {
-                        // TODO: need a temporary place to store this since we don't know the pkgid yet
-                        unimplemented!("package must be parsed from primary.xml first");
-                        // assert!(pkg.pkgid() == &pkgid); // TODO err instead of assert
-                    } else {
-                        let pkg = Package::default();
-                        *package = Some(pkg);
-                    };
-                }
-                TAG_NAME => {
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_name(reader.read_text(TAG_NAME, &mut text_buf)?.as_str());
-                }
-                TAG_VERSION => {
+        state.buf.clear();
+        text_buf.clear();
+        let field = match reader.read_event(&mut state.buf)? {
+            Event::Start(e) => match (state.position, e.name()) {
+                (Between, TAG_PACKAGE) => {
+                    context.start_entry(reader, &e);
+                    let ptype = context.attribute(reader, &e, "type", "rpm")?;
+                    if ptype != "rpm" {
+                        context.deviation(MetadataError::UnknownAttributeError(format!(
+                            "unsupported package type {}",
+                            ptype
+                        )))?;
+                    }
+                    let attributes = context.unknown_attributes(reader, &e, &["type"])?;
+                    if !attributes.is_empty() {
+                        state.pending = Some(PackageEvent::Field(PackageField::UnknownAttributes(
+                            attributes,
+                        )));
+                    }
+                    state.position = Package;
+                    return Ok(Some(PackageEvent::Start));
+                }
+                (Between, _) => {
+                    reader.read_to_end(e.name(), &mut Vec::new())?;
+                    continue;
+                }
+                (Package, TAG_NAME) => {
+                    let name = reader.read_text(TAG_NAME, text_buf)?;
+                    context.entry = Some(name.clone());
+                    PackageField::Name(name)
+                }
+                (Package, TAG_VERSION) => {
                     // TODO: unescape_and_decode_value allocates, that can probably be avoided
-                    let epoch = e
-                        .try_get_attribute("epoch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("epoch"))?
-                        .unescape_and_decode_value(reader)?;
-
-                    let version = e
-                        .try_get_attribute("ver")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("ver"))?
-                        .unescape_and_decode_value(reader)?;
-
-                    let release = e
-                        .try_get_attribute("rel")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("rel"))?
-                        .unescape_and_decode_value(reader)?;
-
-                    // TODO: temporary conversions
-                    let evr = EVR::new(epoch, version, release);
-                    package.as_mut().unwrap().set_evr(evr);
-                }
-                TAG_CHECKSUM => {
-                    let checksum_type = e
-                        .try_get_attribute("type")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?
-                        .unescape_and_decode_value(reader)?;
-                    let checksum_value = reader.read_text(TAG_CHECKSUM, &mut text_buf)?;
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_checksum(Checksum::try_create(checksum_type, checksum_value)?);
-                }
-                TAG_ARCH => {
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_arch(reader.read_text(TAG_ARCH, &mut text_buf)?.as_str());
-                }
-                TAG_SUMMARY => {
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_summary(reader.read_text(TAG_SUMMARY, &mut text_buf)?.as_str());
-                }
-                TAG_DESCRIPTION => {
-                    package.as_mut().unwrap().set_description(
-                        reader.read_text(TAG_DESCRIPTION, &mut text_buf)?.as_str(),
-                    );
-                }
-                TAG_PACKAGER => {
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_packager(reader.read_text(TAG_PACKAGER, &mut text_buf)?.as_str());
-                }
-                TAG_URL => {
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_url(reader.read_text(TAG_URL, &mut text_buf)?.as_str());
-                }
-                TAG_TIME => {
-                    let time_file = e
-                        .try_get_attribute("file")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("file"))?
-                        .unescape_and_decode_value(reader)?
-                        .parse()?;
-
-                    let time_build = e
-                        .try_get_attribute("build")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("build"))?
-                        .unescape_and_decode_value(reader)?
-                        .parse()?;
-
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_time_file(time_file)
-                        .set_time_build(time_build);
-                }
-                TAG_SIZE => {
-                    let package_size = e
-                        .try_get_attribute("package")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("package"))?
-                        .unescape_and_decode_value(reader)?
-                        .parse()?;
-
-                    let installed_size = e
-                        .try_get_attribute("installed")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("installed"))?
-                        .unescape_and_decode_value(reader)?
-                        .parse()?;
-
-                    let archive_size = e
-                        .try_get_attribute("archive")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("archive"))?
-                        .unescape_and_decode_value(reader)?
-                        .parse()?;
-
-                    package
-                        .as_mut()
-                        .unwrap()
-                        .set_size_package(package_size)
-                        .set_size_installed(installed_size)
-                        .set_size_archive(archive_size);
-                }
-                TAG_LOCATION => {
-                    let location_href = e
-                        .try_get_attribute("href")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("href"))?
-                        .unescape_and_decode_value(reader)?;
-                    let location_base = e
-                        .try_get_attribute("base")?
-                        .map(|a| a.unescape_and_decode_value(reader).unwrap());
-
-                    if let Some(location_base) = location_base {
-                        package
-                            .as_mut()
-                            .unwrap()
-                            .set_location_base(Some(location_base.as_str()));
+                    let epoch = context.attribute(reader, &e, "epoch", "0")?;
+                    let version = context.attribute(reader, &e, "ver", "")?;
+                    let release = context.attribute(reader, &e, "rel", "")?;
+                    PackageField::Evr(EVR::new(epoch, version, release))
+                }
+                (Package, TAG_CHECKSUM) => {
+                    let checksum_type = context.attribute(reader, &e, "type", "")?;
+                    let checksum_value = reader.read_text(TAG_CHECKSUM, text_buf)?;
+                    let checksum = context.tolerate(
+                        Checksum::try_create(checksum_type.as_str(), checksum_value.as_str()),
+                        || Checksum::Unknown(checksum_value.clone()),
+                    )?;
+                    PackageField::Checksum(checksum)
+                }
+                (Package, TAG_ARCH) => PackageField::Arch(reader.read_text(TAG_ARCH, text_buf)?),
+                (Package, TAG_SUMMARY) => {
+                    PackageField::Summary(reader.read_text(TAG_SUMMARY, text_buf)?)
+                }
+                (Package, TAG_DESCRIPTION) => {
+                    PackageField::Description(reader.read_text(TAG_DESCRIPTION, text_buf)?)
+                }
+                (Package, TAG_PACKAGER) => {
+                    PackageField::Packager(reader.read_text(TAG_PACKAGER, text_buf)?)
+                }
+                (Package, TAG_URL) => PackageField::Url(reader.read_text(TAG_URL, text_buf)?),
+                (Package, TAG_TIME) => PackageField::Time {
+                    file: context.numeric_attribute(reader, &e, "file")?,
+                    build: context.numeric_attribute(reader, &e, "build")?,
+                },
+                (Package, TAG_SIZE) => PackageField::Size {
+                    package: context.numeric_attribute(reader, &e, "package")?,
+                    installed: context.numeric_attribute(reader, &e, "installed")?,
+                    archive: context.numeric_attribute(reader, &e, "archive")?,
+                },
+                (Package, TAG_LOCATION) => {
+                    let href = context.attribute(reader, &e, "href", "")?;
+                    let base = match e.try_get_attribute("xml:base")? {
+                        Some(base) => Some(base),
+                        None => e.try_get_attribute("base")?,
                     }
-                    package.as_mut().unwrap().set_location_href(&location_href);
-                }
-                TAG_FORMAT => {
-                    // TODO: allocations
-                    buf.clear();
-                    text_buf.clear();
-                    loop {
-                        match reader.read_event(&mut buf)? {
-                            Event::End(e) if e.name().as_ref() == TAG_FORMAT => break,
-                            Event::Start(e) => match e.name().as_ref() {
-                                TAG_RPM_LICENSE => {
-                                    package.as_mut().unwrap().set_rpm_license(
-                                        reader.read_text(TAG_RPM_LICENSE, &mut text_buf)?.as_str(),
-                                    );
-                                }
-                                TAG_RPM_VENDOR => {
-                                    package.as_mut().unwrap().set_rpm_vendor(
-                                        reader.read_text(TAG_RPM_VENDOR, &mut text_buf)?.as_str(),
-                                    );
-                                }
-                                TAG_RPM_GROUP => {
-                                    package.as_mut().unwrap().set_rpm_group(
-                                        reader.read_text(TAG_RPM_GROUP, &mut text_buf)?.as_str(),
-                                    );
-                                }
-                                TAG_RPM_BUILDHOST => {
-                                    package.as_mut().unwrap().set_rpm_buildhost(
-                                        reader
-                                            .read_text(TAG_RPM_BUILDHOST, &mut text_buf)?
-                                            .as_str(),
-                                    );
-                                }
-                                TAG_RPM_SOURCERPM => {
-                                    package.as_mut().unwrap().set_rpm_sourcerpm(
-                                        reader
-                                            .read_text(TAG_RPM_SOURCERPM, &mut text_buf)?
-                                            .as_str(),
-                                    );
-                                }
-                                TAG_RPM_HEADER_RANGE => {
-                                    let start = e
-                                        .try_get_attribute("start")?
-                                        .ok_or_else(|| {
-                                            MetadataError::MissingAttributeError("start")
-                                        })?
-                                        .unescape_and_decode_value(reader)?
-                                        .parse()?;
-
-                                    let end = e
-                                        .try_get_attribute("end")?
-                                        .ok_or_else(|| MetadataError::MissingAttributeError("end"))?
-                                        .unescape_and_decode_value(reader)?
-                                        .parse()?;
-
-                                    package.as_mut().unwrap().set_rpm_header_range(start, end);
-                                }
-                                TAG_RPM_PROVIDES => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_provides(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_REQUIRES => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_requires(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_CONFLICTS => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_conflicts(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_OBSOLETES => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_obsoletes(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_SUGGESTS => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_suggests(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_ENHANCES => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_enhances(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_RECOMMENDS => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_recommends(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_RPM_SUPPLEMENTS => {
-                                    package
-                                        .as_mut()
-                                        .unwrap()
-                                        .set_supplements(parse_requirement_list(reader, &e)?);
-                                }
-                                TAG_FILE => (),
-                                // TODO: share implementation w/ filelists, but don't parse twice.
-                                // use IndexSet to enforce uniqueness while keeping order
-                                _ => (),
-                            },
-                            _ => (),
-                        }
+                    .map(|a| a.unescape_and_decode_value(reader))
+                    .transpose()?;
+                    PackageField::Location { href, base }
+                }
+                (Package, TAG_FORMAT) => {
+                    context.enter(TAG_FORMAT);
+                    state.position = Format;
+                    continue;
+                }
+                (Package, _) => match context.unknown_element(reader, &e)? {
+                    Some(xml) => PackageField::UnknownElement(xml),
+                    None => continue,
+                },
+                (Format, TAG_RPM_LICENSE) => {
+                    PackageField::License(reader.read_text(TAG_RPM_LICENSE, text_buf)?)
+                }
+                (Format, TAG_RPM_VENDOR) => {
+                    PackageField::Vendor(reader.read_text(TAG_RPM_VENDOR, text_buf)?)
+                }
+                (Format, TAG_RPM_GROUP) => {
+                    PackageField::Group(reader.read_text(TAG_RPM_GROUP, text_buf)?)
+                }
+                (Format, TAG_RPM_BUILDHOST) => {
+                    PackageField::Buildhost(reader.read_text(TAG_RPM_BUILDHOST, text_buf)?)
+                }
+                (Format, TAG_RPM_SOURCERPM) => {
+                    PackageField::Sourcerpm(reader.read_text(TAG_RPM_SOURCERPM, text_buf)?)
+                }
+                (Format, TAG_RPM_HEADER_RANGE) => PackageField::HeaderRange {
+                    start: context.numeric_attribute(reader, &e, "start")?,
+                    end: context.numeric_attribute(reader, &e, "end")?,
+                },
+                (Format, TAG_RPM_PROVIDES) => {
+                    PackageField::Provides(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_REQUIRES) => {
+                    PackageField::Requires(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_CONFLICTS) => {
+                    PackageField::Conflicts(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_OBSOLETES) => {
+                    PackageField::Obsoletes(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_SUGGESTS) => {
+                    PackageField::Suggests(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_ENHANCES) => {
+                    PackageField::Enhances(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_RECOMMENDS) => {
+                    PackageField::Recommends(parse_requirement_list(reader, &e, context)?)
+                }
+                (Format, TAG_RPM_SUPPLEMENTS) => {
+                    PackageField::Supplements(parse_requirement_list(reader, &e, context)?)
+                }
+                // TODO: share implementation w/ filelists, but don't parse twice.
+                // use IndexSet to enforce uniqueness while keeping order
+                (Format, TAG_FILE) => {
+                    if let Some(files) = &mut state.files {
+                        files.push(filelist::parse_file(reader, &e, context)?);
                     }
+                    continue;
+                }
+                (Format, _) => match context.unknown_element(reader, &e)? {
+                    Some(xml) => PackageField::UnknownFormatElement(xml),
+                    None => continue,
+                },
+            },
+            Event::End(e) => match (state.position, e.name()) {
+                (Format, TAG_FORMAT) => {
+                    context.leave();
+                    state.position = Package;
+                    continue;
+                }
+                (Package, TAG_PACKAGE) => {
+                    state.position = Between;
+                    return Ok(Some(PackageEvent::End));
                 }
-                _ => (),
+                _ => continue,
             },
-            Event::Eof => break,
-            _ => (),
+            Event::Eof => return Ok(None),
             // TODO: match arms, make sure nothing falls through
-        }
-        buf.clear();
-        text_buf.clear();
+            _ => continue,
+        };
+        return Ok(Some(PackageEvent::Field(field)));
     }
-
-    // package.parse_state |= ParseState::PRIMARY;
-    Ok(())
 }
 
 pub struct PrimaryXmlWriter<W: Write> {
     writer: Writer<W>,
+    style: XmlStyle,
+    elements: PrimaryElements,
 }
 
 impl<W: Write> PrimaryXmlWriter<W> {
+    /// Set how the metadata is laid out and escaped. Must be set before the header is written.
+    pub fn set_style(&mut self, style: XmlStyle) {
+        self.style = style;
+    }
+
+    /// Set which of the optional elements of packages are written, see [`PrimaryElements`].
+    pub fn set_elements(&mut self, elements: PrimaryElements) {
+        self.elements = elements;
+    }
+
     pub fn write_header(&mut self, num_pkgs: usize) -> Result<(), MetadataError> {
         // <?xml version="1.0" encoding="UTF-8"?>
         self.writer
@@ -427,14 +659,20 @@ impl<W: Write> PrimaryXmlWriter<W> {
         metadata_tag.push_attribute(("xmlns", XML_NS_COMMON));
         metadata_tag.push_attribute(("xmlns:rpm", XML_NS_RPM));
         metadata_tag.push_attribute(("packages", num_pkgs.to_string().as_str()));
-        self.writer
-            .write_event(Event::Start(metadata_tag.to_borrowed()))?;
+        match self.style {
+            XmlStyle::Standard | XmlStyle::Legacy => self
+                .writer
+                .write_event(Event::Start(metadata_tag.to_borrowed()))?,
+            XmlStyle::CreaterepoC => {
+                utils::write_unindented_start(&mut self.writer, &metadata_tag)?
+            }
+        }
 
         Ok(())
     }
 
     pub fn write_package(&mut self, package: &Package) -> Result<(), MetadataError> {
-        write_package(&mut self.writer, package)?;
+        write_package(&mut self.writer, package, self.style, self.elements)?;
         Ok(())
     }
 
@@ -458,82 +696,128 @@ impl<W: Write> PrimaryXmlWriter<W> {
     }
 }
 
+/// Whether `path` is one of the files listed in primary.xml (and the primary database) as well as in
+/// filelists.xml.
+pub(crate) fn is_primary_file(path: &str) -> bool {
+    // strange algorithm, but it's what the original uses
+    path.starts_with("/etc/") || path.contains("bin/") || path.starts_with("/usr/lib/sendmail")
+}
+
 pub fn write_package<W: Write>(
     writer: &mut Writer<W>,
     package: &Package,
+    style: XmlStyle,
+    elements: PrimaryElements,
 ) -> Result<(), MetadataError> {
     // <package type="rpm">
+    let no_unknown_xml = UnknownPackageXml::default();
+    let unknown = package.unknown_xml().unwrap_or(&no_unknown_xml);
     let mut package_tag = BytesStart::borrowed_name(TAG_PACKAGE);
-    package_tag.push_attribute(("type", "rpm"));
+    package_tag.push_attribute(utils::package_attribute(style, "type", "rpm"));
+    unknown.primary.push_attributes(&mut package_tag);
     writer.write_event(Event::Start(package_tag.to_borrowed()))?;
 
     // <name>horse</name>
     writer
         .create_element(TAG_NAME)
-        .write_text_content(BytesText::from_plain_str(package.name()))?;
+        .write_text_content(utils::text(style, package.name()))?;
 
     // <arch>noarch</arch>
     writer
         .create_element(TAG_ARCH)
-        .write_text_content(BytesText::from_plain_str(package.arch()))?;
+        .write_text_content(utils::text(style, package.arch()))?;
 
     // <version epoch="0" ver="4.1" rel="1"/>
     let (epoch, version, release) = package.evr().values();
     writer
         .create_element(TAG_VERSION)
-        .with_attribute(("epoch", epoch))
-        .with_attribute(("ver", version))
-        .with_attribute(("rel", release))
+        .with_attribute(utils::package_attribute(style, "epoch", epoch))
+        .with_attribute(utils::package_attribute(style, "ver", version))
+        .with_attribute(utils::package_attribute(style, "rel", release))
         .write_empty()?;
 
     // <checksum type="sha256" pkgid="YES">6d0fd7f08cef63677726973d327e0b99f819b1983f90c2b656bb27cd2112cb7f</checksum>
-    let (checksum_type, checksum_value) = package.checksum().to_values()?;
+    let (checksum_type, checksum_value) = utils::checksum_values(style, package.checksum())?;
     writer
         .create_element(TAG_CHECKSUM)
-        .with_attribute(("type", checksum_type))
-        .with_attribute(("pkgid", "YES"))
-        .write_text_content(BytesText::from_plain_str(checksum_value))?;
+        .with_attribute(utils::package_attribute(style, "type", checksum_type))
+        .with_attribute(utils::package_attribute(style, "pkgid", "YES"))
+        .write_text_content(utils::text(style, checksum_value))?;
 
     // <summary>A dummy package of horse</summary>
     writer
         .create_element(TAG_SUMMARY)
-        .write_text_content(BytesText::from_plain_str(package.summary()))?;
+        .write_text_content(utils::text(style, package.summary()))?;
 
     // <description>A dummy package of horse</description>
     writer
         .create_element(TAG_DESCRIPTION)
-        .write_text_content(BytesText::from_plain_str(package.description()))?;
+        .write_text_content(utils::text(style, package.description()))?;
 
     // <packager>Bojack Horseman</packager>
-    writer
-        .create_element(TAG_PACKAGER)
-        .write_text_content(BytesText::from_plain_str(package.packager()))?;
+    if elements.packager.writes(package.packager().is_empty()) {
+        writer
+            .create_element(TAG_PACKAGER)
+            .write_text_content(utils::text(style, package.packager()))?;
+    }
 
     // <url>http://arandomaddress.com</url>
     writer
         .create_element(TAG_URL)
-        .write_text_content(BytesText::from_plain_str(package.url()))?;
+        .write_text_content(utils::text(style, package.url()))?;
 
     // <time file="1615451135" build="1331831374"/>
     writer
         .create_element(TAG_TIME)
-        .with_attribute(("file", package.time_file().to_string().as_str()))
-        .with_attribute(("build", package.time_build().to_string().as_str()))
+        .with_attribute(utils::package_attribute(
+            style,
+            "file",
+            package.time_file().to_string().as_str(),
+        ))
+        .with_attribute(utils::package_attribute(
+            style,
+            "build",
+            package.time_build().to_string().as_str(),
+        ))
         .write_empty()?;
 
     // <size package="1846" installed="42" archive="296"/>
     writer
         .create_element(TAG_SIZE)
-        .with_attribute(("package", package.size_package().to_string().as_str()))
-        .with_attribute(("installed", package.size_installed().to_string().as_str()))
-        .with_attribute(("archive", package.size_archive().to_string().as_str()))
+        .with_attribute(utils::package_attribute(
+            style,
+            "package",
+            package.size_package().to_string().as_str(),
+        ))
+        .with_attribute(utils::package_attribute(
+            style,
+            "installed",
+            package.size_installed().to_string().as_str(),
+        ))
+        .with_attribute(utils::package_attribute(
+            style,
+            "archive",
+            package.size_archive().to_string().as_str(),
+        ))
         .write_empty()?;
 
     // <location href="horse-4.1-1.noarch.rpm"/>
-    writer
-        .create_element(TAG_LOCATION)
-        .with_attribute(("href", package.location_href()))
-        .write_empty()?;
+    let mut location_tag = BytesStart::borrowed_name(TAG_LOCATION);
+    if let (XmlStyle::CreaterepoC, Some(base)) = (style, package.location_base()) {
+        // createrepo_c turns local paths into URLs
+        let base = if base.starts_with('/') {
+            format!("file://{}", base)
+        } else {
+            base.to_owned()
+        };
+        location_tag.push_attribute(utils::package_attribute(style, "xml:base", &base));
+    }
+    location_tag.push_attribute(utils::package_attribute(
+        style,
+        "href",
+        package.location_href(),
+    ));
+    writer.write_event(Event::Empty(location_tag))?;
 
     // <format>
     let format_tag = BytesStart::borrowed_name(TAG_FORMAT);
@@ -542,66 +826,78 @@ pub fn write_package<W: Write>(
     // <rpm:license>GPLv2</rpm:license>
     writer
         .create_element(TAG_RPM_LICENSE)
-        .write_text_content(BytesText::from_plain_str(package.rpm_license()))?;
+        .write_text_content(utils::text(style, package.rpm_license()))?;
 
     // <rpm:vendor></rpm:vendor>
     writer
         .create_element(TAG_RPM_VENDOR)
-        .write_text_content(BytesText::from_plain_str(package.rpm_vendor()))?;
+        .write_text_content(utils::text(style, package.rpm_vendor()))?;
 
     // <rpm:group>Internet/Applications</rpm:group>
     writer
         .create_element(TAG_RPM_GROUP)
-        .write_text_content(BytesText::from_plain_str(&package.rpm_group()))?;
+        .write_text_content(utils::text(style, package.rpm_group()))?;
 
     // <rpm:buildhost>smqe-ws15</rpm:buildhost>
-    writer
-        .create_element(TAG_RPM_BUILDHOST)
-        .write_text_content(BytesText::from_plain_str(&package.rpm_buildhost()))?;
+    if elements
+        .buildhost
+        .writes(package.rpm_buildhost().is_empty())
+    {
+        writer
+            .create_element(TAG_RPM_BUILDHOST)
+            .write_text_content(utils::text(style, package.rpm_buildhost()))?;
+    }
 
     // <rpm:sourcerpm>horse-4.1-1.src.rpm</rpm:sourcerpm>
     writer
         .create_element(TAG_RPM_SOURCERPM)
-        .write_text_content(BytesText::from_plain_str(&package.rpm_sourcerpm()))?;
+        .write_text_content(utils::text(style, package.rpm_sourcerpm()))?;
 
     // <rpm:header-range start="280" end="1697"/>
-    let header_start = package.rpm_header_range().start.to_string();
-    let header_end = package.rpm_header_range().end.to_string();
-    writer
-        .create_element(TAG_RPM_HEADER_RANGE)
-        .with_attribute(("start", header_start.as_str()))
-        .with_attribute(("end", header_end.as_str()))
-        .write_empty()?;
+    let header_range = package.rpm_header_range();
+    if elements
+        .header_range
+        .writes(header_range.start == 0 && header_range.end == 0)
+    {
+        let header_start = header_range.start.to_string();
+        let header_end = header_range.end.to_string();
+        writer
+            .create_element(TAG_RPM_HEADER_RANGE)
+            .with_attribute(utils::package_attribute(
+                style,
+                "start",
+                header_start.as_str(),
+            ))
+            .with_attribute(utils::package_attribute(style, "end", header_end.as_str()))
+            .write_empty()?;
+    }
 
     // <rpm:supplements>
     //   <rpm:entry name="horse" flags="EQ" epoch="0" ver="4.1" rel="1"/>
     // </rpm:supplements>
-    write_requirement_section(writer, TAG_RPM_PROVIDES, package.provides())?;
-    write_requirement_section(writer, TAG_RPM_REQUIRES, package.requires())?;
-    write_requirement_section(writer, TAG_RPM_CONFLICTS, package.conflicts())?;
-    write_requirement_section(writer, TAG_RPM_OBSOLETES, package.obsoletes())?;
-    write_requirement_section(writer, TAG_RPM_SUGGESTS, package.suggests())?;
-    write_requirement_section(writer, TAG_RPM_ENHANCES, package.enhances())?;
-    write_requirement_section(writer, TAG_RPM_RECOMMENDS, package.recommends())?;
-    write_requirement_section(writer, TAG_RPM_SUPPLEMENTS, package.supplements())?;
-
-    fn include_file(f: &PackageFile) -> bool {
-        // strange algorithm, but it's what the original uses
-        f.path.starts_with("/etc/")
-            || f.path.contains("bin/")
-            || f.path.starts_with("/usr/lib/sendmail")
-    }
+    write_requirement_section(writer, style, TAG_RPM_PROVIDES, package.provides())?;
+    write_requirement_section(writer, style, TAG_RPM_REQUIRES, package.requires())?;
+    write_requirement_section(writer, style, TAG_RPM_CONFLICTS, package.conflicts())?;
+    write_requirement_section(writer, style, TAG_RPM_OBSOLETES, package.obsoletes())?;
+    write_requirement_section(writer, style, TAG_RPM_SUGGESTS, package.suggests())?;
+    write_requirement_section(writer, style, TAG_RPM_ENHANCES, package.enhances())?;
+    write_requirement_section(writer, style, TAG_RPM_RECOMMENDS, package.recommends())?;
+    write_requirement_section(writer, style, TAG_RPM_SUPPLEMENTS, package.supplements())?;
 
     // <file>/usr/bin/bash</file>
     package
-        .files()
+        .load_files()
         .iter()
-        .filter(|&f| include_file(f))
-        .try_for_each(|f| filelist::write_file_element(writer, f))?;
+        .filter(|&f| is_primary_file(&f.path))
+        .try_for_each(|f| filelist::write_file_element(writer, f, style))?;
+
+    unknown.format.write_elements(writer)?;
 
     // </format>
     writer.write_event(Event::End(format_tag.to_end()))?;
 
+    unknown.primary.write_elements(writer)?;
+
     // </package>
     writer.write_event(Event::End(package_tag.to_end()))?;
 
@@ -611,8 +907,9 @@ pub fn write_package<W: Write>(
 // <rpm:supplements>
 //   <rpm:entry name="horse" flags="EQ" epoch="0" ver="4.1" rel="1"/>
 // </rpm:supplements>
-fn write_requirement_section<W: Write, N: AsRef<[u8]> + Sized>(
+pub(crate) fn write_requirement_section<W: Write, N: AsRef<[u8]> + Sized>(
     writer: &mut Writer<W>,
+    style: XmlStyle,
     section_name: N,
     entry_list: &[Requirement],
 ) -> Result<(), MetadataError> {
@@ -626,25 +923,25 @@ fn write_requirement_section<W: Write, N: AsRef<[u8]> + Sized>(
 
     for entry in entry_list {
         let mut entry_tag = BytesStart::borrowed_name(b"rpm:entry");
-        entry_tag.push_attribute(("name", entry.name.as_str()));
+        entry_tag.push_attribute(utils::package_attribute(style, "name", entry.name.as_str()));
 
         if let Some(flags) = &entry.flags {
-            entry_tag.push_attribute(("flags", flags.as_str()));
+            entry_tag.push_attribute(utils::package_attribute(style, "flags", flags.as_str()));
         }
 
         if let Some(epoch) = &entry.epoch {
-            entry_tag.push_attribute(("epoch", epoch.as_str()));
+            entry_tag.push_attribute(utils::package_attribute(style, "epoch", epoch.as_str()));
         }
 
         if let Some(version) = &entry.version {
-            entry_tag.push_attribute(("ver", version.as_str()));
+            entry_tag.push_attribute(utils::package_attribute(style, "ver", version.as_str()));
         }
 
         if let Some(release) = &entry.release {
-            entry_tag.push_attribute(("rel", release.as_str()));
+            entry_tag.push_attribute(utils::package_attribute(style, "rel", release.as_str()));
         }
         if entry.preinstall {
-            entry_tag.push_attribute(("pre", "1"));
+            entry_tag.push_attribute(utils::package_attribute(style, "pre", "1"));
         }
         writer.write_event(Event::Empty(entry_tag))?;
     }
@@ -657,19 +954,21 @@ fn write_requirement_section<W: Write, N: AsRef<[u8]> + Sized>(
 pub fn parse_requirement_list<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &mut ParseContext,
 ) -> Result<Vec<Requirement>, MetadataError> {
     let mut list = Vec::with_capacity(10);
 
     // TODO: another hot allocation
     let mut buf = Vec::with_capacity(128);
 
+    context.enter(open_tag.name());
     loop {
         match reader.read_event(&mut buf)? {
-            Event::Start(e) if e.name().as_ref() == TAG_RPM_ENTRY => {
+            Event::Start(e) if e.name() == TAG_RPM_ENTRY => {
                 let mut requirement = Requirement::default();
                 for attr in e.attributes() {
-                    let attr = attr.map_err(|e| quick_xml::Error::from(e))?;
-                    match attr.key.as_ref() {
+                    let attr = attr.map_err(quick_xml::Error::from)?;
+                    match attr.key {
                         b"name" => {
                             requirement.name = attr.unescape_and_decode_value(reader)?;
                         }
@@ -688,21 +987,21 @@ pub fn parse_requirement_list<R: BufRead>(
                         b"pre" => {
                             requirement.preinstall = attr
                                 .unescape_and_decode_value(reader)
-                                .ok()
-                                .filter(|val| val != "0" && !val.eq_ignore_ascii_case("false"))
-                                .is_some()
+                                .is_ok_and(|val| utils::parse_flag(&val))
                         }
-                        a @ _ => {
-                            return Err(MetadataError::UnknownAttributeError(format!(
+                        a => {
+                            context.deviation(MetadataError::UnknownAttributeError(format!(
                                 "unrecognized attribute {}",
                                 std::str::from_utf8(a)?
-                            )))
+                            )))?;
                         }
                     }
                 }
 
                 if requirement.name.is_empty() {
-                    return Err(MetadataError::MissingAttributeError("name"));
+                    // an entry without a name is meaningless, so it's dropped in lenient mode
+                    context.deviation(MetadataError::MissingAttributeError("name"))?;
+                    continue;
                 }
 
                 list.push(requirement);
@@ -711,6 +1010,7 @@ pub fn parse_requirement_list<R: BufRead>(
             _ => (), // TODO
         }
     }
+    context.leave();
 
     Ok(list)
 }
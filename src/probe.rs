@@ -0,0 +1,221 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{utils, Checksum, ChecksumType, MetadataError, MetadataType, RepomdRecord, RepomdXml};
+
+/// What a repository's `repomd.xml` says about it, as found by [`probe_repository()`], without reading
+/// any of its other metadata.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepositoryProbe {
+    /// The SHA-256 checksum of `repomd.xml` itself, which changes with any change to the repository
+    pub checksum: Checksum,
+    pub revision: Option<String>,
+    /// The newest timestamp of the records, if there are any
+    pub timestamp: Option<i64>,
+    /// The records of the metadata files, with their types, timestamps and checksums
+    pub records: Vec<RepomdRecord>,
+}
+
+impl RepositoryProbe {
+    /// Parse the contents of a `repomd.xml` file.
+    pub fn from_bytes(repomd: &[u8]) -> Result<Self, MetadataError> {
+        let data = RepomdXml::read_data(utils::create_xml_reader(repomd))?;
+        let records = data.records().clone();
+        Ok(Self {
+            checksum: utils::checksum_bytes(repomd, ChecksumType::Sha256)?,
+            revision: data.revision().map(str::to_owned),
+            timestamp: records.iter().map(|record| record.timestamp).max(),
+            records,
+        })
+    }
+
+    /// The types of metadata the repository has, in the order of `repomd.xml`.
+    pub fn metadata_types(&self) -> impl Iterator<Item = &MetadataType> {
+        self.records.iter().map(|record| &record.metadata_type)
+    }
+
+    pub fn record(&self, metadata_type: &MetadataType) -> Option<&RepomdRecord> {
+        self.records
+            .iter()
+            .find(|record| record.metadata_type == *metadata_type)
+    }
+
+    /// Whether `repomd.xml` has changed since `previous` was probed, and with it any of the metadata.
+    pub fn has_changed_since(&self, previous: &RepositoryProbe) -> bool {
+        self.checksum != previous.checksum
+    }
+}
+
+/// A change in a repository between two probes, found by [`RepoMonitor::update()`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepoChange {
+    /// The revision in `repomd.xml` changed
+    RevisionChanged {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// A type of metadata was added
+    RecordAdded(MetadataType),
+    /// A metadata file changed
+    RecordUpdated(MetadataType),
+    /// A type of metadata disappeared
+    RecordRemoved(MetadataType),
+    /// The timestamp of a record went backwards, as when a mirror serves an older copy of the repository
+    TimestampRegressed {
+        metadata_type: MetadataType,
+        from: i64,
+        to: i64,
+    },
+    /// A record has a weaker type of checksum than before
+    ChecksumDowngraded {
+        metadata_type: MetadataType,
+        from: ChecksumType,
+        to: ChecksumType,
+    },
+}
+
+impl RepoChange {
+    /// Whether the change suggests something went wrong with the repository or its mirror, rather than
+    /// it having been updated: records disappearing, timestamps going backwards and checksums getting
+    /// weaker.
+    pub fn is_regression(&self) -> bool {
+        matches!(
+            self,
+            RepoChange::RecordRemoved(_)
+                | RepoChange::TimestampRegressed { .. }
+                | RepoChange::ChecksumDowngraded { .. }
+        )
+    }
+}
+
+/// Keeps track of the state of a repository across probes, to find out what changed each time it's
+/// polled, e.g. for a dashboard of the health of mirrors.
+///
+/// The monitor remembers the latest probe, whether or not it was a regression, so that each regression is
+/// only reported once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoMonitor {
+    last: Option<RepositoryProbe>,
+}
+
+impl RepoMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from a probe made earlier, e.g. one saved by a previous run.
+    pub fn from_probe(probe: RepositoryProbe) -> Self {
+        Self { last: Some(probe) }
+    }
+
+    /// The latest probe, if there was one.
+    pub fn last(&self) -> Option<&RepositoryProbe> {
+        self.last.as_ref()
+    }
+
+    /// Probe the repository at `location` with [`probe_repository()`] and [`update()`](Self::update) the
+    /// monitor with it.
+    pub fn poll(&mut self, location: &str) -> Result<Vec<RepoChange>, MetadataError> {
+        Ok(self.update(probe_repository(location)?))
+    }
+
+    /// The changes since the latest probe, in the order of the records of `repomd.xml` and with removed
+    /// records last, and remember `probe` as the latest. There are none for the first probe or if `repomd.xml` hasn't changed.
+    pub fn update(&mut self, probe: RepositoryProbe) -> Vec<RepoChange> {
+        let changes = match &self.last {
+            Some(last) => changes_between(last, &probe),
+            None => Vec::new(),
+        };
+        self.last = Some(probe);
+        changes
+    }
+}
+
+/// The changes from the probe `last` to `probe`.
+fn changes_between(last: &RepositoryProbe, probe: &RepositoryProbe) -> Vec<RepoChange> {
+    let mut changes = Vec::new();
+    if !probe.has_changed_since(last) {
+        return changes;
+    }
+
+    if probe.revision != last.revision {
+        changes.push(RepoChange::RevisionChanged {
+            from: last.revision.clone(),
+            to: probe.revision.clone(),
+        });
+    }
+    for record in &probe.records {
+        let metadata_type = &record.metadata_type;
+        let Some(previous) = last.record(metadata_type) else {
+            changes.push(RepoChange::RecordAdded(metadata_type.clone()));
+            continue;
+        };
+        if record.checksum != previous.checksum {
+            changes.push(RepoChange::RecordUpdated(metadata_type.clone()));
+        }
+        if record.timestamp < previous.timestamp {
+            changes.push(RepoChange::TimestampRegressed {
+                metadata_type: metadata_type.clone(),
+                from: previous.timestamp,
+                to: record.timestamp,
+            });
+        }
+        let (from, to) = (
+            previous.checksum.checksum_type(),
+            record.checksum.checksum_type(),
+        );
+        if strength(to) < strength(from) {
+            changes.push(RepoChange::ChecksumDowngraded {
+                metadata_type: metadata_type.clone(),
+                from,
+                to,
+            });
+        }
+    }
+    for record in &last.records {
+        if probe.record(&record.metadata_type).is_none() {
+            changes.push(RepoChange::RecordRemoved(record.metadata_type.clone()));
+        }
+    }
+    changes
+}
+
+/// How hard a type of checksum is to forge, from unknown types to SHA-512.
+fn strength(checksum_type: ChecksumType) -> u8 {
+    match checksum_type {
+        ChecksumType::Unknown => 0,
+        ChecksumType::Md5 => 1,
+        ChecksumType::Sha1 => 2,
+        ChecksumType::Sha224 => 3,
+        ChecksumType::Sha256 => 4,
+        ChecksumType::Sha384 => 5,
+        ChecksumType::Sha512 => 6,
+    }
+}
+
+/// Read only the `repomd.xml` of the repository at `location`, to cheaply find out whether it has changed
+/// and what metadata it has, e.g. when polling many repositories.
+///
+/// `location` is either the path of a local repository (the directory containing `repodata/`), a
+/// `file://` URL, or with the `download` feature an `http://` URL. For `https://` URLs or mirrorlists,
+/// use `Downloader::probe()` with a suitable transport instead.
+pub fn probe_repository(location: &str) -> Result<RepositoryProbe, MetadataError> {
+    let path = location.strip_prefix("file://").unwrap_or(location);
+    if path.contains("://") {
+        #[cfg(feature = "download")]
+        return crate::Downloader::new(location).probe();
+        #[cfg(not(feature = "download"))]
+        return Err(MetadataError::InvalidFieldError(
+            "local repository path",
+            location.to_owned(),
+        ));
+    }
+    let repomd = fs::read(Path::new(path).join("repodata").join("repomd.xml"))?;
+    RepositoryProbe::from_bytes(&repomd)
+}
@@ -4,10 +4,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+// pyo3 0.20's macros expand to impls which this newer lint doesn't expect
+#![allow(non_local_definitions)]
+
 use std::fmt;
 use std::path::PathBuf;
 
-use pyo3;
 use pyo3::basic::CompareOp;
 use pyo3::prelude::*;
 
@@ -56,14 +58,15 @@ impl Repository {
     fn write_to_directory(&self, path: PathBuf) -> PyResult<()> {
         let options = crate::RepositoryOptions::default();
 
-        self.inner.write_to_directory(&path, options)?;
+        self.inner.write_to_directory_with_options(&path, options)?;
         Ok(())
     }
 }
 
 #[pyclass]
 struct RepositoryWriter {
-    inner: crate::RepositoryWriter,
+    // taken by `finish()`
+    inner: Option<crate::RepositoryWriter>,
 }
 
 #[pymethods]
@@ -71,21 +74,32 @@ impl RepositoryWriter {
     #[new]
     fn new(path: PathBuf, num_pkgs: usize) -> PyResult<Self> {
         let repo_writer = crate::RepositoryWriter::new(&path, num_pkgs)?;
-        let py_repo_writer = RepositoryWriter { inner: repo_writer };
+        let py_repo_writer = RepositoryWriter {
+            inner: Some(repo_writer),
+        };
         Ok(py_repo_writer)
     }
 
     fn add_package(&mut self, pkg: &Package) -> PyResult<()> {
-        self.inner.add_package(&pkg.inner)?;
+        self.writer()?.add_package(&pkg.inner)?;
         Ok(())
     }
 
     fn finish(&mut self) -> PyResult<()> {
-        self.inner.finish()?;
+        self.writer()?;
+        self.inner.take().unwrap().finish()?;
         Ok(())
     }
 }
 
+impl RepositoryWriter {
+    fn writer(&mut self) -> PyResult<&mut crate::RepositoryWriter> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| MetadataError::new_err("the repository writer is already finished"))
+    }
+}
+
 #[pyclass]
 struct RepositoryReader {
     inner: crate::RepositoryReader,
@@ -107,9 +121,11 @@ impl RepositoryReader {
     }
 
     fn iter_advisories(&self) -> PyResult<UpdateinfoReader> {
-        let pkg_reader = self.inner.iter_packages()?;
-        let py_pkg_reader = UpdateinfoReader { inner: pkg_reader };
-        Ok(py_pkg_reader)
+        let advisory_reader = self.inner.iter_advisories()?;
+        let py_advisory_reader = UpdateinfoReader {
+            inner: advisory_reader,
+        };
+        Ok(py_advisory_reader)
     }
 }
 #[pyclass]
@@ -138,8 +154,8 @@ impl Package {
         self.inner.nevra_short()
     }
 
-    fn evr(&self) -> EVR {
-        EVR {
+    fn evr(&self) -> Evr {
+        Evr {
             inner: self.inner.evr.clone(),
         }
     }
@@ -155,12 +171,12 @@ impl Package {
     }
 
     #[setter(epoch)]
-    fn set_epoch(&mut self, epoch: &str) {
+    fn set_epoch(&mut self, epoch: u32) {
         self.inner.set_epoch(epoch);
     }
 
     #[getter(epoch)]
-    fn epoch(&self) -> &str {
+    fn epoch(&self) -> u32 {
         self.inner.epoch()
     }
 
@@ -391,7 +407,7 @@ impl Package {
     pub fn set_requires(&mut self, requires: Vec<RequirementTuple>) {
         let requires: Vec<_> = requires
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_requires(requires);
     }
@@ -401,7 +417,7 @@ impl Package {
         self.inner
             .requires()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -409,7 +425,7 @@ impl Package {
     pub fn set_provides(&mut self, provides: Vec<RequirementTuple>) {
         let provides: Vec<_> = provides
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_provides(provides);
     }
@@ -419,7 +435,7 @@ impl Package {
         self.inner
             .provides()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -427,7 +443,7 @@ impl Package {
     pub fn set_conflicts(&mut self, conflicts: Vec<RequirementTuple>) {
         let conflicts: Vec<_> = conflicts
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_conflicts(conflicts);
     }
@@ -437,7 +453,7 @@ impl Package {
         self.inner
             .conflicts()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -445,7 +461,7 @@ impl Package {
     pub fn set_obsoletes(&mut self, obsoletes: Vec<RequirementTuple>) {
         let obsoletes: Vec<_> = obsoletes
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_obsoletes(obsoletes);
     }
@@ -455,7 +471,7 @@ impl Package {
         self.inner
             .obsoletes()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -463,7 +479,7 @@ impl Package {
     pub fn set_suggests(&mut self, suggests: Vec<RequirementTuple>) {
         let suggests: Vec<_> = suggests
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_suggests(suggests);
     }
@@ -473,7 +489,7 @@ impl Package {
         self.inner
             .suggests()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -481,7 +497,7 @@ impl Package {
     pub fn set_enhances(&mut self, enhances: Vec<RequirementTuple>) {
         let enhances: Vec<_> = enhances
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_enhances(enhances);
     }
@@ -491,7 +507,7 @@ impl Package {
         self.inner
             .enhances()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -499,7 +515,7 @@ impl Package {
     pub fn set_recommends(&mut self, recommends: Vec<RequirementTuple>) {
         let recommends: Vec<_> = recommends
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_recommends(recommends);
     }
@@ -509,7 +525,7 @@ impl Package {
         self.inner
             .recommends()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -517,7 +533,7 @@ impl Package {
     pub fn set_supplements(&mut self, supplements: Vec<RequirementTuple>) {
         let supplements: Vec<_> = supplements
             .iter()
-            .map(|r| crate::metadata::Requirement::from(r))
+            .map(crate::metadata::Requirement::from)
             .collect();
         self.inner.set_supplements(supplements);
     }
@@ -527,7 +543,7 @@ impl Package {
         self.inner
             .supplements()
             .iter()
-            .map(|r| RequirementTuple::from(r))
+            .map(RequirementTuple::from)
             .collect()
     }
 
@@ -544,9 +560,9 @@ impl Package {
     #[getter(files)]
     pub fn files(&self) -> Vec<FileTuple> {
         self.inner
-            .files()
+            .load_files()
             .iter()
-            .map(|r| FileTuple::from(r))
+            .map(FileTuple::from)
             .collect()
     }
 
@@ -563,9 +579,9 @@ impl Package {
     #[getter(files_split)]
     pub fn files_split(&self) -> Vec<CrFileTuple> {
         self.inner
-            .files()
+            .load_files()
             .iter()
-            .map(|r| CrFileTuple::from(r))
+            .map(CrFileTuple::from)
             .collect()
     }
 
@@ -573,18 +589,19 @@ impl Package {
     pub fn set_changelogs(&mut self, changelog_tuples: Vec<ChangelogTuple>) {
         let changelogs: Vec<_> = changelog_tuples
             .into_iter()
-            .map(|r| crate::metadata::Changelog::from(r))
+            .map(crate::metadata::Changelog::from)
             .collect();
         self.inner.set_changelogs(changelogs);
     }
 
     #[getter(changelogs)]
-    pub fn changelogs(&self) -> Vec<ChangelogTuple> {
-        self.inner
-            .changelogs()
+    pub fn changelogs(&self) -> PyResult<Vec<ChangelogTuple>> {
+        Ok(self
+            .inner
+            .load_changelogs()?
             .iter()
-            .map(|r| ChangelogTuple::from(r))
-            .collect()
+            .map(ChangelogTuple::from)
+            .collect())
     }
 
     fn __str__(&self) -> PyResult<String> {
@@ -634,7 +651,7 @@ impl From<&crate::metadata::Requirement> for RequirementTuple {
             req.epoch.clone(),
             req.version.clone(),
             req.release.clone(),
-            req.preinstall.clone(),
+            req.preinstall,
         )
     }
 }
@@ -646,7 +663,7 @@ impl From<ChangelogTuple> for crate::metadata::Changelog {
     fn from(tuple: ChangelogTuple) -> Self {
         crate::metadata::Changelog {
             author: tuple.0,
-            date: tuple.1,
+            timestamp: tuple.1,
             description: tuple.2,
         }
     }
@@ -656,7 +673,7 @@ impl From<&crate::metadata::Changelog> for ChangelogTuple {
     fn from(changelog: &crate::metadata::Changelog) -> Self {
         (
             changelog.author.clone(),
-            changelog.date,
+            changelog.timestamp,
             changelog.description.clone(),
         )
     }
@@ -746,7 +763,7 @@ impl From<&crate::metadata::PackageFile> for CrFileTuple {
 
 #[pyclass]
 struct PackageReader {
-    inner: crate::PackageReader,
+    inner: crate::PackageIterator,
 }
 
 #[pymethods]
@@ -754,7 +771,7 @@ impl PackageReader {
     #[new]
     fn new(primary_path: PathBuf, filelists_path: PathBuf, other_path: PathBuf) -> PyResult<Self> {
         let py_pkg_reader = Self {
-            inner: crate::PackageReader::from_files(&primary_path, &filelists_path, &other_path)?,
+            inner: crate::PackageIterator::from_files(&primary_path, &filelists_path, &other_path)?,
         };
         Ok(py_pkg_reader)
     }
@@ -790,13 +807,13 @@ impl PackageReader {
 
 #[pyclass]
 struct UpdateinfoReader {
-    inner: crate::UpdateinfoIterator,
+    inner: crate::repository::UpdateinfoIterator,
 }
 
 #[pymethods]
 impl UpdateinfoReader {
     fn parse_updaterecord(&mut self) -> PyResult<Option<UpdateRecord>> {
-        let rec = self.inner.parse_updaterecord()?;
+        let rec = self.inner.next().transpose()?;
         let py_rec = rec.map(|rec| UpdateRecord { inner: rec });
         Ok(py_rec)
     }
@@ -805,29 +822,247 @@ impl UpdateinfoReader {
         slf
     }
 
-    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<Package>> {
+    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<UpdateRecord>> {
         slf.parse_updaterecord()
     }
 }
 
 #[pyclass]
-struct UpdateinfoWriter {
-    inner: crate::UpdateinfoXmlWriter,
+struct UpdateRecord {
+    inner: crate::UpdateRecord,
 }
 
 #[pymethods]
-impl UpdateinfoWriter {}
+impl UpdateRecord {
+    #[getter]
+    fn fromstr(&self) -> &str {
+        &self.inner.from
+    }
+
+    #[getter]
+    fn status(&self) -> &str {
+        &self.inner.status
+    }
+
+    #[getter(r#type)]
+    fn update_type(&self) -> &str {
+        &self.inner.update_type
+    }
+
+    #[getter]
+    fn version(&self) -> &str {
+        &self.inner.version
+    }
+
+    #[getter]
+    fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    #[getter]
+    fn title(&self) -> &str {
+        &self.inner.title
+    }
+
+    #[getter]
+    fn issued_date(&self) -> Option<&str> {
+        self.inner.issued_date.as_deref()
+    }
+
+    #[getter]
+    fn updated_date(&self) -> Option<&str> {
+        self.inner.updated_date.as_deref()
+    }
+
+    #[getter]
+    fn rights(&self) -> &str {
+        &self.inner.rights
+    }
+
+    #[getter]
+    fn release(&self) -> &str {
+        &self.inner.release
+    }
+
+    #[getter]
+    fn pushcount(&self) -> Option<&str> {
+        self.inner.pushcount.as_deref()
+    }
+
+    #[getter]
+    fn severity(&self) -> &str {
+        &self.inner.severity
+    }
+
+    #[getter]
+    fn summary(&self) -> &str {
+        &self.inner.summary
+    }
+
+    #[getter]
+    fn description(&self) -> &str {
+        &self.inner.description
+    }
+
+    #[getter]
+    fn solution(&self) -> &str {
+        &self.inner.solution
+    }
+
+    #[getter]
+    fn references(&self) -> Vec<UpdateReference> {
+        self.inner
+            .references
+            .iter()
+            .map(|reference| UpdateReference {
+                inner: reference.clone(),
+            })
+            .collect()
+    }
+
+    #[getter]
+    fn collections(&self) -> Vec<UpdateCollection> {
+        self.inner
+            .pkglist
+            .iter()
+            .map(|collection| UpdateCollection {
+                inner: collection.clone(),
+            })
+            .collect()
+    }
+}
 
 #[pyclass]
-struct EVR {
+struct UpdateReference {
+    inner: crate::UpdateReference,
+}
+
+#[pymethods]
+impl UpdateReference {
+    #[getter]
+    fn href(&self) -> &str {
+        &self.inner.href
+    }
+
+    #[getter]
+    fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    #[getter(r#type)]
+    fn reftype(&self) -> &str {
+        &self.inner.reftype
+    }
+
+    #[getter]
+    fn title(&self) -> &str {
+        &self.inner.title
+    }
+}
+
+#[pyclass]
+struct UpdateCollection {
+    inner: crate::UpdateCollection,
+}
+
+#[pymethods]
+impl UpdateCollection {
+    #[getter]
+    fn shortname(&self) -> Option<&str> {
+        self.inner.shortname.as_deref()
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    #[getter]
+    fn packages(&self) -> Vec<UpdateCollectionPackage> {
+        self.inner
+            .packages
+            .iter()
+            .map(|package| UpdateCollectionPackage {
+                inner: package.clone(),
+            })
+            .collect()
+    }
+}
+
+#[pyclass]
+struct UpdateCollectionPackage {
+    inner: crate::UpdateCollectionPackage,
+}
+
+#[pymethods]
+impl UpdateCollectionPackage {
+    #[getter]
+    fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    #[getter]
+    fn version(&self) -> &str {
+        &self.inner.version
+    }
+
+    #[getter]
+    fn release(&self) -> &str {
+        &self.inner.release
+    }
+
+    #[getter]
+    fn epoch(&self) -> &str {
+        &self.inner.epoch
+    }
+
+    #[getter]
+    fn arch(&self) -> &str {
+        &self.inner.arch
+    }
+
+    #[getter]
+    fn src(&self) -> &str {
+        &self.inner.src
+    }
+
+    #[getter]
+    fn filename(&self) -> &str {
+        &self.inner.filename
+    }
+
+    #[getter]
+    fn sum(&self) -> Option<&str> {
+        self.checksum().map(|(_, sum)| sum)
+    }
+
+    #[getter]
+    fn sum_type(&self) -> Option<&str> {
+        self.checksum().map(|(sum_type, _)| sum_type)
+    }
+
+    #[getter]
+    fn reboot_suggested(&self) -> bool {
+        self.inner.reboot_suggested
+    }
+}
+
+impl UpdateCollectionPackage {
+    fn checksum(&self) -> Option<(&str, &str)> {
+        self.inner.checksum.as_ref()?.to_values().ok()
+    }
+}
+
+#[pyclass(name = "EVR")]
+struct Evr {
     inner: crate::EVR,
 }
 
 #[pymethods]
-impl EVR {
+impl Evr {
     #[new]
-    fn new(epoch: &str, version: &str, release: &str) -> EVR {
-        EVR {
+    fn new(epoch: &str, version: &str, release: &str) -> Evr {
+        Evr {
             inner: crate::EVR::new(epoch, version, release),
         }
     }
@@ -838,8 +1073,8 @@ impl EVR {
 
     #[staticmethod]
     fn parse(evr: &str) -> PyResult<Self> {
-        let py_evr = EVR {
-            inner: crate::EVR::parse_values(evr)?.try_into()?,
+        let py_evr = Evr {
+            inner: crate::EVR::parse(evr),
         };
         Ok(py_evr)
     }
@@ -879,7 +1114,7 @@ impl EVR {
     }
 }
 
-impl fmt::Display for EVR {
+impl fmt::Display for Evr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
@@ -892,15 +1127,19 @@ impl fmt::Display for EVR {
 }
 
 #[pymodule]
-fn rpmrepo_metadata(py: Python, m: &PyModule) -> PyResult<()> {
+fn rpmrepo_metadata(_py: Python, m: &PyModule) -> PyResult<()> {
     // m.add_class::<Repository>()?;
     // m.add_class::<RepositoryWriter>()?;
     m.add_class::<RepositoryReader>()?;
     // m.add_class::<RepositoryOptions>()?;
-    m.add_class::<EVR>()?;
+    m.add_class::<Evr>()?;
     m.add_class::<Package>()?;
     m.add_class::<PackageReader>()?;
     m.add_class::<UpdateinfoReader>()?;
+    m.add_class::<UpdateRecord>()?;
+    m.add_class::<UpdateReference>()?;
+    m.add_class::<UpdateCollection>()?;
+    m.add_class::<UpdateCollectionPackage>()?;
 
     // m.add_class::<RepomdXml>()?;
     // m.add_class::<PrimaryXml>()?;
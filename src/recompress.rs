@@ -0,0 +1,165 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::logging;
+use crate::runtime;
+use crate::{
+    utils, CompressionType, InvalidCharPolicy, MetadataError, MetadataType, RepomdData,
+    RepomdRecord, RepomdXml, XmlFormat, XmlStyle,
+};
+
+/// Options for [`recompress_repository()`].
+///
+/// - `compression` - The type of compression the metadata files are recompressed with.
+/// - `level` - The level of compression, from 0 to 9, or up to 21 for zstd.
+/// - `xml_style` - How the rewritten `repomd.xml` is laid out and escaped, see [`XmlStyle`].
+#[derive(Copy, Clone, Debug)]
+pub struct RecompressOptions {
+    pub compression: CompressionType,
+    pub level: u32,
+    pub xml_style: XmlStyle,
+}
+
+impl Default for RecompressOptions {
+    fn default() -> Self {
+        Self {
+            compression: CompressionType::Zstd,
+            level: 9,
+            xml_style: XmlStyle::Standard,
+        }
+    }
+}
+
+impl RecompressOptions {
+    pub fn compression(self, compression: CompressionType) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn level(self, level: u32) -> Self {
+        Self { level, ..self }
+    }
+
+    pub fn xml_style(self, style: XmlStyle) -> Self {
+        Self {
+            xml_style: style,
+            ..self
+        }
+    }
+}
+
+/// Recompress the metadata files of the repository at `path` (the directory containing `repodata/`),
+/// e.g. from gzip to zstd, and rewrite `repomd.xml` with their new names, sizes and checksums. The
+/// contents of the files are copied as they are, without parsing any packages.
+///
+/// Zchunk files, and the `group` and `group_gz` files whose type says how they're compressed, are
+/// kept as they are. Files named with their checksum keep being named that way, and the old files
+/// are removed. A signature of `repomd.xml` doesn't match the rewritten one, and has to be redone.
+///
+/// Several files are recompressed at once, on threads drawn from the current [`Runtime`](crate::Runtime).
+pub fn recompress_repository(
+    path: &Path,
+    options: RecompressOptions,
+) -> Result<RepomdData, MetadataError> {
+    let _span = logging::span!("recompress {}", path.display());
+    let repomd_path = path.join("repodata").join("repomd.xml");
+    let mut repomd = RepomdXml::read_data(utils::xml_reader_from_file(&repomd_path)?)?;
+    let records: Vec<&mut RepomdRecord> = repomd
+        .records_mut()
+        .iter_mut()
+        .filter(|record| {
+            let metadata_type = &record.metadata_type;
+            !metadata_type.is_zchunk()
+                && !matches!(metadata_type, MetadataType::Group | MetadataType::GroupGz)
+        })
+        .collect();
+    let recompressed = runtime::map_concurrently(&records, 0, |record| {
+        let mut record = (**record).clone();
+        recompress_record(path, &mut record, options).map(|_| record)
+    });
+    for (record, recompressed) in records.into_iter().zip(recompressed) {
+        *record = recompressed?;
+    }
+
+    let (_, mut repomd_writer) = utils::filtered_xml_writer_for_path(
+        &repomd_path,
+        CompressionType::None,
+        InvalidCharPolicy::default(),
+        XmlFormat::default(),
+    )?;
+    RepomdXml::write_data_with_style(&repomd, &mut repomd_writer, options.xml_style)?;
+    Ok(repomd)
+}
+
+/// Recompress the file of `record` and update the record to match.
+fn recompress_record(
+    path: &Path,
+    record: &mut RepomdRecord,
+    options: RecompressOptions,
+) -> Result<(), MetadataError> {
+    let old_path = path.join(&record.location_href);
+    let file_name = record
+        .location_href
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (_, digest) = record.checksum.to_values()?;
+    let prefix = format!("{}-", digest);
+    let checksum_named = file_name.starts_with(&prefix);
+    let name = file_name.strip_prefix(&prefix).unwrap_or(&file_name);
+    let stem = [
+        CompressionType::Gzip,
+        CompressionType::Xz,
+        CompressionType::Bz2,
+        CompressionType::Zstd,
+    ]
+    .iter()
+    .find_map(|compression| name.strip_suffix(compression.to_file_extension()))
+    .unwrap_or(name);
+
+    let (tmp_path, mut writer) = utils::writer_to_file_with_level(
+        &old_path.with_file_name(format!(".{}.recompress", stem)),
+        options.compression,
+        options.level,
+    )?;
+    io::copy(&mut utils::reader_from_file(&old_path)?, &mut writer)?;
+    // the encoders only finish writing when they're dropped
+    drop(writer);
+
+    let checksum_type = record.checksum.checksum_type();
+    let recompressed =
+        RepomdRecord::from_file(record.metadata_type.clone(), &tmp_path, checksum_type)?;
+    let (_, new_digest) = recompressed.checksum.to_values()?;
+    let suffix = options.compression.to_file_extension();
+    let new_name = if checksum_named {
+        format!("{}-{}{}", new_digest, stem, suffix)
+    } else {
+        format!("{}{}", stem, suffix)
+    };
+    let new_path = old_path.with_file_name(&new_name);
+    fs::rename(&tmp_path, &new_path)?;
+    if new_path != old_path {
+        match fs::remove_file(&old_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+    }
+    logging::debug!("recompressed {} to {}", file_name, new_name);
+
+    record.location_href = record.location_href.with_file_name(new_name);
+    record.timestamp = recompressed.timestamp;
+    record.size = recompressed.size;
+    record.checksum = recompressed.checksum;
+    record.open_size = recompressed.open_size;
+    record.open_checksum = recompressed.open_checksum;
+    Ok(())
+}
@@ -16,9 +16,10 @@ use quick_xml::{Reader, Writer};
 
 use super::metadata::RepomdData;
 use super::metadata::{
-    Checksum, MetadataError, RepomdRecord, RepomdXml, RpmMetadata, XML_NS_REPO, XML_NS_RPM,
+    Checksum, MetadataError, MetadataType, ParseContext, ParseOptions, RepomdRecord, RepomdXml,
+    RpmMetadata, UnknownXml, XmlStyle, XML_NS_REPO, XML_NS_RPM,
 };
-use super::Repository;
+use super::{utils, Repository};
 
 // RepoMd
 const TAG_REPOMD: &[u8] = b"repomd";
@@ -49,7 +50,7 @@ impl RpmMetadata for RepomdXml {
         repository: &mut Repository,
         reader: Reader<R>,
     ) -> Result<(), MetadataError> {
-        read_repomd_xml(repository.repomd_mut(), reader)?;
+        read_repomd_xml(repository.repomd_mut(), reader, ParseOptions::default())?;
         Ok(())
     }
 
@@ -58,7 +59,7 @@ impl RpmMetadata for RepomdXml {
         writer: Writer<W>,
     ) -> Result<(), MetadataError> {
         let mut writer = writer;
-        write_repomd_xml(repository.repomd(), &mut writer)?;
+        write_repomd_xml(repository.repomd(), &mut writer, XmlStyle::Standard)?;
         Ok(())
     }
 }
@@ -68,19 +69,36 @@ impl RepomdXml {
         repomd_data: &RepomdData,
         writer: &mut Writer<W>,
     ) -> Result<(), MetadataError> {
-        write_repomd_xml(repomd_data, writer)
+        Self::write_data_with_style(repomd_data, writer, XmlStyle::Standard)
+    }
+
+    /// Like [`RepomdXml::write_data()`], laying out and escaping the metadata as `style` calls for.
+    pub fn write_data_with_style<W: Write>(
+        repomd_data: &RepomdData,
+        writer: &mut Writer<W>,
+        style: XmlStyle,
+    ) -> Result<(), MetadataError> {
+        write_repomd_xml(repomd_data, writer, style)
     }
 
     pub fn read_data<R: BufRead>(reader: Reader<R>) -> Result<RepomdData, MetadataError> {
+        Self::read_data_with_options(reader, ParseOptions::default())
+    }
+
+    /// Like [`RepomdXml::read_data()`]. Only [`ParseOptions::preserve_unknown`] applies to `repomd.xml`.
+    pub fn read_data_with_options<R: BufRead>(
+        reader: Reader<R>,
+        options: ParseOptions,
+    ) -> Result<RepomdData, MetadataError> {
         let mut repomd = RepomdData::default();
-        read_repomd_xml(&mut repomd, reader)?;
+        read_repomd_xml(&mut repomd, reader, options)?;
         Ok(repomd)
     }
 }
 
 #[derive(Debug, PartialEq, Default)]
 struct RepomdRecordBuilder {
-    metadata_name: String,
+    metadata_type: String,
     location_href: Option<PathBuf>,
     location_base: Option<String>,
     timestamp: Option<i64>,
@@ -91,6 +109,7 @@ struct RepomdRecordBuilder {
     header_size: Option<u64>,
     header_checksum: Option<Checksum>,
     database_version: Option<u32>,
+    unknown_xml: UnknownXml,
 }
 
 impl TryFrom<RepomdRecordBuilder> for RepomdRecord {
@@ -98,23 +117,24 @@ impl TryFrom<RepomdRecordBuilder> for RepomdRecord {
 
     fn try_from(builder: RepomdRecordBuilder) -> Result<Self, Self::Error> {
         let mut record = RepomdRecord::default();
-        record.metadata_name = builder.metadata_name;
+        record.metadata_type = builder.metadata_type.into();
         record.location_href = builder
             .location_href
-            .ok_or_else(|| MetadataError::MissingFieldError("location_href"))?;
+            .ok_or(MetadataError::MissingFieldError("location_href"))?;
         record.location_base = builder.location_base;
         record.timestamp = builder
             .timestamp
-            .ok_or_else(|| MetadataError::MissingFieldError("timestamp"))?;
+            .ok_or(MetadataError::MissingFieldError("timestamp"))?;
         record.size = builder.size;
         record.checksum = builder
             .checksum
-            .ok_or_else(|| MetadataError::MissingFieldError("checksum"))?;
+            .ok_or(MetadataError::MissingFieldError("checksum"))?;
         record.open_size = builder.open_size;
-        record.open_checksum = builder.open_checksum; // TODO: do these need to be conditionally required?
+        record.open_checksum = builder.open_checksum; // not written by createrepo on EL5
         record.header_size = builder.header_size;
         record.header_checksum = builder.header_checksum;
         record.database_version = builder.database_version; // TODO: get rid of this
+        record.unknown_xml = builder.unknown_xml;
 
         Ok(record)
     }
@@ -128,25 +148,29 @@ impl TryFrom<RepomdRecordBuilder> for RepomdRecord {
 fn read_repomd_xml<R: BufRead>(
     repomd_data: &mut RepomdData,
     reader: Reader<R>,
+    options: ParseOptions,
 ) -> Result<(), MetadataError> {
     let mut reader = reader;
     let mut event_buf = Vec::new();
     let mut text_buf = Vec::new();
+    let mut context = ParseContext::new("repomd", "repomd");
+    context.preserve_unknown = options.preserve_unknown;
 
     let mut found_metadata_tag = false;
 
     loop {
         match reader.read_event(&mut event_buf)? {
-            Event::Start(e) => match e.name().as_ref() {
+            Event::Start(e) => match e.name() {
                 TAG_REPOMD => {
                     found_metadata_tag = true;
+                    context.start_root(&reader, &e)?;
                 }
                 TAG_REVISION => {
                     let revision = reader.read_text(e.name(), &mut text_buf)?;
                     repomd_data.set_revision(&revision);
                 }
                 TAG_DATA => {
-                    let data = parse_repomdrecord(&mut reader, &e)?;
+                    let data = parse_repomdrecord(&mut reader, &e, &context)?;
                     repomd_data.add_record(data);
                 }
                 TAG_TAGS => {
@@ -157,11 +181,11 @@ fn read_repomd_xml<R: BufRead>(
                     //   </tags>
                     loop {
                         match reader.read_event(&mut event_buf)? {
-                            Event::Start(e) => match e.name().as_ref() {
+                            Event::Start(e) => match e.name() {
                                 TAG_DISTRO => {
-                                    let cpeid = (&e).try_get_attribute("cpeid")?.and_then(|a| {
-                                        a.unescape_and_decode_value(&mut reader).ok()
-                                    });
+                                    let cpeid = e
+                                        .try_get_attribute("cpeid")?
+                                        .and_then(|a| a.unescape_and_decode_value(&reader).ok());
                                     let name = reader.read_text(TAG_DISTRO, &mut text_buf)?;
                                     repomd_data.add_distro_tag(name, cpeid);
                                 }
@@ -176,7 +200,7 @@ fn read_repomd_xml<R: BufRead>(
                                 _ => (),
                             },
 
-                            Event::End(e) if e.name().as_ref() == TAG_TAGS => break,
+                            Event::End(e) if e.name() == TAG_TAGS => break,
                             _ => (),
                         }
                         text_buf.clear();
@@ -208,28 +232,31 @@ fn read_repomd_xml<R: BufRead>(
 pub fn parse_repomdrecord<R: BufRead>(
     reader: &mut Reader<R>,
     open_tag: &BytesStart,
+    context: &ParseContext,
 ) -> Result<RepomdRecord, MetadataError> {
     let mut record_builder = RepomdRecordBuilder::default();
+    record_builder.unknown_xml.attributes =
+        context.unknown_attributes(reader, open_tag, &["type"])?;
 
     let record_type = open_tag
         .try_get_attribute("type")?
-        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?
+        .ok_or(MetadataError::MissingAttributeError("type"))?
         .value
         .iter()
         .cloned()
         .collect();
-    record_builder.metadata_name = String::from_utf8(record_type).map_err(|e| e.utf8_error())?; // TODO weird conversion
+    record_builder.metadata_type = String::from_utf8(record_type).map_err(|e| e.utf8_error())?; // TODO weird conversion
 
     let mut buf = Vec::new();
     let mut record_buf = Vec::new();
 
     loop {
         match reader.read_event(&mut buf)? {
-            Event::Start(e) => match e.name().as_ref() {
+            Event::Start(e) => match e.name() {
                 TAG_CHECKSUM => {
                     let checksum_type = e
                         .try_get_attribute("type")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?;
+                        .ok_or(MetadataError::MissingAttributeError("type"))?;
                     let checksum_value = reader.read_text(e.name(), &mut record_buf)?;
                     let checksum = Checksum::try_create(
                         checksum_type.value.as_ref(),
@@ -240,7 +267,7 @@ pub fn parse_repomdrecord<R: BufRead>(
                 TAG_OPEN_CHECKSUM => {
                     let checksum_type = e
                         .try_get_attribute("type")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?;
+                        .ok_or(MetadataError::MissingAttributeError("type"))?;
                     let checksum_value = reader.read_text(e.name(), &mut record_buf)?;
                     let checksum = Checksum::try_create(
                         checksum_type.value.as_ref(),
@@ -251,7 +278,7 @@ pub fn parse_repomdrecord<R: BufRead>(
                 TAG_HEADER_CHECKSUM => {
                     let checksum_type = e
                         .try_get_attribute("type")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?;
+                        .ok_or(MetadataError::MissingAttributeError("type"))?;
                     let checksum_value = reader.read_text(e.name(), &mut record_buf)?;
                     let checksum = Checksum::try_create(
                         checksum_type.value.as_ref(),
@@ -262,7 +289,7 @@ pub fn parse_repomdrecord<R: BufRead>(
                 TAG_LOCATION => {
                     let location = e
                         .try_get_attribute("href")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("href"))?
+                        .ok_or(MetadataError::MissingAttributeError("href"))?
                         .unescape_and_decode_value(reader)?
                         .into();
                     record_builder.location_href = Some(location);
@@ -287,19 +314,24 @@ pub fn parse_repomdrecord<R: BufRead>(
                     let database_version = reader.read_text(e.name(), &mut record_buf)?.parse()?;
                     record_builder.database_version = Some(database_version);
                 }
-                _ => (),
+                _ => {
+                    if let Some(xml) = context.unknown_element(reader, &e)? {
+                        record_builder.unknown_xml.elements.push(xml);
+                    }
+                }
             },
-            Event::End(e) if e.name().as_ref() == TAG_DATA => break,
+            Event::End(e) if e.name() == TAG_DATA => break,
             _ => (),
         }
         record_buf.clear();
     }
-    Ok(record_builder.try_into()?)
+    record_builder.try_into()
 }
 
 fn write_repomd_xml<W: Write>(
     repomd_data: &RepomdData,
     writer: &mut Writer<W>,
+    style: XmlStyle,
 ) -> Result<(), MetadataError> {
     // <?xml version="1.0" encoding="UTF-8"?>
     writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
@@ -318,18 +350,24 @@ fn write_repomd_xml<W: Write>(
             .as_secs()
             .to_string()
     };
-    let revision = if let Some(revision) = repomd_data.revision() {
-        revision.to_owned()
-    } else {
-        get_current_time()
+    let revision = match (repomd_data.revision(), style) {
+        (Some(revision), _) => Some(revision.to_owned()),
+        (None, XmlStyle::Legacy) => None,
+        (None, _) => Some(get_current_time()),
     };
-    writer
-        .create_element(TAG_REVISION)
-        .write_text_content(BytesText::from_plain_str(revision.as_str()))?;
+    if let Some(revision) = revision {
+        writer
+            .create_element(TAG_REVISION)
+            .write_text_content(utils::text(style, revision.as_str()))?;
+    }
 
-    write_tags(repomd_data, writer)?;
-    for record in repomd_data.records() {
-        write_data(record, writer)?;
+    write_tags(repomd_data, writer, style)?;
+    let mut records: Vec<_> = repomd_data.records().iter().collect();
+    if style == XmlStyle::CreaterepoC {
+        records.sort_by_key(|record| createrepo_c_record_order(record));
+    }
+    for record in records {
+        write_data(record, writer, style)?;
     }
 
     // </repomd>
@@ -344,10 +382,11 @@ fn write_repomd_xml<W: Write>(
 ///   <repo>Fedora</repo>
 ///   <distro cpeid="cpe:/o:fedoraproject:fedora:33">Fedora 33</distro>
 ///   <content>binary-x86_64</content>
-//// </tags>
+/// </tags>
 fn write_tags<W: Write>(
     repomd_data: &RepomdData,
     writer: &mut Writer<W>,
+    style: XmlStyle,
 ) -> Result<(), MetadataError> {
     let has_distro_tags = !repomd_data.distro_tags().is_empty();
     let has_repo_tags = !repomd_data.repo_tags().is_empty();
@@ -362,24 +401,24 @@ fn write_tags<W: Write>(
             // <content>binary-x86_64</content>
             writer
                 .create_element(TAG_CONTENT)
-                .write_text_content(BytesText::from_plain_str(item))?;
+                .write_text_content(utils::text(style, item))?;
         }
 
         for item in repomd_data.repo_tags() {
             // <repo>Fedora</repo>
             writer
                 .create_element(TAG_REPO)
-                .write_text_content(BytesText::from_plain_str(item))?;
+                .write_text_content(utils::text(style, item))?;
         }
 
         for item in repomd_data.distro_tags() {
             // <distro cpeid="cpe:/o:fedoraproject:fedora:33">Fedora 33</distro>
             let mut distro_tag = BytesStart::borrowed_name(TAG_DISTRO);
             if let Some(cpeid) = &item.cpeid {
-                distro_tag.push_attribute(("cpeid", cpeid.as_str()))
+                distro_tag.push_attribute(utils::attribute(style, "cpeid", cpeid.as_str()))
             }
             writer.write_event(Event::Start(distro_tag.to_borrowed()))?;
-            writer.write_event(Event::Text(BytesText::from_plain_str(item.name.as_str())))?;
+            writer.write_event(Event::Text(utils::text(style, item.name.as_str())))?;
             writer.write_event(Event::End(distro_tag.to_end()))?;
         }
 
@@ -396,78 +435,111 @@ fn write_tags<W: Write>(
 ///    <size>5830735</size>
 ///    <open-size>53965949</open-size>
 ///  </data>
-fn write_data<W: Write>(data: &RepomdRecord, writer: &mut Writer<W>) -> Result<(), MetadataError> {
+/// The position of a record in a `repomd.xml` written by createrepo_c: the main metadata types first, the
+/// others after them by type, and then by location.
+fn createrepo_c_record_order(record: &RepomdRecord) -> (usize, &str, &PathBuf) {
+    const ORDER: &[MetadataType] = &[
+        MetadataType::Primary,
+        MetadataType::Filelists,
+        MetadataType::Other,
+        MetadataType::PrimaryDb,
+        MetadataType::FilelistsDb,
+        MetadataType::OtherDb,
+        MetadataType::PrimaryZck,
+        MetadataType::FilelistsZck,
+        MetadataType::OtherZck,
+    ];
+    let name = record.metadata_type.as_str();
+    let position = ORDER
+        .iter()
+        .position(|t| *t == record.metadata_type)
+        .unwrap_or(ORDER.len());
+    (position, name, &record.location_href)
+}
+
+fn write_data<W: Write>(
+    data: &RepomdRecord,
+    writer: &mut Writer<W>,
+    style: XmlStyle,
+) -> Result<(), MetadataError> {
     // <data>
     let mut data_tag = BytesStart::borrowed_name(TAG_DATA);
-    data_tag.push_attribute(("type".as_bytes(), data.metadata_name.as_bytes()));
+    data_tag.push_attribute(utils::attribute(style, "type", data.metadata_type.as_str()));
+    data.unknown_xml.push_attributes(&mut data_tag);
     writer.write_event(Event::Start(data_tag.to_borrowed()))?;
 
     // <checksum type="sha256">afdc6dc379e58d097ed0b350536812bc6a604bbce50c5c109d8d98e28301dc4b</checksum>
-    let (checksum_type, checksum_value) = data.checksum.to_values()?;
+    let (checksum_type, checksum_value) = utils::checksum_values(style, &data.checksum)?;
     writer
         .create_element(TAG_CHECKSUM)
-        .with_attribute(("type", checksum_type))
-        .write_text_content(BytesText::from_plain_str(checksum_value))?;
+        .with_attribute(utils::attribute(style, "type", checksum_type))
+        .write_text_content(utils::text(style, checksum_value))?;
 
     // <open-checksum type="sha256">afdc6dc379e58d097ed0b350536812bc6a604bbce50c5c109d8d98e28301dc4b</open-checksum> (maybe)
     if let Some(open_checksum) = &data.open_checksum {
-        let (checksum_type, checksum_value) = open_checksum.to_values()?;
+        let (checksum_type, checksum_value) = utils::checksum_values(style, open_checksum)?;
         writer
             .create_element(TAG_OPEN_CHECKSUM)
-            .with_attribute(("type", checksum_type))
-            .write_text_content(BytesText::from_plain_str(checksum_value))?;
+            .with_attribute(utils::attribute(style, "type", checksum_type))
+            .write_text_content(utils::text(style, checksum_value))?;
     }
 
     // <header-checksum type="sha256">afdc6dc379e58d097ed0b350536812bc6a604bbce50c5c109d8d98e28301dc4b</header-checksum> (maybe)
     if let Some(header_checksum) = &data.header_checksum {
-        let (checksum_type, checksum_value) = header_checksum.to_values()?;
+        let (checksum_type, checksum_value) = utils::checksum_values(style, header_checksum)?;
         writer
             .create_element(TAG_HEADER_CHECKSUM)
-            .with_attribute(("type", checksum_type))
-            .write_text_content(BytesText::from_plain_str(checksum_value))?;
+            .with_attribute(utils::attribute(style, "type", checksum_type))
+            .write_text_content(utils::text(style, checksum_value))?;
     }
 
     // <location href="repodata/primary.xml.gz">
-    writer
-        .create_element(TAG_LOCATION)
-        .with_attribute(("href".as_bytes(), data.location_href.as_os_str().as_bytes()))
-        .write_empty()?;
+    let mut location_tag = BytesStart::borrowed_name(TAG_LOCATION);
+    location_tag.push_attribute(utils::attribute(
+        style,
+        "href",
+        data.location_href.as_os_str().as_bytes(),
+    ));
+    if let (Some(location_base), XmlStyle::CreaterepoC) = (&data.location_base, style) {
+        location_tag.push_attribute(utils::attribute(style, "xml:base", location_base));
+    }
+    writer.write_event(Event::Empty(location_tag))?;
 
     // <timestamp>1602869947</timestamp>
     writer
         .create_element(TAG_TIMESTAMP)
-        .write_text_content(BytesText::from_plain_str(
-            data.timestamp.to_string().as_str(),
-        ))?;
+        .write_text_content(utils::text(style, data.timestamp.to_string().as_str()))?;
 
     // <size>123987</size> (maybe)
     if let Some(size) = data.size {
         writer
             .create_element(TAG_SIZE)
-            .write_text_content(BytesText::from_plain_str(&size.to_string()))?;
+            .write_text_content(utils::text(style, &size.to_string()))?;
     }
 
     // <open-size>68652</open-size> (maybe)
     if let Some(open_size) = data.open_size {
         writer
             .create_element(TAG_OPEN_SIZE)
-            .write_text_content(BytesText::from_plain_str(&open_size.to_string()))?;
+            .write_text_content(utils::text(style, &open_size.to_string()))?;
     }
 
     // <header-size>761487</header-size> (maybe)
     if let Some(size_header) = data.header_size {
         writer
             .create_element(TAG_HEADER_SIZE)
-            .write_text_content(BytesText::from_plain_str(&size_header.to_string()))?;
+            .write_text_content(utils::text(style, &size_header.to_string()))?;
     }
 
     // <database_version>10</database_version>
     if let Some(database_version) = data.database_version {
         writer
             .create_element(TAG_DATABASE_VERSION)
-            .write_text_content(BytesText::from_plain_str(&database_version.to_string()))?;
+            .write_text_content(utils::text(style, &database_version.to_string()))?;
     }
 
+    data.unknown_xml.write_elements(writer)?;
+
     // </data>
     writer.write_event(Event::End(data_tag.to_end()))?;
 
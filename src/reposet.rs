@@ -0,0 +1,156 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use crate::capability::Providers;
+use crate::{Package, Repository, Requirement, UpdateRecord};
+
+/// The priority repositories have in dnf unless they're configured otherwise.
+pub const DEFAULT_PRIORITY: i32 = 99;
+
+/// Several repositories looked at together, like the repositories enabled in a dnf configuration.
+///
+/// Each repository has an ID and a priority, where a lower number is a higher priority as in dnf. A
+/// package is only visible if no repository with a higher priority has a package of the same name and
+/// arch, and among the visible packages, the newest wins. The repositories aren't merged or changed.
+#[derive(Debug, Default)]
+pub struct RepoSet {
+    repos: Vec<(String, i32, Repository)>,
+}
+
+impl RepoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a repository with the given ID and priority, replacing any repository with the same ID.
+    pub fn add(
+        &mut self,
+        id: impl Into<String>,
+        priority: i32,
+        repository: Repository,
+    ) -> &mut Self {
+        let id = id.into();
+        self.repos.retain(|(other, ..)| *other != id);
+        self.repos.push((id, priority, repository));
+        self
+    }
+
+    /// Remove the repository with the given ID, returning it.
+    pub fn remove(&mut self, id: &str) -> Option<Repository> {
+        let index = self.repos.iter().position(|(other, ..)| other == id)?;
+        Some(self.repos.remove(index).2)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Repository> {
+        self.repos
+            .iter()
+            .find(|(other, ..)| other == id)
+            .map(|(.., repository)| repository)
+    }
+
+    /// The ID, priority and contents of each repository, in the order they were added.
+    pub fn repositories(&self) -> impl Iterator<Item = (&str, i32, &Repository)> {
+        self.repos
+            .iter()
+            .map(|(id, priority, repository)| (id.as_str(), *priority, repository))
+    }
+
+    /// The packages which aren't excluded by the priorities of the repositories, with the ID of the
+    /// repository of each, in the order of the repositories.
+    pub fn packages(&self) -> Vec<(&str, &Package)> {
+        let mut best: HashMap<(&str, &str), i32> = HashMap::new();
+        for (_, priority, repository) in &self.repos {
+            for package in repository.packages().values() {
+                best.entry((package.name(), package.arch()))
+                    .and_modify(|best| *best = (*best).min(*priority))
+                    .or_insert(*priority);
+            }
+        }
+        self.repos
+            .iter()
+            .flat_map(|(id, priority, repository)| {
+                let best = &best;
+                repository
+                    .packages()
+                    .values()
+                    .filter(move |package| best[&(package.name(), package.arch())] == *priority)
+                    .map(move |package| (id.as_str(), package))
+            })
+            .collect()
+    }
+
+    /// The newest visible package of each arch with the given name, see [`RepoSet::packages()`].
+    pub fn latest(&self, name: &str) -> Vec<(&str, &Package)> {
+        let mut latest: Vec<(&str, &Package)> = Vec::new();
+        for (id, package) in self.packages() {
+            if package.name() != name {
+                continue;
+            }
+            match latest.iter_mut().find(|(_, p)| p.arch() == package.arch()) {
+                Some(entry) if package.evr() > entry.1.evr() => *entry = (id, package),
+                Some(_) => (),
+                None => latest.push((id, package)),
+            }
+        }
+        latest
+    }
+
+    /// The visible packages which satisfy `require`, see [`Repository::whatprovides()`].
+    pub fn whatprovides(&self, require: &Requirement) -> Vec<(&str, &Package)> {
+        let packages = self.packages();
+        let providers = Providers::new(packages.iter().map(|(_, package)| *package));
+        providers
+            .what_provides(require)
+            .into_iter()
+            .map(|provider| {
+                let (id, _) = packages
+                    .iter()
+                    .find(|(_, package)| std::ptr::eq(*package, provider))
+                    .unwrap();
+                (*id, provider)
+            })
+            .collect()
+    }
+
+    /// The provider of `require` which would be picked: the package with the newest EVR, and of those the
+    /// one in the repository added first.
+    pub fn best_provider(&self, require: &Requirement) -> Option<(&str, &Package)> {
+        self.whatprovides(require).into_iter().reduce(|best, next| {
+            match next.1.evr() > best.1.evr() {
+                true => next,
+                false => best,
+            }
+        })
+    }
+
+    /// The requirements of the visible packages which no visible package satisfies, see
+    /// [`Repository::repoclosure()`].
+    pub fn repoclosure(&self) -> Vec<(&str, &Package, &Requirement)> {
+        let packages = self.packages();
+        let providers = Providers::new(packages.iter().map(|(_, package)| *package));
+        let mut unresolved = Vec::new();
+        for (id, package) in packages {
+            for require in package.requires() {
+                if !require.name.starts_with("rpmlib(") && !providers.is_satisfied(require) {
+                    unresolved.push((id, package, require));
+                }
+            }
+        }
+        unresolved
+    }
+
+    /// The advisory with the given ID, from the first repository which has it.
+    pub fn advisory(&self, id: &str) -> Option<(&str, &UpdateRecord)> {
+        self.repos.iter().find_map(|(repo_id, _, repository)| {
+            repository
+                .advisories()
+                .get(id)
+                .map(|advisory| (repo_id.as_str(), advisory))
+        })
+    }
+}
@@ -4,27 +4,63 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::{BitAnd, BitOr, BitOrAssign};
 use std::path::{Path, PathBuf};
-
+use std::sync::Arc;
+
+use crate::capability::Providers;
+use crate::compare::{self, CompareOptions, PackageUpdate, RepositoryDiff};
+use crate::delta::{self, RepositoryDelta};
+use crate::drafts;
+use crate::hooks::RepositoryHooks;
+use crate::logging;
+use crate::runtime;
+use crate::storage::{LocalStorage, Storage};
+use crate::suse;
+use crate::transcode;
 use crate::updateinfo::{UpdateinfoXmlReader, UpdateinfoXmlWriter};
+use crate::validate::{self, ValidationReport};
+use crate::verifier::{verify_files_in, FileCheck, VerificationReport, VerifyOptions};
+use crate::zchunk::ZchunkWriter;
 use crate::UpdateinfoXml;
-use crate::{utils, PackageIterator};
+use crate::{
+    utils, ChangelogStorage, ChangelogStore, Comps, FileStorage, Modules, PackageEvent,
+    PackageIterator, PathTable, EVR,
+};
 
 use super::filelist::FilelistsXmlWriter;
 use super::metadata::{
+    Changelog,
     ChecksumType,
     CompressionType,
     FilelistsXml,
+    InvalidCharPolicy,
+    MetadataType,
     OtherXml,
     Package,
+    ParseError,
+    ParseOptions,
+    ParseReport,
+    ParseWarning,
+    Pattern,
+    PatternsXml,
+    PrimaryElements,
     PrimaryXml,
+    Product,
+    ProductsXml,
+    RecordOrder,
     RepomdData,
     RepomdRecord,
     RepomdXml,
+    Requirement,
     RpmMetadata,
-    UpdateRecord, // DistroTag, MetadataType
+    UpdateCollectionPackage,
+    UpdateRecord, // DistroTag
+    XmlFormat,
+    XmlStyle,
 };
 use super::other::OtherXmlWriter;
 use super::primary::PrimaryXmlWriter;
@@ -45,6 +81,8 @@ pub struct Repository {
     repomd_data: RepomdData,
     packages: IndexMap<String, Package>,
     advisories: IndexMap<String, UpdateRecord>,
+    products: Vec<Product>,
+    patterns: Vec<Pattern>,
 }
 
 // TODO: worth doing any allocation tricks? (probably not)
@@ -66,11 +104,11 @@ impl Repository {
         Self::default()
     }
 
-    pub fn repomd<'repo>(&'repo self) -> &'repo RepomdData {
+    pub fn repomd(&self) -> &RepomdData {
         &self.repomd_data
     }
 
-    pub fn repomd_mut<'repo>(&'repo mut self) -> &'repo mut RepomdData {
+    pub fn repomd_mut(&mut self) -> &mut RepomdData {
         &mut self.repomd_data
     }
 
@@ -92,6 +130,24 @@ impl Repository {
         &mut self.advisories
     }
 
+    /// The products of a SUSE repository.
+    pub fn products(&self) -> &[Product] {
+        &self.products
+    }
+
+    pub fn products_mut(&mut self) -> &mut Vec<Product> {
+        &mut self.products
+    }
+
+    /// The patterns of a SUSE repository.
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    pub fn patterns_mut(&mut self) -> &mut Vec<Pattern> {
+        &mut self.patterns
+    }
+
     /// Sorts the package entries by `location_href`.
     ///
     /// Helps with compression ratios for certain types of compression, and makes it more easily searchable.
@@ -100,24 +156,439 @@ impl Repository {
             .sort_by(|_k1, v1, _k2, v2| v1.location_href().cmp(v2.location_href()));
     }
 
+    /// Replace the `location_href` of every package with what `rewrite` returns for it, e.g. to flatten
+    /// `Packages/a/abc-1.0-1.noarch.rpm` into `abc-1.0-1.noarch.rpm` when the repository is hosted
+    /// somewhere else. The filenames of the packages listed by advisories are updated to match.
+    ///
+    /// The packages are written out to `primary.xml`, `filelists.xml` and `other.xml` alike, so they all
+    /// agree on the new locations. Metadata which isn't part of the `Repository` (such as `prestodelta`,
+    /// whose file is only ever copied) isn't rewritten.
+    pub fn rewrite_locations(&mut self, mut rewrite: impl FnMut(&str) -> String) {
+        fn file_name(href: &str) -> &str {
+            href.rsplit('/').next().unwrap_or(href)
+        }
+
+        let mut renamed = HashMap::new();
+        for package in self.packages.values_mut() {
+            let old = package.location_href().to_owned();
+            let new = rewrite(&old);
+            if file_name(&old) != file_name(&new) {
+                renamed.insert(file_name(&old).to_owned(), file_name(&new).to_owned());
+            }
+            package.set_location_href(&new);
+        }
+        logging::debug!(
+            "rewrote the locations of {} packages, renaming {}",
+            self.packages.len(),
+            renamed.len()
+        );
+        if renamed.is_empty() {
+            return;
+        }
+        let collections = self
+            .advisories
+            .values_mut()
+            .flat_map(|advisory| advisory.pkglist.iter_mut());
+        for package in collections.flat_map(|collection| collection.packages.iter_mut()) {
+            if let Some(new) = renamed.get(&package.filename) {
+                package.filename = new.clone();
+            }
+        }
+    }
+
+    /// Put the packages under `prefix`, which is a directory relative to the repository (e.g.
+    /// `Packages`) or a base URL (e.g. `https://mirror.example.com/fedora/`), see
+    /// [`Repository::rewrite_locations()`].
+    pub fn prefix_locations(&mut self, prefix: &str) {
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            return;
+        }
+        self.rewrite_locations(|href| format!("{}/{}", prefix, href.trim_start_matches('/')));
+    }
+
+    /// Check the packages for invalid EVRs and for timestamps which aren't set or are in the future, and
+    /// the `repomd.xml` records for timestamps in the future.
+    ///
+    /// Problems with the metadata files themselves (such as packages missing from some of them) can't be
+    /// seen once they've been loaded, [`RepositoryReader::validate()`] checks for those as well. Fails only
+    /// if changelogs stored out-of-line (see [`Package::store_changelogs()`]) can't be read.
+    pub fn validate(&self) -> Result<ValidationReport, MetadataError> {
+        validate::validate_repository(self)
+    }
+
+    /// Check the package groups of `comps` against the packages of the repository, since clients such as
+    /// `dnf group install` skip any packages which are missing (or fail, for mandatory packages).
+    ///
+    /// This finds packages of groups which aren't in the repository (for the arches they're limited to,
+    /// if any), conditional packages whose condition can never be met because the package they depend on
+    /// isn't in the repository, and groups listed by categories and environments which don't exist.
+    /// Source RPMs don't count.
+    pub fn validate_comps(&self, comps: &Comps) -> ValidationReport {
+        validate::validate_comps(self, comps)
+    }
+
+    /// Check the modular advisories of the repository against the module streams of `modules`, since an
+    /// advisory for a module stream which isn't in `modules.yaml` (such as one left behind when the stream
+    /// was removed from a mirror) can't be applied by clients.
+    ///
+    /// This finds advisories for module streams (by name, stream, version, context and arch) which don't
+    /// exist, packages of modular advisories which aren't artifacts of their module stream, and packages of
+    /// non-modular advisories which are artifacts of a module stream.
+    pub fn validate_modules(&self, modules: &Modules) -> ValidationReport {
+        validate::validate_modules(self, modules)
+    }
+
+    /// Draft an advisory for each build with packages which aren't in the `previous` snapshot of the
+    /// repository, as a starting point for writing errata by hand.
+    ///
+    /// The packages built from the same source RPM share an advisory, titled after the first line of
+    /// their latest changelog entry. It describes the changelog entries added since the previous version
+    /// of the packages (or just the latest entry for new packages) and is issued at the time the packages
+    /// were built. Builds without new changelog entries are left out. The ID is `DRAFT-<source NVR>`, and
+    /// who the advisory is from, its severity and its references are left for its author to fill in. Fails
+    /// only if changelogs stored out-of-line (see [`Package::store_changelogs()`]) can't be read.
+    pub fn draft_advisories(
+        &self,
+        previous: &Repository,
+    ) -> Result<Vec<UpdateRecord>, MetadataError> {
+        drafts::draft_advisories(previous, self)
+    }
+
+    /// Compare the content of this repository with `other`, e.g. to check that metadata written by
+    /// another tool from the same packages is equivalent.
+    ///
+    /// Packages are matched up by their pkgid and advisories by their ID, so the order they're listed in
+    /// doesn't matter. The records of `repomd.xml` are compared by their type and location but not their
+    /// checksums, which change with any difference in how the metadata is written. `options` choose
+    /// what else to disregard, by default the order of files, timestamps which only reflect when the
+    /// metadata was created, and the checksum prefixes of unique metadata filenames. Fails only if
+    /// changelogs stored out-of-line (see [`Package::store_changelogs()`]) can't be read.
+    pub fn compare(
+        &self,
+        other: &Repository,
+        options: CompareOptions,
+    ) -> Result<RepositoryDiff, MetadataError> {
+        compare::compare_repositories(self, other, options)
+    }
+
+    /// The newest version of each package (by name and arch) in this repository and in `candidate`, in
+    /// order of name and arch, like `dnf check-update` with this repository installed and `candidate`
+    /// enabled. [`PackageUpdate::is_upgrade()`] tells which ones `candidate` upgrades.
+    ///
+    /// Source packages are left out. Packages only match if they have the same arch, so a package which
+    /// changes between `noarch` and an arch is listed twice, without an upgrade.
+    pub fn check_update(&self, candidate: &Repository) -> Vec<PackageUpdate> {
+        compare::check_update(self, candidate)
+    }
+
+    /// The metadata changes which turn this repository into `target`, see [`RepositoryDelta::between()`].
+    pub fn delta_to(&self, target: &Repository) -> RepositoryDelta {
+        RepositoryDelta::between(self, target)
+    }
+
+    /// Upgrade this repository to the revision `delta` was made for, without loading its full metadata.
+    ///
+    /// Fails if this isn't the revision `delta` was made from (going by the checksum of the `primary`
+    /// record of `repomd.xml`), or if it doesn't have a package or advisory which `delta` removes, in which
+    /// case the repository is left unchanged.
+    pub fn apply_delta(&mut self, delta: &RepositoryDelta) -> Result<(), MetadataError> {
+        delta::apply_delta(self, delta)
+    }
+
+    /// A copy of the repository with only the newest version of each package (by name and arch), e.g. for
+    /// a minimal repository to bootstrap systems from.
+    ///
+    /// The pkglists of the advisories only keep the packages which are left, and advisories without any
+    /// packages left are dropped (those which never had any are kept). The products and patterns are
+    /// kept, and `repomd.xml` is left to be written again.
+    pub fn squash(&self) -> Repository {
+        let mut newest: HashMap<(&str, &str), &Package> = HashMap::new();
+        for package in self.packages.values() {
+            let current = newest
+                .entry((package.name(), package.arch()))
+                .or_insert(package);
+            if package.evr() > current.evr() {
+                *current = package;
+            }
+        }
+
+        self.subset(
+            |package| std::ptr::eq(newest[&(package.name(), package.arch())], package),
+            |package| {
+                newest
+                    .get(&(package.name.as_str(), package.arch.as_str()))
+                    .is_some_and(|newest| {
+                        *newest.evr()
+                            == EVR::new(&package.epoch, &package.version, &package.release)
+                    })
+            },
+        )
+    }
+
+    /// A copy of the repository with only the packages for which `keep` is true, shared by
+    /// [`Repository::squash()`] and [`Repository::extract()`].
+    ///
+    /// The pkglists of the advisories only keep the packages for which `retained` is true, and advisories
+    /// without any packages left are dropped, while those which never had any are kept as they are.
+    fn subset(
+        &self,
+        keep: impl Fn(&Package) -> bool,
+        retained: impl Fn(&UpdateCollectionPackage) -> bool,
+    ) -> Repository {
+        let mut subset = Repository::new();
+        subset.packages = self
+            .packages
+            .iter()
+            .filter(|(_, package)| keep(package))
+            .map(|(pkgid, package)| (pkgid.clone(), package.clone()))
+            .collect();
+        for (id, advisory) in &self.advisories {
+            let mut advisory = advisory.clone();
+            if advisory.packages().next().is_some() {
+                for collection in &mut advisory.pkglist {
+                    collection.packages.retain(|package| retained(package));
+                }
+                advisory
+                    .pkglist
+                    .retain(|collection| !collection.packages.is_empty());
+                if advisory.pkglist.is_empty() {
+                    continue;
+                }
+            }
+            subset.advisories.insert(id.clone(), advisory);
+        }
+        subset.products = self.products.clone();
+        subset.patterns = self.patterns.clone();
+        subset
+    }
+
+    /// A copy of the repository as it was at `timestamp` (a Unix timestamp), approximating the snapshots
+    /// vendors publish of their repositories, e.g. for reproducible image builds.
+    ///
+    /// Packages built after `timestamp` are left out, as are the advisories issued after it and the
+    /// packages they released, whenever those were built. Advisories without an issued date which can be
+    /// read are taken to be issued after it. The products and patterns are kept, and `repomd.xml` is left
+    /// to be written again.
+    pub fn snapshot_at(&self, timestamp: u64) -> Repository {
+        let (issued, later): (Vec<_>, Vec<_>) =
+            self.advisories.iter().partition(|(_, advisory)| {
+                advisory
+                    .issued_date
+                    .as_deref()
+                    .and_then(utils::parse_advisory_date)
+                    .is_some_and(|issued| issued <= timestamp)
+            });
+        let released_later = |package: &Package| {
+            later.iter().any(|(_, advisory)| {
+                advisory.packages().any(|update| {
+                    update.name == package.name()
+                        && update.arch == package.arch()
+                        && EVR::new(&update.epoch, &update.version, &update.release)
+                            == *package.evr()
+                })
+            })
+        };
+
+        let mut snapshot = Repository::new();
+        snapshot.packages = self
+            .packages
+            .iter()
+            .filter(|(_, package)| package.time_build() <= timestamp && !released_later(package))
+            .map(|(pkgid, package)| (pkgid.clone(), package.clone()))
+            .collect();
+        snapshot.advisories = issued
+            .into_iter()
+            .map(|(id, advisory)| (id.clone(), advisory.clone()))
+            .collect();
+        snapshot.products = self.products.clone();
+        snapshot.patterns = self.patterns.clone();
+        snapshot
+    }
+
+    /// The packages which satisfy `require`, with one of their provides or one of their files for a
+    /// dependency on a path. See the [`capability`](crate::capability) module for how they're matched.
+    pub fn whatprovides(&self, require: &Requirement) -> Vec<&Package> {
+        Providers::new(self.packages.values()).what_provides(require)
+    }
+
+    /// The dependencies of the packages which aren't satisfied by any package of the repository, in the
+    /// order of the packages, e.g. to check that a repository is self-contained before publishing it.
+    ///
+    /// Dependencies on `rpmlib(...)` features are provided by rpm itself and aren't checked.
+    pub fn repoclosure(&self) -> Vec<(&Package, &Requirement)> {
+        let providers = Providers::new(self.packages.values());
+        let mut unresolved = Vec::new();
+        for package in self.packages.values() {
+            for require in package.requires() {
+                if !require.name.starts_with("rpmlib(") && !providers.is_satisfied(require) {
+                    unresolved.push((package, require));
+                }
+            }
+        }
+        unresolved
+    }
+
+    /// The packages with the given NEVRAs and everything they require, transitively, in the order they
+    /// were reached.
+    ///
+    /// Where several packages satisfy a requirement, one is picked the way dnf roughly would: a package
+    /// which is already part of the closure, else the provider with the newest EVR. Requirements which
+    /// nothing satisfies (and `rpmlib(...)` features) are skipped, see [`Repository::repoclosure()`].
+    pub fn dependency_closure(&self, nevras: &[&str]) -> Result<Vec<&Package>, MetadataError> {
+        let providers = Providers::new(self.packages.values());
+        let by_nevra: HashMap<String, &Package> = match nevras.is_empty() {
+            true => HashMap::new(),
+            false => self
+                .packages
+                .values()
+                .map(|package| (package.nevra(), package))
+                .collect(),
+        };
+        let mut closure: Vec<&Package> = Vec::new();
+        let mut reached: HashSet<*const Package> = HashSet::new();
+        for nevra in nevras {
+            let package = by_nevra.get(*nevra).copied().ok_or_else(|| {
+                MetadataError::InvalidFieldError("package NEVRA", nevra.to_string())
+            })?;
+            if reached.insert(package) {
+                closure.push(package);
+            }
+        }
+
+        let mut next = 0;
+        while let Some(package) = closure.get(next).copied() {
+            next += 1;
+            for require in package.requires() {
+                if require.name.starts_with("rpmlib(") {
+                    continue;
+                }
+                let candidates = providers.what_provides(require);
+                if candidates
+                    .iter()
+                    .any(|candidate| reached.contains(&(*candidate as *const Package)))
+                {
+                    continue;
+                }
+                let best = candidates.into_iter().reduce(|best, candidate| {
+                    match candidate.evr() > best.evr() {
+                        true => candidate,
+                        false => best,
+                    }
+                });
+                if let Some(best) = best {
+                    reached.insert(best);
+                    closure.push(best);
+                }
+            }
+        }
+        Ok(closure)
+    }
+
+    /// The disk space the packages with the given NEVRAs would take up once installed, along with their
+    /// dependencies (see [`Repository::dependency_closure()`]): the sum of their installed sizes.
+    ///
+    /// This is an estimate, as it doesn't account for what's installed already, for file system overhead,
+    /// or for files shared between packages.
+    pub fn estimated_install_size(&self, nevras: &[&str]) -> Result<u64, MetadataError> {
+        Ok(self
+            .dependency_closure(nevras)?
+            .iter()
+            .map(|package| package.size_installed())
+            .sum())
+    }
+
+    /// A copy of the repository with only the packages with the given NEVRAs and their dependencies (see
+    /// [`Repository::dependency_closure()`]), e.g. for a minimal repository to build an image from a
+    /// kickstart or a lockfile. Use [`extract_repository()`](crate::extract_repository) to write it out
+    /// along with the RPMs.
+    ///
+    /// The packages keep the order they had. The pkglists of the advisories only keep the packages which
+    /// are left, and advisories without any packages left are dropped (those which never had any are
+    /// kept). The products and patterns are kept, and `repomd.xml` is left to be written again.
+    pub fn extract(&self, nevras: &[&str]) -> Result<Repository, MetadataError> {
+        let closure = self.dependency_closure(nevras)?;
+        let kept: HashSet<*const Package> = closure.iter().map(|p| *p as *const Package).collect();
+        let mut by_name: HashMap<(&str, &str), Vec<&Package>> = HashMap::new();
+        for package in &closure {
+            by_name
+                .entry((package.name(), package.arch()))
+                .or_default()
+                .push(package);
+        }
+
+        Ok(self.subset(
+            |package| kept.contains(&(package as *const Package)),
+            |package| {
+                let evr = EVR::new(&package.epoch, &package.version, &package.release);
+                by_name
+                    .get(&(package.name.as_str(), package.arch.as_str()))
+                    .is_some_and(|packages| packages.iter().any(|p| *p.evr() == evr))
+            },
+        ))
+    }
+
+    /// The changelog entries whose text contains `pattern`, ignoring case, and which are no older than
+    /// `since` (a Unix timestamp) if it's given, in the order of the packages.
+    ///
+    /// Use [`RepositoryReader::search_changelogs()`] to search `other.xml` without loading the repository.
+    /// Fails only if changelogs stored out-of-line (see [`Package::store_changelogs()`]) can't be read.
+    pub fn search_changelogs(
+        &self,
+        pattern: &str,
+        since: Option<u64>,
+    ) -> Result<Vec<ChangelogMatch>, MetadataError> {
+        let pattern = pattern.to_lowercase();
+        let mut matches = Vec::new();
+        for package in self.packages.values() {
+            ChangelogMatch::find(package, &pattern, since, &mut matches)?;
+        }
+        Ok(matches)
+    }
+
     /// Create a new [`Repository`] from a path pointing to an RPM repository.
     ///
     /// Will fail if the RPM repository is not valid.
     pub fn load_from_directory(path: &Path) -> Result<Self, MetadataError> {
+        let _span = logging::span!("load repository {}", path.display());
         let reader = RepositoryReader::new_from_directory(path)?;
-        Ok(reader.into_repo()?)
+        reader.into_repo()
+    }
+
+    /// Create a new [`Repository`] from a path pointing to an RPM repository, parsing the metadata and
+    /// loading only the types of metadata selected by `options`.
+    ///
+    /// The locations of the metadata files are taken from `repodata/repomd.xml`. Metadata which the repository
+    /// doesn't have is skipped, except for `primary.xml` if it's selected, without which this fails. The records
+    /// of the metadata types which aren't loaded (groups, modules etc.) are kept in the [`RepomdData`] of the
+    /// repository.
+    ///
+    /// Loading only what's needed can save a lot of time, e.g. `filelists.xml` is usually by far the largest
+    /// metadata file and isn't needed to find out which advisories apply to which packages.
+    ///
+    /// Returns the warnings recorded for metadata which doesn't follow the spec in [`ParseMode::Lenient`],
+    /// and the errors of the packages and advisories which were skipped in [`ParseMode::Recover`].
+    pub fn load_from_directory_with_options(
+        path: &Path,
+        options: LoadOptions,
+    ) -> Result<(Self, ParseReport), MetadataError> {
+        let _span = logging::span!("load repository {}", path.display());
+        let reader = RepositoryReader::new_from_directory_with_options(path, options)?;
+        reader.into_repo_with_report()
     }
 
     /// Load a metadata file into an existing repository.
     pub fn load_metadata_file<M: RpmMetadata>(&mut self, path: &Path) -> Result<(), MetadataError> {
+        let _span = logging::span!("parse {}", path.display());
         let reader = utils::xml_reader_from_file(path)?;
         M::load_metadata(self, reader)
+            .map_err(|e| e.with_line_from(|| utils::reader_from_file(path)))
     }
 
     /// Load metadata from a string into an existing repository.
     pub fn load_metadata_str<M: RpmMetadata>(&mut self, str: &str) -> Result<(), MetadataError> {
         let reader = utils::create_xml_reader(str.as_bytes());
-        M::load_metadata(self, reader)
+        M::load_metadata(self, reader).map_err(|e| e.with_line_from(|| Ok(str.as_bytes())))
     }
 
     /// Load metadata from an array of bytes (assumed to be UTF-8) into an existing repository.
@@ -131,15 +602,17 @@ impl Repository {
 
     /// Write all the RPM metadata out to a directory with default options.
     pub fn write_to_directory(&self, path: &Path) -> Result<(), MetadataError> {
-        Self::write_to_directory_with_options(&self, path, RepositoryOptions::default())
+        Self::write_to_directory_with_options(self, path, RepositoryOptions::default())
     }
 
-    /// Write all the RPM metadata out to a directory with the provided options.
+    /// Write all the RPM metadata out to a directory with the provided options, which choose the types of
+    /// metadata written, their compression and checksums and how their files are named.
     pub fn write_to_directory_with_options(
         &self,
         path: &Path,
         options: RepositoryOptions,
     ) -> Result<(), MetadataError> {
+        let _span = logging::span!("write repository {}", path.display());
         let mut writer = RepositoryWriter::new_with_options(path, self.packages().len(), options)?;
 
         for (_, pkg) in self.packages() {
@@ -148,6 +621,12 @@ impl Repository {
         for (_, advisory) in self.advisories() {
             writer.add_advisory(advisory)?;
         }
+        for product in self.products() {
+            writer.add_product(product);
+        }
+        for pattern in self.patterns() {
+            writer.add_pattern(pattern);
+        }
 
         writer.finish()?;
 
@@ -182,27 +661,97 @@ impl Repository {
     }
 }
 
+/// A changelog entry found by [`Repository::search_changelogs()`] or
+/// [`RepositoryReader::search_changelogs()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangelogMatch {
+    pub pkgid: String,
+    pub nevra: String,
+    pub changelog: Changelog,
+}
+
+impl ChangelogMatch {
+    /// Add the changelog entries of `package` which match to `matches`. `pattern` is lowercase.
+    fn find(
+        package: &Package,
+        pattern: &str,
+        since: Option<u64>,
+        matches: &mut Vec<Self>,
+    ) -> Result<(), MetadataError> {
+        for changelog in package.load_changelogs()?.iter() {
+            if since.is_some_and(|since| changelog.timestamp < since)
+                || !changelog.description.to_lowercase().contains(pattern)
+            {
+                continue;
+            }
+            matches.push(ChangelogMatch {
+                pkgid: package.pkgid().to_owned(),
+                nevra: package.nevra(),
+                changelog: changelog.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
 /// Options for writing RPM repository metadata.
 ///
-/// - `simple_metadata_filenames` - Determines whether filenames should be bare e.g. `filelists.xml` or should include the file checksum.
+/// - `simple_metadata_filenames` - Determines whether filenames should be bare e.g. `filelists.xml` or should
+///   start with the file checksum, e.g. `6e3b...-filelists.xml`.
 /// - `metadata_compression_type` - The type of compression to use for repository metadata.
 /// - `metadata_checksum_type` - The type of checksums to use for metadata.
 /// - `package_checksum_type` - The type of checksums to use for packages.
+/// - `xml_style` - How the metadata is laid out and escaped, see [`XmlStyle`].
+/// - `xml_format` - How the metadata is indented and formatted, see [`XmlFormat`].
+/// - `invalid_chars` - What to do with characters which aren't allowed in XML, see [`InvalidCharPolicy`].
+/// - `primary_elements` - Which of the optional elements of packages are written to `primary.xml`, see
+///   [`PrimaryElements`].
+/// - `filelists` - Whether `filelists.xml` is written.
+/// - `other` - Whether `other.xml` is written.
+/// - `updateinfo` - Whether `updateinfo.xml` is written (if there are any advisories).
+/// - `updateinfo_split` - Whether the advisories are split between several documents, see
+///   [`UpdateinfoSplit`].
+/// - `duplicate_advisories` - What's done with advisories whose ID was already written, see
+///   [`DuplicateAdvisoryPolicy`].
+/// - `zchunk` - Whether a zchunk version of each metadata file (e.g. `primary.xml.zck`) is written as well.
+/// - `record_order` - The order of the records in `repomd.xml`, see [`RecordOrder`].
 #[derive(Copy, Clone, Debug)]
 pub struct RepositoryOptions {
     pub simple_metadata_filenames: bool,
     pub metadata_compression_type: CompressionType,
     pub metadata_checksum_type: ChecksumType,
     pub package_checksum_type: ChecksumType,
+    pub xml_style: XmlStyle,
+    pub xml_format: XmlFormat,
+    pub invalid_chars: InvalidCharPolicy,
+    pub primary_elements: PrimaryElements,
+    pub filelists: bool,
+    pub other: bool,
+    pub updateinfo: bool,
+    pub updateinfo_split: UpdateinfoSplit,
+    pub duplicate_advisories: DuplicateAdvisoryPolicy,
+    pub zchunk: bool,
+    pub record_order: RecordOrder,
 }
 
 impl Default for RepositoryOptions {
     fn default() -> Self {
         Self {
-            simple_metadata_filenames: false,
+            simple_metadata_filenames: true,
             metadata_compression_type: CompressionType::Zstd,
             metadata_checksum_type: ChecksumType::Sha256,
             package_checksum_type: ChecksumType::Sha256,
+            xml_style: XmlStyle::Standard,
+            xml_format: XmlFormat::default(),
+            invalid_chars: InvalidCharPolicy::default(),
+            primary_elements: PrimaryElements::default(),
+            filelists: true,
+            other: true,
+            updateinfo: true,
+            updateinfo_split: UpdateinfoSplit::None,
+            duplicate_advisories: DuplicateAdvisoryPolicy::Error,
+            zchunk: false,
+            record_order: RecordOrder::CreaterepoC,
         }
     }
 }
@@ -235,6 +784,285 @@ impl RepositoryOptions {
             ..self
         }
     }
+
+    pub fn xml_style(self, style: XmlStyle) -> Self {
+        Self {
+            xml_style: style,
+            ..self
+        }
+    }
+
+    pub fn xml_format(self, format: XmlFormat) -> Self {
+        Self {
+            xml_format: format,
+            ..self
+        }
+    }
+
+    pub fn invalid_chars(self, policy: InvalidCharPolicy) -> Self {
+        Self {
+            invalid_chars: policy,
+            ..self
+        }
+    }
+
+    pub fn primary_elements(self, elements: PrimaryElements) -> Self {
+        Self {
+            primary_elements: elements,
+            ..self
+        }
+    }
+
+    pub fn filelists(self, val: bool) -> Self {
+        Self {
+            filelists: val,
+            ..self
+        }
+    }
+
+    pub fn other(self, val: bool) -> Self {
+        Self { other: val, ..self }
+    }
+
+    pub fn updateinfo(self, val: bool) -> Self {
+        Self {
+            updateinfo: val,
+            ..self
+        }
+    }
+
+    pub fn updateinfo_split(self, split: UpdateinfoSplit) -> Self {
+        Self {
+            updateinfo_split: split,
+            ..self
+        }
+    }
+
+    pub fn duplicate_advisories(self, policy: DuplicateAdvisoryPolicy) -> Self {
+        Self {
+            duplicate_advisories: policy,
+            ..self
+        }
+    }
+
+    pub fn zchunk(self, val: bool) -> Self {
+        Self {
+            zchunk: val,
+            ..self
+        }
+    }
+
+    pub fn record_order(self, order: RecordOrder) -> Self {
+        Self {
+            record_order: order,
+            ..self
+        }
+    }
+}
+
+/// How advisories are split between updateinfo documents, see [`RepositoryOptions::updateinfo_split`].
+///
+/// The split documents have records of their own type in `repomd.xml`, e.g. `updateinfo-2023`, which
+/// are read back along with `updateinfo` by [`Repository::load_from_directory()`] and
+/// [`RepositoryReader::iter_advisories()`]. dnf only reads `updateinfo`, and zchunk versions of the split
+/// documents aren't written.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpdateinfoSplit {
+    /// Write every advisory to `updateinfo.xml`
+    #[default]
+    None,
+    /// Write the advisories issued in each year to a document of their own, e.g.
+    /// `updateinfo-2023.xml`, and those without an issued date to `updateinfo-undated.xml`
+    ByYear,
+    /// Write the advisories of each type to a document of their own, e.g. `updateinfo-security.xml`
+    ByType,
+}
+
+impl UpdateinfoSplit {
+    /// The type of the record of the document `advisory` is written to.
+    pub fn metadata_type(self, advisory: &UpdateRecord) -> MetadataType {
+        let suffix = match self {
+            UpdateinfoSplit::None => return MetadataType::Updateinfo,
+            UpdateinfoSplit::ByYear => match advisory.issued_date.as_deref() {
+                Some(date) if date.len() >= 4 && date[..4].bytes().all(|b| b.is_ascii_digit()) => {
+                    date[..4].to_owned()
+                }
+                _ => "undated".to_owned(),
+            },
+            UpdateinfoSplit::ByType => match advisory.update_type.trim() {
+                "" => "unknown".to_owned(),
+                update_type => update_type.to_ascii_lowercase().replace(
+                    |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_',
+                    "_",
+                ),
+            },
+        };
+        MetadataType::Custom(format!("{}{}", SPLIT_UPDATEINFO_PREFIX, suffix))
+    }
+}
+
+/// What a [`RepositoryWriter`] does with an advisory whose ID it already wrote, e.g. when the advisories
+/// are assembled from several sources, see [`RepositoryOptions::duplicate_advisories`]. Clients expect
+/// the IDs of the advisories to be unique, across all of the updateinfo documents.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateAdvisoryPolicy {
+    /// Fail with an error
+    #[default]
+    Error,
+    /// Keep only the newest advisory of each ID, by its updated date, or its issued date if it has none,
+    /// and the one added first if neither is newer. The advisories are held until
+    /// [`RepositoryWriter::finish()`] to find out which are the newest.
+    KeepNewest,
+    /// Keep every advisory, suffixing the ID of each duplicate with a number which makes it unique, e.g.
+    /// `FEDORA-2023-1a2b3c-2`
+    Suffix,
+}
+
+/// When `advisory` was last updated, for [`DuplicateAdvisoryPolicy::KeepNewest`].
+fn advisory_date(advisory: &UpdateRecord) -> Option<u64> {
+    advisory
+        .updated_date
+        .as_deref()
+        .or(advisory.issued_date.as_deref())
+        .and_then(utils::parse_advisory_date)
+}
+
+/// The prefix of the types of split updateinfo documents.
+pub(crate) const SPLIT_UPDATEINFO_PREFIX: &str = "updateinfo-";
+
+/// A set of types of metadata, combined with `|`, e.g. `MetadataSelection::PRIMARY | MetadataSelection::UPDATEINFO`.
+///
+/// The packages are read from `primary.xml`, their files from `filelists.xml` and their changelogs from
+/// `other.xml`, so `FILELISTS` and `OTHER` have no effect without `PRIMARY`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MetadataSelection(u8);
+
+impl MetadataSelection {
+    pub const PRIMARY: Self = Self(1);
+    pub const FILELISTS: Self = Self(1 << 1);
+    pub const OTHER: Self = Self(1 << 2);
+    pub const UPDATEINFO: Self = Self(1 << 3);
+    pub const COMPS: Self = Self(1 << 4);
+    pub const MODULES: Self = Self(1 << 5);
+    pub const PRODUCTS: Self = Self(1 << 6);
+    pub const PATTERNS: Self = Self(1 << 7);
+    pub const ALL: Self = Self(u8::MAX);
+
+    /// No metadata at all.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Whether every type of metadata in `other` is selected.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether files of `metadata_type` are part of the selection. Only the XML (or YAML) versions are,
+    /// not the sqlite databases or zchunk files.
+    pub fn contains_type(self, metadata_type: &MetadataType) -> bool {
+        let selection = match metadata_type {
+            MetadataType::Primary => Self::PRIMARY,
+            MetadataType::Filelists => Self::FILELISTS,
+            MetadataType::Other => Self::OTHER,
+            MetadataType::Updateinfo => Self::UPDATEINFO,
+            MetadataType::Group | MetadataType::GroupGz => Self::COMPS,
+            MetadataType::Modules => Self::MODULES,
+            MetadataType::Products => Self::PRODUCTS,
+            MetadataType::Patterns => Self::PATTERNS,
+            _ => return false,
+        };
+        self.contains(selection)
+    }
+}
+
+impl Default for MetadataSelection {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl BitOr for MetadataSelection {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for MetadataSelection {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for MetadataSelection {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// Options for loading a [`Repository`], see [`Repository::load_from_directory_with_options()`].
+///
+/// - `parse_options` - How the metadata files are parsed, see [`ParseOptions`].
+/// - `metadata` - The types of metadata loaded, all of them by default. Groups (comps) and modules aren't
+///   part of a [`Repository`], so `COMPS` and `MODULES` only make a difference when downloading.
+/// - `changelogs` - Where the descriptions of the changelogs are kept, see [`ChangelogStorage`]. They're
+///   moved out of each package as it's read, so they're never all in memory at once.
+/// - `files` - How the file lists of the packages are kept, see [`FileStorage`]. Like the changelogs,
+///   they're stored as each package is read.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub parse_options: ParseOptions,
+    pub metadata: MetadataSelection,
+    pub changelogs: ChangelogStorage,
+    pub files: FileStorage,
+}
+
+impl LoadOptions {
+    pub fn parse_options(self, options: ParseOptions) -> Self {
+        Self {
+            parse_options: options,
+            ..self
+        }
+    }
+
+    pub fn metadata(self, selection: MetadataSelection) -> Self {
+        Self {
+            metadata: selection,
+            ..self
+        }
+    }
+
+    pub fn changelogs(self, storage: ChangelogStorage) -> Self {
+        Self {
+            changelogs: storage,
+            ..self
+        }
+    }
+
+    pub fn files(self, storage: FileStorage) -> Self {
+        Self {
+            files: storage,
+            ..self
+        }
+    }
+}
+
+/// The metadata files changed by a [`RepositoryWriter::finish()`], compared to the revision of the
+/// repository which was there before, e.g. to purge exactly those URLs from a CDN rather than the
+/// whole repository.
+///
+/// The paths are relative to the repository, like the `location_href` of the records in `repomd.xml`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PublishReport {
+    /// The files which are new or whose contents changed, `repodata/repomd.xml` last
+    pub changed: Vec<PathBuf>,
+    /// The files of the previous revision which the new `repomd.xml` no longer lists. They're left in
+    /// place for the clients which are still reading the previous revision.
+    pub removed: Vec<PathBuf>,
 }
 
 /// Helper for writing RPM repository metadata manually.
@@ -249,12 +1077,25 @@ pub struct RepositoryWriter {
     primary_xml_writer: Option<PrimaryXmlWriter<Box<dyn Write + Send>>>,
     filelists_xml_writer: Option<FilelistsXmlWriter<Box<dyn Write + Send>>>,
     other_xml_writer: Option<OtherXmlWriter<Box<dyn Write + Send>>>,
-    updateinfo_xml_writer: Option<UpdateinfoXmlWriter<Box<dyn Write + Send>>>,
+    // the type of each updateinfo document, and its writer
+    updateinfo_xml_writers: Vec<(MetadataType, UpdateinfoXmlWriter<Box<dyn Write + Send>>)>,
+    // the IDs of the advisories written so far
+    advisory_ids: HashSet<String>,
+    // with DuplicateAdvisoryPolicy::KeepNewest, the advisories are written by finish()
+    pending_advisories: IndexMap<String, UpdateRecord>,
+    // there are few of them, so they're written all at once by finish()
+    products: Vec<Product>,
+    patterns: Vec<Pattern>,
+    // the metadata files copied in by add_metadata_file(), and their locations
+    metadata_files: Vec<(MetadataType, PathBuf)>,
 
     num_pkgs_written: usize,
     num_pkgs: usize,
 
     repomd_data: RepomdData,
+    // repomd.xml of the revision being replaced, if there is one
+    previous_repomd: Option<RepomdData>,
+    hooks: Option<Arc<dyn RepositoryHooks>>,
 }
 
 impl RepositoryWriter {
@@ -271,44 +1112,87 @@ impl RepositoryWriter {
     ) -> Result<Self, MetadataError> {
         let repodata_dir = path.join("repodata");
         std::fs::create_dir_all(&repodata_dir)?;
+        logging::debug!(
+            "writing {} packages to {} ({:?})",
+            num_pkgs,
+            repodata_dir.display(),
+            options
+        );
+        // a previous revision which can't be read is replaced all the same
+        let previous_repomd = utils::xml_reader_from_file(&repodata_dir.join("repomd.xml"))
+            .and_then(RepomdXml::read_data)
+            .ok();
 
-        let (_primary_path, primary_writer) = utils::xml_writer_for_path(
+        let (_primary_path, primary_writer) = utils::filtered_xml_writer_for_path(
             &repodata_dir.join("primary.xml"),
             options.metadata_compression_type,
+            options.invalid_chars,
+            options.xml_format,
         )?;
-        let (_filelists_path, filelists_writer) = utils::xml_writer_for_path(
-            &repodata_dir.join("filelists.xml"),
-            options.metadata_compression_type,
-        )?;
-        let (_other_path, other_writer) = utils::xml_writer_for_path(
-            &repodata_dir.join("other.xml"),
-            options.metadata_compression_type,
-        )?;
-
         let mut primary_xml_writer = PrimaryXml::new_writer(primary_writer);
-        let mut filelists_xml_writer = FilelistsXml::new_writer(filelists_writer);
-        let mut other_xml_writer = OtherXml::new_writer(other_writer);
-
+        primary_xml_writer.set_style(options.xml_style);
+        primary_xml_writer.set_elements(options.primary_elements);
         primary_xml_writer.write_header(num_pkgs)?;
-        filelists_xml_writer.write_header(num_pkgs)?;
-        other_xml_writer.write_header(num_pkgs)?;
+
+        let filelists_xml_writer = if options.filelists {
+            let (_filelists_path, filelists_writer) = utils::filtered_xml_writer_for_path(
+                &repodata_dir.join("filelists.xml"),
+                options.metadata_compression_type,
+                options.invalid_chars,
+                options.xml_format,
+            )?;
+            let mut filelists_xml_writer = FilelistsXml::new_writer(filelists_writer);
+            filelists_xml_writer.set_style(options.xml_style);
+            filelists_xml_writer.write_header(num_pkgs)?;
+            Some(filelists_xml_writer)
+        } else {
+            None
+        };
+
+        let other_xml_writer = if options.other {
+            let (_other_path, other_writer) = utils::filtered_xml_writer_for_path(
+                &repodata_dir.join("other.xml"),
+                options.metadata_compression_type,
+                options.invalid_chars,
+                options.xml_format,
+            )?;
+            let mut other_xml_writer = OtherXml::new_writer(other_writer);
+            other_xml_writer.set_style(options.xml_style);
+            other_xml_writer.write_header(num_pkgs)?;
+            Some(other_xml_writer)
+        } else {
+            None
+        };
 
         Ok(Self {
             options,
             path: path.to_owned(),
 
             primary_xml_writer: Some(primary_xml_writer),
-            filelists_xml_writer: Some(filelists_xml_writer),
-            other_xml_writer: Some(other_xml_writer),
-            updateinfo_xml_writer: None,
-
-            num_pkgs: num_pkgs,
+            filelists_xml_writer,
+            other_xml_writer,
+            updateinfo_xml_writers: Vec::new(),
+            advisory_ids: HashSet::new(),
+            pending_advisories: IndexMap::new(),
+            products: Vec::new(),
+            patterns: Vec::new(),
+            metadata_files: Vec::new(),
+
+            num_pkgs,
             num_pkgs_written: 0,
 
             repomd_data: RepomdData::default(),
+            previous_repomd,
+            hooks: None,
         })
     }
 
+    /// Call `hooks` as packages are written, metadata files are finished and `repomd.xml` is written.
+    pub fn with_hooks(mut self, hooks: Arc<dyn RepositoryHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
     /// Mutable accessor for the [`RepomdData`] struct which is written as repomd.xml later.
     pub fn repomd_mut(&mut self) -> &mut RepomdData {
         &mut self.repomd_data
@@ -328,45 +1212,174 @@ impl RepositoryWriter {
             .as_mut()
             .unwrap()
             .write_package(pkg)?;
-        self.filelists_xml_writer
-            .as_mut()
-            .unwrap()
-            .write_package(pkg)?;
-        self.other_xml_writer.as_mut().unwrap().write_package(pkg)?;
+        if let Some(filelists_xml_writer) = &mut self.filelists_xml_writer {
+            filelists_xml_writer.write_package(pkg)?;
+        }
+        if let Some(other_xml_writer) = &mut self.other_xml_writer {
+            other_xml_writer.write_package(pkg)?;
+        }
+        if let Some(hooks) = &self.hooks {
+            hooks.package_written(pkg)?;
+        }
+
+        logging::progress("write", self.num_pkgs_written, self.num_pkgs);
 
         Ok(())
     }
 
-    /// Write an `UpdateRecord` to the repo metadata.
+    /// Write an `UpdateRecord` to the repo metadata, in the updateinfo document picked by
+    /// [`RepositoryOptions::updateinfo_split`]. An advisory whose ID was already added is dealt with
+    /// according to [`RepositoryOptions::duplicate_advisories`].
+    ///
+    /// Does nothing if `updateinfo.xml` isn't written, see [`RepositoryOptions::updateinfo`].
     pub fn add_advisory(&mut self, record: &UpdateRecord) -> Result<(), MetadataError> {
-        // TODO: clean this up
-        if self.updateinfo_xml_writer.is_none() {
-            let repodata_dir = self.path.join("repodata");
-            let (updateinfo_path, updateinfo_writer) = utils::xml_writer_for_path(
-                &repodata_dir.join("updateinfo.xml"),
-                self.options.metadata_compression_type,
-            )?;
-
-            let mut updateinfo_xml_writer = UpdateinfoXml::new_writer(updateinfo_writer);
-            updateinfo_xml_writer.write_header()?;
-
-            self.updateinfo_xml_writer = Some(updateinfo_xml_writer)
+        if !self.options.updateinfo {
+            return Ok(());
         }
+        match self.options.duplicate_advisories {
+            DuplicateAdvisoryPolicy::Error => {
+                if !self.advisory_ids.insert(record.id.clone()) {
+                    return Err(MetadataError::InconsistentMetadataError(format!(
+                        "advisory {} was already written",
+                        record.id
+                    )));
+                }
+                self.write_advisory(record)
+            }
+            DuplicateAdvisoryPolicy::KeepNewest => {
+                match self.pending_advisories.get_mut(&record.id) {
+                    Some(kept) if advisory_date(record) > advisory_date(kept) => {
+                        logging::debug!("replacing advisory {} with a newer one", record.id);
+                        *kept = record.clone();
+                    }
+                    Some(_) => logging::debug!("skipping older advisory {}", record.id),
+                    None => {
+                        self.pending_advisories
+                            .insert(record.id.clone(), record.clone());
+                    }
+                }
+                Ok(())
+            }
+            DuplicateAdvisoryPolicy::Suffix => {
+                if self.advisory_ids.insert(record.id.clone()) {
+                    return self.write_advisory(record);
+                }
+                let id = (2..)
+                    .map(|n| format!("{}-{}", record.id, n))
+                    .find(|id| !self.advisory_ids.contains(id))
+                    .unwrap();
+                logging::debug!("writing duplicate advisory {} as {}", record.id, id);
+                self.advisory_ids.insert(id.clone());
+                let mut record = record.clone();
+                record.id = id;
+                self.write_advisory(&record)
+            }
+        }
+    }
 
-        self.updateinfo_xml_writer
-            .as_mut()
-            .unwrap()
+    fn write_advisory(&mut self, record: &UpdateRecord) -> Result<(), MetadataError> {
+        let metadata_type = self.options.updateinfo_split.metadata_type(record);
+        let index = match self
+            .updateinfo_xml_writers
+            .iter()
+            .position(|(other, _)| other == &metadata_type)
+        {
+            Some(index) => index,
+            None => {
+                let repodata_dir = self.path.join("repodata");
+                let (_updateinfo_path, updateinfo_writer) = utils::filtered_xml_writer_for_path(
+                    &repodata_dir.join(metadata_type.file_name(CompressionType::None)),
+                    self.options.metadata_compression_type,
+                    self.options.invalid_chars,
+                    self.options.xml_format,
+                )?;
+
+                let mut updateinfo_xml_writer = UpdateinfoXml::new_writer(updateinfo_writer);
+                updateinfo_xml_writer.set_style(self.options.xml_style);
+                updateinfo_xml_writer.write_header()?;
+
+                self.updateinfo_xml_writers
+                    .push((metadata_type, updateinfo_xml_writer));
+                self.updateinfo_xml_writers.len() - 1
+            }
+        };
+
+        self.updateinfo_xml_writers[index]
+            .1
             .write_updaterecord(record)?;
 
         Ok(())
     }
 
+    /// Add a `Product` to the `products.xml` of a SUSE repository, which is only written if there are any.
+    pub fn add_product(&mut self, product: &Product) {
+        self.products.push(product.clone());
+    }
+
+    /// Add a `Pattern` to the `patterns.xml` of a SUSE repository, which is only written if there are any.
+    pub fn add_pattern(&mut self, pattern: &Pattern) {
+        self.patterns.push(pattern.clone());
+    }
+
+    /// Copy a metadata file which isn't generated from the packages into the repository, such as the
+    /// `appstream` data of desktop applications and the `appstream-icons` tarball, so that `repomd.xml`
+    /// lists it along with the rest of the metadata.
+    ///
+    /// The file at `source` may be compressed in any supported format. It's recompressed with
+    /// [`RepositoryOptions::metadata_compression_type`] and named after its type like the generated
+    /// metadata, e.g. `repodata/appstream.xml.gz`, or `repodata/<checksum>-appstream.xml.gz` unless
+    /// [`RepositoryOptions::simple_metadata_filenames`] is set. `group` files stay uncompressed and
+    /// `group_gz` files are always gzipped. Adding another file of the same type replaces it.
+    pub fn add_metadata_file(
+        &mut self,
+        metadata_type: impl Into<MetadataType>,
+        source: &Path,
+    ) -> Result<(), MetadataError> {
+        let metadata_type = metadata_type.into();
+        let generated = matches!(
+            metadata_type.base(),
+            MetadataType::Primary
+                | MetadataType::Filelists
+                | MetadataType::Other
+                | MetadataType::Updateinfo
+                | MetadataType::Products
+                | MetadataType::Patterns
+        );
+        if generated || metadata_type.is_zchunk() {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "{} metadata is written by the RepositoryWriter, it can't be added as a file",
+                metadata_type
+            )));
+        }
+
+        let compression = match metadata_type {
+            MetadataType::Group => CompressionType::None,
+            MetadataType::GroupGz => CompressionType::Gzip,
+            _ => self.options.metadata_compression_type,
+        };
+        let repodata_dir = self.path.join("repodata");
+        let path = repodata_dir.join(metadata_type.base().file_name(CompressionType::None));
+        let _span = logging::span!("copy {}", source.display());
+        let mut reader = utils::reader_from_file(source)?;
+        let (path, mut writer) = utils::writer_to_file(&path, compression)?;
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+
+        let href = path.strip_prefix(&self.path).unwrap().to_owned();
+        self.metadata_files
+            .retain(|(other, _)| other != &metadata_type);
+        self.metadata_files.push((metadata_type, href));
+        Ok(())
+    }
+
     /// Consume the [`RepositoryWriter`], and finish writing the repository metadata to disk.
     ///
     /// - Checks that the number of packages written matches the number of packages declared.
     /// - Completes all metadata files.
-    /// - Writes `repomd.xml`.
-    pub fn finish(mut self) -> Result<(), MetadataError> {
+    /// - Writes `repomd.xml`, replacing the previous one atomically.
+    ///
+    /// Returns which metadata files changed compared to the previous revision, see [`PublishReport`].
+    pub fn finish(mut self) -> Result<PublishReport, MetadataError> {
         assert_eq!(
             self.num_pkgs_written, self.num_pkgs,
             "Number of packages written {} is different from the number declared in the header {}.",
@@ -374,24 +1387,19 @@ impl RepositoryWriter {
         );
 
         // TODO: this is a mess
-        let path = self.path.clone();
         let repodata_dir = self.path.join("repodata");
-        let primary_path = utils::apply_compression_suffix(
-            &PathBuf::from("repodata").join("primary.xml"),
-            self.options.metadata_compression_type,
-        );
-        let filelists_path = utils::apply_compression_suffix(
-            &PathBuf::from("repodata").join("filelists.xml"),
-            self.options.metadata_compression_type,
-        );
-        let other_path = utils::apply_compression_suffix(
-            &PathBuf::from("repodata").join("other.xml"),
-            self.options.metadata_compression_type,
-        );
 
+        let span = logging::span!("finish package metadata");
+        let mut written = vec![MetadataType::Primary];
         self.primary_xml_writer.as_mut().unwrap().finish()?;
-        self.filelists_xml_writer.as_mut().unwrap().finish()?;
-        self.other_xml_writer.as_mut().unwrap().finish()?;
+        if let Some(filelists_xml_writer) = &mut self.filelists_xml_writer {
+            filelists_xml_writer.finish()?;
+            written.push(MetadataType::Filelists);
+        }
+        if let Some(other_xml_writer) = &mut self.other_xml_writer {
+            other_xml_writer.finish()?;
+            written.push(MetadataType::Other);
+        }
 
         // TODO: maybe clean this up?
         // All of the ceremony, including making the fields in the struct optional, is required to
@@ -401,51 +1409,185 @@ impl RepositoryWriter {
         drop(self.primary_xml_writer.take());
         drop(self.filelists_xml_writer.take());
         drop(self.other_xml_writer.take());
+        drop(span);
 
-        let primary_xml = RepomdRecord::new(
-            "primary",
-            &primary_path.as_ref(),
-            &path,
-            self.options.metadata_checksum_type,
-        )?;
-        self.repomd_mut().add_record(primary_xml);
-        let filelists_xml = RepomdRecord::new(
-            "filelists",
-            &filelists_path.as_ref(),
-            &path,
-            self.options.metadata_checksum_type,
-        )?;
-        self.repomd_mut().add_record(filelists_xml);
-        let other_xml = RepomdRecord::new(
-            "other",
-            &other_path.as_ref(),
-            &path,
-            self.options.metadata_checksum_type,
-        )?;
-        self.repomd_mut().add_record(other_xml);
-
-        if let Some(updateinfo_xml_writer) = &mut self.updateinfo_xml_writer {
+        for record in std::mem::take(&mut self.pending_advisories).into_values() {
+            self.write_advisory(&record)?;
+        }
+        let mut updateinfo_xml_writers = std::mem::take(&mut self.updateinfo_xml_writers);
+        updateinfo_xml_writers.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        for (metadata_type, mut updateinfo_xml_writer) in updateinfo_xml_writers {
             updateinfo_xml_writer.finish()?;
-            self.updateinfo_xml_writer = None;
-            let updateinfo_path = utils::apply_compression_suffix(
-                &PathBuf::from("repodata").join("updateinfo.xml"),
+            written.push(metadata_type);
+        }
+        if !self.products.is_empty() {
+            let (_, mut writer) = utils::filtered_xml_writer_for_path(
+                &repodata_dir.join(ProductsXml::filename()),
                 self.options.metadata_compression_type,
-            );
-            let updateinfo_xml = RepomdRecord::new(
-                "updateinfo",
-                &updateinfo_path.as_ref(),
-                &path,
-                self.options.metadata_checksum_type,
+                self.options.invalid_chars,
+                self.options.xml_format,
             )?;
-            self.repomd_mut().add_record(updateinfo_xml);
+            suse::write_products(&mut writer, &self.products, self.options.xml_style)?;
+            written.push(MetadataType::Products);
         }
+        if !self.patterns.is_empty() {
+            let (_, mut writer) = utils::filtered_xml_writer_for_path(
+                &repodata_dir.join(PatternsXml::filename()),
+                self.options.metadata_compression_type,
+                self.options.invalid_chars,
+                self.options.xml_format,
+            )?;
+            suse::write_patterns(&mut writer, &self.patterns, self.options.xml_style)?;
+            written.push(MetadataType::Patterns);
+        }
+
+        // the files are checksummed, and their zchunk versions written, several at once
+        let (path, options) = (&self.path, self.options);
+        let mut records = Vec::new();
+        let mut zchunk_records = Vec::new();
+        for result in runtime::map_concurrently(&written, 0, |metadata_type| {
+            Self::write_records(path, options, metadata_type)
+        }) {
+            let (record, zchunk_record) = result?;
+            records.push(record);
+            zchunk_records.extend(zchunk_record);
+        }
+        for (metadata_type, href) in std::mem::take(&mut self.metadata_files) {
+            records.push(RepomdRecord::new(
+                metadata_type,
+                &href,
+                &self.path,
+                self.options.metadata_checksum_type,
+            )?);
+        }
+        for record in records.into_iter().chain(zchunk_records) {
+            self.add_record(record)?;
+        }
+        self.repomd_data.sort_records_by(self.options.record_order);
 
-        let (_, mut repomd_writer) =
-            utils::xml_writer_for_path(&repodata_dir.join("repomd.xml"), CompressionType::None)?;
-        RepomdXml::write_data(&self.repomd_data, &mut repomd_writer)?;
+        let (tmp_path, mut repomd_writer) = utils::filtered_xml_writer_for_path(
+            &repodata_dir.join(".repomd.xml.tmp"),
+            CompressionType::None,
+            self.options.invalid_chars,
+            self.options.xml_format,
+        )?;
+        RepomdXml::write_data_with_style(
+            &self.repomd_data,
+            &mut repomd_writer,
+            self.options.xml_style,
+        )?;
+        // flushes repomd.xml before it's moved into place
+        drop(repomd_writer);
+        std::fs::rename(tmp_path, repodata_dir.join("repomd.xml"))?;
+        logging::debug!(
+            "wrote repomd.xml with {} records",
+            self.repomd_data.records().len()
+        );
+        if let Some(hooks) = &self.hooks {
+            hooks.repository_published(&self.path, &self.repomd_data)?;
+        }
 
-        // TODO: a report of the files created?
+        Ok(self.publish_report())
+    }
 
+    /// Compare the records of the new `repomd.xml` with those of the previous one.
+    fn publish_report(&self) -> PublishReport {
+        let previous = self
+            .previous_repomd
+            .as_ref()
+            .map(|repomd| repomd.records().as_slice())
+            .unwrap_or_default();
+        let records = self.repomd_data.records();
+        let mut changed: Vec<PathBuf> = records
+            .iter()
+            .filter(|record| {
+                !previous.iter().any(|old| {
+                    old.location_href == record.location_href && old.checksum == record.checksum
+                })
+            })
+            .map(|record| record.location_href.clone())
+            .collect();
+        changed.push(PathBuf::from("repodata/repomd.xml"));
+        let removed = previous
+            .iter()
+            .filter(|old| {
+                !records
+                    .iter()
+                    .any(|record| record.location_href == old.location_href)
+            })
+            .map(|old| old.location_href.clone())
+            .collect();
+        logging::debug!("changed {:?}, removed {:?}", changed, removed);
+        PublishReport { changed, removed }
+    }
+
+    /// The record of the metadata file of `metadata_type` in the repository at `path`, and the record of
+    /// its zchunk version if one is written.
+    fn write_records(
+        path: &Path,
+        options: RepositoryOptions,
+        metadata_type: &MetadataType,
+    ) -> Result<(RepomdRecord, Option<RepomdRecord>), MetadataError> {
+        let href = PathBuf::from("repodata")
+            .join(metadata_type.file_name(options.metadata_compression_type));
+        // SUSE tools don't read zchunk files, so only the Fedora metadata has them, and split
+        // updateinfo documents aren't read by dnf at all
+        let zchunked = matches!(
+            metadata_type,
+            MetadataType::Primary
+                | MetadataType::Filelists
+                | MetadataType::Other
+                | MetadataType::Updateinfo
+        );
+        let zchunk_record = if options.zchunk && zchunked {
+            Some(Self::write_zchunk(path, options, metadata_type, &href)?)
+        } else {
+            None
+        };
+        let record = RepomdRecord::new(
+            metadata_type.clone(),
+            &href,
+            path,
+            options.metadata_checksum_type,
+        )?;
+        Ok((record, zchunk_record))
+    }
+
+    /// Write the zchunk version of the metadata file at `href`, returning its record.
+    fn write_zchunk(
+        path: &Path,
+        options: RepositoryOptions,
+        metadata_type: &MetadataType,
+        href: &Path,
+    ) -> Result<RepomdRecord, MetadataError> {
+        let (zchunk_type, element) = transcode::zchunk_type(metadata_type)
+            .unwrap_or_else(|| unreachable!("no zchunk version of {} is written", metadata_type));
+        let zchunk_path = path
+            .join("repodata")
+            .join(zchunk_type.file_name(options.metadata_compression_type));
+        let _span = logging::span!("write {}", zchunk_path.display());
+
+        let file = BufWriter::new(File::create(&zchunk_path)?);
+        let mut writer = ZchunkWriter::new(file, element);
+        std::io::copy(&mut utils::reader_from_file(&path.join(href))?, &mut writer)?;
+        writer.finish()?;
+        RepomdRecord::from_file(zchunk_type, &zchunk_path, options.metadata_checksum_type)
+    }
+
+    /// Add the record of a metadata file to `repomd.xml`, first renaming the file so that its name starts
+    /// with its checksum unless [`RepositoryOptions::simple_metadata_filenames`] is set.
+    fn add_record(&mut self, mut record: RepomdRecord) -> Result<(), MetadataError> {
+        if !self.options.simple_metadata_filenames {
+            let (_, digest) = record.checksum.to_values()?;
+            let file_name = record.location_href.file_name().unwrap().to_string_lossy();
+            let href = PathBuf::from("repodata").join(format!("{}-{}", digest, file_name));
+            std::fs::rename(self.path.join(&record.location_href), self.path.join(&href))?;
+            record.location_href = href;
+        }
+        if let Some(hooks) = &self.hooks {
+            hooks.metadata_file_finished(&self.path.join(&record.location_href), &record)?;
+        }
+        self.repomd_mut().add_record(record);
         Ok(())
     }
 }
@@ -459,7 +1601,10 @@ pub struct RepositoryReader {
     // TODO: we're only using this for the repomd, maybe just use it directly
     // but need to figure out how to generically support loading metadata files
     repository: Repository,
+    /// Where the files are read from, with `path` being the directory of the repository in it
+    storage: Arc<dyn Storage>,
     path: PathBuf,
+    options: LoadOptions,
 }
 
 impl RepositoryReader {
@@ -467,84 +1612,468 @@ impl RepositoryReader {
     ///
     /// If `repodata/repomd.xml` cannot be found or if it cannot be parsed, this will fail.
     pub fn new_from_directory(path: &Path) -> Result<Self, MetadataError> {
+        Self::new_from_directory_with_options(path, LoadOptions::default())
+    }
+
+    /// Create a new `RepositoryReader` for a given directory `path`, which parses the metadata files
+    /// according to the [`ParseOptions`] of `options`, and which reads the metadata it selects into a
+    /// [`Repository`] with [`RepositoryReader::into_repo()`].
+    ///
+    /// If `repodata/repomd.xml` cannot be found or if it cannot be parsed, this will fail.
+    pub fn new_from_directory_with_options(
+        path: &Path,
+        options: LoadOptions,
+    ) -> Result<Self, MetadataError> {
+        Self::new_from_storage_with_options(Arc::new(LocalStorage), path, options)
+    }
+
+    /// Create a new `RepositoryReader` for the directory `path` in `storage`, such as a tar archive or
+    /// an ISO image opened with [`open_archive()`](crate::open_archive), without extracting it.
+    ///
+    /// If `repodata/repomd.xml` cannot be found or if it cannot be parsed, this will fail.
+    pub fn new_from_storage(storage: Arc<dyn Storage>, path: &Path) -> Result<Self, MetadataError> {
+        Self::new_from_storage_with_options(storage, path, LoadOptions::default())
+    }
+
+    /// Like [`RepositoryReader::new_from_storage()`], parsing the metadata files according to `options`.
+    pub fn new_from_storage_with_options(
+        storage: Arc<dyn Storage>,
+        path: &Path,
+        options: LoadOptions,
+    ) -> Result<Self, MetadataError> {
+        let repomd_path = path.join("repodata/repomd.xml");
+        let _span = logging::span!("parse {}", repomd_path.display());
+        let parse_options = options.parse_options;
+        let reader =
+            utils::filtered_xml_reader_from_storage(&*storage, &repomd_path, parse_options)?;
         let mut repo = Repository::new();
-        repo.load_metadata_file::<RepomdXml>(&path.join("repodata/repomd.xml"))?;
+        *repo.repomd_mut() =
+            RepomdXml::read_data_with_options(reader, parse_options).map_err(|e| {
+                e.with_line_from(|| utils::reader_from_storage(&*storage, &repomd_path))
+            })?;
+        logging::debug!(
+            "found {} metadata records in {}",
+            repo.repomd().records().len(),
+            path.display()
+        );
 
         Ok(Self {
             repository: repo,
+            storage,
             path: path.to_owned(),
+            options,
         })
     }
 
     /// Return the contents of `repomd.xml` in a `RepomdData` struct.
     pub fn repomd(&self) -> &RepomdData {
-        &self.repository.repomd()
+        self.repository.repomd()
     }
 
     /// Iterate over the packages of the repo.
     ///
     /// Create an iterator over the package metadata which will yield packages until completion or error.
     pub fn iter_packages(&self) -> Result<PackageIterator, MetadataError> {
-        PackageIterator::from_repodata(&self.path, self.repository.repomd())
+        PackageIterator::from_repodata_selected(
+            self.storage.clone(),
+            &self.path,
+            self.repository.repomd(),
+            self.options.parse_options,
+            true,
+            true,
+        )
     }
 
     /// Iterate over the advisories of the repo.
     ///
     /// Create an iterator over "advisory" / updateinfo metadata which will yield updaterecords until completion or error.
     pub fn iter_advisories(&self) -> Result<UpdateinfoIterator, MetadataError> {
-        UpdateinfoIterator::from_metadata(&self.path, self.repository.repomd())
+        UpdateinfoIterator::from_metadata(
+            self.storage.clone(),
+            &self.path,
+            self.repository.repomd(),
+            self.options.parse_options,
+        )
+    }
+
+    /// Open the file of `record` for reading, decompressed if it's compressed, checking it against the
+    /// sizes and checksums of the record as it's read. See [`RepomdRecord::open()`].
+    pub fn open_record(
+        &self,
+        record: &RepomdRecord,
+    ) -> Result<Box<dyn BufRead + Send>, MetadataError> {
+        utils::verified_record_reader(
+            &*self.storage,
+            &self.path,
+            record,
+            self.options.parse_options.ignore_trailing_data,
+        )
     }
 
-    // pub fn iter_comps(&self) -> Result<> {
+    /// Open the file of the `repomd.xml` record of `metadata_type` (e.g. `modules` or the name of a custom
+    /// type), see [`RepositoryReader::open_record()`]. Fails if there's no such record.
+    pub fn open_metadata(
+        &self,
+        metadata_type: &str,
+    ) -> Result<Box<dyn BufRead + Send>, MetadataError> {
+        let record = self.repomd().get_record(metadata_type).ok_or_else(|| {
+            MetadataError::InconsistentMetadataError(format!(
+                "repomd.xml has no {} record",
+                metadata_type
+            ))
+        })?;
+        self.open_record(record)
+    }
 
-    // }
+    /// Check the metadata files of the repo, reading them one at a time.
+    ///
+    /// Besides the checks of [`Repository::validate()`], this finds `repomd.xml` records pointing at files
+    /// which don't exist, files in `repodata/` which aren't referenced, package counts which don't match,
+    /// and packages which are missing from some of `primary.xml`, `filelists.xml` and `other.xml`. Fails only
+    /// if a metadata file can't be parsed.
+    pub fn validate(&self) -> Result<ValidationReport, MetadataError> {
+        let _span = logging::span!("validate {}", self.path.display());
+        validate::validate_directory(
+            &*self.storage,
+            &self.path,
+            self.repository.repomd(),
+            self.options.parse_options,
+        )
+    }
+
+    /// Check the metadata files and packages of the repo against the sizes and checksums `repomd.xml` and
+    /// `primary.xml` give them, hashing many of them at once with [`verify_files()`](crate::verify_files). Packages with a
+    /// `location_base` are somewhere else, and aren't checked.
+    pub fn verify_files(
+        &self,
+        options: VerifyOptions,
+    ) -> Result<VerificationReport, MetadataError> {
+        let _span = logging::span!("verify files of {}", self.path.display());
+        let repomd = self.repository.repomd();
+        let mut files: Vec<FileCheck> = repomd
+            .records()
+            .iter()
+            .map(|record| {
+                FileCheck::new(
+                    self.path.join(&record.location_href),
+                    record.checksum.clone(),
+                    record.size,
+                )
+            })
+            .collect();
+
+        let record = repomd
+            .get_record(MetadataType::Primary.as_str())
+            .ok_or_else(|| {
+                MetadataError::InconsistentMetadataError(
+                    "repomd.xml has no primary record".to_owned(),
+                )
+            })?;
+        let path = self.path.join(&record.location_href);
+        let mut reader = PrimaryXml::new_reader(utils::filtered_xml_reader_from_storage(
+            &*self.storage,
+            &path,
+            self.options.parse_options,
+        )?);
+        reader.set_parse_mode(self.options.parse_options.mode);
+        reader.read_header()?;
+        loop {
+            let mut package = None;
+            reader.read_package(&mut package).map_err(|e| {
+                e.with_line_from(|| utils::reader_from_storage(&*self.storage, &path))
+            })?;
+            let Some(package) = package else {
+                break;
+            };
+            if package.location_base().is_none() {
+                files.push(FileCheck::new(
+                    self.path.join(package.location_href()),
+                    package.checksum().clone(),
+                    Some(package.size_package()),
+                ));
+            }
+        }
+        Ok(verify_files_in(&*self.storage, &files, options))
+    }
+
+    /// Call `handler` with each step of reading the packages from `primary.xml`, without building the
+    /// [`Package`]s, so that custom extractors (e.g. of only the license and size of each package) don't
+    /// pay for the rest. See [`PackageEvent`].
+    pub fn read_package_events<F>(&self, mut handler: F) -> Result<(), MetadataError>
+    where
+        F: FnMut(PackageEvent) -> Result<(), MetadataError>,
+    {
+        let record = self
+            .repository
+            .repomd()
+            .get_record(MetadataType::Primary.as_str())
+            .ok_or_else(|| {
+                MetadataError::InconsistentMetadataError(
+                    "repomd.xml has no primary record".to_owned(),
+                )
+            })?;
+        let path = self.path.join(&record.location_href);
+        let _span = logging::span!("read package events of {}", path.display());
+        let mut reader = PrimaryXml::new_reader(utils::filtered_xml_reader_from_storage(
+            &*self.storage,
+            &path,
+            self.options.parse_options,
+        )?);
+        reader.set_parse_mode(self.options.parse_options.mode);
+        reader.set_preserve_unknown(self.options.parse_options.preserve_unknown);
+        reader.read_header()?;
+        while let Some(event) = reader
+            .read_event()
+            .map_err(|e| e.with_line_from(|| utils::reader_from_storage(&*self.storage, &path)))?
+        {
+            handler(event)?;
+        }
+        Ok(())
+    }
 
-    /// Consume the `RepositoryReader` and yield a [`Repository`] struct with the full repository contents.
-    pub fn into_repo(mut self) -> Result<Repository, MetadataError> {
-        let packages = self.iter_packages()?;
+    /// Like [`Repository::search_changelogs()`], but reading `other.xml` one package at a time rather than
+    /// loading the repository, so that only the changelogs of one package are held in memory at once.
+    pub fn search_changelogs(
+        &self,
+        pattern: &str,
+        since: Option<u64>,
+    ) -> Result<Vec<ChangelogMatch>, MetadataError> {
+        let record = self
+            .repository
+            .repomd()
+            .get_record(MetadataType::Other.as_str())
+            .ok_or_else(|| {
+                MetadataError::InconsistentMetadataError(
+                    "repomd.xml has no other record".to_owned(),
+                )
+            })?;
+        let path = self.path.join(&record.location_href);
+        let _span = logging::span!("search changelogs of {}", path.display());
+        let mut reader = OtherXml::new_reader(utils::filtered_xml_reader_from_storage(
+            &*self.storage,
+            &path,
+            self.options.parse_options,
+        )?);
+        reader.set_parse_mode(self.options.parse_options.mode);
+        reader.read_header()?;
+
+        let pattern = pattern.to_lowercase();
+        let mut matches = Vec::new();
+        loop {
+            let mut package = None;
+            reader.read_package(&mut package).map_err(|e| {
+                e.with_line_from(|| utils::reader_from_storage(&*self.storage, &path))
+            })?;
+            match package {
+                Some(package) => ChangelogMatch::find(&package, &pattern, since, &mut matches)?,
+                None => return Ok(matches),
+            }
+        }
+    }
+
+    /// Read the package groups of the repo, from the `group` record of `repomd.xml` (or `group_gz`), if it
+    /// has either.
+    pub fn read_comps(&self) -> Result<Option<Comps>, MetadataError> {
+        let repomd = self.repository.repomd();
+        let record = [MetadataType::Group, MetadataType::GroupGz]
+            .iter()
+            .find_map(|metadata_type| repomd.get_record(metadata_type.as_str()));
+        match record {
+            Some(record) => {
+                let path = self.path.join(&record.location_href);
+                let reader = utils::reader_from_storage(&*self.storage, &path)?;
+                Ok(Some(Comps::from_reader(reader)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Consume the `RepositoryReader` and yield a [`Repository`] struct with the repository contents
+    /// selected by the [`LoadOptions`] it was created with, all of them by default.
+    pub fn into_repo(self) -> Result<Repository, MetadataError> {
+        Ok(self.into_repo_with_report()?.0)
+    }
+
+    /// Like [`RepositoryReader::into_repo()`], but also return the problems recorded in
+    /// [`ParseMode::Lenient`] and [`ParseMode::Recover`].
+    pub fn into_repo_with_report(mut self) -> Result<(Repository, ParseReport), MetadataError> {
+        let options = self.options;
+        let mut report = if options.metadata.contains(MetadataSelection::PRIMARY) {
+            self.read_packages(options.metadata, options.changelogs, options.files)?
+        } else {
+            ParseReport::default()
+        };
+        if options.metadata.contains(MetadataSelection::UPDATEINFO) {
+            let _span = logging::span!("read advisories");
+            let mut advisories = self.iter_advisories()?;
+            for advisory in &mut advisories {
+                let advisory = advisory?;
+                self.repository
+                    .advisories_mut()
+                    .insert(advisory.id.to_owned(), advisory);
+            }
+            report.warnings.extend(advisories.take_warnings());
+            report.errors.extend(advisories.take_errors());
+        }
+        if options.metadata.contains(MetadataSelection::PRODUCTS) {
+            self.read_metadata::<ProductsXml>(MetadataType::Products)?;
+        }
+        if options.metadata.contains(MetadataSelection::PATTERNS) {
+            self.read_metadata::<PatternsXml>(MetadataType::Patterns)?;
+        }
+
+        Ok((self.repository, report))
+    }
+
+    /// Read the file of `metadata_type` into the [`Repository`] with `M`, if `repomd.xml` has a record for
+    /// it.
+    fn read_metadata<M: RpmMetadata>(
+        &mut self,
+        metadata_type: MetadataType,
+    ) -> Result<(), MetadataError> {
+        let path = match self.repository.repomd().get_record(metadata_type.as_str()) {
+            Some(record) => self.path.join(&record.location_href),
+            None => return Ok(()),
+        };
+        let _span = logging::span!("read {}", metadata_type);
+        let reader = utils::filtered_xml_reader_from_storage(
+            &*self.storage,
+            &path,
+            self.options.parse_options,
+        )?;
+        M::load_metadata(&mut self.repository, reader)
+    }
+
+    /// Read the packages into the [`Repository`], with their files and changelogs if `selection`
+    /// contains `FILELISTS` and `OTHER`, keeping the descriptions of the changelogs according to
+    /// `changelogs` and the files according to `files`.
+    fn read_packages(
+        &mut self,
+        selection: MetadataSelection,
+        changelogs: ChangelogStorage,
+        files: FileStorage,
+    ) -> Result<ParseReport, MetadataError> {
+        let mut packages = PackageIterator::from_repodata_selected(
+            self.storage.clone(),
+            &self.path,
+            self.repository.repomd(),
+            self.options.parse_options,
+            selection.contains(MetadataSelection::FILELISTS),
+            selection.contains(MetadataSelection::OTHER),
+        )?;
         self.repository
             .packages_mut()
             .reserve(packages.total_packages());
 
-        for package in packages {
-            let package = package?;
+        let store = match selection.contains(MetadataSelection::OTHER) {
+            true => ChangelogStore::new(changelogs)?,
+            false => None,
+        };
+        let compact = files == FileStorage::Compact;
+        let table = match selection.contains(MetadataSelection::FILELISTS) && compact {
+            true => Some(Arc::new(PathTable::new())),
+            false => None,
+        };
+        let _span = logging::span!("read packages");
+        for package in &mut packages {
+            let mut package = package?;
+            if let Some(store) = &store {
+                package.store_changelogs(store)?;
+            }
+            if let Some(table) = &table {
+                package.store_files(table)?;
+            }
             self.repository
                 .packages_mut()
                 .insert(package.pkgid().to_owned(), package);
         }
-
-        let advisories = self.iter_advisories()?;
-        for advisory in advisories {
-            let advisory = advisory?;
-            self.repository
-                .advisories_mut()
-                .insert(advisory.id.to_owned(), advisory);
-        }
-
-        Ok(self.repository)
+        Ok(ParseReport {
+            warnings: packages.take_warnings(),
+            errors: packages.take_errors(),
+        })
     }
 }
 
 pub struct UpdateinfoIterator {
     updateinfo: Option<UpdateinfoXmlReader<BufReader<Box<dyn std::io::Read + Send>>>>,
+    path: Option<PathBuf>,
+    /// The documents left to read after the current one, for repositories with split updateinfo
+    remaining: Vec<PathBuf>,
+    storage: Arc<dyn Storage>,
+    options: ParseOptions,
+    warnings: Vec<ParseWarning>,
+    errors: Vec<ParseError>,
 }
 
 impl UpdateinfoIterator {
-    fn from_metadata(base: &Path, repomd: &RepomdData) -> Result<Self, MetadataError> {
-        let updateinfo_href = repomd
+    fn from_metadata(
+        storage: Arc<dyn Storage>,
+        base: &Path,
+        repomd: &RepomdData,
+        options: ParseOptions,
+    ) -> Result<Self, MetadataError> {
+        // `updateinfo` first, then any split documents in the order of their records
+        let mut paths: Vec<PathBuf> = repomd
             .get_record(crate::metadata::METADATA_UPDATEINFO)
-            .map(|u| base.join(&u.location_href));
+            .into_iter()
+            .chain(repomd.records().iter().filter(|record| {
+                record
+                    .metadata_type
+                    .as_str()
+                    .starts_with(SPLIT_UPDATEINFO_PREFIX)
+            }))
+            .map(|record| base.join(&record.location_href))
+            .collect();
+        paths.reverse();
+
+        let mut iterator = Self {
+            updateinfo: None,
+            path: None,
+            remaining: paths,
+            storage,
+            options,
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        };
+        iterator.open_next()?;
+        Ok(iterator)
+    }
 
-        let reader = if let Some(updateinfo_href) = updateinfo_href {
-            let reader = UpdateinfoXml::new_reader(utils::xml_reader_from_file(
-                &base.join(updateinfo_href),
+    /// Move on to the next document, if any, keeping the warnings and errors of the current one.
+    fn open_next(&mut self) -> Result<(), MetadataError> {
+        if let Some(mut reader) = self.updateinfo.take() {
+            self.warnings.extend(reader.take_warnings());
+            self.errors.extend(reader.take_errors());
+        }
+        self.path = self.remaining.pop();
+        if let Some(path) = &self.path {
+            let mut reader = UpdateinfoXml::new_reader(utils::filtered_xml_reader_from_storage(
+                &*self.storage,
+                path,
+                self.options,
             )?);
-            Some(reader)
-        } else {
-            None
-        };
+            reader.set_parse_mode(self.options.mode);
+            self.updateinfo = Some(reader);
+        }
+        Ok(())
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        if let Some(reader) = self.updateinfo.as_mut() {
+            warnings.extend(reader.take_warnings());
+        }
+        warnings
+    }
 
-        Ok(Self { updateinfo: reader })
+    /// Take the errors of the advisories skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        let mut errors = std::mem::take(&mut self.errors);
+        if let Some(reader) = self.updateinfo.as_mut() {
+            errors.extend(reader.take_errors());
+        }
+        errors
     }
 }
 
@@ -552,6 +2081,26 @@ impl Iterator for UpdateinfoIterator {
     type Item = Result<UpdateRecord, MetadataError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.updateinfo.as_mut()?.read_update().transpose()
+        loop {
+            let (path, storage) = (&self.path, &self.storage);
+            let update = self
+                .updateinfo
+                .as_mut()?
+                .read_update()
+                .map_err(|e| {
+                    e.with_line_from(|| {
+                        utils::reader_from_storage(&**storage, path.as_ref().unwrap())
+                    })
+                })
+                .transpose();
+            match update {
+                None => {
+                    if let Err(e) = self.open_next() {
+                        return Some(Err(e));
+                    }
+                }
+                update => return update,
+            }
+        }
     }
 }
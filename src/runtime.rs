@@ -0,0 +1,254 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::logging;
+
+/// How long a thread of a [`Runtime`] waits for more work before it exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Counts the jobs of a [`Runtime::scope()`] which haven't finished yet.
+#[derive(Default)]
+struct Latch {
+    pending: Mutex<usize>,
+    done: Condvar,
+}
+
+/// The pool of threads shared by everything which runs work concurrently: reading RPMs, hashing (e.g. in
+/// [`verify_files()`](crate::verify_files)), writing and compressing metadata files, and the syncs of
+/// [`Downloader::sync_to_directory_async()`](crate::Downloader::sync_to_directory_async). See
+/// [`set_runtime()`].
+///
+/// The thread which calls into the crate always does its share of the work, and only takes the threads
+/// of the pool which are free, so work never waits for them, but when several repositories are built
+/// at once they share the pool rather than each starting one thread per CPU. Work which runs in the
+/// background, such as a sync, waits for a thread if they're all busy.
+///
+/// The threads are started as they're needed, and exit once they've had no work for a while.
+#[derive(Debug)]
+pub struct Runtime {
+    max_threads: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    jobs: VecDeque<(Job, Option<Arc<Latch>>)>,
+    threads: usize,
+    idle: usize,
+    running: usize,
+}
+
+impl std::fmt::Debug for PoolState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolState")
+            .field("jobs", &self.jobs.len())
+            .field("threads", &self.threads)
+            .field("idle", &self.idle)
+            .field("running", &self.running)
+            .finish()
+    }
+}
+
+impl Runtime {
+    /// A runtime of at most `max_threads` threads. `0` means no threads, so that all of the work is
+    /// done on the calling threads, except for background work which gets one thread regardless.
+    pub fn new(max_threads: usize) -> Self {
+        Self {
+            max_threads,
+            state: Mutex::new(PoolState::default()),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn max_threads(&self) -> usize {
+        self.max_threads
+    }
+
+    /// How many of the threads are running work right now.
+    pub fn busy_threads(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    /// Run `job` in the background, once a thread is free.
+    pub(crate) fn spawn(self: &Arc<Self>, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back((job, None));
+        self.start_thread(&mut state, self.max_threads.max(1));
+    }
+
+    /// Run `job` on a thread which is free right now, counting it in `latch` until it has finished, or
+    /// return `false` if there's none.
+    fn try_spawn(self: &Arc<Self>, job: Job, latch: &Arc<Latch>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.running + state.jobs.len() >= self.max_threads {
+            return false;
+        }
+        *latch.pending.lock().unwrap() += 1;
+        state.jobs.push_back((job, Some(latch.clone())));
+        self.start_thread(&mut state, self.max_threads);
+        true
+    }
+
+    /// Wake an idle thread for a new job, or start one if there's none and there's room for it.
+    fn start_thread(self: &Arc<Self>, state: &mut PoolState, max_threads: usize) {
+        if state.idle == 0 && state.threads < max_threads {
+            state.threads += 1;
+            let runtime = self.clone();
+            thread::spawn(move || runtime.work());
+        } else {
+            self.available.notify_one();
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some((job, latch)) = state.jobs.pop_front() {
+                state.running += 1;
+                drop(state);
+                // the panics of scoped work are passed on to the thread which waits for it
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                state = self.state.lock().unwrap();
+                state.running -= 1;
+                if let Some(latch) = latch {
+                    *latch.pending.lock().unwrap() -= 1;
+                    latch.done.notify_all();
+                }
+                continue;
+            }
+            state.idle += 1;
+            let (next, timeout) = self.available.wait_timeout(state, IDLE_TIMEOUT).unwrap();
+            state = next;
+            state.idle -= 1;
+            if timeout.timed_out() && state.jobs.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+
+    /// Run `helper` on up to `helpers` threads which are free right now, and `work` on the calling thread
+    /// with the number of them there were, returning the result of `work` once they've all finished.
+    pub(crate) fn scope<T>(
+        self: &Arc<Self>,
+        helpers: usize,
+        helper: &(dyn Fn() + Sync),
+        work: impl FnOnce(usize) -> T,
+    ) -> T {
+        let latch = Arc::new(Latch::default());
+        let panicked = Mutex::new(None);
+        let mut started = 0;
+        while started < helpers {
+            let panicked = &panicked;
+            let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+                if let Err(e) = panic::catch_unwind(AssertUnwindSafe(helper)) {
+                    *panicked.lock().unwrap() = Some(e);
+                }
+            });
+            // SAFETY: the job only borrows from this call, which waits below for every job it managed to
+            // start to finish, even if `work` panics. The jobs which didn't start are dropped unrun.
+            let job: Job = unsafe { mem::transmute(job) };
+            if !self.try_spawn(job, &latch) {
+                break;
+            }
+            started += 1;
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| work(started)));
+        drop(
+            latch
+                .done
+                .wait_while(latch.pending.lock().unwrap(), |pending| *pending > 0)
+                .unwrap(),
+        );
+        if let Some(e) = panicked.into_inner().unwrap() {
+            panic::resume_unwind(e);
+        }
+        result.unwrap_or_else(|e| panic::resume_unwind(e))
+    }
+}
+
+/// One thread per CPU.
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+static RUNTIME: RwLock<Option<Arc<Runtime>>> = RwLock::new(None);
+
+/// Draw the threads of all of the concurrent work from `runtime` from now on, in the whole process.
+pub fn set_runtime(runtime: Runtime) {
+    *RUNTIME.write().unwrap() = Some(Arc::new(runtime));
+}
+
+/// Go back to the default [`Runtime`], of one thread per CPU.
+pub fn reset_runtime() {
+    *RUNTIME.write().unwrap() = None;
+}
+
+/// The runtime the threads of concurrent work are drawn from, see [`set_runtime()`].
+pub fn current_runtime() -> Arc<Runtime> {
+    static DEFAULT: OnceLock<Arc<Runtime>> = OnceLock::new();
+    RUNTIME
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT.get_or_init(Default::default).clone())
+}
+
+/// Call `work` with each of `items`, on the calling thread and on up to `max_workers - 1` threads of the
+/// current [`Runtime`] which are free, returning the results in the order of the items. `0` workers
+/// means as many as the runtime has threads.
+pub(crate) fn map_concurrently<I, T, F>(items: &[I], max_workers: usize, work: F) -> Vec<T>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> T + Sync,
+{
+    let runtime = current_runtime();
+    let max_workers = match max_workers {
+        0 => runtime.max_threads() + 1,
+        max_workers => max_workers,
+    };
+    let next = AtomicUsize::new(0);
+    let worker = || {
+        let mut results = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(item) = items.get(index) else {
+                return results;
+            };
+            results.push((index, work(item)));
+        }
+    };
+
+    let shared = Mutex::new(Vec::new());
+    let helper = || {
+        let results = worker();
+        shared.lock().unwrap().extend(results);
+    };
+    let (mut results, helpers) = runtime.scope(
+        max_workers.min(items.len()).saturating_sub(1),
+        &helper,
+        |helpers| (worker(), helpers),
+    );
+    logging::trace!("ran {} items on {} threads", items.len(), helpers + 1);
+    results.extend(shared.into_inner().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
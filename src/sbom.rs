@@ -0,0 +1,340 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Export the packages of a [`Repository`] as a software bill of materials, as an SPDX 2.3 or a
+//! CycloneDX 1.5 JSON document.
+//!
+//! Each package becomes a package (SPDX) or a component (CycloneDX) with its name, EVR, license,
+//! checksum, supplier (the vendor) and download location, identified by its package URL
+//! (`pkg:rpm/...`). The license is copied as it is, which is only a valid SPDX license expression if
+//! the packages were built with them, as Fedora's are.
+
+use std::io::Write;
+use std::time::SystemTime;
+
+use serde_json::{json, Value};
+
+use crate::{utils, Checksum, ChecksumType, MetadataError, Package, Repository};
+
+const TOOL_NAME: &str = env!("CARGO_PKG_NAME");
+const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Options for exporting a software bill of materials.
+///
+/// - `name` - The name of the document, e.g. the name of the repository.
+/// - `namespace` - The SPDX `documentNamespace`, a URI unique to this document. If it's empty, one is
+///   made up from the name and the checksums of the packages.
+/// - `base_url` - The URL the repository is published at, which the download locations of the packages
+///   are relative to. Packages with a `location_base` are downloaded from there instead, and packages
+///   have no download location if neither is set.
+/// - `purl_namespace` - The namespace of the package URLs, which is the distribution, e.g. `fedora`.
+/// - `created` - When the document was created, as a Unix timestamp, or now if it isn't set. Set it for
+///   the same repository to give the same document.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SbomOptions {
+    pub name: String,
+    pub namespace: String,
+    pub base_url: Option<String>,
+    pub purl_namespace: Option<String>,
+    pub created: Option<u64>,
+}
+
+impl SbomOptions {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn namespace(self, namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            ..self
+        }
+    }
+
+    pub fn base_url(self, url: impl Into<String>) -> Self {
+        Self {
+            base_url: Some(url.into()),
+            ..self
+        }
+    }
+
+    pub fn purl_namespace(self, namespace: impl Into<String>) -> Self {
+        Self {
+            purl_namespace: Some(namespace.into()),
+            ..self
+        }
+    }
+
+    pub fn created(self, timestamp: u64) -> Self {
+        Self {
+            created: Some(timestamp),
+            ..self
+        }
+    }
+
+    /// The creation time as an ISO 8601 UTC timestamp, e.g. `2023-05-02T10:30:00Z`.
+    fn timestamp(&self) -> String {
+        let created = self.created.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs())
+        });
+        format!(
+            "{}Z",
+            utils::format_timestamp(created).replacen(' ', "T", 1)
+        )
+    }
+
+    fn download_location(&self, package: &Package) -> Option<String> {
+        let base = package.location_base().or(self.base_url.as_deref())?;
+        Some(format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            package.location_href()
+        ))
+    }
+
+    /// The package URL of `package`, see <https://github.com/package-url/purl-spec>.
+    fn purl(&self, package: &Package) -> String {
+        let evr = package.evr();
+        let mut purl = String::from("pkg:rpm/");
+        if let Some(namespace) = &self.purl_namespace {
+            purl.push_str(&purl_encode(namespace));
+            purl.push('/');
+        }
+        purl.push_str(&format!(
+            "{}@{}-{}?arch={}",
+            purl_encode(package.name()),
+            purl_encode(&evr.version),
+            purl_encode(&evr.release),
+            purl_encode(package.arch())
+        ));
+        if !matches!(evr.epoch.as_str(), "" | "0") {
+            purl.push_str(&format!("&epoch={}", purl_encode(&evr.epoch)));
+        }
+        purl
+    }
+}
+
+/// The repository as an SPDX 2.3 document. Each package is described by the document.
+pub fn to_spdx(repository: &Repository, options: &SbomOptions) -> Value {
+    let mut packages = Vec::new();
+    let mut relationships = Vec::new();
+    for (idx, package) in repository.packages().values().enumerate() {
+        let id = format!("SPDXRef-Package-{}", idx);
+        let mut spdx_package = json!({
+            "SPDXID": id,
+            "name": package.name(),
+            "versionInfo": version(package),
+            "supplier": or_noassertion(package.rpm_vendor(), |vendor| format!("Organization: {}", vendor)),
+            "downloadLocation": options
+                .download_location(package)
+                .unwrap_or_else(|| "NOASSERTION".to_owned()),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": or_noassertion(package.rpm_license(), str::to_owned),
+            "copyrightText": "NOASSERTION",
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": options.purl(package),
+            }],
+        });
+        if let Some((algorithm, value)) = checksum(package.checksum(), spdx_algorithm) {
+            spdx_package["checksums"] = json!([{ "algorithm": algorithm, "checksumValue": value }]);
+        }
+        if !package.summary().is_empty() {
+            spdx_package["summary"] = json!(package.summary());
+        }
+        if !package.url().is_empty() {
+            spdx_package["homepage"] = json!(package.url());
+        }
+        packages.push(spdx_package);
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": id,
+        }));
+    }
+
+    let namespace = match options.namespace.is_empty() {
+        true => default_namespace(repository, options),
+        false => options.namespace.clone(),
+    };
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": options.name,
+        "documentNamespace": namespace,
+        "creationInfo": {
+            "created": options.timestamp(),
+            "creators": [format!("Tool: {}-{}", TOOL_NAME, TOOL_VERSION)],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// The repository as a CycloneDX 1.5 document, with a component for each package.
+pub fn to_cyclonedx(repository: &Repository, options: &SbomOptions) -> Value {
+    let components: Vec<Value> = repository
+        .packages()
+        .values()
+        .map(|package| {
+            let purl = options.purl(package);
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": purl,
+                "name": package.name(),
+                "version": version(package),
+                "purl": purl,
+                "properties": [{ "name": "rpm:arch", "value": package.arch() }],
+            });
+            if !package.rpm_vendor().is_empty() {
+                component["supplier"] = json!({ "name": package.rpm_vendor() });
+            }
+            if !package.rpm_license().is_empty() {
+                component["licenses"] = json!([{ "expression": package.rpm_license() }]);
+            }
+            if let Some((algorithm, value)) = checksum(package.checksum(), cyclonedx_algorithm) {
+                component["hashes"] = json!([{ "alg": algorithm, "content": value }]);
+            }
+            if !package.summary().is_empty() {
+                component["description"] = json!(package.summary());
+            }
+            let mut references = Vec::new();
+            if let Some(url) = options.download_location(package) {
+                references.push(json!({ "type": "distribution", "url": url }));
+            }
+            if !package.url().is_empty() {
+                references.push(json!({ "type": "website", "url": package.url() }));
+            }
+            if !references.is_empty() {
+                component["externalReferences"] = json!(references);
+            }
+            component
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": options.timestamp(),
+            "tools": {
+                "components": [{ "type": "application", "name": TOOL_NAME, "version": TOOL_VERSION }],
+            },
+            "component": { "type": "data", "name": options.name },
+        },
+        "components": components,
+    })
+}
+
+/// Write the repository as an SPDX 2.3 JSON document, see [`to_spdx()`].
+pub fn write_spdx<W: Write>(
+    repository: &Repository,
+    options: &SbomOptions,
+    writer: W,
+) -> Result<(), MetadataError> {
+    serde_json::to_writer_pretty(writer, &to_spdx(repository, options))?;
+    Ok(())
+}
+
+/// Write the repository as a CycloneDX 1.5 JSON document, see [`to_cyclonedx()`].
+pub fn write_cyclonedx<W: Write>(
+    repository: &Repository,
+    options: &SbomOptions,
+    writer: W,
+) -> Result<(), MetadataError> {
+    serde_json::to_writer_pretty(writer, &to_cyclonedx(repository, options))?;
+    Ok(())
+}
+
+/// The EVR of `package`, leaving out an epoch of 0 the way rpm does.
+fn version(package: &Package) -> String {
+    let evr = package.evr();
+    match evr.epoch.as_str() {
+        "" | "0" => format!("{}-{}", evr.version, evr.release),
+        epoch => format!("{}:{}-{}", epoch, evr.version, evr.release),
+    }
+}
+
+fn or_noassertion(value: &str, f: impl FnOnce(&str) -> String) -> String {
+    match value.is_empty() {
+        true => "NOASSERTION".to_owned(),
+        false => f(value),
+    }
+}
+
+/// The name of the algorithm of `checksum` in a document (if it has a name for it) and the hex digest.
+fn checksum(
+    checksum: &Checksum,
+    algorithm: fn(ChecksumType) -> Option<&'static str>,
+) -> Option<(&'static str, &str)> {
+    let name = algorithm(checksum.checksum_type())?;
+    let (_, value) = checksum.to_values().ok()?;
+    Some((name, value))
+}
+
+fn spdx_algorithm(checksum_type: ChecksumType) -> Option<&'static str> {
+    match checksum_type {
+        ChecksumType::Md5 => Some("MD5"),
+        ChecksumType::Sha1 => Some("SHA1"),
+        ChecksumType::Sha224 => Some("SHA224"),
+        ChecksumType::Sha256 => Some("SHA256"),
+        ChecksumType::Sha384 => Some("SHA384"),
+        ChecksumType::Sha512 => Some("SHA512"),
+        ChecksumType::Unknown => None,
+    }
+}
+
+fn cyclonedx_algorithm(checksum_type: ChecksumType) -> Option<&'static str> {
+    match checksum_type {
+        ChecksumType::Md5 => Some("MD5"),
+        ChecksumType::Sha1 => Some("SHA-1"),
+        ChecksumType::Sha256 => Some("SHA-256"),
+        ChecksumType::Sha384 => Some("SHA-384"),
+        ChecksumType::Sha512 => Some("SHA-512"),
+        ChecksumType::Sha224 | ChecksumType::Unknown => None,
+    }
+}
+
+/// A namespace unique to the name of the document and the packages it describes.
+fn default_namespace(repository: &Repository, options: &SbomOptions) -> String {
+    let mut contents = options.name.clone();
+    for pkgid in repository.packages().keys() {
+        contents.push('\n');
+        contents.push_str(pkgid);
+    }
+    let digest = utils::checksum_bytes(contents.as_bytes(), ChecksumType::Sha256)
+        .ok()
+        .and_then(|checksum| checksum.to_values().ok().map(|(_, d)| d[..16].to_owned()))
+        .unwrap_or_default();
+    format!(
+        "https://spdx.org/spdxdocs/{}-{}",
+        purl_encode(&options.name),
+        digest
+    )
+}
+
+/// Percent-encode the characters of `value` which aren't allowed as they are in a package URL.
+fn purl_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
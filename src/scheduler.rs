@@ -0,0 +1,374 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::logging;
+use crate::runtime::{self, Runtime};
+use crate::MetadataError;
+
+/// Identifies a job submitted to a [`Scheduler`].
+pub type JobId = u64;
+
+/// How urgent a job is. Jobs of a higher priority are started first, and jobs of the same priority in
+/// the order they were submitted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JobPriority {
+    /// e.g. mirroring a repository, see [`SchedulerOptions::background_interval`]
+    Background,
+    #[default]
+    Normal,
+    /// e.g. verifying a repository for someone who's waiting for the answer
+    Interactive,
+}
+
+/// What has become of a job.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    /// The job returned an error or panicked, with its message
+    Failed(String),
+    /// The job was cancelled before it was started, or stopped early after it was cancelled
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job is done with, one way or another.
+    pub fn is_done(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// A job submitted to a [`Scheduler`], as returned by [`Scheduler::jobs()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub name: String,
+    pub priority: JobPriority,
+    pub status: JobStatus,
+}
+
+/// Passed to a running job, so that it can find out whether it was cancelled.
+#[derive(Clone, Debug)]
+pub struct JobContext {
+    id: JobId,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobContext {
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Whether the job was cancelled, in which case it should stop as soon as it can. A job is never
+    /// interrupted, so long jobs should check this regularly.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::Relaxed)
+    }
+}
+
+/// Options for a [`Scheduler`].
+///
+/// - `workers` - How many jobs are run at once, on threads of the current [`Runtime`](crate::Runtime).
+///   The concurrent work the jobs do, e.g. hashing, draws on the threads of the runtime which are left.
+/// - `background_interval` - The least time between starting two [`JobPriority::Background`] jobs, so
+///   that e.g. mirroring many repositories doesn't hit their servers all at once. Jobs of a higher
+///   priority are started as soon as there's a worker for them.
+#[derive(Copy, Clone, Debug)]
+pub struct SchedulerOptions {
+    pub workers: usize,
+    pub background_interval: Duration,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            background_interval: Duration::ZERO,
+        }
+    }
+}
+
+impl SchedulerOptions {
+    pub fn workers(self, val: usize) -> Self {
+        Self {
+            workers: val,
+            ..self
+        }
+    }
+
+    pub fn background_interval(self, val: Duration) -> Self {
+        Self {
+            background_interval: val,
+            ..self
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&JobContext) -> Result<(), MetadataError> + Send>;
+
+struct QueuedJob {
+    id: JobId,
+    priority: JobPriority,
+    job: Job,
+}
+
+// the heap pops the highest priority first, and the oldest job of a priority first
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, Reverse(self.id)).cmp(&(other.priority, Reverse(other.id)))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for QueuedJob {}
+
+struct JobEntry {
+    info: JobInfo,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: JobId,
+    queue: BinaryHeap<QueuedJob>,
+    jobs: HashMap<JobId, JobEntry>,
+    last_background_start: Option<Instant>,
+    /// The number of threads running jobs
+    runners: usize,
+    shutdown: bool,
+}
+
+struct Shared {
+    options: SchedulerOptions,
+    runtime: Arc<Runtime>,
+    state: Mutex<State>,
+    // notified when a job is queued or finishes, and on shutdown
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Runs repository operations (syncs, builds, verifications...) a fixed number at a time on the threads
+/// of the current [`Runtime`](crate::Runtime), highest [`JobPriority`] first, keeping track of each job
+/// so that it can be looked up or cancelled by its [`JobId`]. The building block of a service managing
+/// many repositories.
+///
+/// Dropping the scheduler cancels the jobs which haven't started, and waits for the running ones.
+pub struct Scheduler {
+    shared: Arc<Shared>,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("options", &self.shared.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Scheduler {
+    pub fn new(options: SchedulerOptions) -> Self {
+        let shared = Arc::new(Shared {
+            options,
+            runtime: runtime::current_runtime(),
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        });
+        Self { shared }
+    }
+
+    /// Queue `job`, which is called with a [`JobContext`] once a worker is free for it.
+    pub fn submit<F>(&self, name: impl Into<String>, priority: JobPriority, job: F) -> JobId
+    where
+        F: FnOnce(&JobContext) -> Result<(), MetadataError> + Send + 'static,
+    {
+        let mut state = self.shared.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        let info = JobInfo {
+            id,
+            name: name.into(),
+            priority,
+            status: JobStatus::Queued,
+        };
+        logging::debug!("queued job {} ({}, {:?})", id, info.name, priority);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        state.jobs.insert(id, JobEntry { info, cancelled });
+        state.queue.push(QueuedJob {
+            id,
+            priority,
+            job: Box::new(job),
+        });
+        if state.runners < self.shared.options.workers.max(1) {
+            state.runners += 1;
+            let shared = self.shared.clone();
+            self.shared
+                .runtime
+                .spawn(Box::new(move || run_jobs(&shared)));
+        }
+        self.shared.changed.notify_all();
+        id
+    }
+
+    /// The status of the job `id`, or `None` if there's no such job.
+    pub fn status(&self, id: JobId) -> Option<JobStatus> {
+        let state = self.shared.lock();
+        state.jobs.get(&id).map(|entry| entry.info.status.clone())
+    }
+
+    /// All of the jobs which haven't been forgotten yet (see [`Scheduler::forget_done()`]), in the order
+    /// they were submitted.
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        let state = self.shared.lock();
+        let mut jobs: Vec<JobInfo> = state
+            .jobs
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect();
+        jobs.sort_by_key(|job| job.id);
+        jobs
+    }
+
+    /// Cancel the job `id`. A queued job is never started, and a running one is told to stop through
+    /// [`JobContext::is_cancelled()`]. Returns whether the job was queued or running.
+    pub fn cancel(&self, id: JobId) -> bool {
+        let mut state = self.shared.lock();
+        let Some(entry) = state.jobs.get_mut(&id) else {
+            return false;
+        };
+        entry.cancelled.store(true, atomic::Ordering::Relaxed);
+        match entry.info.status {
+            JobStatus::Queued => {
+                entry.info.status = JobStatus::Cancelled;
+                state.queue.retain(|job| job.id != id);
+                self.shared.changed.notify_all();
+                true
+            }
+            JobStatus::Running => true,
+            _ => false,
+        }
+    }
+
+    /// Wait for the job `id` to be done with, returning its final status, or `None` if there's no such job.
+    pub fn wait(&self, id: JobId) -> Option<JobStatus> {
+        let mut state = self.shared.lock();
+        loop {
+            let status = &state.jobs.get(&id)?.info.status;
+            if status.is_done() {
+                return Some(status.clone());
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Forget the jobs which are done with, so that a long-lived scheduler doesn't keep them all.
+    pub fn forget_done(&self) {
+        let mut state = self.shared.lock();
+        state.jobs.retain(|_, entry| !entry.info.status.is_done());
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.shutdown = true;
+        for job in std::mem::take(&mut state.queue) {
+            if let Some(entry) = state.jobs.get_mut(&job.id) {
+                entry.info.status = JobStatus::Cancelled;
+            }
+        }
+        self.shared.changed.notify_all();
+        let _state = self
+            .shared
+            .changed
+            .wait_while(state, |state| state.runners > 0)
+            .unwrap();
+    }
+}
+
+/// Run the queued jobs one after another until there are none left or the scheduler shuts down.
+fn run_jobs(shared: &Shared) {
+    loop {
+        let (job, context) = {
+            let mut state = shared.lock();
+            let job = loop {
+                if state.shutdown || state.queue.is_empty() {
+                    state.runners -= 1;
+                    shared.changed.notify_all();
+                    return;
+                }
+                let wait = match state.queue.peek() {
+                    Some(job) if job.priority == JobPriority::Background => state
+                        .last_background_start
+                        .map(|start| start + shared.options.background_interval)
+                        .and_then(|next| next.checked_duration_since(Instant::now()))
+                        .filter(|wait| !wait.is_zero()),
+                    _ => None,
+                };
+                match wait {
+                    Some(wait) => state = shared.changed.wait_timeout(state, wait).unwrap().0,
+                    None => break state.queue.pop().unwrap(),
+                }
+            };
+            if job.priority == JobPriority::Background {
+                state.last_background_start = Some(Instant::now());
+            }
+            let entry = state.jobs.get_mut(&job.id).unwrap();
+            entry.info.status = JobStatus::Running;
+            let context = JobContext {
+                id: job.id,
+                cancelled: entry.cancelled.clone(),
+            };
+            (job, context)
+        };
+
+        logging::debug!("started job {}", job.id);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (job.job)(&context)));
+        let status = match result {
+            _ if context.is_cancelled() => JobStatus::Cancelled,
+            Ok(Ok(())) => JobStatus::Finished,
+            Ok(Err(e)) => JobStatus::Failed(e.to_string()),
+            Err(panic) => JobStatus::Failed(
+                panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "the job panicked".to_owned()),
+            ),
+        };
+        logging::debug!("job {} is done: {:?}", job.id, status);
+
+        let mut state = shared.lock();
+        if let Some(entry) = state.jobs.get_mut(&job.id) {
+            entry.info.status = status;
+        }
+        shared.changed.notify_all();
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use crate::logging;
+use crate::repository::SPLIT_UPDATEINFO_PREFIX;
+use crate::{
+    utils, ChecksumType, MetadataError, MetadataType, Package, PublishReport, RepositoryOptions,
+    RepositoryReader, RepositoryWriter, UpdateRecord,
+};
+
+/// What is done to a field which reveals something about the people or infrastructure behind a
+/// repository, see [`ScrubOptions`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ScrubAction {
+    /// Leave the field as it is
+    #[default]
+    Keep,
+    /// Remove the field, or the email addresses in it
+    Strip,
+    /// Replace the field, or the email addresses in it, with a salted hash of it, so that the same value is
+    /// still recognizably the same everywhere without revealing what it was
+    Hash,
+}
+
+/// How metadata is scrubbed before it's published somewhere it shouldn't reveal the internals of where it
+/// was built, see [`scrub_repository()`].
+///
+/// - `packager_emails` - The email addresses in the packager of each package.
+/// - `buildhosts` - The name of the host each package was built on.
+/// - `changelog_emails` - The email addresses in the authors of the changelogs.
+/// - `advisory_emails` - The email addresses of the issuers (`from`) of advisories.
+/// - `salt` - Mixed into the hashes of [`ScrubAction::Hash`], so that they can't be reversed by hashing
+///   guesses. Keep it secret, and keep it the same across runs for the hashes to stay the same.
+///
+/// Everything is scrubbed with [`ScrubOptions::all()`], and nothing by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScrubOptions {
+    pub packager_emails: ScrubAction,
+    pub buildhosts: ScrubAction,
+    pub changelog_emails: ScrubAction,
+    pub advisory_emails: ScrubAction,
+    pub salt: String,
+}
+
+impl ScrubOptions {
+    /// Scrub every field with `action`.
+    pub fn all(action: ScrubAction) -> Self {
+        Self {
+            packager_emails: action,
+            buildhosts: action,
+            changelog_emails: action,
+            advisory_emails: action,
+            salt: String::new(),
+        }
+    }
+
+    pub fn packager_emails(self, action: ScrubAction) -> Self {
+        Self {
+            packager_emails: action,
+            ..self
+        }
+    }
+
+    pub fn buildhosts(self, action: ScrubAction) -> Self {
+        Self {
+            buildhosts: action,
+            ..self
+        }
+    }
+
+    pub fn changelog_emails(self, action: ScrubAction) -> Self {
+        Self {
+            changelog_emails: action,
+            ..self
+        }
+    }
+
+    pub fn advisory_emails(self, action: ScrubAction) -> Self {
+        Self {
+            advisory_emails: action,
+            ..self
+        }
+    }
+
+    pub fn salt(self, salt: impl Into<String>) -> Self {
+        Self {
+            salt: salt.into(),
+            ..self
+        }
+    }
+
+    /// Scrub the fields of `package`.
+    pub fn scrub_package(&self, package: &mut Package) -> Result<(), MetadataError> {
+        if self.packager_emails != ScrubAction::Keep {
+            let packager = self.scrub_emails(package.packager(), self.packager_emails)?;
+            package.set_packager(packager);
+        }
+        match self.buildhosts {
+            ScrubAction::Keep => (),
+            ScrubAction::Strip => {
+                package.set_rpm_buildhost("");
+            }
+            ScrubAction::Hash => {
+                if !package.rpm_buildhost().is_empty() {
+                    let buildhost = self.hash(package.rpm_buildhost())?;
+                    package.set_rpm_buildhost(buildhost);
+                }
+            }
+        }
+        if self.changelog_emails != ScrubAction::Keep {
+            // in place, so that descriptions stored out-of-line stay where they are
+            for changelog in &mut package.rpm_changelogs {
+                changelog.author = self.scrub_emails(&changelog.author, self.changelog_emails)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scrub the fields of `advisory`.
+    pub fn scrub_advisory(&self, advisory: &mut UpdateRecord) -> Result<(), MetadataError> {
+        if self.advisory_emails != ScrubAction::Keep {
+            advisory.from = self.scrub_emails(&advisory.from, self.advisory_emails)?;
+        }
+        Ok(())
+    }
+
+    /// Strip or hash the email addresses in `text`, e.g. `Jane Doe <jane@example.com>`.
+    fn scrub_emails(&self, text: &str, action: ScrubAction) -> Result<String, MetadataError> {
+        let mut scrubbed = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some((start, end)) = find_email(rest) {
+            let (before, after) = (&rest[..start], &rest[end..]);
+            match action {
+                ScrubAction::Keep => scrubbed.push_str(&rest[..end]),
+                ScrubAction::Hash => {
+                    scrubbed.push_str(before);
+                    scrubbed.push_str(&self.hash(&rest[start..end])?);
+                    scrubbed.push_str("@scrubbed.invalid");
+                }
+                // leave out the brackets around the address too, and the space before them
+                ScrubAction::Strip => match (before.strip_suffix('<'), after.strip_prefix('>')) {
+                    (Some(before), Some(after)) => {
+                        scrubbed.push_str(before.trim_end());
+                        rest = after;
+                        continue;
+                    }
+                    _ => scrubbed.push_str(before),
+                },
+            }
+            rest = after;
+        }
+        scrubbed.push_str(rest);
+        Ok(scrubbed)
+    }
+
+    /// The first 16 hex digits of the salted SHA-256 of `value`.
+    fn hash(&self, value: &str) -> Result<String, MetadataError> {
+        let salted = [self.salt.as_bytes(), value.as_bytes()].concat();
+        let checksum = utils::checksum_bytes(&salted, ChecksumType::Sha256)?;
+        Ok(checksum.to_values()?.1[..16].to_owned())
+    }
+}
+
+/// The byte range of the first email address in `text`, if there is one.
+fn find_email(text: &str) -> Option<(usize, usize)> {
+    let is_email_char = |c: char| c.is_alphanumeric() || "._%+-".contains(c);
+    let mut offset = 0;
+    while let Some(at) = text[offset..].find('@').map(|at| offset + at) {
+        let start = text[..at]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !is_email_char(*c))
+            .map_or(0, |(pos, c)| pos + c.len_utf8());
+        let end = text[at + 1..]
+            .find(|c| !is_email_char(c))
+            .map_or(text.len(), |pos| at + 1 + pos);
+        if start < at && at + 1 < end {
+            return Some((start, end));
+        }
+        offset = at + 1;
+    }
+    None
+}
+
+/// Copy the repository at `source` to `destination` for publishing it somewhere public, scrubbing the
+/// packages and advisories according to `scrub` on the way (see [`ScrubOptions`]) and writing the metadata
+/// according to `options`.
+///
+/// The packages are read, scrubbed and written one at a time, so that a repository of any size can be
+/// scrubbed without loading it. The metadata files which aren't generated from the packages (such as
+/// `group` and `modules`) are copied as they are, zchunk files are written again if `options` asks for
+/// them, and the `products` and `patterns` of SUSE repositories are left out. The RPMs themselves aren't
+/// copied.
+pub fn scrub_repository(
+    source: &Path,
+    destination: &Path,
+    scrub: &ScrubOptions,
+    options: RepositoryOptions,
+) -> Result<PublishReport, MetadataError> {
+    let _span = logging::span!("scrub {} into {}", source.display(), destination.display());
+    let reader = RepositoryReader::new_from_directory(source)?;
+    let mut packages = reader.iter_packages()?;
+    let mut writer =
+        RepositoryWriter::new_with_options(destination, packages.total_packages(), options)?;
+    for package in &mut packages {
+        let mut package = package?;
+        scrub.scrub_package(&mut package)?;
+        writer.add_package(&package)?;
+    }
+    for advisory in reader.iter_advisories()? {
+        let mut advisory = advisory?;
+        scrub.scrub_advisory(&mut advisory)?;
+        writer.add_advisory(&advisory)?;
+    }
+    for record in reader.repomd().records() {
+        let generated = matches!(
+            record.metadata_type.base(),
+            MetadataType::Primary
+                | MetadataType::Filelists
+                | MetadataType::Other
+                | MetadataType::Updateinfo
+                | MetadataType::Products
+                | MetadataType::Patterns
+        ) || record
+            .metadata_type
+            .as_str()
+            .starts_with(SPLIT_UPDATEINFO_PREFIX);
+        if !generated && !record.metadata_type.is_zchunk() {
+            logging::debug!("copying {} as it is", record.location_href.display());
+            writer.add_metadata_file(
+                record.metadata_type.clone(),
+                &source.join(&record.location_href),
+            )?;
+        }
+    }
+    writer.finish()
+}
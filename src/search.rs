@@ -0,0 +1,210 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Keyword search over the names, summaries and descriptions of packages, like `dnf search`.
+//!
+//! A [`SearchIndex`] is an inverted index built once from the packages, after which a query only
+//! looks at the packages containing its words rather than scanning every description. Text is split
+//! into lowercase words at anything that isn't a letter or digit, so `python3-requests` is found by
+//! both `python3` and `requests`. Each word of a query matches the words of a package which it's a
+//! prefix of, and a package has to match every word of the query.
+//!
+//! Results are ranked the way `dnf search` orders them: packages with the query as their name first,
+//! then packages matching in their name, then in their summary, then only in their description.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use crate::{Package, Repository};
+
+/// A field of a package which is searched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SearchField {
+    Name,
+    Summary,
+    Description,
+}
+
+impl SearchField {
+    pub const ALL: [SearchField; 3] = [
+        SearchField::Name,
+        SearchField::Summary,
+        SearchField::Description,
+    ];
+
+    fn bit(self) -> u8 {
+        match self {
+            SearchField::Name => 1,
+            SearchField::Summary => 1 << 1,
+            SearchField::Description => 1 << 2,
+        }
+    }
+
+    /// How much a match in the field counts towards the score of a package.
+    fn weight(self) -> u32 {
+        match self {
+            SearchField::Name => 4,
+            SearchField::Summary => 2,
+            SearchField::Description => 1,
+        }
+    }
+
+    fn text(self, package: &Package) -> &str {
+        match self {
+            SearchField::Name => package.name(),
+            SearchField::Summary => package.summary(),
+            SearchField::Description => package.description(),
+        }
+    }
+}
+
+/// A package found by [`SearchIndex::search()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit<'a> {
+    pub package: &'a Package,
+    /// Higher for better matches, see the [module documentation](self)
+    pub score: u32,
+    /// The fields which any of the words of the query were found in
+    pub fields: Vec<SearchField>,
+}
+
+/// An index of the words in the names, summaries and descriptions of packages.
+pub struct SearchIndex<'a> {
+    packages: Vec<&'a Package>,
+    /// The packages containing each word, with the fields they contain it in, by package index
+    words: BTreeMap<String, Vec<(u32, u8)>>,
+}
+
+impl<'a> SearchIndex<'a> {
+    pub fn new(packages: impl IntoIterator<Item = &'a Package>) -> Self {
+        let packages: Vec<&'a Package> = packages.into_iter().collect();
+        let mut words: BTreeMap<String, Vec<(u32, u8)>> = BTreeMap::new();
+        let mut package_words: HashMap<String, u8> = HashMap::new();
+        for (index, package) in packages.iter().enumerate() {
+            for field in SearchField::ALL {
+                for word in split_words(field.text(package)) {
+                    *package_words.entry(word).or_default() |= field.bit();
+                }
+            }
+            for (word, fields) in package_words.drain() {
+                words.entry(word).or_default().push((index as u32, fields));
+            }
+        }
+        Self { packages, words }
+    }
+
+    /// The number of packages indexed.
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// The packages matching every word of `query` in any field, best matches first.
+    pub fn search(&self, query: &str) -> Vec<SearchHit<'a>> {
+        self.search_fields(query, &SearchField::ALL)
+    }
+
+    /// The packages matching every word of `query` in one of `fields`, best matches first. Packages
+    /// with the same score are ordered by name, then by NEVRA.
+    pub fn search_fields(&self, query: &str, fields: &[SearchField]) -> Vec<SearchHit<'a>> {
+        let mask = fields.iter().fold(0, |mask, field| mask | field.bit());
+        let query_words = split_words(query);
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+
+        // the fields matched by each word of the query, for the packages which matched all of them so far
+        let mut matched: Option<HashMap<u32, Vec<u8>>> = None;
+        for query_word in &query_words {
+            let mut word_matches: HashMap<u32, u8> = HashMap::new();
+            let prefixed = self
+                .words
+                .range::<str, _>((Bound::Included(query_word.as_str()), Bound::Unbounded))
+                .take_while(|(word, _)| word.starts_with(query_word.as_str()));
+            for (_, postings) in prefixed {
+                for (index, fields) in postings {
+                    if fields & mask != 0 {
+                        *word_matches.entry(*index).or_default() |= fields & mask;
+                    }
+                }
+            }
+            matched = Some(match matched {
+                None => word_matches
+                    .into_iter()
+                    .map(|(index, fields)| (index, vec![fields]))
+                    .collect(),
+                Some(mut matched) => {
+                    matched.retain(|index, found| match word_matches.get(index) {
+                        Some(fields) => {
+                            found.push(*fields);
+                            true
+                        }
+                        None => false,
+                    });
+                    matched
+                }
+            });
+        }
+
+        let query = query.trim().to_lowercase();
+        let mut hits: Vec<SearchHit<'a>> = matched
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(index, found)| {
+                let package = self.packages[index as usize];
+                let mut score: u32 = found
+                    .iter()
+                    .map(|fields| {
+                        SearchField::ALL
+                            .iter()
+                            .filter(|field| fields & field.bit() != 0)
+                            .map(|field| field.weight())
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .sum();
+                if mask & SearchField::Name.bit() != 0 && package.name().to_lowercase() == query {
+                    score += 100;
+                }
+                let all_found = found.iter().fold(0, |all, fields| all | fields);
+                let fields = SearchField::ALL
+                    .into_iter()
+                    .filter(|field| all_found & field.bit() != 0)
+                    .collect();
+                SearchHit {
+                    package,
+                    score,
+                    fields,
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.package.name().cmp(b.package.name()))
+                .then_with(|| a.package.nevra().cmp(&b.package.nevra()))
+        });
+        hits
+    }
+}
+
+impl Repository {
+    /// Index the packages of the repository for [`SearchIndex::search()`].
+    pub fn search_index(&self) -> SearchIndex<'_> {
+        SearchIndex::new(self.packages().values())
+    }
+}
+
+/// The lowercase words of `text`.
+fn split_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use crate::{Package, Repository, UpdateRecord, EVR};
+
+/// The severity of an advisory, as in updateinfo.xml. Severities are ordered from `None` to `Critical`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AdvisorySeverity {
+    None,
+    Low,
+    Moderate,
+    Important,
+    Critical,
+}
+
+impl AdvisorySeverity {
+    /// The severity named `severity`, ignoring case. An advisory without a severity has `None`, as in dnf.
+    pub fn parse(severity: &str) -> Option<Self> {
+        let severity = severity.trim();
+        if severity.is_empty() {
+            return Some(AdvisorySeverity::None);
+        }
+        [
+            AdvisorySeverity::None,
+            AdvisorySeverity::Low,
+            AdvisorySeverity::Moderate,
+            AdvisorySeverity::Important,
+            AdvisorySeverity::Critical,
+        ]
+        .into_iter()
+        .find(|s| s.as_str().eq_ignore_ascii_case(severity))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdvisorySeverity::None => "None",
+            AdvisorySeverity::Low => "Low",
+            AdvisorySeverity::Moderate => "Moderate",
+            AdvisorySeverity::Important => "Important",
+            AdvisorySeverity::Critical => "Critical",
+        }
+    }
+}
+
+/// An installed package which a security advisory updates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityUpdatePackage {
+    pub name: String,
+    pub arch: String,
+    pub installed: EVR,
+    /// The version the advisory fixes the package in
+    pub fixed: EVR,
+    /// The newest version of the package in the repository, at least as new as `fixed`
+    pub available: EVR,
+}
+
+/// A security advisory which applies to the installed packages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityUpdate {
+    pub id: String,
+    pub title: String,
+    pub severity: AdvisorySeverity,
+    pub issued_date: Option<String>,
+    /// The IDs of the CVEs the advisory references
+    pub cves: Vec<String>,
+    pub packages: Vec<SecurityUpdatePackage>,
+}
+
+/// The security updates found by [`Repository::security_updates()`], most severe first and otherwise
+/// in order of their IDs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityFeed {
+    pub updates: Vec<SecurityUpdate>,
+}
+
+impl SecurityFeed {
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    /// The number of updates of each severity.
+    pub fn counts(&self) -> HashMap<AdvisorySeverity, usize> {
+        let mut counts = HashMap::new();
+        for update in &self.updates {
+            *counts.entry(update.severity).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The feed as JSON: an object with a list of `updates`, each with the `id`, `title`, `severity`,
+    /// `issued_date` and `cves` of the advisory and the `packages` it updates, with their `name`, `arch`
+    /// and `installed`, `fixed` and `available` EVRs.
+    #[cfg(feature = "errata")]
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let updates: Vec<serde_json::Value> = self
+            .updates
+            .iter()
+            .map(|update| {
+                let packages: Vec<serde_json::Value> = update
+                    .packages
+                    .iter()
+                    .map(|package| {
+                        json!({
+                            "name": package.name,
+                            "arch": package.arch,
+                            "installed": package.installed.to_string(),
+                            "fixed": package.fixed.to_string(),
+                            "available": package.available.to_string(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "id": update.id,
+                    "title": update.title,
+                    "severity": update.severity.as_str(),
+                    "issued_date": update.issued_date,
+                    "cves": update.cves,
+                    "packages": packages,
+                })
+            })
+            .collect();
+        json!({ "updates": updates })
+    }
+}
+
+impl Repository {
+    /// The security advisories of at least `min_severity` which update any of the `installed` packages
+    /// to a version the repository has, combining the advisories with the newest versions of the packages
+    /// as [`Repository::check_update()`] finds them.
+    ///
+    /// An installed package is updated by an advisory if the advisory has a newer version of a package of
+    /// the same name and arch. Advisories with a severity which isn't understood are left out.
+    pub fn security_updates<'a>(
+        &self,
+        installed: impl IntoIterator<Item = &'a Package>,
+        min_severity: AdvisorySeverity,
+    ) -> SecurityFeed {
+        let mut installed_evrs: HashMap<(&str, &str), Vec<&EVR>> = HashMap::new();
+        for package in installed {
+            installed_evrs
+                .entry((package.name(), package.arch()))
+                .or_default()
+                .push(package.evr());
+        }
+        let mut newest: HashMap<(&str, &str), &EVR> = HashMap::new();
+        for package in self.packages().values() {
+            let evr = newest
+                .entry((package.name(), package.arch()))
+                .or_insert(package.evr());
+            if package.evr() > *evr {
+                *evr = package.evr();
+            }
+        }
+
+        let mut updates: Vec<SecurityUpdate> = self
+            .advisories()
+            .values()
+            .filter(|advisory| advisory.update_type == "security")
+            .filter_map(|advisory| {
+                let severity = AdvisorySeverity::parse(&advisory.severity)?;
+                if severity < min_severity {
+                    return None;
+                }
+                let packages = updated_packages(advisory, &installed_evrs, &newest);
+                if packages.is_empty() {
+                    return None;
+                }
+                Some(SecurityUpdate {
+                    id: advisory.id.clone(),
+                    title: advisory.title.clone(),
+                    severity,
+                    issued_date: advisory.issued_date.clone(),
+                    cves: advisory
+                        .references
+                        .iter()
+                        .filter(|reference| reference.reftype == "cve")
+                        .map(|reference| reference.id.clone())
+                        .collect(),
+                    packages,
+                })
+            })
+            .collect();
+        updates.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.id.cmp(&b.id)));
+        SecurityFeed { updates }
+    }
+}
+
+/// The installed packages which `advisory` updates, to a version available in the repository.
+fn updated_packages(
+    advisory: &UpdateRecord,
+    installed: &HashMap<(&str, &str), Vec<&EVR>>,
+    newest: &HashMap<(&str, &str), &EVR>,
+) -> Vec<SecurityUpdatePackage> {
+    let mut packages: Vec<SecurityUpdatePackage> = Vec::new();
+    for package in advisory.packages() {
+        let key = (package.name.as_str(), package.arch.as_str());
+        let fixed = EVR::new(&package.epoch, &package.version, &package.release);
+        let Some(available) = newest.get(&key).filter(|available| ***available >= fixed) else {
+            continue;
+        };
+        for installed in installed.get(&key).into_iter().flatten() {
+            let listed = packages.iter().any(|p| {
+                p.name == package.name && p.arch == package.arch && p.installed == **installed
+            });
+            if **installed < fixed && !listed {
+                packages.push(SecurityUpdatePackage {
+                    name: package.name.clone(),
+                    arch: package.arch.clone(),
+                    installed: (*installed).clone(),
+                    fixed: fixed.clone(),
+                    available: (*available).clone(),
+                });
+            }
+        }
+    }
+    packages
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::{MetadataError, Package, Repository};
+
+/// The tags of the signature header which hold OpenPGP signatures, in the order rpm prefers them: the
+/// RSA and DSA/EdDSA signatures of the header, then the older signatures of the header and payload.
+const SIGNATURE_TAGS: [rpm::IndexSignatureTag; 4] = [
+    rpm::IndexSignatureTag::RPMSIGTAG_RSA,
+    rpm::IndexSignatureTag::RPMSIGTAG_DSA,
+    rpm::IndexSignatureTag::RPMSIGTAG_PGP,
+    rpm::IndexSignatureTag::RPMSIGTAG_GPG,
+];
+
+/// Whether the RPM of a package is signed, and with which key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Unsigned,
+    /// Signed with one of the expected keys, or with any key if no keys are expected
+    Signed(String),
+    /// Signed with a key which isn't one of the expected keys
+    UnexpectedKey(String),
+}
+
+impl SignatureStatus {
+    /// The status of an RPM signed with `key_id` (`None` if it isn't signed), given the IDs of the keys
+    /// which are allowed to sign packages. If no keys are given, any signature is accepted.
+    ///
+    /// The allowed keys may be given as short (8 hex digits) or long (16 hex digits) key IDs, or as
+    /// fingerprints, in upper or lower case.
+    pub fn new(key_id: Option<&str>, allowed_keys: &[&str]) -> Self {
+        let Some(key_id) = key_id else {
+            return SignatureStatus::Unsigned;
+        };
+        let key_id = key_id.to_ascii_lowercase();
+        let allowed = allowed_keys.is_empty()
+            || allowed_keys.iter().any(|allowed| {
+                let allowed = allowed.to_ascii_lowercase();
+                match allowed.len() <= key_id.len() {
+                    true => key_id.ends_with(&allowed),
+                    false => allowed.ends_with(&key_id),
+                }
+            });
+        match allowed {
+            true => SignatureStatus::Signed(key_id),
+            false => SignatureStatus::UnexpectedKey(key_id),
+        }
+    }
+
+    /// The ID of the signing key, `None` if the RPM isn't signed.
+    pub fn key_id(&self) -> Option<&str> {
+        match self {
+            SignatureStatus::Unsigned => None,
+            SignatureStatus::Signed(key_id) | SignatureStatus::UnexpectedKey(key_id) => {
+                Some(key_id)
+            }
+        }
+    }
+}
+
+/// The signature status of the packages of a repository, by NEVRA.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignatureReport {
+    pub packages: BTreeMap<String, SignatureStatus>,
+}
+
+impl SignatureReport {
+    /// Whether every package is signed with one of the expected keys.
+    pub fn is_compliant(&self) -> bool {
+        self.packages
+            .values()
+            .all(|status| matches!(status, SignatureStatus::Signed(_)))
+    }
+
+    /// The NEVRAs of the packages which aren't signed.
+    pub fn unsigned(&self) -> impl Iterator<Item = &str> {
+        self.packages
+            .iter()
+            .filter(|(_, status)| **status == SignatureStatus::Unsigned)
+            .map(|(nevra, _)| nevra.as_str())
+    }
+
+    /// The NEVRAs of the packages signed with a key which isn't expected, and the IDs of those keys.
+    pub fn unexpected_keys(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.packages
+            .iter()
+            .filter_map(|(nevra, status)| match status {
+                SignatureStatus::UnexpectedKey(key_id) => Some((nevra.as_str(), key_id.as_str())),
+                _ => None,
+            })
+    }
+}
+
+impl Package {
+    /// The ID of the key that the RPM of the package (found at `location_href` under `repo_root`) is
+    /// signed with, as 16 lowercase hex digits, or `None` if it isn't signed. Only the headers of the RPM
+    /// are read, and the signature isn't verified.
+    pub fn signature_key_id(&self, repo_root: &Path) -> Result<Option<String>, MetadataError> {
+        rpm_signature_key_id(&repo_root.join(self.location_href()))
+    }
+}
+
+impl Repository {
+    /// Check which of the packages are signed, and whether they're signed with one of `allowed_keys`,
+    /// reading the RPMs under `repo_root`. See [`SignatureStatus::new()`] for how keys are matched.
+    pub fn check_signatures(
+        &self,
+        repo_root: &Path,
+        allowed_keys: &[&str],
+    ) -> Result<SignatureReport, MetadataError> {
+        let mut report = SignatureReport::default();
+        for package in self.packages().values() {
+            let key_id = package.signature_key_id(repo_root)?;
+            let status = SignatureStatus::new(key_id.as_deref(), allowed_keys);
+            report.packages.insert(package.nevra(), status);
+        }
+        Ok(report)
+    }
+}
+
+/// The ID of the key that the RPM at `path` is signed with, see [`Package::signature_key_id()`].
+pub fn rpm_signature_key_id(path: &Path) -> Result<Option<String>, MetadataError> {
+    let metadata = rpm::PackageMetadata::parse(&mut BufReader::new(File::open(path)?))?;
+    for tag in SIGNATURE_TAGS {
+        let Ok(signature) = metadata.signature.get_entry_data_as_binary(tag) else {
+            continue;
+        };
+        return match issuer(signature) {
+            Some(key_id) => Ok(Some(hex::encode(key_id))),
+            None => Err(MetadataError::InvalidFieldError(
+                "OpenPGP signature",
+                path.display().to_string(),
+            )),
+        };
+    }
+    Ok(None)
+}
+
+/// The key ID of the issuer of an OpenPGP signature packet (RFC 4880), `None` if it can't be parsed.
+fn issuer(packet: &[u8]) -> Option<&[u8]> {
+    let (&first, rest) = packet.split_first()?;
+    if first & 0x80 == 0 {
+        return None;
+    }
+    let (tag, body) = if first & 0x40 != 0 {
+        let (&length, rest) = rest.split_first()?;
+        let (length, rest) = match length {
+            0..=191 => (length as usize, rest),
+            192..=223 => (
+                ((length as usize - 192) << 8) + *rest.first()? as usize + 192,
+                rest.get(1..)?,
+            ),
+            255 => (
+                u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize,
+                rest.get(4..)?,
+            ),
+            // partial lengths aren't used for signatures
+            _ => return None,
+        };
+        (first & 0x3f, rest.get(..length)?)
+    } else {
+        let (length, rest) = match first & 0x03 {
+            0 => (*rest.first()? as usize, rest.get(1..)?),
+            1 => (
+                u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize,
+                rest.get(2..)?,
+            ),
+            2 => (
+                u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize,
+                rest.get(4..)?,
+            ),
+            _ => (rest.len(), rest),
+        };
+        ((first >> 2) & 0x0f, rest.get(..length)?)
+    };
+    if tag != 2 {
+        return None;
+    }
+
+    match body.first()? {
+        // the key ID follows the signature type and creation time
+        3 => body.get(7..15),
+        4 => {
+            let hashed_len = u16::from_be_bytes(body.get(4..6)?.try_into().ok()?) as usize;
+            let hashed = body.get(6..6 + hashed_len)?;
+            let rest = body.get(6 + hashed_len..)?;
+            let unhashed_len = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+            let unhashed = rest.get(2..2 + unhashed_len)?;
+            let subpackets = || subpackets(hashed).chain(subpackets(unhashed));
+            // the issuer, or else the issuer fingerprint (a version, then a v4 fingerprint of 20 bytes
+            // ending in the key ID)
+            subpackets()
+                .find_map(|(kind, data)| (kind == 16 && data.len() == 8).then_some(data))
+                .or_else(|| {
+                    subpackets().find_map(|(kind, data)| match (kind, data.first()) {
+                        (33, Some(4)) if data.len() == 21 => data.get(13..),
+                        _ => None,
+                    })
+                })
+        }
+        _ => None,
+    }
+}
+
+/// The type and contents of the signature subpackets in `data`, stopping at the first malformed one.
+fn subpackets(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (&first, rest) = data.split_first()?;
+        let (length, rest) = match first {
+            0..=191 => (first as usize, rest),
+            192..=254 => (
+                ((first as usize - 192) << 8) + *rest.first()? as usize + 192,
+                rest.get(1..)?,
+            ),
+            255 => (
+                u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize,
+                rest.get(4..)?,
+            ),
+        };
+        let subpacket = rest.get(..length)?;
+        data = &rest[length..];
+        let (&kind, contents) = subpacket.split_first()?;
+        // the top bit marks the subpacket as critical
+        Some((kind & 0x7f, contents))
+    })
+}
@@ -0,0 +1,785 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The sqlite versions of `primary.xml`, `filelists.xml` and `other.xml` which createrepo_c can write, and
+//! which yum and older versions of dnf read instead of the XML.
+//!
+//! The schema is createrepo_c's (database version 10), down to the text of the statements creating the
+//! tables and indexes, so that clients can't tell the databases apart from the ones createrepo_c writes.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use indexmap::IndexMap;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::primary::is_primary_file;
+use crate::{
+    logging, utils, Checksum, ChecksumType, FileType, FilelistsXml, MetadataError, MetadataType,
+    OtherXml, Package, PackageFile, PrimaryXml, Repository, Requirement, EVR,
+};
+
+/// The version of createrepo_c's schema, recorded in the databases and in their `repomd.xml` records.
+pub const DATABASE_VERSION: u32 = 10;
+
+const DB_INFO_TABLE: &str = "CREATE TABLE db_info (dbversion INTEGER, checksum TEXT)";
+
+const PRIMARY_PACKAGES_TABLE: &str = concat!(
+    "CREATE TABLE packages (",
+    "  pkgKey INTEGER PRIMARY KEY,",
+    "  pkgId TEXT,",
+    "  name TEXT,",
+    "  arch TEXT,",
+    "  version TEXT,",
+    "  epoch TEXT,",
+    "  release TEXT,",
+    "  summary TEXT,",
+    "  description TEXT,",
+    "  url TEXT,",
+    "  time_file INTEGER,",
+    "  time_build INTEGER,",
+    "  rpm_license TEXT,",
+    "  rpm_vendor TEXT,",
+    "  rpm_group TEXT,",
+    "  rpm_buildhost TEXT,",
+    "  rpm_sourcerpm TEXT,",
+    "  rpm_header_start INTEGER,",
+    "  rpm_header_end INTEGER,",
+    "  rpm_packager TEXT,",
+    "  size_package INTEGER,",
+    "  size_installed INTEGER,",
+    "  size_archive INTEGER,",
+    "  location_href TEXT,",
+    "  location_base TEXT,",
+    "  checksum_type TEXT)"
+);
+
+const PRIMARY_FILES_TABLE: &str = concat!(
+    "CREATE TABLE files (",
+    "  name TEXT,",
+    "  type TEXT,",
+    "  pkgKey INTEGER)"
+);
+
+const PRIMARY_TRIGGER: &str = concat!(
+    "CREATE TRIGGER removals AFTER DELETE ON packages",
+    "  BEGIN",
+    "    DELETE FROM files WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM requires WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM provides WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM conflicts WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM obsoletes WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM suggests WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM enhances WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM recommends WHERE pkgKey = old.pkgKey;",
+    "    DELETE FROM supplements WHERE pkgKey = old.pkgKey;",
+    "  END;"
+);
+
+const INSERT_PRIMARY_PACKAGE: &str = concat!(
+    "INSERT INTO packages (",
+    "  pkgId, name, arch, version, epoch, release, summary, description,",
+    "  url, time_file, time_build, rpm_license, rpm_vendor, rpm_group,",
+    "  rpm_buildhost, rpm_sourcerpm, rpm_header_start, rpm_header_end,",
+    "  rpm_packager, size_package, size_installed, size_archive,",
+    "  location_href, location_base, checksum_type) ",
+    "VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+);
+
+/// The tables of the dependencies of packages in the primary database. Only `requires` records whether
+/// the dependency is needed before the package is installed.
+const DEPENDENCY_TABLES: [&str; 8] = [
+    "requires",
+    "provides",
+    "conflicts",
+    "obsoletes",
+    "suggests",
+    "enhances",
+    "recommends",
+    "supplements",
+];
+
+const FILELISTS_PACKAGES_TABLE: &str = concat!(
+    "CREATE TABLE packages (",
+    "  pkgKey INTEGER PRIMARY KEY,",
+    "  pkgId TEXT)"
+);
+
+const FILELISTS_FILELIST_TABLE: &str = concat!(
+    "CREATE TABLE filelist (",
+    "  pkgKey INTEGER,",
+    "  dirname TEXT,",
+    "  filenames TEXT,",
+    "  filetypes TEXT)"
+);
+
+const FILELISTS_TRIGGER: &str = concat!(
+    "CREATE TRIGGER remove_filelist AFTER DELETE ON packages",
+    "  BEGIN",
+    "    DELETE FROM filelist WHERE pkgKey = old.pkgKey;",
+    "  END;"
+);
+
+const OTHER_CHANGELOG_TABLE: &str = concat!(
+    "CREATE TABLE changelog (",
+    "  pkgKey INTEGER,",
+    "  author TEXT,",
+    "  date INTEGER,",
+    "  changelog TEXT)"
+);
+
+const OTHER_TRIGGER: &str = concat!(
+    "CREATE TRIGGER remove_changelogs AFTER DELETE ON packages",
+    "  BEGIN",
+    "    DELETE FROM changelog WHERE pkgKey = old.pkgKey;",
+    "  END;"
+);
+
+/// The statements creating the tables of the database of `metadata_type`, and those creating its indexes
+/// (which are only created once the packages are in).
+fn schema(metadata_type: &MetadataType) -> Result<(Vec<String>, Vec<String>), MetadataError> {
+    let schema = match metadata_type {
+        MetadataType::PrimaryDb => {
+            let mut tables = vec![
+                PRIMARY_PACKAGES_TABLE.to_owned(),
+                PRIMARY_FILES_TABLE.to_owned(),
+            ];
+            let mut indexes = vec![
+                "CREATE INDEX IF NOT EXISTS packagename ON packages (name)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS packageId ON packages (pkgId)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS filenames ON files (name)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS pkgfiles ON files (pkgKey)".to_owned(),
+            ];
+            for (index, table) in DEPENDENCY_TABLES.iter().enumerate() {
+                let pre = match *table {
+                    "requires" => ", pre BOOLEAN DEFAULT FALSE",
+                    _ => "",
+                };
+                tables.push(format!(
+                    "CREATE TABLE {} (  name TEXT,  flags TEXT,  epoch TEXT,  version TEXT,  release TEXT,  pkgKey INTEGER {})",
+                    table, pre
+                ));
+                indexes.push(format!(
+                    "CREATE INDEX IF NOT EXISTS pkg{} on {} (pkgKey)",
+                    table, table
+                ));
+                // only requires and provides are looked up by name
+                if index < 2 {
+                    indexes.push(format!(
+                        "CREATE INDEX IF NOT EXISTS {}name ON {} (name)",
+                        table, table
+                    ));
+                }
+            }
+            tables.push(PRIMARY_TRIGGER.to_owned());
+            (tables, indexes)
+        }
+        MetadataType::FilelistsDb => (
+            vec![
+                FILELISTS_PACKAGES_TABLE.to_owned(),
+                FILELISTS_FILELIST_TABLE.to_owned(),
+                FILELISTS_TRIGGER.to_owned(),
+            ],
+            vec![
+                "CREATE INDEX IF NOT EXISTS keyfile ON filelist (pkgKey)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS pkgId ON packages (pkgId)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS dirnames ON filelist (dirname)".to_owned(),
+            ],
+        ),
+        MetadataType::OtherDb => (
+            vec![
+                FILELISTS_PACKAGES_TABLE.to_owned(),
+                OTHER_CHANGELOG_TABLE.to_owned(),
+                OTHER_TRIGGER.to_owned(),
+            ],
+            vec![
+                "CREATE INDEX IF NOT EXISTS keychange ON changelog (pkgKey)".to_owned(),
+                "CREATE INDEX IF NOT EXISTS pkgId ON packages (pkgId)".to_owned(),
+            ],
+        ),
+        other => {
+            return Err(MetadataError::InvalidFieldError(
+                "sqlite metadata type",
+                other.to_string(),
+            ))
+        }
+    };
+    Ok(schema)
+}
+
+/// Writes one of the sqlite databases of a repository, `primary.sqlite`, `filelists.sqlite` or
+/// `other.sqlite`, a package at a time.
+///
+/// The packages are written in a single transaction which is committed by [`SqliteWriter::finish()`], so
+/// the database is empty if it's not finished.
+pub struct SqliteWriter {
+    connection: Connection,
+    metadata_type: MetadataType,
+    indexes: Vec<String>,
+}
+
+impl SqliteWriter {
+    /// Create the database of `metadata_type` ([`MetadataType::PrimaryDb`], [`MetadataType::FilelistsDb`]
+    /// or [`MetadataType::OtherDb`]) at `path`, replacing the file that's there.
+    pub fn new(path: &Path, metadata_type: &MetadataType) -> Result<Self, MetadataError> {
+        let (tables, indexes) = schema(metadata_type)?;
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        let connection = Connection::open(path)?;
+        // the database is only complete once it's finished anyway, so it's not synced as it's written
+        connection.execute_batch(
+            "PRAGMA synchronous = OFF; PRAGMA journal_mode = MEMORY; PRAGMA temp_store = MEMORY; BEGIN",
+        )?;
+        connection.execute(DB_INFO_TABLE, [])?;
+        for sql in &tables {
+            connection.execute(sql, [])?;
+        }
+        Ok(SqliteWriter {
+            connection,
+            metadata_type: metadata_type.clone(),
+            indexes,
+        })
+    }
+
+    /// Write `package` to the database.
+    pub fn write_package(&mut self, package: &Package) -> Result<(), MetadataError> {
+        match self.metadata_type {
+            MetadataType::PrimaryDb => self.write_primary(package),
+            MetadataType::FilelistsDb => self.write_filelists(package),
+            _ => self.write_other(package),
+        }
+    }
+
+    fn write_primary(&self, package: &Package) -> Result<(), MetadataError> {
+        let (checksum_type, pkgid) = package.checksum().to_values()?;
+        let evr = package.evr();
+        let header_range = package.rpm_header_range();
+        self.connection
+            .prepare_cached(INSERT_PRIMARY_PACKAGE)?
+            .execute(params![
+                pkgid,
+                package.name(),
+                package.arch(),
+                evr.version(),
+                evr.epoch(),
+                evr.release(),
+                package.summary(),
+                package.description(),
+                package.url(),
+                package.time_file(),
+                package.time_build(),
+                package.rpm_license(),
+                package.rpm_vendor(),
+                package.rpm_group(),
+                package.rpm_buildhost(),
+                package.rpm_sourcerpm(),
+                header_range.start,
+                header_range.end,
+                package.packager(),
+                package.size_package(),
+                package.size_installed(),
+                package.size_archive(),
+                package.location_href(),
+                package.location_base(),
+                checksum_type,
+            ])?;
+        let pkg_key = self.connection.last_insert_rowid();
+
+        for table in DEPENDENCY_TABLES {
+            for requirement in dependencies(package, table) {
+                let (name, flags, epoch, version, release) = (
+                    &requirement.name,
+                    &requirement.flags,
+                    &requirement.epoch,
+                    &requirement.version,
+                    &requirement.release,
+                );
+                if table == "requires" {
+                    let pre = if requirement.preinstall {
+                        "TRUE"
+                    } else {
+                        "FALSE"
+                    };
+                    self.connection
+                        .prepare_cached(
+                            "INSERT INTO requires (name, flags, epoch, version, release, pkgKey, pre) VALUES (?, ?, ?, ?, ?, ?, ?)",
+                        )?
+                        .execute(params![name, flags, epoch, version, release, pkg_key, pre])?;
+                } else {
+                    self.connection
+                        .prepare_cached(&format!(
+                            "INSERT INTO {} (name, flags, epoch, version, release, pkgKey) VALUES (?, ?, ?, ?, ?, ?)",
+                            table
+                        ))?
+                        .execute(params![name, flags, epoch, version, release, pkg_key])?;
+                }
+            }
+        }
+
+        let mut insert_file = self
+            .connection
+            .prepare_cached("INSERT INTO files (name, type, pkgKey) VALUES (?, ?, ?)")?;
+        for file in package.load_files().iter() {
+            if is_primary_file(&file.path) {
+                let filetype = std::str::from_utf8(file.filetype.to_values())?;
+                insert_file.execute(params![file.path, filetype, pkg_key])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_filelists(&self, package: &Package) -> Result<(), MetadataError> {
+        let pkg_key = self.insert_pkgid(package)?;
+
+        // the files are listed by directory, with their names separated by slashes and their types
+        // abbreviated to a character each
+        let files = package.load_files();
+        let mut dirs: IndexMap<&str, (String, String)> = IndexMap::new();
+        for file in files.iter() {
+            let (dirname, filename) = match file.path.rfind('/') {
+                Some(0) => ("/", &file.path[1..]),
+                Some(idx) => (&file.path[..idx], &file.path[idx + 1..]),
+                None => ("", file.path.as_str()),
+            };
+            let (filenames, filetypes) = dirs.entry(dirname).or_default();
+            if !filetypes.is_empty() {
+                filenames.push('/');
+            }
+            filenames.push_str(filename);
+            filetypes.push(match file.filetype {
+                FileType::File => 'f',
+                FileType::Dir => 'd',
+                FileType::Ghost => 'g',
+            });
+        }
+        let mut insert_files = self.connection.prepare_cached(
+            "INSERT INTO filelist (pkgKey, dirname, filenames, filetypes) VALUES (?, ?, ?, ?)",
+        )?;
+        for (dirname, (filenames, filetypes)) in dirs {
+            insert_files.execute(params![pkg_key, dirname, filenames, filetypes])?;
+        }
+        Ok(())
+    }
+
+    fn write_other(&self, package: &Package) -> Result<(), MetadataError> {
+        let pkg_key = self.insert_pkgid(package)?;
+
+        let mut insert_changelog = self.connection.prepare_cached(
+            "INSERT INTO changelog (pkgKey, author, date, changelog) VALUES (?, ?, ?, ?)",
+        )?;
+        for changelog in package.load_changelogs()?.iter() {
+            insert_changelog.execute(params![
+                pkg_key,
+                changelog.author,
+                changelog.timestamp,
+                changelog.description
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Add `package` to the `packages` table of the filelists or other database, returning its key.
+    fn insert_pkgid(&self, package: &Package) -> Result<i64, MetadataError> {
+        self.connection
+            .prepare_cached("INSERT INTO packages (pkgId) VALUES (?)")?
+            .execute([package.pkgid()])?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Create the indexes, record `xml_checksum`, the checksum of the XML metadata file the packages came
+    /// from, and commit the database.
+    pub fn finish(self, xml_checksum: &Checksum) -> Result<(), MetadataError> {
+        for sql in &self.indexes {
+            self.connection.execute(sql, [])?;
+        }
+        let (_, checksum) = xml_checksum.to_values()?;
+        self.connection.execute(
+            "INSERT INTO db_info (dbversion, checksum) VALUES (?, ?)",
+            params![DATABASE_VERSION, checksum],
+        )?;
+        self.connection.execute_batch("COMMIT")?;
+        self.connection.close().map_err(|(_, e)| e)?;
+        Ok(())
+    }
+}
+
+/// Write the database of `metadata_type` at `path`, with the packages of the primary, filelists or other
+/// XML metadata file at `source`. The checksum of `source` recorded in the database is of `checksum_type`.
+pub(crate) fn write_database_from_xml(
+    source: &Path,
+    metadata_type: &MetadataType,
+    path: &Path,
+    checksum_type: ChecksumType,
+) -> Result<(), MetadataError> {
+    let mut writer = SqliteWriter::new(path, metadata_type)?;
+    let reader = utils::xml_reader_from_file(source)?;
+    let mut write = |package: Option<Package>| -> Result<bool, MetadataError> {
+        match package {
+            Some(package) => writer.write_package(&package).map(|_| true),
+            None => Ok(false),
+        }
+    };
+    match metadata_type.base() {
+        MetadataType::Primary => {
+            let mut reader = PrimaryXml::new_reader(reader);
+            reader.read_header()?;
+            loop {
+                let mut package = None;
+                reader.read_package_with_files(&mut package)?;
+                if !write(package)? {
+                    break;
+                }
+            }
+        }
+        MetadataType::Filelists => {
+            let mut reader = FilelistsXml::new_reader(reader);
+            reader.read_header()?;
+            loop {
+                let mut package = None;
+                reader.read_package(&mut package)?;
+                if !write(package)? {
+                    break;
+                }
+            }
+        }
+        _ => {
+            let mut reader = OtherXml::new_reader(reader);
+            reader.read_header()?;
+            loop {
+                let mut package = None;
+                reader.read_package(&mut package)?;
+                if !write(package)? {
+                    break;
+                }
+            }
+        }
+    }
+    writer.finish(&utils::checksum_file(source, checksum_type)?)
+}
+
+/// The dependencies of `package` which are stored in `table`.
+fn dependencies<'a>(package: &'a Package, table: &str) -> &'a [Requirement] {
+    match table {
+        "requires" => package.requires(),
+        "provides" => package.provides(),
+        "conflicts" => package.conflicts(),
+        "obsoletes" => package.obsoletes(),
+        "suggests" => package.suggests(),
+        "enhances" => package.enhances(),
+        "recommends" => package.recommends(),
+        _ => package.supplements(),
+    }
+}
+
+fn dependencies_mut<'a>(package: &'a mut Package, table: &str) -> &'a mut Vec<Requirement> {
+    match table {
+        "requires" => &mut package.rpm_requires,
+        "provides" => &mut package.rpm_provides,
+        "conflicts" => &mut package.rpm_conflicts,
+        "obsoletes" => &mut package.rpm_obsoletes,
+        "suggests" => &mut package.rpm_suggests,
+        "enhances" => &mut package.rpm_enhances,
+        "recommends" => &mut package.rpm_recommends,
+        _ => &mut package.rpm_supplements,
+    }
+}
+
+/// A database opened for reading. A compressed database is decompressed to a temporary file first,
+/// which is removed again when it's dropped.
+struct Database {
+    connection: Connection,
+    path: PathBuf,
+    temp_path: Option<PathBuf>,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self, MetadataError> {
+        let mut magic = [0; 16];
+        let read = File::open(path)?.read(&mut magic)?;
+        let temp_path = if &magic[..read] == b"SQLite format 3\0" {
+            None
+        } else {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let temp_path = std::env::temp_dir().join(format!(
+                "rpmrepo-sqlite-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _span = logging::span!("decompress {}", path.display());
+            io::copy(
+                &mut utils::reader_from_file(path)?,
+                &mut File::create(&temp_path)?,
+            )?;
+            Some(temp_path)
+        };
+        let connection = match Connection::open_with_flags(
+            temp_path.as_deref().unwrap_or(path),
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        ) {
+            Ok(connection) => connection,
+            Err(e) => {
+                if let Some(temp_path) = &temp_path {
+                    let _ = fs::remove_file(temp_path);
+                }
+                return Err(e.into());
+            }
+        };
+        let database = Database {
+            connection,
+            path: path.to_owned(),
+            temp_path,
+        };
+
+        let version: u32 =
+            database
+                .connection
+                .query_row("SELECT dbversion FROM db_info", [], |row| row.get(0))?;
+        if version != DATABASE_VERSION {
+            return Err(MetadataError::InvalidFieldError(
+                "sqlite database version",
+                version.to_string(),
+            ));
+        }
+        Ok(database)
+    }
+
+    /// The pkgIds of the `packages` table of the filelists or other database, by key.
+    fn pkgids(&self) -> Result<HashMap<i64, String>, MetadataError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT pkgKey, pkgId FROM packages")?;
+        let pkgids = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(pkgids)
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Some(temp_path) = &self.temp_path {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+/// Text which createrepo_c may have left `NULL`.
+fn text(value: Option<String>) -> String {
+    value.unwrap_or_default()
+}
+
+impl Repository {
+    /// Read the packages of a set of sqlite databases, e.g. to write the XML metadata of a repository
+    /// which createrepo_c wrote the databases of. The databases may be compressed.
+    ///
+    /// The packages have the files of `filelists`, or only the ones `primary` lists if it's not given, and
+    /// the changelogs of `other`. The databases don't keep the order of the files of a package across
+    /// directories, so the files are put in the order of their paths, which is the order RPM and
+    /// createrepo_c list them in.
+    pub fn load_from_sqlite_databases(
+        primary: &Path,
+        filelists: Option<&Path>,
+        other: Option<&Path>,
+    ) -> Result<Self, MetadataError> {
+        let _span = logging::span!("load sqlite databases {}", primary.display());
+        let primary = Database::open(primary)?;
+
+        let mut packages = Vec::new();
+        // the index in `packages` of each package, by key
+        let mut keys = HashMap::new();
+        let mut statement = primary.connection.prepare(concat!(
+            "SELECT pkgKey, pkgId, name, arch, version, epoch, release, summary, description,",
+            "  url, time_file, time_build, rpm_license, rpm_vendor, rpm_group,",
+            "  rpm_buildhost, rpm_sourcerpm, rpm_header_start, rpm_header_end,",
+            "  rpm_packager, size_package, size_installed, size_archive,",
+            "  location_href, location_base, checksum_type ",
+            "FROM packages ORDER BY pkgKey"
+        ))?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut package = Package::default();
+            let checksum_type: String = row.get(25)?;
+            let pkgid: String = row.get(1)?;
+            package
+                .set_checksum(Checksum::try_create(
+                    checksum_type.as_str(),
+                    pkgid.as_str(),
+                )?)
+                .set_name(text(row.get(2)?))
+                .set_arch(text(row.get(3)?))
+                .set_evr(EVR::new(
+                    text(row.get(5)?),
+                    text(row.get(4)?),
+                    text(row.get(6)?),
+                ))
+                .set_summary(text(row.get(7)?))
+                .set_description(text(row.get(8)?))
+                .set_url(text(row.get(9)?))
+                .set_time_file(row.get::<_, Option<u64>>(10)?.unwrap_or(0))
+                .set_time_build(row.get::<_, Option<u64>>(11)?.unwrap_or(0))
+                .set_rpm_license(text(row.get(12)?))
+                .set_rpm_vendor(text(row.get(13)?))
+                .set_rpm_group(text(row.get(14)?))
+                .set_rpm_buildhost(text(row.get(15)?))
+                .set_rpm_sourcerpm(text(row.get(16)?))
+                .set_rpm_header_range(
+                    row.get::<_, Option<u64>>(17)?.unwrap_or(0),
+                    row.get::<_, Option<u64>>(18)?.unwrap_or(0),
+                )
+                .set_packager(text(row.get(19)?))
+                .set_size_package(row.get::<_, Option<u64>>(20)?.unwrap_or(0))
+                .set_size_installed(row.get::<_, Option<u64>>(21)?.unwrap_or(0))
+                .set_size_archive(row.get::<_, Option<u64>>(22)?.unwrap_or(0))
+                .set_location_href(text(row.get(23)?))
+                .set_location_base(row.get::<_, Option<String>>(24)?);
+            keys.insert(row.get::<_, i64>(0)?, packages.len());
+            packages.push(package);
+        }
+        drop(rows);
+        drop(statement);
+
+        for table in DEPENDENCY_TABLES {
+            let pre = match table {
+                "requires" => "pre",
+                _ => "NULL",
+            };
+            let mut statement = primary.connection.prepare(&format!(
+                "SELECT pkgKey, name, flags, epoch, version, release, {} FROM {} ORDER BY rowid",
+                pre, table
+            ))?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let Some(&index) = keys.get(&row.get::<_, i64>(0)?) else {
+                    continue;
+                };
+                let preinstall = match row.get::<_, Value>(6)? {
+                    Value::Integer(pre) => pre != 0,
+                    Value::Text(pre) => pre.eq_ignore_ascii_case("TRUE") || pre == "1",
+                    _ => false,
+                };
+                dependencies_mut(&mut packages[index], table).push(Requirement {
+                    name: text(row.get(1)?),
+                    flags: row.get(2)?,
+                    epoch: row.get(3)?,
+                    version: row.get(4)?,
+                    release: row.get(5)?,
+                    preinstall,
+                });
+            }
+        }
+
+        let mut files: Vec<Vec<PackageFile>> = vec![Vec::new(); packages.len()];
+        match filelists {
+            Some(filelists) => {
+                let filelists = Database::open(filelists)?;
+                let indexes = package_indexes(&filelists, &packages)?;
+                let mut statement = filelists.connection.prepare(
+                    "SELECT pkgKey, dirname, filenames, filetypes FROM filelist ORDER BY rowid",
+                )?;
+                let mut rows = statement.query([])?;
+                while let Some(row) = rows.next()? {
+                    let Some(&index) = indexes.get(&row.get::<_, i64>(0)?) else {
+                        continue;
+                    };
+                    let dirname = text(row.get(1)?);
+                    let filenames = text(row.get(2)?);
+                    let filetypes = text(row.get(3)?);
+                    for (filename, filetype) in filenames.split('/').zip(filetypes.chars()) {
+                        let path = match dirname.as_str() {
+                            "/" => format!("/{}", filename),
+                            "" => filename.to_owned(),
+                            dirname => format!("{}/{}", dirname, filename),
+                        };
+                        let filetype = match filetype {
+                            'd' => FileType::Dir,
+                            'g' => FileType::Ghost,
+                            _ => FileType::File,
+                        };
+                        files[index].push(PackageFile { filetype, path });
+                    }
+                }
+            }
+            None => {
+                let mut statement = primary
+                    .connection
+                    .prepare("SELECT pkgKey, name, type FROM files ORDER BY rowid")?;
+                let mut rows = statement.query([])?;
+                while let Some(row) = rows.next()? {
+                    let Some(&index) = keys.get(&row.get::<_, i64>(0)?) else {
+                        continue;
+                    };
+                    let filetype: String = row.get(2)?;
+                    files[index].push(PackageFile {
+                        filetype: FileType::try_create(filetype.as_str())?,
+                        path: text(row.get(1)?),
+                    });
+                }
+            }
+        }
+        for (package, mut files) in packages.iter_mut().zip(files) {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            package.set_files(files);
+        }
+
+        if let Some(other) = other {
+            let other = Database::open(other)?;
+            let indexes = package_indexes(&other, &packages)?;
+            let mut statement = other
+                .connection
+                .prepare("SELECT pkgKey, author, date, changelog FROM changelog ORDER BY rowid")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let Some(&index) = indexes.get(&row.get::<_, i64>(0)?) else {
+                    continue;
+                };
+                packages[index].add_changelog(
+                    &text(row.get(1)?),
+                    &text(row.get(3)?),
+                    row.get::<_, Option<u64>>(2)?.unwrap_or(0),
+                );
+            }
+        }
+
+        let mut repo = Repository::new();
+        for package in packages {
+            repo.packages_mut()
+                .insert(package.pkgid().to_owned(), package);
+        }
+        logging::debug!("read {} packages", repo.packages().len());
+        Ok(repo)
+    }
+}
+
+/// The index in `packages` (read from the primary database) of each package in the `packages` table of
+/// the filelists or other `database`, by its key there.
+fn package_indexes(
+    database: &Database,
+    packages: &[Package],
+) -> Result<HashMap<i64, usize>, MetadataError> {
+    let by_pkgid: HashMap<&str, usize> = packages
+        .iter()
+        .enumerate()
+        .map(|(index, package)| (package.pkgid(), index))
+        .collect();
+    database
+        .pkgids()?
+        .into_iter()
+        .map(|(key, pkgid)| match by_pkgid.get(pkgid.as_str()) {
+            Some(&index) => Ok((key, index)),
+            None => Err(MetadataError::InconsistentMetadataError(format!(
+                "{} has package {} which the primary database doesn't",
+                database.path.display(),
+                pkgid
+            ))),
+        })
+        .collect()
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Where the files of a repository are read from: the local filesystem, or (with the `archive` feature)
+//! an ISO 9660 image or a tar archive which is read in place.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path};
+#[cfg(feature = "archive")]
+use std::sync::Arc;
+
+#[cfg(feature = "archive")]
+use crate::MetadataError;
+
+#[cfg(feature = "archive")]
+mod iso;
+#[cfg(feature = "archive")]
+mod tar;
+
+#[cfg(feature = "archive")]
+pub use iso::IsoStorage;
+#[cfg(all(feature = "archive", feature = "read_rpm"))]
+pub(crate) use tar::read_files as read_tar_files;
+#[cfg(feature = "archive")]
+pub use tar::TarStorage;
+#[cfg(feature = "archive")]
+pub(crate) use tar::TarWriter;
+
+/// A tree of files which a [`RepositoryReader`](crate::RepositoryReader) reads a repository from.
+///
+/// Paths are `/`-separated and relative to the root of the storage, except for [`LocalStorage`] which
+/// takes them as they are.
+pub trait Storage: Send + Sync {
+    /// Open the file at `path` for reading, as it's stored (without decompressing it).
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    /// The size of the file at `path`. Fails with [`io::ErrorKind::NotFound`] if there's no file there.
+    fn size(&self, path: &Path) -> io::Result<u64>;
+
+    /// The names of the files (not subdirectories) in the directory at `path`, in no particular order.
+    fn list(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /// Whether there's a file at `path`.
+    fn is_file(&self, path: &Path) -> bool {
+        self.size(path).is_ok()
+    }
+}
+
+/// The local filesystem.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        let metadata = fs::metadata(path)?;
+        match metadata.is_file() {
+            true => Ok(metadata.len()),
+            false => Err(not_found(path)),
+        }
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Open the ISO 9660 image or (possibly compressed) tar archive at `path`, telling them apart by their
+/// contents.
+#[cfg(feature = "archive")]
+pub fn open_archive(path: &Path) -> Result<Arc<dyn Storage>, MetadataError> {
+    match iso::is_iso_image(path)? {
+        true => Ok(Arc::new(IsoStorage::open(path)?)),
+        false => Ok(Arc::new(TarStorage::open(path)?)),
+    }
+}
+
+/// The `/`-separated form of `path` which archives are indexed by, without `.` components or leading
+/// and trailing slashes.
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+fn normalize(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            Component::ParentDir => Some("..".to_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} isn't a file", path.display()),
+    )
+}
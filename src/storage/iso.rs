@@ -0,0 +1,291 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::{normalize, not_found, Storage};
+use crate::logging;
+use crate::MetadataError;
+
+const SECTOR_SIZE: u64 = 2048;
+/// The sector the volume descriptors start at
+const FIRST_DESCRIPTOR: u64 = 16;
+const IDENTIFIER: &[u8] = b"CD001";
+
+/// How the names of the files of an image are recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Names {
+    /// Rock Ridge `NM` entries, with the plain ISO 9660 names as a fallback
+    RockRidge,
+    /// The UCS-2 names of the Joliet directory tree
+    Joliet,
+    /// Plain ISO 9660 names, which are matched regardless of case and without their `;1` versions
+    Plain,
+}
+
+/// An entry of a directory of the image.
+#[derive(Clone, Debug)]
+struct Record {
+    name: String,
+    sector: u64,
+    size: u64,
+    is_dir: bool,
+}
+
+/// An ISO 9660 image, such as installation media, whose files are read in place.
+///
+/// Rock Ridge names are used when the image has them, otherwise Joliet ones, otherwise the plain
+/// ISO 9660 names. Directories are read as paths are looked up, so opening an image is cheap.
+#[derive(Debug)]
+pub struct IsoStorage {
+    path: PathBuf,
+    root: Record,
+    names: Names,
+}
+
+impl IsoStorage {
+    /// Open the ISO 9660 image at `path`.
+    pub fn open(path: &Path) -> Result<Self, MetadataError> {
+        let _span = logging::span!("open {}", path.display());
+        let mut file = BufReader::new(File::open(path)?);
+        let invalid = |message: &str| {
+            MetadataError::InvalidFieldError(
+                "ISO 9660 image",
+                format!("{}: {}", path.display(), message),
+            )
+        };
+
+        let mut primary = None;
+        let mut joliet = None;
+        let mut descriptor = [0u8; SECTOR_SIZE as usize];
+        for sector in FIRST_DESCRIPTOR.. {
+            file.seek(SeekFrom::Start(sector * SECTOR_SIZE))?;
+            file.read_exact(&mut descriptor)
+                .map_err(|_| invalid("no volume descriptor terminator"))?;
+            if &descriptor[1..6] != IDENTIFIER {
+                return Err(invalid("bad volume descriptor"));
+            }
+            match descriptor[0] {
+                1 => primary = parse_record(&descriptor[156..190], Names::Plain),
+                // the escape sequence which marks a Joliet supplementary volume descriptor
+                2 if [b"%/@", b"%/C", b"%/E"].contains(&&[
+                    descriptor[88],
+                    descriptor[89],
+                    descriptor[90],
+                ]) =>
+                {
+                    joliet = parse_record(&descriptor[156..190], Names::Joliet)
+                }
+                255 => break,
+                _ => (),
+            }
+        }
+        let primary = primary.ok_or_else(|| invalid("no primary volume descriptor"))?;
+
+        let mut storage = Self {
+            path: path.to_owned(),
+            root: primary,
+            names: Names::RockRidge,
+        };
+        // Rock Ridge images start the system use area of the root directory with a `SP` entry
+        let has_rock_ridge = storage
+            .read_raw(&storage.root)?
+            .first()
+            .is_some_and(|dot| system_use(dot).starts_with(b"SP"));
+        if !has_rock_ridge {
+            match joliet {
+                Some(root) => {
+                    storage.root = root;
+                    storage.names = Names::Joliet;
+                }
+                None => storage.names = Names::Plain,
+            }
+        }
+        logging::debug!("reading {} with {:?} names", path.display(), storage.names);
+        Ok(storage)
+    }
+
+    /// The entries of the directory `dir`, as raw directory records.
+    fn read_raw(&self, dir: &Record) -> io::Result<Vec<Vec<u8>>> {
+        let mut file = File::open(&self.path)?;
+        let start = dir.sector * SECTOR_SIZE;
+        // the size comes from the image, so don't trust it with an allocation
+        if start.saturating_add(dir.size) > file.metadata()?.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "directory extends past the end of the image",
+            ));
+        }
+        file.seek(SeekFrom::Start(start))?;
+        let mut data = vec![0u8; dir.size as usize];
+        file.read_exact(&mut data)?;
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = data[pos] as usize;
+            if len == 0 {
+                // records don't cross sectors, the rest of this one is padding
+                pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            let record = data
+                .get(pos..pos + len)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated directory"))?;
+            records.push(record.to_vec());
+            pos += len;
+        }
+        Ok(records)
+    }
+
+    /// The entries of the directory `dir`, without `.` and `..`.
+    fn read_dir(&self, dir: &Record) -> io::Result<Vec<Record>> {
+        Ok(self
+            .read_raw(dir)?
+            .iter()
+            .filter(|raw| !is_dot(raw))
+            .filter_map(|raw| parse_record(raw, self.names))
+            .collect())
+    }
+
+    /// The entry at `path`.
+    fn find(&self, path: &Path) -> io::Result<Record> {
+        let path = normalize(path);
+        let mut record = self.root.clone();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !record.is_dir {
+                return Err(not_found(Path::new(&path)));
+            }
+            record = self
+                .read_dir(&record)?
+                .into_iter()
+                .find(|entry| match self.names {
+                    Names::Plain => entry.name.eq_ignore_ascii_case(component),
+                    _ => entry.name == component,
+                })
+                .ok_or_else(|| not_found(Path::new(&path)))?;
+        }
+        Ok(record)
+    }
+
+    fn find_file(&self, path: &Path) -> io::Result<Record> {
+        match self.find(path)? {
+            record if record.is_dir => Err(not_found(path)),
+            record => Ok(record),
+        }
+    }
+}
+
+impl Storage for IsoStorage {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let record = self.find_file(path)?;
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(record.sector * SECTOR_SIZE))?;
+        Ok(Box::new(BufReader::new(file).take(record.size)))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.find_file(path)?.size)
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<String>> {
+        let dir = self.find(path)?;
+        if !dir.is_dir {
+            return Err(not_found(path));
+        }
+        Ok(self
+            .read_dir(&dir)?
+            .into_iter()
+            .filter(|record| !record.is_dir)
+            .map(|record| record.name)
+            .collect())
+    }
+}
+
+/// Whether the file at `path` is an ISO 9660 image.
+pub(super) fn is_iso_image(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(FIRST_DESCRIPTOR * SECTOR_SIZE + 1))?;
+    let mut identifier = [0u8; 5];
+    match file.read_exact(&mut identifier) {
+        Ok(()) => Ok(identifier == IDENTIFIER),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `raw` is the directory record of `.` or `..`.
+fn is_dot(raw: &[u8]) -> bool {
+    raw.get(32) == Some(&1) && matches!(raw.get(33), Some(0 | 1))
+}
+
+/// Parse a directory record, or return `None` if it's cut short.
+fn parse_record(raw: &[u8], names: Names) -> Option<Record> {
+    let name_len = *raw.get(32)? as usize;
+    let name = raw.get(33..33 + name_len)?;
+    let sector = u32::from_le_bytes(raw[2..6].try_into().ok()?);
+    let size = u32::from_le_bytes(raw[10..14].try_into().ok()?);
+    let is_dir = raw[25] & 0x02 != 0;
+
+    let name = match names {
+        Names::RockRidge => rock_ridge_name(system_use(raw)).unwrap_or_else(|| plain_name(name)),
+        Names::Joliet => {
+            let units: Vec<u16> = name
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            strip_version(&String::from_utf16_lossy(&units)).to_owned()
+        }
+        Names::Plain => plain_name(name),
+    };
+    Some(Record {
+        name,
+        sector: u64::from(sector),
+        size: u64::from(size),
+        is_dir,
+    })
+}
+
+/// The system use area of a directory record, which holds the Rock Ridge entries.
+fn system_use(raw: &[u8]) -> &[u8] {
+    let name_len = raw.get(32).copied().unwrap_or_default() as usize;
+    // the name is padded to an even length
+    let start = 33 + name_len + (1 - name_len % 2);
+    raw.get(start..).unwrap_or_default()
+}
+
+/// The name in the `NM` entries of a system use area, if it has any.
+fn rock_ridge_name(mut area: &[u8]) -> Option<String> {
+    let mut name = Vec::new();
+    while area.len() >= 4 {
+        let len = area[2] as usize;
+        if len < 4 || len > area.len() {
+            break;
+        }
+        match &area[..2] {
+            b"NM" if len > 5 => name.extend_from_slice(&area[5..len]),
+            b"ST" => break,
+            _ => (),
+        }
+        area = &area[len..];
+    }
+    match name.is_empty() {
+        true => None,
+        false => Some(String::from_utf8_lossy(&name).into_owned()),
+    }
+}
+
+fn plain_name(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    strip_version(&name).trim_end_matches('.').to_owned()
+}
+
+/// `name` without the `;1` version of ISO 9660 file names.
+fn strip_version(name: &str) -> &str {
+    name.rsplit_once(';').map_or(name, |(name, _)| name)
+}
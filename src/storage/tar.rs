@@ -0,0 +1,412 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{normalize, not_found, Storage};
+use crate::logging;
+use crate::{utils, MetadataError};
+
+const BLOCK_SIZE: u64 = 512;
+
+/// A tar archive, compressed with gzip, xz, bzip2 or zstd or not at all, whose files are read without
+/// extracting it.
+///
+/// The archive is indexed once when it's opened. The files of an uncompressed archive are read in
+/// place, while a compressed one is decompressed once, into a temporary file which is removed along
+/// with the storage, so that its files don't each have to be decompressed from the start.
+#[derive(Debug)]
+pub struct TarStorage {
+    path: PathBuf,
+    /// The decompressed archive, if it's compressed
+    decompressed: Option<TempFile>,
+    /// The offset of the contents of each file in the (decompressed) archive, and their size
+    entries: HashMap<String, (u64, u64)>,
+}
+
+impl TarStorage {
+    /// Index the tar archive at `path`.
+    pub fn open(path: &Path) -> Result<Self, MetadataError> {
+        let _span = logging::span!("index {}", path.display());
+        let mut file = File::open(path)?;
+        let mut header = [0u8; BLOCK_SIZE as usize];
+        let compressed = match file.read_exact(&mut header) {
+            Ok(()) => !is_header(&header),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => true,
+            Err(e) => return Err(e.into()),
+        };
+        file.rewind()?;
+
+        let decompressed = match compressed {
+            true => {
+                let temp = TempFile::new()?;
+                let mut output = File::options().write(true).open(&temp.0)?;
+                io::copy(&mut utils::reader_from(BufReader::new(file))?, &mut output)
+                    .map_err(|e| archive_error(path, e))?;
+                file = File::open(&temp.0)?;
+                logging::debug!("decompressed {} into {}", path.display(), temp.0.display());
+                Some(temp)
+            }
+            false => None,
+        };
+
+        let mut entries = HashMap::new();
+        read_entries(
+            path,
+            &mut BufReader::new(file),
+            |reader, len| reader.seek_relative(len as i64),
+            |entry, _| {
+                match &entry.link {
+                    None => {
+                        entries.insert(entry.path.clone(), (entry.offset, entry.size));
+                    }
+                    Some(target) => {
+                        if let Some(target) = entries.get(target).copied() {
+                            entries.insert(entry.path.clone(), target);
+                        }
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        logging::debug!("indexed {} files in {}", entries.len(), path.display());
+        Ok(Self {
+            path: path.to_owned(),
+            decompressed,
+            entries,
+        })
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<(u64, u64)> {
+        self.entries
+            .get(&normalize(path))
+            .copied()
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+/// A temporary file, which is removed when it's dropped.
+#[derive(Debug)]
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rpmrepo-tar-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        File::options().write(true).create_new(true).open(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Read the regular files of the tar archive at `path`, compressed or not, in a single pass, calling
+/// `visit` with each of them and its contents in the order they're in the archive. Hard links are
+/// visited with the path they link to and no contents.
+#[cfg(all(feature = "archive", feature = "read_rpm"))]
+pub(crate) fn read_files(
+    path: &Path,
+    visit: impl FnMut(&TarEntry, &mut dyn Read) -> Result<(), MetadataError>,
+) -> Result<(), MetadataError> {
+    let _span = logging::span!("read {}", path.display());
+    let reader = utils::reader_from(BufReader::new(File::open(path)?))?;
+    read_entries(
+        path,
+        &mut BufReader::new(reader),
+        |reader, len| io::copy(&mut reader.take(len), &mut io::sink()).map(|_| ()),
+        visit,
+    )
+}
+
+impl Storage for TarStorage {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let (offset, size) = self.entry(path)?;
+        let mut file = match &self.decompressed {
+            Some(decompressed) => File::open(&decompressed.0)?,
+            None => File::open(&self.path)?,
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(BufReader::new(file).take(size)))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.entry(path)?.1)
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<String>> {
+        let dir = normalize(path);
+        Ok(self
+            .entries
+            .keys()
+            .filter_map(|name| match name.rsplit_once('/') {
+                Some((parent, name)) if parent == dir => Some(name.to_owned()),
+                None if dir.is_empty() => Some(name.to_owned()),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Writes a tar archive of regular files, using GNU long name entries for paths which don't fit in a
+/// header. The entries have no owner and a timestamp of 0, so the same files make the same archive.
+pub(crate) struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Add the file `path`, whose `size` bytes are read from `contents`.
+    pub(crate) fn append(
+        &mut self,
+        path: &str,
+        size: u64,
+        contents: &mut impl Read,
+    ) -> io::Result<()> {
+        if path.len() > 100 {
+            let long_name = format!("{}\0", path);
+            self.write_entry(
+                "././@LongLink",
+                b'L',
+                long_name.len() as u64,
+                &mut long_name.as_bytes(),
+            )?;
+        }
+        self.write_entry(path, b'0', size, contents)
+    }
+
+    fn write_entry(
+        &mut self,
+        path: &str,
+        kind: u8,
+        size: u64,
+        contents: &mut impl Read,
+    ) -> io::Result<()> {
+        let mut header = [0u8; BLOCK_SIZE as usize];
+        let name = &path.as_bytes()[..path.len().min(100)];
+        header[..name.len()].copy_from_slice(name);
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        if size < 8 << 30 {
+            header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        } else {
+            header[124] = 0x80;
+            header[128..136].copy_from_slice(&size.to_be_bytes());
+        }
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = kind;
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        let checksum = header_checksum(&header);
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        self.inner.write_all(&header)?;
+
+        let written = io::copy(&mut contents.take(size), &mut self.inner)?;
+        if written != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} is shorter than {} bytes", path, size),
+            ));
+        }
+        let padding = size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE - size;
+        self.inner.write_all(&vec![0u8; padding as usize])
+    }
+
+    /// End the archive, and return the writer it was written to.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// A regular file of a tar archive, or a hard link to one.
+pub(crate) struct TarEntry {
+    pub(crate) path: String,
+    /// The path of the file a hard link is to
+    pub(crate) link: Option<String>,
+    /// The offset of the contents in the (decompressed) archive
+    offset: u64,
+    size: u64,
+}
+
+/// Walk through the entries of the tar archive `reader`, calling `visit` with the regular files and
+/// hard links and a reader of their contents, and `skip` to move past whatever of the entries isn't
+/// read.
+fn read_entries<R: Read>(
+    path: &Path,
+    reader: &mut R,
+    mut skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+    mut visit: impl FnMut(&TarEntry, &mut dyn Read) -> Result<(), MetadataError>,
+) -> Result<(), MetadataError> {
+    let fail = |e| archive_error(path, e);
+    let mut offset = 0;
+    // set by GNU long name and pax extended headers, for the entry which follows them
+    let mut long_name: Option<String> = None;
+    let mut header = [0u8; BLOCK_SIZE as usize];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            // archives are supposed to end with two empty blocks, but not all of them do
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && offset > 0 => break,
+            Err(e) => return Err(fail(e)),
+        }
+        offset += BLOCK_SIZE;
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        if !is_header(&header) {
+            return Err(fail(invalid("bad header checksum")));
+        }
+
+        let size = parse_size(&header[124..136]).map_err(fail)?;
+        let padded = size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => header_name(&header),
+        };
+        match header[156] {
+            b'L' | b'x' => {
+                let mut data = Vec::new();
+                (&mut *reader)
+                    .take(size)
+                    .read_to_end(&mut data)
+                    .map_err(fail)?;
+                if data.len() as u64 != size {
+                    return Err(fail(invalid("truncated entry")));
+                }
+                skip(reader, padded - size).map_err(fail)?;
+                long_name = match header[156] {
+                    b'L' => Some(cstr(&data)),
+                    _ => pax_path(&data),
+                };
+            }
+            b'0' | b'7' | 0 => {
+                let entry = TarEntry {
+                    path: normalize(Path::new(&name)),
+                    link: None,
+                    offset,
+                    size,
+                };
+                let mut contents = (&mut *reader).take(size);
+                visit(&entry, &mut contents)?;
+                let unread = contents.limit();
+                skip(reader, unread + padded - size).map_err(fail)?;
+            }
+            b'1' => {
+                let entry = TarEntry {
+                    path: normalize(Path::new(&name)),
+                    link: Some(normalize(Path::new(&cstr(&header[157..257])))),
+                    offset,
+                    size: 0,
+                };
+                visit(&entry, &mut io::empty())?;
+                skip(reader, padded).map_err(fail)?;
+            }
+            _ => skip(reader, padded).map_err(fail)?,
+        }
+        offset += padded;
+    }
+    Ok(())
+}
+
+/// An error reading the tar archive at `path`.
+fn archive_error(path: &Path, e: io::Error) -> MetadataError {
+    match e.kind() {
+        io::ErrorKind::InvalidData => {
+            MetadataError::InvalidFieldError("tar archive", format!("{}: {}", path.display(), e))
+        }
+        _ => e.into(),
+    }
+}
+
+/// Whether `header` is a tar header, according to its checksum.
+fn is_header(header: &[u8]) -> bool {
+    parse_size(&header[148..156]).is_ok_and(|expected| expected == header_checksum(header))
+}
+
+/// The checksum of `header`, which is computed with the checksum field filled with spaces.
+fn header_checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| match i {
+            148..=155 => u64::from(b' '),
+            _ => u64::from(*byte),
+        })
+        .sum()
+}
+
+/// The path of the entry, including the ustar prefix.
+fn header_name(header: &[u8]) -> String {
+    let name = cstr(&header[0..100]);
+    if &header[257..262] != b"ustar" {
+        return name;
+    }
+    match cstr(&header[345..500]) {
+        prefix if prefix.is_empty() => name,
+        prefix => format!("{}/{}", prefix, name),
+    }
+}
+
+/// A number field of a header, in octal or (for large sizes) in GNU's base-256 encoding.
+fn parse_size(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7f), |value, byte| {
+                (value << 8) | u64::from(*byte)
+            }));
+    }
+    let digits = cstr(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    match digits.is_empty() {
+        true => Ok(0),
+        false => u64::from_str_radix(digits, 8).map_err(|_| invalid("bad number field")),
+    }
+}
+
+/// The `path` of a pax extended header, made of `<length> <key>=<value>\n` records.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let data = String::from_utf8_lossy(data);
+    let mut rest = &*data;
+    while let Some((length, _)) = rest.split_once(' ') {
+        let length: usize = length.parse().ok()?;
+        let record = rest.get(..length)?;
+        rest = &rest[length..];
+        let (_, pair) = record.split_once(' ')?;
+        if let Some(path) = pair.trim_end_matches('\n').strip_prefix("path=") {
+            return Some(path.to_owned());
+        }
+    }
+    None
+}
+
+fn cstr(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
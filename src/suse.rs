@@ -0,0 +1,397 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The metadata only SUSE repositories have: the products of `products.xml` and the patterns of
+//! `patterns.xml`.
+
+use std::io::{BufRead, Write};
+
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::primary::{parse_requirement_list, write_requirement_section};
+
+use super::metadata::{
+    LocalizedText, ParseContext, Pattern, PatternsXml, Product, ProductsXml, RpmMetadata, XmlStyle,
+    XML_NS_PATTERN, XML_NS_RPM,
+};
+use super::{utils, MetadataError, Repository, EVR};
+
+const TAG_PRODUCTS: &[u8] = b"products";
+const TAG_PRODUCT: &[u8] = b"product";
+const TAG_PATTERNS: &[u8] = b"patterns";
+const TAG_PATTERN: &[u8] = b"pattern";
+const TAG_VENDOR: &[u8] = b"vendor";
+const TAG_NAME: &[u8] = b"name";
+const TAG_VERSION: &[u8] = b"version";
+const TAG_ARCH: &[u8] = b"arch";
+const TAG_SUMMARY: &[u8] = b"summary";
+const TAG_DESCRIPTION: &[u8] = b"description";
+const TAG_CATEGORY: &[u8] = b"category";
+const TAG_USERVISIBLE: &[u8] = b"uservisible";
+const TAG_ICON: &[u8] = b"icon";
+const TAG_ORDER: &[u8] = b"order";
+
+const TAG_RPM_PROVIDES: &[u8] = b"rpm:provides";
+const TAG_RPM_REQUIRES: &[u8] = b"rpm:requires";
+const TAG_RPM_CONFLICTS: &[u8] = b"rpm:conflicts";
+const TAG_RPM_OBSOLETES: &[u8] = b"rpm:obsoletes";
+const TAG_RPM_SUGGESTS: &[u8] = b"rpm:suggests";
+const TAG_RPM_ENHANCES: &[u8] = b"rpm:enhances";
+const TAG_RPM_RECOMMENDS: &[u8] = b"rpm:recommends";
+const TAG_RPM_SUPPLEMENTS: &[u8] = b"rpm:supplements";
+
+impl RpmMetadata for ProductsXml {
+    fn filename() -> &'static str {
+        "products.xml"
+    }
+
+    fn load_metadata<R: BufRead>(
+        repository: &mut Repository,
+        mut reader: Reader<R>,
+    ) -> Result<(), MetadataError> {
+        let mut context = ParseContext::new("products", "products");
+        let products = read_entries(&mut reader, &mut context, TAG_PRODUCT, parse_product)?;
+        repository.products_mut().extend(products);
+        Ok(())
+    }
+
+    fn write_metadata<W: Write>(
+        repository: &Repository,
+        mut writer: Writer<W>,
+    ) -> Result<(), MetadataError> {
+        write_products(&mut writer, repository.products(), XmlStyle::Standard)
+    }
+}
+
+impl RpmMetadata for PatternsXml {
+    fn filename() -> &'static str {
+        "patterns.xml"
+    }
+
+    fn load_metadata<R: BufRead>(
+        repository: &mut Repository,
+        mut reader: Reader<R>,
+    ) -> Result<(), MetadataError> {
+        let mut context = ParseContext::new("patterns", "patterns");
+        let patterns = read_entries(&mut reader, &mut context, TAG_PATTERN, parse_pattern)?;
+        repository.patterns_mut().extend(patterns);
+        Ok(())
+    }
+
+    fn write_metadata<W: Write>(
+        repository: &Repository,
+        mut writer: Writer<W>,
+    ) -> Result<(), MetadataError> {
+        write_patterns(&mut writer, repository.patterns(), XmlStyle::Standard)
+    }
+}
+
+/// Read every `entry_tag` element of the file with `parse_entry`, which is called with the start tag.
+fn read_entries<R: BufRead, T>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+    entry_tag: &[u8],
+    parse_entry: fn(&mut Reader<R>, &mut ParseContext) -> Result<T, MetadataError>,
+) -> Result<Vec<T>, MetadataError> {
+    let mut entries = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) if e.name() == entry_tag => {
+                context.next_entry();
+                context.start_entry(reader, &e);
+                let entry =
+                    parse_entry(reader, context).map_err(|e| context.locate(reader, e, None))?;
+                entries.push(entry);
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(entries)
+}
+
+/// The EVR of a `<version epoch="0" ver="15.4" rel="0"/>` tag.
+fn parse_version<R: BufRead>(reader: &Reader<R>, tag: &BytesStart) -> Result<EVR, MetadataError> {
+    let mut values = Vec::with_capacity(3);
+    for name in ["epoch", "ver", "rel"] {
+        values.push(match tag.try_get_attribute(name)? {
+            Some(value) => value.unescape_and_decode_value(reader)?,
+            None => String::new(),
+        });
+    }
+    Ok(EVR::new(
+        values[0].as_str(),
+        values[1].as_str(),
+        values[2].as_str(),
+    ))
+}
+
+fn parse_localized_text<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &BytesStart,
+    text_buf: &mut Vec<u8>,
+) -> Result<LocalizedText, MetadataError> {
+    let lang = match tag.try_get_attribute("lang")? {
+        Some(value) => Some(value.unescape_and_decode_value(reader)?),
+        None => None,
+    };
+    let text = reader.read_text(tag.name(), text_buf)?;
+    Ok(LocalizedText { lang, text })
+}
+
+fn parse_product<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+) -> Result<Product, MetadataError> {
+    let mut product = Product::default();
+    let mut buf = Vec::new();
+    let mut text_buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::End(e) if e.name() == TAG_PRODUCT => break,
+            Event::Start(e) => match e.name() {
+                TAG_VENDOR => product.vendor = reader.read_text(TAG_VENDOR, &mut text_buf)?,
+                TAG_NAME => {
+                    product.name = reader.read_text(TAG_NAME, &mut text_buf)?;
+                    context.entry = Some(product.name.clone());
+                }
+                TAG_VERSION => product.evr = parse_version(reader, &e)?,
+                TAG_ARCH => product.arch = reader.read_text(TAG_ARCH, &mut text_buf)?,
+                TAG_SUMMARY => product.summary = reader.read_text(TAG_SUMMARY, &mut text_buf)?,
+                TAG_DESCRIPTION => {
+                    product.description = reader.read_text(TAG_DESCRIPTION, &mut text_buf)?
+                }
+                _ => (),
+            },
+            Event::Eof => return Err(MetadataError::MissingFieldError("</product>")),
+            _ => (),
+        }
+        buf.clear();
+        text_buf.clear();
+    }
+    Ok(product)
+}
+
+fn parse_pattern<R: BufRead>(
+    reader: &mut Reader<R>,
+    context: &mut ParseContext,
+) -> Result<Pattern, MetadataError> {
+    let mut pattern = Pattern::default();
+    let mut buf = Vec::new();
+    let mut text_buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::End(e) if e.name() == TAG_PATTERN => break,
+            Event::Start(e) => match e.name() {
+                TAG_NAME => {
+                    pattern.name = reader.read_text(TAG_NAME, &mut text_buf)?;
+                    context.entry = Some(pattern.name.clone());
+                }
+                TAG_VERSION => pattern.evr = parse_version(reader, &e)?,
+                TAG_ARCH => pattern.arch = reader.read_text(TAG_ARCH, &mut text_buf)?,
+                TAG_SUMMARY => {
+                    pattern
+                        .summary
+                        .push(parse_localized_text(reader, &e, &mut text_buf)?)
+                }
+                TAG_DESCRIPTION => {
+                    pattern
+                        .description
+                        .push(parse_localized_text(reader, &e, &mut text_buf)?)
+                }
+                TAG_CATEGORY => {
+                    pattern
+                        .category
+                        .push(parse_localized_text(reader, &e, &mut text_buf)?)
+                }
+                TAG_USERVISIBLE => pattern.uservisible = true,
+                TAG_ICON => pattern.icon = Some(reader.read_text(TAG_ICON, &mut text_buf)?),
+                TAG_ORDER => pattern.order = Some(reader.read_text(TAG_ORDER, &mut text_buf)?),
+                TAG_RPM_PROVIDES => {
+                    pattern.rpm_provides = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_REQUIRES => {
+                    pattern.rpm_requires = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_CONFLICTS => {
+                    pattern.rpm_conflicts = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_OBSOLETES => {
+                    pattern.rpm_obsoletes = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_SUGGESTS => {
+                    pattern.rpm_suggests = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_ENHANCES => {
+                    pattern.rpm_enhances = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_RECOMMENDS => {
+                    pattern.rpm_recommends = parse_requirement_list(reader, &e, context)?
+                }
+                TAG_RPM_SUPPLEMENTS => {
+                    pattern.rpm_supplements = parse_requirement_list(reader, &e, context)?
+                }
+                _ => (),
+            },
+            Event::Eof => return Err(MetadataError::MissingFieldError("</pattern>")),
+            _ => (),
+        }
+        buf.clear();
+        text_buf.clear();
+    }
+    Ok(pattern)
+}
+
+fn write_header<W: Write>(writer: &mut Writer<W>, root: BytesStart) -> Result<(), MetadataError> {
+    // <?xml version="1.0" encoding="UTF-8"?>
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    writer.write_event(Event::Start(root))?;
+    Ok(())
+}
+
+fn write_footer<W: Write>(writer: &mut Writer<W>, root: &[u8]) -> Result<(), MetadataError> {
+    writer.write_event(Event::End(BytesEnd::borrowed(root)))?;
+
+    // trailing newline
+    writer.write_event(Event::Text(BytesText::from_plain_str("\n")))?;
+
+    // write everything out to disk - otherwise it won't happen until drop() which impedes debugging
+    writer.inner().flush()?;
+    Ok(())
+}
+
+fn write_text_element<W: Write>(
+    writer: &mut Writer<W>,
+    style: XmlStyle,
+    tag: &[u8],
+    value: &str,
+) -> Result<(), MetadataError> {
+    writer
+        .create_element(tag)
+        .write_text_content(utils::text(style, value))?;
+    Ok(())
+}
+
+fn write_version<W: Write>(
+    writer: &mut Writer<W>,
+    style: XmlStyle,
+    evr: &EVR,
+) -> Result<(), MetadataError> {
+    // <version epoch="0" ver="15.4" rel="0"/>
+    let (epoch, version, release) = evr.values();
+    writer
+        .create_element(TAG_VERSION)
+        .with_attribute(utils::attribute(style, "epoch", epoch))
+        .with_attribute(utils::attribute(style, "ver", version))
+        .with_attribute(utils::attribute(style, "rel", release))
+        .write_empty()?;
+    Ok(())
+}
+
+/// Write `products.xml` with the `products`.
+pub(crate) fn write_products<W: Write>(
+    writer: &mut Writer<W>,
+    products: &[Product],
+    style: XmlStyle,
+) -> Result<(), MetadataError> {
+    // <products>
+    write_header(writer, BytesStart::borrowed_name(TAG_PRODUCTS))?;
+
+    for product in products {
+        // <product>
+        writer.write_event(Event::Start(BytesStart::borrowed_name(TAG_PRODUCT)))?;
+        write_text_element(writer, style, TAG_VENDOR, &product.vendor)?;
+        write_text_element(writer, style, TAG_NAME, &product.name)?;
+        write_version(writer, style, &product.evr)?;
+        write_text_element(writer, style, TAG_ARCH, &product.arch)?;
+        write_text_element(writer, style, TAG_SUMMARY, &product.summary)?;
+        write_text_element(writer, style, TAG_DESCRIPTION, &product.description)?;
+        // </product>
+        writer.write_event(Event::End(BytesEnd::borrowed(TAG_PRODUCT)))?;
+    }
+
+    // </products>
+    write_footer(writer, TAG_PRODUCTS)
+}
+
+/// Write `patterns.xml` with the `patterns`.
+pub(crate) fn write_patterns<W: Write>(
+    writer: &mut Writer<W>,
+    patterns: &[Pattern],
+    style: XmlStyle,
+) -> Result<(), MetadataError> {
+    // <patterns xmlns="http://novell.com/package/metadata/suse/pattern" xmlns:rpm="http://linux.duke.edu/metadata/rpm" count="1">
+    let count = patterns.len().to_string();
+    let mut patterns_tag = BytesStart::borrowed_name(TAG_PATTERNS);
+    patterns_tag.push_attribute(("xmlns", XML_NS_PATTERN));
+    patterns_tag.push_attribute(("xmlns:rpm", XML_NS_RPM));
+    patterns_tag.push_attribute(("count", count.as_str()));
+    write_header(writer, patterns_tag)?;
+
+    for pattern in patterns {
+        // <pattern>
+        writer.write_event(Event::Start(BytesStart::borrowed_name(TAG_PATTERN)))?;
+        write_text_element(writer, style, TAG_NAME, &pattern.name)?;
+        write_version(writer, style, &pattern.evr)?;
+        write_text_element(writer, style, TAG_ARCH, &pattern.arch)?;
+
+        // <summary lang="de">Basissystem</summary>
+        for (tag, texts) in [
+            (TAG_SUMMARY, &pattern.summary),
+            (TAG_DESCRIPTION, &pattern.description),
+        ] {
+            write_localized_texts(writer, style, tag, texts)?;
+        }
+        if pattern.uservisible {
+            // <uservisible/>
+            writer.create_element(TAG_USERVISIBLE).write_empty()?;
+        }
+        write_localized_texts(writer, style, TAG_CATEGORY, &pattern.category)?;
+        if let Some(icon) = &pattern.icon {
+            write_text_element(writer, style, TAG_ICON, icon)?;
+        }
+        if let Some(order) = &pattern.order {
+            write_text_element(writer, style, TAG_ORDER, order)?;
+        }
+
+        for (tag, requirements) in [
+            (TAG_RPM_PROVIDES, &pattern.rpm_provides),
+            (TAG_RPM_REQUIRES, &pattern.rpm_requires),
+            (TAG_RPM_CONFLICTS, &pattern.rpm_conflicts),
+            (TAG_RPM_OBSOLETES, &pattern.rpm_obsoletes),
+            (TAG_RPM_SUGGESTS, &pattern.rpm_suggests),
+            (TAG_RPM_ENHANCES, &pattern.rpm_enhances),
+            (TAG_RPM_RECOMMENDS, &pattern.rpm_recommends),
+            (TAG_RPM_SUPPLEMENTS, &pattern.rpm_supplements),
+        ] {
+            write_requirement_section(writer, style, tag, requirements)?;
+        }
+        // </pattern>
+        writer.write_event(Event::End(BytesEnd::borrowed(TAG_PATTERN)))?;
+    }
+
+    // </patterns>
+    write_footer(writer, TAG_PATTERNS)
+}
+
+fn write_localized_texts<W: Write>(
+    writer: &mut Writer<W>,
+    style: XmlStyle,
+    tag: &[u8],
+    texts: &[LocalizedText],
+) -> Result<(), MetadataError> {
+    for text in texts {
+        let mut element = writer.create_element(tag);
+        if let Some(lang) = &text.lang {
+            element = element.with_attribute(utils::attribute(style, "lang", lang.as_str()));
+        }
+        element.write_text_content(utils::text(style, &text.text))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,422 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Fabricated repositories for tests and benchmarks, so that they don't need fixture metadata.
+//!
+//! The contents are made up but look like those of a real distribution: packages of source builds are
+//! split into subpackages, libraries provide sonames which other packages require, and advisories list
+//! the packages of a build. The same options always produce the same repository.
+//!
+//! For property tests and fuzzing, the model types implement `proptest::arbitrary::Arbitrary` with the
+//! `proptest` feature and `arbitrary::Arbitrary` with the `arbitrary` feature. Unlike the generated
+//! repositories the values don't resemble anything real, but they stay within what the metadata can
+//! represent, so that e.g. writing and reading them back should give equal values.
+
+use crate::{
+    utils, ChecksumType, FileType, Package, Repository, Requirement, UpdateRecord, UpdateReference,
+    EVR,
+};
+
+#[cfg(any(feature = "proptest", feature = "arbitrary"))]
+mod generate;
+
+const PREFIXES: &[&str] = &["", "", "", "lib", "python3-", "perl-", "golang-"];
+const STEMS: &[&str] = &[
+    "acorn", "baler", "cinder", "dapple", "ember", "fathom", "gusset", "hollow", "ingot", "jetty",
+    "kestrel", "lumen", "mortar", "nimbus", "osprey", "pewter", "quill", "rivet", "sable",
+    "tamber", "umber", "vellum", "wicket", "yarrow", "zephyr",
+];
+const AUTHORS: &[&str] = &[
+    "Lucille Bluth <lucille@bluthcompany.com>",
+    "Gob Bluth <gob@alliance-of-magicians.com>",
+    "Lindsay Fünke <lindsay@bluthcompany.com>",
+    "Buster Bluth <buster@army.mil>",
+];
+const CHANGES: &[&str] = &[
+    "Update to {version}",
+    "Rebuild for the new toolchain",
+    "Fix a crash on startup",
+    "Add a missing dependency",
+    "Fix CVE-{year}-{number}",
+];
+const DIST_TAG: &str = "fc38";
+/// 2023-04-18, when the builds of the repository start
+const FIRST_BUILD_TIME: u64 = 1681776000;
+
+/// Generates a [`Repository`] of made up packages and advisories.
+///
+/// - `seed` - Different seeds give different repositories.
+/// - `packages` - The number of packages, counting subpackages.
+/// - `files_per_package` - The number of files of a package, besides those every package has.
+/// - `changelogs_per_package` - The number of changelog entries of a package.
+/// - `advisories` - The number of advisories, each of which is for the packages of one build.
+#[derive(Copy, Clone, Debug)]
+pub struct RepositoryGenerator {
+    pub seed: u64,
+    pub packages: usize,
+    pub files_per_package: usize,
+    pub changelogs_per_package: usize,
+    pub advisories: usize,
+}
+
+impl Default for RepositoryGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            packages: 100,
+            files_per_package: 10,
+            changelogs_per_package: 3,
+            advisories: 10,
+        }
+    }
+}
+
+impl RepositoryGenerator {
+    pub fn seed(self, val: u64) -> Self {
+        Self { seed: val, ..self }
+    }
+
+    pub fn packages(self, val: usize) -> Self {
+        Self {
+            packages: val,
+            ..self
+        }
+    }
+
+    pub fn files_per_package(self, val: usize) -> Self {
+        Self {
+            files_per_package: val,
+            ..self
+        }
+    }
+
+    pub fn changelogs_per_package(self, val: usize) -> Self {
+        Self {
+            changelogs_per_package: val,
+            ..self
+        }
+    }
+
+    pub fn advisories(self, val: usize) -> Self {
+        Self {
+            advisories: val,
+            ..self
+        }
+    }
+
+    /// Generate the repository.
+    pub fn generate(&self) -> Repository {
+        let mut rng = Rng::new(self.seed);
+        let mut repo = Repository::new();
+        // the packages of each build, in order
+        let mut builds: Vec<Vec<String>> = Vec::new();
+
+        while repo.packages().len() < self.packages {
+            let name = self.build_name(builds.len());
+            let evr = EVR::new(
+                "0",
+                &format!("{}.{}.{}", rng.below(10), rng.below(20), rng.below(10) + 1),
+                &format!("{}.{}", rng.below(3) + 1, DIST_TAG),
+            );
+            let build_time = FIRST_BUILD_TIME + builds.len() as u64 * 3600 + rng.below(3600);
+            let existing: Vec<&Package> = repo.packages().values().collect();
+            let dependencies = self.dependencies(&mut rng, &existing);
+
+            // a build is a main package, sometimes with a -devel subpackage (libraries always have one)
+            let mut subpackages = vec![name.clone()];
+            if name.starts_with("lib") || rng.below(3) == 0 {
+                subpackages.push(format!("{}-devel", name));
+            }
+            let subpackages =
+                &subpackages[..subpackages.len().min(self.packages - repo.packages().len())];
+
+            let mut pkgids = Vec::new();
+            for subpackage in subpackages {
+                let package =
+                    self.package(&mut rng, subpackage, &name, &evr, build_time, &dependencies);
+                pkgids.push(package.pkgid().to_owned());
+                repo.packages_mut()
+                    .insert(package.pkgid().to_owned(), package);
+            }
+            builds.push(pkgids);
+        }
+
+        for index in 0..self.advisories.min(builds.len()) {
+            // the most recent builds are the ones with advisories
+            let build = &builds[builds.len() - 1 - index];
+            let packages: Vec<&Package> =
+                build.iter().map(|pkgid| &repo.packages()[pkgid]).collect();
+            let advisory = self.advisory(&mut rng, &packages);
+            repo.advisories_mut().insert(advisory.id.clone(), advisory);
+        }
+
+        repo
+    }
+
+    /// The name of the `index`th build, e.g. `libcinder` or `python3-ember2`.
+    fn build_name(&self, index: usize) -> String {
+        let stem = STEMS[index % STEMS.len()];
+        let round = index / STEMS.len();
+        let prefix = PREFIXES[(index + round + self.seed as usize) % PREFIXES.len()];
+        match round {
+            0 => format!("{}{}", prefix, stem),
+            _ => format!("{}{}{}", prefix, stem, round + 1),
+        }
+    }
+
+    /// A few of the `existing` packages for a new build to require, by their name or a soname they provide.
+    fn dependencies(&self, rng: &mut Rng, existing: &[&Package]) -> Vec<Requirement> {
+        if existing.is_empty() {
+            return Vec::new();
+        }
+        let mut requires = Vec::new();
+        for _ in 0..rng.below(4) {
+            let package = existing[rng.below(existing.len() as u64) as usize];
+            let requirement = match package.provides().iter().find(|p| p.name.contains(".so.")) {
+                Some(soname) => Requirement {
+                    name: soname.name.clone(),
+                    ..Requirement::default()
+                },
+                None => Requirement {
+                    name: package.name().to_owned(),
+                    flags: Some("GE".to_owned()),
+                    epoch: Some("0".to_owned()),
+                    version: Some(package.evr().version().to_owned()),
+                    ..Requirement::default()
+                },
+            };
+            if !package.name().ends_with("-devel") && !requires.contains(&requirement) {
+                requires.push(requirement);
+            }
+        }
+        requires
+    }
+
+    fn package(
+        &self,
+        rng: &mut Rng,
+        name: &str,
+        build: &str,
+        evr: &EVR,
+        build_time: u64,
+        dependencies: &[Requirement],
+    ) -> Package {
+        let arch = if name.starts_with("python3-") || name.starts_with("perl-") {
+            "noarch"
+        } else {
+            "x86_64"
+        };
+        let version = evr.version();
+        let release = evr.release();
+        let nvra = format!("{}-{}-{}.{}", name, version, release, arch);
+        let checksum = utils::checksum_bytes(nvra.as_bytes(), ChecksumType::Sha256)
+            .expect("sha256 is supported");
+
+        let mut package = Package::default();
+        package
+            .set_name(name)
+            .set_arch(arch)
+            .set_evr(evr.clone())
+            .set_checksum(checksum)
+            .set_location_href(format!(
+                "Packages/{}/{}.rpm",
+                name.chars().next().unwrap_or('_'),
+                nvra
+            ))
+            .set_summary(format!("The {} package", name))
+            .set_description(format!(
+                "{} is a made up package, generated for testing software which reads RPM metadata.",
+                name
+            ))
+            .set_packager("Fedora Project")
+            .set_url(format!("https://{}.example.org/", build))
+            .set_time_build(build_time)
+            .set_time_file(build_time + 600)
+            .set_rpm_license("MIT")
+            .set_rpm_vendor("Fedora Project")
+            .set_rpm_group("Unspecified")
+            .set_rpm_buildhost("buildvm-x86-01.example.org")
+            .set_rpm_sourcerpm(format!("{}-{}-{}.src.rpm", build, version, release));
+
+        let mut provides = package.self_provides();
+        let mut requires = Vec::new();
+        if let Some(main) = name.strip_suffix("-devel") {
+            requires.push(Requirement {
+                name: main.to_owned(),
+                flags: Some("EQ".to_owned()),
+                epoch: Some(evr.epoch().to_owned()),
+                version: Some(version.to_owned()),
+                release: Some(release.to_owned()),
+                ..Requirement::default()
+            });
+            package.add_file(FileType::Dir, &format!("/usr/include/{}", main));
+            package.add_file(FileType::File, &format!("/usr/include/{}/{}.h", main, main));
+            if main.starts_with("lib") {
+                package.add_file(FileType::File, &format!("/usr/lib64/{}.so", main));
+            }
+        } else if name.starts_with("lib") {
+            let major = version.split('.').next().unwrap_or("0");
+            let soname = format!("{}.so.{}", name, major);
+            provides.push(Requirement {
+                name: format!("{}()(64bit)", soname),
+                ..Requirement::default()
+            });
+            package.add_file(FileType::File, &format!("/usr/lib64/{}", soname));
+        } else if let Some(module) = name.strip_prefix("python3-") {
+            provides.push(self_provide(&format!("python3.11dist({})", module), evr));
+            let dir = format!("/usr/lib/python3.11/site-packages/{}", module);
+            package.add_file(FileType::Dir, &dir);
+            package.add_file(FileType::File, &format!("{}/__init__.py", dir));
+            requires.push(Requirement {
+                name: "python(abi)".to_owned(),
+                flags: Some("EQ".to_owned()),
+                version: Some("3.11".to_owned()),
+                ..Requirement::default()
+            });
+        } else {
+            package.add_file(FileType::File, &format!("/usr/bin/{}", name));
+            requires.push(Requirement {
+                name: "/bin/sh".to_owned(),
+                ..Requirement::default()
+            });
+        }
+        if !name.ends_with("-devel") {
+            requires.extend(dependencies.iter().cloned());
+            let doc_dir = format!("/usr/share/doc/{}", name);
+            package.add_file(FileType::Dir, &doc_dir);
+            package.add_file(FileType::File, &format!("{}/README.md", doc_dir));
+        }
+        for index in 0..self.files_per_package {
+            package.add_file(
+                FileType::File,
+                &format!("/usr/share/{}/data/{:04}.dat", build, index),
+            );
+        }
+        package.set_provides(provides).set_requires(requires);
+
+        let installed = 1024 * (rng.below(4096) + 1);
+        package
+            .set_size_installed(installed)
+            .set_size_archive(installed + 512)
+            .set_size_package(installed / 3 + 4096)
+            .set_rpm_header_range(4504, 4504 + 2048 + rng.below(8192));
+
+        // the changelog is the same for all the packages of a build, newest last like in other.xml
+        let mut changelog_rng = Rng::new(self.seed ^ build_time);
+        for index in (0..self.changelogs_per_package).rev() {
+            let timestamp =
+                (build_time - build_time % 86400).saturating_sub(86400 * 30 * index as u64);
+            let (entry_version, entry_release) = match index {
+                0 => (version.to_owned(), release.to_owned()),
+                _ => (format!("{}~{}", version, index), format!("1.{}", DIST_TAG)),
+            };
+            let author = AUTHORS[changelog_rng.below(AUTHORS.len() as u64) as usize];
+            let change = CHANGES[changelog_rng.below(CHANGES.len() as u64) as usize]
+                .replace("{version}", &entry_version)
+                .replace("{year}", "2023")
+                .replace(
+                    "{number}",
+                    &(changelog_rng.below(90000) + 10000).to_string(),
+                );
+            package.add_changelog(
+                &format!("{} - {}-{}", author, entry_version, entry_release),
+                &format!("- {}", change),
+                timestamp,
+            );
+        }
+
+        package
+    }
+
+    /// An advisory for the `packages` of one build.
+    fn advisory(&self, rng: &mut Rng, packages: &[&Package]) -> UpdateRecord {
+        let main = packages[0];
+        let build = format!("{}-{}", main.name(), main.evr().version());
+        let issued = main.time_build() + 86400;
+        let id = format!("FEDORA-2023-{:010x}", rng.next_u64() >> 24);
+
+        let builder = UpdateRecord::builder()
+            .id(id.clone())
+            .from("updates@fedoraproject.org")
+            .issued_date(utils::format_timestamp(issued))
+            .updated_date(utils::format_timestamp(issued + 3600))
+            .release("Fedora 38")
+            .rights("Copyright (C) 2023 Red Hat, Inc. and others.")
+            .description(format!(
+                "Update {} to {}.",
+                main.name(),
+                main.evr().version()
+            ))
+            .reference(UpdateReference {
+                href: format!("https://bodhi.fedoraproject.org/updates/{}", id),
+                id: id.clone(),
+                title: build.clone(),
+                reftype: "self".to_owned(),
+            })
+            .packages("F38", "Fedora 38", packages.iter().copied());
+        let builder = match rng.below(3) {
+            0 => {
+                let cve = format!("CVE-2023-{}", rng.below(90000) + 10000);
+                builder
+                    .update_type("security")
+                    .severity(["Low", "Moderate", "Important", "Critical"][rng.below(4) as usize])
+                    .title(format!("{} security update", build))
+                    .reference(UpdateReference {
+                        href: format!("https://www.cve.org/CVERecord?id={}", cve),
+                        id: cve.clone(),
+                        title: cve,
+                        reftype: "cve".to_owned(),
+                    })
+            }
+            1 => builder
+                .update_type("bugfix")
+                .severity("None")
+                .title(format!("{} bug fix update", build)),
+            _ => builder
+                .update_type("enhancement")
+                .severity("None")
+                .title(format!("{} enhancement update", build)),
+        };
+        builder
+            .build()
+            .expect("the generated advisories have all the required fields")
+    }
+}
+
+/// The `name = evr` provide of a package.
+fn self_provide(name: &str, evr: &EVR) -> Requirement {
+    Requirement {
+        name: name.to_owned(),
+        flags: Some("EQ".to_owned()),
+        epoch: Some(evr.epoch().to_owned()),
+        version: Some(evr.version().to_owned()),
+        release: Some(evr.release().to_owned()),
+        ..Requirement::default()
+    }
+}
+
+/// A small deterministic random number generator (SplitMix64), so that generated repositories only
+/// depend on the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`, which must not be 0.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use super::FIRST_BUILD_TIME;
+use crate::metadata::HeaderRange;
+use crate::{
+    utils, Changelog, Checksum, FileType, Package, PackageFile, Requirement, UpdateCollection,
+    UpdateCollectionPackage, UpdateRecord, UpdateReference, EVR,
+};
+
+/// The choices that arbitrary values are made up from.
+trait Source {
+    /// A number below `bound`, which must not be 0.
+    fn below(&mut self, bound: u64) -> u64;
+
+    fn chance(&mut self, one_in: u64) -> bool {
+        self.below(one_in) == 0
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len() as u64) as usize]
+    }
+
+    fn arbitrary<T: Generate>(&mut self) -> T
+    where
+        Self: Sized,
+    {
+        T::generate(self)
+    }
+
+    fn vec<T: Generate>(&mut self, max: u64) -> Vec<T>
+    where
+        Self: Sized,
+    {
+        (0..self.below(max + 1)).map(|_| self.arbitrary()).collect()
+    }
+
+    fn hex(&mut self, len: usize) -> String {
+        (0..len)
+            .map(|_| char::from_digit(self.below(16) as u32, 16).unwrap_or('0'))
+            .collect()
+    }
+
+    /// Letters, digits and the punctuation names and versions are made of, e.g. `perl-Foo_2.1+b`.
+    fn word(&mut self) -> String {
+        const FIRST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let len = self.below(12) + 1;
+        (0..len)
+            .map(|index| match index {
+                0 => FIRST[self.below(FIRST.len() as u64) as usize] as char,
+                _ => match self.below(12) {
+                    0 => ['.', '_', '+', '~', '^'][self.below(5) as usize],
+                    _ => FIRST[self.below(FIRST.len() as u64) as usize] as char,
+                },
+            })
+            .collect()
+    }
+
+    /// A word which RPM accepts in a version or release, i.e. without `-` or `:`.
+    fn version(&mut self) -> String {
+        let mut version = self.below(100).to_string();
+        for _ in 0..self.below(3) {
+            version.push('.');
+            version.push_str(&self.word());
+        }
+        version
+    }
+
+    /// Free text, including the characters XML has to escape and some which aren't ASCII, but without
+    /// leading or trailing whitespace.
+    fn text(&mut self) -> String {
+        const WORDS: &[&str] = &[
+            "package",
+            "Fix",
+            "the",
+            "a",
+            "&",
+            "<tag>",
+            "\"quoted\"",
+            "it's",
+            "Fünke",
+            "日本語",
+            "100%",
+            "-",
+            "(64bit)",
+            "\\",
+            "#1234",
+        ];
+        let len = self.below(8) + 1;
+        (0..len)
+            .map(|_| self.pick(WORDS))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The choices made by proptest: a string of bytes which it shrinks by removing bytes and moving them
+/// towards 0, making for fewer and smaller values. Choices past the end are 0.
+#[cfg(feature = "proptest")]
+struct Bytes<'a>(&'a [u8]);
+
+#[cfg(feature = "proptest")]
+impl Source for Bytes<'_> {
+    fn below(&mut self, bound: u64) -> u64 {
+        let len = (64 - (bound - 1).leading_zeros() as usize).div_ceil(8);
+        let (bytes, rest) = self.0.split_at(len.min(self.0.len()));
+        self.0 = rest;
+        bytes.iter().fold(0, |n, b| (n << 8) | u64::from(*b)) % bound
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl Source for arbitrary::Unstructured<'_> {
+    fn below(&mut self, bound: u64) -> u64 {
+        // out of data, the choices are all 0
+        self.int_in_range(0..=bound - 1).unwrap_or(0)
+    }
+}
+
+/// Makes up arbitrary values of the model types, for the `Arbitrary` impls.
+///
+/// The values are of the shapes the metadata formats can hold (e.g. requirement flags are always ones
+/// RPM uses, and text has no leading or trailing whitespace), but otherwise unconstrained, so they
+/// exercise what fixtures and generated repositories typically don't. What only some XML styles write,
+/// such as the `xml:base` of a package location or the pushcount of an advisory, is left unset.
+trait Generate: Sized {
+    fn generate(rng: &mut impl Source) -> Self;
+}
+
+/// The most bytes of choices proptest makes for one value, plenty for the largest package.
+#[cfg(feature = "proptest")]
+const MAX_CHOICE_BYTES: usize = 4096;
+
+/// Implement [`proptest::arbitrary::Arbitrary`] and [`arbitrary::Arbitrary`] with [`Generate`].
+macro_rules! impl_arbitrary {
+    ($($ty:ty),*) => {
+        $(
+            #[cfg(feature = "proptest")]
+            impl proptest::arbitrary::Arbitrary for $ty {
+                type Parameters = ();
+                type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+                fn arbitrary_with(_: ()) -> Self::Strategy {
+                    use proptest::strategy::Strategy;
+                    proptest::collection::vec(proptest::num::u8::ANY, 0..MAX_CHOICE_BYTES)
+                        .prop_map(|bytes| Self::generate(&mut Bytes(&bytes)))
+                        .boxed()
+                }
+            }
+
+            #[cfg(feature = "arbitrary")]
+            impl<'a> arbitrary::Arbitrary<'a> for $ty {
+                fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+                    Ok(Self::generate(u))
+                }
+            }
+        )*
+    };
+}
+
+impl_arbitrary!(
+    EVR,
+    Requirement,
+    Changelog,
+    PackageFile,
+    Checksum,
+    Package,
+    UpdateReference,
+    UpdateCollectionPackage,
+    UpdateCollection,
+    UpdateRecord
+);
+
+impl Generate for EVR {
+    fn generate(rng: &mut impl Source) -> Self {
+        let epoch = match rng.below(3) {
+            0 => String::new(),
+            1 => "0".to_owned(),
+            _ => rng.below(10).to_string(),
+        };
+        EVR {
+            epoch,
+            version: rng.version(),
+            release: rng.version(),
+        }
+    }
+}
+
+impl Generate for Requirement {
+    fn generate(rng: &mut impl Source) -> Self {
+        let name = match rng.below(4) {
+            0 => format!("{}({})", rng.word(), rng.word()),
+            1 => format!("/usr/bin/{}", rng.word()),
+            _ => rng.word(),
+        };
+        let mut requirement = Requirement {
+            name,
+            preinstall: rng.chance(5),
+            ..Requirement::default()
+        };
+        if rng.chance(2) {
+            let evr: EVR = rng.arbitrary();
+            requirement.flags = Some(rng.pick(&["EQ", "LT", "GT", "LE", "GE"]).to_owned());
+            requirement.epoch = Some(evr.epoch).filter(|e| !e.is_empty());
+            requirement.version = Some(evr.version);
+            requirement.release = Some(evr.release).filter(|_| rng.chance(2));
+        }
+        requirement
+    }
+}
+
+impl Generate for Changelog {
+    fn generate(rng: &mut impl Source) -> Self {
+        let lines = rng.below(3) + 1;
+        Changelog {
+            author: format!(
+                "{} <{}@example.org> - {}",
+                rng.text(),
+                rng.word(),
+                rng.version()
+            ),
+            timestamp: FIRST_BUILD_TIME + rng.below(86400 * 365),
+            description: (0..lines)
+                .map(|_| format!("- {}", rng.text()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl Generate for PackageFile {
+    fn generate(rng: &mut impl Source) -> Self {
+        let filetype = match rng.below(4) {
+            0 => FileType::Dir,
+            1 => FileType::Ghost,
+            _ => FileType::File,
+        };
+        let depth = rng.below(4) + 1;
+        PackageFile {
+            filetype,
+            path: (0..depth).map(|_| format!("/{}", rng.word())).collect(),
+        }
+    }
+}
+
+impl Generate for Checksum {
+    fn generate(rng: &mut impl Source) -> Self {
+        match rng.below(4) {
+            0 => Checksum::Sha1(rng.hex(40)),
+            1 => Checksum::Sha512(rng.hex(128)),
+            _ => Checksum::Sha256(rng.hex(64)),
+        }
+    }
+}
+
+impl Generate for Package {
+    fn generate(rng: &mut impl Source) -> Self {
+        let name = rng.word();
+        let arch = rng
+            .pick(&["x86_64", "aarch64", "i686", "noarch", "src"])
+            .to_owned();
+        let evr: EVR = rng.arbitrary();
+        let location_href = format!(
+            "Packages/{}-{}-{}.{}.rpm",
+            name, evr.version, evr.release, arch
+        );
+        let header_start = rng.below(8192);
+
+        // file paths have to be unique within a package
+        let mut files: Vec<PackageFile> = Vec::new();
+        for file in rng.vec::<PackageFile>(8) {
+            if !files.iter().any(|f| f.path == file.path) {
+                files.push(file);
+            }
+        }
+        let mut changelogs: Vec<Changelog> = rng.vec(4);
+        changelogs.sort_by_key(|c| c.timestamp);
+
+        Package {
+            rpm_sourcerpm: match arch.as_str() {
+                "src" => String::new(),
+                _ => format!("{}-{}-{}.src.rpm", name, evr.version, evr.release),
+            },
+            name,
+            arch,
+            evr,
+            checksum: rng.arbitrary(),
+            location_href,
+            location_base: None,
+            summary: rng.text(),
+            description: rng.text(),
+            packager: rng.text(),
+            url: format!("https://{}.example.org/{}", rng.word(), rng.word()),
+            time_file: FIRST_BUILD_TIME + rng.below(86400 * 365),
+            time_build: FIRST_BUILD_TIME + rng.below(86400 * 365),
+            size_package: rng.below(1 << 32),
+            size_installed: rng.below(1 << 32),
+            size_archive: rng.below(1 << 32),
+            rpm_license: rng.text(),
+            rpm_vendor: rng.text(),
+            rpm_group: rng.text(),
+            rpm_buildhost: format!("{}.example.org", rng.word()),
+            rpm_header_range: HeaderRange {
+                start: header_start,
+                end: header_start + rng.below(1 << 20),
+            },
+            rpm_requires: rng.vec(6),
+            rpm_provides: rng.vec(6),
+            rpm_conflicts: rng.vec(2),
+            rpm_obsoletes: rng.vec(2),
+            rpm_suggests: rng.vec(2),
+            rpm_enhances: rng.vec(2),
+            rpm_recommends: rng.vec(2),
+            rpm_supplements: rng.vec(2),
+            rpm_changelogs: changelogs,
+            rpm_files: files,
+            stored_changelogs: None,
+            stored_files: None,
+            unknown_xml: None,
+        }
+    }
+}
+
+impl Generate for UpdateReference {
+    fn generate(rng: &mut impl Source) -> Self {
+        let id = rng.word();
+        UpdateReference {
+            href: format!("https://{}.example.org/{}", rng.word(), id),
+            id,
+            title: rng.text(),
+            reftype: rng.pick(&["self", "bugzilla", "cve", "other"]).to_owned(),
+        }
+    }
+}
+
+impl Generate for UpdateCollectionPackage {
+    fn generate(rng: &mut impl Source) -> Self {
+        let package: Package = rng.arbitrary();
+        UpdateCollectionPackage {
+            reboot_suggested: rng.chance(4),
+            restart_suggested: rng.chance(4),
+            relogin_suggested: rng.chance(4),
+            checksum: Some(package.checksum.clone()).filter(|_| rng.chance(2)),
+            ..UpdateCollectionPackage::from(&package)
+        }
+    }
+}
+
+impl Generate for UpdateCollection {
+    fn generate(rng: &mut impl Source) -> Self {
+        UpdateCollection {
+            name: rng.text(),
+            shortname: Some(rng.word()).filter(|_| rng.chance(2)),
+            packages: rng.vec(4),
+            module: None,
+        }
+    }
+}
+
+impl Generate for UpdateRecord {
+    fn generate(rng: &mut impl Source) -> Self {
+        let issued = FIRST_BUILD_TIME + rng.below(86400 * 365);
+        UpdateRecord {
+            from: format!("{}@example.org", rng.word()),
+            update_type: rng
+                .pick(&["security", "bugfix", "enhancement", "newpackage"])
+                .to_owned(),
+            status: rng.pick(&["final", "stable", "testing"]).to_owned(),
+            version: (rng.below(3) + 1).to_string(),
+            id: rng.word(),
+            title: rng.text(),
+            issued_date: Some(utils::format_timestamp(issued)),
+            updated_date: Some(utils::format_timestamp(issued + rng.below(86400 * 30)))
+                .filter(|_| rng.chance(2)),
+            rights: rng.text(),
+            release: rng.text(),
+            pushcount: None,
+            severity: rng
+                .pick(&["None", "Low", "Moderate", "Important", "Critical"])
+                .to_owned(),
+            summary: rng.text(),
+            description: rng.text(),
+            solution: rng.text(),
+            references: rng.vec(3),
+            pkglist: rng.vec(2),
+        }
+    }
+}
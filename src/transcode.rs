@@ -0,0 +1,167 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::logging;
+use crate::zchunk::ZchunkWriter;
+use crate::{utils, ChecksumType, CompressionType, MetadataError, MetadataType, RepomdRecord};
+
+/// How a metadata file is stored, see [`transcode_metadata_file()`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MetadataFormat {
+    /// The XML document, compressed with the given type of compression
+    Xml(CompressionType),
+    /// A zchunk file with a chunk for each package or advisory, e.g. `primary.xml.zck`
+    Zchunk,
+    /// The sqlite database createrepo_c writes for `primary`, `filelists` and `other`, compressed with the
+    /// given type of compression, e.g. `primary.sqlite.bz2`
+    #[cfg(feature = "sqlite")]
+    Sqlite(CompressionType),
+}
+
+impl MetadataFormat {
+    /// The type of the record of a file of `metadata_type` (or of a version of it, like
+    /// [`MetadataType::PrimaryZck`]) stored in this format, if there is one.
+    pub fn metadata_type(self, metadata_type: &MetadataType) -> Option<MetadataType> {
+        match self {
+            MetadataFormat::Xml(_) => Some(metadata_type.base()),
+            MetadataFormat::Zchunk => zchunk_type(&metadata_type.base()).map(|(zck, _)| zck),
+            #[cfg(feature = "sqlite")]
+            MetadataFormat::Sqlite(_) => database_type(&metadata_type.base()),
+        }
+    }
+}
+
+/// The zchunk version of `metadata_type`, and the element each of its chunks starts with.
+pub(crate) fn zchunk_type(metadata_type: &MetadataType) -> Option<(MetadataType, &'static str)> {
+    match metadata_type {
+        MetadataType::Primary => Some((MetadataType::PrimaryZck, "package")),
+        MetadataType::Filelists => Some((MetadataType::FilelistsZck, "package")),
+        MetadataType::Other => Some((MetadataType::OtherZck, "package")),
+        MetadataType::Updateinfo => Some((MetadataType::UpdateinfoZck, "update")),
+        _ => None,
+    }
+}
+
+/// The sqlite version of `metadata_type`.
+#[cfg(feature = "sqlite")]
+fn database_type(metadata_type: &MetadataType) -> Option<MetadataType> {
+    match metadata_type {
+        MetadataType::Primary => Some(MetadataType::PrimaryDb),
+        MetadataType::Filelists => Some(MetadataType::FilelistsDb),
+        MetadataType::Other => Some(MetadataType::OtherDb),
+        _ => None,
+    }
+}
+
+/// Convert the `primary`, `filelists`, `other` or `updateinfo` metadata file at `source`, of type
+/// `metadata_type` in any of its formats, to `format`, and return the record of the new file with checksums
+/// of `checksum_type`.
+///
+/// The new file is written next to `source` and named as [`MetadataType::file_name()`] says, replacing
+/// `source` if that's the same file. The document is streamed through without being parsed, so only its
+/// compressed chunks are held in memory when writing zchunk, and nothing when writing XML. Packages are
+/// parsed one at a time to write them to a sqlite database.
+///
+/// A database can only be recompressed: the packages in it are spread across the databases of the
+/// repository, so it takes all of them to write the XML again, see
+/// [`Repository::load_from_sqlite_databases()`](crate::Repository::load_from_sqlite_databases).
+pub fn transcode_metadata_file(
+    source: &Path,
+    metadata_type: &MetadataType,
+    format: MetadataFormat,
+    checksum_type: ChecksumType,
+) -> Result<RepomdRecord, MetadataError> {
+    let (target_type, element) = match format {
+        MetadataFormat::Xml(_) => (metadata_type.base(), None),
+        MetadataFormat::Zchunk => {
+            let (zchunk_type, element) = zchunk_type(&metadata_type.base()).ok_or_else(|| {
+                MetadataError::InvalidFieldError("zchunk metadata type", metadata_type.to_string())
+            })?;
+            (zchunk_type, Some(element))
+        }
+        #[cfg(feature = "sqlite")]
+        MetadataFormat::Sqlite(_) => {
+            let database_type = database_type(&metadata_type.base()).ok_or_else(|| {
+                MetadataError::InvalidFieldError("sqlite metadata type", metadata_type.to_string())
+            })?;
+            (database_type, None)
+        }
+    };
+    if metadata_type.is_database() && !target_type.is_database() {
+        return Err(MetadataError::InvalidFieldError(
+            "xml metadata type",
+            metadata_type.to_string(),
+        ));
+    }
+    let compression = match format {
+        MetadataFormat::Xml(compression) => compression,
+        MetadataFormat::Zchunk => CompressionType::None,
+        #[cfg(feature = "sqlite")]
+        MetadataFormat::Sqlite(compression) => compression,
+    };
+    let dir = source.parent().unwrap_or_else(|| Path::new(""));
+    let path = dir.join(target_type.file_name(compression));
+    let _span = logging::span!("transcode {} to {}", source.display(), path.display());
+
+    // the new file is only put in place once it's complete, in case it replaces `source`
+    let tmp_path = dir.join(format!(".{}.transcode", target_type.as_str()));
+    #[cfg(feature = "sqlite")]
+    if target_type.is_database() && !metadata_type.is_database() {
+        // the database is written uncompressed, and then compressed like an XML file is
+        let db_path = dir.join(format!(".{}.transcode.sqlite", target_type.as_str()));
+        let written =
+            crate::sqlite::write_database_from_xml(source, &target_type, &db_path, checksum_type)
+                .and_then(|()| {
+                    let (tmp_path, mut writer) = utils::writer_to_file(&tmp_path, compression)?;
+                    io::copy(&mut File::open(&db_path)?, &mut writer)?;
+                    drop(writer);
+                    fs::rename(tmp_path, &path)?;
+                    Ok(())
+                });
+        let _ = fs::remove_file(&db_path);
+        written?;
+        logging::debug!("transcoded {} to {}", source.display(), path.display());
+        return record_of(target_type, &path, checksum_type);
+    }
+    let mut reader = utils::reader_from_file(source)?;
+    match element {
+        None => {
+            let (tmp_path, mut writer) = utils::writer_to_file(&tmp_path, compression)?;
+            io::copy(&mut reader, &mut writer)?;
+            // the encoders only finish writing when they're dropped
+            drop(writer);
+            fs::rename(tmp_path, &path)?;
+        }
+        Some(element) => {
+            let file = BufWriter::new(File::create(&tmp_path)?);
+            let mut writer = ZchunkWriter::new(file, element);
+            io::copy(&mut reader, &mut writer)?;
+            writer.finish()?;
+            fs::rename(&tmp_path, &path)?;
+        }
+    }
+    logging::debug!("transcoded {} to {}", source.display(), path.display());
+    record_of(target_type, &path, checksum_type)
+}
+
+/// The record of the transcoded file at `path`.
+fn record_of(
+    metadata_type: MetadataType,
+    path: &Path,
+    checksum_type: ChecksumType,
+) -> Result<RepomdRecord, MetadataError> {
+    #[cfg(feature = "sqlite")]
+    if metadata_type.is_database() {
+        let mut record = RepomdRecord::from_file(metadata_type, path, checksum_type)?;
+        record.database_version = Some(crate::sqlite::DATABASE_VERSION);
+        return Ok(record);
+    }
+    RepomdRecord::from_file(metadata_type, path, checksum_type)
+}
@@ -10,11 +10,14 @@ use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::{Reader, Writer};
 
 use crate::metadata::{
-    UpdateCollection, UpdateCollectionModule, UpdateCollectionPackage, UpdateReference,
+    Checksum, UpdateCollection, UpdateCollectionModule, UpdateCollectionPackage, UpdateReference,
 };
 
-use super::metadata::{RpmMetadata, UpdateRecord, UpdateinfoXml};
-use super::{MetadataError, Repository};
+use super::metadata::{
+    ParseContext, ParseError, ParseMode, ParseWarning, RpmMetadata, UpdateRecord, UpdateinfoXml,
+    XmlStyle,
+};
+use super::{utils, MetadataError, Repository};
 
 const TAG_UPDATES: &[u8] = b"updates";
 const TAG_UPDATE: &[u8] = b"update";
@@ -25,6 +28,8 @@ const TAG_SEVERITY: &[u8] = b"severity";
 const TAG_ISSUED: &[u8] = b"issued";
 const TAG_UPDATED: &[u8] = b"updated";
 const TAG_RIGHTS: &[u8] = b"copyright";
+const TAG_RIGHTS_CREATEREPO_C: &[u8] = b"rights";
+const TAG_PUSHCOUNT: &[u8] = b"pushcount";
 const TAG_SUMMARY: &[u8] = b"summary";
 const TAG_DESCRIPTION: &[u8] = b"description";
 const TAG_SOLUTION: &[u8] = b"solution";
@@ -34,7 +39,10 @@ const TAG_NAME: &[u8] = b"name";
 const TAG_MODULE: &[u8] = b"module";
 const TAG_PACKAGE: &[u8] = b"package";
 const TAG_FILENAME: &[u8] = b"filename";
+const TAG_SUM: &[u8] = b"sum";
 const TAG_REBOOT_SUGGESTED: &[u8] = b"reboot_suggested";
+const TAG_RESTART_SUGGESTED: &[u8] = b"restart_suggested";
+const TAG_RELOGIN_SUGGESTED: &[u8] = b"relogin_suggested";
 const TAG_REFERENCES: &[u8] = b"references";
 const TAG_REFERENCE: &[u8] = b"reference";
 
@@ -73,6 +81,7 @@ impl RpmMetadata for UpdateinfoXml {
 
 pub struct UpdateinfoXmlWriter<W: Write> {
     writer: Writer<W>,
+    style: XmlStyle,
 }
 
 impl<W: Write> UpdateinfoXmlWriter<W> {
@@ -90,7 +99,12 @@ impl<W: Write> UpdateinfoXmlWriter<W> {
     }
 
     pub fn write_updaterecord(&mut self, record: &UpdateRecord) -> Result<(), MetadataError> {
-        write_updaterecord(record, &mut self.writer)
+        write_updaterecord(record, &mut self.writer, self.style)
+    }
+
+    /// Set how the metadata is laid out and escaped.
+    pub fn set_style(&mut self, style: XmlStyle) {
+        self.style = style;
     }
 
     pub fn finish(&mut self) -> Result<(), MetadataError> {
@@ -115,11 +129,41 @@ impl<W: Write> UpdateinfoXmlWriter<W> {
 
 pub struct UpdateinfoXmlReader<R: BufRead> {
     reader: Reader<R>,
+    context: ParseContext,
 }
 
 impl<R: BufRead> UpdateinfoXmlReader<R> {
     pub fn read_update(&mut self) -> Result<Option<UpdateRecord>, MetadataError> {
-        parse_updaterecord(&mut self.reader)
+        loop {
+            self.context.next_entry();
+            match parse_updaterecord(&mut self.reader, &mut self.context) {
+                Err(e) => {
+                    if let Err(e) = self.context.recover(&mut self.reader, TAG_UPDATE, e) {
+                        return Err(self.context.locate(&self.reader, e, None));
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Set how metadata which doesn't follow the spec is dealt with. Strict by default.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.context.mode = mode;
+    }
+
+    pub fn parse_mode(&self) -> ParseMode {
+        self.context.mode
+    }
+
+    /// Take the warnings recorded so far in [`ParseMode::Lenient`].
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        self.context.take_warnings()
+    }
+
+    /// Take the errors of the entries skipped so far in [`ParseMode::Recover`].
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        self.context.take_errors()
     }
 }
 
@@ -133,27 +177,35 @@ impl<R: BufRead> Iterator for UpdateinfoXmlReader<R> {
 
 impl UpdateinfoXml {
     pub fn new_writer<W: Write>(writer: quick_xml::Writer<W>) -> UpdateinfoXmlWriter<W> {
-        UpdateinfoXmlWriter { writer }
+        UpdateinfoXmlWriter {
+            writer,
+            style: XmlStyle::Standard,
+        }
     }
 
     pub fn new_reader<R: BufRead>(reader: quick_xml::Reader<R>) -> UpdateinfoXmlReader<R> {
-        UpdateinfoXmlReader { reader }
+        UpdateinfoXmlReader {
+            reader,
+            context: ParseContext::new("updateinfo", "updates"),
+        }
     }
 }
 
 fn parse_updaterecord<R: BufRead>(
     reader: &mut Reader<R>,
+    context: &mut ParseContext,
 ) -> Result<Option<UpdateRecord>, MetadataError> {
     let mut buf = Vec::new();
     let mut format_text_buf = Vec::new();
 
     let mut record = UpdateRecord::default();
+    let mut suggested = UpdateCollectionPackage::default();
 
     // TODO: get rid of unwraps, various branches could happen in wrong order
     loop {
         match reader.read_event(&mut buf)? {
-            Event::End(e) if e.name().as_ref() == TAG_UPDATE => break,
-            Event::Start(e) => match e.name().as_ref() {
+            Event::End(e) if e.name() == TAG_UPDATE => break,
+            Event::Start(e) => match e.name() {
                 TAG_UPDATE => {
                     // for attr in e.attributes() {
                     //     let attr = attr?;
@@ -167,42 +219,34 @@ fn parse_updaterecord<R: BufRead>(
                     //     }
                     // }
 
-                    record.status = e
-                        .try_get_attribute("status")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("status"))?
-                        .unescape_and_decode_value(reader)?;
-                    record.from = e
-                        .try_get_attribute("from")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("from"))?
-                        .unescape_and_decode_value(reader)?;
-                    record.update_type = e
-                        .try_get_attribute("type")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("type"))?
-                        .unescape_and_decode_value(reader)?;
-                    record.version = e
-                        .try_get_attribute("version")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("version"))?
-                        .unescape_and_decode_value(reader)?;
+                    context.start_entry(reader, &e);
+                    record.status = optional_attribute(reader, &e, "status", "")?;
+                    record.from = optional_attribute(reader, &e, "from", "")?;
+                    record.update_type = optional_attribute(reader, &e, "type", "")?;
+                    record.version = optional_attribute(reader, &e, "version", "")?;
                 }
                 TAG_ID => {
                     record.id = reader.read_text(TAG_ID, &mut format_text_buf)?;
+                    context.entry = Some(record.id.clone());
                 }
                 TAG_TITLE => {
                     record.title = reader.read_text(TAG_TITLE, &mut format_text_buf)?;
                 }
                 TAG_ISSUED => {
-                    record.issued_date = Some(reader.read_text(TAG_ISSUED, &mut format_text_buf)?);
+                    record.issued_date = Some(read_date(reader, &e, &mut format_text_buf)?);
                 }
                 TAG_UPDATED => {
-                    record.updated_date =
-                        Some(reader.read_text(TAG_UPDATED, &mut format_text_buf)?);
+                    record.updated_date = Some(read_date(reader, &e, &mut format_text_buf)?);
                 }
-                TAG_RIGHTS => {
-                    record.rights = reader.read_text(TAG_RIGHTS, &mut format_text_buf)?;
+                TAG_RIGHTS | TAG_RIGHTS_CREATEREPO_C => {
+                    record.rights = reader.read_text(e.name(), &mut format_text_buf)?;
                 }
                 TAG_RELEASE => {
                     record.release = reader.read_text(TAG_RELEASE, &mut format_text_buf)?;
                 }
+                TAG_PUSHCOUNT => {
+                    record.pushcount = Some(reader.read_text(TAG_PUSHCOUNT, &mut format_text_buf)?);
+                }
                 TAG_SEVERITY => {
                     record.severity = reader.read_text(TAG_SEVERITY, &mut format_text_buf)?;
                 }
@@ -215,38 +259,41 @@ fn parse_updaterecord<R: BufRead>(
                 TAG_SOLUTION => {
                     record.solution = reader.read_text(TAG_SOLUTION, &mut format_text_buf)?;
                 }
-                // reboot_suggested, not clear if it needs to be parsed
+                // old versions of yum put these in the update rather than in each of its packages
+                name @ (TAG_REBOOT_SUGGESTED | TAG_RESTART_SUGGESTED | TAG_RELOGIN_SUGGESTED) => {
+                    let value = reader.read_text(name, &mut format_text_buf)?;
+                    set_suggested(&mut suggested, name, utils::parse_flag(&value));
+                }
                 TAG_REFERENCES => {
+                    context.enter(TAG_REFERENCES);
                     loop {
                         match reader.read_event(&mut buf)? {
-                            Event::Start(e) if e.name().as_ref() == TAG_REFERENCE => {
-                                let mut reference = UpdateReference::default();
+                            Event::Start(e) if e.name() == TAG_REFERENCE => {
                                 // for attr in e.attributes() {
                                 // let attr = attr?;
-                                reference.href = e
-                                    .try_get_attribute("href")?
-                                    .ok_or_else(|| MetadataError::MissingAttributeError("href"))?
-                                    .unescape_and_decode_value(reader)?;
-                                reference.id = e
-                                    .try_get_attribute("id")?
-                                    .ok_or_else(|| MetadataError::MissingAttributeError("id"))?
-                                    .unescape_and_decode_value(reader)?;
-                                reference.reftype = e
-                                    .try_get_attribute("type")?
-                                    .ok_or_else(|| MetadataError::MissingAttributeError("type"))?
-                                    .unescape_and_decode_value(reader)?;
-                                reference.title = e
-                                    .try_get_attribute("title")?
-                                    .ok_or_else(|| MetadataError::MissingAttributeError("title"))?
-                                    .unescape_and_decode_value(reader)?;
+                                let reference = UpdateReference {
+                                    href: optional_attribute(reader, &e, "href", "")?,
+                                    id: optional_attribute(reader, &e, "id", "")?,
+                                    reftype: optional_attribute(reader, &e, "type", "")?,
+                                    title: optional_attribute(reader, &e, "title", "")?,
+                                };
                                 record.references.push(reference);
                             }
-                            Event::End(e) if e.name().as_ref() == TAG_REFERENCES => break,
+                            Event::End(e) if e.name() == TAG_REFERENCES => {
+                                context.leave();
+                                break;
+                            }
                             _ => (), // TODO
                         }
                     }
                 }
-                TAG_PKGLIST => record.pkglist = parse_pkglist(reader)?,
+                TAG_PKGLIST => record.pkglist = parse_pkglist(reader, context)?,
+                _ => (),
+            },
+            // <issued date="2020-05-27 04:10:31"/>
+            Event::Empty(e) => match e.name() {
+                TAG_ISSUED => record.issued_date = date_attribute(reader, &e)?,
+                TAG_UPDATED => record.updated_date = date_attribute(reader, &e)?,
                 _ => (),
             },
             Event::Eof => return Ok(None),
@@ -256,11 +303,60 @@ fn parse_updaterecord<R: BufRead>(
         format_text_buf.clear();
     }
 
+    for package in record
+        .pkglist
+        .iter_mut()
+        .flat_map(|collection| collection.packages.iter_mut())
+    {
+        package.reboot_suggested |= suggested.reboot_suggested;
+        package.restart_suggested |= suggested.restart_suggested;
+        package.relogin_suggested |= suggested.relogin_suggested;
+    }
+
     Ok(Some(record))
 }
 
+/// The value of an attribute which may be left out, as createrepo_c does with the empty ones, or `default`.
+fn optional_attribute<R: BufRead>(
+    reader: &Reader<R>,
+    tag: &BytesStart,
+    name: &str,
+    default: &str,
+) -> Result<String, MetadataError> {
+    match tag.try_get_attribute(name)? {
+        Some(value) => Ok(value.unescape_and_decode_value(reader)?),
+        None => Ok(default.to_owned()),
+    }
+}
+
+fn date_attribute<R: BufRead>(
+    reader: &Reader<R>,
+    tag: &BytesStart,
+) -> Result<Option<String>, MetadataError> {
+    match tag.try_get_attribute("date")? {
+        Some(value) => Ok(Some(value.unescape_and_decode_value(reader)?)),
+        None => Ok(None),
+    }
+}
+
+/// The date of an `<issued>` or `<updated>` element, which is either its `date` attribute or its text.
+fn read_date<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &BytesStart,
+    buf: &mut Vec<u8>,
+) -> Result<String, MetadataError> {
+    match date_attribute(reader, tag)? {
+        Some(date) => {
+            reader.read_to_end(tag.name(), buf)?;
+            Ok(date)
+        }
+        None => Ok(reader.read_text(tag.name(), buf)?),
+    }
+}
+
 pub fn parse_pkglist<R: BufRead>(
     reader: &mut Reader<R>,
+    context: &mut ParseContext,
 ) -> Result<Vec<UpdateCollection>, MetadataError> {
     let mut current_collection = None;
     let mut current_package = None;
@@ -268,49 +364,49 @@ pub fn parse_pkglist<R: BufRead>(
     let mut text_buf = Vec::with_capacity(256);
     let mut collections = Vec::new();
 
+    context.enter(TAG_PKGLIST);
     loop {
         match reader.read_event(&mut buf)? {
-            Event::End(e) if e.name().as_ref() == TAG_PKGLIST => break,
-            Event::Start(e) if e.name().as_ref() == TAG_COLLECTION => {
-                current_collection = Some(UpdateCollection::default());
+            Event::End(e) if e.name() == TAG_PKGLIST => break,
+            Event::Start(e) if e.name() == TAG_COLLECTION => {
+                context.enter(TAG_COLLECTION);
+                current_collection = Some(UpdateCollection {
+                    shortname: e
+                        .try_get_attribute("short")?
+                        .map(|value| value.unescape_and_decode_value(reader))
+                        .transpose()?,
+                    ..UpdateCollection::default()
+                });
             }
-            Event::End(e) if e.name().as_ref() == TAG_COLLECTION => {
+            Event::End(e) if e.name() == TAG_PACKAGE => {
+                if let Some(package) = current_package.take() {
+                    current_collection.as_mut().unwrap().packages.push(package);
+                }
+            }
+            Event::End(e) if e.name() == TAG_COLLECTION => {
+                context.leave();
                 collections.push(current_collection.take().unwrap());
             }
-            Event::Start(e) => match e.name().as_ref() {
+            Event::Start(e) => match e.name() {
                 TAG_NAME => {
                     current_collection.as_mut().unwrap().name =
                         reader.read_text(TAG_NAME, &mut text_buf)?
                 }
                 TAG_MODULE => {
-                    let name = e
-                        .try_get_attribute("name")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("name"))?
-                        .unescape_and_decode_value(reader)?;
-                    let stream = e
-                        .try_get_attribute("stream")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("stream"))?
-                        .unescape_and_decode_value(reader)?;
-                    let version = e
-                        .try_get_attribute("version")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("version"))?
-                        .unescape_and_decode_value(reader)?;
-                    let context = e
-                        .try_get_attribute("context")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("context"))?
-                        .unescape_and_decode_value(reader)?;
-                    let arch = e
-                        .try_get_attribute("arch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("arch"))?
-                        .unescape_and_decode_value(reader)?;
-
-                    let version = version.parse()?;
+                    let name = context.attribute(reader, &e, "name", "")?;
+                    let stream = context.attribute(reader, &e, "stream", "")?;
+                    let version = context.attribute(reader, &e, "version", "")?;
+                    let module_context = context.attribute(reader, &e, "context", "")?;
+                    let arch = context.attribute(reader, &e, "arch", "")?;
+
+                    let version =
+                        context.tolerate(version.parse().map_err(MetadataError::from), || 0)?;
 
                     let module = UpdateCollectionModule {
                         name,
                         stream,
                         version,
-                        context,
+                        context: module_context,
                         arch,
                     };
                     current_collection.as_mut().unwrap().module = Some(module);
@@ -318,30 +414,12 @@ pub fn parse_pkglist<R: BufRead>(
                 TAG_PACKAGE => {
                     let mut package = UpdateCollectionPackage::default();
 
-                    let name = e
-                        .try_get_attribute("name")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("name"))?
-                        .unescape_and_decode_value(reader)?;
-                    let version = e
-                        .try_get_attribute("version")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("version"))?
-                        .unescape_and_decode_value(reader)?;
-                    let epoch = e
-                        .try_get_attribute("epoch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("epoch"))?
-                        .unescape_and_decode_value(reader)?;
-                    let src = e
-                        .try_get_attribute("src")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("src"))?
-                        .unescape_and_decode_value(reader)?;
-                    let release = e
-                        .try_get_attribute("release")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("release"))?
-                        .unescape_and_decode_value(reader)?;
-                    let arch = e
-                        .try_get_attribute("arch")?
-                        .ok_or_else(|| MetadataError::MissingAttributeError("arch"))?
-                        .unescape_and_decode_value(reader)?;
+                    let name = optional_attribute(reader, &e, "name", "")?;
+                    let version = optional_attribute(reader, &e, "version", "")?;
+                    let epoch = optional_attribute(reader, &e, "epoch", "0")?;
+                    let src = optional_attribute(reader, &e, "src", "")?;
+                    let release = optional_attribute(reader, &e, "release", "")?;
+                    let arch = optional_attribute(reader, &e, "arch", "")?;
 
                     package.name = name;
                     package.version = version;
@@ -349,6 +427,17 @@ pub fn parse_pkglist<R: BufRead>(
                     package.arch = arch;
                     package.epoch = epoch;
                     package.src = src;
+                    // some producers give the flags as attributes
+                    for tag in [
+                        TAG_REBOOT_SUGGESTED,
+                        TAG_RESTART_SUGGESTED,
+                        TAG_RELOGIN_SUGGESTED,
+                    ] {
+                        if let Some(value) = e.try_get_attribute(tag)? {
+                            let value = value.unescape_and_decode_value(reader)?;
+                            set_suggested(&mut package, tag, utils::parse_flag(&value));
+                        }
+                    }
                     current_package = Some(package);
                     // current_collection.unwrap().packages.push(package);
                 }
@@ -356,82 +445,147 @@ pub fn parse_pkglist<R: BufRead>(
                     current_package.as_mut().unwrap().filename =
                         reader.read_text(TAG_FILENAME, &mut text_buf)?;
                 }
-                e @ _ => panic!("{}", dbg!(std::str::from_utf8(e).unwrap())),
+                TAG_SUM => {
+                    let checksum_type = context.attribute(reader, &e, "type", "")?;
+                    let checksum_value = reader.read_text(TAG_SUM, &mut text_buf)?;
+                    let checksum = Checksum::try_create(checksum_type, checksum_value)?;
+                    current_package.as_mut().unwrap().checksum = Some(checksum);
+                }
+                // "True" from createrepo_c, "1" from this library
+                name @ (TAG_REBOOT_SUGGESTED | TAG_RESTART_SUGGESTED | TAG_RELOGIN_SUGGESTED) => {
+                    let value = reader.read_text(name, &mut text_buf)?;
+                    let package = current_package.as_mut().unwrap();
+                    set_suggested(package, name, utils::parse_flag(&value));
+                }
+                e => context.deviation(MetadataError::UnknownAttributeError(format!(
+                    "unrecognized element {}",
+                    std::str::from_utf8(e)?
+                )))?,
             },
             _ => (), // TODO
         }
         buf.clear();
         text_buf.clear();
     }
+    context.leave();
 
     Ok(collections)
 }
 
+/// Set the `reboot_suggested`, `restart_suggested` or `relogin_suggested` flag of `package`.
+fn set_suggested(package: &mut UpdateCollectionPackage, tag: &[u8], value: bool) {
+    match tag {
+        TAG_REBOOT_SUGGESTED => package.reboot_suggested = value,
+        TAG_RESTART_SUGGESTED => package.restart_suggested = value,
+        _ => package.relogin_suggested = value,
+    }
+}
+
+/// Write a text element, which createrepo_c leaves out if it's empty.
+fn write_text_element<W: Write>(
+    writer: &mut Writer<W>,
+    style: XmlStyle,
+    tag: &[u8],
+    value: &str,
+) -> Result<(), MetadataError> {
+    if style == XmlStyle::CreaterepoC && value.is_empty() {
+        return Ok(());
+    }
+    writer
+        .create_element(tag)
+        .write_text_content(utils::text(style, value))?;
+    Ok(())
+}
+
+/// Add the attributes to `tag`, leaving out the empty ones for createrepo_c.
+fn push_attributes(tag: &mut BytesStart, style: XmlStyle, attributes: &[(&str, &str)]) {
+    for (key, value) in attributes {
+        if style == XmlStyle::CreaterepoC && value.is_empty() {
+            continue;
+        }
+        tag.push_attribute(utils::attribute(style, *key, *value));
+    }
+}
+
 fn write_updaterecord<W: Write>(
     record: &UpdateRecord,
     writer: &mut Writer<W>,
+    style: XmlStyle,
 ) -> Result<(), MetadataError> {
     // <update from="updates@fedoraproject.org" status="stable" type="bugfix" version="2.0">
     let mut updates_tag = BytesStart::borrowed_name(TAG_UPDATE);
-    updates_tag.push_attribute(("status", record.status.as_str()));
-    updates_tag.push_attribute(("from", record.from.as_str()));
-    updates_tag.push_attribute(("type", record.update_type.as_str()));
-    updates_tag.push_attribute(("version", record.version.as_str()));
+    let (status, from) = (
+        ("status", record.status.as_str()),
+        ("from", record.from.as_str()),
+    );
+    let (first, second) = match style {
+        XmlStyle::Standard | XmlStyle::Legacy => (status, from),
+        XmlStyle::CreaterepoC => (from, status),
+    };
+    push_attributes(
+        &mut updates_tag,
+        style,
+        &[
+            first,
+            second,
+            ("type", record.update_type.as_str()),
+            ("version", record.version.as_str()),
+        ],
+    );
     writer.write_event(Event::Start(updates_tag.to_borrowed()))?;
 
     // <id>FEDORA-2020-15f9382449</id>
-    writer
-        .create_element(TAG_ID)
-        .write_text_content(BytesText::from_plain_str(record.id.as_str()))?;
+    write_text_element(writer, style, TAG_ID, &record.id)?;
 
     // <title>nano-4.9.3-1.fc32</title>
-    writer
-        .create_element(TAG_TITLE)
-        .write_text_content(BytesText::from_plain_str(record.title.as_str()))?;
+    write_text_element(writer, style, TAG_TITLE, &record.title)?;
 
     // <issued date="2020-05-27 04:10:31"/>
-    if let Some(issued_date) = &record.issued_date {
-        writer
-            .create_element(TAG_ISSUED)
-            .write_text_content(BytesText::from_plain_str(issued_date.as_str()))?;
-    }
-
     // <updated date="2021-04-03 00:15:00"/>
-    if let Some(updated_date) = &record.updated_date {
-        writer
-            .create_element(TAG_UPDATED)
-            .write_text_content(BytesText::from_plain_str(updated_date.as_str()))?;
+    for (tag, date) in [
+        (TAG_ISSUED, &record.issued_date),
+        (TAG_UPDATED, &record.updated_date),
+    ] {
+        match (date, style) {
+            (Some(date), XmlStyle::Standard | XmlStyle::Legacy) => {
+                write_text_element(writer, style, tag, date)?
+            }
+            (Some(date), XmlStyle::CreaterepoC) => {
+                writer
+                    .create_element(tag)
+                    .with_attribute(utils::attribute(style, "date", date))
+                    .write_empty()?;
+            }
+            (None, _) => (),
+        }
     }
 
     // <rights>Copyright (C) 2021 blah blah blah.</rights>
-    writer
-        .create_element(TAG_RIGHTS)
-        .write_text_content(BytesText::from_plain_str(record.rights.as_str()))?;
+    let rights_tag = match style {
+        XmlStyle::Standard | XmlStyle::Legacy => TAG_RIGHTS,
+        XmlStyle::CreaterepoC => TAG_RIGHTS_CREATEREPO_C,
+    };
+    write_text_element(writer, style, rights_tag, &record.rights)?;
 
     // <release>Fedora 32</release>
-    writer
-        .create_element(TAG_RELEASE)
-        .write_text_content(BytesText::from_plain_str(record.release.as_str()))?;
+    write_text_element(writer, style, TAG_RELEASE, &record.release)?;
+
+    // <pushcount>1</pushcount> (createrepo_c only)
+    if let (Some(pushcount), XmlStyle::CreaterepoC) = (&record.pushcount, style) {
+        write_text_element(writer, style, TAG_PUSHCOUNT, pushcount)?;
+    }
 
     // <severity>Moderate</severity>
-    writer
-        .create_element(TAG_SEVERITY)
-        .write_text_content(BytesText::from_plain_str(record.severity.as_str()))?;
+    write_text_element(writer, style, TAG_SEVERITY, &record.severity)?;
 
     // <summary>nano-4.9.3-1.fc32 bugfix update</summary>
-    writer
-        .create_element(TAG_SUMMARY)
-        .write_text_content(BytesText::from_plain_str(record.summary.as_str()))?;
+    write_text_element(writer, style, TAG_SUMMARY, &record.summary)?;
 
     // <description>- update to the latest upstream bugfix release</description>
-    writer
-        .create_element(TAG_DESCRIPTION)
-        .write_text_content(BytesText::from_plain_str(record.description.as_str()))?;
+    write_text_element(writer, style, TAG_DESCRIPTION, &record.description)?;
 
     // <solution>Another description, usually about how the update should be applied</solution>
-    writer
-        .create_element(TAG_SOLUTION)
-        .write_text_content(BytesText::from_plain_str(record.solution.as_str()))?;
+    write_text_element(writer, style, TAG_SOLUTION, &record.solution)?;
 
     // It's not clear that any metadata actually uses this
     // // <reboot_suggested>True</reboot_suggestion> (optional)
@@ -448,13 +602,18 @@ fn write_updaterecord<W: Write>(
 
         for reference in &record.references {
             // <reference href="https://bugzilla.redhat.com/show_bug.cgi?id=1839351" id="1839351" type="bugzilla" title="nano-4.9.3 is available"/>
-            writer
-                .create_element(TAG_REFERENCE)
-                .with_attribute(("href", reference.href.as_str()))
-                .with_attribute(("id", reference.id.as_str()))
-                .with_attribute(("type", reference.reftype.as_str()))
-                .with_attribute(("title", reference.title.as_str()))
-                .write_empty()?;
+            let mut reference_tag = BytesStart::borrowed_name(TAG_REFERENCE);
+            push_attributes(
+                &mut reference_tag,
+                style,
+                &[
+                    ("href", reference.href.as_str()),
+                    ("id", reference.id.as_str()),
+                    ("type", reference.reftype.as_str()),
+                    ("title", reference.title.as_str()),
+                ],
+            );
+            writer.write_event(Event::Empty(reference_tag))?;
         }
 
         // </references>
@@ -470,66 +629,79 @@ fn write_updaterecord<W: Write>(
         writer.write_event(Event::Start(tag_pkglist.to_borrowed()))?;
 
         for collection in &record.pkglist {
-            // <collection short="F35">
+            // <collection short="F35"> (not every producer sets a short name)
             let mut tag_collection = BytesStart::borrowed_name(TAG_COLLECTION);
-            tag_collection.push_attribute(("short", collection.shortname.as_str()));
+            if let Some(shortname) = &collection.shortname {
+                push_attributes(&mut tag_collection, style, &[("short", shortname.as_str())]);
+            }
             writer.write_event(Event::Start(tag_collection.to_borrowed()))?;
 
-            // <name>Fedora 35</name>
-            writer
-                .create_element(TAG_NAME)
-                .write_text_content(BytesText::from_plain_str(&collection.name))?;
+            // <name>Fedora 35</name> (optional)
+            if !collection.name.is_empty() {
+                write_text_element(writer, style, TAG_NAME, &collection.name)?;
+            }
 
             // <module stream="3.0" version="8000020190425181943" arch="x86_64" name="freeradius" context="75ec4169" />
             if let Some(module) = &collection.module {
-                writer
-                    .create_element(TAG_MODULE)
-                    .with_attribute(("name", module.name.as_str()))
-                    .with_attribute(("stream", module.stream.as_str()))
-                    .with_attribute(("version", module.version.to_string().as_str()))
-                    .with_attribute(("context", module.context.as_str()))
-                    .with_attribute(("arch", module.arch.as_str()))
-                    .write_empty()?;
+                let version = module.version.to_string();
+                let mut module_tag = BytesStart::borrowed_name(TAG_MODULE);
+                push_attributes(
+                    &mut module_tag,
+                    style,
+                    &[
+                        ("name", module.name.as_str()),
+                        ("stream", module.stream.as_str()),
+                        ("version", version.as_str()),
+                        ("context", module.context.as_str()),
+                        ("arch", module.arch.as_str()),
+                    ],
+                );
+                writer.write_event(Event::Empty(module_tag))?;
             }
 
             for package in &collection.packages {
                 // <package src="kexec-tools-2.0.4-32.el7_0.1.src.rpm" name="kexec-tools" epoch="0" version="2.0.4" release="32.el7" arch="x86_64">
                 let mut package_tag = BytesStart::borrowed_name(TAG_PACKAGE);
-                package_tag.push_attribute(("name", package.name.as_str()));
-                package_tag.push_attribute(("version", package.version.as_str()));
-                package_tag.push_attribute(("release", package.release.as_str()));
-                package_tag.push_attribute(("epoch", package.epoch.to_string().as_str()));
-                package_tag.push_attribute(("arch", package.arch.as_str()));
-                package_tag.push_attribute(("src", package.src.as_str()));
+                push_attributes(
+                    &mut package_tag,
+                    style,
+                    &[
+                        ("name", package.name.as_str()),
+                        ("version", package.version.as_str()),
+                        ("release", package.release.as_str()),
+                        ("epoch", package.epoch.as_str()),
+                        ("arch", package.arch.as_str()),
+                        ("src", package.src.as_str()),
+                    ],
+                );
                 writer.write_event(Event::Start(package_tag.to_borrowed()))?;
 
                 // <filename>pypy-7.3.6-1.fc35.src.rpm</filename>
-                writer
-                    .create_element(TAG_FILENAME)
-                    .write_text_content(BytesText::from_plain_str(&package.filename))?;
+                write_text_element(writer, style, TAG_FILENAME, &package.filename)?;
 
                 // <sum type="sha256">8e214681104e4ba73726e0ce11d21b963ec0390fd70458d439ddc72372082034</sum> (optional)
                 if let Some(checksum) = &package.checksum {
-                    let (checksum_type, value) = checksum.to_values()?;
-                    writer
-                        .create_element("sum")
-                        .with_attribute(("type", checksum_type))
-                        .write_text_content(BytesText::from_plain_str(value))?;
-                }
-                if package.reboot_suggested {
+                    let (checksum_type, value) = utils::checksum_values(style, checksum)?;
                     writer
-                        .create_element("reboot_suggested")
-                        .write_text_content(BytesText::from_plain_str("1"))?;
+                        .create_element(TAG_SUM)
+                        .with_attribute(utils::attribute(style, "type", checksum_type))
+                        .write_text_content(utils::text(style, value))?;
                 }
-                if package.restart_suggested {
-                    writer
-                        .create_element("restart_suggested")
-                        .write_text_content(BytesText::from_plain_str("1"))?;
-                }
-                if package.relogin_suggested {
-                    writer
-                        .create_element("relogin_suggested")
-                        .write_text_content(BytesText::from_plain_str("1"))?;
+                // createrepo_c writes "True" rather than "1"
+                let suggested = match style {
+                    XmlStyle::Standard | XmlStyle::Legacy => "1",
+                    XmlStyle::CreaterepoC => "True",
+                };
+                for (tag, set) in [
+                    (TAG_REBOOT_SUGGESTED, package.reboot_suggested),
+                    (TAG_RESTART_SUGGESTED, package.restart_suggested),
+                    (TAG_RELOGIN_SUGGESTED, package.relogin_suggested),
+                ] {
+                    if set {
+                        writer
+                            .create_element(tag)
+                            .write_text_content(BytesText::from_plain_str(suggested))?;
+                    }
                 }
 
                 // </package>
@@ -551,7 +723,9 @@ fn write_updaterecord<W: Write>(
     writer.write_event(Event::End(updates_tag.to_end()))?;
 
     // trailing newline
-    writer.write_event(Event::Text(BytesText::from_plain_str("\n")))?;
+    if style != XmlStyle::CreaterepoC {
+        writer.write_event(Event::Text(BytesText::from_plain_str("\n")))?;
+    }
 
     // write everything out to disk - otherwise it won't happen until drop() which impedes debugging
     writer.inner().flush()?;
@@ -4,23 +4,34 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{self, BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use digest;
 use hex;
 use niffler;
 use quick_xml;
-use sha1;
-use sha2;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, BytesText, Event};
 
-use crate::{Checksum, ChecksumType, CompressionType, MetadataError};
+use crate::hasher;
+use crate::logging;
+use crate::storage::Storage;
+use crate::zchunk;
+use crate::{
+    AttributeOrder, Checksum, ChecksumType, CompressionType, DecompressionError, FallbackEncoding,
+    InvalidCharPolicy, LineEnding, MetadataError, ParseOptions, RepomdRecord, XmlFormat, XmlStyle,
+};
 
-// TODO: these Box<dyn Read> shouldn't be necessary
-fn get_digest<D: digest::Digest>(mut reader: Box<dyn Read>) -> Result<String, MetadataError> {
+fn get_digest<R: Read>(
+    mut reader: R,
+    checksum_type: ChecksumType,
+) -> Result<String, MetadataError> {
     let mut buffer = [0; 4096];
-    let mut hasher = D::new();
+    let mut hasher = hasher::new_hasher(checksum_type)
+        .expect("Cannot create digest using type Checksum::Unknown");
 
     loop {
         let count = reader.read(&mut buffer)?;
@@ -30,23 +41,125 @@ fn get_digest<D: digest::Digest>(mut reader: Box<dyn Read>) -> Result<String, Me
         hasher.update(&buffer[..count]);
     }
 
-    Ok(hex::encode(hasher.finalize().as_ref()))
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The checksum of `checksum_type` with the hex `digest`.
+pub(crate) fn checksum_from_digest(checksum_type: ChecksumType, digest: String) -> Checksum {
+    match checksum_type {
+        ChecksumType::Md5 => Checksum::Md5(digest),
+        ChecksumType::Sha1 => Checksum::Sha1(digest),
+        ChecksumType::Sha224 => Checksum::Sha224(digest),
+        ChecksumType::Sha256 => Checksum::Sha256(digest),
+        ChecksumType::Sha384 => Checksum::Sha384(digest),
+        ChecksumType::Sha512 => Checksum::Sha512(digest),
+        ChecksumType::Unknown => Checksum::Unknown(digest),
+    }
 }
 
 pub fn checksum_file(path: &Path, checksum_type: ChecksumType) -> Result<Checksum, MetadataError> {
-    let reader = Box::new(BufReader::new(File::open(path).unwrap())) as Box<dyn Read>;
+    let _span = logging::span!("checksum {}", path.display());
+    let reader = BufReader::new(File::open(path)?);
+    checksum_reader(reader, checksum_type)
+}
+
+/// Format a Unix timestamp as a UTC date and time the way advisories give them, e.g. `2022-03-01 12:30:00`.
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parse a date in one of the formats of [`is_valid_advisory_date()`] into a Unix timestamp, taking dates
+/// without a time to be at midnight UTC.
+pub(crate) fn parse_advisory_date(date: &str) -> Option<u64> {
+    if !is_valid_advisory_date(date) {
+        return None;
+    }
+    if date.bytes().all(|b| b.is_ascii_digit()) {
+        return date.parse().ok();
+    }
+    let number = |range: std::ops::Range<usize>| date.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let seconds = match date.len() > 10 {
+        true => number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?,
+        false => 0,
+    };
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days * 86400 + seconds).ok()
+}
+
+/// Whether `date` is in one of the formats dnf understands in advisories: `YYYY-MM-DD HH:MM:SS`,
+/// `YYYY-MM-DD` or a Unix timestamp.
+pub(crate) fn is_valid_advisory_date(date: &str) -> bool {
+    fn field(value: Option<&str>, len: usize, range: std::ops::RangeInclusive<u32>) -> bool {
+        value.is_some_and(|value| {
+            value.len() == len
+                && value.bytes().all(|b| b.is_ascii_digit())
+                && value.parse().is_ok_and(|value| range.contains(&value))
+        })
+    }
 
-    let result = match checksum_type {
-        ChecksumType::Md5 => Checksum::Md5(get_digest::<md5::Md5>(reader)?),
-        ChecksumType::Sha1 => Checksum::Sha1(get_digest::<sha1::Sha1>(reader)?),
-        ChecksumType::Sha224 => Checksum::Sha224(get_digest::<sha2::Sha224>(reader)?),
-        ChecksumType::Sha256 => Checksum::Sha256(get_digest::<sha2::Sha256>(reader)?),
-        ChecksumType::Sha384 => Checksum::Sha384(get_digest::<sha2::Sha384>(reader)?),
-        ChecksumType::Sha512 => Checksum::Sha512(get_digest::<sha2::Sha512>(reader)?),
-        ChecksumType::Unknown => panic!("Cannot create digest using type Checksum::Unknown"),
+    if !date.is_empty() && date.bytes().all(|b| b.is_ascii_digit()) {
+        return true;
+    }
+    let (day, time) = match date.split_once(' ') {
+        Some((day, time)) => (day, Some(time)),
+        None => (date, None),
     };
+    let mut day = day.split('-');
+    let mut valid = field(day.next(), 4, 0..=9999)
+        && field(day.next(), 2, 1..=12)
+        && field(day.next(), 2, 1..=31)
+        && day.next().is_none();
+    if let Some(time) = time {
+        let mut time = time.split(':');
+        valid &= field(time.next(), 2, 0..=23)
+            && field(time.next(), 2, 0..=59)
+            && field(time.next(), 2, 0..=60)
+            && time.next().is_none();
+    }
+    valid
+}
 
-    Ok(result)
+pub fn checksum_bytes(
+    bytes: &[u8],
+    checksum_type: ChecksumType,
+) -> Result<Checksum, MetadataError> {
+    checksum_reader(bytes, checksum_type)
+}
+
+fn checksum_reader<R: Read>(
+    reader: R,
+    checksum_type: ChecksumType,
+) -> Result<Checksum, MetadataError> {
+    let digest = get_digest(reader, checksum_type)?;
+    Ok(checksum_from_digest(checksum_type, digest))
 }
 // TODO: not efficient to iterate the file twice
 
@@ -59,26 +172,18 @@ pub fn checksum_inner_file(
     if format == niffler::Format::No {
         return Ok(None);
     }
+    let _span = logging::span!("checksum decompressed {}", path.display());
 
-    let result = match checksum_type {
-        ChecksumType::Md5 => Checksum::Md5(get_digest::<md5::Md5>(reader)?),
-        ChecksumType::Sha1 => Checksum::Sha1(get_digest::<sha1::Sha1>(reader)?),
-        ChecksumType::Sha224 => Checksum::Sha224(get_digest::<sha2::Sha224>(reader)?),
-        ChecksumType::Sha256 => Checksum::Sha256(get_digest::<sha2::Sha256>(reader)?),
-        ChecksumType::Sha384 => Checksum::Sha384(get_digest::<sha2::Sha384>(reader)?),
-        ChecksumType::Sha512 => Checksum::Sha512(get_digest::<sha2::Sha512>(reader)?),
-        ChecksumType::Unknown => panic!("Cannot create digest using type Checksum::Unknown"),
-    };
-
-    Ok(Some(result))
+    Ok(Some(checksum_reader(reader, checksum_type)?))
 }
 
 pub fn size_inner_file(path: &Path) -> Result<Option<u64>, MetadataError> {
+    let _span = logging::span!("measure decompressed size {}", path.display());
     let (reader, format) = niffler::from_path(path)?;
 
     let inner_size = match format {
         niffler::Format::No => None,
-        _ => Some(reader.bytes().into_iter().count() as u64),
+        _ => Some(io::copy(&mut BufReader::new(reader), &mut io::sink())?),
     };
 
     Ok(inner_size)
@@ -94,9 +199,944 @@ pub fn create_xml_writer<W: io::Write + Send>(inner: W) -> quick_xml::Writer<W>
     quick_xml::Writer::new_with_indent(inner, b' ', 2)
 }
 
+/// Like [`create_xml_writer()`], laying out the XML according to `format`.
+pub fn create_xml_writer_with_format<W: io::Write + Send + 'static>(
+    inner: W,
+    format: XmlFormat,
+) -> quick_xml::Writer<Box<dyn io::Write + Send>> {
+    let inner: Box<dyn io::Write + Send> = match (format.line_ending, format.attribute_order) {
+        (LineEnding::Lf, AttributeOrder::Standard) => Box::new(inner),
+        _ => Box::new(XmlFormatter::new(inner, format)),
+    };
+    match format.compact {
+        true => quick_xml::Writer::new(inner),
+        false => quick_xml::Writer::new_with_indent(inner, format.indent_char, format.indent_width),
+    }
+}
+
+/// Rewrites the XML written through it with the line endings and attribute order of an [`XmlFormat`],
+/// which quick_xml has no settings for.
+struct XmlFormatter<W> {
+    inner: W,
+    format: XmlFormat,
+    /// The markup (tag, comment, CDATA section...) being written, which is written out once it's complete
+    markup: Vec<u8>,
+    /// The quote character of the attribute value being written, if any
+    quote: Option<u8>,
+    /// The last byte written, so that existing `\r\n` line endings are left alone
+    last: u8,
+    out: Vec<u8>,
+}
+
+impl<W> XmlFormatter<W> {
+    fn new(inner: W, format: XmlFormat) -> Self {
+        XmlFormatter {
+            inner,
+            format,
+            markup: Vec::new(),
+            quote: None,
+            last: 0,
+            out: Vec::new(),
+        }
+    }
+
+    /// Add `data` to `self.out` with the line endings converted.
+    fn push(&mut self, data: &[u8]) {
+        for &byte in data {
+            if byte == b'\n' && self.format.line_ending == LineEnding::CrLf && self.last != b'\r' {
+                self.out.push(b'\r');
+            }
+            self.out.push(byte);
+            self.last = byte;
+        }
+    }
+
+    /// Whether `self.markup` is complete, having just written `>` outside of an attribute value.
+    fn markup_complete(&self) -> bool {
+        let markup = &self.markup;
+        if markup.starts_with(b"<!--") {
+            markup.len() >= 7 && markup.ends_with(b"-->")
+        } else if markup.starts_with(b"<![CDATA[") {
+            markup.ends_with(b"]]>")
+        } else if markup.starts_with(b"<?") {
+            markup.ends_with(b"?>")
+        } else {
+            true
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for XmlFormatter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        for &byte in buf {
+            if self.markup.is_empty() {
+                match byte {
+                    b'<' => self.markup.push(byte),
+                    _ => self.push(&[byte]),
+                }
+                continue;
+            }
+            self.markup.push(byte);
+            // quotes only delimit attribute values in the tags of elements
+            let in_tag = !matches!(self.markup.get(1), Some(b'!' | b'?'));
+            match (self.quote, byte) {
+                (Some(quote), _) if byte == quote => self.quote = None,
+                (Some(_), _) => (),
+                (None, b'"' | b'\'') if in_tag => self.quote = Some(byte),
+                (None, b'>') if self.markup_complete() => {
+                    let mut markup = std::mem::take(&mut self.markup);
+                    if in_tag && self.format.attribute_order == AttributeOrder::Alphabetical {
+                        markup = sort_attributes(&markup).unwrap_or(markup);
+                    }
+                    self.push(&markup);
+                }
+                _ => (),
+            }
+        }
+        self.inner.write_all(&self.out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The start tag `tag` with its attributes sorted by name, namespace declarations first. `None` if it's
+/// an end tag or can't be parsed.
+fn sort_attributes(tag: &[u8]) -> Option<Vec<u8>> {
+    let is_space = |byte: &u8| byte.is_ascii_whitespace();
+    let inner = tag.strip_prefix(b"<")?.strip_suffix(b">")?;
+    if inner.first() == Some(&b'/') {
+        return None;
+    }
+    let (inner, empty) = match inner.strip_suffix(b"/") {
+        Some(inner) => (inner, true),
+        None => (inner, false),
+    };
+    let name_end = inner.iter().position(is_space).unwrap_or(inner.len());
+    let (name, mut rest) = inner.split_at(name_end);
+
+    let mut attributes: Vec<(&[u8], &[u8])> = Vec::new();
+    loop {
+        let start = rest.iter().position(|byte| !is_space(byte));
+        let Some(start) = start else {
+            break;
+        };
+        rest = &rest[start..];
+        let equals = rest.iter().position(|byte| *byte == b'=')?;
+        let key = &rest[..equals];
+        let value = rest[equals + 1..].trim_ascii_start();
+        let quote = *value.first()?;
+        if quote != b'"' && quote != b'\'' {
+            return None;
+        }
+        let end = value[1..].iter().position(|byte| *byte == quote)? + 2;
+        attributes.push((key.trim_ascii_end(), &value[..end]));
+        rest = &value[end..];
+    }
+    attributes.sort_by_key(|(key, _)| (!(*key == b"xmlns" || key.starts_with(b"xmlns:")), *key));
+
+    let mut sorted = Vec::with_capacity(tag.len());
+    sorted.push(b'<');
+    sorted.extend_from_slice(name);
+    for (key, value) in attributes {
+        sorted.push(b' ');
+        sorted.extend_from_slice(key);
+        sorted.push(b'=');
+        sorted.extend_from_slice(value);
+    }
+    if empty {
+        sorted.push(b'/');
+    }
+    sorted.push(b'>');
+    Some(sorted)
+}
+
+/// The namespace prefixes declared on `tag`, as `(prefix, URI)` pairs.
+pub(crate) fn namespace_declarations<R: io::BufRead>(
+    reader: &quick_xml::Reader<R>,
+    tag: &BytesStart,
+) -> Result<Vec<(String, String)>, MetadataError> {
+    let mut namespaces = Vec::new();
+    for attr in tag.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        if let Some(prefix) = attr.key.strip_prefix(b"xmlns:") {
+            namespaces.push((
+                String::from_utf8_lossy(prefix).into_owned(),
+                attr.unescape_and_decode_value(reader)?,
+            ));
+        }
+    }
+    Ok(namespaces)
+}
+
+/// Read the rest of the element whose start tag `start` was just read, and serialize the whole element.
+///
+/// Namespace prefixes used in the element which are declared in `namespaces` (i.e. on the root element)
+/// are declared again on the element itself, so that it can be written into another file.
+pub(crate) fn read_element_xml<R: io::BufRead>(
+    reader: &mut quick_xml::Reader<R>,
+    start: &BytesStart,
+    namespaces: &[(String, String)],
+) -> Result<String, MetadataError> {
+    let mut events = vec![Event::Start(start.to_owned())];
+    let mut buf = Vec::new();
+    let mut depth = 1;
+    while depth > 0 {
+        let event = reader.read_event(&mut buf)?.into_owned();
+        buf.clear();
+        match &event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Eof => {
+                let name = String::from_utf8_lossy(start.name()).into_owned();
+                return Err(quick_xml::Error::UnexpectedEof(name).into());
+            }
+            _ => (),
+        }
+        // the reader expands empty elements, collapse them again
+        match (event, events.last()) {
+            (Event::End(_), Some(Event::Start(_))) => {
+                if let Some(Event::Start(e)) = events.pop() {
+                    events.push(Event::Empty(e));
+                }
+            }
+            (event, _) => events.push(event),
+        }
+    }
+
+    let mut prefixes = Vec::new();
+    for event in &events {
+        if let Event::Start(e) | Event::Empty(e) = event {
+            let names = e.attributes().flatten().map(|a| a.key).chain([e.name()]);
+            for name in names {
+                if let Some((prefix, _)) = std::str::from_utf8(name)?.split_once(':') {
+                    if prefix != "xmlns" && prefix != "xml" && !prefixes.iter().any(|p| p == prefix)
+                    {
+                        prefixes.push(prefix.to_owned());
+                    }
+                }
+            }
+        }
+    }
+    if let Event::Start(e) | Event::Empty(e) = &mut events[0] {
+        for (prefix, uri) in namespaces {
+            let key = format!("xmlns:{}", prefix);
+            let declared = e.attributes().flatten().any(|a| a.key == key.as_bytes());
+            if prefixes.contains(prefix) && !declared {
+                e.push_attribute((key.as_str(), uri.as_str()));
+            }
+        }
+    }
+
+    let mut writer = quick_xml::Writer::new(Vec::new());
+    for event in &events {
+        writer.write_event(event)?;
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| e.utf8_error().into())
+}
+
+/// The namespace URI (if it's declared on the element itself) and the local name of the element
+/// serialized as `xml` by [`read_element_xml()`].
+pub(crate) fn element_namespace(xml: &str) -> Option<(Option<String>, String)> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let (Ok(Event::Start(e)) | Ok(Event::Empty(e))) = reader.read_event(&mut buf) else {
+        return None;
+    };
+    let name = std::str::from_utf8(e.name()).ok()?;
+    let (declaration, local) = match name.split_once(':') {
+        Some((prefix, local)) => (format!("xmlns:{}", prefix), local),
+        None => ("xmlns".to_owned(), name),
+    };
+    let uri = e
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key == declaration.as_bytes())
+        .and_then(|attr| attr.unescape_and_decode_value(&reader).ok());
+    Some((uri, local.to_owned()))
+}
+
+/// Write an element serialized by [`read_element_xml()`].
+pub(crate) fn write_element_xml<W: io::Write>(
+    writer: &mut quick_xml::Writer<W>,
+    xml: &str,
+) -> Result<(), MetadataError> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Eof => return Ok(()),
+            event => writer.write_event(event)?,
+        }
+        buf.clear();
+    }
+}
+
+/// Write the start tag of the root element `tag` without indenting the elements inside it, which is how
+/// createrepo_c lays out primary.xml, filelists.xml and other.xml.
+pub(crate) fn write_unindented_start<W: io::Write>(
+    writer: &mut quick_xml::Writer<W>,
+    tag: &BytesStart,
+) -> Result<(), MetadataError> {
+    writer.write_indent()?;
+    writer.write(b"<")?;
+    writer.write(tag)?;
+    writer.write(b">")?;
+    Ok(())
+}
+
+/// Whether a flag such as `pre="1"` or `<reboot_suggested>True</reboot_suggested>` is set. Producers spell
+/// these differently (`1`, `True`, `true`, `yes`, or just an empty element), so anything but `0`, `false`,
+/// `no` and `off` in any case counts as set.
+pub(crate) fn parse_flag(value: &str) -> bool {
+    let value = value.trim();
+    !["0", "false", "no", "off"]
+        .iter()
+        .any(|unset| value.eq_ignore_ascii_case(unset))
+}
+
+/// Text content, escaped the way `style` calls for.
+pub(crate) fn text(style: XmlStyle, value: &str) -> BytesText<'_> {
+    match style {
+        XmlStyle::Standard | XmlStyle::Legacy => BytesText::from_plain_str(value),
+        XmlStyle::CreaterepoC => {
+            BytesText::from_escaped(libxml2_escape(value.as_bytes(), false, false))
+        }
+    }
+}
+
+/// An attribute, with the value escaped the way `style` calls for.
+pub(crate) fn attribute<'a, K, V>(style: XmlStyle, key: &'a K, value: &'a V) -> Attribute<'a>
+where
+    K: AsRef<[u8]> + ?Sized,
+    V: AsRef<[u8]> + ?Sized,
+{
+    let value = match style {
+        XmlStyle::Standard | XmlStyle::Legacy => quick_xml::escape::escape(value.as_ref()),
+        XmlStyle::CreaterepoC => libxml2_escape(value.as_ref(), true, false),
+    };
+    Attribute {
+        key: key.as_ref(),
+        value,
+    }
+}
+
+/// The type and value of `checksum`, with the type named the way `style` calls for.
+pub(crate) fn checksum_values(
+    style: XmlStyle,
+    checksum: &Checksum,
+) -> Result<(&str, &str), MetadataError> {
+    match (style, checksum.to_values()?) {
+        (XmlStyle::Legacy, ("sha1", value)) => Ok(("sha", value)),
+        (_, values) => Ok(values),
+    }
+}
+
+/// Like [`attribute()`], for the attributes of packages. createrepo_c writes each package as a node which
+/// doesn't belong to a document, and libxml2 writes the non-ASCII characters in the attributes of such
+/// nodes as character references.
+pub(crate) fn package_attribute<'a, K, V>(
+    style: XmlStyle,
+    key: &'a K,
+    value: &'a V,
+) -> Attribute<'a>
+where
+    K: AsRef<[u8]> + ?Sized,
+    V: AsRef<[u8]> + ?Sized,
+{
+    match style {
+        XmlStyle::Standard | XmlStyle::Legacy => attribute(style, key, value),
+        XmlStyle::CreaterepoC => Attribute {
+            key: key.as_ref(),
+            value: libxml2_escape(value.as_ref(), true, true),
+        },
+    }
+}
+
+/// Escape `value` (UTF-8) the way libxml2 does, as text content or as an attribute value. If `ascii` is
+/// set, non-ASCII characters are written as character references.
+fn libxml2_escape(value: &[u8], attribute: bool, ascii: bool) -> Cow<'_, [u8]> {
+    let needs_escape = |b: u8| match b {
+        b'<' | b'>' | b'&' | b'\r' => true,
+        b'"' | b'\n' | b'\t' => attribute,
+        _ => ascii && b >= 0x80,
+    };
+    if !value.iter().any(|&b| needs_escape(b)) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = Vec::with_capacity(value.len() + 16);
+    let mut pos = 0;
+    while pos < value.len() {
+        let b = value[pos];
+        pos += 1;
+        if !needs_escape(b) {
+            escaped.push(b);
+            continue;
+        }
+        match b {
+            b'<' => escaped.extend_from_slice(b"&lt;"),
+            b'>' => escaped.extend_from_slice(b"&gt;"),
+            b'&' => escaped.extend_from_slice(b"&amp;"),
+            b'\r' => escaped.extend_from_slice(b"&#13;"),
+            b'"' => escaped.extend_from_slice(b"&quot;"),
+            b'\n' => escaped.extend_from_slice(b"&#10;"),
+            b'\t' => escaped.extend_from_slice(b"&#9;"),
+            _ => {
+                let len = match b {
+                    0xf0.. => 4,
+                    0xe0.. => 3,
+                    _ => 2,
+                };
+                let char = value
+                    .get(pos - 1..pos - 1 + len)
+                    .and_then(|c| std::str::from_utf8(c).ok())
+                    .and_then(|c| c.chars().next());
+                match char {
+                    Some(c) => {
+                        escaped.extend_from_slice(format!("&#x{:X};", c as u32).as_bytes());
+                        pos += len - 1;
+                    }
+                    None => escaped.push(b),
+                }
+            }
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Deals with the characters which aren't allowed in XML in the data read from or written to `inner`,
+/// according to the [`InvalidCharPolicy`], decoding bytes which aren't valid UTF-8 with the
+/// [`FallbackEncoding`] if there is one.
+pub(crate) struct XmlCharFilter<T> {
+    inner: T,
+    policy: InvalidCharPolicy,
+    fallback: Option<FallbackEncoding>,
+    /// Offset of the next unfiltered byte, for error messages
+    offset: u64,
+    /// The start of a character split across reads or writes
+    partial: Vec<u8>,
+    /// Filtered data which hasn't been read yet
+    filtered: Vec<u8>,
+    pos: usize,
+}
+
+impl<T> XmlCharFilter<T> {
+    pub(crate) fn new(inner: T, policy: InvalidCharPolicy) -> Self {
+        XmlCharFilter {
+            inner,
+            policy,
+            fallback: None,
+            offset: 0,
+            partial: Vec::new(),
+            filtered: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn with_fallback(self, fallback: Option<FallbackEncoding>) -> Self {
+        XmlCharFilter { fallback, ..self }
+    }
+
+    /// Filter `data`, following on from any partial character, into `self.filtered`. If `end` isn't set an
+    /// incomplete character at the end is kept for later.
+    fn filter(&mut self, data: &[u8], end: bool) -> io::Result<()> {
+        let mut input = std::mem::take(&mut self.partial);
+        input.extend_from_slice(data);
+        let consumed = filter_xml_chars(
+            &input,
+            end,
+            (self.policy, self.fallback),
+            self.offset,
+            &mut self.filtered,
+        )?;
+        self.offset += consumed as u64;
+        self.partial = input.split_off(consumed);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for XmlCharFilter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.filtered.len() {
+            self.filtered.clear();
+            self.pos = 0;
+            let mut chunk = [0; 8192];
+            let count = self.inner.read(&mut chunk)?;
+            if count == 0 && self.partial.is_empty() {
+                return Ok(0);
+            }
+            self.filter(&chunk[..count], count == 0)?;
+        }
+        let count = buf.len().min(self.filtered.len() - self.pos);
+        buf[..count].copy_from_slice(&self.filtered[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+impl<W: io::Write> io::Write for XmlCharFilter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.filtered.clear();
+        self.filter(buf, false)?;
+        self.inner.write_all(&self.filtered)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copy `input` to `out`, dealing with the characters which aren't allowed in XML according to `policy`,
+/// or decoding the bytes which aren't valid UTF-8 with `fallback` if it's set. `offset` is the position of `input` in the whole file. Returns the number of bytes consumed, which is
+/// less than the length of `input` if it ends with an incomplete character and `end` isn't set.
+fn filter_xml_chars(
+    input: &[u8],
+    end: bool,
+    (policy, fallback): (InvalidCharPolicy, Option<FallbackEncoding>),
+    offset: u64,
+    out: &mut Vec<u8>,
+) -> io::Result<usize> {
+    let invalid = |bytes: &[u8], pos: usize, out: &mut Vec<u8>| match policy {
+        InvalidCharPolicy::Strip => Ok(()),
+        InvalidCharPolicy::Replace => {
+            out.extend_from_slice("\u{FFFD}".as_bytes());
+            Ok(())
+        }
+        InvalidCharPolicy::Error => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "bytes {:02X?} at offset {} aren't a character allowed in XML",
+                bytes,
+                offset + pos as u64
+            ),
+        )),
+    };
+    let not_utf8 = |bytes: &[u8], pos: usize, out: &mut Vec<u8>| match fallback {
+        Some(encoding) => {
+            for byte in bytes {
+                out.extend_from_slice(encoding.decode(*byte).encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            Ok(())
+        }
+        None => invalid(bytes, pos, out),
+    };
+
+    let mut pos = 0;
+    while pos < input.len() {
+        let (valid, error) = match std::str::from_utf8(&input[pos..]) {
+            Ok(_) => (input.len() - pos, None),
+            Err(e) => (e.valid_up_to(), Some(e.error_len())),
+        };
+
+        // valid UTF-8, which may still contain characters that aren't allowed
+        let stop = pos + valid;
+        let mut start = pos;
+        while pos < stop {
+            let bad_len = match input[pos] {
+                b'\t' | b'\n' | b'\r' => 0,
+                0..=0x1f => 1,
+                // U+FFFE, U+FFFF
+                0xef if input[pos + 1] == 0xbf && input[pos + 2] >= 0xbe => 3,
+                _ => 0,
+            };
+            if bad_len == 0 {
+                pos += 1;
+                continue;
+            }
+            out.extend_from_slice(&input[start..pos]);
+            invalid(&input[pos..pos + bad_len], pos, out)?;
+            pos += bad_len;
+            start = pos;
+        }
+        out.extend_from_slice(&input[start..stop]);
+
+        match error {
+            None => (),
+            Some(Some(len)) => {
+                not_utf8(&input[pos..pos + len], pos, out)?;
+                pos += len;
+            }
+            Some(None) if end => {
+                not_utf8(&input[pos..], pos, out)?;
+                pos = input.len();
+            }
+            Some(None) => break,
+        }
+    }
+    Ok(pos)
+}
+
 pub fn reader_from_file(path: &Path) -> Result<Box<dyn io::Read + Send>, MetadataError> {
-    let (compress_reader, _compression) = niffler::send::from_path(path)?;
-    Ok(compress_reader)
+    decompressing_reader(path, false)
+}
+
+/// Decompress `reader` according to the compression (gzip, xz, bzip2, zstd or zchunk) its contents
+/// start with. Unlike [`reader_from_file()`] this works for sources without a file name, such as pipes
+/// or HTTP response bodies.
+pub fn reader_from<R: io::Read + Send + 'static>(
+    reader: R,
+) -> Result<Box<dyn io::Read + Send>, MetadataError> {
+    decompress(Box::new(reader), false)
+}
+
+/// Open `path` for reading, decompressing it if it's compressed, regardless of its extension. Gzip
+/// files may consist of several streams, and anything following the last one is skipped if
+/// `ignore_trailing_data` is set.
+pub(crate) fn decompressing_reader(
+    path: &Path,
+    ignore_trailing_data: bool,
+) -> Result<Box<dyn io::Read + Send>, MetadataError> {
+    let file = File::open(path).map_err(niffler::Error::IOError)?;
+    logging::trace!("opened {} for reading", path.display());
+    decompress(Box::new(BufReader::new(file)), ignore_trailing_data)
+}
+
+/// Like [`reader_from_file()`], for the file at `path` in `storage`.
+pub(crate) fn reader_from_storage(
+    storage: &dyn Storage,
+    path: &Path,
+) -> Result<Box<dyn io::Read + Send>, MetadataError> {
+    decompressing_storage_reader(storage, path, false)
+}
+
+fn decompressing_storage_reader(
+    storage: &dyn Storage,
+    path: &Path,
+    ignore_trailing_data: bool,
+) -> Result<Box<dyn io::Read + Send>, MetadataError> {
+    let file = storage.open(path).map_err(niffler::Error::IOError)?;
+    logging::trace!("opened {} for reading", path.display());
+    decompress(Box::new(BufReader::new(file)), ignore_trailing_data)
+}
+
+fn decompress(
+    reader: Box<dyn io::Read + Send>,
+    ignore_trailing_data: bool,
+) -> Result<Box<dyn io::Read + Send>, MetadataError> {
+    Ok(sniff_and_decompress(reader, ignore_trailing_data)?.0)
+}
+
+/// The size and checksum of the decompressed contents of `reader`, or `None` if it isn't compressed.
+pub(crate) fn measure_decompressed(
+    reader: Box<dyn io::Read + Send>,
+    checksum_type: ChecksumType,
+) -> Result<Option<(u64, Checksum)>, MetadataError> {
+    let (reader, compressed) = sniff_and_decompress(reader, false)?;
+    if !compressed {
+        return Ok(None);
+    }
+    let mut reader = CountingReader {
+        inner: reader,
+        count: 0,
+    };
+    let checksum = checksum_reader(&mut reader, checksum_type)?;
+    Ok(Some((reader.count, checksum)))
+}
+
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+/// Computes the size and checksum of everything read through it.
+#[cfg(feature = "read_rpm")]
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    checksum_type: ChecksumType,
+    hasher: Box<dyn hasher::Hasher>,
+    count: u64,
+}
+
+#[cfg(feature = "read_rpm")]
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R, checksum_type: ChecksumType) -> Self {
+        let hasher = hasher::new_hasher(checksum_type)
+            .expect("Cannot create digest using type Checksum::Unknown");
+        HashingReader {
+            inner,
+            checksum_type,
+            hasher,
+            count: 0,
+        }
+    }
+
+    /// The size and checksum of the data read so far.
+    pub(crate) fn finish(self) -> (u64, Checksum) {
+        let digest = hex::encode(self.hasher.finalize());
+        (self.count, checksum_from_digest(self.checksum_type, digest))
+    }
+}
+
+#[cfg(feature = "read_rpm")]
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.hasher.update(&buf[..count]);
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+/// Decompress `reader`, returning whether it was compressed at all.
+fn sniff_and_decompress(
+    mut reader: Box<dyn io::Read + Send>,
+    ignore_trailing_data: bool,
+) -> Result<(Box<dyn io::Read + Send>, bool), MetadataError> {
+    // niffler doesn't know about zchunk
+    let mut magic = Vec::new();
+    (&mut reader)
+        .take(zchunk::ZCK_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let is_zchunk = magic == zchunk::ZCK_MAGIC;
+    let reader: Box<dyn io::Read + Send> = Box::new(io::Cursor::new(magic).chain(reader));
+    if is_zchunk {
+        logging::trace!("compression: zchunk");
+        let reader = zchunk::ZchunkReader::new(reader).map_err(decompression_error)?;
+        return Ok((Box::new(DecompressionErrors(reader)), true));
+    }
+
+    let (reader, compression) = niffler::send::sniff(reader)?;
+    logging::trace!("compression: {:?}", compression);
+    let reader: Box<dyn io::Read + Send> = match compression {
+        niffler::send::compression::Format::No => return Ok((reader, false)),
+        niffler::send::compression::Format::Gzip => Box::new(MultiGzipReader::new(
+            BufReader::new(reader),
+            ignore_trailing_data,
+        )),
+        _ => niffler::send::get_reader(reader)?.0,
+    };
+    Ok((Box::new(DecompressionErrors(reader)), true))
+}
+
+/// Open the file of `record` in the repository at `base` in `storage`, decompressing it and checking it
+/// against the sizes and checksums of the record as it's read, see [`RepomdRecord::open()`].
+pub(crate) fn verified_record_reader(
+    storage: &dyn Storage,
+    base: &Path,
+    record: &RepomdRecord,
+    ignore_trailing_data: bool,
+) -> Result<Box<dyn BufRead + Send>, MetadataError> {
+    let path = base.join(&record.location_href);
+    let href = record.location_href.display().to_string();
+    let file = storage.open(&path).map_err(niffler::Error::IOError)?;
+    logging::trace!("opened {} for reading", path.display());
+    let file = Arc::new(Mutex::new(VerifyingReader::new(
+        file,
+        href.clone(),
+        &record.checksum,
+        record.size,
+    )?));
+    let (reader, compressed) = sniff_and_decompress(
+        Box::new(BufReader::new(SharedReader(file.clone()))),
+        ignore_trailing_data,
+    )?;
+    let reader: Box<dyn Read + Send> = match &record.open_checksum {
+        Some(open_checksum) if compressed => Box::new(VerifyingReader::new(
+            reader,
+            href,
+            open_checksum,
+            record.open_size,
+        )?),
+        _ => reader,
+    };
+    Ok(Box::new(BufReader::new(RecordReader { reader, file })))
+}
+
+/// The decompressed contents of a file, which reads the rest of the file once they end, so that all of it
+/// is verified even if the decompressor stops short of the end.
+struct RecordReader<R> {
+    reader: Box<dyn Read + Send>,
+    file: Arc<Mutex<VerifyingReader<R>>>,
+}
+
+impl<R: Read> Read for RecordReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        if count == 0 && !buf.is_empty() {
+            io::copy(&mut *self.file.lock().unwrap(), &mut io::sink())?;
+        }
+        Ok(count)
+    }
+}
+
+struct SharedReader<R>(Arc<Mutex<R>>);
+
+impl<R: Read> Read for SharedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+/// Checks the size and checksum of everything read through it against those expected once the end is
+/// reached, failing the last read if they don't match.
+struct VerifyingReader<R> {
+    inner: R,
+    name: String,
+    expected: Checksum,
+    expected_size: Option<u64>,
+    /// `None` once verified
+    hasher: Option<Box<dyn hasher::Hasher>>,
+    size: u64,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    /// Fails if there is no hasher for the type of `expected`, as the contents couldn't be verified.
+    fn new(
+        inner: R,
+        name: String,
+        expected: &Checksum,
+        expected_size: Option<u64>,
+    ) -> Result<Self, MetadataError> {
+        let Some(hasher) = hasher::new_hasher(expected.checksum_type()) else {
+            let checksum_type = match expected {
+                Checksum::Empty => "empty",
+                _ => expected.to_values()?.0,
+            };
+            return Err(MetadataError::UnsupportedChecksumTypeError(
+                checksum_type.to_owned(),
+            ));
+        };
+        Ok(VerifyingReader {
+            inner,
+            name,
+            expected: expected.clone(),
+            expected_size,
+            hasher: Some(hasher),
+            size: 0,
+        })
+    }
+
+    fn verify(&mut self) -> Result<(), MetadataError> {
+        if let Some(expected) = self.expected_size.filter(|expected| *expected != self.size) {
+            return Err(MetadataError::InconsistentMetadataError(format!(
+                "{} is {} bytes, but repomd.xml says {}",
+                self.name, self.size, expected
+            )));
+        }
+        let Some(hasher) = self.hasher.take() else {
+            return Ok(());
+        };
+        let (_, expected) = self.expected.to_values()?;
+        let actual = hex::encode(hasher.finalize());
+        if actual != expected {
+            return Err(MetadataError::ChecksumMismatchError(
+                self.name.clone(),
+                expected.to_owned(),
+                actual,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if count > 0 {
+            self.size += count as u64;
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&buf[..count]);
+            }
+        } else if !buf.is_empty() {
+            self.verify()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        Ok(count)
+    }
+}
+
+/// The first byte of a gzip member.
+const GZIP_MAGIC: u8 = 0x1f;
+
+/// Decompresses each of the gzip streams ("members") in a row, as produced by `cat a.gz b.gz`.
+struct MultiGzipReader<R: BufRead> {
+    decoder: Option<flate2::bufread::GzDecoder<R>>,
+    ignore_trailing_data: bool,
+}
+
+impl<R: BufRead> MultiGzipReader<R> {
+    fn new(inner: R, ignore_trailing_data: bool) -> Self {
+        MultiGzipReader {
+            decoder: Some(flate2::bufread::GzDecoder::new(inner)),
+            ignore_trailing_data,
+        }
+    }
+}
+
+impl<R: BufRead> Read for MultiGzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(decoder) = &mut self.decoder {
+            let count = decoder.read(buf)?;
+            if count > 0 || buf.is_empty() {
+                return Ok(count);
+            }
+            // the end of a member, which may be followed by another one
+            let mut inner = self.decoder.take().unwrap().into_inner();
+            let next = inner.fill_buf()?;
+            if next.is_empty() {
+                break;
+            } else if next[0] == GZIP_MAGIC {
+                self.decoder = Some(flate2::bufread::GzDecoder::new(inner));
+            } else if !self.ignore_trailing_data {
+                return Err(DecompressionError::TrailingData.into());
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Reports the errors of a decompressor as [`DecompressionError`]s.
+struct DecompressionErrors<R: Read>(R);
+
+impl<R: Read> Read for DecompressionErrors<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(decompression_error)
+    }
+}
+
+fn decompression_error(e: io::Error) -> io::Error {
+    if e.get_ref()
+        .is_some_and(|inner| inner.is::<DecompressionError>() || inner.is::<MetadataError>())
+    {
+        return e;
+    }
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => DecompressionError::Truncated.into(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+            DecompressionError::Corrupt(e.to_string()).into()
+        }
+        _ => e,
+    }
+}
+
+/// The number of the line (counting from 1) containing byte `offset` of `reader`.
+pub(crate) fn line_at_offset<R: Read>(reader: R, offset: usize) -> io::Result<usize> {
+    let mut reader = BufReader::new(reader.take(offset as u64));
+    let mut line = 1;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(line);
+        }
+        line += buf.iter().filter(|&&b| b == b'\n').count();
+        let len = buf.len();
+        reader.consume(len);
+    }
 }
 
 pub fn xml_reader_from_file(
@@ -106,6 +1146,42 @@ pub fn xml_reader_from_file(
     Ok(create_xml_reader(BufReader::new(compress_reader)))
 }
 
+/// Like [`xml_reader_from_file()`], for a (possibly compressed) source without a file name, see
+/// [`reader_from()`].
+pub fn xml_reader_from<R: io::Read + Send + 'static>(
+    reader: R,
+) -> Result<quick_xml::Reader<BufReader<Box<dyn io::Read + Send>>>, MetadataError> {
+    let compress_reader = reader_from(reader)?;
+    Ok(create_xml_reader(BufReader::new(compress_reader)))
+}
+
+/// Like [`xml_reader_from_file()`], for the file at `path` in `storage`, dealing with characters which
+/// aren't allowed in XML and bytes which aren't valid UTF-8 according to `options` (unless the input is
+/// trusted).
+pub(crate) fn filtered_xml_reader_from_storage(
+    storage: &dyn Storage,
+    path: &Path,
+    options: ParseOptions,
+) -> Result<quick_xml::Reader<BufReader<Box<dyn io::Read + Send>>>, MetadataError> {
+    let compress_reader =
+        decompressing_storage_reader(storage, path, options.ignore_trailing_data)?;
+    Ok(filtered_xml_reader(compress_reader, options))
+}
+
+fn filtered_xml_reader(
+    compress_reader: Box<dyn io::Read + Send>,
+    options: ParseOptions,
+) -> quick_xml::Reader<BufReader<Box<dyn io::Read + Send>>> {
+    if options.trusted_input {
+        return create_xml_reader(BufReader::new(compress_reader));
+    }
+    let filter: Box<dyn io::Read + Send> = Box::new(
+        XmlCharFilter::new(compress_reader, options.invalid_chars)
+            .with_fallback(options.fallback_encoding),
+    );
+    create_xml_reader(BufReader::new(filter))
+}
+
 // TODO: maybe split this up so that it just configures the writer, but takes a Box<dyn Write> which can be pre-configured with compression
 pub fn xml_writer_for_path(
     path: &Path,
@@ -116,11 +1192,36 @@ pub fn xml_writer_for_path(
     Ok((filename, writer))
 }
 
+/// Like [`xml_writer_for_path()`], dealing with characters which aren't allowed in XML according to `policy`
+/// and laying out the XML according to `format`.
+pub(crate) fn filtered_xml_writer_for_path(
+    path: &Path,
+    compression: CompressionType,
+    policy: InvalidCharPolicy,
+    format: XmlFormat,
+) -> Result<(PathBuf, quick_xml::Writer<Box<dyn io::Write + Send>>), MetadataError> {
+    let (filename, inner_writer) = writer_to_file(path, compression)?;
+    let filter = XmlCharFilter::new(inner_writer, policy);
+    Ok((filename, create_xml_writer_with_format(filter, format)))
+}
+
+/// Refuse `href` if it would end up outside of the directory it's relative to, i.e. if it isn't made of
+/// normal components only. `field` is what the error calls it.
+pub(crate) fn check_href(href: &Path, field: &'static str) -> Result<(), MetadataError> {
+    match href.components().all(|c| matches!(c, Component::Normal(_))) {
+        true => Ok(()),
+        false => Err(MetadataError::InvalidFieldError(
+            field,
+            href.display().to_string(),
+        )),
+    }
+}
+
 pub fn apply_compression_suffix(path: &Path, compression: CompressionType) -> PathBuf {
     let extension = compression.to_file_extension();
     // TODO: easier way to do this?
     let mut filename = path.as_os_str().to_owned();
-    filename.push(&extension);
+    filename.push(extension);
     PathBuf::from(&filename)
 }
 
@@ -128,6 +1229,44 @@ pub fn writer_to_file(
     path: &Path,
     compression: CompressionType,
 ) -> Result<(PathBuf, Box<dyn io::Write + Send>), MetadataError> {
+    writer_to_file_with_level(path, compression, 9)
+}
+
+/// Like [`writer_to_file()`], compressing at `level`. Levels above what the type of compression supports
+/// (9 for all but zstd, which goes up to 21) are treated as its highest level.
+pub fn writer_to_file_with_level(
+    path: &Path,
+    compression: CompressionType,
+    level: u32,
+) -> Result<(PathBuf, Box<dyn io::Write + Send>), MetadataError> {
+    let highest = match compression {
+        CompressionType::Zstd => 21,
+        _ => 9,
+    };
+    let level = match level.min(highest) {
+        0 => niffler::Level::Zero,
+        1 => niffler::Level::One,
+        2 => niffler::Level::Two,
+        3 => niffler::Level::Three,
+        4 => niffler::Level::Four,
+        5 => niffler::Level::Five,
+        6 => niffler::Level::Six,
+        7 => niffler::Level::Seven,
+        8 => niffler::Level::Eight,
+        9 => niffler::Level::Nine,
+        10 => niffler::Level::Ten,
+        11 => niffler::Level::Eleven,
+        12 => niffler::Level::Twelve,
+        13 => niffler::Level::Thirteen,
+        14 => niffler::Level::Fourteen,
+        15 => niffler::Level::Fifteen,
+        16 => niffler::Level::Sixteen,
+        17 => niffler::Level::Seventeen,
+        18 => niffler::Level::Eighteen,
+        19 => niffler::Level::Nineteen,
+        20 => niffler::Level::Twenty,
+        _ => niffler::Level::TwentyOne,
+    };
     let filename = apply_compression_suffix(path, compression);
     let format = match compression {
         CompressionType::None => niffler::send::compression::Format::No,
@@ -136,9 +1275,92 @@ pub fn writer_to_file(
         CompressionType::Bz2 => niffler::send::compression::Format::Bzip,
         CompressionType::Zstd => niffler::send::compression::Format::Zstd,
     };
-    let writer = niffler::send::to_path(&filename, format, niffler::Level::Nine)?;
+    let writer = niffler::send::to_path(&filename, format, level)?;
+    logging::trace!(
+        "opened {} for writing (compression: {:?})",
+        filename.display(),
+        compression
+    );
     Ok((filename, writer))
 }
 
 #[cfg(feature = "read_rpm")]
-pub use crate::package::rpm_parsing::load_rpm_package;
+pub use crate::package::rpm_parsing::{
+    load_rpm_bytes, load_rpm_directory, load_rpm_directory_with_checkpoint,
+    load_rpm_directory_with_hooks, load_rpm_package, load_rpm_reader, FailurePolicy,
+    PackageFailure, PayloadEntry, PayloadReader, QuarantineCallback, RpmDirectoryReport,
+};
+#[cfg(feature = "read_rpm")]
+pub use crate::signatures::rpm_signature_key_id;
+
+/// Split a `<name>-[<epoch>:]<version>-<release>.<arch>` string into its fields.
+pub(crate) fn split_nevra(nevra: &str) -> Option<(&str, &str, &str, &str, &str)> {
+    let (nevr, arch) = nevra.rsplit_once('.')?;
+    let (nev, release) = nevr.rsplit_once('-')?;
+    let (name, ev) = nev.rsplit_once('-')?;
+    let (epoch, version) = ev.split_once(':').unwrap_or(("", ev));
+    Some((name, epoch, version, release, arch))
+}
+
+/// Match `text` against a shell-style glob `pattern`.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // position to backtrack to after the last `*`: (pattern index, text index)
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern, p, text[t]),
+            Some(c) if *c == text[t] => Some(p + 1),
+            _ => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Match `c` against the character class starting at `pattern[start]` (a `[`). Returns the index after
+/// the class if it matches.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut idx = start + 1;
+    let negated = matches!(pattern.get(idx), Some('!' | '^'));
+    if negated {
+        idx += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while let Some(&class_char) = pattern.get(idx) {
+        if class_char == ']' && !first {
+            return (matched != negated).then_some(idx + 1);
+        }
+        if pattern.get(idx + 1) == Some(&'-') && pattern.get(idx + 2).is_some_and(|e| *e != ']') {
+            matched |= class_char <= c && c <= pattern[idx + 2];
+            idx += 3;
+        } else {
+            matched |= class_char == c;
+            idx += 1;
+        }
+        first = false;
+    }
+    // an unterminated class matches a literal `[`
+    (c == '[').then_some(start + 1)
+}
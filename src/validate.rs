@@ -0,0 +1,660 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::metadata::{METADATA_FILELISTS, METADATA_OTHER, METADATA_PRIMARY};
+use crate::storage::Storage;
+use crate::{
+    utils, Comps, CompsPackageType, FilelistsXml, MetadataError, Modules, OtherXml, Package,
+    ParseOptions, PrimaryXml, RepomdData, Repository, UpdateCollectionPackage,
+};
+
+/// Timestamps this far past the current time are assumed to be wrong rather than the result of clock skew.
+const MAX_CLOCK_SKEW: u64 = 24 * 60 * 60;
+
+/// How serious a problem found by validation is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// Worth knowing about, but harmless
+    Info,
+    /// Clients can use the repository, but may behave oddly
+    Warning,
+    /// Clients are likely to fail or to misbehave
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// The checks made by [`Repository::validate()`] and [`RepositoryReader::validate()`](crate::RepositoryReader::validate).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ValidationCheck {
+    /// `repomd.xml` has no record for a required metadata file
+    MissingRecord,
+    /// A `repomd.xml` record points at a file which doesn't exist
+    DanglingRecord,
+    /// A file in `repodata/` which isn't referenced by `repomd.xml`
+    UnreferencedFile,
+    /// The package count in the header of a metadata file doesn't match the packages it contains, or the
+    /// metadata files contain different numbers of packages
+    CountMismatch,
+    /// A package of `primary.xml` is missing from `filelists.xml` or `other.xml`, or the other way around
+    MissingPackage,
+    /// An epoch, version or release which RPM wouldn't accept
+    InvalidEvr,
+    /// A timestamp which isn't set, or which is in the future
+    TimestampOutOfRange,
+    /// A comps group lists a package which isn't in the repository
+    CompsMissingPackage,
+    /// A conditional package of a comps group depends on a package which isn't in the repository, so it's
+    /// never installed
+    CompsUnmatchableConditional,
+    /// A comps category or environment lists a group which doesn't exist
+    CompsMissingGroup,
+    /// An advisory is for a module stream which isn't in `modules.yaml`
+    AdvisoryUnknownModule,
+    /// A package of an advisory isn't an artifact of the module stream the advisory is for, or is an
+    /// artifact of a module stream although the advisory isn't for one
+    AdvisoryModuleMismatch,
+}
+
+impl ValidationCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationCheck::MissingRecord => "missing-record",
+            ValidationCheck::DanglingRecord => "dangling-record",
+            ValidationCheck::UnreferencedFile => "unreferenced-file",
+            ValidationCheck::CountMismatch => "count-mismatch",
+            ValidationCheck::MissingPackage => "missing-package",
+            ValidationCheck::InvalidEvr => "invalid-evr",
+            ValidationCheck::TimestampOutOfRange => "timestamp-out-of-range",
+            ValidationCheck::CompsMissingPackage => "comps-missing-package",
+            ValidationCheck::CompsUnmatchableConditional => "comps-unmatchable-conditional",
+            ValidationCheck::CompsMissingGroup => "comps-missing-group",
+            ValidationCheck::AdvisoryUnknownModule => "advisory-unknown-module",
+            ValidationCheck::AdvisoryModuleMismatch => "advisory-module-mismatch",
+        }
+    }
+}
+
+/// A problem found by validation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub check: ValidationCheck,
+    /// The type of metadata, e.g. `primary`, if the problem concerns a particular file
+    pub metadata: Option<String>,
+    /// The package (or comps group, category or environment) the problem was found in, if any
+    pub entry: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    /// e.g. `error[missing-package]: filelists: foo-1.0-1.noarch: package ... is missing from filelists`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: ", self.severity.as_str(), self.check.as_str())?;
+        if let Some(metadata) = &self.metadata {
+            write!(f, "{}: ", metadata)?;
+        }
+        if let Some(entry) = &self.entry {
+            write!(f, "{}: ", entry)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The problems found by [`Repository::validate()`] or
+/// [`RepositoryReader::validate()`](crate::RepositoryReader::validate), in the order they were found.
+///
+/// Its `Display` output lists one issue per line followed by a summary, suitable for CI logs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Whether no errors were found. Warnings and info don't count.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.with_severity(Severity::Warning)
+    }
+
+    pub fn infos(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.with_severity(Severity::Info)
+    }
+
+    fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(move |issue| issue.severity == severity)
+    }
+
+    fn add(
+        &mut self,
+        severity: Severity,
+        check: ValidationCheck,
+        metadata: Option<&str>,
+        entry: Option<String>,
+        message: String,
+    ) {
+        self.issues.push(ValidationIssue {
+            severity,
+            check,
+            metadata: metadata.map(str::to_owned),
+            entry,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        write!(
+            f,
+            "{} errors, {} warnings, {} info",
+            self.errors().count(),
+            self.warnings().count(),
+            self.infos().count()
+        )
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Check the EVR and timestamps of a package, as read from `primary.xml`.
+fn validate_package(package: &Package, now: u64, report: &mut ValidationReport) {
+    let entry = || Some(package.nevra());
+    if let Err(MetadataError::InvalidEvrError(_, reason)) = package.evr().validate() {
+        report.add(
+            Severity::Error,
+            ValidationCheck::InvalidEvr,
+            None,
+            entry(),
+            format!("invalid EVR \"{}\": {}", package.evr(), reason),
+        );
+    }
+
+    for (name, time) in [
+        ("build time", package.time_build()),
+        ("file time", package.time_file()),
+    ] {
+        if time == 0 {
+            report.add(
+                Severity::Warning,
+                ValidationCheck::TimestampOutOfRange,
+                None,
+                entry(),
+                format!("the {} isn't set", name),
+            );
+        } else if time > now + MAX_CLOCK_SKEW {
+            report.add(
+                Severity::Warning,
+                ValidationCheck::TimestampOutOfRange,
+                None,
+                entry(),
+                format!("the {} {} is in the future", name, time),
+            );
+        }
+    }
+}
+
+/// Check the changelog of a package, as read from `other.xml`.
+fn validate_changelogs(
+    package: &Package,
+    now: u64,
+    report: &mut ValidationReport,
+) -> Result<(), MetadataError> {
+    for changelog in package.load_changelogs()?.iter() {
+        if changelog.timestamp > now + MAX_CLOCK_SKEW {
+            report.add(
+                Severity::Warning,
+                ValidationCheck::TimestampOutOfRange,
+                None,
+                Some(package.nevra()),
+                format!(
+                    "the changelog entry by {} has a timestamp in the future ({})",
+                    changelog.author, changelog.timestamp
+                ),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check the timestamps of the `repomd.xml` records.
+fn validate_repomd_timestamps(repomd: &RepomdData, now: u64, report: &mut ValidationReport) {
+    for record in repomd.records() {
+        if record.timestamp > (now + MAX_CLOCK_SKEW) as i64 {
+            report.add(
+                Severity::Warning,
+                ValidationCheck::TimestampOutOfRange,
+                Some(record.metadata_type.as_str()),
+                None,
+                format!("the record timestamp {} is in the future", record.timestamp),
+            );
+        }
+    }
+}
+
+/// The checks which can be made on the contents of a loaded [`Repository`].
+pub(crate) fn validate_repository(
+    repository: &Repository,
+) -> Result<ValidationReport, MetadataError> {
+    let now = now();
+    let mut report = ValidationReport::default();
+    validate_repomd_timestamps(repository.repomd(), now, &mut report);
+    for package in repository.packages().values() {
+        validate_package(package, now, &mut report);
+        validate_changelogs(package, now, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// The checks of the groups, categories and environments of `comps` against the packages of `repository`.
+pub(crate) fn validate_comps(repository: &Repository, comps: &Comps) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut arches: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for package in repository.packages().values() {
+        if package.arch() != "src" {
+            arches
+                .entry(package.name())
+                .or_default()
+                .insert(package.arch());
+        }
+    }
+    // whether the repository has the package for any of the arches it's limited to
+    let available = |name: &str, limited_to: &[String]| match arches.get(name) {
+        Some(found) => {
+            limited_to.is_empty()
+                || found.contains("noarch")
+                || limited_to.iter().any(|arch| found.contains(arch.as_str()))
+        }
+        None => false,
+    };
+    let arch_note = |limited_to: &[String]| match limited_to.is_empty() {
+        true => String::new(),
+        false => format!(" for {}", limited_to.join(", ")),
+    };
+
+    for group in &comps.groups {
+        for package in &group.packages {
+            if package.package_type == CompsPackageType::Unknown {
+                continue;
+            }
+            if !available(&package.name, &package.arches) {
+                let severity = match package.package_type {
+                    CompsPackageType::Mandatory => Severity::Error,
+                    _ => Severity::Warning,
+                };
+                report.add(
+                    severity,
+                    ValidationCheck::CompsMissingPackage,
+                    Some("group"),
+                    Some(group.id.clone()),
+                    format!(
+                        "the {} package {} isn't in the repository{}",
+                        package.package_type.as_str(),
+                        package.name,
+                        arch_note(&package.arches)
+                    ),
+                );
+            }
+            if package.package_type != CompsPackageType::Conditional {
+                continue;
+            }
+            match &package.requires {
+                Some(requires) if !available(requires, &package.arches) => report.add(
+                    Severity::Warning,
+                    ValidationCheck::CompsUnmatchableConditional,
+                    Some("group"),
+                    Some(group.id.clone()),
+                    format!(
+                        "{} is conditional on {}, which isn't in the repository{}",
+                        package.name,
+                        requires,
+                        arch_note(&package.arches)
+                    ),
+                ),
+                Some(_) => (),
+                None => report.add(
+                    Severity::Warning,
+                    ValidationCheck::CompsUnmatchableConditional,
+                    Some("group"),
+                    Some(group.id.clone()),
+                    format!("{} is conditional, but not on any package", package.name),
+                ),
+            }
+        }
+    }
+
+    let groups: HashSet<&str> = comps.groups.iter().map(|group| group.id.as_str()).collect();
+    let listed = comps
+        .categories
+        .iter()
+        .map(|category| ("category", &category.id, &category.group_ids))
+        .chain(comps.environments.iter().flat_map(|environment| {
+            [
+                ("environment", &environment.id, &environment.group_ids),
+                ("environment", &environment.id, &environment.option_ids),
+            ]
+        }));
+    for (kind, id, group_ids) in listed {
+        for group_id in group_ids {
+            if !groups.contains(group_id.as_str()) {
+                report.add(
+                    Severity::Warning,
+                    ValidationCheck::CompsMissingGroup,
+                    Some("group"),
+                    Some(id.clone()),
+                    format!(
+                        "the {} lists the group {}, which doesn't exist",
+                        kind, group_id
+                    ),
+                );
+            }
+        }
+    }
+    report
+}
+
+/// A NEVRA with the epoch filled in, so that `foo-1.0-1.noarch` and `foo-0:1.0-1.noarch` compare equal.
+fn normalize_nevra(nevra: &str) -> String {
+    match utils::split_nevra(nevra) {
+        Some((name, "", version, release, arch)) => {
+            format!("{}-0:{}-{}.{}", name, version, release, arch)
+        }
+        _ => nevra.to_owned(),
+    }
+}
+
+/// The NEVRA of a package of an advisory, with the epoch filled in.
+fn collection_package_nevra(package: &UpdateCollectionPackage) -> String {
+    let epoch = match package.epoch.as_str() {
+        "" => "0",
+        epoch => epoch,
+    };
+    format!(
+        "{}-{}:{}-{}.{}",
+        package.name, epoch, package.version, package.release, package.arch
+    )
+}
+
+/// The checks of the modular advisories of `repository` against the module streams of `modules`.
+pub(crate) fn validate_modules(repository: &Repository, modules: &Modules) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut artifacts: HashMap<String, HashSet<String>> = HashMap::new();
+    // the module streams each package is an artifact of
+    let mut artifact_of: HashMap<String, Vec<String>> = HashMap::new();
+    for stream in modules.streams() {
+        let nevras: HashSet<String> = stream
+            .rpm_artifacts()
+            .iter()
+            .map(|nevra| normalize_nevra(nevra))
+            .collect();
+        for nevra in &nevras {
+            artifact_of.entry(nevra.clone()).or_default().push(format!(
+                "{}:{}",
+                stream.name(),
+                stream.stream()
+            ));
+        }
+        artifacts.entry(stream.nsvca()).or_default().extend(nevras);
+    }
+
+    for advisory in repository.advisories().values() {
+        for collection in &advisory.pkglist {
+            let entry = || Some(advisory.id.clone());
+            let Some(module) = &collection.module else {
+                for package in &collection.packages {
+                    let nevra = collection_package_nevra(package);
+                    if let Some(streams) = artifact_of.get(&nevra) {
+                        report.add(
+                            Severity::Warning,
+                            ValidationCheck::AdvisoryModuleMismatch,
+                            Some("updateinfo"),
+                            entry(),
+                            format!(
+                                "{} is an artifact of the module stream {}, but the advisory isn't modular",
+                                nevra,
+                                streams.join(", ")
+                            ),
+                        );
+                    }
+                }
+                continue;
+            };
+            let nsvca = format!(
+                "{}:{}:{}:{}:{}",
+                module.name, module.stream, module.version, module.context, module.arch
+            );
+            let Some(stream_artifacts) = artifacts.get(&nsvca) else {
+                report.add(
+                    Severity::Error,
+                    ValidationCheck::AdvisoryUnknownModule,
+                    Some("updateinfo"),
+                    entry(),
+                    format!("the module stream {} isn't in modules.yaml", nsvca),
+                );
+                continue;
+            };
+            for package in &collection.packages {
+                let nevra = collection_package_nevra(package);
+                if !stream_artifacts.contains(&nevra) {
+                    report.add(
+                        Severity::Warning,
+                        ValidationCheck::AdvisoryModuleMismatch,
+                        Some("updateinfo"),
+                        entry(),
+                        format!("{} isn't an artifact of the module stream {}", nevra, nsvca),
+                    );
+                }
+            }
+        }
+    }
+    report
+}
+
+/// Read every package of a metadata file with `read_package`, passing each one to `each`.
+fn read_all(
+    mut read_package: impl FnMut(&mut Option<Package>) -> Result<(), MetadataError>,
+    mut each: impl FnMut(Package) -> Result<(), MetadataError>,
+) -> Result<(), MetadataError> {
+    loop {
+        let mut package = None;
+        read_package(&mut package)?;
+        match package {
+            Some(package) => each(package)?,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// The checks which need the metadata files of the repository at `base` in `storage`, as well as those
+/// of [`validate_repository()`].
+pub(crate) fn validate_directory(
+    storage: &dyn Storage,
+    base: &Path,
+    repomd: &RepomdData,
+    options: ParseOptions,
+) -> Result<ValidationReport, MetadataError> {
+    let now = now();
+    let mut report = ValidationReport::default();
+    validate_repomd_timestamps(repomd, now, &mut report);
+
+    let mut referenced = HashSet::new();
+    for record in repomd.records() {
+        let path = base.join(&record.location_href);
+        if !storage.is_file(&path) {
+            report.add(
+                Severity::Error,
+                ValidationCheck::DanglingRecord,
+                Some(record.metadata_type.as_str()),
+                None,
+                format!("{} doesn't exist", record.location_href.display()),
+            );
+        }
+        referenced.insert(path);
+    }
+    if let Ok(names) = storage.list(&base.join("repodata")) {
+        let mut unreferenced = Vec::new();
+        for name in names {
+            let path = base.join("repodata").join(&name);
+            if !referenced.contains(&path) && !name.starts_with("repomd.xml") {
+                unreferenced.push(format!("repodata/{}", name));
+            }
+        }
+        unreferenced.sort();
+        for name in unreferenced {
+            report.add(
+                Severity::Info,
+                ValidationCheck::UnreferencedFile,
+                None,
+                None,
+                format!("{} isn't referenced by repomd.xml", name),
+            );
+        }
+    }
+
+    // the packages of each file, by pkgid
+    let mut pkgids: Vec<(&str, Vec<(String, String)>)> = Vec::new();
+    for metadata in [METADATA_PRIMARY, METADATA_FILELISTS, METADATA_OTHER] {
+        let Some(record) = repomd.get_record(metadata) else {
+            report.add(
+                Severity::Error,
+                ValidationCheck::MissingRecord,
+                Some(metadata),
+                None,
+                format!("repomd.xml has no {} record", metadata),
+            );
+            continue;
+        };
+        let path = base.join(&record.location_href);
+        if !storage.is_file(&path) {
+            continue;
+        }
+
+        let mut packages = Vec::new();
+        let mut each = |package: Package| {
+            match metadata {
+                METADATA_PRIMARY => validate_package(&package, now, &mut report),
+                METADATA_OTHER => validate_changelogs(&package, now, &mut report)?,
+                _ => (),
+            }
+            packages.push((package.pkgid().to_owned(), package.nevra()));
+            Ok(())
+        };
+        let xml = utils::filtered_xml_reader_from_storage(storage, &path, options)?;
+        let declared = match metadata {
+            METADATA_PRIMARY => {
+                let mut reader = PrimaryXml::new_reader(xml);
+                reader.set_parse_mode(options.mode);
+                let declared = reader.read_header()?;
+                read_all(|p| reader.read_package(p), &mut each).map(|_| declared)
+            }
+            METADATA_FILELISTS => {
+                let mut reader = FilelistsXml::new_reader(xml);
+                reader.set_parse_mode(options.mode);
+                let declared = reader.read_header()?;
+                read_all(|p| reader.read_package(p), &mut each).map(|_| declared)
+            }
+            _ => {
+                let mut reader = OtherXml::new_reader(xml);
+                reader.set_parse_mode(options.mode);
+                let declared = reader.read_header()?;
+                read_all(|p| reader.read_package(p), &mut each).map(|_| declared)
+            }
+        }
+        .map_err(|e| e.with_line_from(|| utils::reader_from_storage(storage, &path)))?;
+
+        if declared != packages.len() {
+            report.add(
+                Severity::Error,
+                ValidationCheck::CountMismatch,
+                Some(metadata),
+                None,
+                format!(
+                    "the header declares {} packages, but the file contains {}",
+                    declared,
+                    packages.len()
+                ),
+            );
+        }
+        pkgids.push((metadata, packages));
+    }
+
+    let Some((_, primary)) = pkgids.iter().find(|(m, _)| *m == METADATA_PRIMARY) else {
+        return Ok(report);
+    };
+    let in_primary: HashSet<&str> = primary.iter().map(|(pkgid, _)| pkgid.as_str()).collect();
+    for (metadata, packages) in pkgids.iter().filter(|(m, _)| *m != METADATA_PRIMARY) {
+        if packages.len() != primary.len() {
+            report.add(
+                Severity::Error,
+                ValidationCheck::CountMismatch,
+                Some(metadata),
+                None,
+                format!(
+                    "{} packages, but primary has {}",
+                    packages.len(),
+                    primary.len()
+                ),
+            );
+        }
+        let in_file: HashSet<&str> = packages.iter().map(|(pkgid, _)| pkgid.as_str()).collect();
+        for (pkgid, nevra) in primary {
+            if !in_file.contains(pkgid.as_str()) {
+                report.add(
+                    Severity::Error,
+                    ValidationCheck::MissingPackage,
+                    Some(metadata),
+                    Some(nevra.clone()),
+                    format!("package {} is missing from {}", pkgid, metadata),
+                );
+            }
+        }
+        for (pkgid, nevra) in packages {
+            if !in_primary.contains(pkgid.as_str()) {
+                report.add(
+                    Severity::Error,
+                    ValidationCheck::MissingPackage,
+                    Some(metadata),
+                    Some(nevra.clone()),
+                    format!("package {} is missing from primary", pkgid),
+                );
+            }
+        }
+    }
+    Ok(report)
+}
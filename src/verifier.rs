@@ -0,0 +1,251 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::hasher::{self, Hasher};
+use crate::logging;
+use crate::runtime;
+use crate::storage::{LocalStorage, Storage};
+use crate::{utils, Checksum};
+
+/// Options for [`verify_files()`].
+///
+/// - `workers` - How many files are hashed at once, at most. `0` means as many as the current
+///   [`Runtime`](crate::Runtime) has threads. Fewer are if it doesn't have the threads to spare.
+/// - `readahead` - How many bytes of a file are read ahead while the bytes before them are hashed.
+#[derive(Copy, Clone, Debug)]
+pub struct VerifyOptions {
+    pub workers: usize,
+    pub readahead: usize,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            workers: 0,
+            readahead: 1 << 20,
+        }
+    }
+}
+
+impl VerifyOptions {
+    pub fn workers(self, val: usize) -> Self {
+        Self {
+            workers: val,
+            ..self
+        }
+    }
+
+    pub fn readahead(self, val: usize) -> Self {
+        Self {
+            readahead: val,
+            ..self
+        }
+    }
+}
+
+/// A file to check against the size and checksum the metadata gives it, for [`verify_files()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileCheck {
+    pub path: PathBuf,
+    pub checksum: Checksum,
+    pub size: Option<u64>,
+}
+
+impl FileCheck {
+    pub fn new(path: impl Into<PathBuf>, checksum: Checksum, size: Option<u64>) -> Self {
+        Self {
+            path: path.into(),
+            checksum,
+            size,
+        }
+    }
+}
+
+/// What [`verify_files()`] found out about a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileStatus {
+    /// The file has the expected size and checksum
+    Valid,
+    /// The file has the expected size, but its checksum is of a type which can't be computed (see
+    /// [`Checksum::Other`])
+    Unverifiable,
+    /// The file doesn't exist
+    Missing,
+    /// The file has the wrong size, so its checksum wasn't computed
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The file has the wrong checksum
+    ChecksumMismatch { actual: Checksum },
+    /// The file couldn't be read
+    Unreadable(String),
+}
+
+impl FileStatus {
+    /// Whether nothing is known to be wrong with the file: it's valid, or has the right size and a
+    /// checksum which can't be checked.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, FileStatus::Valid | FileStatus::Unverifiable)
+    }
+}
+
+/// What [`verify_files()`] found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerificationReport {
+    /// The status of each file, in the order they were given
+    pub files: Vec<(PathBuf, FileStatus)>,
+    /// How many bytes were hashed
+    pub bytes_hashed: u64,
+    /// How long the verification took
+    pub elapsed: Duration,
+}
+
+impl VerificationReport {
+    /// Whether nothing is known to be wrong with any of the files, see [`FileStatus::is_ok()`].
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|(_, status)| status.is_ok())
+    }
+
+    /// The files which are missing, or don't match their size or checksum.
+    pub fn failures(&self) -> impl Iterator<Item = &(PathBuf, FileStatus)> {
+        self.files.iter().filter(|(_, status)| !status.is_ok())
+    }
+
+    /// How many bytes were hashed per second.
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            elapsed if elapsed > 0.0 => self.bytes_hashed as f64 / elapsed,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Check many files against their sizes and checksums at once, hashing several of them concurrently.
+///
+/// Each of the [`VerifyOptions::workers`] takes the next file to check until there are none left, and
+/// reads the blocks of large files on a thread of its own so that reading and hashing overlap. The
+/// threads are drawn from the current [`Runtime`](crate::Runtime), and the checksums are computed with
+/// the current [`HashProvider`](crate::HashProvider).
+pub fn verify_files(files: &[FileCheck], options: VerifyOptions) -> VerificationReport {
+    verify_files_in(&LocalStorage, files, options)
+}
+
+/// Like [`verify_files()`], for files in `storage`, e.g. inside an archive.
+pub fn verify_files_in(
+    storage: &dyn Storage,
+    files: &[FileCheck],
+    options: VerifyOptions,
+) -> VerificationReport {
+    let _span = logging::span!("verify {} files", files.len());
+    let start = Instant::now();
+    let readahead = options.readahead.max(4096);
+
+    let results = runtime::map_concurrently(files, options.workers, |file| {
+        verify_file(storage, file, readahead)
+    });
+
+    let report = VerificationReport {
+        bytes_hashed: results.iter().map(|(_, hashed)| hashed).sum(),
+        files: files
+            .iter()
+            .zip(results)
+            .map(|(file, (status, _))| (file.path.clone(), status))
+            .collect(),
+        elapsed: start.elapsed(),
+    };
+    logging::debug!(
+        "verified {} files ({} bytes) in {:?}, {:.1} MB/s",
+        report.files.len(),
+        report.bytes_hashed,
+        report.elapsed,
+        report.throughput() / 1e6
+    );
+    report
+}
+
+/// The status of `file`, and how many bytes of it were hashed.
+fn verify_file(storage: &dyn Storage, file: &FileCheck, readahead: usize) -> (FileStatus, u64) {
+    let actual = match storage.size(&file.path) {
+        Ok(size) => size,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return (FileStatus::Missing, 0),
+        Err(e) => return (FileStatus::Unreadable(e.to_string()), 0),
+    };
+    if let Some(expected) = file.size.filter(|expected| *expected != actual) {
+        return (FileStatus::SizeMismatch { expected, actual }, 0);
+    }
+    let checksum_type = file.checksum.checksum_type();
+    let Some(hasher) = hasher::new_hasher(checksum_type) else {
+        return (FileStatus::Unverifiable, 0);
+    };
+    match hash_file(storage, &file.path, hasher, actual, readahead) {
+        Ok(digest) => {
+            let checksum = utils::checksum_from_digest(checksum_type, hex::encode(digest));
+            match checksum == file.checksum {
+                true => (FileStatus::Valid, actual),
+                false => (FileStatus::ChecksumMismatch { actual: checksum }, actual),
+            }
+        }
+        Err(e) => (FileStatus::Unreadable(e.to_string()), 0),
+    }
+}
+
+/// Hash the file at `path` in `storage`, which is `size` bytes long, reading up to `readahead` bytes
+/// ahead of the hasher on a thread from the current [`Runtime`](crate::Runtime).
+fn hash_file(
+    storage: &dyn Storage,
+    path: &Path,
+    mut hasher: Box<dyn Hasher>,
+    size: u64,
+    readahead: usize,
+) -> io::Result<Vec<u8>> {
+    let mut file = storage.open(path)?;
+    if size <= readahead as u64 {
+        let mut contents = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut contents)?;
+        hasher.update(&contents);
+        return Ok(hasher.finalize());
+    }
+    let (blocks, received) = mpsc::sync_channel::<io::Result<Vec<u8>>>(1);
+    let reading = Mutex::new(Some((file, blocks)));
+    let read_ahead = || {
+        let Some((mut file, blocks)) = reading.lock().unwrap().take() else {
+            return;
+        };
+        loop {
+            let mut block = Vec::with_capacity(readahead);
+            match (&mut file).take(readahead as u64).read_to_end(&mut block) {
+                Ok(0) => return,
+                Ok(_) if blocks.send(Ok(block)).is_ok() => (),
+                // the hasher gave up
+                Ok(_) => return,
+                Err(e) => {
+                    let _ = blocks.send(Err(e));
+                    return;
+                }
+            }
+        }
+    };
+    runtime::current_runtime().scope(1, &read_ahead, |helpers| {
+        // the blocks are read on the hashing thread if there's no thread to spare
+        if helpers == 0 {
+            let (mut file, _) = reading.lock().unwrap().take().unwrap();
+            let mut block = Vec::with_capacity(readahead);
+            while (&mut file).take(readahead as u64).read_to_end(&mut block)? > 0 {
+                hasher.update(&block);
+                block.clear();
+            }
+            return Ok(());
+        }
+        for block in received {
+            hasher.update(&block?);
+        }
+        Ok::<_, io::Error>(())
+    })?;
+    Ok(hasher.finalize())
+}
@@ -0,0 +1,464 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The [zchunk](https://github.com/zchunk/zchunk/blob/main/zchunk_format.txt) file format.
+//!
+//! A zchunk file is a header listing the checksum and length of each independently compressed chunk,
+//! followed by the chunks. The first chunk is the (possibly empty) dictionary the others are compressed
+//! with.
+
+use std::io::{self, Read, Write};
+
+use crate::hasher;
+use crate::{Checksum, ChecksumType, DecompressionError};
+
+pub(crate) const ZCK_MAGIC: &[u8] = b"\0ZCK1";
+
+const FLAG_STREAMS: u64 = 1;
+const FLAG_OPTIONAL_ELEMENTS: u64 = 2;
+const FLAG_UNCOMPRESSED_CHECKSUMS: u64 = 4;
+
+const COMPRESSION_NONE: u64 = 0;
+const COMPRESSION_ZSTD: u64 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum ZckChecksumType {
+    Sha1,
+    Sha256,
+    Sha512,
+    Sha512_128,
+}
+
+impl ZckChecksumType {
+    fn from_code(code: u64) -> Option<Self> {
+        match code {
+            0 => Some(Self::Sha1),
+            1 => Some(Self::Sha256),
+            2 => Some(Self::Sha512),
+            3 => Some(Self::Sha512_128),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> u64 {
+        match self {
+            Self::Sha1 => 0,
+            Self::Sha256 => 1,
+            Self::Sha512 => 2,
+            Self::Sha512_128 => 3,
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+            Self::Sha512_128 => 16,
+        }
+    }
+
+    pub(crate) fn digest(self, parts: &[&[u8]]) -> Vec<u8> {
+        let checksum_type = match self {
+            Self::Sha1 => ChecksumType::Sha1,
+            Self::Sha256 => ChecksumType::Sha256,
+            Self::Sha512 | Self::Sha512_128 => ChecksumType::Sha512,
+        };
+        let mut hasher = hasher::new_hasher(checksum_type).unwrap();
+        for part in parts {
+            hasher.update(part);
+        }
+        let mut digest = hasher.finalize();
+        digest.truncate(self.len());
+        digest
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Chunk {
+    pub(crate) checksum: Vec<u8>,
+    pub(crate) offset: usize,
+    pub(crate) length: usize,
+    pub(crate) uncompressed_length: usize,
+}
+
+/// The parts of a zchunk header needed to decompress a file, or to tell which chunks two files have in
+/// common.
+#[derive(Clone, Debug)]
+pub(crate) struct ZchunkHeader {
+    /// Length of the whole header including the lead, i.e. the offset of the first chunk
+    pub(crate) len: usize,
+    /// The header checksum recorded in the lead
+    pub(crate) checksum: Vec<u8>,
+    pub(crate) header_checksum_type: ZckChecksumType,
+    /// The header checksum computed from the header itself
+    pub(crate) actual_checksum: Vec<u8>,
+    pub(crate) compression_type: u64,
+    pub(crate) chunk_checksum_type: ZckChecksumType,
+    pub(crate) chunks: Vec<Chunk>,
+}
+
+impl ZchunkHeader {
+    /// Parse the header at the start of `bytes`. Returns `None` if it's invalid, truncated, or uses
+    /// features which aren't supported here (data streams, uncompressed chunk checksums).
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut pos = ZCK_MAGIC.len();
+        if !bytes.starts_with(ZCK_MAGIC) {
+            return None;
+        }
+
+        // lead
+        let checksum_type = ZckChecksumType::from_code(read_int(bytes, &mut pos)?)?;
+        let header_size = read_int(bytes, &mut pos)? as usize;
+        let checksum_start = pos;
+        pos += checksum_type.len();
+        let len = pos.checked_add(header_size)?;
+        let checksum = bytes.get(checksum_start..pos)?.to_vec();
+        let header = bytes.get(..len)?;
+        // the header checksum covers the whole header except for itself
+        let actual_checksum = checksum_type.digest(&[&header[..checksum_start], &header[pos..]]);
+
+        // preface
+        pos += checksum_type.len(); // checksum of the data
+        let flags = read_int(header, &mut pos)?;
+        if flags & (FLAG_STREAMS | FLAG_UNCOMPRESSED_CHECKSUMS) != 0 {
+            return None;
+        }
+        let compression_type = read_int(header, &mut pos)?;
+        if flags & FLAG_OPTIONAL_ELEMENTS != 0 {
+            for _ in 0..read_int(header, &mut pos)? {
+                let _id = read_int(header, &mut pos)?;
+                pos += read_int(header, &mut pos)? as usize;
+            }
+        }
+
+        // index - the first chunk is the (possibly empty) compression dictionary
+        let _index_size = read_int(header, &mut pos)?;
+        let chunk_checksum_type = ZckChecksumType::from_code(read_int(header, &mut pos)?)?;
+        let chunk_count = read_int(header, &mut pos)?;
+        let mut chunks = Vec::new();
+        let mut offset = len;
+        for _ in 0..chunk_count {
+            let checksum = header.get(pos..pos + chunk_checksum_type.len())?.to_vec();
+            pos += chunk_checksum_type.len();
+            let length = read_int(header, &mut pos)? as usize;
+            let uncompressed_length = read_int(header, &mut pos)? as usize;
+            chunks.push(Chunk {
+                checksum,
+                offset,
+                length,
+                uncompressed_length,
+            });
+            offset = offset.checked_add(length)?;
+        }
+
+        Some(ZchunkHeader {
+            len,
+            checksum,
+            header_checksum_type: checksum_type,
+            actual_checksum,
+            compression_type,
+            chunk_checksum_type,
+            chunks,
+        })
+    }
+
+    /// Read the header from the start of `reader`. Returns `None` if it isn't a zchunk file.
+    pub(crate) fn read_from<R: Read>(mut reader: R) -> io::Result<Option<Self>> {
+        let mut magic = Vec::new();
+        (&mut reader)
+            .take(ZCK_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != ZCK_MAGIC {
+            return Ok(None);
+        }
+        let bytes = read_header(&mut io::Cursor::new(magic).chain(reader))?;
+        ZchunkHeader::parse(&bytes)
+            .filter(|header| header.len == bytes.len())
+            .map(Some)
+            .ok_or_else(|| corrupt("invalid or unsupported zchunk header"))
+    }
+
+    /// The header checksum recorded in the lead, as recorded in `repomd.xml`.
+    pub(crate) fn header_checksum(&self) -> Checksum {
+        let digest = hex::encode(&self.checksum);
+        match self.header_checksum_type {
+            ZckChecksumType::Sha1 => Checksum::Sha1(digest),
+            ZckChecksumType::Sha256 => Checksum::Sha256(digest),
+            ZckChecksumType::Sha512 => Checksum::Sha512(digest),
+            ZckChecksumType::Sha512_128 => Checksum::Other("sha512_128".to_owned(), digest),
+        }
+    }
+
+    pub(crate) fn chunk_matches(&self, chunk: &Chunk, data: &[u8]) -> bool {
+        self.chunk_checksum_type.digest(&[data]) == chunk.checksum
+    }
+}
+
+/// Read a zchunk "compressed integer": little-endian, 7 bits per byte, the last byte has the high bit set.
+fn read_int(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64).checked_shl(shift)?;
+        if byte & 0x80 != 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            return None;
+        }
+    }
+}
+
+/// Append `value` to `bytes` as a zchunk "compressed integer".
+fn write_int(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte | 0x80);
+            return;
+        }
+        bytes.push(byte);
+    }
+}
+
+/// Writes an XML document to `inner` as a zchunk file, compressing the chunks with zstd.
+///
+/// A new chunk starts at each `<element>` tag, so that an entry which doesn't change between two versions
+/// of the document is a chunk which doesn't have to be downloaded again. The header comes first in the
+/// file but lists every chunk, so the compressed chunks are kept in memory until
+/// [`finish()`](Self::finish) writes the file. Nothing is written to `inner` before then.
+pub(crate) struct ZchunkWriter<W: Write> {
+    inner: W,
+    tag: Vec<u8>,
+    /// The uncompressed contents of the chunk being written
+    pending: Vec<u8>,
+    /// How far `pending` has been searched for the start of the next chunk
+    scanned: usize,
+    /// The compressed chunks and their uncompressed lengths, starting with the dictionary, which is empty
+    chunks: Vec<(Vec<u8>, usize)>,
+}
+
+impl<W: Write> ZchunkWriter<W> {
+    pub(crate) fn new(inner: W, element: &str) -> Self {
+        ZchunkWriter {
+            inner,
+            tag: format!("<{}", element).into_bytes(),
+            pending: Vec::new(),
+            scanned: 1,
+            chunks: vec![(Vec::new(), 0)],
+        }
+    }
+
+    /// Compress the start of `pending`, up to `end`, as a chunk.
+    fn push_chunk(&mut self, end: usize) -> io::Result<()> {
+        let chunk: Vec<u8> = self.pending.drain(..end).collect();
+        self.chunks
+            .push((zstd::bulk::compress(&chunk, 0)?, chunk.len()));
+        self.scanned = 1;
+        Ok(())
+    }
+
+    /// Write the header and the chunks to the inner writer, returning it.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            self.push_chunk(self.pending.len())?;
+        }
+        let chunk_checksum_type = ZckChecksumType::Sha512_128;
+        let header_checksum_type = ZckChecksumType::Sha256;
+
+        let mut index = Vec::new();
+        write_int(&mut index, chunk_checksum_type.code());
+        write_int(&mut index, self.chunks.len() as u64);
+        for (chunk, uncompressed_length) in &self.chunks {
+            index.extend(chunk_checksum_type.digest(&[chunk]));
+            write_int(&mut index, chunk.len() as u64);
+            write_int(&mut index, *uncompressed_length as u64);
+        }
+
+        let chunk_data: Vec<&[u8]> = self
+            .chunks
+            .iter()
+            .map(|(chunk, _)| chunk.as_slice())
+            .collect();
+        let mut header = header_checksum_type.digest(&chunk_data);
+        write_int(&mut header, 0); // flags
+        write_int(&mut header, COMPRESSION_ZSTD);
+        write_int(&mut header, index.len() as u64);
+        header.extend(index);
+        write_int(&mut header, 0); // signatures
+
+        let mut lead = ZCK_MAGIC.to_vec();
+        write_int(&mut lead, header_checksum_type.code());
+        write_int(&mut lead, header.len() as u64);
+        let checksum = header_checksum_type.digest(&[&lead, &header]);
+
+        self.inner.write_all(&lead)?;
+        self.inner.write_all(&checksum)?;
+        self.inner.write_all(&header)?;
+        for chunk in chunk_data {
+            self.inner.write_all(chunk)?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ZchunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        loop {
+            // a chunk starts at a tag followed by a space or `>`, which has to have been written already
+            let end = self.pending.len().saturating_sub(self.tag.len());
+            let start = (self.scanned..end).find(|&pos| {
+                self.pending[pos..].starts_with(&self.tag)
+                    && matches!(self.pending[pos + self.tag.len()], b' ' | b'>')
+            });
+            match start {
+                Some(pos) => self.push_chunk(pos)?,
+                None => {
+                    self.scanned = end.max(1);
+                    return Ok(buf.len());
+                }
+            }
+        }
+    }
+
+    /// Does nothing, the file can only be written once it's complete.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn corrupt(reason: &str) -> io::Error {
+    DecompressionError::Corrupt(reason.to_owned()).into()
+}
+
+/// Decompresses a zchunk file one chunk at a time, checking each chunk against the header.
+pub(crate) struct ZchunkReader<R: Read> {
+    inner: R,
+    header: ZchunkHeader,
+    /// `None` if the chunks aren't compressed
+    decompressor: Option<zstd::bulk::Decompressor<'static>>,
+    next_chunk: usize,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> ZchunkReader<R> {
+    pub(crate) fn new(mut inner: R) -> io::Result<Self> {
+        let bytes = read_header(&mut inner)?;
+        let header = ZchunkHeader::parse(&bytes)
+            .filter(|header| header.len == bytes.len())
+            .ok_or_else(|| corrupt("invalid or unsupported zchunk header"))?;
+        if header.actual_checksum != header.checksum {
+            return Err(corrupt("zchunk header checksum mismatch"));
+        }
+        let mut reader = ZchunkReader {
+            inner,
+            header,
+            decompressor: None,
+            next_chunk: 0,
+            data: Vec::new(),
+            pos: 0,
+        };
+
+        let dictionary = reader.read_chunk()?.unwrap_or_default().0;
+        reader.decompressor = match reader.header.compression_type {
+            COMPRESSION_NONE => None,
+            COMPRESSION_ZSTD if dictionary.is_empty() => Some(zstd::bulk::Decompressor::new()?),
+            COMPRESSION_ZSTD => {
+                let length = reader.header.chunks[0].uncompressed_length;
+                let dictionary = zstd::bulk::decompress(&dictionary, length)
+                    .map_err(|e| corrupt(&format!("zchunk dictionary: {}", e)))?;
+                Some(zstd::bulk::Decompressor::with_dictionary(&dictionary)?)
+            }
+            other => {
+                return Err(corrupt(&format!(
+                    "unsupported zchunk compression type {}",
+                    other
+                )))
+            }
+        };
+        Ok(reader)
+    }
+
+    /// Read the next chunk as it's stored, along with its uncompressed length.
+    fn read_chunk(&mut self) -> io::Result<Option<(Vec<u8>, usize)>> {
+        let Some(chunk) = self.header.chunks.get(self.next_chunk) else {
+            return Ok(None);
+        };
+        self.next_chunk += 1;
+        let mut data = vec![0; chunk.length];
+        self.inner.read_exact(&mut data)?;
+        if !self.header.chunk_matches(chunk, &data) {
+            return Err(corrupt(&format!(
+                "checksum mismatch for the zchunk chunk at offset {}",
+                chunk.offset
+            )));
+        }
+        Ok(Some((data, chunk.uncompressed_length)))
+    }
+}
+
+impl<R: Read> Read for ZchunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            let Some((data, length)) = self.read_chunk()? else {
+                return Ok(0);
+            };
+            self.data = match &mut self.decompressor {
+                Some(decompressor) if !data.is_empty() => decompressor
+                    .decompress(&data, length)
+                    .map_err(|e| corrupt(&format!("zchunk chunk: {}", e)))?,
+                _ => data,
+            };
+            self.pos = 0;
+        }
+        let count = buf.len().min(self.data.len() - self.pos);
+        buf[..count].copy_from_slice(&self.data[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Read the header (including the lead) from the start of a zchunk file.
+fn read_header<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; ZCK_MAGIC.len()];
+    reader.read_exact(&mut bytes)?;
+    let checksum_type = ZckChecksumType::from_code(read_lead_int(reader, &mut bytes)?)
+        .ok_or_else(|| corrupt("unsupported zchunk checksum type"))?;
+    let header_size = read_lead_int(reader, &mut bytes)?;
+    let len =
+        (bytes.len() as u64).saturating_add(header_size.saturating_add(checksum_type.len() as u64));
+    reader
+        .take(len - bytes.len() as u64)
+        .read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+/// Read a compressed integer of the lead from `reader`, appending its bytes to `bytes`.
+fn read_lead_int<R: Read>(reader: &mut R, bytes: &mut Vec<u8>) -> io::Result<u64> {
+    let mut pos = bytes.len();
+    loop {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        bytes.push(byte[0]);
+        if byte[0] & 0x80 != 0 {
+            return read_int(bytes, &mut pos).ok_or_else(|| corrupt("invalid zchunk lead"));
+        }
+        if bytes.len() - pos > 9 {
+            return Err(corrupt("invalid zchunk lead"));
+        }
+    }
+}
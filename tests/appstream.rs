@@ -0,0 +1,84 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    ChecksumType, CompressionType, MetadataError, MetadataType, Repository, RepositoryOptions,
+    RepositoryWriter,
+};
+use tempdir::TempDir;
+
+const APPSTREAM_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<components origin="fedora" version="0.14">
+  <component type="desktop-application">
+    <id>org.gnome.Calculator</id>
+    <pkgname>gnome-calculator</pkgname>
+    <name>Calculator</name>
+  </component>
+</components>
+"#;
+
+#[test]
+fn test_appstream_metadata_files() -> Result<(), MetadataError> {
+    let source_dir = TempDir::new("test_appstream_metadata_files")?;
+    let appstream_source = source_dir.path().join("appstream.xml.gz");
+    let mut encoder = GzEncoder::new(
+        fs::File::create(&appstream_source)?,
+        flate2::Compression::default(),
+    );
+    encoder.write_all(APPSTREAM_XML.as_bytes())?;
+    encoder.finish()?;
+    let icons_source = source_dir.path().join("icons.tar");
+    fs::write(&icons_source, b"not really a tarball")?;
+
+    let tmp_dir = TempDir::new("test_appstream_metadata_files")?;
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(CompressionType::Zstd)
+        .simple_metadata_filenames(false);
+    let mut writer = RepositoryWriter::new_with_options(tmp_dir.path(), 0, options)?;
+    assert!(writer
+        .add_metadata_file(MetadataType::Primary, &icons_source)
+        .is_err());
+    writer.add_metadata_file(MetadataType::Appstream, &icons_source)?;
+    // replaces the previous one
+    writer.add_metadata_file(MetadataType::Appstream, &appstream_source)?;
+    writer.add_metadata_file(MetadataType::AppstreamIcons, &icons_source)?;
+    writer.finish()?;
+
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
+    let appstream = repo.repomd().get_record("appstream").unwrap();
+    let icons = repo.repomd().get_record("appstream-icons").unwrap();
+    let file_name =
+        |href: &std::path::Path| href.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(file_name(&appstream.location_href).ends_with("-appstream.xml.zst"));
+    assert!(file_name(&icons.location_href).ends_with("-appstream-icons.tar.zst"));
+    assert_eq!(
+        repo.repomd()
+            .records()
+            .iter()
+            .filter(|r| r.metadata_type == MetadataType::Appstream)
+            .count(),
+        1
+    );
+    assert_eq!(appstream.open_size, Some(APPSTREAM_XML.len() as u64));
+    appstream.verify(tmp_dir.path())?;
+    icons.verify(tmp_dir.path())?;
+
+    // a changed file no longer matches its record until the record is refreshed
+    let mut appstream = appstream.clone();
+    let path = tmp_dir.path().join(&appstream.location_href);
+    fs::write(&path, "<components/>")?;
+    assert!(appstream.verify(tmp_dir.path()).is_err());
+    appstream.refresh(tmp_dir.path(), ChecksumType::Sha256)?;
+    appstream.verify(tmp_dir.path())?;
+    assert_eq!(appstream.size, Some(13));
+
+    Ok(())
+}
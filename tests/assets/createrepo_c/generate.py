@@ -0,0 +1,98 @@
+#!/usr/bin/env python3
+
+# Regenerates the createrepo_c golden files in this directory with createrepo_c
+# Copyright (C) 2022 Daniel Alley
+
+# The following GPL-2.0 license notice applies to this file (only)
+# by virtue of using createrepo_c, a GPL-2.0 licensed library.
+# =============================================================
+
+# This program is free software; you can redistribute it and/or
+# modify it under the terms of the GNU General Public License
+# version 2 as published by the Free Software Foundation.
+
+# This program is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+# GNU General Public License for more details.
+
+# You should have received a copy of the GNU General Public License
+# along with this program; If not, see <http://www.gnu.org/licenses/>.
+
+# createrepo_c reads the metadata in this directory and writes it back out in place, so the files only
+# stay unchanged (which CI checks with `git diff`) if they are exactly what createrepo_c writes for the
+# same packages, advisory and repomd records.
+#
+# It also writes the sqlite databases of the packages to databases/, and their schema to *.sqlite.sql,
+# which tests/sqlite.rs compares the databases rpmrepo_metadata writes against.
+#
+# Usage: generate.py [DIRECTORY]
+
+import hashlib
+import os
+import os.path
+import sqlite3
+import sys
+
+import createrepo_c as cr
+
+
+def sha256(path):
+    with open(path, "rb") as f:
+        return hashlib.sha256(f.read()).hexdigest()
+
+
+def write_schema(database_path, schema_path):
+    connection = sqlite3.connect(database_path)
+    with open(schema_path, "w") as f:
+        for (sql,) in connection.execute("SELECT sql FROM sqlite_master ORDER BY rowid"):
+            f.write(sql + ";\n")
+    connection.close()
+
+
+def main(directory):
+    path = lambda name: os.path.join(directory, name)
+
+    packages = list(cr.PackageIterator(
+        primary_path=path("primary.xml"),
+        filelists_path=path("filelists.xml"),
+        other_path=path("other.xml"),
+    ))
+    updateinfo = cr.UpdateInfo(path("updateinfo.xml"))
+    repomd = cr.Repomd(path("repomd.xml"))
+
+    for (name, xml_file) in [
+        ("primary", cr.PrimaryXmlFile),
+        ("filelists", cr.FilelistsXmlFile),
+        ("other", cr.OtherXmlFile),
+    ]:
+        f = xml_file(path(name + ".xml"), compressiontype=cr.NO_COMPRESSION)
+        f.set_num_of_pkgs(len(packages))
+        for package in packages:
+            f.add_pkg(package)
+        f.close()
+
+    with open(path("updateinfo.xml"), "w") as f:
+        f.write(updateinfo.xml_dump())
+    with open(path("repomd.xml"), "w") as f:
+        f.write(repomd.xml_dump())
+
+    os.makedirs(path("databases"), exist_ok=True)
+    for (name, database) in [
+        ("primary", cr.PrimarySqlite),
+        ("filelists", cr.FilelistsSqlite),
+        ("other", cr.OtherSqlite),
+    ]:
+        database_path = os.path.join(path("databases"), name + ".sqlite")
+        if os.path.exists(database_path):
+            os.remove(database_path)
+        db = database(database_path)
+        for package in packages:
+            db.add_pkg(package)
+        db.dbinfo_update(sha256(path(name + ".xml")))
+        db.close()
+        write_schema(database_path, path(name + ".sqlite.sql"))
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__)))
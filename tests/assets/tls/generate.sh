@@ -0,0 +1,24 @@
+#!/bin/bash
+# Generates the certificates used by the TLS tests in tests/download.rs: a CA, a certificate for
+# the test server at 127.0.0.1 / localhost and a client certificate, all signed by the CA.
+set -euo pipefail
+cd "$(dirname "$0")"
+
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 -subj "/CN=rpmrepo_metadata test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign" \
+    -keyout ca-key.pem -out ca.pem
+
+for name in server client; do
+    openssl req -newkey rsa:2048 -nodes -subj "/CN=rpmrepo_metadata test $name" \
+        -keyout "$name-key.pem" -out "$name.csr"
+done
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca-key.pem -set_serial 2 -days 36500 \
+    -extfile <(printf "subjectAltName=IP:127.0.0.1,DNS:localhost\nextendedKeyUsage=serverAuth\nbasicConstraints=CA:FALSE") \
+    -out server.pem
+openssl x509 -req -in client.csr -CA ca.pem -CAkey ca-key.pem -set_serial 3 -days 36500 \
+    -extfile <(printf "extendedKeyUsage=clientAuth\nbasicConstraints=CA:FALSE") \
+    -out client.pem
+# a client certificate with its key in the same file
+cat client.pem client-key.pem > client-combined.pem
+
+rm ca-key.pem server.csr client.csr
@@ -0,0 +1,96 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{AuditCheck, AuditPolicy, Repository};
+
+mod common;
+
+fn fixture_repo() -> Repository {
+    common::repository(
+        [
+            ("bash", "GPLv3+", "Fedora Project"),
+            ("zlib", "zlib and Boost", "Fedora Project"),
+            ("agpl-tool", "MIT and (AGPLv3 or GPLv2)", "Fedora Project"),
+            ("unlicensed", "", "Fedora Project"),
+            ("vendored", "MIT", "Acme, Inc."),
+            ("orphan", "MIT", " "),
+        ]
+        .map(|(name, license, vendor)| {
+            common::package_builder(name, "1.0", "noarch")
+                .rpm_license(license)
+                .rpm_vendor(vendor)
+                .build()
+                .unwrap()
+        }),
+    )
+}
+
+#[test]
+fn test_audit() {
+    let repo = fixture_repo();
+    let policy = AuditPolicy::default()
+        .deny_license("AGPLv3")
+        .deny_license("GPLv3")
+        .deny_vendor("Acme, Inc.");
+    let report = repo.audit(&policy);
+
+    assert_eq!(
+        report.licenses.keys().collect::<Vec<_>>(),
+        [
+            "",
+            "GPLv3+",
+            "MIT",
+            "MIT and (AGPLv3 or GPLv2)",
+            "zlib and Boost"
+        ]
+    );
+    assert_eq!(
+        report.licenses["MIT"],
+        ["vendored-0:1.0-1.noarch", "orphan-0:1.0-1.noarch"]
+    );
+    assert_eq!(report.vendors["Fedora Project"].len(), 4);
+    assert_eq!(report.vendors[""], ["orphan-0:1.0-1.noarch"]);
+
+    // licenses are matched exactly, so GPLv3+ isn't denied by GPLv3
+    assert!(!report.is_clean());
+    let findings: Vec<String> = report.findings.iter().map(|f| f.to_string()).collect();
+    assert_eq!(
+        findings,
+        [
+            "denied-license: agpl-tool-0:1.0-1.noarch: AGPLv3",
+            "missing-license: unlicensed-0:1.0-1.noarch",
+            "denied-vendor: vendored-0:1.0-1.noarch: Acme, Inc.",
+            "missing-vendor: orphan-0:1.0-1.noarch",
+        ]
+    );
+    assert_eq!(report.with_check(AuditCheck::DeniedVendor).count(), 1);
+
+    assert_eq!(
+        report.to_csv(),
+        "check,package,value\n\
+         denied-license,agpl-tool-0:1.0-1.noarch,AGPLv3\n\
+         missing-license,unlicensed-0:1.0-1.noarch,\n\
+         denied-vendor,vendored-0:1.0-1.noarch,\"Acme, Inc.\"\n\
+         missing-vendor,orphan-0:1.0-1.noarch,\n"
+    );
+    assert!(report
+        .to_string()
+        .ends_with("4 findings, 5 licenses, 3 vendors"));
+}
+
+#[test]
+fn test_audit_whole_expression() {
+    let repo = fixture_repo();
+    let report = repo.audit(&AuditPolicy::default().deny_license("zlib and Boost"));
+    assert_eq!(
+        report
+            .with_check(AuditCheck::DeniedLicense)
+            .map(|f| (f.package.as_str(), f.value.as_str()))
+            .collect::<Vec<_>>(),
+        [("zlib-0:1.0-1.noarch", "zlib and Boost")]
+    );
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    utils, ChecksumType, LocalizedText, MetadataError, Pattern, Product, Repository, Requirement,
+    UpdateRecord, EVR,
+};
+use tempdir::TempDir;
+
+mod common;
+
+fn fixture_repo() -> Repository {
+    let mut repo = common::repository([
+        common::COMPLEX_PACKAGE.clone(),
+        common::RPM_EMPTY.clone(),
+        common::RPM_WITH_NON_ASCII.clone(),
+    ]);
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2023-0001")
+        .from("updates@fedoraproject.org")
+        .title("rpm-empty bug fix update")
+        .update_type("bugfix")
+        .issued_date("2023-04-18 00:00:00")
+        .updated_date("2023-04-18 00:00:00")
+        .packages("F38", "Fedora 38", [&*common::RPM_EMPTY])
+        .build()
+        .unwrap();
+    repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    repo.products_mut().push(Product {
+        vendor: "SUSE".to_owned(),
+        name: "SLES".to_owned(),
+        evr: EVR::new("0", "15.4", "0"),
+        arch: "x86_64".to_owned(),
+        ..Product::default()
+    });
+    repo.patterns_mut().push(Pattern {
+        name: "apparmor".to_owned(),
+        summary: vec![
+            LocalizedText::new(None, "AppArmor"),
+            LocalizedText::new(Some("de"), "AppArmor-Sicherheit"),
+        ],
+        uservisible: true,
+        icon: Some("pattern-apparmor".to_owned()),
+        rpm_requires: vec![Requirement {
+            name: "apparmor-parser".to_owned(),
+            ..Requirement::default()
+        }],
+        ..Pattern::default()
+    });
+    repo.repomd_mut().set_revision("1681833600");
+    repo.repomd_mut()
+        .add_content_tag("binary-x86_64".to_owned());
+    repo.repomd_mut().add_distro_tag(
+        "Fedora 38".to_owned(),
+        Some("cpe:/o:fedoraproject:fedora:38".to_owned()),
+    );
+    repo
+}
+
+#[test]
+fn test_binary_roundtrip() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_binary_roundtrip")?;
+    let mut repo = fixture_repo();
+    repo.write_to_directory(tmp_dir.path())?;
+    // the records written along with the metadata
+    let repo_with_records = Repository::load_from_directory(tmp_dir.path())?;
+    *repo.repomd_mut() = repo_with_records.repomd().clone();
+
+    let checksum = utils::checksum_bytes(b"repomd", ChecksumType::Sha256)?;
+    let mut written = Vec::new();
+    repo.write_binary(&checksum, &mut written)?;
+    let read = Repository::read_binary(written.as_slice(), &checksum)?.unwrap();
+    assert_eq!(read, repo);
+
+    // written from another repomd.xml
+    let other = utils::checksum_bytes(b"other", ChecksumType::Sha256)?;
+    assert!(Repository::read_binary(written.as_slice(), &other)?.is_none());
+    // written by a version of the library with another format
+    let mut other_version = written.clone();
+    other_version[8] += 1;
+    assert!(Repository::read_binary(other_version.as_slice(), &checksum)?.is_none());
+
+    assert!(Repository::read_binary(&written[..written.len() - 1], &checksum).is_err());
+    assert!(Repository::read_binary(&b"<?xml version=\"1.0\"?>"[..], &checksum).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_load_from_directory_cached() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_load_from_directory_cached")?;
+    let repo_path = tmp_dir.path().join("repo");
+    let cache_path = tmp_dir.path().join("repo.bin");
+    fixture_repo().write_to_directory(&repo_path)?;
+
+    let repo = Repository::load_from_directory_cached(&repo_path, &cache_path)?;
+    assert_eq!(repo, Repository::load_from_directory(&repo_path)?);
+    assert!(cache_path.exists());
+    assert_eq!(
+        Repository::load_from_directory_cached(&repo_path, &cache_path)?,
+        repo
+    );
+
+    // the cache is read instead of the metadata, as long as repomd.xml hasn't changed
+    let repomd = fs::read(repo_path.join("repodata/repomd.xml"))?;
+    let checksum = utils::checksum_bytes(&repomd, ChecksumType::Sha256)?;
+    let mut cached = Repository::new();
+    cached.patterns_mut().push(Pattern::default());
+    cached.write_binary(&checksum, fs::File::create(&cache_path)?)?;
+    assert_eq!(
+        Repository::load_from_directory_cached(&repo_path, &cache_path)?,
+        cached
+    );
+
+    // a cache file which can't be read is replaced
+    fs::write(&cache_path, b"garbage")?;
+    assert_eq!(
+        Repository::load_from_directory_cached(&repo_path, &cache_path)?,
+        repo
+    );
+    assert!(Repository::read_binary(fs::File::open(&cache_path)?, &checksum)?.is_some());
+
+    Ok(())
+}
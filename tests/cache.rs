@@ -0,0 +1,163 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    utils, ChecksumType, MetadataCache, MetadataError, MetadataExpire, Repository,
+};
+use tempdir::TempDir;
+
+mod common;
+
+fn write_repo(path: &Path) -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    let package = common::COMPLEX_PACKAGE.clone();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package);
+    repo.write_to_directory(path)
+}
+
+#[test]
+fn test_metadata_cache() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_metadata_cache")?;
+    let source = tmp_dir.path().join("download");
+    write_repo(&source)?;
+    let mut cache = MetadataCache::open(&tmp_dir.path().join("cache"))?;
+    assert!(cache.get("fedora", None)?.is_none());
+    assert!(cache.is_expired("fedora", MetadataExpire::Never)?);
+
+    let checksum = cache.store("fedora", &source)?;
+    let repomd = fs::read(source.join("repodata/repomd.xml"))?;
+    assert_eq!(
+        checksum,
+        utils::checksum_bytes(&repomd, ChecksumType::Sha256)?
+    );
+    assert_eq!(cache.repo_ids()?, ["fedora"]);
+    assert_eq!(cache.repomd_checksum("fedora")?, Some(checksum.clone()));
+
+    let repository = cache.get("fedora", Some(&checksum))?.unwrap();
+    assert_eq!(repository.packages().len(), 1);
+    // the repository is only loaded once
+    assert!(Arc::ptr_eq(
+        &cache.get("fedora", None)?.unwrap(),
+        &repository
+    ));
+    // the repository changed since it was cached
+    let other = utils::checksum_bytes(b"other", ChecksumType::Sha256)?;
+    assert!(cache.get("fedora", Some(&other))?.is_none());
+
+    assert!(!cache.is_expired("fedora", MetadataExpire::After(3600))?);
+    assert!(cache.is_expired("fedora", MetadataExpire::After(0))?);
+    let repomd_path = cache.entry_path("fedora")?.join("repodata/repomd.xml");
+    File::options()
+        .append(true)
+        .open(&repomd_path)?
+        .set_modified(SystemTime::now() - Duration::from_secs(7200))?;
+    assert!(cache.is_expired("fedora", MetadataExpire::After(3600))?);
+    cache.mark_checked("fedora")?;
+    assert!(!cache.is_expired("fedora", MetadataExpire::After(3600))?);
+
+    assert!(cache.entry_path("../etc").is_err());
+    assert!(cache.entry_path("fedora\\..\\etc").is_err());
+    assert!(cache.entry_path("fedora..").is_err());
+    assert!(cache.store(".hidden", &source).is_err());
+
+    // broken entries are removed
+    let primary = fs::read_dir(cache.entry_path("fedora")?.join("repodata"))?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().contains("primary"))
+        .unwrap();
+    fs::write(primary, b"truncated")?;
+    let mut cache = MetadataCache::open(cache.path())?;
+    assert!(cache.get("fedora", None)?.is_none());
+    assert!(cache.repo_ids()?.is_empty());
+
+    // only complete metadata is cached
+    let primary = fs::read_dir(source.join("repodata"))?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().contains("primary"))
+        .unwrap();
+    fs::write(primary, b"truncated")?;
+    assert!(cache.store("fedora", &source).is_err());
+    assert!(cache.repo_ids()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_cache_href_outside() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_metadata_cache_href_outside")?;
+    let source = tmp_dir.path().join("download");
+    write_repo(&source)?;
+    let mut cache = MetadataCache::open(&tmp_dir.path().join("cache"))?;
+    cache.store("fedora", &source)?;
+
+    // files outside of the entry are neither stored nor read
+    let escape = |repomd_path: &Path| -> Result<(), MetadataError> {
+        let repomd = fs::read_to_string(repomd_path)?;
+        fs::write(
+            repomd_path,
+            repomd.replace("href=\"repodata/", "href=\"../../download/repodata/"),
+        )?;
+        Ok(())
+    };
+    escape(&source.join("repodata/repomd.xml"))?;
+    assert!(matches!(
+        cache.store("fedora", &source),
+        Err(MetadataError::InvalidFieldError("location_href", _))
+    ));
+    escape(&cache.entry_path("fedora")?.join("repodata/repomd.xml"))?;
+    let mut cache = MetadataCache::open(cache.path())?;
+    assert!(cache.get("fedora", None)?.is_none());
+    assert!(cache.repo_ids()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_cache_evict() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_metadata_cache_evict")?;
+    let source = tmp_dir.path().join("download");
+    write_repo(&source)?;
+    let cache_path = tmp_dir.path().join("cache");
+
+    let mut cache = MetadataCache::open(&cache_path)?;
+    for repo_id in ["appstream", "baseos", "extras"] {
+        cache.store(repo_id, &source)?;
+    }
+    let set_used = |repo_id: &str, ago: u64| {
+        File::options()
+            .append(true)
+            .open(cache_path.join(repo_id).join(".used"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(ago))
+            .unwrap();
+    };
+    set_used("appstream", 600);
+    set_used("baseos", 60);
+    set_used("extras", 7200);
+    assert!(cache.evict()?.is_empty());
+
+    let size = cache.size()?;
+    let mut cache = MetadataCache::open(&cache_path)?
+        .max_age(3600)
+        .max_size(size / 2);
+    assert_eq!(cache.evict()?, ["extras", "appstream"]);
+    assert_eq!(cache.repo_ids()?, ["baseos"]);
+
+    // getting an entry uses it
+    let mut cache = MetadataCache::open(&cache_path)?.max_age(3600);
+    set_used("baseos", 7200);
+    assert!(cache.get("baseos", None)?.is_some());
+    assert!(cache.evict()?.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::capability::{self, Providers};
+use rpmrepo_metadata::{FileType, MetadataError, Package, Repository, Requirement, UpdateRecord};
+
+mod common;
+
+use common::requirement;
+
+#[test]
+fn test_satisfies_versions() {
+    let cases = [
+        // (provide, require, satisfied)
+        ("foo", "foo", true),
+        ("foo", "bar", false),
+        ("foo", "foo >= 1.0", true),
+        ("foo = 1.0-1", "foo", true),
+        ("foo = 1.0-1", "foo >= 1.0", true),
+        ("foo = 1.0-1", "foo > 1.0", false),
+        ("foo = 1.0-1", "foo > 1.0-0", true),
+        ("foo = 1.0-1", "foo < 1.0-2", true),
+        ("foo = 1.0-1", "foo <= 0.9", false),
+        ("foo = 1.0-1", "foo = 1.0", true),
+        ("foo = 1.0-1", "foo = 1.0-2", false),
+        ("foo = 1.0", "foo = 1.0-2", true),
+        ("foo = 2.0~rc1-1", "foo >= 2.0", false),
+        ("foo = 2.0^git1-1", "foo > 2.0", true),
+        // a missing epoch is 0
+        ("foo = 1:1.0-1", "foo >= 2.0", true),
+        ("foo = 1.0-1", "foo >= 0:1.0", true),
+        ("foo = 1.0-1", "foo >= 1:0.1", false),
+        // ranges which overlap
+        ("foo >= 2.0", "foo < 3.0", true),
+        ("foo >= 2.0", "foo >= 3.0", true),
+        ("foo >= 2.0", "foo < 2.0", false),
+        ("foo >= 2.0", "foo <= 2.0", true),
+        ("foo < 2.0", "foo > 2.0", false),
+        ("foo < 2.0", "foo = 1.0", true),
+        ("foo > 2.0", "foo = 2.0", false),
+    ];
+    for (provide, require, satisfied) in cases {
+        assert_eq!(
+            capability::satisfies(&requirement(provide), &requirement(require)),
+            satisfied,
+            "{} satisfies {}",
+            provide,
+            require
+        );
+        // overlapping is symmetric
+        assert_eq!(
+            capability::ranges_overlap(&requirement(require), &requirement(provide)),
+            provide.split(' ').next() != require.split(' ').next() || satisfied,
+            "{} overlaps {}",
+            require,
+            provide
+        );
+    }
+}
+
+#[test]
+fn test_satisfies_rich() {
+    let provided = ["foo = 1.0-1", "bar = 2.0-1"];
+    let is_provided = |require: &Requirement| {
+        provided
+            .iter()
+            .any(|p| capability::satisfies(&requirement(p), require))
+    };
+    let cases = [
+        ("(foo or baz)", true),
+        ("(baz or qux)", false),
+        ("(foo and bar)", true),
+        ("(foo and baz)", false),
+        ("(foo >= 1.0 and bar < 2.0)", false),
+        ("(foo >= 1.0 and (bar < 2.0 or bar >= 2.0-1))", true),
+        ("(foo and bar and baz)", false),
+        ("(baz or qux or bar)", true),
+        ("(baz if foo)", false),
+        ("(baz if qux)", true),
+        ("(baz if qux else bar)", true),
+        ("(baz if foo else bar)", false),
+        ("(baz unless foo)", true),
+        ("(baz unless qux)", false),
+        ("(baz unless foo else bar)", true),
+        ("(foo with bar)", true),
+        ("(foo without bar)", false),
+        ("(foo without baz)", true),
+        ("(foo)", true),
+        // which can't be parsed
+        ("(foo or", false),
+        ("(foo bar)", false),
+        ("(foo or bar) and", false),
+    ];
+    for (require, satisfied) in cases {
+        assert!(capability::is_rich(&requirement(require)));
+        assert_eq!(
+            capability::evaluate(&requirement(require), is_provided),
+            satisfied,
+            "{}",
+            require
+        );
+    }
+
+    // a single provide satisfies a rich dependency it's enough for
+    assert!(capability::satisfies(
+        &requirement("foo = 1.0-1"),
+        &requirement("(foo or bar)")
+    ));
+    assert!(!capability::satisfies(
+        &requirement("foo = 1.0-1"),
+        &requirement("(foo and bar)")
+    ));
+}
+
+#[test]
+fn test_package_satisfies() {
+    let mut shell = common::package_with_deps("shell", "5.1", "x86_64", &["sh"], &[]);
+    shell.add_file(FileType::File, "/usr/bin/sh");
+    shell.add_file(FileType::File, "/usr/bin/bash");
+    assert!(capability::package_satisfies(
+        &shell,
+        &requirement("shell >= 5")
+    ));
+    assert!(capability::package_satisfies(
+        &shell,
+        &requirement("shell(x86-64)")
+    ));
+    assert!(capability::package_satisfies(&shell, &requirement("sh")));
+    assert!(capability::package_satisfies(
+        &shell,
+        &requirement("/usr/bin/sh")
+    ));
+    assert!(!capability::package_satisfies(
+        &shell,
+        &requirement("/usr/bin/zsh")
+    ));
+    assert!(!capability::package_satisfies(
+        &shell,
+        &requirement("shell < 5")
+    ));
+    assert!(capability::package_satisfies(
+        &shell,
+        &requirement("(/usr/bin/bash and sh)")
+    ));
+}
+
+#[test]
+fn test_providers() {
+    let mut shell = common::package_with_deps("shell", "5.1", "x86_64", &["sh"], &[]);
+    shell.add_file(FileType::File, "/usr/bin/sh");
+    let mut busybox = common::package_with_deps("busybox", "1.36", "x86_64", &["sh"], &[]);
+    busybox.add_file(FileType::File, "/usr/sbin/sh");
+    let libc = common::package_with_deps("libc", "2.34", "x86_64", &[], &[]);
+    let providers = Providers::new([&shell, &busybox, &libc]);
+
+    let names = |packages: Vec<&Package>| -> Vec<String> {
+        packages.iter().map(|p| p.name().to_owned()).collect()
+    };
+    assert_eq!(
+        names(providers.what_provides(&requirement("sh"))),
+        ["shell", "busybox"]
+    );
+    assert_eq!(
+        names(providers.what_provides(&requirement("/usr/sbin/sh"))),
+        ["busybox"]
+    );
+    assert_eq!(
+        names(providers.what_provides(&requirement("(busybox or libc >= 2.30)"))),
+        ["busybox", "libc"]
+    );
+    assert!(providers.what_provides(&requirement("libc < 2")).is_empty());
+
+    // several packages can satisfy a rich dependency together
+    assert!(providers
+        .what_provides(&requirement("(shell and libc)"))
+        .is_empty());
+    assert!(providers.is_satisfied(&requirement("(shell and libc)")));
+    assert!(!providers.is_satisfied(&requirement("(shell and glibc)")));
+}
+
+#[test]
+fn test_repository_queries() {
+    let mut shell = common::package_with_deps(
+        "shell",
+        "5.1",
+        "x86_64",
+        &[],
+        &["libc >= 2.0", "rpmlib(PayloadFilesHavePrefix) <= 4.0-1"],
+    );
+    shell.add_file(FileType::File, "/usr/bin/sh");
+    let libc = common::package_with_deps(
+        "libc",
+        "2.34",
+        "x86_64",
+        &[],
+        &["/usr/bin/sh", "(tzdata if glibc-langpack)"],
+    );
+    let docs = common::package_with_deps(
+        "docs",
+        "1.0",
+        "x86_64",
+        &[],
+        &["shell < 5.0", "(libc or musl)"],
+    );
+    let repo = common::repository([shell, libc, docs]);
+
+    let providers: Vec<&str> = repo
+        .whatprovides(&requirement("/usr/bin/sh"))
+        .iter()
+        .map(|p| p.name())
+        .collect();
+    assert_eq!(providers, ["shell"]);
+
+    let unresolved: Vec<(&str, &str)> = repo
+        .repoclosure()
+        .into_iter()
+        .map(|(package, require)| (package.name(), require.name.as_str()))
+        .collect();
+    assert_eq!(unresolved, [("docs", "shell")]);
+}
+
+#[test]
+fn test_advisory_applies_to() {
+    let installed = [
+        common::package_with_deps("shell", "5.1", "x86_64", &[], &[]),
+        common::package_with_deps("libc", "2.34", "x86_64", &[], &[]),
+    ];
+    let update = |name: &str, version: &str, arch: &str| {
+        let update = common::package(name, version, arch);
+        UpdateRecord::builder()
+            .id("FEDORA-2023-0001")
+            .from("updates@fedoraproject.org")
+            .title("update")
+            .update_type("bugfix")
+            .issued_date("2023-04-18 00:00:00")
+            .packages("F38", "Fedora 38", [&update])
+            .build()
+            .unwrap()
+    };
+
+    assert!(update("shell", "5.2", "x86_64").applies_to(&installed));
+    assert!(!update("shell", "5.1", "x86_64").applies_to(&installed));
+    assert!(!update("shell", "5.0", "x86_64").applies_to(&installed));
+    assert!(!update("shell", "5.2", "src").applies_to(&installed));
+    assert!(!update("shell", "5.2", "aarch64").applies_to(&installed));
+    assert!(!update("zsh", "5.9", "x86_64").applies_to(&installed));
+}
+
+#[test]
+fn test_estimated_install_size() -> Result<(), MetadataError> {
+    let sized = |name: &str, version: &str, provides: &[&str], size: u64| {
+        let mut package = common::package_with_deps(name, version, "x86_64", provides, &[]);
+        package.set_size_installed(size);
+        package
+    };
+    let mut app = sized("app", "1.0", &[], 1000);
+    app.set_requires(vec![
+        requirement("libfoo >= 2"),
+        requirement("sh"),
+        requirement("rpmlib(CompressedFileNames) <= 3.0.4-1"),
+        requirement("missing"),
+    ]);
+    let mut libfoo = sized("libfoo", "2.1", &[], 200);
+    libfoo.set_requires(vec![requirement("sh"), requirement("app")]);
+    let old_libfoo = sized("libfoo", "1.0", &[], 100);
+    let bash = sized("bash", "5.2", &["sh"], 30);
+    let dash = sized("dash", "0.5", &["sh"], 4);
+    let busybox = sized("busybox", "1.36", &["sh"], 50);
+
+    let mut repo = Repository::new();
+    // both builds of libfoo have the same checksum, so they're keyed by NVRA instead
+    for package in [app, libfoo, old_libfoo, bash, dash, busybox] {
+        repo.packages_mut().insert(package.nvra(), package);
+    }
+
+    // of the providers of sh, the one with the newest EVR is picked
+    let closure: Vec<String> = repo
+        .dependency_closure(&["app-0:1.0-1.x86_64"])?
+        .iter()
+        .map(|p| p.nvra())
+        .collect();
+    assert_eq!(
+        closure,
+        [
+            "app-1.0-1.x86_64",
+            "libfoo-2.1-1.x86_64",
+            "bash-5.2-1.x86_64"
+        ]
+    );
+    assert_eq!(repo.estimated_install_size(&["app-0:1.0-1.x86_64"])?, 1230);
+
+    // a provider which is part of the closure anyway is preferred
+    assert_eq!(
+        repo.estimated_install_size(&["app-0:1.0-1.x86_64", "dash-0:0.5-1.x86_64"])?,
+        1204
+    );
+    assert_eq!(repo.estimated_install_size(&[])?, 0);
+    assert!(repo
+        .estimated_install_size(&["zsh-0:5.9-1.x86_64"])
+        .is_err());
+
+    Ok(())
+}
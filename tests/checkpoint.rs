@@ -0,0 +1,74 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{MetadataError, ScanCheckpoint};
+use tempdir::TempDir;
+
+mod common;
+
+#[test]
+fn test_resume_checkpoint() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_resume_checkpoint")?;
+    let path = tmp_dir.path().join("checkpoint");
+
+    let mut checkpoint = ScanCheckpoint::open(&path)?.interval(2);
+    assert!(checkpoint.is_empty());
+    for href in ["a.rpm", "b.rpm", "c.rpm"] {
+        checkpoint.add(common::package_at(href))?;
+    }
+    assert_eq!(checkpoint.len(), 3);
+    // interrupted before c.rpm was saved
+    drop(checkpoint);
+
+    let mut checkpoint = ScanCheckpoint::open(&path)?.interval(2);
+    assert_eq!(checkpoint.len(), 2);
+    assert_eq!(checkpoint.get("a.rpm"), Some(&common::package_at("a.rpm")));
+    assert_eq!(checkpoint.get("c.rpm"), None);
+    checkpoint.add(common::package_at("c.rpm"))?;
+    checkpoint.save()?;
+
+    let checkpoint = ScanCheckpoint::open(&path)?;
+    assert_eq!(checkpoint.len(), 3);
+    assert_eq!(checkpoint.get("c.rpm"), Some(&common::package_at("c.rpm")));
+
+    checkpoint.remove()?;
+    assert!(!path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_checkpoint() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_validate_checkpoint")?;
+    let path = tmp_dir.path().join("checkpoint");
+
+    let mut checkpoint = ScanCheckpoint::open(&path)?.interval(1);
+    for href in ["a.rpm", "b.rpm", "c.rpm"] {
+        checkpoint.add(common::package_at(href))?;
+    }
+
+    // a damaged batch is dropped along with the batches after it
+    std::fs::write(
+        path.join("batch-000001/repodata/primary.xml.zst"),
+        b"garbage",
+    )?;
+    let mut checkpoint = ScanCheckpoint::open(&path)?.interval(1);
+    assert_eq!(checkpoint.len(), 1);
+    assert_eq!(checkpoint.get("a.rpm"), Some(&common::package_at("a.rpm")));
+    assert!(!path.join("batch-000002").exists());
+
+    checkpoint.add(common::package_at("b.rpm"))?;
+    assert_eq!(ScanCheckpoint::open(&path)?.len(), 2);
+
+    // as is everything, if the list of batches can't be read
+    std::fs::write(path.join("CHECKPOINT"), b"something else")?;
+    let checkpoint = ScanCheckpoint::open(&path)?;
+    assert!(checkpoint.is_empty());
+    assert!(!path.join("batch-000000").exists());
+
+    Ok(())
+}
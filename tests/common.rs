@@ -4,8 +4,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+#![allow(dead_code)]
+
 use once_cell::sync::Lazy;
-use rpmrepo_metadata::{Checksum, FileType, Package, Requirement, EVR};
+use rpmrepo_metadata::{
+    utils, Checksum, ChecksumType, FileType, Package, PackageBuilder, Repository, Requirement, EVR,
+};
 
 // const FIXTURE_FILELIST_PATH: &str = "./tests/assets/complex_repo/repodata/filelists.xml.gz";
 
@@ -357,3 +361,163 @@ pub fn complex_repo_fixture_data() -> Vec<&'static Package> {
     ]
     // })
 }
+
+/// A builder of the package `name-version-1.arch`, checksummed with the SHA-256 of its NEVRA so that
+/// distinct packages get distinct ids
+pub fn package_builder(name: &str, version: &str, arch: &str) -> PackageBuilder {
+    let nevra = format!("{}-{}-1.{}", name, version, arch);
+    Package::builder()
+        .name(name)
+        .arch(arch)
+        .evr(EVR::new("0", version, "1"))
+        .checksum(utils::checksum_bytes(nevra.as_bytes(), ChecksumType::Sha256).unwrap())
+        .location_href(format!("{}.rpm", nevra))
+}
+
+/// The package `name-version-1.arch`, see [`package_builder`]
+pub fn package(name: &str, version: &str, arch: &str) -> Package {
+    package_builder(name, version, arch).build().unwrap()
+}
+
+/// [`COMPLEX_PACKAGE`] stored at `location_href`
+pub fn package_at(location_href: &str) -> Package {
+    let mut package = COMPLEX_PACKAGE.clone();
+    package.set_location_href(location_href);
+    package
+}
+
+/// A requirement (or a provide) written the way spec files do: `foo`, `foo >= 1.0`, `foo = 1:2.0-3` or a
+/// rich dependency such as `(foo or bar)`
+pub fn requirement(dependency: &str) -> Requirement {
+    let mut parts = dependency.split_whitespace();
+    let (Some(name), Some(operator), Some(evr), false) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        dependency.starts_with('('),
+    ) else {
+        return Requirement {
+            name: dependency.to_owned(),
+            ..Requirement::default()
+        };
+    };
+    let flags = match operator {
+        "<" => "LT",
+        "<=" => "LE",
+        "=" => "EQ",
+        ">=" => "GE",
+        ">" => "GT",
+        other => panic!("unknown operator {}", other),
+    };
+    let (epoch, version, release) = EVR::parse_values(evr);
+    Requirement {
+        name: name.to_owned(),
+        flags: Some(flags.to_owned()),
+        epoch: (!epoch.is_empty()).then(|| epoch.to_owned()),
+        version: Some(version.to_owned()),
+        release: (!release.is_empty()).then(|| release.to_owned()),
+        ..Requirement::default()
+    }
+}
+
+/// The package `name-version-1.arch` (see [`package_builder`]) which provides itself and `provides`, and
+/// requires `requires`, see [`requirement`]
+pub fn package_with_deps(
+    name: &str,
+    version: &str,
+    arch: &str,
+    provides: &[&str],
+    requires: &[&str],
+) -> Package {
+    package_builder(name, version, arch)
+        .provides(provides.iter().map(|p| requirement(p)).collect())
+        .requires(requires.iter().map(|r| requirement(r)).collect())
+        .self_provides(true)
+        .build()
+        .unwrap()
+}
+
+/// A repository of `packages`, keyed by their pkgid
+pub fn repository(packages: impl IntoIterator<Item = Package>) -> Repository {
+    let mut repo = Repository::new();
+    for package in packages {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+    repo
+}
+
+/// Encode a zchunk "compressed integer".
+pub fn zck_int(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.push(value as u8 | 0x80);
+    bytes
+}
+
+/// Build a zchunk file from already compressed chunks and their uncompressed lengths, the first of which
+/// is the (possibly empty) dictionary, returning it along with its header checksum.
+pub fn zck_file(chunks: &[(&[u8], usize)]) -> (Vec<u8>, String) {
+    use sha2::{Digest, Sha256};
+
+    let mut index = Vec::new();
+    index.extend(zck_int(1)); // SHA-256 chunk checksums
+    index.extend(zck_int(chunks.len() as u64));
+    for (chunk, uncompressed_len) in chunks {
+        index.extend(Sha256::digest(chunk));
+        index.extend(zck_int(chunk.len() as u64));
+        index.extend(zck_int(*uncompressed_len as u64));
+    }
+    let data: Vec<u8> = chunks
+        .iter()
+        .flat_map(|(chunk, _)| *chunk)
+        .copied()
+        .collect();
+
+    let mut header = Sha256::digest(&data).to_vec();
+    header.extend(zck_int(0)); // flags
+    header.extend(zck_int(2)); // zstd
+    header.extend(zck_int(index.len() as u64));
+    header.extend(index);
+    header.extend(zck_int(0)); // signatures
+
+    let mut file = b"\0ZCK1".to_vec();
+    file.extend(zck_int(1)); // SHA-256
+    file.extend(zck_int(header.len() as u64));
+    let header_checksum = Sha256::new()
+        .chain_update(&file)
+        .chain_update(&header)
+        .finalize();
+    file.extend(header_checksum);
+    file.extend(header);
+    file.extend(data);
+    (file, hex::encode(header_checksum))
+}
+
+/// A ustar header of an entry of `kind`, e.g. `b'0'` for a regular file.
+pub fn tar_header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+    let mut header = vec![0u8; 512];
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|byte| u32::from(*byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+/// Append an entry with `header` and `data` to `archive`, padded to a whole block.
+pub fn tar_entry(archive: &mut Vec<u8>, header: Vec<u8>, data: &[u8]) {
+    archive.extend(header);
+    archive.extend(data);
+    archive.resize(archive.len().div_ceil(512) * 512, 0);
+}
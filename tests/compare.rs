@@ -0,0 +1,163 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    CompareOptions, DifferenceKind, MetadataError, Repository, RepositoryOptions, UpdateRecord,
+};
+use tempdir::TempDir;
+mod common;
+
+fn fixture_repo() -> Repository {
+    let mut repo = common::repository([common::COMPLEX_PACKAGE.clone(), common::RPM_EMPTY.clone()]);
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2023-0001")
+        .from("updates@fedoraproject.org")
+        .title("rpm-empty bug fix update")
+        .update_type("bugfix")
+        .issued_date("2023-04-18 00:00:00")
+        .packages("F38", "Fedora 38", [&*common::RPM_EMPTY])
+        .build()
+        .unwrap();
+    repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    repo
+}
+
+#[test]
+fn test_compare_equivalent_repositories() -> Result<(), MetadataError> {
+    let first_dir = TempDir::new("test_compare_equivalent_repositories")?;
+    let second_dir = TempDir::new("test_compare_equivalent_repositories")?;
+
+    let repo = fixture_repo();
+    repo.write_to_directory(first_dir.path())?;
+
+    // the same content, written in a different order, with unique filenames and other file times
+    let mut other = fixture_repo();
+    other.packages_mut().reverse();
+    for package in other.packages_mut().values_mut() {
+        package.rpm_files.reverse();
+        package.time_file += 3600;
+    }
+    other.write_to_directory_with_options(
+        second_dir.path(),
+        RepositoryOptions::default().simple_metadata_filenames(false),
+    )?;
+
+    let first = Repository::load_from_directory(first_dir.path())?;
+    let second = Repository::load_from_directory(second_dir.path())?;
+    let diff = first.compare(&second, CompareOptions::default())?;
+    assert!(diff.is_empty(), "{}", diff);
+    assert_eq!(diff.to_string(), "0 differences");
+
+    // nothing ignored
+    let diff = first.compare(
+        &second,
+        CompareOptions::default()
+            .ignore_file_order(false)
+            .ignore_timestamps(false)
+            .ignore_filename_prefixes(false),
+    )?;
+    let changed: Vec<(&str, &[&str])> = diff
+        .differences
+        .iter()
+        .filter(|d| d.metadata != "repomd")
+        .map(|d| (d.entry.as_str(), &d.fields[..]))
+        .collect();
+    assert_eq!(
+        changed,
+        vec![
+            (
+                "complex-package-1:2.3.4-5.el8.x86_64",
+                &["time_file", "rpm_files"][..]
+            ),
+            ("rpm-empty-0:0-0.x86_64", &["time_file"][..]),
+        ]
+    );
+    assert!(diff
+        .differences
+        .iter()
+        .filter(|d| d.metadata == "repomd" && d.entry != "repomd.xml")
+        .all(|d| d.fields.contains(&"location_href")));
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_different_repositories() -> Result<(), MetadataError> {
+    let first = fixture_repo();
+    let mut second = fixture_repo();
+    let complex_pkgid = common::COMPLEX_PACKAGE.pkgid().to_owned();
+    let package = second.packages_mut().get_mut(&complex_pkgid).unwrap();
+    package.set_summary("Something else");
+    package.rpm_requires.pop();
+    second
+        .packages_mut()
+        .shift_remove(common::RPM_EMPTY.pkgid());
+    let package = common::RPM_WITH_NON_ASCII.clone();
+    second
+        .packages_mut()
+        .insert(package.pkgid().to_owned(), package);
+    second.advisories_mut().clear();
+
+    let diff = first.compare(&second, CompareOptions::default())?;
+    let differences: Vec<String> = diff.differences.iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        differences,
+        vec![
+            "changed: primary: complex-package-1:2.3.4-5.el8.x86_64: summary, rpm_requires",
+            "only-in-first: primary: rpm-empty-0:0-0.x86_64",
+            "only-in-second: primary: rpm-with-non-ascii-0:1-1.fc33.noarch",
+            "only-in-first: updateinfo: FEDORA-2023-0001",
+        ]
+    );
+    assert_eq!(diff.differences[0].kind, DifferenceKind::Changed);
+    assert!(diff.to_string().ends_with("\n4 differences"));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_update() {
+    let package = common::package;
+    let base = common::repository([
+        package("bash", "5.2.15", "x86_64"),
+        package("bash", "5.2.15", "i686"),
+        package("python3", "3.11.2", "x86_64"),
+        package("zlib", "1.2.13", "x86_64"),
+        package("tzdata", "2023a", "noarch"),
+    ]);
+    let candidate = common::repository([
+        package("bash", "5.2.15", "src"),
+        package("bash", "5.2.21", "x86_64"),
+        package("bash", "5.2.26", "x86_64"),
+        package("bash", "5.2.15", "i686"),
+        package("python3", "3.11.1", "x86_64"),
+        package("tzdata", "2023c", "noarch"),
+        package("glibc", "2.37", "x86_64"),
+    ]);
+
+    let updates = base.check_update(&candidate);
+    let lines: Vec<String> = updates.iter().map(|u| u.to_string()).collect();
+    assert_eq!(
+        lines,
+        [
+            "bash.i686: 0:5.2.15-1 -> 0:5.2.15-1",
+            "bash.x86_64: 0:5.2.15-1 -> 0:5.2.26-1",
+            "glibc.x86_64: - -> 0:2.37-1",
+            "python3.x86_64: 0:3.11.2-1 -> 0:3.11.1-1",
+            "tzdata.noarch: 0:2023a-1 -> 0:2023c-1",
+            "zlib.x86_64: 0:1.2.13-1 -> -",
+        ]
+    );
+    let upgrades: Vec<&str> = updates
+        .iter()
+        .filter(|u| u.is_upgrade())
+        .map(|u| u.name.as_str())
+        .collect();
+    assert_eq!(upgrades, ["bash", "tzdata"]);
+    assert!(updates[3].is_downgrade());
+    assert!(!updates[2].is_upgrade() && !updates[2].is_downgrade());
+}
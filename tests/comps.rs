@@ -0,0 +1,492 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    Comps, CompsGroup, CompsPackageType, GroupSelection, Langpack, MetadataError, Repository,
+    Severity, ValidationCheck,
+};
+
+mod common;
+
+const COMPS_FIXTURE_PATH: &str = "./tests/assets/comps.xml";
+
+const COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE comps PUBLIC "-//Red Hat, Inc.//DTD Comps info//EN" "comps.dtd">
+<comps>
+  <group>
+    <id>core</id>
+    <name>Core</name>
+    <name xml:lang="de">Kern</name>
+    <description>Smallest possible installation</description>
+    <description xml:lang="de">Kleinstmögliche Installation</description>
+    <default>false</default>
+    <uservisible>false</uservisible>
+    <packagelist>
+      <packagereq type="mandatory">bash</packagereq>
+      <packagereq type="mandatory">coreutils</packagereq>
+      <packagereq type="default" arch="x86_64,aarch64">grub2-efi</packagereq>
+      <packagereq type="optional">zsh</packagereq>
+      <packagereq type="conditional" requires="selinux-policy">policycoreutils</packagereq>
+      <packagereq type="conditional" requires="bash" arch="s390x">s390utils</packagereq>
+    </packagelist>
+  </group>
+  <category>
+    <id>base-system</id>
+    <name>Base System</name>
+    <display_order>10</display_order>
+    <grouplist>
+      <groupid>core</groupid>
+      <groupid>standard</groupid>
+    </grouplist>
+  </category>
+  <environment>
+    <id>minimal-environment</id>
+    <name>Minimal Install</name>
+    <display_order>5</display_order>
+    <grouplist>
+      <groupid>core</groupid>
+    </grouplist>
+    <optionlist>
+      <groupid>guest-agents</groupid>
+    </optionlist>
+  </environment>
+  <langpacks>
+    <match name="hunspell" install="hunspell-%s"/>
+  </langpacks>
+</comps>
+"#;
+
+/// Another version of [`COMPS`], for merging
+const OTHER_COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<comps>
+  <group>
+    <id>core</id>
+    <name>Core Packages</name>
+    <name xml:lang="de">Kernpakete</name>
+    <name xml:lang="fr">Noyau</name>
+    <display_order>3</display_order>
+    <packagelist>
+      <packagereq type="optional">bash</packagereq>
+      <packagereq type="mandatory">zsh</packagereq>
+      <packagereq type="default" arch="ppc64le">grub2-efi</packagereq>
+      <packagereq type="conditional" requires="bash" arch="s390x">s390utils</packagereq>
+      <packagereq type="optional">vim-minimal</packagereq>
+      <packagereq type="default">vim-minimal</packagereq>
+    </packagelist>
+  </group>
+  <group>
+    <id>standard</id>
+    <name>Standard</name>
+    <packagelist>
+      <packagereq type="default">rsync</packagereq>
+      <packagereq type="mandatory">rsync</packagereq>
+    </packagelist>
+  </group>
+  <category>
+    <id>base-system</id>
+    <name>Base</name>
+    <display_order>20</display_order>
+    <grouplist>
+      <groupid>standard</groupid>
+      <groupid>hardware-support</groupid>
+    </grouplist>
+  </category>
+  <environment>
+    <id>minimal-environment</id>
+    <optionlist>
+      <groupid>standard</groupid>
+      <groupid>guest-agents</groupid>
+    </optionlist>
+  </environment>
+  <environment>
+    <id>server-environment</id>
+    <name>Server</name>
+    <grouplist>
+      <groupid>core</groupid>
+      <groupid>core</groupid>
+    </grouplist>
+  </environment>
+  <langpacks>
+    <match name="hunspell" install="hunspell-dict-%s"/>
+    <match name="glibc" install="glibc-langpack-%s"/>
+  </langpacks>
+</comps>
+"#;
+
+#[test]
+fn test_read_comps_fixture() -> Result<(), MetadataError> {
+    let comps = Comps::from_file(Path::new(COMPS_FIXTURE_PATH))?;
+    let ids: Vec<&str> = comps.groups.iter().map(|group| group.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        [
+            "additional-devel",
+            "backup-client",
+            "backup-server",
+            "ansible-node",
+            "d-development",
+            "empty-group-1",
+            "empty-group-2",
+            "unknown-group"
+        ]
+    );
+
+    let devel = comps.group("additional-devel").unwrap();
+    assert_eq!(devel.name, "Additional Development");
+    assert!(!devel.default && !devel.uservisible);
+    assert_eq!(devel.langonly.as_deref(), Some("fr"));
+    assert_eq!(devel.packages.len(), 6);
+
+    let ansible = comps.group("ansible-node").unwrap();
+    assert_eq!(ansible.description, "");
+    let conditional = &ansible.packages[1];
+    assert_eq!(conditional.name, "libselinux-python");
+    assert_eq!(conditional.package_type, CompsPackageType::Conditional);
+    assert_eq!(conditional.requires.as_deref(), Some("selinux-policy"));
+
+    let d = comps.group("d-development").unwrap();
+    assert!(d.packages[0].basearchonly);
+    assert!(!d.packages[4].basearchonly);
+    assert!(comps.group("empty-group-1").unwrap().packages.is_empty());
+    assert!(comps
+        .group("unknown-group")
+        .unwrap()
+        .packages
+        .iter()
+        .all(|package| package.package_type == CompsPackageType::Unknown));
+    Ok(())
+}
+
+#[test]
+fn test_read_comps() -> Result<(), MetadataError> {
+    let comps: Comps = COMPS.parse()?;
+    let core = comps.group("core").unwrap();
+    assert_eq!(core.name, "Core");
+    assert_eq!(core.translated_names.get("de").unwrap(), "Kern");
+    assert_eq!(
+        core.translated_descriptions.get("de").unwrap(),
+        "Kleinstmögliche Installation"
+    );
+    assert_eq!(core.packages[2].arches, ["x86_64", "aarch64"]);
+
+    let category = comps.category("base-system").unwrap();
+    assert_eq!(category.display_order, Some(10));
+    assert_eq!(category.group_ids, ["core", "standard"]);
+    let environment = comps.environment("minimal-environment").unwrap();
+    assert_eq!(environment.group_ids, ["core"]);
+    assert_eq!(environment.option_ids, ["guest-agents"]);
+    assert_eq!(
+        comps.langpacks,
+        [Langpack {
+            name: "hunspell".to_owned(),
+            install: "hunspell-%s".to_owned()
+        }]
+    );
+
+    assert!(matches!(
+        "<comps><group><name>no id</name></group></comps>".parse::<Comps>(),
+        Err(MetadataError::MissingFieldError("id"))
+    ));
+    assert_eq!(Comps::from_reader(COMPS.as_bytes())?, comps);
+    Ok(())
+}
+
+#[test]
+fn test_merge_comps() -> Result<(), MetadataError> {
+    let mut comps: Comps = COMPS.parse()?;
+    comps.merge(&OTHER_COMPS.parse()?);
+
+    let ids = |comps: &Comps| -> Vec<String> {
+        comps.groups.iter().map(|group| group.id.clone()).collect()
+    };
+    assert_eq!(ids(&comps), ["core", "standard"]);
+    let core = comps.group("core").unwrap();
+    // the first input wins, but fills in what it doesn't have from the second
+    assert_eq!(core.name, "Core");
+    assert_eq!(core.description, "Smallest possible installation");
+    assert!(!core.uservisible);
+    assert_eq!(core.display_order, Some(3));
+    assert_eq!(
+        core.translated_names.iter().collect::<Vec<_>>(),
+        [
+            (&"de".to_owned(), &"Kern".to_owned()),
+            (&"fr".to_owned(), &"Noyau".to_owned())
+        ]
+    );
+    assert_eq!(core.translated_descriptions.len(), 1);
+
+    // packages are listed once, with the strongest type
+    let packages: Vec<(&str, CompsPackageType)> = core
+        .packages
+        .iter()
+        .map(|package| (package.name.as_str(), package.package_type))
+        .collect();
+    assert_eq!(
+        packages,
+        [
+            ("bash", CompsPackageType::Mandatory),
+            ("coreutils", CompsPackageType::Mandatory),
+            ("grub2-efi", CompsPackageType::Default),
+            ("zsh", CompsPackageType::Mandatory),
+            ("policycoreutils", CompsPackageType::Conditional),
+            ("s390utils", CompsPackageType::Conditional),
+            ("vim-minimal", CompsPackageType::Default),
+        ]
+    );
+    assert_eq!(core.packages[2].arches, ["x86_64", "aarch64", "ppc64le"]);
+    assert!(core.packages[3].arches.is_empty());
+    assert_eq!(core.packages[5].requires.as_deref(), Some("bash"));
+    let standard = comps.group("standard").unwrap();
+    assert_eq!(standard.packages.len(), 1);
+    assert_eq!(
+        standard.packages[0].package_type,
+        CompsPackageType::Mandatory
+    );
+
+    let category = comps.category("base-system").unwrap();
+    assert_eq!(category.name, "Base System");
+    assert_eq!(category.display_order, Some(10));
+    assert_eq!(category.group_ids, ["core", "standard", "hardware-support"]);
+    let environment = comps.environment("minimal-environment").unwrap();
+    assert_eq!(environment.name, "Minimal Install");
+    assert_eq!(environment.group_ids, ["core"]);
+    assert_eq!(environment.option_ids, ["guest-agents", "standard"]);
+    assert_eq!(
+        comps.environment("server-environment").unwrap().group_ids,
+        ["core"]
+    );
+    assert_eq!(
+        comps.langpacks,
+        [
+            Langpack {
+                name: "hunspell".to_owned(),
+                install: "hunspell-%s".to_owned()
+            },
+            Langpack {
+                name: "glibc".to_owned(),
+                install: "glibc-langpack-%s".to_owned()
+            }
+        ]
+    );
+
+    // merging the same groups again changes nothing
+    let merged = comps.clone();
+    comps.merge(&merged);
+    assert_eq!(comps, merged);
+    let mut empty = Comps::new();
+    empty.merge(&merged);
+    assert_eq!(empty, merged);
+    Ok(())
+}
+
+#[test]
+fn test_validate_comps() -> Result<(), MetadataError> {
+    let comps: Comps = COMPS.parse()?;
+    let repo = common::repository([
+        common::package("bash", "1.0", "x86_64"),
+        common::package("grub2-efi", "1.0", "aarch64"),
+        common::package("policycoreutils", "1.0", "x86_64"),
+        common::package("s390utils", "1.0", "s390x"),
+        // source packages can't be installed
+        common::package("coreutils", "1.0", "src"),
+    ]);
+
+    let report = repo.validate_comps(&comps);
+    let issues: Vec<(Severity, ValidationCheck, &str)> = report
+        .issues
+        .iter()
+        .map(|issue| {
+            (
+                issue.severity,
+                issue.check,
+                issue.entry.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        issues,
+        [
+            (
+                Severity::Error,
+                ValidationCheck::CompsMissingPackage,
+                "core"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::CompsMissingPackage,
+                "core"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::CompsUnmatchableConditional,
+                "core"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::CompsUnmatchableConditional,
+                "core"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::CompsMissingGroup,
+                "base-system"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::CompsMissingGroup,
+                "minimal-environment"
+            ),
+        ]
+    );
+    assert_eq!(
+        report.issues[0].to_string(),
+        "error[comps-missing-package]: group: core: the mandatory package coreutils isn't in the repository"
+    );
+    assert_eq!(
+        report.issues[3].message,
+        "s390utils is conditional on bash, which isn't in the repository for s390x"
+    );
+    assert!(!report.is_valid());
+    Ok(())
+}
+
+const WORKSTATION_COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<comps>
+  <group>
+    <id>core</id>
+    <name>Core</name>
+    <packagelist>
+      <packagereq type="mandatory">bash</packagereq>
+      <packagereq type="default">vim-minimal</packagereq>
+      <packagereq type="optional">zsh</packagereq>
+      <packagereq type="default" arch="s390x">s390utils</packagereq>
+    </packagelist>
+  </group>
+  <group>
+    <id>office</id>
+    <name>Office</name>
+    <packagelist>
+      <packagereq type="mandatory">libreoffice-writer</packagereq>
+      <packagereq type="conditional" requires="libreoffice-writer">libreoffice-help</packagereq>
+      <packagereq type="conditional" requires="libreoffice-help">libreoffice-help-viewer</packagereq>
+      <packagereq type="conditional" requires="emacs">emacs-spell</packagereq>
+      <packagereq type="default">hunspell</packagereq>
+    </packagelist>
+  </group>
+  <group>
+    <id>games</id>
+    <name>Games</name>
+    <packagelist>
+      <packagereq type="default">supertux</packagereq>
+    </packagelist>
+  </group>
+  <environment>
+    <id>workstation-environment</id>
+    <name>Workstation</name>
+    <grouplist>
+      <groupid>core</groupid>
+      <groupid>office</groupid>
+      <groupid>multimedia</groupid>
+    </grouplist>
+    <optionlist>
+      <groupid>games</groupid>
+    </optionlist>
+  </environment>
+  <langpacks>
+    <match name="hunspell" install="hunspell-%s"/>
+  </langpacks>
+</comps>
+"#;
+
+#[test]
+fn test_resolve_groups() -> Result<(), MetadataError> {
+    let comps: Comps = WORKSTATION_COMPS.parse()?;
+    let names = |groups: &[&CompsGroup]| -> Vec<String> {
+        groups.iter().map(|group| group.id.clone()).collect()
+    };
+    assert_eq!(
+        names(&comps.environment_groups("workstation-environment").unwrap()),
+        ["core", "office"]
+    );
+    assert!(comps.environment_groups("server-environment").is_none());
+    let defaults: Vec<&str> = comps
+        .group("core")
+        .unwrap()
+        .default_packages()
+        .map(|package| package.name.as_str())
+        .collect();
+    assert_eq!(defaults, ["bash", "vim-minimal", "s390utils"]);
+    assert_eq!(
+        comps.langpacks_for("hunspell", "pt_BR.UTF-8"),
+        ["hunspell-pt_BR", "hunspell-pt"]
+    );
+    assert!(comps.langpacks_for("bash", "pt_BR").is_empty());
+
+    let mut repo = Repository::new();
+    for name in [
+        "bash",
+        "vim-minimal",
+        "zsh",
+        "libreoffice-writer",
+        "libreoffice-help",
+        "libreoffice-help-viewer",
+        "hunspell",
+        "hunspell-de",
+        "hunspell-pt_BR",
+        "hunspell-pt",
+        "supertux",
+    ] {
+        let package = common::package(name, "1.0", "x86_64");
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+
+    let selection = GroupSelection::default()
+        .environment("workstation-environment")
+        .arch("x86_64")
+        .locale("de_AT.UTF-8")
+        .locale("pt_BR");
+    let resolution = repo.resolve_groups(&comps, &selection);
+    assert_eq!(resolution.groups, ["core", "office"]);
+    assert_eq!(resolution.unknown, ["multimedia"]);
+    let packages: Vec<&str> = resolution.packages.iter().map(String::as_str).collect();
+    assert_eq!(
+        packages,
+        [
+            "bash",
+            "hunspell",
+            "hunspell-de",
+            "hunspell-pt_BR",
+            "libreoffice-help",
+            "libreoffice-help-viewer",
+            "libreoffice-writer",
+            "vim-minimal"
+        ]
+    );
+    assert!(resolution.missing_packages.is_empty());
+
+    // the options of an environment have to be selected, and packages for other arches are only left out
+    // when the arch is known
+    let selection = GroupSelection::default()
+        .group("core")
+        .group("games")
+        .group("core")
+        .with_optional(true);
+    let resolution = repo.resolve_groups(&comps, &selection);
+    assert_eq!(resolution.groups, ["core", "games"]);
+    let packages: Vec<&str> = resolution.packages.iter().map(String::as_str).collect();
+    assert_eq!(packages, ["bash", "supertux", "vim-minimal", "zsh"]);
+    let missing: Vec<&str> = resolution
+        .missing_packages
+        .iter()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(missing, ["s390utils"]);
+    Ok(())
+}
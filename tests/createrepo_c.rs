@@ -0,0 +1,280 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Golden file tests for [`XmlStyle::CreaterepoC`]. The files in `tests/assets/createrepo_c/` are laid out
+//! and escaped the way createrepo_c writes the same metadata: CI has createrepo_c (from the Fedora image the
+//! workflow names, which logs the version) read them and write them back out with
+//! `tests/assets/createrepo_c/generate.py`, and fails if they change.
+
+extern crate rpmrepo_metadata;
+
+use once_cell::sync::Lazy;
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::*;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+mod common;
+
+static CREATEREPO_C_PRIMARY: &str = include_str!("assets/createrepo_c/primary.xml");
+static CREATEREPO_C_FILELISTS: &str = include_str!("assets/createrepo_c/filelists.xml");
+static CREATEREPO_C_OTHER: &str = include_str!("assets/createrepo_c/other.xml");
+static CREATEREPO_C_UPDATEINFO: &str = include_str!("assets/createrepo_c/updateinfo.xml");
+static CREATEREPO_C_REPOMD: &str = include_str!("assets/createrepo_c/repomd.xml");
+
+/// A package with characters which createrepo_c escapes differently, and a location base.
+static RPM_WITH_SPECIAL_CHARS: Lazy<Package> = Lazy::new(|| {
+    let mut package = Package::default();
+
+    package.set_name("rpm-with-special-chars");
+    package.set_arch("noarch");
+    package.set_evr(EVR::new("0", "1", "1.fc33"));
+    package.set_checksum(Checksum::Sha256(
+        "0a8a2b3d5f8c3a0ba6b3d42e74ab83e0c2f9c71a44342ba3fc38b2d5f24f22ab".to_owned(),
+    ));
+    package.set_summary(r#"Quotes "double" and 'single', & <angle brackets>"#);
+    package.set_description("Carriage\r\nreturns,\ttabs and non-ASCII: Ā ƀ");
+    package.set_packager("Zoë <zoe@example.com>");
+    package.set_url("https://example.com/?a=1&b=2");
+    package.set_location_href("rpm-with-special-chars-1-1.fc33.noarch.rpm");
+    package.set_location_base(Some("https://example.com/packages/"));
+    package.set_time_build(1617418325);
+    package.set_time_file(1625930845);
+    package.set_size_package(6489);
+    package.set_size_installed(42);
+    package.set_size_archive(296);
+
+    package.set_rpm_license("Public Domain");
+    package.set_rpm_vendor("Ünïcode \"Vendor\"");
+    package.set_rpm_sourcerpm("rpm-with-special-chars-1-1.fc33.src.rpm");
+    package.set_rpm_buildhost("localhost");
+    package.set_rpm_group("Unspecified");
+    package.set_rpm_header_range(4504, 6445);
+
+    package.set_provides(vec![Requirement {
+        name: "config(spëcial) \"quoted\"\tand 'single'".to_owned(),
+        flags: Some("EQ".to_owned()),
+        epoch: Some("0".to_owned()),
+        version: Some("1".to_owned()),
+        release: Some("1.fc33".to_owned()),
+        preinstall: false,
+    }]);
+    package.set_requires(vec![Requirement {
+        name: "/usr/bin/python3 < 4".to_owned(),
+        ..Requirement::default()
+    }]);
+    package.set_files(vec![
+        PackageFile {
+            filetype: FileType::Dir,
+            path: "/usr/share/spëcial".to_owned(),
+        },
+        PackageFile {
+            filetype: FileType::File,
+            path: "/usr/share/spëcial/\"quoted\" & 'single'".to_owned(),
+        },
+    ]);
+    package.add_changelog(
+        "Zoë <zoe@example.com> - 1-1",
+        "- \"Quoted\" & 'single'\r\n- Ā ƀ",
+        1617192000,
+    );
+
+    package
+});
+
+fn packages() -> Vec<&'static Package> {
+    vec![&common::COMPLEX_PACKAGE, &RPM_WITH_SPECIAL_CHARS]
+}
+
+fn advisory() -> UpdateRecord {
+    let package = UpdateCollectionPackage {
+        name: "nano".to_owned(),
+        epoch: "0".to_owned(),
+        version: "4.9.3".to_owned(),
+        release: "1.fc32".to_owned(),
+        arch: "x86_64".to_owned(),
+        src: "https://download.fedoraproject.org/pub/fedora/linux/updates/32/SRPMS/n/nano-4.9.3-1.fc32.src.rpm".to_owned(),
+        filename: "nano-4.9.3-1.fc32.x86_64.rpm".to_owned(),
+        checksum: Some(Checksum::Sha256(
+            "8e214681104e4ba73726e0ce11d21b963ec0390fd70458d439ddc72372082034".to_owned(),
+        )),
+        reboot_suggested: true,
+        ..UpdateCollectionPackage::default()
+    };
+    UpdateRecord {
+        from: "updates@fedoraproject.org".to_owned(),
+        status: "stable".to_owned(),
+        update_type: "bugfix".to_owned(),
+        version: "2.0".to_owned(),
+        id: "FEDORA-2020-15f9382449".to_owned(),
+        title: "nano-4.9.3-1.fc32".to_owned(),
+        issued_date: Some("2020-05-27 04:10:31".to_owned()),
+        updated_date: Some("2020-05-28 12:00:00".to_owned()),
+        release: "Fedora 32".to_owned(),
+        pushcount: Some("1".to_owned()),
+        severity: "Moderate".to_owned(),
+        summary: "nano-4.9.3-1.fc32 bugfix update".to_owned(),
+        description: "- update to the latest \"upstream\" bugfix release\r\n- Zoë's fix".to_owned(),
+        references: vec![UpdateReference {
+            href: "https://bugzilla.redhat.com/show_bug.cgi?id=1839351&x=\"y\"".to_owned(),
+            id: "1839351".to_owned(),
+            title: String::new(),
+            reftype: "bugzilla".to_owned(),
+        }],
+        pkglist: vec![UpdateCollection {
+            name: "Fedora 32".to_owned(),
+            shortname: Some("F32".to_owned()),
+            packages: vec![package],
+            module: None,
+        }],
+        ..UpdateRecord::default()
+    }
+}
+
+fn repomd() -> RepomdData {
+    let mut repomd = RepomdData::default();
+    repomd.set_revision("1615686706");
+    repomd.add_content_tag(String::from("binary-x86_64"));
+    for (name, href, base) in [
+        ("updateinfo", "repodata/updateinfo.xml.gz", None),
+        ("other", "repodata/other.xml.gz", None),
+        ("modules", "repodata/modules.yaml.gz", None),
+        (
+            "primary",
+            "repodata/primary.xml.gz",
+            Some("https://example.com/"),
+        ),
+        ("filelists", "repodata/filelists.xml.gz", None),
+    ] {
+        let mut record = RepomdRecord::default();
+        record.metadata_type = name.into();
+        record.checksum = Checksum::Sha256(String::from(
+            "e6104a05bf3101c01321a5af9098d569ff974a8e6a8f72c5982bf074efbaf036",
+        ));
+        record.open_checksum = Some(Checksum::Sha256(String::from(
+            "03fb79ab50c4ac35db2ca86964047c68a3561e0978e380be7f4fbc0ac4d6c530",
+        )));
+        record.timestamp = 1639195237;
+        record.size = Some(1971);
+        record.open_size = Some(6527);
+        record.location_href = PathBuf::from(href);
+        record.location_base = base.map(str::to_owned);
+        repomd.add_record(record);
+    }
+    repomd
+}
+
+fn write_primary(style: XmlStyle) -> Result<String, MetadataError> {
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(style);
+    writer.write_header(packages().len())?;
+    for package in packages() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+}
+
+fn write_filelists(style: XmlStyle) -> Result<String, MetadataError> {
+    let mut writer = FilelistsXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(style);
+    writer.write_header(packages().len())?;
+    for package in packages() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+}
+
+fn write_other(style: XmlStyle) -> Result<String, MetadataError> {
+    let mut writer = OtherXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(style);
+    writer.write_header(packages().len())?;
+    for package in packages() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+}
+
+#[test]
+fn test_createrepo_c_primary_xml() -> Result<(), MetadataError> {
+    let actual = write_primary(XmlStyle::CreaterepoC)?;
+    assert_eq!(&actual, CREATEREPO_C_PRIMARY);
+
+    // the character references are read back as the characters they stand for
+    let mut reader = PrimaryXml::new_reader(utils::create_xml_reader(actual.as_bytes()));
+    reader.read_header()?;
+    reader.read_package(&mut None)?;
+    let mut package = None;
+    reader.read_package(&mut package)?;
+    let package = package.unwrap();
+    assert_eq!(package.provides(), RPM_WITH_SPECIAL_CHARS.provides());
+    assert_eq!(package.description(), RPM_WITH_SPECIAL_CHARS.description());
+    assert_eq!(
+        package.location_base(),
+        RPM_WITH_SPECIAL_CHARS.location_base()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_createrepo_c_filelists_xml() -> Result<(), MetadataError> {
+    assert_eq!(
+        &write_filelists(XmlStyle::CreaterepoC)?,
+        CREATEREPO_C_FILELISTS
+    );
+    Ok(())
+}
+
+#[test]
+fn test_createrepo_c_other_xml() -> Result<(), MetadataError> {
+    assert_eq!(&write_other(XmlStyle::CreaterepoC)?, CREATEREPO_C_OTHER);
+    Ok(())
+}
+
+#[test]
+fn test_createrepo_c_updateinfo_xml() -> Result<(), MetadataError> {
+    let mut writer = UpdateinfoXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(XmlStyle::CreaterepoC);
+    writer.write_header()?;
+    writer.write_updaterecord(&advisory())?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, CREATEREPO_C_UPDATEINFO);
+
+    // dates given as attributes, and attributes which are left out, are read back
+    let mut reader = UpdateinfoXml::new_reader(utils::create_xml_reader(buffer.as_slice()));
+    assert_eq!(reader.read_update()?, Some(advisory()));
+
+    Ok(())
+}
+
+#[test]
+fn test_createrepo_c_repomd_xml() -> Result<(), MetadataError> {
+    let mut writer = utils::create_xml_writer(Cursor::new(Vec::new()));
+    RepomdXml::write_data_with_style(&repomd(), &mut writer, XmlStyle::CreaterepoC)?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, CREATEREPO_C_REPOMD);
+    Ok(())
+}
+
+#[test]
+fn test_standard_style_unchanged() -> Result<(), MetadataError> {
+    // packages are indented, and quotes are escaped in text
+    let primary = write_primary(XmlStyle::Standard)?;
+    assert!(primary.contains("\n  <package type=\"rpm\">\n    <name>complex-package</name>"));
+    assert!(primary.contains("<summary>Quotes &quot;double&quot; and &apos;single&apos;"));
+    assert!(primary.contains("<location href=\"rpm-with-special-chars-1-1.fc33.noarch.rpm\"/>"));
+
+    let mut writer = utils::create_xml_writer(Cursor::new(Vec::new()));
+    RepomdXml::write_data(&repomd(), &mut writer)?;
+    let repomd = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+    assert!(repomd.find("updateinfo") < repomd.find("primary"));
+
+    Ok(())
+}
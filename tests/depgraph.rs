@@ -0,0 +1,159 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::*;
+
+mod common;
+
+use common::requirement;
+
+fn fixture_repo() -> Repository {
+    let mut shell = common::package_with_deps("shell", "5.1", "noarch", &[], &["libc >= 2.0"]);
+    shell.add_file(FileType::File, "/usr/bin/sh");
+
+    let mut libc =
+        common::package_with_deps("libc", "2.34", "noarch", &[], &["/usr/bin/sh", "libc"]);
+    libc.set_recommends(vec![requirement("docs")]);
+
+    let docs = common::package_with_deps("docs", "1.0", "noarch", &[], &["shell < 5.0"]);
+
+    common::repository([shell, libc, docs])
+}
+
+#[test]
+fn test_depgraph_edges() {
+    let repo = fixture_repo();
+
+    let graph = DependencyGraph::from_repository(&repo, DependencyGraphOptions::default());
+    assert_eq!(graph.nodes().len(), 3);
+    // the file dependency, the self-dependency and the unsatisfiable versioned dependency are all skipped
+    assert_eq!(
+        graph.edges(),
+        &[DependencyEdge {
+            from: 0,
+            to: 1,
+            kind: DependencyKind::Requires,
+            capability: "libc".to_owned(),
+        }]
+    );
+    assert!(graph.cycles().is_empty());
+
+    let options = DependencyGraphOptions::default()
+        .include_file_deps(true)
+        .include_weak_deps(true);
+    let graph = DependencyGraph::from_repository(&repo, options);
+    assert_eq!(graph.edges().len(), 3);
+    assert_eq!(graph.cycles(), vec![vec![0, 1]]);
+}
+
+#[test]
+fn test_depgraph_weak_dependencies() {
+    let mut repo = fixture_repo();
+    // the German language pack is installed along with docs, without docs recommending it
+    let mut langpack =
+        common::package_with_deps("docs-langpack-de", "1.0", "noarch", &[], &["libc"]);
+    langpack.set_supplements(vec![requirement("docs")]);
+    let mut plugin = common::package_with_deps("shell-plugin", "1.0", "noarch", &[], &[]);
+    plugin.set_enhances(vec![requirement("shell")]);
+    let mut shell = repo.packages()[0].clone();
+    shell.set_suggests(vec![requirement("docs")]);
+    repo.packages_mut().insert(shell.pkgid().to_owned(), shell);
+    for pkg in [langpack, plugin] {
+        repo.packages_mut().insert(pkg.pkgid().to_owned(), pkg);
+    }
+
+    let options = DependencyGraphOptions::default().include_weak_deps(true);
+    let graph = DependencyGraph::from_repository(&repo, options);
+    let node = |nevra: &str| graph.node(nevra).unwrap();
+    let (shell, libc, docs, langpack, plugin) = (
+        node("shell-0:5.1-1.noarch"),
+        node("libc-0:2.34-1.noarch"),
+        node("docs-0:1.0-1.noarch"),
+        node("docs-langpack-de-0:1.0-1.noarch"),
+        node("shell-plugin-0:1.0-1.noarch"),
+    );
+
+    assert_eq!(graph.recommended(libc), vec![docs]);
+    assert_eq!(graph.recommended(docs), vec![langpack]);
+    assert_eq!(graph.suggested(shell), vec![docs, plugin]);
+    assert_eq!(
+        graph.dependents(docs, DependencyKind::Supplements),
+        vec![langpack]
+    );
+    assert_eq!(
+        graph.dependencies(langpack, DependencyKind::Requires),
+        vec![libc]
+    );
+
+    assert_eq!(graph.install_set(shell, false), vec![libc]);
+    assert_eq!(graph.install_set(shell, true), vec![libc, docs, langpack]);
+
+    // without weak dependencies in the graph there is nothing to recommend
+    let graph = DependencyGraph::from_repository(&repo, DependencyGraphOptions::default());
+    assert!(graph.recommended(libc).is_empty());
+    assert!(graph.node("missing-0:1-1.noarch").is_none());
+}
+
+#[test]
+fn test_depgraph_write_dot() -> Result<(), MetadataError> {
+    let repo = fixture_repo();
+    let options = DependencyGraphOptions::default()
+        .include_file_deps(true)
+        .include_weak_deps(true);
+    let graph = DependencyGraph::from_repository(&repo, options);
+
+    let mut buffer = Vec::new();
+    graph.write_dot(&mut buffer)?;
+
+    let expected = r#"digraph packages {
+  n0 [label="shell-0:5.1-1.noarch"];
+  n1 [label="libc-0:2.34-1.noarch"];
+  n2 [label="docs-0:1.0-1.noarch"];
+  n0 -> n1 [label="libc"];
+  n1 -> n0 [label="/usr/bin/sh"];
+  n1 -> n2 [label="docs (recommends)", style=dashed];
+}
+"#;
+    assert_eq!(std::str::from_utf8(&buffer)?, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_depgraph_write_graphml() -> Result<(), MetadataError> {
+    let repo = fixture_repo();
+    let graph = DependencyGraph::from_repository(&repo, DependencyGraphOptions::default());
+
+    let mut buffer = Vec::new();
+    graph.write_graphml(&mut buffer)?;
+
+    let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="nevra" for="node" attr.name="nevra" attr.type="string"/>
+  <key id="capability" for="edge" attr.name="capability" attr.type="string"/>
+  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>
+  <graph id="packages" edgedefault="directed">
+    <node id="n0">
+      <data key="nevra">shell-0:5.1-1.noarch</data>
+    </node>
+    <node id="n1">
+      <data key="nevra">libc-0:2.34-1.noarch</data>
+    </node>
+    <node id="n2">
+      <data key="nevra">docs-0:1.0-1.noarch</data>
+    </node>
+    <edge source="n0" target="n1">
+      <data key="capability">libc</data>
+      <data key="kind">requires</data>
+    </edge>
+  </graph>
+</graphml>
+"#;
+    assert_eq!(std::str::from_utf8(&buffer)?, expected);
+
+    Ok(())
+}
@@ -0,0 +1,1640 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Take, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::*;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use tempdir::TempDir;
+
+mod common;
+
+/// The paths (and ranges) requested from a [`TestServer`].
+type RequestLog = Mutex<Vec<(String, Option<u64>)>>;
+
+/// A minimal HTTP server serving the files of a directory, recording the paths (and ranges) requested.
+/// Files can be uploaded to it with `PUT` requests.
+struct TestServer {
+    url: String,
+    requests: Arc<RequestLog>,
+}
+
+impl TestServer {
+    fn serve(root: &Path) -> TestServer {
+        Self::serve_with(root, None)
+    }
+
+    /// Serve `https://` URLs, with the certificates in tests/assets/tls. Clients must present a
+    /// certificate signed by the test CA.
+    fn serve_tls(root: &Path) -> TestServer {
+        let tls = Path::new("./tests/assets/tls");
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(tls.join("ca.pem")).unwrap())
+            .unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .unwrap();
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![CertificateDer::from_pem_file(tls.join("server.pem")).unwrap()],
+                PrivateKeyDer::from_pem_file(tls.join("server-key.pem")).unwrap(),
+            )
+            .unwrap();
+        Self::serve_with(root, Some(Arc::new(config)))
+    }
+
+    fn serve_with(root: &Path, tls: Option<Arc<rustls::ServerConfig>>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let url = format!("{}://{}", scheme, listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let root = root.to_owned();
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                // connections which fail, e.g. because the TLS handshake is rejected, are dropped
+                let _ = match &tls {
+                    Some(config) => {
+                        let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                        handle_request(rustls::StreamOwned::new(connection, stream), &root, &log)
+                    }
+                    None => handle_request(stream, &root, &log),
+                };
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    fn requests(&self) -> Vec<(String, Option<u64>)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle_request<S: Read + Write>(
+    stream: S,
+    root: &Path,
+    log: &RequestLog,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // a proxy is asked to open a tunnel to the host, which the request is then sent through
+    let mut tunnel = None;
+    if let Some(target) = request_line.strip_prefix("CONNECT ") {
+        tunnel = target.split_whitespace().next().map(str::to_owned);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+            line.clear();
+        }
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 Connection established\r\n\r\n"
+        )?;
+        reader.get_mut().flush()?;
+        request_line.clear();
+        reader.read_line(&mut request_line)?;
+    }
+    let mut range_start = None;
+    let mut range_end = None;
+    let mut if_none_match = None;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let Some((name, value)) = line.trim().split_once(": ") else {
+            break;
+        };
+        match &*name.to_ascii_lowercase() {
+            "range" => {
+                let range = value.strip_prefix("bytes=").unwrap();
+                let (start, end) = range.split_once('-').unwrap();
+                range_start = start.parse::<u64>().ok();
+                range_end = end.parse::<usize>().ok();
+            }
+            "if-none-match" => if_none_match = Some(value.to_owned()),
+            "content-length" => content_length = value.parse::<usize>().unwrap(),
+            _ => (),
+        }
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap().to_owned();
+    let logged = match &tunnel {
+        Some(host) => format!("http://{}{}", host, target),
+        None => target.clone(),
+    };
+    log.lock().unwrap().push((logged, range_start));
+    // proxies are sent absolute URLs
+    let path = match target.strip_prefix("http://") {
+        Some(rest) => rest.find('/').map_or("", |idx| &rest[idx..]),
+        None => &target,
+    };
+    let file = root.join(path.trim_start_matches('/'));
+    if request_line.starts_with("PUT ") {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(&file, body)?;
+        let stream = reader.get_mut();
+        write!(stream, "HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")?;
+        return stream.flush();
+    }
+    let stream = reader.get_mut();
+    match fs::read(&file) {
+        Ok(body) if range_start.is_some() => {
+            let start = range_start.unwrap() as usize;
+            let end = range_end.unwrap_or(body.len() - 1).min(body.len() - 1);
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                start,
+                end,
+                body.len(),
+                end + 1 - start
+            )?;
+            stream.write_all(&body[start..=end])?;
+        }
+        Ok(body) if if_none_match == Some(etag(&body)) => write!(
+            stream,
+            "HTTP/1.1 304 Not Modified\r\nContent-Length: 0\r\n\r\n"
+        )?,
+        Ok(body) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n\r\n",
+                etag(&body),
+                body.len()
+            )?;
+            stream.write_all(&body)?;
+        }
+        Err(_) => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+        )?,
+    }
+    stream.flush()
+}
+
+fn etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:x}\"", hasher.finish())
+}
+
+/// Cuts off the body of the first response for `target` after `limit` bytes, like a dropped connection.
+struct InterruptingTransport {
+    inner: DefaultTransport,
+    target: &'static str,
+    limit: u64,
+    interrupted: AtomicBool,
+}
+
+struct Interrupted {
+    inner: Take<Box<dyn Read + Send>>,
+}
+
+impl Read for Interrupted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.inner.read(buf)? {
+            0 => Err(std::io::ErrorKind::ConnectionReset.into()),
+            count => Ok(count),
+        }
+    }
+}
+
+impl Transport for InterruptingTransport {
+    fn fetch(&self, request: &Request) -> Result<Response, MetadataError> {
+        let mut response = self.inner.fetch(request)?;
+        if request.url.ends_with(self.target) && !self.interrupted.swap(true, Ordering::SeqCst) {
+            response.body = Box::new(Interrupted {
+                inner: response.body.take(self.limit),
+            });
+        }
+        Ok(response)
+    }
+}
+
+/// Fails the first `failures` requests for `target` with a `503 Service Unavailable`.
+struct FlakyTransport {
+    inner: DefaultTransport,
+    target: &'static str,
+    failures: Mutex<usize>,
+}
+
+impl Transport for FlakyTransport {
+    fn fetch(&self, request: &Request) -> Result<Response, MetadataError> {
+        let mut failures = self.failures.lock().unwrap();
+        if request.url.ends_with(self.target) && *failures > 0 {
+            *failures -= 1;
+            return Ok(Response {
+                status: 503,
+                headers: Vec::new(),
+                body: Box::new(std::io::empty()),
+            });
+        }
+        self.inner.fetch(request)
+    }
+}
+
+/// Create a repository with a couple of (fake) packages in `path`.
+fn create_upstream_repo(path: &Path) -> Result<Repository, MetadataError> {
+    create_upstream_repo_with(
+        path,
+        &[("alpha", "alpha payload"), ("beta", "beta payload")],
+    )
+}
+
+fn create_upstream_repo_with(
+    path: &Path,
+    packages: &[(&str, &str)],
+) -> Result<Repository, MetadataError> {
+    let packages = packages
+        .iter()
+        .map(|(name, payload)| (*name, "1.0", "noarch", *payload))
+        .collect::<Vec<_>>();
+    create_upstream_repo_with_versions(path, &packages)
+}
+
+/// Create a repository of `(name, version, arch, payload)` packages in `path`.
+fn create_upstream_repo_with_versions(
+    path: &Path,
+    packages: &[(&str, &str, &str, &str)],
+) -> Result<Repository, MetadataError> {
+    let mut repo = Repository::new();
+
+    for (name, version, arch, payload) in packages.iter().copied() {
+        let href = format!("Packages/{}-{}-1.{}.rpm", name, version, arch);
+        fs::create_dir_all(path.join("Packages"))?;
+        fs::write(path.join(&href), payload)?;
+        let checksum = utils::checksum_file(&path.join(&href), ChecksumType::Sha256)?;
+
+        let mut package = Package::new(name, &EVR::new("0", version, "1"), arch, &checksum, &href);
+        package.set_size_package(payload.len() as u64);
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+
+    repo.write_to_directory(path)?;
+    Ok(repo)
+}
+
+/// Add a `primary_zck` record made of `chunks` to the repository in `path`.
+fn add_zchunk_record(path: &Path, chunks: &[&[u8]]) -> Result<PathBuf, MetadataError> {
+    let mut zck_chunks: Vec<(&[u8], usize)> = vec![(b"", 0)];
+    zck_chunks.extend(chunks.iter().map(|chunk| (*chunk, chunk.len())));
+    let (file, header_checksum) = common::zck_file(&zck_chunks);
+    let header_size = file.len() - chunks.concat().len();
+    let href = PathBuf::from(format!(
+        "repodata/{}-primary.xml.zck",
+        &header_checksum[..16]
+    ));
+    fs::write(path.join(&href), file)?;
+
+    let repomd_path = path.join("repodata/repomd.xml");
+    let mut repo = Repository::new();
+    repo.load_metadata_file::<RepomdXml>(&repomd_path)?;
+    let mut record = RepomdRecord::new("primary_zck", &href, path, ChecksumType::Sha256)?;
+    record.header_size = Some(header_size as u64);
+    record.header_checksum = Some(Checksum::Sha256(header_checksum));
+    repo.repomd_mut().add_record(record);
+    fs::write(&repomd_path, repo.write_metadata_bytes::<RepomdXml>()?)?;
+    Ok(href)
+}
+
+/// Wakes up the thread running [`block_on()`].
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run `poll` until it's ready - just enough of an executor to test futures without an async runtime.
+fn block_on<T>(mut poll: impl FnMut(&mut Context<'_>) -> Poll<T>) -> T {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(value) = poll(&mut cx) {
+            return value;
+        }
+        thread::park();
+    }
+}
+
+fn sorted(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_sync_metadata_and_packages() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let upstream_repo = create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let options = DownloadOptions::default().download_packages(true);
+    let downloader = Downloader::new(&server.url).with_options(options);
+    let report = downloader.sync_to_directory(mirror.path())?;
+
+    assert_eq!(report.downloaded.len(), 6);
+    assert!(report.skipped.is_empty());
+    assert_eq!(
+        fs::read(mirror.path().join("Packages/beta-1.0-1.noarch.rpm"))?,
+        b"beta payload"
+    );
+    assert_eq!(
+        fs::read(mirror.path().join("repodata/repomd.xml"))?,
+        fs::read(upstream.path().join("repodata/repomd.xml"))?
+    );
+
+    let mirrored_repo = Repository::load_from_directory(mirror.path())?;
+    assert_eq!(mirrored_repo.packages(), upstream_repo.packages());
+
+    // A second sync finds everything already in place
+    let report = downloader.sync_to_directory(mirror.path())?;
+    assert_eq!(
+        sorted(report.downloaded),
+        vec![PathBuf::from("repodata/repomd.xml")]
+    );
+    assert_eq!(report.skipped.len(), 5);
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|(path, _)| path.ends_with(".rpm"))
+            .count(),
+        2
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_refuses_paths_outside_of_destination() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let payload = b"escaped payload";
+    fs::write(upstream.path().join("escape.rpm"), payload)?;
+    let checksum = utils::checksum_bytes(payload, ChecksumType::Sha256)?;
+    let mut package = Package::new(
+        "escape",
+        &EVR::new("0", "1.0", "1"),
+        "noarch",
+        &checksum,
+        "Packages/../../escape.rpm",
+    );
+    package.set_size_package(payload.len() as u64);
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package);
+    repo.write_to_directory(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let options = DownloadOptions::default().download_packages(true);
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(&mirror.path().join("repo"));
+    assert!(matches!(
+        result,
+        Err(MetadataError::InvalidFieldError("location_href", _))
+    ));
+    assert!(!mirror.path().join("escape.rpm").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_selected_metadata() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let downloader = Downloader::new(&server.url).metadata_types(&["primary"]);
+    let report = downloader.sync_to_directory(mirror.path())?;
+
+    let repomd = downloader.fetch_repomd()?;
+    let primary = repomd.get_record("primary").unwrap();
+    assert_eq!(
+        sorted(report.downloaded),
+        vec![
+            primary.location_href.clone(),
+            PathBuf::from("repodata/repomd.xml")
+        ]
+    );
+    assert!(!mirror.path().join("Packages").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_checksum_mismatch() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    // same size, different content
+    fs::write(
+        upstream.path().join("Packages/alpha-1.0-1.noarch.rpm"),
+        "gamma payload",
+    )?;
+
+    let url = format!("file://{}", upstream.path().display());
+    let options = DownloadOptions::default().download_packages(true);
+    let result = Downloader::new(&url)
+        .with_options(options)
+        .sync_to_directory(mirror.path());
+
+    assert!(matches!(
+        result,
+        Err(MetadataError::ChecksumMismatchError(..))
+    ));
+    // nothing is left behind for the broken package, and an incomplete mirror has no repomd.xml
+    assert!(!mirror
+        .path()
+        .join("Packages/alpha-1.0-1.noarch.rpm")
+        .exists());
+    assert!(!mirror
+        .path()
+        .join("Packages/alpha-1.0-1.noarch.rpm.part")
+        .exists());
+    assert!(!mirror.path().join("repodata/repomd.xml").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_unsupported_checksum_type() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let href = "Packages/delta-1.0-1.noarch.rpm";
+    fs::create_dir_all(upstream.path().join("Packages"))?;
+    fs::write(upstream.path().join(href), "delta payload")?;
+    let checksum = Checksum::try_create(
+        "sha3-256",
+        "2c8f1e6b3a4d5c6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6",
+    )?;
+    assert!(matches!(checksum, Checksum::Other(..)));
+    let mut repo = Repository::new();
+    let mut package = Package::new(
+        "delta",
+        &EVR::new("0", "1.0", "1"),
+        "noarch",
+        &checksum,
+        href,
+    );
+    package.set_size_package(13);
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package);
+    repo.write_to_directory(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    // the package is downloaded, but flagged because its checksum couldn't be checked
+    let options = DownloadOptions::default().download_packages(true);
+    let downloader = Downloader::new(&server.url).with_options(options);
+    let report = downloader.sync_to_directory(mirror.path())?;
+    assert!(report.downloaded.contains(&PathBuf::from(href)));
+    assert_eq!(report.unverified, vec![PathBuf::from(href)]);
+
+    let mirrored_repo = Repository::load_from_directory(mirror.path())?;
+    assert_eq!(mirrored_repo.packages(), repo.packages());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_missing_repository() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let server = TestServer::serve(upstream.path());
+
+    let result = Downloader::new(&server.url).sync_to_directory(mirror.path());
+    assert!(matches!(
+        result,
+        Err(MetadataError::HttpStatusError(_, 404))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_resume_interrupted_download() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .retries(0);
+
+    let transport = InterruptingTransport {
+        inner: DefaultTransport::new(),
+        target: "alpha-1.0-1.noarch.rpm",
+        limit: 5,
+        interrupted: AtomicBool::new(false),
+    };
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(transport)
+        .sync_to_directory(mirror.path());
+    assert!(matches!(result, Err(MetadataError::IoError(_))));
+    assert_eq!(
+        fs::read(mirror.path().join("Packages/alpha-1.0-1.noarch.rpm.part"))?,
+        b"alpha"
+    );
+    assert!(!mirror.path().join("repodata/repomd.xml").exists());
+
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(mirror.path())?;
+    assert_eq!(
+        fs::read(mirror.path().join("Packages/alpha-1.0-1.noarch.rpm"))?,
+        b"alpha payload"
+    );
+    // the metadata finished downloading the first time around, the package is picked up from where it stopped
+    assert_eq!(report.skipped.len(), 3);
+    assert!(server
+        .requests()
+        .contains(&("/Packages/alpha-1.0-1.noarch.rpm".to_owned(), Some(5))));
+    assert!(Repository::load_from_directory(mirror.path()).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_discards_stale_partial_download() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .retries(0);
+
+    let transport = InterruptingTransport {
+        inner: DefaultTransport::new(),
+        target: "alpha-1.0-1.noarch.rpm",
+        limit: 5,
+        interrupted: AtomicBool::new(false),
+    };
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(transport)
+        .sync_to_directory(mirror.path());
+    assert!(result.is_err());
+
+    // the package is rebuilt upstream before the sync is retried
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo_with(upstream.path(), &[("alpha", "ALPHA PAYLOAD")])?;
+
+    Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(mirror.path())?;
+    assert_eq!(
+        fs::read(mirror.path().join("Packages/alpha-1.0-1.noarch.rpm"))?,
+        b"ALPHA PAYLOAD"
+    );
+    assert!(!server.requests().iter().any(|(_, range)| range.is_some()));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_fails_over_to_next_mirror() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let empty = TempDir::new("empty")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let good = TestServer::serve(upstream.path());
+    let bad = TestServer::serve(empty.path());
+
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .retry_delay(Duration::ZERO);
+    let downloader = Downloader::from_mirrors(&[&bad.url, &good.url]).with_options(options);
+    let report = downloader.sync_to_directory(mirror.path())?;
+    assert_eq!(report.downloaded.len(), 6);
+
+    // the broken mirror fails once, after which the healthy one is tried first
+    let status = downloader.mirror_status();
+    assert_eq!(status[0].url, bad.url);
+    assert_eq!((status[0].successes, status[0].failures), (0, 1));
+    assert_eq!(status[1].url, good.url);
+    assert_eq!((status[1].successes, status[1].failures), (6, 0));
+    assert_eq!(bad.requests().len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_retries_transient_errors() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let transport = FlakyTransport {
+        inner: DefaultTransport::new(),
+        target: "repomd.xml",
+        failures: Mutex::new(2),
+    };
+    let options = DownloadOptions::default().retry_delay(Duration::ZERO);
+    let downloader = Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(transport);
+    downloader.sync_to_directory(mirror.path())?;
+    assert_eq!(downloader.mirror_status()[0].failures, 2);
+
+    // without retries the error is passed on
+    let transport = FlakyTransport {
+        inner: DefaultTransport::new(),
+        target: "repomd.xml",
+        failures: Mutex::new(1),
+    };
+    let result = Downloader::new(&server.url)
+        .with_options(options.retries(0))
+        .with_transport(transport)
+        .fetch_repomd();
+    assert!(matches!(
+        result,
+        Err(MetadataError::HttpStatusError(_, 503))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_from_mirrorlist() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let mirrorlist = format!(
+        "# mirrors\nfile:///nonexistent\n\n{}/repodata/repomd.xml\n",
+        server.url
+    );
+    assert_eq!(
+        parse_mirrorlist(&mirrorlist),
+        vec!["file:///nonexistent".to_owned(), server.url.clone()]
+    );
+
+    let list_path = mirror.path().join("mirrorlist");
+    fs::write(&list_path, mirrorlist)?;
+    let downloader = Downloader::from_mirrorlist(&format!("file://{}", list_path.display()))
+        .with_options(DownloadOptions::default().retry_delay(Duration::ZERO));
+    let report = downloader.sync_to_directory(&mirror.path().join("repo"))?;
+    assert_eq!(report.downloaded.len(), 4);
+    assert_eq!(downloader.mirror_status().len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_from_metalink() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let repomd_checksum = utils::checksum_file(
+        &upstream.path().join("repodata/repomd.xml"),
+        ChecksumType::Sha256,
+    )?;
+    let Checksum::Sha256(repomd_sha256) = &repomd_checksum else {
+        unreachable!()
+    };
+
+    let metalink_with = |sha256: &str| {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/">
+  <files>
+    <file name="repomd.xml">
+      <verification>
+        <hash type="md5">0123456789abcdef0123456789abcdef</hash>
+        <hash type="sha256">{sha256}</hash>
+      </verification>
+      <resources maxconnections="1">
+        <url protocol="file" type="file" preference="50">file:///nonexistent/repodata/repomd.xml</url>
+        <url protocol="http" type="http" preference="100">{url}/repodata/repomd.xml</url>
+      </resources>
+    </file>
+  </files>
+</metalink>"#,
+            sha256 = sha256,
+            url = server.url
+        )
+    };
+
+    let metalink = Metalink::parse(metalink_with(repomd_sha256).as_bytes())?;
+    assert_eq!(
+        metalink.mirrors,
+        vec![server.url.clone(), "file:///nonexistent".to_owned()]
+    );
+    assert!(metalink.repomd_checksums.contains(&repomd_checksum));
+
+    let metalink_path = mirror.path().join("metalink.xml");
+    fs::write(&metalink_path, metalink_with(repomd_sha256))?;
+    let metalink_url = format!("file://{}", metalink_path.display());
+    let report =
+        Downloader::from_metalink(&metalink_url).sync_to_directory(&mirror.path().join("repo"))?;
+    assert_eq!(report.downloaded.len(), 4);
+
+    // a repomd.xml which doesn't match the metalink is rejected
+    fs::write(&metalink_path, metalink_with(&"0".repeat(64)))?;
+    let downloader = Downloader::from_metalink(&metalink_url)
+        .with_options(DownloadOptions::default().retry_delay(Duration::ZERO));
+    assert!(downloader.fetch_repomd().is_err());
+    let status = downloader.mirror_status();
+    assert_eq!(status[0].url, server.url);
+    assert_eq!((status[0].successes, status[0].failures), (0, 1));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_through_proxy() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    // the "proxy" serves the upstream repository for any host
+    let proxy = TestServer::serve(upstream.path());
+    let proxy_url = proxy.url.replace("http://", "http://user:secret@");
+
+    let transport = DefaultTransport::new().proxy(Some(&proxy_url));
+    let report = Downloader::new("http://repo.invalid:8080")
+        .with_transport(transport)
+        .sync_to_directory(mirror.path())?;
+    assert_eq!(report.downloaded.len(), 4);
+    assert!(proxy.requests().contains(&(
+        "http://repo.invalid:8080/repodata/repomd.xml".to_owned(),
+        None
+    )));
+
+    // hosts excluded from proxying are accessed directly
+    let server = TestServer::serve(upstream.path());
+    let transport = DefaultTransport::new()
+        .proxy(Some("http://127.0.0.1:1"))
+        .no_proxy("example.com, 127.0.0.1");
+    Downloader::new(&server.url)
+        .with_transport(transport)
+        .fetch_repomd()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_https_connection_errors() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let options = DownloadOptions::default().retries(0);
+
+    // connection problems are reported as I/O errors, so they get retried
+    let result = Downloader::new("https://127.0.0.1:1")
+        .with_options(options)
+        .fetch_repomd();
+    assert!(matches!(result, Err(MetadataError::IoError(_))));
+
+    // the plain http server can't complete a TLS handshake
+    let result = Downloader::new(&server.url.replace("http://", "https://"))
+        .with_options(options)
+        .fetch_repomd();
+    assert!(result.is_err());
+    assert!(server.requests().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_over_https() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve_tls(upstream.path());
+    let tls = Path::new("./tests/assets/tls");
+    let options = DownloadOptions::default().retries(0);
+
+    // the test CA isn't trusted by default
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .fetch_repomd();
+    assert!(matches!(result, Err(MetadataError::DownloadError(..))));
+
+    // the server requires a client certificate
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(DefaultTransport::new().ca_bundle(tls.join("ca.pem")))
+        .fetch_repomd();
+    assert!(result.is_err());
+    assert!(server.requests().is_empty());
+
+    let transport = DefaultTransport::new()
+        .ca_bundle(tls.join("ca.pem"))
+        .client_cert(tls.join("client.pem"), Some(tls.join("client-key.pem")));
+    let report = Downloader::new(&server.url)
+        .with_options(options.download_packages(true))
+        .with_transport(transport.clone())
+        .sync_to_directory(&mirror.path().join("direct"))?;
+    assert_eq!(report.downloaded.len(), 6);
+
+    // the key can be in the same file as the certificate
+    let combined = DefaultTransport::new()
+        .ca_bundle(tls.join("ca.pem"))
+        .client_cert(tls.join("client-combined.pem"), None);
+    Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(combined)
+        .fetch_repomd()?;
+
+    // an https proxy, which requests are tunnelled through
+    let report = Downloader::new("http://repo.invalid")
+        .with_options(options)
+        .with_transport(transport.clone().proxy(Some(&server.url)))
+        .sync_to_directory(&mirror.path().join("proxied"))?;
+    assert_eq!(report.downloaded.len(), 4);
+    assert!(server.requests().contains(&(
+        "http://repo.invalid:80/repodata/repomd.xml".to_owned(),
+        None
+    )));
+
+    let report = Uploader::new(&format!("{}/published", server.url))
+        .with_transport(transport)
+        .upload_directory(&mirror.path().join("direct"))?;
+    assert_eq!(report.uploaded.len(), 6);
+    assert_eq!(
+        fs::read(upstream.path().join("published/repodata/repomd.xml"))?,
+        fs::read(upstream.path().join("repodata/repomd.xml"))?
+    );
+
+    // a CA bundle without certificates is rejected
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_transport(DefaultTransport::new().ca_bundle(tls.join("client-key.pem")))
+        .fetch_repomd();
+    match result {
+        Err(MetadataError::DownloadError(path, message)) => {
+            assert!(path.ends_with("client-key.pem"));
+            assert_eq!(message, "no certificates found");
+        }
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_redirect_to_other_scheme() -> Result<(), MetadataError> {
+    // a server which redirects every request to a local file
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+                    break;
+                }
+            }
+            let response =
+                "HTTP/1.1 302 Found\r\nLocation: file:///etc/passwd\r\nContent-Length: 0\r\n\r\n";
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    let result = Downloader::new(&url)
+        .with_options(DownloadOptions::default().retries(0))
+        .fetch_repomd();
+    match result {
+        Err(MetadataError::DownloadError(_, message)) => {
+            assert!(message.contains("file:///etc/passwd"))
+        }
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_zchunk_delta() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let downloader = Downloader::new(&server.url).metadata_types(&["primary_zck"]);
+
+    let old_href = add_zchunk_record(
+        upstream.path(),
+        &[b"first chunk", b"second chunk", b"third"],
+    )?;
+    downloader.sync_to_directory(mirror.path())?;
+    assert!(mirror.path().join(&old_href).exists());
+
+    // the second chunk changes and a fourth is appended
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo(upstream.path())?;
+    let new_href = add_zchunk_record(
+        upstream.path(),
+        &[b"first chunk", b"SECOND CHUNK", b"third", b"fourth"],
+    )?;
+    let report = downloader.sync_to_directory(mirror.path())?;
+
+    assert_eq!(
+        fs::read(mirror.path().join(&new_href))?,
+        fs::read(upstream.path().join(&new_href))?
+    );
+    let header_size = fs::metadata(upstream.path().join(&new_href))?.len() - 34;
+    let repomd_size = fs::metadata(upstream.path().join("repodata/repomd.xml"))?.len();
+    assert_eq!(report.bytes_downloaded, repomd_size + header_size + 12 + 6);
+    // only the header and the two new chunks were requested
+    let ranges = server
+        .requests()
+        .into_iter()
+        .filter(|(path, _)| path.ends_with(&*new_href.to_string_lossy()))
+        .map(|(_, range)| range)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ranges,
+        vec![Some(0), Some(header_size + 11), Some(header_size + 28)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_zchunk_delta_fallback() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let downloader = Downloader::new(&server.url).metadata_types(&["primary_zck"]);
+
+    let old_href = add_zchunk_record(upstream.path(), &[b"first chunk", b"second chunk"])?;
+    downloader.sync_to_directory(mirror.path())?;
+    // the local copy got damaged, so no chunk can be reused
+    fs::write(mirror.path().join(&old_href), "not a zchunk file")?;
+
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo(upstream.path())?;
+    let new_href = add_zchunk_record(upstream.path(), &[b"first chunk", b"SECOND CHUNK"])?;
+    downloader.sync_to_directory(mirror.path())?;
+    assert_eq!(
+        fs::read(mirror.path().join(&new_href))?,
+        fs::read(upstream.path().join(&new_href))?
+    );
+
+    // with deltas disabled the file is downloaded in one go
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo(upstream.path())?;
+    let newest_href = add_zchunk_record(upstream.path(), &[b"first chunk", b"2nd chunk"])?;
+    Downloader::new(&server.url)
+        .metadata_types(&["primary_zck"])
+        .with_options(DownloadOptions::default().zchunk_deltas(false))
+        .sync_to_directory(mirror.path())?;
+    assert!(server
+        .requests()
+        .contains(&(format!("/{}", newest_href.display()), None)));
+
+    Ok(())
+}
+
+#[test]
+fn test_probe_repository() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let probe = probe_repository(&server.url)?;
+    assert_eq!(
+        server.requests(),
+        [("/repodata/repomd.xml".to_owned(), None)]
+    );
+    let types: Vec<&str> = probe.metadata_types().map(MetadataType::as_str).collect();
+    assert_eq!(types, ["primary", "filelists", "other"]);
+    let primary = probe.record(&MetadataType::Primary).unwrap();
+    assert_eq!(probe.timestamp, Some(primary.timestamp));
+    assert_eq!(
+        probe.checksum,
+        utils::checksum_file(
+            &upstream.path().join("repodata/repomd.xml"),
+            ChecksumType::Sha256
+        )?
+    );
+
+    // the same repository read from the filesystem
+    let local = probe_repository(upstream.path().to_str().unwrap())?;
+    assert_eq!(local, probe);
+    let url = format!("file://{}", upstream.path().display());
+    assert_eq!(probe_repository(&url)?, probe);
+
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo_with(upstream.path(), &[("gamma", "gamma payload")])?;
+    assert!(probe_repository(&server.url)?.has_changed_since(&probe));
+    assert!(!probe_repository(&server.url)?.has_changed_since(&probe_repository(&url)?));
+
+    Ok(())
+}
+
+#[test]
+fn test_refresh_unchanged_repository() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let downloader = Downloader::new(&server.url);
+
+    let RefreshOutcome::Synced(report) = downloader.refresh(mirror.path())? else {
+        panic!("the first refresh must sync");
+    };
+    assert_eq!(report.downloaded.len(), 4);
+    let request_count = server.requests().len();
+
+    // the server answers "304 Not Modified" and nothing else is requested
+    assert_eq!(
+        downloader.refresh(mirror.path())?,
+        RefreshOutcome::Unchanged
+    );
+    assert_eq!(server.requests().len(), request_count + 1);
+
+    fs::remove_dir_all(upstream.path().join("repodata"))?;
+    create_upstream_repo_with(upstream.path(), &[("gamma", "gamma payload")])?;
+    assert!(matches!(
+        downloader.refresh(mirror.path())?,
+        RefreshOutcome::Synced(_)
+    ));
+    let mirrored_repo = Repository::load_from_directory(mirror.path())?;
+    assert_eq!(mirrored_repo.packages().len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_refresh_without_validators() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+
+    // file:// responses carry no validators, so the content of repomd.xml is compared instead
+    let url = format!("file://{}", upstream.path().display());
+    let downloader = Downloader::new(&url);
+    downloader.sync_to_directory(mirror.path())?;
+    assert_eq!(
+        downloader.refresh(mirror.path())?,
+        RefreshOutcome::Unchanged
+    );
+
+    fs::remove_dir_all(mirror.path().join("repodata"))?;
+    assert!(matches!(
+        downloader.refresh(mirror.path())?,
+        RefreshOutcome::Synced(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_bandwidth_limits() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let rate = 20_000;
+
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .max_bytes_per_sec(Some(rate));
+    let start = Instant::now();
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(&mirror.path().join("overall"))?;
+    let expected = Duration::from_secs_f64(report.bytes_downloaded as f64 / rate as f64);
+    assert!(start.elapsed() >= expected.mul_f64(0.9));
+
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .max_bytes_per_sec_per_connection(Some(rate));
+    let start = Instant::now();
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(&mirror.path().join("per_connection"))?;
+    let expected = Duration::from_secs_f64(report.bytes_downloaded as f64 / rate as f64);
+    assert!(start.elapsed() >= expected.mul_f64(0.9));
+
+    Ok(())
+}
+
+#[test]
+fn test_package_filter() -> Result<(), MetadataError> {
+    let package = |name: &str, version: &str, arch: &str| {
+        let checksum = Checksum::Sha256(format!("{:064}", 0));
+        Package::new(name, &EVR::new("0", version, "1"), arch, &checksum, "")
+    };
+    let packages = vec![
+        package("foo", "1.0", "x86_64"),
+        package("foo", "2.0", "x86_64"),
+        package("foo", "1.5", "i686"),
+        package("foo", "2.0", "src"),
+        package("foo-debuginfo", "2.0", "x86_64"),
+        package("foo-debugsource", "2.0", "x86_64"),
+        package("kernel-core", "6.1", "x86_64"),
+        package("kernel-modules", "6.1", "x86_64"),
+        package("bar", "1.0", "noarch"),
+    ];
+    let nevras = |packages: Vec<Package>| packages.iter().map(|p| p.nevra()).collect::<Vec<_>>();
+
+    let filter = PackageFilter::default().newest_only(true);
+    assert_eq!(filter.apply(packages.clone()).len(), 8);
+    assert!(!nevras(filter.apply(packages.clone())).contains(&"foo-0:1.0-1.x86_64".to_owned()));
+
+    let filter = PackageFilter::default()
+        .arches(&["x86_64", "noarch"])
+        .exclude_debuginfo(true)
+        .exclude("kernel-[m]*")
+        .exclude("foo-0:1.?-1.*");
+    assert_eq!(
+        nevras(filter.apply(packages.clone())),
+        vec![
+            "foo-0:2.0-1.x86_64",
+            "kernel-core-0:6.1-1.x86_64",
+            "bar-0:1.0-1.noarch"
+        ]
+    );
+
+    let filter = PackageFilter::default().exclude_source(true).exclude("*");
+    assert!(filter.apply(packages.clone()).is_empty());
+    let filter = PackageFilter::default().exclude_source(true);
+    assert_eq!(filter.apply(packages).len(), 8);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_with_package_filter() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo_with_versions(
+        upstream.path(),
+        &[
+            ("foo", "1.0", "x86_64", "old foo"),
+            ("foo", "2.0", "x86_64", "new foo"),
+            ("foo-debuginfo", "2.0", "x86_64", "symbols"),
+            ("foo", "2.0", "aarch64", "arm foo"),
+        ],
+    )?;
+    let server = TestServer::serve(upstream.path());
+
+    let filter = PackageFilter::default()
+        .newest_only(true)
+        .arches(&["x86_64"])
+        .exclude_debuginfo(true);
+    let options = DownloadOptions::default().download_packages(true);
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .with_filter(filter)
+        .sync_to_directory(mirror.path())?;
+
+    assert!(mirror.path().join("Packages/foo-2.0-1.x86_64.rpm").exists());
+    assert_eq!(report.downloaded.len(), 5);
+    assert_eq!(
+        sorted(report.excluded),
+        vec![
+            PathBuf::from("Packages/foo-1.0-1.x86_64.rpm"),
+            PathBuf::from("Packages/foo-2.0-1.aarch64.rpm"),
+            PathBuf::from("Packages/foo-debuginfo-2.0-1.x86_64.rpm"),
+        ]
+    );
+    // the metadata is mirrored as-is
+    let mirrored_repo = Repository::load_from_directory(mirror.path())?;
+    assert_eq!(mirrored_repo.packages().len(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_mismatch_policies() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    // same size, different content
+    fs::write(
+        upstream.path().join("Packages/alpha-1.0-1.noarch.rpm"),
+        "gamma payload",
+    )?;
+    let server = TestServer::serve(upstream.path());
+    let alpha = PathBuf::from("Packages/alpha-1.0-1.noarch.rpm");
+
+    let options = DownloadOptions::default()
+        .download_packages(true)
+        .on_mismatch(MismatchPolicy::Quarantine);
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(mirror.path())?;
+    assert_eq!(report.failed.len(), 1);
+    let failure = &report.failed[0];
+    assert_eq!(failure.href, alpha);
+    assert_eq!(failure.actual_size, 13);
+    assert_eq!(
+        failure.actual_checksum,
+        Some(utils::checksum_file(
+            &upstream.path().join(&alpha),
+            ChecksumType::Sha256
+        )?)
+    );
+    assert_ne!(
+        failure.actual_checksum.as_ref(),
+        Some(&failure.expected_checksum)
+    );
+    let quarantined = failure.quarantined_to.clone().unwrap();
+    assert_eq!(fs::read(mirror.path().join(quarantined))?, b"gamma payload");
+    // everything else made it, and nothing unverified is left in the mirror
+    assert!(!mirror.path().join(&alpha).exists());
+    assert!(mirror
+        .path()
+        .join("Packages/beta-1.0-1.noarch.rpm")
+        .exists());
+    assert!(mirror.path().join("repodata/repomd.xml").exists());
+
+    let options = options.on_mismatch(MismatchPolicy::Delete);
+    let report = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory(&mirror.path().join("deleted"))?;
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].quarantined_to, None);
+    assert!(!mirror.path().join("deleted/.quarantine").exists());
+    assert!(!mirror.path().join("deleted").join(&alpha).exists());
+    assert!(!mirror
+        .path()
+        .join("deleted/Packages/alpha-1.0-1.noarch.rpm.part")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_progress_events() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let log = events.clone();
+    let options = DownloadOptions::default().download_packages(true);
+    let downloader = Downloader::new(&server.url)
+        .with_options(options)
+        .with_progress(move |event| log.lock().unwrap().push(event.clone()));
+    downloader.sync_to_directory(mirror.path())?;
+
+    let package = PathBuf::from("Packages/beta-1.0-1.noarch.rpm");
+    let package_events: Vec<SyncEvent> = events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| match e {
+            SyncEvent::FileStarted { href, .. }
+            | SyncEvent::FileProgress { href, .. }
+            | SyncEvent::FileFinished { href, .. } => *href == package,
+            _ => false,
+        })
+        .cloned()
+        .collect();
+    assert_eq!(
+        package_events,
+        vec![
+            SyncEvent::FileStarted {
+                href: package.clone(),
+                size: Some(12)
+            },
+            SyncEvent::FileProgress {
+                href: package.clone(),
+                bytes: 12
+            },
+            SyncEvent::FileFinished {
+                href: package.clone(),
+                bytes: 12
+            },
+        ]
+    );
+
+    // Everything is in place the second time around
+    events.lock().unwrap().clear();
+    downloader.sync_to_directory(mirror.path())?;
+    let events = events.lock().unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| matches!(e, SyncEvent::FileSkipped { .. }))
+            .count(),
+        5
+    );
+    assert_eq!(
+        events.last(),
+        Some(&SyncEvent::FileFinished {
+            href: PathBuf::from("repodata/repomd.xml"),
+            bytes: fs::metadata(mirror.path().join("repodata/repomd.xml"))?.len()
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_async() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    let options = DownloadOptions::default().download_packages(true);
+    let mut task = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory_async(mirror.path());
+
+    let mut events = Vec::new();
+    while let Some(event) = block_on(|cx| task.poll_event(cx)) {
+        events.push(event);
+    }
+    let report = block_on(|cx| pin!(&mut task).poll(cx))?;
+
+    assert_eq!(report.downloaded.len(), 6);
+    let finished: Vec<PathBuf> = events
+        .into_iter()
+        .filter_map(|e| match e {
+            SyncEvent::FileFinished { href, .. } => Some(href),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(finished, report.downloaded);
+    assert!(mirror
+        .path()
+        .join("Packages/beta-1.0-1.noarch.rpm")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_async_queued_events() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let payload = "x".repeat(2 * 1024 * 1024);
+    create_upstream_repo_with(upstream.path(), &[("big", &payload)])?;
+    let server = TestServer::serve(upstream.path());
+
+    let options = DownloadOptions::default().download_packages(true);
+    let mut task = Downloader::new(&server.url)
+        .with_options(options)
+        .sync_to_directory_async(mirror.path());
+    let report = block_on(|cx| pin!(&mut task).poll(cx))?;
+    assert_eq!(report.downloaded.len(), 5);
+    // the result can only be taken once
+    assert!(block_on(|cx| pin!(&mut task).poll(cx)).is_err());
+
+    // the progress of the package wasn't polled while it was downloaded, so only the latest is left
+    let mut events = Vec::new();
+    while let Some(event) = block_on(|cx| task.poll_event(cx)) {
+        events.push(event);
+    }
+    let href = PathBuf::from("Packages/big-1.0-1.noarch.rpm");
+    let progress: Vec<u64> = events
+        .iter()
+        .filter_map(|e| match e {
+            SyncEvent::FileProgress { href: h, bytes } if h == &href => Some(*bytes),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(progress, [payload.len() as u64]);
+    assert!(events.contains(&SyncEvent::FileFinished {
+        href,
+        bytes: payload.len() as u64
+    }));
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_sync_async_transport() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+    let options = DownloadOptions::default().download_packages(true);
+
+    // the sync runs as a task on the (single-threaded) runtime it was started from
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (events, report) = runtime.block_on(async {
+        let task = Downloader::new(&server.url)
+            .with_options(options)
+            .with_async_transport(ReqwestTransport::new())
+            .sync_to_directory_async(&mirror.path().join("async"));
+        let mut events = Vec::new();
+        while let Some(event) = std::future::poll_fn(|cx| task.poll_event(cx)).await {
+            events.push(event);
+        }
+        (events, task.await)
+    });
+    let report = report?;
+    assert_eq!(report.downloaded.len(), 6);
+    let finished: Vec<PathBuf> = events
+        .into_iter()
+        .filter_map(|e| match e {
+            SyncEvent::FileFinished { href, .. } => Some(href),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(finished, report.downloaded);
+    assert_eq!(
+        fs::read(mirror.path().join("async/Packages/beta-1.0-1.noarch.rpm"))?,
+        b"beta payload"
+    );
+
+    // the blocking methods work as well, outside of a runtime
+    let downloader = Downloader::new(&server.url)
+        .with_options(options)
+        .with_async_transport(ReqwestTransport::new());
+    let report = downloader.sync_to_directory(&mirror.path().join("blocking"))?;
+    assert_eq!(report.downloaded.len(), 6);
+    let report = downloader.sync_to_directory(&mirror.path().join("blocking"))?;
+    assert_eq!(report.skipped.len(), 5);
+
+    // and so do file:// URLs
+    let report = Downloader::new(&format!("file://{}", upstream.path().display()))
+        .with_options(options)
+        .with_async_transport(ReqwestTransport::new())
+        .sync_to_directory(&mirror.path().join("local"))?;
+    assert_eq!(report.downloaded.len(), 6);
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_async_transport_over_https() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve_tls(upstream.path());
+    let tls = Path::new("./tests/assets/tls");
+    let options = DownloadOptions::default().retries(0);
+
+    // the test CA isn't trusted by default
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_async_transport(ReqwestTransport::new())
+        .fetch_repomd();
+    assert!(matches!(result, Err(MetadataError::DownloadError(..))));
+
+    let transport = ReqwestTransport::new()
+        .ca_bundle(tls.join("ca.pem"))
+        .client_cert(tls.join("client.pem"), Some(tls.join("client-key.pem")));
+    let report = Downloader::new(&server.url)
+        .with_options(options.download_packages(true))
+        .with_async_transport(transport.clone())
+        .sync_to_directory(mirror.path())?;
+    assert_eq!(report.downloaded.len(), 6);
+
+    // through an https proxy
+    Downloader::new("http://repo.invalid")
+        .with_options(options)
+        .with_async_transport(transport.proxy(Some(&server.url)))
+        .fetch_repomd()?;
+    assert!(server
+        .requests()
+        .iter()
+        .any(|(path, _)| path.starts_with("http://repo.invalid")));
+
+    // a CA bundle without certificates is rejected
+    let result = Downloader::new(&server.url)
+        .with_options(options)
+        .with_async_transport(ReqwestTransport::new().ca_bundle(tls.join("client-key.pem")))
+        .fetch_repomd();
+    match result {
+        Err(MetadataError::DownloadError(path, message)) => {
+            assert!(path.ends_with("client-key.pem"));
+            assert_eq!(message, "no certificates found");
+        }
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_upload_directory() -> Result<(), MetadataError> {
+    let local = TempDir::new("local")?;
+    let published = TempDir::new("published")?;
+    let mirror = TempDir::new("mirror")?;
+    let local_repo = create_upstream_repo(local.path())?;
+    fs::write(
+        local.path().join(".sync_state"),
+        "not part of the repository",
+    )?;
+    let server = TestServer::serve(published.path());
+
+    let report = Uploader::new(&format!("{}/repo/", server.url)).upload_directory(local.path())?;
+
+    // packages first, then the metadata, then repomd.xml
+    assert_eq!(report.uploaded.len(), 6);
+    assert_eq!(
+        report.uploaded.last(),
+        Some(&PathBuf::from("repodata/repomd.xml"))
+    );
+    let first_metadata = report
+        .uploaded
+        .iter()
+        .position(|href| href.starts_with("repodata"))
+        .unwrap();
+    assert!(report.uploaded[..first_metadata]
+        .iter()
+        .all(|href| href.starts_with("Packages")));
+    assert_eq!(
+        server.requests().last().unwrap().0,
+        "/repo/repodata/repomd.xml"
+    );
+    assert!(!published.path().join("repo/.sync_state").exists());
+
+    // The published repository can be mirrored again
+    let options = DownloadOptions::default().download_packages(true);
+    Downloader::new(&format!("{}/repo", server.url))
+        .with_options(options)
+        .sync_to_directory(mirror.path())?;
+    let mirrored_repo = Repository::load_from_directory(mirror.path())?;
+    assert_eq!(mirrored_repo.packages(), local_repo.packages());
+
+    // Uploading to a file:// URL copies the files
+    let copy = TempDir::new("copy")?;
+    Uploader::with_urls({
+        let base = copy.path().to_owned();
+        move |href| format!("file://{}", base.join(href).display())
+    })
+    .upload_directory(local.path())?;
+    assert_eq!(
+        fs::read(copy.path().join("repodata/repomd.xml"))?,
+        fs::read(local.path().join("repodata/repomd.xml"))?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_load_selected_metadata_from_url() -> Result<(), MetadataError> {
+    let upstream = TempDir::new("upstream")?;
+    let mirror = TempDir::new("mirror")?;
+    let upstream_repo = create_upstream_repo(upstream.path())?;
+    let server = TestServer::serve(upstream.path());
+
+    // the selection wins over the metadata types and packages the downloader is configured for
+    let downloader = Downloader::new(&server.url)
+        .with_options(DownloadOptions::default().download_packages(true))
+        .metadata_types(&["other"]);
+    let options = LoadOptions::default().metadata(MetadataSelection::PRIMARY);
+    let (repo, report) = downloader.load_repository(mirror.path(), options)?;
+
+    assert!(report.is_empty());
+    assert_eq!(
+        repo.packages().keys().collect::<Vec<_>>(),
+        upstream_repo.packages().keys().collect::<Vec<_>>()
+    );
+    let repomd = downloader.fetch_repomd()?;
+    for record in repomd.records() {
+        assert_eq!(
+            mirror.path().join(&record.location_href).exists(),
+            record.metadata_type == MetadataType::Primary
+        );
+    }
+    assert!(!mirror.path().join("Packages").exists());
+
+    Ok(())
+}
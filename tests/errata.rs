@@ -0,0 +1,325 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::errata::{
+    read_csaf_advisory, read_cvrf_advisory, write_csaf_advisory, CsafProfile,
+};
+use rpmrepo_metadata::*;
+
+static CSAF_ADVISORY: &str = r#"{
+  "document": {
+    "category": "csaf_security_advisory",
+    "title": "Red Hat Security Advisory: bind security update",
+    "aggregate_severity": { "namespace": "https://access.redhat.com/security/updates/classification/", "text": "Important" },
+    "distribution": { "text": "Copyright © Red Hat, Inc. All rights reserved." },
+    "publisher": { "category": "vendor", "contact_details": "https://access.redhat.com/security/team/contact/", "name": "Red Hat Product Security" },
+    "notes": [
+      { "category": "summary", "title": "Topic", "text": "An update for bind is now available." },
+      { "category": "general", "title": "Details", "text": "BIND is an implementation of DNS." },
+      { "category": "legal_disclaimer", "title": "Terms of Use", "text": "..." }
+    ],
+    "references": [
+      { "category": "self", "summary": "https://access.redhat.com/errata/RHSA-2022:1234", "url": "https://access.redhat.com/errata/RHSA-2022:1234" }
+    ],
+    "tracking": {
+      "id": "RHSA-2022:1234",
+      "status": "final",
+      "version": "2",
+      "initial_release_date": "2022-04-05T09:12:33+00:00",
+      "current_release_date": "2022-05-10T13:25:45+00:00"
+    }
+  },
+  "product_tree": {
+    "branches": [
+      {
+        "category": "vendor",
+        "name": "Red Hat",
+        "branches": [
+          {
+            "category": "product_family",
+            "name": "Red Hat Enterprise Linux",
+            "branches": [
+              { "category": "product_name", "name": "Red Hat Enterprise Linux AppStream (v. 8)", "product": { "name": "Red Hat Enterprise Linux AppStream (v. 8)", "product_id": "AppStream-8.5.0.Z.MAIN" } }
+            ]
+          },
+          {
+            "category": "architecture",
+            "name": "x86_64",
+            "branches": [
+              { "category": "product_version", "name": "bind-32:9.11.26-6.el8_5.1.x86_64", "product": { "name": "bind-32:9.11.26-6.el8_5.1.x86_64", "product_id": "bind-32:9.11.26-6.el8_5.1.x86_64", "product_identification_helper": { "purl": "pkg:rpm/redhat/bind@9.11.26-6.el8_5.1?arch=x86_64&epoch=32" } } }
+            ]
+          },
+          {
+            "category": "architecture",
+            "name": "src",
+            "branches": [
+              { "category": "product_version", "name": "bind-32:9.11.26-6.el8_5.1.src", "product": { "name": "bind-32:9.11.26-6.el8_5.1.src", "product_id": "bind-32:9.11.26-6.el8_5.1.src" } }
+            ]
+          }
+        ]
+      }
+    ],
+    "relationships": [
+      { "category": "default_component_of", "product_reference": "bind-32:9.11.26-6.el8_5.1.src", "relates_to_product_reference": "AppStream-8.5.0.Z.MAIN" },
+      { "category": "default_component_of", "product_reference": "bind-32:9.11.26-6.el8_5.1.x86_64", "relates_to_product_reference": "AppStream-8.5.0.Z.MAIN" }
+    ]
+  },
+  "vulnerabilities": [
+    {
+      "cve": "CVE-2021-25220",
+      "ids": [ { "system_name": "Red Hat Bugzilla ID", "text": "2064512" } ],
+      "references": [ { "category": "self", "summary": "Canonical URL", "url": "https://access.redhat.com/security/cve/CVE-2021-25220" } ],
+      "remediations": [ { "category": "vendor_fix", "details": "For details on how to apply this update, refer to the documentation.", "product_ids": [] } ]
+    }
+  ]
+}"#;
+
+// The security data API converts CVRF XML to JSON, so lists with one element are not wrapped in arrays
+static CVRF_ADVISORY: &str = r#"{
+  "cvrf": {
+    "document_title": "Red Hat Bug Fix Advisory: tzdata bug fix update",
+    "document_type": "Bug Fix Advisory",
+    "document_publisher": { "type": "Vendor", "contact_details": "secalert@redhat.com", "issuing_authority": "Red Hat Product Security" },
+    "document_tracking": {
+      "identification": { "id": "RHBA-2022:0042" },
+      "status": "Final",
+      "version": "1",
+      "initial_release_date": "2022-01-21T00:00:00Z",
+      "current_release_date": "2022-01-21T00:00:00Z"
+    },
+    "document_notes": {
+      "note": { "title": "Topic", "type": "Summary", "content": "An update for tzdata is now available." }
+    },
+    "document_distribution": "Copyright © 2022 Red Hat, Inc. All rights reserved.",
+    "document_references": {
+      "reference": [
+        { "type": "Self", "url": "https://access.redhat.com/errata/RHBA-2022:0042", "description": "https://access.redhat.com/errata/RHBA-2022:0042" },
+        { "type": "External", "url": "https://bugzilla.redhat.com/show_bug.cgi?id=2040000", "description": "tzdata-2022a" }
+      ]
+    },
+    "product_tree": {
+      "branch": [
+        {
+          "type": "Product Family",
+          "name": "Red Hat Enterprise Linux",
+          "branch": { "type": "Product Name", "name": "Red Hat Enterprise Linux BaseOS (v. 8)", "full_product_name": { "product_id": "BaseOS-8.5.0.Z.MAIN", "content": "Red Hat Enterprise Linux BaseOS (v. 8)" } }
+        },
+        {
+          "type": "Product Version",
+          "name": "tzdata-2022a-1.el8.noarch",
+          "full_product_name": { "product_id": "tzdata-2022a-1.el8.noarch", "content": "tzdata-2022a-1.el8.noarch" }
+        }
+      ],
+      "relationship": {
+        "product_reference": "tzdata-2022a-1.el8.noarch",
+        "relation_type": "Default Component Of",
+        "relates_to_product_reference": "BaseOS-8.5.0.Z.MAIN"
+      }
+    }
+  }
+}"#;
+
+fn collection_package(
+    name: &str,
+    epoch: &str,
+    version: &str,
+    release: &str,
+    arch: &str,
+) -> UpdateCollectionPackage {
+    UpdateCollectionPackage {
+        name: name.to_owned(),
+        epoch: epoch.to_owned(),
+        version: version.to_owned(),
+        release: release.to_owned(),
+        arch: arch.to_owned(),
+        filename: format!("{}-{}-{}.{}.rpm", name, version, release, arch),
+        ..UpdateCollectionPackage::default()
+    }
+}
+
+#[test]
+fn test_read_csaf_advisory() -> Result<(), MetadataError> {
+    let record = read_csaf_advisory(CSAF_ADVISORY.as_bytes())?;
+
+    let expected = UpdateRecord {
+        from: "https://access.redhat.com/security/team/contact/".to_owned(),
+        update_type: "security".to_owned(),
+        status: "final".to_owned(),
+        version: "2".to_owned(),
+        id: "RHSA-2022:1234".to_owned(),
+        title: "Red Hat Security Advisory: bind security update".to_owned(),
+        issued_date: Some("2022-04-05 09:12:33".to_owned()),
+        updated_date: Some("2022-05-10 13:25:45".to_owned()),
+        rights: "Copyright © Red Hat, Inc. All rights reserved.".to_owned(),
+        severity: "Important".to_owned(),
+        summary: "An update for bind is now available.".to_owned(),
+        description: "BIND is an implementation of DNS.".to_owned(),
+        solution: "For details on how to apply this update, refer to the documentation.".to_owned(),
+        references: vec![
+            UpdateReference {
+                href: "https://access.redhat.com/errata/RHSA-2022:1234".to_owned(),
+                id: "RHSA-2022:1234".to_owned(),
+                title: "https://access.redhat.com/errata/RHSA-2022:1234".to_owned(),
+                reftype: "self".to_owned(),
+            },
+            UpdateReference {
+                href: "https://access.redhat.com/security/cve/CVE-2021-25220".to_owned(),
+                id: "CVE-2021-25220".to_owned(),
+                title: "CVE-2021-25220".to_owned(),
+                reftype: "cve".to_owned(),
+            },
+            UpdateReference {
+                href: "https://bugzilla.redhat.com/show_bug.cgi?id=2064512".to_owned(),
+                id: "2064512".to_owned(),
+                title: "".to_owned(),
+                reftype: "bugzilla".to_owned(),
+            },
+        ],
+        pkglist: vec![UpdateCollection {
+            name: "Red Hat Enterprise Linux AppStream (v. 8)".to_owned(),
+            shortname: Some("AppStream-8.5.0.Z.MAIN".to_owned()),
+            packages: vec![
+                collection_package("bind", "32", "9.11.26", "6.el8_5.1", "src"),
+                collection_package("bind", "32", "9.11.26", "6.el8_5.1", "x86_64"),
+            ],
+            module: None,
+        }],
+        ..UpdateRecord::default()
+    };
+    assert_eq!(record, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_cvrf_advisory() -> Result<(), MetadataError> {
+    let record = read_cvrf_advisory(CVRF_ADVISORY.as_bytes())?;
+
+    assert_eq!(record.id, "RHBA-2022:0042");
+    assert_eq!(record.update_type, "bugfix");
+    assert_eq!(record.status, "final");
+    assert_eq!(record.from, "secalert@redhat.com");
+    assert_eq!(record.issued_date.as_deref(), Some("2022-01-21 00:00:00"));
+    assert_eq!(record.summary, "An update for tzdata is now available.");
+    assert_eq!(
+        record.rights,
+        "Copyright © 2022 Red Hat, Inc. All rights reserved."
+    );
+    assert_eq!(
+        record
+            .references
+            .iter()
+            .map(|r| (r.reftype.as_str(), r.id.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("self", "RHBA-2022:0042"), ("bugzilla", "2040000")]
+    );
+    assert_eq!(
+        record.pkglist,
+        vec![UpdateCollection {
+            name: "Red Hat Enterprise Linux BaseOS (v. 8)".to_owned(),
+            shortname: Some("BaseOS-8.5.0.Z.MAIN".to_owned()),
+            packages: vec![collection_package(
+                "tzdata", "0", "2022a", "1.el8", "noarch"
+            )],
+            module: None,
+        }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_read_advisory_missing_fields() {
+    let result = read_csaf_advisory(r#"{"document": {"title": "no tracking"}}"#.as_bytes());
+    assert!(matches!(
+        result,
+        Err(MetadataError::MissingFieldError("tracking"))
+    ));
+
+    let result = read_cvrf_advisory("not json".as_bytes());
+    assert!(matches!(result, Err(MetadataError::JsonParseError(_))));
+}
+
+#[test]
+fn test_imported_advisory_roundtrip() -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    let record = read_csaf_advisory(CSAF_ADVISORY.as_bytes())?;
+    repo.advisories_mut()
+        .insert(record.id.clone(), record.clone());
+
+    let xml = repo.write_metadata_string::<UpdateinfoXml>()?;
+    let mut repo2 = Repository::new();
+    repo2.load_metadata_str::<UpdateinfoXml>(&xml)?;
+
+    assert_eq!(repo2.advisories()[&record.id].id, record.id);
+    assert_eq!(repo2.advisories()[&record.id].severity, record.severity);
+
+    Ok(())
+}
+
+#[test]
+fn test_write_csaf_advisory_roundtrip() -> Result<(), MetadataError> {
+    let record = read_csaf_advisory(CSAF_ADVISORY.as_bytes())?;
+
+    let mut buffer = Vec::new();
+    write_csaf_advisory(&record, CsafProfile::SecurityAdvisory, &mut buffer)?;
+    let exported = read_csaf_advisory(buffer.as_slice())?;
+
+    // document-level references are written before vulnerabilities, and Bugzilla links gain a title
+    let reference_ids = |record: &UpdateRecord| {
+        let mut ids = record
+            .references
+            .iter()
+            .map(|r| (r.reftype.clone(), r.id.clone(), r.href.clone()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+    assert_eq!(reference_ids(&exported), reference_ids(&record));
+    assert_eq!(
+        UpdateRecord {
+            references: Vec::new(),
+            ..exported
+        },
+        UpdateRecord {
+            references: Vec::new(),
+            ..record
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_write_csaf_vex() -> Result<(), MetadataError> {
+    let record = read_cvrf_advisory(CVRF_ADVISORY.as_bytes())?;
+    let record = UpdateRecord {
+        references: vec![UpdateReference {
+            href: "".to_owned(),
+            id: "CVE-2022-0001".to_owned(),
+            title: "CVE-2022-0001".to_owned(),
+            reftype: "cve".to_owned(),
+        }],
+        ..record
+    };
+
+    let mut buffer = Vec::new();
+    write_csaf_advisory(&record, CsafProfile::Vex, &mut buffer)?;
+    let output = std::str::from_utf8(&buffer)?;
+
+    assert!(output.contains(r#""category": "csaf_vex""#));
+    assert!(output.contains(r#""initial_release_date": "2022-01-21T00:00:00Z""#));
+    assert!(output.contains(r#""cve": "CVE-2022-0001""#));
+    assert!(output.contains(
+        r#""fixed": [
+          "BaseOS-8.5.0.Z.MAIN:tzdata-0:2022a-1.el8.noarch"
+        ]"#
+    ));
+    assert!(output.contains(r#""purl": "pkg:rpm/tzdata@2022a-1.el8?arch=noarch&epoch=0""#));
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    extract_repository, MetadataError, Repository, RepositoryOptions, UpdateCollection,
+    UpdateCollectionPackage, UpdateRecordBuilder,
+};
+use tempdir::TempDir;
+
+mod common;
+
+fn fixture_repo() -> Result<Repository, MetadataError> {
+    let mut repo = common::repository([
+        common::package_with_deps(
+            "app",
+            "1.0",
+            "x86_64",
+            &[],
+            &["libfoo", "rpmlib(CompressedFileNames)"],
+        ),
+        common::package_with_deps("unrelated", "1.0", "x86_64", &[], &[]),
+        common::package_with_deps("libfoo", "1.0", "x86_64", &[], &["sh"]),
+        common::package_with_deps("bash", "1.0", "x86_64", &["sh"], &[]),
+    ]);
+    let update = |name: &str| UpdateCollectionPackage {
+        name: name.to_owned(),
+        epoch: "0".to_owned(),
+        version: "1.0".to_owned(),
+        release: "1".to_owned(),
+        arch: "x86_64".to_owned(),
+        filename: format!("{}-1.0-1.x86_64.rpm", name),
+        ..UpdateCollectionPackage::default()
+    };
+    for (id, packages) in [
+        ("FIX-1", vec![update("libfoo"), update("unrelated")]),
+        ("FIX-2", vec![update("unrelated")]),
+        ("NOTICE-1", vec![]),
+    ] {
+        let mut advisory = UpdateRecordBuilder::new()
+            .id(id)
+            .title("a fix")
+            .from("release-engineering@example.com")
+            .issued_date("1683023400")
+            .update_type("bugfix")
+            .build()?;
+        advisory.pkglist.push(UpdateCollection {
+            name: "collection".to_owned(),
+            packages,
+            ..UpdateCollection::default()
+        });
+        repo.advisories_mut().insert(id.to_owned(), advisory);
+    }
+    Ok(repo)
+}
+
+#[test]
+fn test_extract() -> Result<(), MetadataError> {
+    let repo = fixture_repo()?;
+
+    let extracted = repo.extract(&["app-0:1.0-1.x86_64"])?;
+    let names: Vec<&str> = extracted.packages().values().map(|p| p.name()).collect();
+    assert_eq!(names, ["app", "libfoo", "bash"]);
+    assert!(extracted.repoclosure().is_empty());
+    assert_eq!(extracted.advisories().len(), 2);
+    assert!(extracted.advisories().contains_key("NOTICE-1"));
+    let advisory = &extracted.advisories()["FIX-1"];
+    let updated: Vec<&str> = advisory.packages().map(|p| p.name.as_str()).collect();
+    assert_eq!(updated, ["libfoo"]);
+
+    assert!(repo.extract(&["zsh-0:5.9-1.x86_64"]).is_err());
+    assert!(repo.extract(&[])?.packages().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_repository() -> Result<(), MetadataError> {
+    let source = TempDir::new("test_extract_repository_source")?;
+    let destination = TempDir::new("test_extract_repository_destination")?;
+
+    let repo = fixture_repo()?;
+    repo.write_to_directory(source.path())?;
+    for package in repo.packages().values() {
+        fs::write(source.path().join(package.location_href()), package.name())?;
+    }
+
+    extract_repository(
+        source.path(),
+        destination.path(),
+        &["libfoo-0:1.0-1.x86_64"],
+        RepositoryOptions::default(),
+    )?;
+
+    let extracted = Repository::load_from_directory(destination.path())?;
+    let names: Vec<&str> = extracted.packages().values().map(|p| p.name()).collect();
+    assert_eq!(names, ["libfoo", "bash"]);
+    assert_eq!(extracted.advisories().len(), 2);
+    assert_eq!(
+        fs::read_to_string(destination.path().join("bash-1.0-1.x86_64.rpm"))?,
+        "bash"
+    );
+    assert!(!destination.path().join("app-1.0-1.x86_64.rpm").exists());
+
+    Ok(())
+}
@@ -85,6 +85,7 @@ fn test_filelists_xml_writer_file() -> Result<(), MetadataError> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(filelists_name)
         .unwrap();
     let mut writer = FilelistsXml::new_writer(utils::create_xml_writer(f));
@@ -156,7 +157,7 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(filelists_xml.read_header()?, 0);
     let mut package = None;
     filelists_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that no packaged is parsed when there are no packages and the footer element doesn't exist (EOF)
     let mut filelists_xml = FilelistsXml::new_reader(utils::create_xml_reader(
@@ -165,7 +166,7 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(filelists_xml.read_header()?, 0);
     let mut package = None;
     filelists_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that a package is parsed correctly when there is packages
     let mut filelists_xml =
@@ -173,10 +174,10 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(filelists_xml.read_header()?, 1);
     let mut package = None;
     filelists_xml.read_package(&mut package)?;
-    assert!(matches!(package, Some(_)));
+    assert!(package.is_some());
     package.take();
     filelists_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     Ok(())
 }
@@ -0,0 +1,75 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    reset_hash_provider, set_hash_provider, utils, Checksum, ChecksumType, DefaultHashProvider,
+    HashProvider, Hasher,
+};
+
+/// Hashes SHA-256 with the built-in implementation, counting the bytes hashed, and leaves the other
+/// types to the default provider.
+struct CountingProvider(Arc<AtomicUsize>);
+
+struct CountingHasher(Box<dyn Hasher>, Arc<AtomicUsize>);
+
+impl Hasher for CountingHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.1.fetch_add(data.len(), Ordering::SeqCst);
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize()
+    }
+}
+
+impl HashProvider for CountingProvider {
+    fn hasher(&self, checksum_type: ChecksumType) -> Option<Box<dyn Hasher>> {
+        match checksum_type {
+            ChecksumType::Sha256 => Some(Box::new(CountingHasher(
+                DefaultHashProvider.hasher(checksum_type)?,
+                self.0.clone(),
+            ))),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_hash_provider() -> Result<(), rpmrepo_metadata::MetadataError> {
+    let sha256 = Checksum::Sha256(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned(),
+    );
+    assert_eq!(utils::checksum_bytes(b"abc", ChecksumType::Sha256)?, sha256);
+
+    let hashed = Arc::new(AtomicUsize::new(0));
+    set_hash_provider(CountingProvider(hashed.clone()));
+    assert_eq!(utils::checksum_bytes(b"abc", ChecksumType::Sha256)?, sha256);
+    assert_eq!(hashed.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        utils::checksum_bytes(b"abc", ChecksumType::Sha1)?,
+        Checksum::Sha1("a9993e364706816aba3e25717850c26c9cd0d89d".to_owned())
+    );
+    assert_eq!(hashed.load(Ordering::SeqCst), 3);
+
+    reset_hash_provider();
+    utils::checksum_bytes(b"abc", ChecksumType::Sha256)?;
+    assert_eq!(hashed.load(Ordering::SeqCst), 3);
+    Ok(())
+}
+
+#[test]
+fn test_checksum_missing_file() {
+    let missing = std::path::Path::new("./tests/assets/does-not-exist.xml");
+    assert!(matches!(
+        utils::checksum_file(missing, ChecksumType::Sha256),
+        Err(rpmrepo_metadata::MetadataError::IoError(_))
+    ));
+}
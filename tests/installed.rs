@@ -0,0 +1,113 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    AdvisorySeverity, Checksum, InstalledSet, MetadataError, Package, Repository, UpdateRecord, EVR,
+};
+
+const INVENTORY: &str = "\
+# rpm -qa
+bash-5.2.15-3.fc38.x86_64
+gpg-pubkey-eb10b464-6202d9c6
+kernel-core-6.2.9-300.fc38.x86_64
+kernel-core-6.2.15-300.fc38.x86_64
+
+# rpm -qa --qf '%{NAME} %{EPOCH} %{VERSION} %{RELEASE} %{ARCH}\\n'
+openssl-libs 1 3.0.8 1.fc38 x86_64
+glibc (none) 2.37 4.fc38 i686
+tzdata 2023c 1.fc38 noarch
+";
+
+#[test]
+fn test_parse_installed_set() -> Result<(), MetadataError> {
+    let installed = InstalledSet::parse(INVENTORY)?;
+    let nevras: Vec<String> = installed.iter().map(|package| package.nevra()).collect();
+    assert_eq!(
+        nevras,
+        [
+            "bash-0:5.2.15-3.fc38.x86_64",
+            "kernel-core-0:6.2.9-300.fc38.x86_64",
+            "kernel-core-0:6.2.15-300.fc38.x86_64",
+            "openssl-libs-1:3.0.8-1.fc38.x86_64",
+            "glibc-0:2.37-4.fc38.i686",
+            "tzdata-0:2023c-1.fc38.noarch",
+        ]
+    );
+    assert_eq!(installed.get("kernel-core").count(), 2);
+    assert_eq!(installed.get("zsh").count(), 0);
+
+    let installed = InstalledSet::parse("bash-1:5.2.15-3.fc38.x86_64")?;
+    assert_eq!(
+        installed.iter().next().map(Package::evr),
+        Some(&EVR::new("1", "5.2.15", "3.fc38"))
+    );
+
+    assert!(matches!(
+        InstalledSet::parse("bash"),
+        Err(MetadataError::InvalidFieldError("installed package", line)) if line == "bash"
+    ));
+    assert!(InstalledSet::parse("bash x86_64").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_installed_set_advisories() -> Result<(), MetadataError> {
+    let fixed = Package::builder()
+        .name("openssl-libs")
+        .arch("x86_64")
+        .evr(EVR::new("1", "3.0.9", "1.fc38"))
+        .checksum(Checksum::Sha256("abcd".repeat(16)))
+        .location_href("openssl-libs-3.0.9-1.fc38.x86_64.rpm")
+        .build()?;
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2023-0002")
+        .title("openssl security update")
+        .from("updates@fedoraproject.org")
+        .update_type("security")
+        .severity("Important")
+        .issued_date("2023-05-30 00:00:00")
+        .updated_date("2023-05-30 00:00:00")
+        .packages("F38", "Fedora 38", [&fixed])
+        .build()?;
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(fixed.pkgid().to_owned(), fixed.clone());
+    repo.advisories_mut()
+        .insert(advisory.id.clone(), advisory.clone());
+
+    let installed = InstalledSet::parse(INVENTORY)?;
+    assert!(advisory.applies_to(&installed));
+    let feed = repo.security_updates(&installed, AdvisorySeverity::Moderate);
+    assert_eq!(feed.updates.len(), 1);
+    assert_eq!(
+        feed.updates[0].packages[0].installed,
+        EVR::new("1", "3.0.8", "1.fc38")
+    );
+
+    let installed = InstalledSet::parse("openssl-libs-1:3.0.9-1.fc38.x86_64")?;
+    assert!(!advisory.applies_to(&installed));
+    Ok(())
+}
+
+#[test]
+fn test_repository_from_installed() -> Result<(), MetadataError> {
+    let installed = InstalledSet::parse(INVENTORY)?;
+    let repo = Repository::from_installed(&installed);
+    assert_eq!(repo.packages().len(), installed.len());
+    assert_eq!(
+        repo.packages()["kernel-core-0:6.2.15-300.fc38.x86_64"].evr(),
+        &EVR::new("0", "6.2.15", "300.fc38")
+    );
+    assert_eq!(
+        repo.packages()
+            .values()
+            .filter(|package| package.name() == "kernel-core")
+            .count(),
+        2
+    );
+    Ok(())
+}
@@ -0,0 +1,164 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{Comps, ManifestItem, MetadataError, PackageManifest, Repository};
+
+mod common;
+
+use common::package;
+
+const KICKSTART: &str = r#"
+lang en_US.UTF-8
+rootpw --lock
+
+%packages --ignoremissing --excludedocs
+@^minimal-environment
+@standard --optional
+vim-*
+glibc.i686
+# nobody needs this
+-vim-enhanced
+zsh   # no comment
+%end
+
+%post
+echo "not a package"
+%end
+"#;
+
+const COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<comps>
+  <group>
+    <id>core</id>
+    <name>Core</name>
+    <packagelist>
+      <packagereq type="mandatory">bash</packagereq>
+    </packagelist>
+  </group>
+  <group>
+    <id>standard</id>
+    <name>Standard</name>
+    <packagelist>
+      <packagereq type="default">less</packagereq>
+      <packagereq type="optional">tmux</packagereq>
+      <packagereq type="optional">screen</packagereq>
+    </packagelist>
+  </group>
+  <environment>
+    <id>minimal-environment</id>
+    <name>Minimal</name>
+    <grouplist>
+      <groupid>core</groupid>
+    </grouplist>
+  </environment>
+</comps>
+"#;
+
+fn fixture_repo() -> Repository {
+    common::repository([
+        package("bash", "5.2", "x86_64"),
+        package("glibc", "2.37", "x86_64"),
+        package("glibc", "2.37", "i686"),
+        package("vim-minimal", "9.0", "x86_64"),
+        package("vim-minimal", "9.1", "x86_64"),
+        package("vim-minimal", "9.1", "src"),
+        package("vim-enhanced", "9.1", "x86_64"),
+        package("less", "633", "x86_64"),
+        package("tmux", "3.3a", "x86_64"),
+    ])
+}
+
+#[test]
+fn test_manifest_from_kickstart() -> Result<(), MetadataError> {
+    let manifest = PackageManifest::from_kickstart(KICKSTART)?;
+    assert_eq!(
+        manifest.include,
+        [
+            ManifestItem::Environment("minimal-environment".to_owned()),
+            ManifestItem::Group {
+                id: "standard".to_owned(),
+                optional: true
+            },
+            ManifestItem::Package("vim-*".to_owned()),
+            ManifestItem::Package("glibc.i686".to_owned()),
+            ManifestItem::Package("zsh".to_owned()),
+        ]
+    );
+    assert_eq!(
+        manifest.exclude,
+        [ManifestItem::Package("vim-enhanced".to_owned())]
+    );
+    assert!(manifest.include_core);
+    assert!(manifest.ignore_missing);
+
+    let manifest = PackageManifest::from_kickstart("%packages --nocore\n%end\n")?;
+    assert!(!manifest.include_core);
+    assert!(manifest.include.is_empty());
+    assert!(PackageManifest::from_kickstart("lang en_US.UTF-8\n").is_err());
+
+    let manifest: PackageManifest = "@core\n\n-@^server\nbash-5.2-1.x86_64\n".parse()?;
+    assert_eq!(
+        manifest,
+        PackageManifest::new()
+            .include(ManifestItem::Group {
+                id: "core".to_owned(),
+                optional: false
+            })
+            .include(ManifestItem::Package("bash-5.2-1.x86_64".to_owned()))
+            .exclude(ManifestItem::Environment("server".to_owned()))
+    );
+    assert!("bash --optional".parse::<PackageManifest>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_resolve_manifest() -> Result<(), MetadataError> {
+    let repo = fixture_repo();
+    let comps: Comps = COMPS.parse()?;
+
+    let manifest = PackageManifest::from_kickstart(KICKSTART)?;
+    let resolution = repo.resolve_manifest(&manifest, Some(&comps), None)?;
+    assert_eq!(
+        resolution.nevras,
+        [
+            "bash-0:5.2-1.x86_64",
+            "glibc-0:2.37-1.i686",
+            "vim-minimal-0:9.1-1.x86_64",
+            "less-0:633-1.x86_64",
+            "tmux-0:3.3a-1.x86_64",
+        ]
+    );
+    assert_eq!(resolution.missing, ["screen", "zsh"]);
+
+    // missing packages are an error unless they're ignored
+    let strict = manifest.clone().ignore_missing(false);
+    assert!(repo.resolve_manifest(&strict, Some(&comps), None).is_err());
+
+    // groups can't be found without comps
+    let resolution = repo.resolve_manifest(&manifest, None, None)?;
+    assert!(resolution
+        .missing
+        .contains(&"@^minimal-environment".to_owned()));
+    assert!(!resolution
+        .nevras
+        .contains(&"bash-0:5.2-1.x86_64".to_owned()));
+
+    // only packages of the arch are selected
+    let manifest: PackageManifest = "glibc\nbash-5.2\n".parse()?;
+    let resolution = repo.resolve_manifest(&manifest, None, Some("x86_64"))?;
+    assert_eq!(
+        resolution.nevras,
+        ["bash-0:5.2-1.x86_64", "glibc-0:2.37-1.x86_64"]
+    );
+
+    // the packages can be extracted into a repository of their own
+    let nevras: Vec<&str> = resolution.nevras.iter().map(String::as_str).collect();
+    assert_eq!(repo.extract(&nevras)?.packages().len(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,333 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    MetadataError, ModuleDocument, ModuleObsoletes, Modules, Repository, Severity,
+    UpdateCollection, UpdateCollectionModule, UpdateCollectionPackage, UpdateRecord,
+    ValidationCheck,
+};
+
+const MODULES_YAML: &str = r#"---
+document: modulemd
+version: 2
+data:
+  name: nodejs
+  stream: "18"
+  version: 8070020230306170042
+  context: ad008a3a
+  arch: x86_64
+  summary: Javascript runtime
+  description: >-
+    Node.js is a platform built on Chrome's JavaScript runtime
+    for easily building fast, scalable network applications.
+  license:
+    module:
+    - MIT
+  profiles:
+    common:
+      rpms:
+      - nodejs
+      - npm
+  artifacts:
+    rpms:
+    - nodejs-1:18.14.2-2.module+el8.7.0+18219+7a1a4d0d.src
+    - nodejs-1:18.14.2-2.module+el8.7.0+18219+7a1a4d0d.x86_64 # the runtime
+    - npm-1:9.5.0-1.18.14.2.2.module+el8.7.0+18219+7a1a4d0d.x86_64
+...
+---
+document: modulemd-defaults
+version: 1
+data:
+  module: nodejs
+  stream: "10"
+  profiles:
+    10: [common]
+    18: [common, development]
+...
+---
+document: modulemd-defaults
+version: 1
+data:
+  module: nodejs
+  modified: 202302010000
+  stream: "18"
+  profiles:
+    18: [common]
+...
+---
+document: modulemd-obsoletes
+version: 1
+data:
+  modified: 2022-01-24T08:54Z
+  module: nodejs
+  stream: "12"
+  eol_date: 2022-04-30T00:00Z
+  message: "Module stream nodejs:12 is no longer supported."
+  obsoleted_by:
+    module: nodejs
+    stream: "16"
+...
+---
+document: modulemd-translations
+version: 1
+data:
+  module: nodejs
+  stream: "18"
+  modified: 202301010000
+  translations:
+    de:
+      summary: JavaScript-Laufzeitumgebung
+...
+"#;
+
+#[test]
+fn test_modules_yaml() -> Result<(), MetadataError> {
+    let modules: Modules = MODULES_YAML.parse()?;
+    assert_eq!(modules.documents().len(), 5);
+
+    let streams: Vec<_> = modules.streams().collect();
+    assert_eq!(streams.len(), 1);
+    assert_eq!(
+        streams[0].nsvca(),
+        "nodejs:18:8070020230306170042:ad008a3a:x86_64"
+    );
+    assert_eq!(streams[0].summary(), "Javascript runtime");
+    assert_eq!(streams[0].rpm_artifacts().len(), 3);
+    assert_eq!(
+        streams[0].rpm_artifacts()[1],
+        "nodejs-1:18.14.2-2.module+el8.7.0+18219+7a1a4d0d.x86_64"
+    );
+
+    // the most recently modified defaults win
+    assert_eq!(modules.defaults().count(), 2);
+    assert_eq!(modules.default_stream("nodejs"), Some("18"));
+    assert_eq!(
+        modules.default_profiles("nodejs", "18"),
+        Some(&["common".to_owned()][..])
+    );
+    assert_eq!(modules.default_profiles("nodejs", "10"), None);
+    assert_eq!(modules.default_stream("perl"), None);
+
+    let obsoletes = modules.obsoletes_of("nodejs", "12", None).unwrap();
+    assert_eq!(
+        obsoletes,
+        &ModuleObsoletes {
+            module: "nodejs".to_owned(),
+            stream: "12".to_owned(),
+            modified: "2022-01-24T08:54Z".to_owned(),
+            message: "Module stream nodejs:12 is no longer supported.".to_owned(),
+            eol_date: Some("2022-04-30T00:00Z".to_owned()),
+            obsoleted_by: Some(("nodejs".to_owned(), "16".to_owned())),
+            ..ModuleObsoletes::default()
+        }
+    );
+    assert!(modules.obsoletes_of("nodejs", "18", None).is_none());
+
+    match &modules.documents()[4] {
+        ModuleDocument::Unknown { document, yaml } => {
+            assert_eq!(document, "modulemd-translations");
+            assert!(yaml.contains("summary: JavaScript-Laufzeitumgebung\n"));
+        }
+        other => panic!("unexpected document {:?}", other),
+    }
+
+    // streams and unknown documents are written as they were read
+    let written = modules.to_yaml_string();
+    assert!(written.contains(streams[0].yaml()));
+    assert!(written.contains("translations:\n    de:\n"));
+    let reloaded: Modules = written.parse()?;
+    assert_eq!(reloaded, modules);
+
+    Ok(())
+}
+
+#[test]
+fn test_modules_obsoletes_reset() -> Result<(), MetadataError> {
+    let mut modules: Modules = MODULES_YAML.parse()?;
+    let mut reset = modules.obsoletes_of("nodejs", "12", None).unwrap().clone();
+    reset.modified = "2022-02-01T00:00Z".to_owned();
+    reset.reset = true;
+    reset.obsoleted_by = None;
+    modules
+        .documents_mut()
+        .push(ModuleDocument::Obsoletes(reset.clone()));
+    assert!(modules.obsoletes_of("nodejs", "12", None).is_none());
+
+    let reloaded: Modules = modules.to_yaml_string().parse()?;
+    assert_eq!(reloaded.obsoletes().last(), Some(&reset));
+
+    Ok(())
+}
+
+#[test]
+fn test_modules_yaml_errors() {
+    let missing = "---\ndocument: modulemd-defaults\nversion: 1\ndata:\n  stream: \"1\"\n...\n";
+    assert!(matches!(
+        missing.parse::<Modules>(),
+        Err(MetadataError::MissingFieldError("module"))
+    ));
+
+    let invalid = "---\ndocument: modulemd\ndata:\n  name: x\n    stream: y\n...\n";
+    assert!(matches!(
+        invalid.parse::<Modules>(),
+        Err(MetadataError::YamlParseError(5, _))
+    ));
+}
+
+#[test]
+fn test_validate_modules() -> Result<(), MetadataError> {
+    let modules: Modules = MODULES_YAML.parse()?;
+    let package = |name: &str, epoch: &str, version: &str, release: &str| UpdateCollectionPackage {
+        name: name.to_owned(),
+        epoch: epoch.to_owned(),
+        version: version.to_owned(),
+        release: release.to_owned(),
+        arch: "x86_64".to_owned(),
+        ..UpdateCollectionPackage::default()
+    };
+    let module = |stream: &str| UpdateCollectionModule {
+        name: "nodejs".to_owned(),
+        stream: stream.to_owned(),
+        version: 8070020230306170042,
+        context: "ad008a3a".to_owned(),
+        arch: "x86_64".to_owned(),
+    };
+    let nodejs = package("nodejs", "1", "18.14.2", "2.module+el8.7.0+18219+7a1a4d0d");
+    let npm = package(
+        "npm",
+        "1",
+        "9.5.0",
+        "1.18.14.2.2.module+el8.7.0+18219+7a1a4d0d",
+    );
+
+    let mut repo = Repository::new();
+    for (id, collection) in [
+        (
+            "RHSA-2023:1",
+            UpdateCollection {
+                name: "nodejs".to_owned(),
+                packages: vec![nodejs.clone(), npm.clone()],
+                module: Some(module("18")),
+                ..UpdateCollection::default()
+            },
+        ),
+        // the stream was removed from the repository, but not its advisory
+        (
+            "RHSA-2023:2",
+            UpdateCollection {
+                name: "nodejs".to_owned(),
+                packages: vec![package("nodejs", "1", "16.19.1", "1.module+el8.7.0")],
+                module: Some(module("16")),
+                ..UpdateCollection::default()
+            },
+        ),
+        (
+            "RHSA-2023:3",
+            UpdateCollection {
+                name: "nodejs".to_owned(),
+                packages: vec![nodejs, package("nodejs-docs", "", "18.14.2", "2")],
+                module: Some(module("18")),
+                ..UpdateCollection::default()
+            },
+        ),
+        (
+            "RHBA-2023:4",
+            UpdateCollection {
+                name: "npm".to_owned(),
+                packages: vec![npm, package("bash", "0", "4.4.20", "4.el8")],
+                ..UpdateCollection::default()
+            },
+        ),
+    ] {
+        let advisory = UpdateRecord::builder()
+            .id(id)
+            .title("an update")
+            .from("release-engineering@redhat.com")
+            .update_type("security")
+            .issued_date("2023-03-14 10:00:00")
+            .collection(collection)
+            .build()?;
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+
+    let report = repo.validate_modules(&modules);
+    let issues: Vec<(Severity, ValidationCheck, &str)> = report
+        .issues
+        .iter()
+        .map(|issue| {
+            (
+                issue.severity,
+                issue.check,
+                issue.entry.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        issues,
+        [
+            (
+                Severity::Error,
+                ValidationCheck::AdvisoryUnknownModule,
+                "RHSA-2023:2"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::AdvisoryModuleMismatch,
+                "RHSA-2023:3"
+            ),
+            (
+                Severity::Warning,
+                ValidationCheck::AdvisoryModuleMismatch,
+                "RHBA-2023:4"
+            ),
+        ]
+    );
+    assert_eq!(
+        report.issues[0].message,
+        "the module stream nodejs:16:8070020230306170042:ad008a3a:x86_64 isn't in modules.yaml"
+    );
+    assert_eq!(
+        report.issues[1].message,
+        "nodejs-docs-0:18.14.2-2.x86_64 isn't an artifact of the module stream \
+         nodejs:18:8070020230306170042:ad008a3a:x86_64"
+    );
+    assert!(!report.is_valid());
+
+    Ok(())
+}
+
+#[test]
+fn test_modules_yaml_block_scalars() -> Result<(), MetadataError> {
+    let stream = |summary: &str| -> Result<String, MetadataError> {
+        let yaml = format!(
+            "---\ndocument: modulemd\nversion: 2\ndata:\n  name: nodejs\n  stream: \"18\"\n  summary: {}\n  license:\n    module: [MIT]\n...\n",
+            summary
+        );
+        let modules: Modules = yaml.parse()?;
+        let summary = modules.streams().next().unwrap().summary().to_owned();
+        Ok(summary)
+    };
+
+    // lines are joined with spaces, empty lines are line breaks, more indented lines are kept as they are
+    assert_eq!(
+        stream(
+            ">\n    Javascript runtime\n    for servers\n\n    and the command line\n      with more indentation\n    kept as is\n"
+        )?,
+        "Javascript runtime for servers\nand the command line\n  with more indentation\nkept as is\n"
+    );
+    assert_eq!(
+        stream(">-\n    one\n    two\n\n\n    three\n")?,
+        "one two\n\nthree"
+    );
+    assert_eq!(stream(">+\n    one\n    two\n")?, "one two\n\n");
+    assert_eq!(
+        stream("|\n    one\n    two\n\n    three\n")?,
+        "one\ntwo\n\nthree\n"
+    );
+
+    Ok(())
+}
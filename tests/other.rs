@@ -81,6 +81,7 @@ fn test_other_xml_writer_file() -> Result<(), MetadataError> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(other_name)
         .unwrap();
     let mut writer = OtherXml::new_writer(utils::create_xml_writer(f));
@@ -149,7 +150,7 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(other_xml.read_header()?, 0);
     let mut package = None;
     other_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that no packaged is parsed when there are no packages and the footer element doesn't exist (EOF)
     let mut other_xml = OtherXml::new_reader(utils::create_xml_reader(
@@ -158,7 +159,7 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(other_xml.read_header()?, 0);
     let mut package = None;
     other_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that a package is parsed correctly when there is packages
     let mut other_xml =
@@ -166,10 +167,10 @@ fn test_filelists_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(other_xml.read_header()?, 1);
     let mut package = None;
     other_xml.read_package(&mut package)?;
-    assert!(matches!(package, Some(_)));
+    assert!(package.is_some());
     package.take();
     other_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     Ok(())
 }
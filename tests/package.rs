@@ -9,7 +9,6 @@ extern crate rpmrepo_metadata;
 use pretty_assertions::assert_eq;
 use rpmrepo_metadata::*;
 use std::fs::OpenOptions;
-use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 use tempdir::TempDir;
 
@@ -25,3 +24,281 @@ fn test_read_rpm_from_file() -> Result<(), MetadataError> {
 
     Ok(())
 }
+
+#[test]
+fn test_read_rpm_directory_failure_policy() -> Result<(), MetadataError> {
+    use std::sync::{Arc, Mutex};
+
+    let tmp_dir = TempDir::new("test_read_rpm_directory")?;
+    std::fs::create_dir_all(tmp_dir.path().join("Packages/c"))?;
+    std::fs::copy(
+        COMPLEX_PKG_PATH,
+        tmp_dir
+            .path()
+            .join("Packages/c/complex-package-2.3.4-5.el8.x86_64.rpm"),
+    )?;
+    // e.g. a download which was cut short
+    std::fs::write(
+        tmp_dir.path().join("Packages/broken-1.0-1.noarch.rpm"),
+        b"\xed\xab\xee\xdb\x03\x00",
+    )?;
+
+    let result = utils::load_rpm_directory(tmp_dir.path(), &utils::FailurePolicy::Abort);
+    assert!(result.is_err());
+
+    let report = utils::load_rpm_directory(tmp_dir.path(), &utils::FailurePolicy::Skip)?;
+    assert_eq!(report.packages.len(), 1);
+    assert_eq!(
+        report.packages[0].location_href(),
+        "Packages/c/complex-package-2.3.4-5.el8.x86_64.rpm"
+    );
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(
+        report.failures[0].path,
+        Path::new("Packages/broken-1.0-1.noarch.rpm")
+    );
+
+    let quarantined = Arc::new(Mutex::new(Vec::new()));
+    let callback_quarantined = quarantined.clone();
+    let policy = utils::FailurePolicy::Quarantine(Arc::new(move |path, _error| {
+        callback_quarantined.lock().unwrap().push(path.to_owned());
+        Ok(())
+    }));
+    let report = utils::load_rpm_directory(tmp_dir.path(), &policy)?;
+    assert_eq!(report.packages.len(), 1);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(
+        *quarantined.lock().unwrap(),
+        vec![tmp_dir.path().join("Packages/broken-1.0-1.noarch.rpm")]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_read_rpm_directory_hooks() -> Result<(), MetadataError> {
+    use std::sync::Mutex;
+
+    struct Scanned(Mutex<Vec<String>>);
+
+    impl RepositoryHooks for Scanned {
+        fn package_scanned(&self, path: &Path, package: &Package) -> Result<(), MetadataError> {
+            assert!(path.exists());
+            self.0
+                .lock()
+                .unwrap()
+                .push(package.location_href().to_owned());
+            Ok(())
+        }
+    }
+
+    let tmp_dir = TempDir::new("test_read_rpm_directory_hooks")?;
+    std::fs::copy(
+        COMPLEX_PKG_PATH,
+        tmp_dir
+            .path()
+            .join("complex-package-2.3.4-5.el8.x86_64.rpm"),
+    )?;
+
+    let hooks = Scanned(Mutex::new(Vec::new()));
+    let report =
+        utils::load_rpm_directory_with_hooks(tmp_dir.path(), &utils::FailurePolicy::Abort, &hooks)?;
+    assert_eq!(report.packages.len(), 1);
+    assert_eq!(
+        *hooks.0.lock().unwrap(),
+        ["complex-package-2.3.4-5.el8.x86_64.rpm"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_read_rpm_directory_checkpoint() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_read_rpm_directory_checkpoint")?;
+    let rpms = tmp_dir.path().join("rpms");
+    std::fs::create_dir(&rpms)?;
+    std::fs::copy(
+        COMPLEX_PKG_PATH,
+        rpms.join("complex-package-2.3.4-5.el8.x86_64.rpm"),
+    )?;
+
+    let checkpoint_path = tmp_dir.path().join("checkpoint");
+    let mut checkpoint = ScanCheckpoint::open(&checkpoint_path)?;
+    let report = utils::load_rpm_directory_with_checkpoint(
+        &rpms,
+        &utils::FailurePolicy::Abort,
+        &mut checkpoint,
+    )?;
+    assert_eq!(report.packages.len(), 1);
+
+    // the package comes from the checkpoint as long as the size and mtime of the RPM are unchanged,
+    // so breaking its header without changing either doesn't matter
+    let rpm_path = rpms.join("complex-package-2.3.4-5.el8.x86_64.rpm");
+    let modified = std::fs::metadata(&rpm_path)?.modified()?;
+    let mut rpm = OpenOptions::new().write(true).open(&rpm_path)?;
+    std::io::Write::write_all(&mut rpm, b"\0\0\0\0")?;
+    rpm.set_modified(modified)?;
+    let mut checkpoint = ScanCheckpoint::open(&checkpoint_path)?;
+    let resumed = utils::load_rpm_directory_with_checkpoint(
+        &rpms,
+        &utils::FailurePolicy::Abort,
+        &mut checkpoint,
+    )?;
+    assert_eq!(resumed.packages, report.packages);
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_payload_files() -> Result<(), MetadataError> {
+    let repo_root = Path::new("./tests/assets/packages/");
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_location_href("complex-package-2.3.4-5.el8.x86_64.rpm");
+
+    let mut payload = package.open_rpm(repo_root)?;
+    let mut entries = Vec::new();
+    while let Some(entry) = payload.next_entry()? {
+        entries.push(entry);
+    }
+    let config = entries
+        .iter()
+        .find(|entry| entry.path == "/etc/complex/pkg.cfg")
+        .unwrap();
+    assert!(config.is_file());
+    assert_eq!(config.size, 0);
+    assert!(entries
+        .iter()
+        .any(|entry| entry.path == "/var/lib/complex" && entry.is_dir()));
+    // ghost files aren't part of the payload
+    assert!(!entries
+        .iter()
+        .any(|entry| entry.path == "/var/log/complex.log"));
+
+    // directories and paths the package doesn't have are left out
+    let extracted = package.extract_files(
+        repo_root,
+        &["/etc/complex/pkg.cfg", "/var/lib/complex", "/etc/passwd"],
+    )?;
+    assert_eq!(extracted.len(), 1);
+    assert_eq!(extracted[0].0.path, "/etc/complex/pkg.cfg");
+    assert_eq!(extracted[0].1, b"");
+
+    let tmp_dir = TempDir::new("test_extract_payload_files")?;
+    let written = package.extract_files_to(repo_root, &["/etc/complex/pkg.cfg"], tmp_dir.path())?;
+    assert_eq!(written, vec![tmp_dir.path().join("etc/complex/pkg.cfg")]);
+    assert!(written[0].is_file());
+
+    // the RPM has to be a build of the package
+    package.set_release("6.el8");
+    assert!(matches!(
+        package.open_rpm(repo_root),
+        Err(MetadataError::InconsistentMetadataError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_rpm_bytes() -> Result<(), MetadataError> {
+    let bytes = std::fs::read(COMPLEX_PKG_PATH)?;
+    let mut pkg = utils::load_rpm_bytes(&bytes, "complex-package-2.3.4-5.el8.x86_64.rpm")?;
+    assert_eq!(pkg.time_file(), 0);
+    pkg.set_time_file(common::COMPLEX_PACKAGE.time_file());
+    assert_eq!(&pkg, &*common::COMPLEX_PACKAGE);
+
+    Ok(())
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn test_repository_from_layer() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_from_layer")?;
+    let cache = tmp_dir.path().join("var/cache/dnf/packages");
+    std::fs::create_dir_all(&cache)?;
+    std::fs::copy(
+        COMPLEX_PKG_PATH,
+        cache.join("complex-package-2.3.4-5.el8.x86_64.rpm"),
+    )?;
+    // deleted from the layer below
+    std::fs::write(cache.join(".wh.other-1.0-1.x86_64.rpm"), b"")?;
+
+    let repo = repository_from_layer(tmp_dir.path())?;
+    assert_eq!(repo.packages().len(), 1);
+    let package = repo.packages().values().next().unwrap();
+    assert_eq!(package.nevra(), common::COMPLEX_PACKAGE.nevra());
+    assert_eq!(
+        package.location_href(),
+        "var/cache/dnf/packages/complex-package-2.3.4-5.el8.x86_64.rpm"
+    );
+
+    // the same layer as a compressed blob, as it's stored in an image
+    let rpm = std::fs::read(COMPLEX_PKG_PATH)?;
+    let mut layer = Vec::new();
+    for (name, data) in [
+        ("etc/os-release", &b"NAME=Fedora\n"[..]),
+        ("var/cache/dnf/packages/.wh.other-1.0-1.x86_64.rpm", b""),
+        (
+            "var/cache/dnf/packages/complex-package-2.3.4-5.el8.x86_64.rpm",
+            &rpm,
+        ),
+    ] {
+        common::tar_entry(&mut layer, common::tar_header(name, data.len(), b'0'), data);
+    }
+    layer.extend([0u8; 1024]);
+    let blob = tmp_dir.path().join("layer.tar.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&blob)?,
+        flate2::Compression::default(),
+    );
+    std::io::Write::write_all(&mut encoder, &layer)?;
+    encoder.finish()?;
+    assert_eq!(repository_from_layer(&blob)?.packages(), repo.packages());
+
+    Ok(())
+}
+
+#[test]
+fn test_signature_status() {
+    let fedora_38 = "6A51BBABBA3D5467B6171221809A8D7CEB10B464";
+    assert_eq!(
+        SignatureStatus::new(None, &[fedora_38]),
+        SignatureStatus::Unsigned
+    );
+    // keys can be given as fingerprints, long or short key IDs
+    for allowed in [fedora_38, "809A8D7CEB10B464", "eb10b464"] {
+        assert_eq!(
+            SignatureStatus::new(Some("809a8d7ceb10b464"), &[allowed]),
+            SignatureStatus::Signed("809a8d7ceb10b464".to_owned())
+        );
+    }
+    assert_eq!(
+        SignatureStatus::new(Some("50cb390b3c3359c4"), &[fedora_38]),
+        SignatureStatus::UnexpectedKey("50cb390b3c3359c4".to_owned())
+    );
+    // without expected keys any signature will do
+    assert_eq!(
+        SignatureStatus::new(Some("50cb390b3c3359c4"), &[]),
+        SignatureStatus::Signed("50cb390b3c3359c4".to_owned())
+    );
+}
+
+#[test]
+fn test_check_signatures() -> Result<(), MetadataError> {
+    let repo_root = Path::new("./tests/assets/packages/");
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_location_href("complex-package-2.3.4-5.el8.x86_64.rpm");
+    assert_eq!(package.signature_key_id(repo_root)?, None);
+
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    let report = repo.check_signatures(repo_root, &["eb10b464"])?;
+    assert!(!report.is_compliant());
+    assert_eq!(
+        report.unsigned().collect::<Vec<_>>(),
+        ["complex-package-1:2.3.4-5.el8.x86_64"]
+    );
+    assert_eq!(report.unexpected_keys().count(), 0);
+
+    Ok(())
+}
@@ -134,6 +134,7 @@ fn test_primary_xml_writer_file() -> Result<(), MetadataError> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(other_name)
         .unwrap();
 
@@ -204,7 +205,7 @@ fn test_primary_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(primary_xml.read_header()?, 0);
     let mut package = None;
     primary_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that no packages are parsed when there are no packages and the footer element doesn't exist (EOF)
     let mut primary_xml =
@@ -212,7 +213,7 @@ fn test_primary_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(primary_xml.read_header()?, 0);
     let mut package = None;
     primary_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
 
     // Test that a package is parsed correctly when there is packages
     let mut primary_xml =
@@ -220,10 +221,741 @@ fn test_primary_xml_read_package() -> Result<(), MetadataError> {
     assert_eq!(primary_xml.read_header()?, 1);
     let mut package = None;
     primary_xml.read_package(&mut package)?;
-    assert!(matches!(package, Some(_)));
+    assert!(package.is_some());
+    // packages start out in primary.xml, so they can't be read into one which was already read
+    assert!(matches!(
+        primary_xml.read_package(&mut package),
+        Err(MetadataError::InconsistentMetadataError(_))
+    ));
     package.take();
     primary_xml.read_package(&mut package)?;
-    assert!(matches!(package, None));
+    assert!(package.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_primary_xml_read_events() -> Result<(), MetadataError> {
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(COMPLEX_PRIMARY.as_bytes()));
+    primary_xml.read_header()?;
+
+    // only the license and size of each package
+    let mut extracted = Vec::new();
+    let mut fields = Vec::new();
+    while let Some(event) = primary_xml.read_event()? {
+        match event {
+            PackageEvent::Start => extracted.push((String::new(), 0)),
+            PackageEvent::Field(PackageField::License(license)) => {
+                extracted.last_mut().unwrap().0 = license
+            }
+            PackageEvent::Field(PackageField::Size { package, .. }) => {
+                extracted.last_mut().unwrap().1 = package
+            }
+            PackageEvent::Field(field) => fields.push(field),
+            PackageEvent::End => (),
+        }
+    }
+    assert_eq!(extracted, vec![("MPLv2".to_owned(), 8680)]);
+    assert_eq!(fields[0], PackageField::Name("complex-package".to_owned()));
+
+    // the fields are everything a package is built from
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(COMPLEX_PRIMARY.as_bytes()));
+    primary_xml.read_header()?;
+    let mut package = Package::default();
+    while let Some(event) = primary_xml.read_event()? {
+        if let PackageEvent::Field(field) = event {
+            field.apply(&mut package);
+        }
+    }
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(COMPLEX_PRIMARY.as_bytes()));
+    primary_xml.read_header()?;
+    let mut expected = None;
+    primary_xml.read_package(&mut expected)?;
+    assert_eq!(Some(package), expected);
+
+    Ok(())
+}
+
+static OUT_OF_SPEC_PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+  <package type="rpm">
+    <name>out-of-spec</name>
+    <arch>noarch</arch>
+    <version ver="1.0" rel="1"/>
+    <checksum type="sha257" pkgid="YES">abcdef</checksum>
+    <time file="1627052744"/>
+    <size package="-1" installed="117"/>
+    <location href="out-of-spec-1.0-1.noarch.rpm"/>
+    <format>
+      <rpm:provides>
+        <rpm:entry name="out-of-spec" flags="EQ" epoch="0" ver="1.0" rel="1" weird="yes"/>
+        <rpm:entry flags="EQ"/>
+      </rpm:provides>
+    </format>
+  </package>
+</metadata>
+"#;
+
+#[test]
+fn test_primary_xml_read_lenient() -> Result<(), MetadataError> {
+    // Strict parsing fails on the first problem
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(OUT_OF_SPEC_PRIMARY.as_bytes()));
+    assert!(matches!(
+        primary_xml.read_header(),
+        Err(MetadataError::MissingAttributeError("packages"))
+    ));
+
+    // Lenient parsing substitutes defaults and records a warning for each problem
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(OUT_OF_SPEC_PRIMARY.as_bytes()));
+    primary_xml.set_parse_mode(ParseMode::Lenient);
+    assert_eq!(primary_xml.read_header()?, 0);
+    let mut package = None;
+    primary_xml.read_package(&mut package)?;
+    let package = package.unwrap();
+
+    assert_eq!(package.name(), "out-of-spec");
+    assert_eq!(package.evr(), &EVR::new("0", "1.0", "1"));
+    assert_eq!(package.checksum(), &Checksum::Unknown("abcdef".to_owned()));
+    assert_eq!(package.time_file(), 1627052744);
+    assert_eq!(package.time_build(), 0);
+    assert_eq!(package.size_package(), 0);
+    assert_eq!(package.size_installed(), 117);
+    assert_eq!(package.size_archive(), 0);
+    assert_eq!(package.provides().len(), 1);
+    assert_eq!(package.provides()[0].name, "out-of-spec");
+
+    let warnings = primary_xml.take_warnings();
+    assert_eq!(warnings.len(), 8);
+    assert!(warnings.iter().all(|w| w.metadata == "primary"));
+    assert_eq!(warnings[0].entry, None);
+    assert_eq!(warnings[1].entry.as_deref(), Some("out-of-spec"));
+    assert_eq!(warnings[1].message, "Missing metadata attribute: epoch");
+    assert!(primary_xml.take_warnings().is_empty());
+
+    Ok(())
+}
+
+static BROKEN_PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="4">
+  <package type="rpm">
+    <name>first</name>
+    <arch>noarch</arch>
+    <version epoch="0" ver="1.0" rel="1"/>
+  </package>
+  <package type="rpm">
+    <name>bad-size</name>
+    <arch>noarch</arch>
+    <size package="-1" installed="117" archive="124"/>
+    <format>
+      <rpm:provides>
+        <rpm:entry name="bad-size"/>
+      </rpm:provides>
+    </format>
+  </package>
+  <package type="rpm">
+    <name>bad-escape</name>
+    <summary>A &bogus; summary</summary>
+  </package>
+  <package type="rpm">
+    <name>last</name>
+    <arch>noarch</arch>
+  </package>
+</metadata>
+"#;
+
+#[test]
+fn test_primary_xml_read_recover() -> Result<(), MetadataError> {
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(BROKEN_PRIMARY.as_bytes()));
+    primary_xml.set_parse_mode(ParseMode::Recover);
+    assert_eq!(primary_xml.read_header()?, 4);
+
+    // The broken packages are skipped
+    let mut names = Vec::new();
+    loop {
+        let mut package = None;
+        primary_xml.read_package(&mut package)?;
+        match package {
+            Some(package) => names.push(package.name().to_owned()),
+            None => break,
+        }
+    }
+    assert_eq!(names, ["first", "last"]);
+
+    let errors = primary_xml.take_errors();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|e| e.metadata == "primary"));
+    assert_eq!(errors[0].entry.as_deref(), Some("bad-size"));
+    assert!(matches!(
+        errors[0].error,
+        MetadataError::IntFieldParseError(_)
+    ));
+    assert_eq!(errors[1].entry.as_deref(), Some("bad-escape"));
+    assert!(matches!(errors[1].error, MetadataError::XmlParseError(_)));
+    for error in &errors {
+        let entry = &BROKEN_PRIMARY[error.offset..];
+        assert!(entry.starts_with("<package type=\"rpm\">"));
+        assert!(entry.contains(error.entry.as_deref().unwrap()));
+    }
+    assert!(errors[0].offset < errors[1].offset);
+    assert!(primary_xml.take_warnings().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_primary_xml_error_location() -> Result<(), MetadataError> {
+    // fix the size of "bad-size", but leave out the name of its provides entry
+    let broken = BROKEN_PRIMARY
+        .replace(r#"package="-1""#, r#"package="1""#)
+        .replace(r#"<rpm:entry name="bad-size"/>"#, "<rpm:entry/>");
+    let mut primary_xml = PrimaryXml::new_reader(utils::create_xml_reader(broken.as_bytes()));
+    primary_xml.read_header()?;
+    let mut package = None;
+    primary_xml.read_package(&mut package)?;
+    let error = primary_xml.read_package(&mut None).unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        MetadataError::MissingAttributeError("name")
+    ));
+    let location = error.location().unwrap();
+    assert_eq!(location.metadata, "primary");
+    // lines aren't counted while reading, only by re-reading a file after an error
+    assert_eq!(location.line, None);
+    assert_eq!(location.path, "metadata/package/format/rpm:provides");
+    assert_eq!(location.entry.as_deref(), Some("bad-size"));
+    assert!(broken[..location.offset].ends_with("<rpm:entry/>"));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Failed to parse primary.xml (byte {}) in <metadata/package/format/rpm:provides> \
+             of bad-size: Missing metadata attribute: name",
+            location.offset
+        )
+    );
+
+    Ok(())
+}
+
+static VENDOR_PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" xmlns:pulp="https://pulpproject.org/metadata" packages="1">
+  <package type="rpm" pulp:id="1234">
+    <name>vendor</name>
+    <arch>noarch</arch>
+    <version epoch="0" ver="1.0" rel="1"/>
+    <checksum type="sha256" pkgid="YES">ccc7b0e9350a0f75b923bdd0ef4f9af39765c668a3e70bfd3486ea9f0f618aaf</checksum>
+    <summary>A package with vendor-specific metadata</summary>
+    <description>Vendor package</description>
+    <packager></packager>
+    <url></url>
+    <time file="1627052744" build="1627052743"/>
+    <size package="8680" installed="117" archive="932"/>
+    <location href="vendor-1.0-1.noarch.rpm"/>
+    <format>
+      <rpm:license>MIT</rpm:license>
+      <rpm:vendor></rpm:vendor>
+      <rpm:group>Unspecified</rpm:group>
+      <rpm:buildhost>localhost</rpm:buildhost>
+      <rpm:sourcerpm>vendor-1.0-1.src.rpm</rpm:sourcerpm>
+      <rpm:header-range start="4504" end="8413"/>
+      <pulp:signed key="abcd"/>
+    </format>
+    <pulp:labels>
+      <pulp:label name="tier">gold &amp; silver</pulp:label>
+    </pulp:labels>
+  </package>
+</metadata>
+"#;
+
+#[test]
+fn test_primary_xml_preserve_unknown() -> Result<(), MetadataError> {
+    let read_package = |preserve: bool| -> Result<Package, MetadataError> {
+        let mut primary_xml =
+            PrimaryXml::new_reader(utils::create_xml_reader(VENDOR_PRIMARY.as_bytes()));
+        primary_xml.set_preserve_unknown(preserve);
+        primary_xml.read_header()?;
+        let mut package = None;
+        primary_xml.read_package(&mut package)?;
+        Ok(package.unwrap())
+    };
+
+    // Unknown XML is dropped by default
+    assert!(read_package(false)?.unknown_xml().is_none());
+
+    let package = read_package(true)?;
+    let unknown = package.unknown_xml().unwrap();
+    assert_eq!(
+        unknown.primary.attributes,
+        [
+            ("pulp:id".to_owned(), "1234".to_owned()),
+            (
+                "xmlns:pulp".to_owned(),
+                "https://pulpproject.org/metadata".to_owned()
+            )
+        ]
+    );
+    assert_eq!(unknown.format.elements.len(), 1);
+    assert_eq!(unknown.primary.elements.len(), 1);
+
+    // ...and written out again, with the namespace declared where it's used
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header(1)?;
+    writer.write_package(&package)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    let actual = std::str::from_utf8(&buffer)?;
+    assert!(actual
+        .contains(r#"<pulp:signed key="abcd" xmlns:pulp="https://pulpproject.org/metadata"/>"#));
+    assert!(actual.contains(r#"<pulp:label name="tier">gold &amp; silver</pulp:label>"#));
+
+    let mut primary_xml = PrimaryXml::new_reader(utils::create_xml_reader(actual.as_bytes()));
+    primary_xml.set_preserve_unknown(true);
+    primary_xml.read_header()?;
+    let mut reread = None;
+    primary_xml.read_package(&mut reread)?;
+    assert_eq!(reread.as_ref(), Some(&package));
+
+    Ok(())
+}
+
+/// EL5-era createrepo labels SHA-1 checksums `sha`
+static LEGACY_PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="1">
+  <package type="rpm">
+    <name>legacy-package</name>
+    <arch>noarch</arch>
+    <version epoch="0" ver="1.0" rel="1.el5"/>
+    <checksum type="sha" pkgid="YES">6b4cf1e9378b1e8a4f5c0b5e7c0c1b8e3f5d6a7b</checksum>
+    <summary>A package from an old repository</summary>
+    <description>Legacy package</description>
+    <packager></packager>
+    <url></url>
+    <time file="1238787186" build="1238787100"/>
+    <size package="2236" installed="0" archive="124"/>
+    <location href="legacy-package-1.0-1.el5.noarch.rpm"/>
+    <format>
+      <rpm:license>GPL</rpm:license>
+      <rpm:vendor></rpm:vendor>
+      <rpm:group>Applications/System</rpm:group>
+      <rpm:buildhost>localhost</rpm:buildhost>
+      <rpm:sourcerpm>legacy-package-1.0-1.el5.src.rpm</rpm:sourcerpm>
+      <rpm:header-range start="440" end="2084"/>
+      <rpm:provides>
+        <rpm:entry name="legacy-package" flags="EQ" epoch="0" ver="1.0" rel="1.el5"/>
+      </rpm:provides>
+    </format>
+  </package>
+</metadata>
+"#;
+
+#[test]
+fn test_primary_xml_legacy_roundtrip() -> Result<(), MetadataError> {
+    let mut primary_xml =
+        PrimaryXml::new_reader(utils::create_xml_reader(LEGACY_PRIMARY.as_bytes()));
+    assert_eq!(primary_xml.read_header()?, 1);
+    let mut package = None;
+    primary_xml.read_package(&mut package)?;
+    let package = package.unwrap();
+    assert_eq!(
+        package.checksum(),
+        &Checksum::Sha1("6b4cf1e9378b1e8a4f5c0b5e7c0c1b8e3f5d6a7b".to_owned())
+    );
+
+    // the standard style uses the current name
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header(1)?;
+    writer.write_package(&package)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert!(std::str::from_utf8(&buffer)?.contains(r#"<checksum type="sha1" pkgid="YES">"#));
+
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(XmlStyle::Legacy);
+    writer.write_header(1)?;
+    writer.write_package(&package)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, LEGACY_PRIMARY);
+
+    Ok(())
+}
+
+#[test]
+fn test_primary_xml_future_checksum_type() -> Result<(), MetadataError> {
+    let checksum = "2c8f1e6b3a4d5c6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6";
+    let primary = LEGACY_PRIMARY.replace(
+        r#"<checksum type="sha" pkgid="YES">6b4cf1e9378b1e8a4f5c0b5e7c0c1b8e3f5d6a7b</checksum>"#,
+        &format!(
+            r#"<checksum type="sha3-256" pkgid="YES">{}</checksum>"#,
+            checksum
+        ),
+    );
+    let mut primary_xml = PrimaryXml::new_reader(utils::create_xml_reader(primary.as_bytes()));
+    assert_eq!(primary_xml.read_header()?, 1);
+    let mut package = None;
+    primary_xml.read_package(&mut package)?;
+    let package = package.unwrap();
+    assert_eq!(
+        package.checksum(),
+        &Checksum::Other("sha3-256".to_owned(), checksum.to_owned())
+    );
+    assert_eq!(package.checksum().checksum_type(), ChecksumType::Unknown);
+
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header(1)?;
+    writer.write_package(&package)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, primary);
+
+    // an unknown type is only accepted with something which looks like a digest
+    assert!(matches!(
+        Checksum::try_create("sha257", "abcdef"),
+        Err(MetadataError::UnsupportedChecksumTypeError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_primary_xml_read_pre_flags() -> Result<(), MetadataError> {
+    let requires = r#"</rpm:provides>
+      <rpm:requires>
+        <rpm:entry name="a" pre="1"/>
+        <rpm:entry name="b" pre="True"/>
+        <rpm:entry name="c" pre="yes"/>
+        <rpm:entry name="d" pre="0"/>
+        <rpm:entry name="e" pre="false"/>
+        <rpm:entry name="f"/>
+      </rpm:requires>"#;
+    let primary = LEGACY_PRIMARY.replace("</rpm:provides>", requires);
+    let mut primary_xml = PrimaryXml::new_reader(utils::create_xml_reader(primary.as_bytes()));
+    primary_xml.read_header()?;
+    let mut package = None;
+    primary_xml.read_package(&mut package)?;
+    let package = package.unwrap();
+    let pre = package
+        .requires()
+        .iter()
+        .map(|r| (r.name.as_str(), r.preinstall))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pre,
+        vec![
+            ("a", true),
+            ("b", true),
+            ("c", true),
+            ("d", false),
+            ("e", false),
+            ("f", false)
+        ]
+    );
+
+    // they're all written the same way
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header(1)?;
+    writer.write_package(&package)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    let actual = std::str::from_utf8(&buffer)?;
+    assert!(actual.contains(r#"<rpm:entry name="b" pre="1"/>"#));
+    assert!(actual.contains(r#"<rpm:entry name="e"/>"#));
+
+    Ok(())
+}
+
+#[test]
+fn test_package_builder() -> Result<(), MetadataError> {
+    let package = Package::builder()
+        .name("rpm-empty")
+        .arch("x86_64")
+        .evr(EVR::new("0", "0", "0"))
+        .checksum(common::RPM_EMPTY.checksum().clone())
+        .location_href("rpm-empty-0-0.x86_64.rpm")
+        .summary(r##""""##)
+        .time_build(1615686424)
+        .time_file(1625930845)
+        .size_package(6005)
+        .size_archive(124)
+        .rpm_license("LGPL")
+        .rpm_group("Unspecified")
+        .rpm_buildhost("localhost")
+        .rpm_sourcerpm("rpm-empty-0-0.src.rpm")
+        .rpm_header_range(4504, 5961)
+        .provides(common::RPM_EMPTY.provides().to_vec())
+        .build()?;
+    assert_eq!(package, *common::RPM_EMPTY);
+
+    // the epoch defaults to 0
+    let package = PackageBuilder::new()
+        .name("foo")
+        .arch("noarch")
+        .evr(EVR::new("", "1.0", "1"))
+        .checksum(Checksum::Sha256("abcd".to_owned()))
+        .location_href("foo-1.0-1.noarch.rpm")
+        .build()?;
+    assert_eq!(package.evr().epoch(), "0");
+    assert_eq!(package.nevra(), "foo-0:1.0-1.noarch");
+
+    // every missing field is listed
+    let error = PackageBuilder::new()
+        .name("foo")
+        .location_href("")
+        .build()
+        .unwrap_err();
+    assert!(matches!(
+        &error,
+        MetadataError::MissingFieldsError(fields)
+            if fields == &["arch", "evr", "checksum", "location_href"]
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Missing metadata fields: arch, evr, checksum, location_href"
+    );
+
+    let error = PackageBuilder::new()
+        .name("foo")
+        .arch("noarch")
+        .evr(EVR::new("0", "1.0", ""))
+        .checksum(Checksum::Sha256("abcd".to_owned()))
+        .location_href("foo-1.0.noarch.rpm")
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, MetadataError::InvalidEvrError(..)));
+
+    Ok(())
+}
+
+#[test]
+fn test_package_builder_implicit_provides() -> Result<(), MetadataError> {
+    let builder = || {
+        Package::builder()
+            .name("rpm-empty")
+            .arch("x86_64")
+            .evr(EVR::new("", "0", "0"))
+            .checksum(common::RPM_EMPTY.checksum().clone())
+            .location_href("rpm-empty-0-0.x86_64.rpm")
+    };
+    let package = builder().self_provides(true).build()?;
+    assert_eq!(package.provides(), common::RPM_EMPTY.provides());
+    assert_eq!(package.self_provides(), common::RPM_EMPTY.provides());
+    assert!(builder().build()?.provides().is_empty());
+
+    // provides which are already there aren't duplicated, and files outside of bin dirs aren't provided
+    let package = builder()
+        .provides(common::RPM_EMPTY.provides()[..1].to_vec())
+        .files(vec![
+            PackageFile {
+                filetype: FileType::File,
+                path: "/usr/bin/empty".to_owned(),
+            },
+            PackageFile {
+                filetype: FileType::Dir,
+                path: "/usr/sbin".to_owned(),
+            },
+            PackageFile {
+                filetype: FileType::Ghost,
+                path: "/usr/sbin/empty-helper".to_owned(),
+            },
+            PackageFile {
+                filetype: FileType::File,
+                path: "/usr/share/empty/bin.txt".to_owned(),
+            },
+        ])
+        .self_provides(true)
+        .file_provides(true)
+        .build()?;
+    let names: Vec<&str> = package.provides().iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "rpm-empty",
+            "rpm-empty(x86-64)",
+            "/usr/bin/empty",
+            "/usr/sbin/empty-helper"
+        ]
+    );
+
+    // noarch packages don't get an arch-specific provide
+    let mut noarch = package.clone();
+    noarch.set_arch("noarch");
+    assert_eq!(noarch.self_provides().len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_package_linked_fields() {
+    let mut package = common::RPM_EMPTY.clone();
+    package.set_location_href("Packages/r/rpm-empty-0-0.x86_64.rpm");
+    package.set_provides(
+        [
+            package.provides().to_vec(),
+            vec![Requirement {
+                name: "rpm-empty-devel".to_owned(),
+                ..Requirement::default()
+            }],
+        ]
+        .concat(),
+    );
+
+    package
+        .rename("rpm-full")
+        .change_evr(EVR::new("", "1.0", "2"));
+    let provides: Vec<_> = package
+        .provides()
+        .iter()
+        .map(|p| {
+            (
+                p.name.as_str(),
+                p.epoch.as_deref(),
+                p.version.as_deref(),
+                p.release.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        provides,
+        vec![
+            ("rpm-full", Some("0"), Some("1.0"), Some("2")),
+            ("rpm-full(x86-64)", Some("0"), Some("1.0"), Some("2")),
+            ("rpm-empty-devel", None, None, None),
+        ]
+    );
+    assert_eq!(
+        package.location_href(),
+        "Packages/r/rpm-full-1.0-2.x86_64.rpm"
+    );
+
+    // a location which isn't named after the package is left alone until it's recomputed
+    package.set_location_href("Packages/custom.rpm");
+    package.change_evr(EVR::new("1", "1.1", "1"));
+    assert_eq!(package.location_href(), "Packages/custom.rpm");
+    assert_eq!(package.provides()[0].epoch.as_deref(), Some("1"));
+    package.recompute_location_href();
+    assert_eq!(
+        package.location_href(),
+        "Packages/rpm-full-1.1-1.x86_64.rpm"
+    );
+
+    // the plain setters don't touch anything else
+    package.set_name("rpm-other");
+    assert_eq!(package.provides()[0].name, "rpm-full");
+}
+
+#[test]
+fn test_primary_xml_optional_elements() -> Result<(), MetadataError> {
+    let package = Package::builder()
+        .name("horse")
+        .arch("noarch")
+        .evr(EVR::new("0", "4.1", "1"))
+        .checksum(Checksum::Sha256("abcd".repeat(16)))
+        .location_href("horse-4.1-1.noarch.rpm")
+        .rpm_buildhost("smqe-ws15")
+        .build()?;
+    let write = |elements: PrimaryElements| -> Result<String, MetadataError> {
+        let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+        writer.set_elements(elements);
+        writer.write_header(1)?;
+        writer.write_package(&package)?;
+        writer.finish()?;
+        Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+    };
+
+    // like createrepo_c, everything is written by default
+    let primary = write(PrimaryElements::default())?;
+    assert!(primary.contains("<packager></packager>"));
+    assert!(primary.contains("<rpm:buildhost>smqe-ws15</rpm:buildhost>"));
+    assert!(primary.contains(r#"<rpm:header-range start="0" end="0"/>"#));
+
+    let primary = write(
+        PrimaryElements::default()
+            .header_range(ElementPolicy::IfPresent)
+            .buildhost(ElementPolicy::IfPresent)
+            .packager(ElementPolicy::IfPresent),
+    )?;
+    assert!(!primary.contains("<packager>"));
+    assert!(primary.contains("<rpm:buildhost>smqe-ws15</rpm:buildhost>"));
+    assert!(!primary.contains("<rpm:header-range"));
+
+    let primary = write(PrimaryElements::default().buildhost(ElementPolicy::Omit))?;
+    assert!(!primary.contains("<rpm:buildhost>"));
+
+    // the packages can still be read
+    let mut reader = PrimaryXml::new_reader(utils::create_xml_reader(primary.as_bytes()));
+    assert_eq!(reader.read_header()?, 1);
+    let mut read = None;
+    reader.read_package(&mut read)?;
+    assert_eq!(read.unwrap().nevra(), package.nevra());
+
+    Ok(())
+}
+
+#[test]
+fn test_primary_xml_writer_format() -> Result<(), MetadataError> {
+    let working_dir = TempDir::new("")?;
+    let write = |format: XmlFormat| -> Result<String, MetadataError> {
+        let path = working_dir.path().join("primary.xml");
+        let file = std::fs::File::create(&path)?;
+        let mut writer = PrimaryXml::new_writer(utils::create_xml_writer_with_format(file, format));
+        writer.write_header(1)?;
+        writer.write_package(&common::COMPLEX_PACKAGE)?;
+        writer.finish()?;
+        drop(writer);
+        Ok(std::fs::read_to_string(&path)?)
+    };
+
+    // indented with a tab per level, with Windows line endings
+    let expected: String = COMPLEX_PRIMARY
+        .lines()
+        .map(|line| {
+            let content = line.trim_start_matches(' ');
+            let depth = (line.len() - content.len()) / 2;
+            format!("{}{}\r\n", "\t".repeat(depth), content)
+        })
+        .collect();
+    let actual = write(
+        XmlFormat::default()
+            .indent(b'\t', 1)
+            .line_ending(LineEnding::CrLf),
+    )?;
+    assert_eq!(actual, expected);
+
+    let actual = write(XmlFormat::default().attribute_order(AttributeOrder::Alphabetical))?;
+    assert!(actual.contains(
+        r#"<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="1">"#
+    ));
+    assert!(actual.contains(r#"<version epoch="1" rel="5.el8" ver="2.3.4"/>"#));
+    assert!(actual.contains(r#"<rpm:entry epoch="0" flags="LE" name="fur" ver="2"/>"#));
+    assert!(actual.contains(r#"<rpm:entry name="(job or money &gt; 9000)"/>"#));
+
+    // everything on one line, followed by a trailing newline
+    let actual = write(XmlFormat::default().compact(true))?;
+    assert_eq!(actual.lines().count(), 1);
+    assert!(actual.len() < COMPLEX_PRIMARY.len());
+
+    // the packages read back the same as from the default layout
+    let read = |primary: &str| -> Result<Option<Package>, MetadataError> {
+        let mut reader = PrimaryXml::new_reader(utils::create_xml_reader(primary.as_bytes()));
+        assert_eq!(reader.read_header()?, 1);
+        let mut package = None;
+        reader.read_package(&mut package)?;
+        Ok(package)
+    };
+    let expected = read(COMPLEX_PRIMARY)?;
+    for format in [
+        XmlFormat::default().compact(true),
+        XmlFormat::default()
+            .attribute_order(AttributeOrder::Alphabetical)
+            .line_ending(LineEnding::CrLf),
+    ] {
+        assert_eq!(read(&write(format)?)?, expected);
+    }
 
     Ok(())
 }
@@ -0,0 +1,192 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    CacheStatus, ChecksumType, MetadataExpire, MetadataType, RepoChange, RepoMonitor,
+    RepositoryProbe,
+};
+
+/// A `repomd.xml` with a record of each `(type, timestamp, checksum type, checksum)`.
+fn repomd(revision: &str, records: &[(&str, i64, &str, &str)]) -> RepositoryProbe {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<repomd xmlns=\"http://linux.duke.edu/metadata/repo\">\n  <revision>{}</revision>\n",
+        revision
+    );
+    for (metadata_type, timestamp, checksum_type, checksum) in records {
+        let digest = checksum.repeat(if *checksum_type == "sha1" { 40 } else { 64 });
+        xml.push_str(&format!(
+            "  <data type=\"{0}\">\n    <checksum type=\"{2}\">{3}</checksum>\n    <location href=\"repodata/{3}-{0}.xml.gz\"/>\n    <timestamp>{1}</timestamp>\n  </data>\n",
+            metadata_type, timestamp, checksum_type, digest
+        ));
+    }
+    xml.push_str("</repomd>\n");
+    RepositoryProbe::from_bytes(xml.as_bytes()).unwrap()
+}
+
+#[test]
+fn test_repository_probe() {
+    let probe = repomd(
+        "1700000000",
+        &[
+            ("primary", 1700000000, "sha256", "a"),
+            ("updateinfo", 1700000100, "sha256", "b"),
+        ],
+    );
+    assert_eq!(probe.revision.as_deref(), Some("1700000000"));
+    assert_eq!(probe.timestamp, Some(1700000100));
+    let types: Vec<&MetadataType> = probe.metadata_types().collect();
+    assert_eq!(types, [&MetadataType::Primary, &MetadataType::Updateinfo]);
+    assert!(probe.record(&MetadataType::Other).is_none());
+    assert!(!probe.has_changed_since(&probe.clone()));
+}
+
+#[test]
+fn test_repo_monitor() {
+    let mut monitor = RepoMonitor::new();
+    let first = repomd(
+        "1",
+        &[
+            ("primary", 1700000000, "sha256", "a"),
+            ("other", 1700000000, "sha256", "b"),
+        ],
+    );
+    assert!(monitor.update(first.clone()).is_empty());
+    assert!(monitor.update(first.clone()).is_empty());
+    assert_eq!(monitor.last(), Some(&first));
+
+    let updated = repomd(
+        "2",
+        &[
+            ("primary", 1700000500, "sha256", "c"),
+            ("other", 1700000000, "sha256", "b"),
+            ("updateinfo", 1700000500, "sha256", "d"),
+        ],
+    );
+    let changes = monitor.update(updated.clone());
+    assert_eq!(
+        changes,
+        [
+            RepoChange::RevisionChanged {
+                from: Some("1".to_owned()),
+                to: Some("2".to_owned())
+            },
+            RepoChange::RecordUpdated(MetadataType::Primary),
+            RepoChange::RecordAdded(MetadataType::Updateinfo),
+        ]
+    );
+    assert!(!changes.iter().any(RepoChange::is_regression));
+
+    // a mirror serving an older, differently generated copy
+    let stale = repomd(
+        "2",
+        &[
+            ("primary", 1700000000, "sha1", "e"),
+            ("other", 1700000000, "sha256", "b"),
+        ],
+    );
+    let changes = monitor.update(stale.clone());
+    assert_eq!(
+        changes,
+        [
+            RepoChange::RecordUpdated(MetadataType::Primary),
+            RepoChange::TimestampRegressed {
+                metadata_type: MetadataType::Primary,
+                from: 1700000500,
+                to: 1700000000
+            },
+            RepoChange::ChecksumDowngraded {
+                metadata_type: MetadataType::Primary,
+                from: ChecksumType::Sha256,
+                to: ChecksumType::Sha1
+            },
+            RepoChange::RecordRemoved(MetadataType::Updateinfo),
+        ]
+    );
+    assert_eq!(
+        changes
+            .iter()
+            .filter(|change| change.is_regression())
+            .count(),
+        3
+    );
+    // each regression is only reported once
+    assert!(monitor.update(stale).is_empty());
+
+    let mut monitor = RepoMonitor::from_probe(updated.clone());
+    assert!(monitor.update(updated).is_empty());
+}
+
+#[test]
+fn test_metadata_expire() {
+    assert_eq!(MetadataExpire::default(), MetadataExpire::After(172800));
+    for (value, expected) in [
+        ("never", MetadataExpire::Never),
+        ("-1", MetadataExpire::Never),
+        ("0", MetadataExpire::After(0)),
+        ("3600", MetadataExpire::After(3600)),
+        ("45s", MetadataExpire::After(45)),
+        ("90m", MetadataExpire::After(5400)),
+        ("6h", MetadataExpire::After(21600)),
+        ("2d", MetadataExpire::After(172800)),
+    ] {
+        assert_eq!(value.parse::<MetadataExpire>().unwrap(), expected);
+    }
+    for value in ["", "h", "1w", "-2", "1.5h"] {
+        assert!(value.parse::<MetadataExpire>().is_err());
+    }
+
+    let expire = MetadataExpire::After(3600);
+    assert_eq!(expire.expires_in(1000, 1600), Some(3000));
+    assert!(!expire.is_expired(1000, 4599));
+    assert!(expire.is_expired(1000, 4600));
+    // checked according to a clock which was ahead
+    assert!(expire.is_expired(5000, 1000));
+    assert!(MetadataExpire::After(0).is_expired(1000, 1000));
+    assert_eq!(MetadataExpire::Never.expires_in(0, u64::MAX), None);
+    assert!(!MetadataExpire::Never.is_expired_now(std::time::UNIX_EPOCH));
+    assert!(expire.is_expired_now(std::time::UNIX_EPOCH));
+}
+
+#[test]
+fn test_cache_status() {
+    let cached = repomd(
+        "1700000000",
+        &[
+            ("primary", 1700000000, "sha256", "a"),
+            ("updateinfo", 1700000000, "sha256", "b"),
+        ],
+    );
+    assert_eq!(cached.cache_status(&cached.clone()), CacheStatus::Unchanged);
+
+    let updated = repomd(
+        "1700000100",
+        &[
+            ("primary", 1700000000, "sha256", "a"),
+            ("updateinfo", 1700000100, "sha256", "c"),
+            ("modules", 1700000100, "sha256", "d"),
+        ],
+    );
+    assert_eq!(
+        updated.cache_status(&cached),
+        CacheStatus::Updated {
+            changed: vec![MetadataType::Updateinfo, MetadataType::Modules]
+        }
+    );
+    // a mirror serving the metadata the client had before
+    assert_eq!(cached.cache_status(&updated), CacheStatus::Outdated);
+
+    // revisions which aren't timestamps are compared by the timestamps of the records
+    let cached = repomd("el9", &[("primary", 1700000000, "sha256", "a")]);
+    let older = repomd("el9", &[("primary", 1600000000, "sha256", "e")]);
+    assert_eq!(older.cache_status(&cached), CacheStatus::Outdated);
+    assert_eq!(
+        cached.cache_status(&older),
+        CacheStatus::Updated {
+            changed: vec![MetadataType::Primary]
+        }
+    );
+}
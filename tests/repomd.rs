@@ -6,7 +6,9 @@
 
 use std::fs::File;
 
-use rpmrepo_metadata::{utils, MetadataError, RepomdData, RepomdRecord, RepomdXml};
+use rpmrepo_metadata::{
+    utils, MetadataError, MetadataType, ParseOptions, RepomdData, RepomdRecord, RepomdXml, XmlStyle,
+};
 
 #[cfg(test)]
 mod tests {
@@ -38,7 +40,7 @@ mod tests {
                 Some(String::from("cpe:/o:fedoraproject:fedora:33")),
             );
             let mut record = RepomdRecord::default();
-            record.metadata_name = String::from("primary");
+            record.metadata_type = MetadataType::Primary;
             record.checksum = Checksum::Sha256(String::from(
                 "e6104a05bf3101c01321a5af9098d569ff974a8e6a8f72c5982bf074efbaf036",
             ));
@@ -51,7 +53,7 @@ mod tests {
             record.location_href = PathBuf::from("repodata/primary.xml.gz");
             repomd.add_record(record);
             let mut record = RepomdRecord::default();
-            record.metadata_name = String::from("filelists");
+            record.metadata_type = MetadataType::Filelists;
             record.checksum = Checksum::Sha256(String::from(
                 "128398aea7338ada2735e3d9340c16e5915040133b77bd8f4498d22ace6e5a0e",
             ));
@@ -64,7 +66,7 @@ mod tests {
             record.location_href = PathBuf::from("repodata/filelists.xml.gz");
             repomd.add_record(record);
             let mut record = RepomdRecord::default();
-            record.metadata_name = String::from("other");
+            record.metadata_type = MetadataType::Other;
             record.checksum = Checksum::Sha256(String::from(
                 "9b34aaa221ed94e916f385c0b891c0114c394948140d736bd10ec5127c2ea4e5",
             ));
@@ -162,4 +164,287 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_repomd_preserve_unknown() -> Result<(), MetadataError> {
+        let repomd_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm" xmlns:pulp="https://pulpproject.org/metadata">
+  <revision>1615686706</revision>
+  <data type="primary" pulp:origin="upstream">
+    <checksum type="sha256">afdc6dc379e58d097ed0b350536812bc6a604bbce50c5c109d8d98e28301dc4b</checksum>
+    <location href="repodata/primary.xml.gz"/>
+    <timestamp>1614969700</timestamp>
+    <pulp:signature key="abcd">signed</pulp:signature>
+  </data>
+</repomd>
+"#;
+        let repomd = RepomdXml::read_data(utils::create_xml_reader(repomd_xml.as_bytes()))?;
+        assert!(repomd.get_record("primary").unwrap().unknown_xml.is_empty());
+
+        let options = ParseOptions::default().preserve_unknown(true);
+        let repomd = RepomdXml::read_data_with_options(
+            utils::create_xml_reader(repomd_xml.as_bytes()),
+            options,
+        )?;
+        let record = repomd.get_record("primary").unwrap();
+        assert_eq!(
+            record.unknown_xml.attributes[0],
+            ("pulp:origin".to_owned(), "upstream".to_owned())
+        );
+        assert_eq!(record.unknown_xml.elements.len(), 1);
+
+        let mut buffer = Vec::new();
+        RepomdXml::write_data(&repomd, &mut utils::create_xml_writer(&mut buffer))?;
+        let actual = std::str::from_utf8(&buffer)?;
+        assert!(actual.contains(
+            r#"<pulp:signature key="abcd" xmlns:pulp="https://pulpproject.org/metadata">signed</pulp:signature>"#
+        ));
+        let reread = RepomdXml::read_data_with_options(
+            utils::create_xml_reader(actual.as_bytes()),
+            options,
+        )?;
+        assert_eq!(reread.get_record("primary"), Some(record));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repomd_extensions() -> Result<(), MetadataError> {
+        let pulp = "https://pulpproject.org/metadata";
+        let repomd_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm" xmlns:p="https://pulpproject.org/metadata">
+  <revision>1615686706</revision>
+  <data type="primary" p:origin="upstream">
+    <checksum type="sha256">afdc6dc379e58d097ed0b350536812bc6a604bbce50c5c109d8d98e28301dc4b</checksum>
+    <location href="repodata/primary.xml.gz"/>
+    <timestamp>1614969700</timestamp>
+    <p:signature key="abcd">signed</p:signature>
+    <p:signature key="efgh">signed again</p:signature>
+  </data>
+</repomd>
+"#;
+        let options = ParseOptions::default().preserve_unknown(true);
+        let mut repomd = RepomdXml::read_data_with_options(
+            utils::create_xml_reader(repomd_xml.as_bytes()),
+            options,
+        )?;
+        let record = repomd.get_record("primary").unwrap();
+        // found by namespace, whichever prefix it was declared with
+        assert_eq!(record.extension_attribute(pulp, "origin"), Some("upstream"));
+        assert_eq!(record.unknown_xml.attribute("p:origin"), Some("upstream"));
+        assert_eq!(record.extension_attribute(pulp, "missing"), None);
+        assert_eq!(
+            record.extension_attribute("https://example.com", "origin"),
+            None
+        );
+        assert_eq!(record.extension_elements(pulp, "signature").count(), 2);
+        assert_eq!(record.extension_elements(pulp, "other").count(), 0);
+
+        // added attributes reuse the declared prefix, and survive a round-trip
+        let record = &mut repomd.records_mut()[0];
+        record.set_extension_attribute("pulp", pulp, "origin", "mirror");
+        record.set_extension_attribute("pulp", pulp, "synced", "1615686706");
+        let mut other = record.clone();
+        other.metadata_type = MetadataType::Other;
+        other.unknown_xml = Default::default();
+        other.set_extension_attribute("pulp", pulp, "origin", "local");
+        repomd.add_record(other);
+
+        let mut buffer = Vec::new();
+        RepomdXml::write_data(&repomd, &mut utils::create_xml_writer(&mut buffer))?;
+        let reread =
+            RepomdXml::read_data_with_options(utils::create_xml_reader(&*buffer), options)?;
+        let record = reread.get_record("primary").unwrap();
+        assert_eq!(record.extension_attribute(pulp, "origin"), Some("mirror"));
+        assert_eq!(
+            record.extension_attribute(pulp, "synced"),
+            Some("1615686706")
+        );
+        assert_eq!(record.extension_elements(pulp, "signature").count(), 2);
+        let other = reread.get_record("other").unwrap();
+        assert_eq!(other.extension_attribute(pulp, "origin"), Some("local"));
+        assert_eq!(reread.get_record("primary"), repomd.get_record("primary"));
+
+        Ok(())
+    }
+
+    /// Repositories created by createrepo on EL5 have no revision, label SHA-1 checksums `sha`, and leave
+    /// out the sizes and sometimes the open checksum
+    #[test]
+    fn test_repomd_legacy_roundtrip() -> Result<(), MetadataError> {
+        let repomd_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+  <data type="other">
+    <checksum type="sha">b7a4f6e3d7b9c2e8a1f0d3c5b6a7e8f9d0c1b2a3</checksum>
+    <open-checksum type="sha">0d1b37f1ab3b8e2a6f8d9c0e1f2a3b4c5d6e7f80</open-checksum>
+    <location href="repodata/other.xml.gz"/>
+    <timestamp>1238787186</timestamp>
+  </data>
+  <data type="primary">
+    <checksum type="sha">3f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f60</checksum>
+    <location href="repodata/primary.xml.gz"/>
+    <timestamp>1238787186</timestamp>
+  </data>
+</repomd>
+"#;
+        let repomd = RepomdXml::read_data(utils::create_xml_reader(repomd_xml.as_bytes()))?;
+        assert_eq!(repomd.revision(), None);
+        let record = repomd.get_record("primary").unwrap();
+        assert_eq!(
+            record.checksum,
+            Checksum::Sha1(String::from("3f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f60"))
+        );
+        assert_eq!(record.open_checksum, None);
+        assert_eq!(record.size, None);
+        assert_eq!(record.open_size, None);
+
+        let mut buffer = Vec::new();
+        RepomdXml::write_data_with_style(
+            &repomd,
+            &mut utils::create_xml_writer(&mut buffer),
+            XmlStyle::Legacy,
+        )?;
+        assert_eq!(std::str::from_utf8(&buffer)?, repomd_xml);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repomd_record_from_file() -> Result<(), MetadataError> {
+        use rpmrepo_metadata::ChecksumType;
+        use std::io::Write;
+
+        let contents = "<metadata packages=\"0\">\n</metadata>\n".repeat(100);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(contents.as_bytes())?;
+        let compressed = encoder.finish()?;
+
+        let tmp_dir = tempdir::TempDir::new("test_repomd_record_from_file")?;
+        std::fs::create_dir(tmp_dir.path().join("repodata"))?;
+        let path = tmp_dir.path().join("repodata/primary.xml.gz");
+        std::fs::write(&path, &compressed)?;
+
+        let record = RepomdRecord::from_file("primary", &path, ChecksumType::Sha256)?;
+        assert_eq!(record.metadata_type, MetadataType::Primary);
+        assert_eq!(record.location_href, Path::new("repodata/primary.xml.gz"));
+        assert!(record.timestamp > 0);
+        assert_eq!(record.size, Some(compressed.len() as u64));
+        assert_eq!(
+            record.checksum,
+            utils::checksum_bytes(&compressed, ChecksumType::Sha256)?
+        );
+        assert_eq!(record.open_size, Some(contents.len() as u64));
+        assert_eq!(
+            record.open_checksum,
+            Some(utils::checksum_bytes(
+                contents.as_bytes(),
+                ChecksumType::Sha256
+            )?)
+        );
+        assert_eq!(record.header_size, None);
+        assert_eq!(record.header_checksum, None);
+
+        // the same file, before it's written anywhere
+        let from_reader = RepomdRecord::from_reader(
+            "primary",
+            Path::new("repodata/primary.xml.gz"),
+            compressed.as_slice(),
+            ChecksumType::Sha256,
+        )?;
+        assert_eq!(from_reader.location_href, record.location_href);
+        assert_eq!(from_reader.size, record.size);
+        assert_eq!(from_reader.checksum, record.checksum);
+        assert_eq!(from_reader.open_size, record.open_size);
+        assert_eq!(from_reader.open_checksum, record.open_checksum);
+
+        // an uncompressed file has no open size or checksum
+        let path = tmp_dir.path().join("repodata/comps.xml");
+        std::fs::write(&path, &contents)?;
+        let record = RepomdRecord::from_file("group", &path, ChecksumType::Sha1)?;
+        assert_eq!(record.size, Some(contents.len() as u64));
+        assert_eq!(
+            record.checksum,
+            utils::checksum_bytes(contents.as_bytes(), ChecksumType::Sha1)?
+        );
+        assert_eq!(record.open_size, None);
+        assert_eq!(record.open_checksum, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_type() -> Result<(), MetadataError> {
+        use rpmrepo_metadata::CompressionType;
+
+        for name in [
+            "primary",
+            "filelists_db",
+            "other_zck",
+            "group_gz",
+            "modules",
+        ] {
+            let metadata_type = MetadataType::from(name);
+            assert!(!matches!(metadata_type, MetadataType::Custom(_)));
+            assert_eq!(metadata_type.as_str(), name);
+        }
+        assert_eq!(
+            MetadataType::from("prestodelta"),
+            MetadataType::Custom("prestodelta".to_owned())
+        );
+
+        assert!(MetadataType::FilelistsDb.is_database());
+        assert!(!MetadataType::FilelistsDb.is_zchunk());
+        assert!(MetadataType::UpdateinfoZck.is_zchunk());
+        assert_eq!(MetadataType::OtherZck.base(), MetadataType::Other);
+        assert_eq!(MetadataType::Modules.base(), MetadataType::Modules);
+
+        let file_names: Vec<_> = [
+            MetadataType::Primary,
+            MetadataType::PrimaryDb,
+            MetadataType::PrimaryZck,
+            MetadataType::Group,
+            MetadataType::GroupGz,
+            MetadataType::Modules,
+            MetadataType::Custom("prestodelta".to_owned()),
+        ]
+        .iter()
+        .map(|t| t.file_name(CompressionType::Xz))
+        .collect();
+        assert_eq!(
+            file_names,
+            vec![
+                "primary.xml.xz",
+                "primary.sqlite.xz",
+                "primary.xml.zck",
+                "comps.xml",
+                "comps.xml.gz",
+                "modules.yaml.xz",
+                "prestodelta.xml.xz",
+            ]
+        );
+        assert_eq!(
+            MetadataType::Other.compression_suffix(CompressionType::None),
+            ""
+        );
+
+        // records of any type are read and written back as they were
+        let repomd_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+  <revision>1</revision>
+  <data type="prestodelta">
+    <checksum type="sha256">e6104a05bf3101c01321a5af9098d569ff974a8e6a8f72c5982bf074efbaf036</checksum>
+    <location href="repodata/prestodelta.xml.xz"/>
+    <timestamp>1639195237</timestamp>
+  </data>
+</repomd>
+"#;
+        let repomd = RepomdXml::read_data(utils::create_xml_reader(repomd_xml.as_bytes()))?;
+        let record = repomd.get_record("prestodelta").unwrap();
+        assert_eq!(record.metadata_type, "prestodelta");
+        let mut buffer = Vec::new();
+        RepomdXml::write_data(&repomd, &mut utils::create_xml_writer(&mut buffer))?;
+        assert_eq!(std::str::from_utf8(&buffer)?, repomd_xml);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,133 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{Package, RepoSet, UpdateRecord, DEFAULT_PRIORITY};
+
+mod common;
+
+use common::requirement;
+
+fn nevras<'a>(packages: impl IntoIterator<Item = (&'a str, &'a Package)>) -> Vec<String> {
+    packages
+        .into_iter()
+        .map(|(id, package)| format!("{}: {}", id, package.nevra()))
+        .collect()
+}
+
+fn repo_set() -> RepoSet {
+    let mut fedora = common::repository([
+        common::package_with_deps("bash", "5.2.15", "x86_64", &[], &[]),
+        common::package_with_deps("python3", "3.11.2", "x86_64", &[], &[]),
+    ]);
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2023-0001")
+        .from("updates@fedoraproject.org")
+        .title("bash bug fix update")
+        .update_type("bugfix")
+        .issued_date("2023-04-18 00:00:00")
+        .build()
+        .unwrap();
+    fedora
+        .advisories_mut()
+        .insert(advisory.id.clone(), advisory);
+    let updates = common::repository([
+        common::package_with_deps("bash", "5.2.21", "x86_64", &[], &[]),
+        common::package_with_deps("bash", "5.2.21", "i686", &[], &[]),
+    ]);
+    // an older python3 which is preferred because of the priority of its repository
+    let pinned = common::repository([common::package_with_deps(
+        "python3",
+        "3.11.1",
+        "x86_64",
+        &[],
+        &[],
+    )]);
+
+    let mut repo_set = RepoSet::new();
+    repo_set
+        .add("fedora", DEFAULT_PRIORITY, fedora)
+        .add("updates", DEFAULT_PRIORITY, updates)
+        .add("pinned", 10, pinned);
+    repo_set
+}
+
+#[test]
+fn test_reposet_packages() {
+    let repo_set = repo_set();
+    assert_eq!(
+        repo_set
+            .repositories()
+            .map(|(id, priority, _)| (id, priority))
+            .collect::<Vec<_>>(),
+        [("fedora", 99), ("updates", 99), ("pinned", 10)]
+    );
+    assert_eq!(
+        nevras(repo_set.packages()),
+        [
+            "fedora: bash-0:5.2.15-1.x86_64",
+            "updates: bash-0:5.2.21-1.x86_64",
+            "updates: bash-0:5.2.21-1.i686",
+            "pinned: python3-0:3.11.1-1.x86_64",
+        ]
+    );
+
+    // the newest wins among packages of the same priority
+    assert_eq!(
+        nevras(repo_set.latest("bash")),
+        [
+            "updates: bash-0:5.2.21-1.x86_64",
+            "updates: bash-0:5.2.21-1.i686"
+        ]
+    );
+    assert_eq!(
+        nevras(repo_set.latest("python3")),
+        ["pinned: python3-0:3.11.1-1.x86_64"]
+    );
+    assert!(repo_set.latest("zsh").is_empty());
+}
+
+#[test]
+fn test_reposet_queries() {
+    let mut repo_set = repo_set();
+    assert_eq!(
+        nevras(repo_set.whatprovides(&requirement("bash(x86-64)"))),
+        [
+            "fedora: bash-0:5.2.15-1.x86_64",
+            "updates: bash-0:5.2.21-1.x86_64"
+        ]
+    );
+    assert_eq!(
+        nevras(repo_set.best_provider(&requirement("bash(x86-64)"))),
+        ["updates: bash-0:5.2.21-1.x86_64"]
+    );
+    assert_eq!(
+        nevras(repo_set.best_provider(&requirement("python3"))),
+        ["pinned: python3-0:3.11.1-1.x86_64"]
+    );
+    assert_eq!(
+        repo_set.advisory("FEDORA-2023-0001").map(|(id, _)| id),
+        Some("fedora")
+    );
+
+    // dependencies can be satisfied by packages of another repository
+    let tool = common::package_with_deps("tool", "1.0", "noarch", &[], &["bash", "perl"]);
+    repo_set.add("tools", DEFAULT_PRIORITY, common::repository([tool]));
+    let unresolved: Vec<(&str, &str, &str)> = repo_set
+        .repoclosure()
+        .into_iter()
+        .map(|(id, package, require)| (id, package.name(), require.name.as_str()))
+        .collect();
+    assert_eq!(unresolved, [("tools", "tool", "perl")]);
+
+    // without the pinned repository, the newer python3 becomes visible
+    assert!(repo_set.remove("pinned").is_some());
+    assert!(repo_set.get("pinned").is_none());
+    assert_eq!(
+        nevras(repo_set.latest("python3")),
+        ["fedora: python3-0:3.11.2-1.x86_64"]
+    );
+}
@@ -5,9 +5,17 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use pretty_assertions::assert_eq;
+use rpmrepo_metadata::capability::Providers;
 use rpmrepo_metadata::{
-    MetadataError, Package, Repository, RepositoryOptions, RepositoryReader, RepositoryWriter,
+    recompress_repository, transcode_metadata_file, utils, verify_files, ChangelogStorage,
+    Checksum, ChecksumType, CompressionType, DecompressionError, DuplicateAdvisoryPolicy,
+    FallbackEncoding, FileCheck, FileStatus, FileStorage, FileType, InvalidCharPolicy, LoadOptions,
+    MetadataError, MetadataFormat, MetadataSelection, MetadataType, Package, ParseMode,
+    ParseOptions, PrimaryXml, RecompressOptions, RecordOrder, Repository, RepositoryDelta,
+    RepositoryHooks, RepositoryOptions, RepositoryReader, RepositoryWriter, Requirement,
+    UpdateRecord, UpdateinfoSplit, VerifyOptions,
 };
+use std::io::{Read, Write};
 use tempdir::TempDir;
 mod common;
 
@@ -88,8 +96,8 @@ fn test_read_write_uncompressed() -> Result<(), MetadataError> {
     let options = RepositoryOptions::default()
         .metadata_checksum_type(rpmrepo_metadata::ChecksumType::Sha1)
         .metadata_compression_type(rpmrepo_metadata::CompressionType::None);
-    let mut repo_writer = RepositoryWriter::new_with_options(&tmp_dir.path(), 1, options)?;
-    repo_writer.add_package(&*common::COMPLEX_PACKAGE)?;
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
     repo_writer.finish()?;
 
     assert!(
@@ -109,7 +117,35 @@ fn test_read_write_uncompressed() -> Result<(), MetadataError> {
         "other.xml is missing"
     );
 
-    let repo = Repository::load_from_directory(&tmp_dir.path())?;
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
+    let mut packages_iter = repo.packages().iter().map(|(_, p)| p);
+
+    assert_eq!(packages_iter.next(), Some(&*common::COMPLEX_PACKAGE));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_write_formatted() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_writer")?;
+    let test_repodata_dir = tmp_dir.path().join("repodata");
+
+    let format = rpmrepo_metadata::XmlFormat::default()
+        .compact(true)
+        .line_ending(rpmrepo_metadata::LineEnding::CrLf);
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::None)
+        .xml_format(format);
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.finish()?;
+
+    for file in ["primary.xml", "filelists.xml", "other.xml", "repomd.xml"] {
+        let contents = std::fs::read_to_string(test_repodata_dir.join(file))?;
+        assert_eq!(contents.matches("\r\n").count(), 1, "{}", file);
+    }
+
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
     let mut packages_iter = repo.packages().iter().map(|(_, p)| p);
 
     assert_eq!(packages_iter.next(), Some(&*common::COMPLEX_PACKAGE));
@@ -125,8 +161,8 @@ fn test_read_write_zstd_compressed() -> Result<(), MetadataError> {
     let options = RepositoryOptions::default()
         .metadata_checksum_type(rpmrepo_metadata::ChecksumType::Sha1)
         .metadata_compression_type(rpmrepo_metadata::CompressionType::Zstd);
-    let mut repo_writer = RepositoryWriter::new_with_options(&tmp_dir.path(), 1, options)?;
-    repo_writer.add_package(&*common::COMPLEX_PACKAGE)?;
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
     repo_writer.finish()?;
 
     assert!(
@@ -146,7 +182,7 @@ fn test_read_write_zstd_compressed() -> Result<(), MetadataError> {
         "other.xml.zst is missing"
     );
 
-    let repo = Repository::load_from_directory(&tmp_dir.path())?;
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
     let mut packages_iter = repo.packages().iter().map(|(_, p)| p);
 
     assert_eq!(packages_iter.next(), Some(&*common::COMPLEX_PACKAGE));
@@ -162,8 +198,8 @@ fn test_read_write_xz_compressed() -> Result<(), MetadataError> {
     let options = RepositoryOptions::default()
         .metadata_checksum_type(rpmrepo_metadata::ChecksumType::Sha1)
         .metadata_compression_type(rpmrepo_metadata::CompressionType::Xz);
-    let mut repo_writer = RepositoryWriter::new_with_options(&tmp_dir.path(), 1, options)?;
-    repo_writer.add_package(&*common::COMPLEX_PACKAGE)?;
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
     repo_writer.finish()?;
 
     assert!(
@@ -183,7 +219,7 @@ fn test_read_write_xz_compressed() -> Result<(), MetadataError> {
         "other.xml.xz is missing"
     );
 
-    let repo = Repository::load_from_directory(&tmp_dir.path())?;
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
     let mut packages_iter = repo.packages().iter().map(|(_, p)| p);
 
     assert_eq!(packages_iter.next(), Some(&*common::COMPLEX_PACKAGE));
@@ -199,8 +235,8 @@ fn test_read_write_bz2_compressed() -> Result<(), MetadataError> {
     let options = RepositoryOptions::default()
         .metadata_checksum_type(rpmrepo_metadata::ChecksumType::Sha1)
         .metadata_compression_type(rpmrepo_metadata::CompressionType::Bz2);
-    let mut repo_writer = RepositoryWriter::new_with_options(&tmp_dir.path(), 1, options)?;
-    repo_writer.add_package(&*common::COMPLEX_PACKAGE)?;
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
     repo_writer.finish()?;
 
     assert!(
@@ -220,7 +256,7 @@ fn test_read_write_bz2_compressed() -> Result<(), MetadataError> {
         "other.xml.bz2 is missing"
     );
 
-    let repo = Repository::load_from_directory(&tmp_dir.path())?;
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
     let mut packages_iter = repo.packages().iter().map(|(_, p)| p);
 
     assert_eq!(packages_iter.next(), Some(&*common::COMPLEX_PACKAGE));
@@ -234,7 +270,7 @@ fn test_read_write_bz2_compressed() -> Result<(), MetadataError> {
 fn test_repository_writer_not_enough_packages() {
     let tmp_dir = TempDir::new("test_repository_writer").unwrap();
 
-    let mut repo_writer = RepositoryWriter::new(&tmp_dir.path(), 1).unwrap();
+    let repo_writer = RepositoryWriter::new(tmp_dir.path(), 1).unwrap();
     repo_writer.finish().unwrap();
 }
 
@@ -243,7 +279,1778 @@ fn test_repository_writer_not_enough_packages() {
 fn test_repository_writer_too_many_packages() {
     let tmp_dir = TempDir::new("test_repository_writer").unwrap();
 
-    let mut repo_writer = RepositoryWriter::new(&tmp_dir.path(), 0).unwrap();
-    repo_writer.add_package(&*common::COMPLEX_PACKAGE).unwrap();
+    let mut repo_writer = RepositoryWriter::new(tmp_dir.path(), 0).unwrap();
+    repo_writer.add_package(&common::COMPLEX_PACKAGE).unwrap();
     repo_writer.finish().unwrap();
 }
+
+#[test]
+fn test_read_lenient_reordered_metadata() -> Result<(), MetadataError> {
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::None);
+    let write_repo = |packages: &[&Package]| -> Result<TempDir, MetadataError> {
+        let tmp_dir = TempDir::new("test_repository_lenient")?;
+        let mut repo_writer =
+            RepositoryWriter::new_with_options(tmp_dir.path(), packages.len(), options)?;
+        for package in packages {
+            repo_writer.add_package(package)?;
+        }
+        repo_writer.finish()?;
+        Ok(tmp_dir)
+    };
+    let repo_dir = write_repo(&[&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY])?;
+    let reversed_dir = write_repo(&[&*common::RPM_EMPTY, &*common::COMPLEX_PACKAGE])?;
+
+    // filelists.xml lists the packages in the opposite order to primary.xml
+    let filelists_href = |path: &std::path::Path| -> Result<_, MetadataError> {
+        let reader = RepositoryReader::new_from_directory(path)?;
+        Ok(path.join(
+            &reader
+                .repomd()
+                .get_record("filelists")
+                .unwrap()
+                .location_href,
+        ))
+    };
+    std::fs::copy(
+        filelists_href(reversed_dir.path())?,
+        filelists_href(repo_dir.path())?,
+    )?;
+
+    let error = Repository::load_from_directory(repo_dir.path()).unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        MetadataError::InconsistentMetadataError(_)
+    ));
+    let location = error.location().unwrap();
+    assert_eq!(location.metadata, "filelists");
+    assert_eq!(location.path, "filelists/package");
+    assert_eq!(location.line, Some(3));
+    assert_eq!(location.entry, Some(common::COMPLEX_PACKAGE.nevra_short()));
+
+    let (repo, report) = Repository::load_from_directory_with_options(
+        repo_dir.path(),
+        LoadOptions::default().parse_options(ParseOptions::default().mode(ParseMode::Lenient)),
+    )?;
+    let warnings = report.warnings;
+    assert_eq!(repo.packages().len(), 2);
+    assert_eq!(
+        repo.packages().get(common::COMPLEX_PACKAGE.pkgid()),
+        Some(&*common::COMPLEX_PACKAGE)
+    );
+    assert_eq!(
+        repo.packages().get(common::RPM_EMPTY.pkgid()),
+        Some(&*common::RPM_EMPTY)
+    );
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].metadata, "filelists");
+    assert_eq!(
+        warnings[0].message,
+        "packages are listed in a different order than in primary.xml"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_read_recover_broken_metadata() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_recover")?;
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::None);
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 2, options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.add_package(&common::RPM_EMPTY)?;
+    repo_writer.finish()?;
+
+    // break the filelists.xml entry of the first package
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let filelists_path = tmp_dir.path().join(
+        &reader
+            .repomd()
+            .get_record("filelists")
+            .unwrap()
+            .location_href,
+    );
+    let filelists = std::fs::read_to_string(&filelists_path)?;
+    std::fs::write(&filelists_path, filelists.replacen("epoch=", "epohc=", 1))?;
+
+    let (repo, report) = Repository::load_from_directory_with_options(
+        tmp_dir.path(),
+        LoadOptions::default().parse_options(ParseOptions::default().mode(ParseMode::Recover)),
+    )?;
+    assert_eq!(repo.packages().len(), 1);
+    assert_eq!(
+        repo.packages().get(common::RPM_EMPTY.pkgid()),
+        Some(&*common::RPM_EMPTY)
+    );
+    assert!(report.warnings.is_empty());
+
+    let errors = report.errors;
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].metadata, "filelists");
+    assert!(matches!(
+        errors[0].error,
+        MetadataError::MissingAttributeError("epoch")
+    ));
+    assert_eq!(errors[1].metadata, "primary");
+    assert_eq!(
+        errors[1].entry.as_deref(),
+        Some(common::COMPLEX_PACKAGE.name())
+    );
+    assert!(matches!(
+        errors[1].error,
+        MetadataError::InconsistentMetadataError(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_write_preserve_unknown() -> Result<(), MetadataError> {
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    let unknown = package.unknown_xml_mut();
+    unknown
+        .primary
+        .elements
+        .push(r#"<vendor:label xmlns:vendor="http://acme.com">gold</vendor:label>"#.to_owned());
+    unknown
+        .filelists
+        .attributes
+        .push(("vendor".to_owned(), "acme".to_owned()));
+    unknown
+        .other
+        .elements
+        .push(r#"<changelog-url href="http://acme.com/changes"/>"#.to_owned());
+
+    let tmp_dir = TempDir::new("test_repository_preserve_unknown")?;
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    repo.write_to_directory(tmp_dir.path())?;
+
+    // Unknown XML is dropped by default...
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
+    assert_eq!(
+        repo.packages().get(package.pkgid()),
+        Some(&*common::COMPLEX_PACKAGE)
+    );
+
+    // ...but can be kept
+    let options = ParseOptions::default().preserve_unknown(true);
+    let (repo, report) = Repository::load_from_directory_with_options(
+        tmp_dir.path(),
+        LoadOptions::default().parse_options(options),
+    )?;
+    assert!(report.is_empty());
+    assert_eq!(repo.packages().get(package.pkgid()), Some(&package));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_write_invalid_chars() -> Result<(), MetadataError> {
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_description("Complex\u{1}package\u{FFFF}");
+
+    let write = |policy| -> Result<TempDir, MetadataError> {
+        let tmp_dir = TempDir::new("test_repository_invalid_chars")?;
+        let options = RepositoryOptions::default()
+            .metadata_compression_type(rpmrepo_metadata::CompressionType::None)
+            .invalid_chars(policy);
+        let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+        repo_writer.add_package(&package)?;
+        repo_writer.finish()?;
+        Ok(tmp_dir)
+    };
+    let description = |path: &std::path::Path, options| -> Result<String, MetadataError> {
+        let (repo, _) = Repository::load_from_directory_with_options(
+            path,
+            LoadOptions::default().parse_options(options),
+        )?;
+        let package = repo.packages().get(package.pkgid()).unwrap();
+        Ok(package.description().to_owned())
+    };
+
+    // Characters which aren't allowed in XML are replaced or left out when writing...
+    let tmp_dir = write(InvalidCharPolicy::Replace)?;
+    assert_eq!(
+        description(tmp_dir.path(), ParseOptions::default())?,
+        "Complex\u{FFFD}package\u{FFFD}"
+    );
+    let tmp_dir = write(InvalidCharPolicy::Strip)?;
+    assert_eq!(
+        description(tmp_dir.path(), ParseOptions::default())?,
+        "Complexpackage"
+    );
+    // ...which is only done if asked, by default they're an error
+    let error = write(InvalidCharPolicy::default()).err().unwrap();
+    assert!(error.to_string().contains("[01] at offset"), "{}", error);
+
+    // The same goes for reading, along with bytes which aren't valid UTF-8
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let primary_path = tmp_dir
+        .path()
+        .join(&reader.repomd().get_record("primary").unwrap().location_href);
+    let primary = std::fs::read(&primary_path)?;
+    let garbage = String::from_utf8_lossy(&primary)
+        .replacen("Complexpackage", "Complex\u{2}pack\u{1b}age", 1)
+        .into_bytes();
+    let pos = garbage.windows(5).position(|w| w == b"pack\x1b").unwrap();
+    let garbage = [&garbage[..pos], b"\xff\xfe", &garbage[pos..]].concat();
+    std::fs::write(&primary_path, garbage)?;
+
+    assert!(description(tmp_dir.path(), ParseOptions::default()).is_err());
+    let options = ParseOptions::default().invalid_chars(InvalidCharPolicy::Replace);
+    assert_eq!(
+        description(tmp_dir.path(), options)?,
+        "Complex\u{FFFD}\u{FFFD}\u{FFFD}pack\u{FFFD}age"
+    );
+    let options = ParseOptions::default().invalid_chars(InvalidCharPolicy::Strip);
+    assert_eq!(description(tmp_dir.path(), options)?, "Complexpackage");
+
+    Ok(())
+}
+
+#[test]
+fn test_read_fallback_encoding() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_fallback_encoding")?;
+    let write_options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::None);
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_packager("Jose Bluth");
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, write_options)?;
+    repo_writer.add_package(&package)?;
+    repo_writer.finish()?;
+
+    // an old repository with a Latin-1 packager
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let primary_path = tmp_dir
+        .path()
+        .join(&reader.repomd().get_record("primary").unwrap().location_href);
+    let primary = std::fs::read_to_string(&primary_path)?;
+    let primary = primary
+        .replacen("Jose Bluth", "Jos\u{1}Bluth \u{2}", 1)
+        .into_bytes();
+    let primary = primary
+        .iter()
+        .map(|b| match b {
+            1 => 0xe9,
+            2 => 0x80,
+            _ => *b,
+        })
+        .collect::<Vec<_>>();
+    std::fs::write(&primary_path, primary)?;
+
+    let packager = |options| -> Result<String, MetadataError> {
+        let (repo, _) = Repository::load_from_directory_with_options(
+            tmp_dir.path(),
+            LoadOptions::default().parse_options(options),
+        )?;
+        Ok(repo.packages()[package.pkgid()].packager().to_owned())
+    };
+    assert!(packager(ParseOptions::default()).is_err());
+    let options = ParseOptions::default().invalid_chars(InvalidCharPolicy::Replace);
+    assert_eq!(packager(options)?, "Jos\u{FFFD}Bluth \u{FFFD}");
+    let options = ParseOptions::default().fallback_encoding(Some(FallbackEncoding::Latin1));
+    assert_eq!(packager(options)?, "Jos\u{e9}Bluth \u{80}");
+    let options = ParseOptions::default().fallback_encoding(Some(FallbackEncoding::Windows1252));
+    assert_eq!(packager(options)?, "Jos\u{e9}Bluth \u{20ac}");
+
+    // writing the repository out again leaves it valid UTF-8
+    let (repo, _) = Repository::load_from_directory_with_options(
+        tmp_dir.path(),
+        LoadOptions::default().parse_options(options),
+    )?;
+    let out_dir = TempDir::new("test_repository_fallback_encoding_out")?;
+    repo.write_to_directory_with_options(out_dir.path(), write_options)?;
+    let reader = RepositoryReader::new_from_directory(out_dir.path())?;
+    let primary = std::fs::read(
+        out_dir
+            .path()
+            .join(&reader.repomd().get_record("primary").unwrap().location_href),
+    )?;
+    assert!(std::str::from_utf8(&primary)?.contains("<packager>Jos\u{e9}Bluth \u{20ac}</packager>"));
+
+    Ok(())
+}
+
+#[test]
+fn test_read_trusted_input() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_trusted_input")?;
+    let write_options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::None);
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, write_options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.finish()?;
+
+    let load = |options| -> Result<Repository, MetadataError> {
+        Ok(Repository::load_from_directory_with_options(
+            tmp_dir.path(),
+            LoadOptions::default().parse_options(options),
+        )?
+        .0)
+    };
+    let trusted = ParseOptions::default().trusted_input(true);
+    assert_eq!(
+        load(trusted)?.packages(),
+        load(ParseOptions::default())?.packages()
+    );
+
+    // characters which aren't allowed in XML are read as they are...
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let primary_path = tmp_dir
+        .path()
+        .join(&reader.repomd().get_record("primary").unwrap().location_href);
+    let primary = std::fs::read_to_string(&primary_path)?;
+    std::fs::write(
+        &primary_path,
+        primary.replacen("<packager>", "<packager>\u{1}", 1),
+    )?;
+    let pkgid = common::COMPLEX_PACKAGE.pkgid();
+    assert!(load(trusted)?.packages()[pkgid]
+        .packager()
+        .starts_with('\u{1}'));
+
+    // ...but text which isn't valid UTF-8 still fails to parse
+    let garbage = primary
+        .replacen("<packager>", "<packager>\u{1}", 1)
+        .into_bytes();
+    let garbage: Vec<u8> = garbage
+        .into_iter()
+        .map(|b| if b == 1 { 0xff } else { b })
+        .collect();
+    std::fs::write(&primary_path, garbage)?;
+    assert!(load(trusted).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_read_gzip_streams() -> Result<(), MetadataError> {
+    use flate2::write::GzEncoder;
+
+    let tmp_dir = TempDir::new("test_repository_gzip_streams")?;
+    let write_options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::Gzip);
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, write_options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.finish()?;
+
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let primary_path = tmp_dir
+        .path()
+        .join(&reader.repomd().get_record("primary").unwrap().location_href);
+    let original = std::fs::read(&primary_path)?;
+    let mut primary = Vec::new();
+    flate2::read::GzDecoder::new(&original[..]).read_to_end(&mut primary)?;
+
+    let load = |options| -> Result<usize, MetadataError> {
+        let (repo, _) = Repository::load_from_directory_with_options(
+            tmp_dir.path(),
+            LoadOptions::default().parse_options(options),
+        )?;
+        Ok(repo.packages().len())
+    };
+    let decompression_error = |options| match load(options) {
+        Err(e) => match e.root_cause() {
+            MetadataError::DecompressionError(e) => e.clone(),
+            e => panic!("unexpected error: {}", e),
+        },
+        Ok(_) => panic!("expected an error"),
+    };
+
+    // the file split into two gzip streams, as produced by `cat`
+    let mut streams = Vec::new();
+    for part in primary.chunks(primary.len() / 2 + 1) {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(part)?;
+        streams.extend(encoder.finish()?);
+    }
+    std::fs::write(&primary_path, &streams)?;
+    assert_eq!(load(ParseOptions::default())?, 1);
+
+    // zero padding after the last stream
+    streams.extend([0; 512]);
+    std::fs::write(&primary_path, &streams)?;
+    assert_eq!(
+        decompression_error(ParseOptions::default()),
+        DecompressionError::TrailingData
+    );
+    assert_eq!(load(ParseOptions::default().ignore_trailing_data(true))?, 1);
+
+    // a download which was cut short
+    std::fs::write(&primary_path, &original[..original.len() / 2])?;
+    assert_eq!(
+        decompression_error(ParseOptions::default()),
+        DecompressionError::Truncated
+    );
+
+    // a damaged CRC in the gzip trailer
+    let mut corrupt = original.clone();
+    let crc = corrupt.len() - 8;
+    corrupt[crc] ^= 0xff;
+    std::fs::write(&primary_path, &corrupt)?;
+    assert!(matches!(
+        decompression_error(ParseOptions::default()),
+        DecompressionError::Corrupt(_)
+    ));
+
+    Ok(())
+}
+
+/// Build a zchunk file splitting `data` into `chunk_count` chunks compressed with `dictionary`.
+fn zck_file(data: &[u8], dictionary: &[u8], chunk_count: usize) -> Vec<u8> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(3, dictionary).unwrap();
+    let mut chunks = vec![(
+        zstd::bulk::compress(dictionary, 3).unwrap(),
+        dictionary.len(),
+    )];
+    for part in data.chunks(data.len() / chunk_count + 1) {
+        chunks.push((compressor.compress(part).unwrap(), part.len()));
+    }
+    let chunks: Vec<(&[u8], usize)> = chunks
+        .iter()
+        .map(|(chunk, len)| (chunk.as_slice(), *len))
+        .collect();
+    common::zck_file(&chunks).0
+}
+
+#[test]
+fn test_read_compression_by_content() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_repository_compression_by_content")?;
+    let write_options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::Gzip);
+    let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, write_options)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.finish()?;
+
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let primary_path = tmp_dir
+        .path()
+        .join(&reader.repomd().get_record("primary").unwrap().location_href);
+    let mut primary = Vec::new();
+    utils::reader_from_file(&primary_path)?.read_to_end(&mut primary)?;
+    let load = || -> Result<Package, MetadataError> {
+        let repo = Repository::load_from_directory(tmp_dir.path())?;
+        Ok(repo.packages()[common::COMPLEX_PACKAGE.pkgid()].clone())
+    };
+
+    // `primary.xml.gz` which is actually xz, as served by misconfigured publishers
+    let mut writer = niffler::send::to_path(
+        &primary_path,
+        niffler::send::compression::Format::Lzma,
+        niffler::Level::One,
+    )?;
+    writer.write_all(&primary)?;
+    drop(writer);
+    assert_eq!(load()?, *common::COMPLEX_PACKAGE);
+
+    // ... or zchunk
+    let dictionary = b"<package type=\"rpm\"><name></name><arch></arch><version epoch=\"0\"";
+    std::fs::write(&primary_path, zck_file(&primary, dictionary, 3))?;
+    assert_eq!(load()?, *common::COMPLEX_PACKAGE);
+
+    // a reader without a file name, e.g. an HTTP response body
+    let body = std::io::Cursor::new(zstd::encode_all(&primary[..], 3)?);
+    let mut reader = PrimaryXml::new_reader(utils::xml_reader_from(body)?);
+    assert_eq!(reader.read_header()?, 1);
+    let mut package = None;
+    reader.read_package(&mut package)?;
+    let package = package.unwrap();
+    assert_eq!(package.pkgid(), common::COMPLEX_PACKAGE.pkgid());
+    assert_eq!(package.name(), common::COMPLEX_PACKAGE.name());
+
+    // a damaged zchunk chunk
+    let mut file = zck_file(&primary, dictionary, 3);
+    let last = file.len() - 1;
+    file[last] ^= 0xff;
+    std::fs::write(&primary_path, file)?;
+    match load() {
+        Err(e) => assert!(matches!(
+            e.root_cause(),
+            MetadataError::DecompressionError(DecompressionError::Corrupt(_))
+        )),
+        Ok(_) => panic!("expected an error"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_draft_advisories() -> Result<(), MetadataError> {
+    use rpmrepo_metadata::{Checksum, EVR};
+
+    let repo_of = |packages: &[&Package]| {
+        let mut repo = Repository::new();
+        for package in packages {
+            repo.packages_mut()
+                .insert(package.pkgid().to_owned(), (*package).clone());
+        }
+        repo
+    };
+
+    // the previous version of complex-package, and a rebuild of it
+    let mut previous_complex = common::COMPLEX_PACKAGE.clone();
+    previous_complex.change_evr(EVR::new("1", "2.2.2", "2"));
+    previous_complex.set_checksum(Checksum::Sha256("1111".to_owned()));
+    previous_complex.set_changelogs(common::COMPLEX_PACKAGE.changelogs()[..2].to_vec());
+    let mut rebuilt_empty = common::RPM_EMPTY.clone();
+    rebuilt_empty.set_checksum(Checksum::Sha256("2222".to_owned()));
+    let previous = repo_of(&[&previous_complex, &common::RPM_EMPTY]);
+
+    // a subpackage built alongside complex-package, and a package which is new altogether
+    let mut devel = common::COMPLEX_PACKAGE.clone();
+    devel.rename("complex-package-devel");
+    devel.set_checksum(Checksum::Sha256("3333".to_owned()));
+    let new_package = Package::builder()
+        .name("brand-new")
+        .arch("noarch")
+        .evr(EVR::new("0", "1.0", "1"))
+        .checksum(Checksum::Sha256("4444".to_owned()))
+        .location_href("brand-new-1.0-1.noarch.rpm")
+        .rpm_sourcerpm("brand-new-1.0-1.src.rpm")
+        .time_build(1690000000)
+        .changelogs(vec![
+            rpmrepo_metadata::Changelog {
+                author: "Tobias Fünke - 0.9-1".to_owned(),
+                timestamp: 1680000000,
+                description: "- Packaging review".to_owned(),
+            },
+            rpmrepo_metadata::Changelog {
+                author: "Tobias Fünke - 1.0-1".to_owned(),
+                timestamp: 1690000000,
+                description: "\n- First release\n- Second line".to_owned(),
+            },
+        ])
+        .build()?;
+    let current = repo_of(&[
+        &common::COMPLEX_PACKAGE,
+        &devel,
+        &rebuilt_empty,
+        &new_package,
+    ]);
+
+    let drafts = current.draft_advisories(&previous)?;
+    assert_eq!(drafts.len(), 2);
+
+    let complex = &drafts[0];
+    assert_eq!(complex.id, "DRAFT-complex-package-2.3.4-5.el8");
+    assert_eq!(complex.title, "There’s always money in the banana stand");
+    assert_eq!(complex.update_type, "bugfix");
+    assert_eq!(complex.issued_date.as_deref(), Some("2021-07-23 15:05:43"));
+    assert_eq!(
+        complex.description,
+        "* George Bluth <george@federalprison.gov> - 3.3.3-3\n- There’s always money in the banana stand"
+    );
+    let filenames: Vec<_> = complex.packages().map(|p| p.filename.as_str()).collect();
+    assert_eq!(
+        filenames,
+        vec![
+            "complex-package-2.3.4-5.el8.x86_64.rpm",
+            "complex-package-devel-2.3.4-5.el8.x86_64.rpm"
+        ]
+    );
+
+    let brand_new = &drafts[1];
+    assert_eq!(brand_new.id, "DRAFT-brand-new-1.0-1");
+    assert_eq!(brand_new.title, "First release");
+    assert_eq!(brand_new.update_type, "newpackage");
+    assert_eq!(
+        brand_new.description,
+        "* Tobias Fünke - 1.0-1\n- First release\n- Second line"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_load_selected_metadata() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_load_selected_metadata")?;
+    let mut repo = Repository::new();
+    repo.packages_mut().insert(
+        common::COMPLEX_PACKAGE.pkgid().to_owned(),
+        common::COMPLEX_PACKAGE.clone(),
+    );
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2021-1")
+        .title("complex update")
+        .from("updates@fedoraproject.org")
+        .update_type("bugfix")
+        .issued_date("2021-07-23 15:05:43")
+        .build()?;
+    repo.advisories_mut()
+        .insert(advisory.id.clone(), advisory.clone());
+    repo.write_to_directory(tmp_dir.path())?;
+
+    let (loaded, report) =
+        Repository::load_from_directory_with_options(tmp_dir.path(), LoadOptions::default())?;
+    assert!(report.is_empty());
+    assert_eq!(loaded, Repository::load_from_directory(tmp_dir.path())?);
+    assert_eq!(loaded.advisories().get("FEDORA-2021-1"), Some(&advisory));
+
+    let options = LoadOptions::default().metadata(MetadataSelection::PRIMARY);
+    let (loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    let package = loaded
+        .packages()
+        .get(common::COMPLEX_PACKAGE.pkgid())
+        .unwrap();
+    assert_eq!(package.nevra(), common::COMPLEX_PACKAGE.nevra());
+    assert_eq!(package.provides(), common::COMPLEX_PACKAGE.provides());
+    assert!(package.files().is_empty());
+    assert!(package.changelogs().is_empty());
+    assert!(loaded.advisories().is_empty());
+    assert!(loaded.repomd().get_record("updateinfo").is_some());
+
+    // A repository without some of the metadata is still loaded
+    std::fs::remove_file(
+        tmp_dir
+            .path()
+            .join(&loaded.repomd().get_record("other").unwrap().location_href),
+    )?;
+    assert!(Repository::load_from_directory(tmp_dir.path()).is_err());
+    let options =
+        LoadOptions::default().metadata(MetadataSelection::PRIMARY | MetadataSelection::FILELISTS);
+    let (loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    let package = loaded
+        .packages()
+        .get(common::COMPLEX_PACKAGE.pkgid())
+        .unwrap();
+    assert_eq!(package.files(), common::COMPLEX_PACKAGE.files());
+    assert!(package.changelogs().is_empty());
+    assert!(loaded.advisories().is_empty());
+
+    // The advisories can be loaded without any packages
+    let options = LoadOptions::default().metadata(MetadataSelection::UPDATEINFO);
+    let (loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    assert!(loaded.packages().is_empty());
+    assert_eq!(loaded.advisories().get("FEDORA-2021-1"), Some(&advisory));
+
+    let mut selection = MetadataSelection::empty();
+    selection |= MetadataSelection::OTHER;
+    assert!(MetadataSelection::ALL.contains(selection));
+    assert!(!selection.contains(MetadataSelection::OTHER | MetadataSelection::PRIMARY));
+    assert!(selection.contains_type(&MetadataType::Other));
+    assert!(!selection.contains_type(&MetadataType::OtherZck));
+    assert!(MetadataSelection::COMPS.contains_type(&MetadataType::GroupGz));
+
+    Ok(())
+}
+
+#[test]
+fn test_write_selected_metadata() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_write_selected_metadata")?;
+    let mut repo = Repository::new();
+    for package in [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2021-1")
+        .title("complex update")
+        .from("updates@fedoraproject.org")
+        .update_type("bugfix")
+        .issued_date("2021-07-23 15:05:43")
+        .build()?;
+    repo.advisories_mut()
+        .insert(advisory.id.clone(), advisory.clone());
+
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(rpmrepo_metadata::CompressionType::Gzip)
+        .simple_metadata_filenames(false)
+        .other(false)
+        .zchunk(true);
+    repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let records = reader.repomd().records();
+    assert_eq!(
+        records
+            .iter()
+            .map(|record| record.metadata_type.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "primary",
+            "filelists",
+            "primary_zck",
+            "filelists_zck",
+            "updateinfo",
+            "updateinfo_zck"
+        ]
+    );
+    for record in records {
+        let file_name = record.location_href.file_name().unwrap().to_string_lossy();
+        let (_, digest) = record.checksum.to_values()?;
+        assert_eq!(
+            file_name,
+            format!(
+                "{}-{}",
+                digest,
+                record
+                    .metadata_type
+                    .file_name(rpmrepo_metadata::CompressionType::Gzip)
+            )
+        );
+        assert!(tmp_dir.path().join(&record.location_href).exists());
+    }
+    assert!(!tmp_dir.path().join("repodata/primary.xml.gz").exists());
+
+    // the zchunk files have the same contents as the others
+    let read = |href: &std::path::Path| -> Result<String, MetadataError> {
+        let mut contents = String::new();
+        utils::reader_from_file(&tmp_dir.path().join(href))?.read_to_string(&mut contents)?;
+        Ok(contents)
+    };
+    for (metadata, zchunk) in [
+        ("primary", "primary_zck"),
+        ("filelists", "filelists_zck"),
+        ("updateinfo", "updateinfo_zck"),
+    ] {
+        let record = reader.repomd().get_record(metadata).unwrap();
+        let zchunk_record = reader.repomd().get_record(zchunk).unwrap();
+        assert_eq!(
+            read(&record.location_href)?,
+            read(&zchunk_record.location_href)?
+        );
+        assert_eq!(zchunk_record.open_checksum, record.open_checksum);
+        assert!(zchunk_record.header_size.is_some());
+        assert!(zchunk_record.header_checksum.is_some());
+    }
+
+    let options = LoadOptions::default().metadata(
+        MetadataSelection::PRIMARY | MetadataSelection::FILELISTS | MetadataSelection::UPDATEINFO,
+    );
+    let (loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    assert_eq!(loaded.packages().len(), 2);
+    assert_eq!(loaded.advisories().get("FEDORA-2021-1"), Some(&advisory));
+
+    Ok(())
+}
+
+#[test]
+fn test_record_order() -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    let package = common::COMPLEX_PACKAGE.clone();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package);
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2021-1")
+        .title("complex update")
+        .from("updates@fedoraproject.org")
+        .update_type("bugfix")
+        .issued_date("2021-07-23 15:05:43")
+        .build()?;
+    repo.advisories_mut().insert(advisory.id.clone(), advisory);
+
+    for (order, types) in [
+        (
+            RecordOrder::Added,
+            vec![
+                "primary",
+                "filelists",
+                "other",
+                "updateinfo",
+                "primary_zck",
+                "filelists_zck",
+                "other_zck",
+                "updateinfo_zck",
+            ],
+        ),
+        (
+            RecordOrder::Alphabetical,
+            vec![
+                "filelists",
+                "filelists_zck",
+                "other",
+                "other_zck",
+                "primary",
+                "primary_zck",
+                "updateinfo",
+                "updateinfo_zck",
+            ],
+        ),
+    ] {
+        let tmp_dir = TempDir::new("test_record_order")?;
+        let options = RepositoryOptions::default()
+            .zchunk(true)
+            .record_order(order);
+        repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+
+        let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+        let mut repomd = reader.repomd().clone();
+        let records: Vec<&str> = repomd
+            .records()
+            .iter()
+            .map(|record| record.metadata_type.as_str())
+            .collect();
+        assert_eq!(records, types);
+
+        repomd.retain_records(|record| !record.metadata_type.is_zchunk());
+        repomd.sort_records();
+        let records: Vec<&str> = repomd
+            .records()
+            .iter()
+            .map(|record| record.metadata_type.as_str())
+            .collect();
+        assert_eq!(records, ["primary", "filelists", "other", "updateinfo"]);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_recompress_repository() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_recompress_repository")?;
+    let mut repo = Repository::new();
+    for package in [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(CompressionType::Gzip)
+        .simple_metadata_filenames(false)
+        .zchunk(true);
+    repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+    let before = RepositoryReader::new_from_directory(tmp_dir.path())?
+        .repomd()
+        .clone();
+
+    let options = RecompressOptions::default()
+        .compression(CompressionType::Zstd)
+        .level(19);
+    let repomd = recompress_repository(tmp_dir.path(), options)?;
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    assert_eq!(reader.repomd(), &repomd);
+    for (old, new) in before.records().iter().zip(repomd.records()) {
+        assert_eq!(new.metadata_type, old.metadata_type);
+        assert_eq!(new.open_checksum, old.open_checksum);
+        assert_eq!(new.open_size, old.open_size);
+        new.verify(tmp_dir.path())?;
+        let file_name = new.location_href.file_name().unwrap().to_string_lossy();
+        let (_, digest) = new.checksum.to_values()?;
+        if new.metadata_type.is_zchunk() {
+            assert_eq!(new, old);
+        } else {
+            assert_eq!(
+                file_name,
+                format!(
+                    "{}-{}",
+                    digest,
+                    new.metadata_type.file_name(CompressionType::Zstd)
+                )
+            );
+            assert!(!tmp_dir.path().join(&old.location_href).exists());
+        }
+    }
+    assert_eq!(
+        std::fs::read_dir(tmp_dir.path().join("repodata"))?.count(),
+        7
+    );
+    assert_eq!(reader.into_repo()?.packages(), repo.packages());
+    Ok(())
+}
+
+#[test]
+fn test_verify_files() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_verify_files")?;
+    let mut repo = Repository::new();
+    let contents = vec![7u8; 100_000];
+    for (index, package) in [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY]
+        .into_iter()
+        .enumerate()
+    {
+        let mut package = package.clone();
+        let href = format!("Packages/{}.rpm", index);
+        let contents = &contents[..contents.len() - index];
+        std::fs::create_dir_all(tmp_dir.path().join("Packages"))?;
+        std::fs::write(tmp_dir.path().join(&href), contents)?;
+        package
+            .set_location_href(href)
+            .set_checksum(utils::checksum_bytes(contents, ChecksumType::Sha256)?)
+            .set_size_package(contents.len() as u64);
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+    repo.write_to_directory(tmp_dir.path())?;
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let record_count = reader.repomd().records().len();
+
+    // blocks smaller than the packages, so they're read on a thread of their own
+    let options = VerifyOptions::default().workers(2).readahead(4096);
+    let report = reader.verify_files(options)?;
+    assert!(report.is_ok());
+    assert_eq!(report.files.len(), record_count + 2);
+    assert!(report.bytes_hashed >= 2 * 100_000 - 1);
+
+    std::fs::write(tmp_dir.path().join("Packages/0.rpm"), vec![8u8; 100_000])?;
+    std::fs::write(tmp_dir.path().join("Packages/1.rpm"), b"short")?;
+    let primary = reader.repomd().get_record("primary").unwrap();
+    let primary_check = FileCheck::new(
+        tmp_dir.path().join(&primary.location_href),
+        primary.checksum.clone(),
+        primary.size,
+    );
+    let package_checks: Vec<_> = reader
+        .into_repo()?
+        .packages()
+        .values()
+        .map(|package| {
+            FileCheck::new(
+                tmp_dir.path().join(package.location_href()),
+                package.checksum().clone(),
+                Some(package.size_package()),
+            )
+        })
+        .collect();
+    std::fs::remove_file(&primary_check.path)?;
+    let mut checks = vec![primary_check];
+    checks.extend(package_checks);
+    let report = verify_files(&checks, options);
+    assert!(!report.is_ok());
+    assert_eq!(report.failures().count(), 3);
+    assert_eq!(report.files[0].1, FileStatus::Missing);
+    let statuses: Vec<_> = report.files[1..].iter().map(|(_, status)| status).collect();
+    assert!(statuses
+        .iter()
+        .any(|status| matches!(status, FileStatus::ChecksumMismatch { .. })));
+    assert!(statuses.contains(&&FileStatus::SizeMismatch {
+        expected: 99_999,
+        actual: 5
+    }));
+    Ok(())
+}
+
+#[test]
+fn test_open_record() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_open_record")?;
+    let appstream = "<components version=\"0.14\"/>\n";
+    let comps = "<comps/>\n";
+    std::fs::write(tmp_dir.path().join("appstream.xml"), appstream)?;
+    std::fs::write(tmp_dir.path().join("comps.xml"), comps)?;
+    let mut repo_writer = RepositoryWriter::new(tmp_dir.path(), 1)?;
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.add_metadata_file("appstream", &tmp_dir.path().join("appstream.xml"))?;
+    repo_writer.add_metadata_file("group", &tmp_dir.path().join("comps.xml"))?;
+    repo_writer.finish()?;
+
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    for (metadata_type, expected) in [("appstream", appstream), ("group", comps)] {
+        let mut contents = String::new();
+        reader
+            .open_metadata(metadata_type)?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, expected);
+    }
+    let mut primary = String::new();
+    reader
+        .open_metadata("primary")?
+        .read_to_string(&mut primary)?;
+    assert!(primary.contains("<name>complex-package</name>"));
+    assert!(reader.open_metadata("modules").is_err());
+
+    // the contents are only rejected once the end is reached
+    let mut record = reader.repomd().get_record("appstream").unwrap().clone();
+    record.open_checksum = Some(utils::checksum_bytes(b"other", ChecksumType::Sha256)?);
+    let mut contents = Vec::new();
+    let error = record.open(tmp_dir.path())?.read_to_end(&mut contents);
+    assert!(matches!(
+        error.map_err(MetadataError::from),
+        Err(MetadataError::ChecksumMismatchError(..))
+    ));
+    assert_eq!(contents, appstream.as_bytes());
+
+    let mut record = reader.repomd().get_record("group").unwrap().clone();
+    record.size = Some(1);
+    let error = record.open(tmp_dir.path())?.read_to_end(&mut Vec::new());
+    assert!(matches!(
+        error.map_err(MetadataError::from),
+        Err(MetadataError::InconsistentMetadataError(_))
+    ));
+
+    // contents which can't be verified aren't opened at all
+    let mut record = reader.repomd().get_record("group").unwrap().clone();
+    record.checksum = Checksum::Other("sha3-256".to_owned(), "abcd".repeat(16));
+    assert!(matches!(
+        record.open(tmp_dir.path()).map(|_| ()),
+        Err(MetadataError::UnsupportedChecksumTypeError(checksum_type)) if checksum_type == "sha3-256"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_transcode_metadata_file() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_transcode_metadata_file")?;
+    let mut repo = Repository::new();
+    for package in [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    let options = RepositoryOptions::default()
+        .metadata_compression_type(CompressionType::Gzip)
+        .zchunk(true);
+    repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+    let repodata = tmp_dir.path().join("repodata");
+
+    let out_dir = TempDir::new("test_transcode_metadata_file_out")?;
+    let source = out_dir.path().join("primary.xml.gz");
+    std::fs::copy(repodata.join("primary.xml.gz"), &source)?;
+    let record = transcode_metadata_file(
+        &source,
+        &MetadataType::Primary,
+        MetadataFormat::Zchunk,
+        ChecksumType::Sha256,
+    )?;
+    assert_eq!(record.metadata_type, MetadataType::PrimaryZck);
+    let zchunk = out_dir.path().join("primary.xml.zck");
+    // the same as the zchunk file the repository was written with
+    assert_eq!(
+        std::fs::read(&zchunk)?,
+        std::fs::read(repodata.join("primary.xml.zck"))?
+    );
+
+    let record = transcode_metadata_file(
+        &zchunk,
+        &MetadataType::PrimaryZck,
+        MetadataFormat::Xml(CompressionType::Xz),
+        ChecksumType::Sha256,
+    )?;
+    assert_eq!(record.metadata_type, MetadataType::Primary);
+    assert_eq!(
+        record.location_href,
+        std::path::Path::new("repodata/primary.xml.xz")
+    );
+    let read = |path: &std::path::Path| -> Result<String, MetadataError> {
+        let mut contents = String::new();
+        utils::reader_from_file(path)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    };
+    assert_eq!(
+        read(&out_dir.path().join("primary.xml.xz"))?,
+        read(&source)?
+    );
+    assert_eq!(
+        std::fs::read_dir(out_dir.path())?.count(),
+        3,
+        "no temporary files are left"
+    );
+
+    assert!(matches!(
+        transcode_metadata_file(
+            &source,
+            &MetadataType::Group,
+            MetadataFormat::Zchunk,
+            ChecksumType::Sha256
+        ),
+        Err(MetadataError::InvalidFieldError("zchunk metadata type", name)) if name == "group"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_search_changelogs() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_search_changelogs")?;
+    let mut repo = Repository::new();
+    for package in [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+
+    let matches = repo.search_changelogs("BANANA", None)?;
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].pkgid, common::COMPLEX_PACKAGE.pkgid());
+    assert_eq!(matches[0].nevra, common::COMPLEX_PACKAGE.nevra());
+    assert_eq!(
+        matches[0].changelog,
+        common::COMPLEX_PACKAGE.changelogs()[0]
+    );
+    assert_eq!(matches[1].changelog.timestamp, 1623672000);
+
+    let recent = repo.search_changelogs("banana", Some(1619352000))?;
+    assert_eq!(recent, matches[1..]);
+    assert!(repo.search_changelogs("CVE-2024-", None)?.is_empty());
+
+    // other.xml is searched without loading the rest of the repository
+    repo.write_to_directory(tmp_dir.path())?;
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    assert_eq!(reader.search_changelogs("BANANA", None)?, matches);
+    assert_eq!(
+        reader.search_changelogs("banana", Some(1619352000))?,
+        recent
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_split_updateinfo() -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    for (id, update_type, issued_date) in [
+        ("FEDORA-2022-1", "security", "2022-11-02 10:00:00"),
+        ("FEDORA-2023-1", "bugfix", "2023-01-05 10:00:00"),
+        ("FEDORA-2023-2", "security", "2023-03-14 10:00:00"),
+    ] {
+        let advisory = UpdateRecord::builder()
+            .id(id)
+            .title("an update")
+            .from("updates@fedoraproject.org")
+            .update_type(update_type)
+            .issued_date(issued_date)
+            .updated_date(issued_date)
+            .build()?;
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+
+    for (split, types) in [
+        (
+            UpdateinfoSplit::ByYear,
+            vec!["updateinfo-2022", "updateinfo-2023"],
+        ),
+        (
+            UpdateinfoSplit::ByType,
+            vec!["updateinfo-bugfix", "updateinfo-security"],
+        ),
+    ] {
+        let tmp_dir = TempDir::new("test_split_updateinfo")?;
+        let options = RepositoryOptions::default()
+            .updateinfo_split(split)
+            .zchunk(true);
+        repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+
+        let loaded = Repository::load_from_directory(tmp_dir.path())?;
+        let records: Vec<&str> = loaded
+            .repomd()
+            .records()
+            .iter()
+            .map(|record| record.metadata_type.as_str())
+            .filter(|name| name.starts_with("updateinfo"))
+            .collect();
+        assert_eq!(records, types);
+        for name in types {
+            let record = loaded.repomd().get_record(name).unwrap();
+            assert_eq!(
+                record.location_href,
+                std::path::PathBuf::from(format!("repodata/{}.xml.zst", name))
+            );
+        }
+        assert_eq!(loaded.advisories(), repo.advisories());
+    }
+
+    // the documents are read back without loading everything else
+    let tmp_dir = TempDir::new("test_split_updateinfo")?;
+    let options = RepositoryOptions::default().updateinfo_split(UpdateinfoSplit::ByType);
+    repo.write_to_directory_with_options(tmp_dir.path(), options)?;
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let ids = reader
+        .iter_advisories()?
+        .map(|advisory| advisory.map(|advisory| advisory.id))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(ids, ["FEDORA-2023-1", "FEDORA-2022-1", "FEDORA-2023-2"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_duplicate_advisories() -> Result<(), MetadataError> {
+    let advisory = |title: &str, updated_date: &str| {
+        UpdateRecord::builder()
+            .id("FEDORA-2023-1")
+            .title(title)
+            .from("updates@fedoraproject.org")
+            .update_type("bugfix")
+            .issued_date("2023-01-05 10:00:00")
+            .updated_date(updated_date)
+            .build()
+    };
+    let newer = advisory("the newer update", "2023-02-01 10:00:00")?;
+    let older = advisory("the older update", "2023-01-05 10:00:00")?;
+    let write_repo = |policy: DuplicateAdvisoryPolicy| -> Result<Repository, MetadataError> {
+        let tmp_dir = TempDir::new("test_duplicate_advisories")?;
+        let options = RepositoryOptions::default().duplicate_advisories(policy);
+        let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 0, options)?;
+        repo_writer.add_advisory(&older)?;
+        repo_writer.add_advisory(&newer)?;
+        repo_writer.add_advisory(&older)?;
+        repo_writer.finish()?;
+        Repository::load_from_directory(tmp_dir.path())
+    };
+
+    assert!(write_repo(DuplicateAdvisoryPolicy::Error).is_err());
+
+    let repo = write_repo(DuplicateAdvisoryPolicy::KeepNewest)?;
+    assert_eq!(repo.advisories().len(), 1);
+    assert_eq!(repo.advisories()["FEDORA-2023-1"].title, "the newer update");
+
+    let repo = write_repo(DuplicateAdvisoryPolicy::Suffix)?;
+    let titles: Vec<(&str, &str)> = repo
+        .advisories()
+        .values()
+        .map(|advisory| (advisory.id.as_str(), advisory.title.as_str()))
+        .collect();
+    assert_eq!(
+        titles,
+        [
+            ("FEDORA-2023-1", "the older update"),
+            ("FEDORA-2023-1-2", "the newer update"),
+            ("FEDORA-2023-1-3", "the older update"),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_squash() -> Result<(), MetadataError> {
+    let package = common::package;
+    let bash_old = package("bash", "5.2.15", "x86_64");
+    let bash_new = package("bash", "5.2.21", "x86_64");
+    let bash_i686 = package("bash", "5.2.15", "i686");
+    let curl_old = package("curl", "8.0.1", "x86_64");
+    let curl_new = package("curl", "8.1.0", "x86_64");
+
+    let mut repo = Repository::new();
+    for package in [&bash_old, &bash_new, &bash_i686, &curl_new, &curl_old] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    let advisory = |id: &str, packages: &[&Package]| {
+        UpdateRecord::builder()
+            .id(id)
+            .title("an update")
+            .from("updates@fedoraproject.org")
+            .update_type("bugfix")
+            .issued_date("2023-04-18 00:00:00")
+            .updated_date("2023-04-18 00:00:00")
+            .packages("F38", "Fedora 38", packages.iter().copied())
+            .build()
+            .unwrap()
+    };
+    for advisory in [
+        advisory("FEDORA-2023-1", &[&bash_old, &bash_i686]),
+        advisory("FEDORA-2023-2", &[&bash_new, &curl_old]),
+        advisory("FEDORA-2023-3", &[&curl_old]),
+        advisory("FEDORA-2023-4", &[]),
+    ] {
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+
+    let squashed = repo.squash();
+    let nevras: Vec<String> = squashed
+        .packages()
+        .values()
+        .map(|package| package.nevra())
+        .collect();
+    assert_eq!(
+        nevras,
+        [
+            "bash-0:5.2.21-1.x86_64",
+            "bash-0:5.2.15-1.i686",
+            "curl-0:8.1.0-1.x86_64"
+        ]
+    );
+
+    let advisories: Vec<(&str, Vec<&str>)> = squashed
+        .advisories()
+        .values()
+        .map(|advisory| {
+            let filenames = advisory
+                .packages()
+                .map(|package| package.filename.as_str())
+                .collect();
+            (advisory.id.as_str(), filenames)
+        })
+        .collect();
+    assert_eq!(
+        advisories,
+        [
+            ("FEDORA-2023-1", vec!["bash-5.2.15-1.i686.rpm"]),
+            ("FEDORA-2023-2", vec!["bash-5.2.21-1.x86_64.rpm"]),
+            ("FEDORA-2023-4", vec![]),
+        ]
+    );
+    assert_eq!(repo.packages().len(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_at() -> Result<(), MetadataError> {
+    let package = |name: &str, version: &str, time_build: u64| {
+        common::package_builder(name, version, "x86_64")
+            .time_build(time_build)
+            .build()
+            .unwrap()
+    };
+    // 2023-03-01 00:00:00 and 2023-04-01 00:00:00
+    let (march, april) = (1677628800, 1680307200);
+    let bash_old = package("bash", "5.2.15", march - 86400);
+    // built before the snapshot, but only released by an advisory after it
+    let bash_new = package("bash", "5.2.21", march + 3600);
+    let curl = package("curl", "8.0.1", april);
+
+    let mut repo = Repository::new();
+    for package in [&bash_old, &bash_new, &curl] {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package.clone());
+    }
+    for (id, issued_date, package) in [
+        ("FEDORA-2023-1", "2023-02-28 10:00:00", &bash_old),
+        ("FEDORA-2023-2", "2023-04-01", &bash_new),
+        ("FEDORA-2023-3", "1680393600", &curl),
+    ] {
+        let advisory = UpdateRecord::builder()
+            .id(id)
+            .title("an update")
+            .from("updates@fedoraproject.org")
+            .update_type("bugfix")
+            .issued_date(issued_date)
+            .updated_date(issued_date)
+            .packages("F38", "Fedora 38", [package])
+            .build()?;
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+
+    let nevras = |repo: &Repository| -> Vec<String> {
+        repo.packages()
+            .values()
+            .map(|package| package.nevra())
+            .collect()
+    };
+    let snapshot = repo.snapshot_at(march + 7200);
+    assert_eq!(nevras(&snapshot), ["bash-0:5.2.15-1.x86_64"]);
+    let ids: Vec<&str> = snapshot.advisories().keys().map(String::as_str).collect();
+    assert_eq!(ids, ["FEDORA-2023-1"]);
+
+    // a date without a time is at midnight, and curl isn't released until the next day
+    let snapshot = repo.snapshot_at(april);
+    assert_eq!(
+        nevras(&snapshot),
+        ["bash-0:5.2.15-1.x86_64", "bash-0:5.2.21-1.x86_64"]
+    );
+    assert_eq!(snapshot.advisories().len(), 2);
+    assert_eq!(repo.snapshot_at(april + 86400), repo);
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_delta() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_apply_delta")?;
+    let package = |name: &str, version: &str| common::package(name, version, "x86_64");
+    let advisory = |id: &str, title: &str, package: &Package| {
+        UpdateRecord::builder()
+            .id(id)
+            .title(title)
+            .from("updates@fedoraproject.org")
+            .update_type("bugfix")
+            .issued_date("2023-04-18 00:00:00")
+            .updated_date("2023-04-18 00:00:00")
+            .packages("F38", "Fedora 38", [package])
+            .build()
+            .unwrap()
+    };
+    let bash_old = package("bash", "5.2.15");
+    let bash_new = package("bash", "5.2.21");
+    let curl = package("curl", "8.0.1");
+    let zsh = package("zsh", "5.9");
+
+    let write = |name: &str, packages: &[&Package], advisories: &[UpdateRecord]| {
+        let mut repo = Repository::new();
+        for package in packages {
+            repo.packages_mut()
+                .insert(package.pkgid().to_owned(), (*package).clone());
+        }
+        for advisory in advisories {
+            repo.advisories_mut()
+                .insert(advisory.id.clone(), advisory.clone());
+        }
+        let path = tmp_dir.path().join(name);
+        repo.write_to_directory(&path)?;
+        Repository::load_from_directory(&path)
+    };
+    let mut base = write(
+        "base",
+        &[&bash_old, &curl],
+        &[
+            advisory("FEDORA-2023-1", "a bash update", &bash_old),
+            advisory("FEDORA-2023-2", "a curl update", &curl),
+        ],
+    )?;
+    let target = write(
+        "target",
+        &[&bash_new, &curl, &zsh],
+        &[
+            advisory("FEDORA-2023-2", "a curl security update", &curl),
+            advisory("FEDORA-2023-3", "a bash update", &bash_new),
+        ],
+    )?;
+
+    let delta = base.delta_to(&target);
+    let nevras = |packages: &[Package]| -> Vec<String> {
+        packages.iter().map(|package| package.nevra()).collect()
+    };
+    assert_eq!(
+        nevras(&delta.added_packages),
+        ["bash-0:5.2.21-1.x86_64", "zsh-0:5.9-1.x86_64"]
+    );
+    assert_eq!(delta.removed_packages, [bash_old.pkgid()]);
+    let ids: Vec<&str> = delta
+        .changed_advisories
+        .iter()
+        .map(|advisory| advisory.id.as_str())
+        .collect();
+    assert_eq!(ids, ["FEDORA-2023-2", "FEDORA-2023-3"]);
+    assert_eq!(delta.removed_advisories, ["FEDORA-2023-1"]);
+
+    let delta_path = tmp_dir.path().join("delta");
+    delta.write_to_directory(&delta_path)?;
+    let delta = RepositoryDelta::read_from_directory(&delta_path)?;
+    assert_eq!(delta, base.delta_to(&target));
+
+    // the delta only applies to the revision it was made from
+    let mut other = Repository::load_from_directory(&tmp_dir.path().join("target"))?;
+    assert!(matches!(
+        other.apply_delta(&delta),
+        Err(MetadataError::InconsistentMetadataError(_))
+    ));
+    assert_eq!(other, target);
+
+    base.apply_delta(&delta)?;
+    assert_eq!(base.repomd(), target.repomd());
+    let mut packages: Vec<&Package> = base.packages().values().collect();
+    packages.sort_by_key(|package| package.nevra());
+    assert_eq!(packages, target.packages().values().collect::<Vec<_>>());
+    for (id, advisory) in target.advisories() {
+        assert_eq!(base.advisories().get(id), Some(advisory));
+    }
+    assert_eq!(base.advisories().len(), target.advisories().len());
+    assert!(base.delta_to(&target).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_repository_hooks() -> Result<(), MetadataError> {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct AuditLog(Mutex<Vec<String>>);
+
+    impl RepositoryHooks for AuditLog {
+        fn package_written(&self, package: &Package) -> Result<(), MetadataError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("wrote {}", package.nevra()));
+            Ok(())
+        }
+
+        fn metadata_file_finished(
+            &self,
+            path: &Path,
+            record: &rpmrepo_metadata::RepomdRecord,
+        ) -> Result<(), MetadataError> {
+            assert!(path.exists());
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("finished {}", record.metadata_type));
+            Ok(())
+        }
+
+        fn repository_published(
+            &self,
+            path: &Path,
+            repomd: &rpmrepo_metadata::RepomdData,
+        ) -> Result<(), MetadataError> {
+            let published = Repository::load_from_directory(path)?;
+            assert_eq!(published.packages().len(), 1);
+            assert_eq!(published.repomd().records().len(), repomd.records().len());
+            self.0.lock().unwrap().push("published".to_owned());
+            Ok(())
+        }
+    }
+
+    let tmp_dir = TempDir::new("test_repository_hooks")?;
+    let log = Arc::new(AuditLog::default());
+    let options = RepositoryOptions::default().simple_metadata_filenames(false);
+    let mut repo_writer =
+        RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?.with_hooks(log.clone());
+    repo_writer.add_package(&common::COMPLEX_PACKAGE)?;
+    repo_writer.finish()?;
+
+    assert_eq!(
+        *log.0.lock().unwrap(),
+        [
+            format!("wrote {}", common::COMPLEX_PACKAGE.nevra()),
+            "finished primary".to_owned(),
+            "finished filelists".to_owned(),
+            "finished other".to_owned(),
+            "published".to_owned(),
+        ]
+    );
+
+    // an error from a hook stops the build
+    struct Failing;
+    impl RepositoryHooks for Failing {
+        fn package_written(&self, _package: &Package) -> Result<(), MetadataError> {
+            Err(MetadataError::InconsistentMetadataError("no".to_owned()))
+        }
+    }
+    let mut repo_writer = RepositoryWriter::new(tmp_dir.path(), 1)?.with_hooks(Arc::new(Failing));
+    assert!(repo_writer.add_package(&common::COMPLEX_PACKAGE).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_publish_report() -> Result<(), MetadataError> {
+    use std::path::PathBuf;
+
+    let tmp_dir = TempDir::new("test_publish_report")?;
+    let options = RepositoryOptions::default()
+        .simple_metadata_filenames(false)
+        .updateinfo(false);
+    let publish = |package: &Package| -> Result<_, MetadataError> {
+        let mut repo_writer = RepositoryWriter::new_with_options(tmp_dir.path(), 1, options)?;
+        repo_writer.add_package(package)?;
+        repo_writer.finish()
+    };
+    let hrefs = |repo: &Repository| -> Vec<PathBuf> {
+        repo.repomd()
+            .records()
+            .iter()
+            .map(|record| record.location_href.clone())
+            .collect()
+    };
+
+    let report = publish(&common::COMPLEX_PACKAGE)?;
+    let first = Repository::load_from_directory(tmp_dir.path())?;
+    let mut expected = hrefs(&first);
+    expected.push(PathBuf::from("repodata/repomd.xml"));
+    assert_eq!(report.changed, expected);
+    assert!(report.removed.is_empty());
+
+    // nothing but repomd.xml changes if the same metadata is written again
+    let report = publish(&common::COMPLEX_PACKAGE)?;
+    assert_eq!(report.changed, [PathBuf::from("repodata/repomd.xml")]);
+    assert!(report.removed.is_empty());
+
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_summary("A package with a new summary");
+    let report = publish(&package)?;
+    let second = Repository::load_from_directory(tmp_dir.path())?;
+    let (old, new) = (hrefs(&first), hrefs(&second));
+    // the summary is only in primary.xml
+    assert_eq!(
+        report.changed,
+        [new[0].clone(), "repodata/repomd.xml".into()]
+    );
+    assert_eq!(report.removed, [old[0].clone()]);
+    assert_eq!(old[1..], new[1..]);
+    assert!(tmp_dir.path().join(&old[0]).exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_rewrite_locations() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_rewrite_locations")?;
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_location_href("Packages/c/complex-package-2.3.4-5.el8.x86_64.rpm");
+    let advisory = UpdateRecord::builder()
+        .id("FEDORA-2023-0001")
+        .title("complex-package update")
+        .from("updates@fedoraproject.org")
+        .update_type("bugfix")
+        .issued_date("2023-04-18 00:00:00")
+        .packages("F38", "Fedora 38", [&package])
+        .build()?;
+    let filename = |repo: &Repository| {
+        repo.advisories()["FEDORA-2023-0001"].pkglist[0].packages[0]
+            .filename
+            .clone()
+    };
+
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    repo.advisories_mut()
+        .insert(advisory.id.clone(), advisory.clone());
+    assert_eq!(filename(&repo), "complex-package-2.3.4-5.el8.x86_64.rpm");
+
+    // flattened, and the file name of the RPM changes along with the location
+    repo.rewrite_locations(|href| {
+        href.rsplit('/')
+            .next()
+            .unwrap()
+            .replace(".rpm", ".signed.rpm")
+    });
+    repo.prefix_locations("https://mirror.example.com/fedora/");
+    repo.write_to_directory(tmp_dir.path())?;
+
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
+    assert_eq!(
+        repo.packages()[package.pkgid()].location_href(),
+        "https://mirror.example.com/fedora/complex-package-2.3.4-5.el8.x86_64.signed.rpm"
+    );
+    assert_eq!(
+        filename(&repo),
+        "complex-package-2.3.4-5.el8.x86_64.signed.rpm"
+    );
+    assert_eq!(repo.packages()[package.pkgid()].files(), package.files());
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_footprint() {
+    let mut repo = Repository::new();
+    assert_eq!(repo.memory_footprint().total(), 0);
+
+    let package = common::COMPLEX_PACKAGE.clone();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    let footprint = repo.memory_footprint();
+    assert!(footprint.packages >= std::mem::size_of::<Package>());
+    assert!(footprint.strings >= package.description().len() + package.summary().len());
+    assert!(footprint.requirements > 0);
+    assert!(footprint.files >= package.files().iter().map(|f| f.path.len()).sum());
+    assert!(footprint.changelogs > 0);
+    assert_eq!(footprint.advisories, 0);
+    assert_eq!(
+        footprint.total(),
+        footprint.packages
+            + footprint.strings
+            + footprint.requirements
+            + footprint.files
+            + footprint.changelogs
+    );
+
+    // leaving out the file lists and changelogs saves what they use
+    let package = &mut repo.packages_mut()[0];
+    package.rpm_files = Vec::new();
+    package.rpm_changelogs = Vec::new();
+    let without = repo.memory_footprint();
+    assert_eq!((without.files, without.changelogs), (0, 0));
+    assert_eq!(
+        without.total(),
+        footprint.total() - footprint.files - footprint.changelogs
+    );
+}
+
+#[test]
+fn test_changelog_storage() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_changelog_storage")?;
+    let package = &*common::COMPLEX_PACKAGE;
+    let mut repo = Repository::new();
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    repo.write_to_directory(tmp_dir.path())?;
+
+    for storage in [ChangelogStorage::Buffer, ChangelogStorage::TempFile] {
+        let options = LoadOptions::default().changelogs(storage);
+        let (mut loaded, _) =
+            Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+        let stored = &loaded.packages()[package.pkgid()];
+        assert!(stored.stored_changelogs().is_some());
+        // the authors and timestamps are kept, the descriptions are read when needed
+        assert_eq!(stored.changelogs().len(), package.changelogs().len());
+        assert!(stored.changelogs().iter().all(|c| c.description.is_empty()));
+        assert_eq!(&*stored.load_changelogs()?, package.changelogs());
+        assert!(loaded.memory_footprint().changelogs < repo.memory_footprint().changelogs);
+        assert_eq!(
+            loaded.search_changelogs("banana", None)?,
+            repo.search_changelogs("banana", None)?
+        );
+
+        // the descriptions are written out
+        let written = TempDir::new("test_changelog_storage")?;
+        loaded.write_to_directory(written.path())?;
+        let reloaded = Repository::load_from_directory(written.path())?;
+        assert_eq!(
+            reloaded.packages()[package.pkgid()].changelogs(),
+            package.changelogs()
+        );
+
+        loaded.store_changelogs(ChangelogStorage::Inline)?;
+        let inlined = &loaded.packages()[package.pkgid()];
+        assert!(inlined.stored_changelogs().is_none());
+        assert_eq!(inlined.changelogs(), package.changelogs());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_file_storage() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_file_storage")?;
+    let mut repo = Repository::new();
+    for idx in 0..50 {
+        let name = format!("python3-module{}", idx);
+        let requires = match idx {
+            0 => vec![Requirement {
+                name: "/usr/lib/python3.12/site-packages/module7/file3.py".to_owned(),
+                ..Requirement::default()
+            }],
+            _ => Vec::new(),
+        };
+        let mut package = common::package_builder(&name, "1.0", "noarch")
+            .requires(requires)
+            .self_provides(true)
+            .build()?;
+        let dir = format!("/usr/lib/python3.12/site-packages/module{}", idx);
+        package.add_file(FileType::Dir, &dir);
+        for file in 0..20 {
+            package.add_file(FileType::File, &format!("{}/file{}.py", dir, file));
+        }
+        package.add_file(FileType::File, &format!("/usr/bin/{}", name));
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+    repo.write_to_directory(tmp_dir.path())?;
+
+    let options = LoadOptions::default().files(FileStorage::Compact);
+    let (mut loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    for (pkgid, package) in repo.packages() {
+        let stored = &loaded.packages()[pkgid];
+        assert!(stored.stored_files().is_some());
+        assert!(stored.files().is_empty());
+        assert_eq!(&*stored.load_files(), package.files());
+        assert!(stored.contains_file(&package.files()[3].path));
+        assert_eq!(stored.file_provides(), package.file_provides());
+    }
+    assert!(!loaded.packages()[repo.packages()[0].pkgid()]
+        .contains_file("/usr/lib/python3.12/site-packages/module1/file3.py"));
+    assert!(loaded.memory_footprint().files < repo.memory_footprint().files);
+
+    // file dependencies are resolved against the stored files
+    assert!(loaded.repoclosure().is_empty());
+    let providers = Providers::new(loaded.packages().values());
+    let owners = providers.what_provides(&repo.packages()[0].requires()[0]);
+    assert_eq!(owners.len(), 1);
+    assert_eq!(owners[0].name(), "python3-module7");
+
+    // files added since they were stored are kept along with them
+    let pkgid = repo.packages()[1].pkgid().to_owned();
+    let package = loaded.packages_mut().get_mut(&pkgid).unwrap();
+    package.add_file(FileType::Ghost, "/var/log/module1.log");
+    assert!(package.contains_file("/var/log/module1.log"));
+    assert_eq!(package.load_files().len(), 23);
+
+    // the files are written out
+    let written = TempDir::new("test_file_storage")?;
+    loaded.write_to_directory(written.path())?;
+    let reloaded = Repository::load_from_directory(written.path())?;
+    assert_eq!(
+        reloaded.packages()[repo.packages()[0].pkgid()].files(),
+        repo.packages()[0].files()
+    );
+    assert_eq!(reloaded.packages()[&pkgid].files().len(), 23);
+
+    loaded.store_files(FileStorage::Inline)?;
+    let inlined = &loaded.packages()[repo.packages()[0].pkgid()];
+    assert!(inlined.stored_files().is_none());
+    assert_eq!(inlined.files(), repo.packages()[0].files());
+
+    Ok(())
+}
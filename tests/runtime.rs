@@ -0,0 +1,68 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    current_runtime, reset_runtime, set_runtime, utils, verify_files, ChecksumType, FileCheck,
+    FileStatus, MetadataError, Runtime, VerifyOptions,
+};
+use tempdir::TempDir;
+
+#[test]
+fn test_runtime() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_runtime")?;
+    let mut files = Vec::new();
+    for i in 0..8 {
+        let contents = vec![i as u8; 100_000];
+        let path = tmp_dir.path().join(format!("file-{}", i));
+        std::fs::write(&path, &contents)?;
+        let checksum = utils::checksum_bytes(&contents, ChecksumType::Sha256)?;
+        files.push(FileCheck::new(path, checksum, Some(contents.len() as u64)));
+    }
+    // large enough to be read ahead of the hasher, if there's a thread for it
+    let options = VerifyOptions::default().workers(4).readahead(4096);
+
+    // everything is done on the calling thread
+    set_runtime(Runtime::new(0));
+    assert_eq!(current_runtime().max_threads(), 0);
+    let report = verify_files(&files, options);
+    assert!(report.is_ok());
+    assert_eq!(report.files.len(), 8);
+
+    set_runtime(Runtime::new(2));
+    let report = verify_files(&files, options);
+    assert!(report
+        .files
+        .iter()
+        .all(|(_, status)| status == &FileStatus::Valid));
+    assert_eq!(current_runtime().busy_threads(), 0);
+
+    // several verifications at once share the threads of the runtime
+    std::thread::scope(|scope| {
+        let verifications: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| verify_files(&files, options)))
+            .collect();
+        for verification in verifications {
+            assert!(verification.join().unwrap().is_ok());
+        }
+    });
+    assert_eq!(current_runtime().busy_threads(), 0);
+
+    reset_runtime();
+    assert_eq!(
+        current_runtime().max_threads(),
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_errors_are_send() {
+    // errors are sent back from the threads work is done on
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MetadataError>();
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::sbom::{self, SbomOptions};
+use rpmrepo_metadata::MetadataError;
+use serde_json::Value;
+
+mod common;
+
+#[test]
+fn test_spdx() -> Result<(), MetadataError> {
+    let repo = common::repository([common::COMPLEX_PACKAGE.clone()]);
+    let options = SbomOptions::new("baseos")
+        .base_url("https://example.com/baseos/")
+        .purl_namespace("redhat")
+        .created(1683023400);
+    let package = &*common::COMPLEX_PACKAGE;
+
+    let mut written = Vec::new();
+    sbom::write_spdx(&repo, &options, &mut written)?;
+    let document: Value = serde_json::from_slice(&written)?;
+    assert_eq!(document, sbom::to_spdx(&repo, &options));
+
+    assert_eq!(document["spdxVersion"], "SPDX-2.3");
+    assert_eq!(document["creationInfo"]["created"], "2023-05-02T10:30:00Z");
+    let namespace = document["documentNamespace"].as_str().unwrap();
+    assert!(namespace.starts_with("https://spdx.org/spdxdocs/baseos-"));
+    // the same repository gets the same namespace
+    assert_eq!(
+        sbom::to_spdx(&repo, &options)["documentNamespace"],
+        namespace
+    );
+
+    let spdx_package = &document["packages"][0];
+    assert_eq!(spdx_package["name"], "complex-package");
+    assert_eq!(spdx_package["versionInfo"], "1:2.3.4-5.el8");
+    assert_eq!(spdx_package["licenseDeclared"], package.rpm_license());
+    assert_eq!(
+        spdx_package["supplier"],
+        format!("Organization: {}", package.rpm_vendor())
+    );
+    assert_eq!(
+        spdx_package["downloadLocation"],
+        format!("https://example.com/baseos/{}", package.location_href())
+    );
+    assert_eq!(spdx_package["checksums"][0]["algorithm"], "SHA256");
+    assert_eq!(
+        spdx_package["externalRefs"][0]["referenceLocator"],
+        "pkg:rpm/redhat/complex-package@2.3.4-5.el8?arch=x86_64&epoch=1"
+    );
+    assert_eq!(
+        document["relationships"][0]["relatedSpdxElement"],
+        spdx_package["SPDXID"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cyclonedx() -> Result<(), MetadataError> {
+    let repo = common::repository([common::COMPLEX_PACKAGE.clone()]);
+    let options = SbomOptions::new("baseos").created(1683023400);
+    let package = &*common::COMPLEX_PACKAGE;
+
+    let mut written = Vec::new();
+    sbom::write_cyclonedx(&repo, &options, &mut written)?;
+    let document: Value = serde_json::from_slice(&written)?;
+
+    assert_eq!(document["bomFormat"], "CycloneDX");
+    assert_eq!(document["metadata"]["timestamp"], "2023-05-02T10:30:00Z");
+    let component = &document["components"][0];
+    assert_eq!(
+        component["purl"],
+        "pkg:rpm/complex-package@2.3.4-5.el8?arch=x86_64&epoch=1"
+    );
+    assert_eq!(component["bom-ref"], component["purl"]);
+    assert_eq!(component["version"], "1:2.3.4-5.el8");
+    assert_eq!(component["supplier"]["name"], package.rpm_vendor());
+    assert_eq!(
+        component["licenses"][0]["expression"],
+        package.rpm_license()
+    );
+    assert_eq!(component["hashes"][0]["alg"], "SHA-256");
+    assert_eq!(
+        component["hashes"][0]["content"],
+        package.checksum().to_values()?.1
+    );
+    // there's no download location without a base URL
+    let references = component["externalReferences"].as_array().unwrap();
+    assert!(references
+        .iter()
+        .all(|reference| reference["type"] != "distribution"));
+
+    Ok(())
+}
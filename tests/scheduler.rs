@@ -0,0 +1,125 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    current_runtime, JobPriority, JobStatus, MetadataError, Scheduler, SchedulerOptions,
+};
+
+#[test]
+fn test_scheduler_priorities() {
+    let scheduler = Scheduler::new(SchedulerOptions::default().workers(1));
+
+    // hold the only worker until every job is queued
+    let (release, blocked) = mpsc::channel::<()>();
+    let blocker = scheduler.submit("blocker", JobPriority::Normal, move |_| {
+        blocked.recv().ok();
+        Ok(())
+    });
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut jobs = Vec::new();
+    for (name, priority) in [
+        ("mirror", JobPriority::Background),
+        ("build", JobPriority::Normal),
+        ("verify", JobPriority::Interactive),
+        ("build again", JobPriority::Normal),
+    ] {
+        let order = order.clone();
+        jobs.push(scheduler.submit(name, priority, move |_| {
+            order.lock().unwrap().push(name);
+            Ok(())
+        }));
+    }
+    let failed = scheduler.submit("broken", JobPriority::Background, |_| {
+        Err(MetadataError::InconsistentMetadataError(
+            "broken".to_owned(),
+        ))
+    });
+    let panicked = scheduler.submit("panicked", JobPriority::Background, |_| panic!("oops"));
+
+    assert_eq!(scheduler.status(jobs[0]), Some(JobStatus::Queued));
+    assert_eq!(scheduler.jobs().len(), 7);
+    release.send(()).unwrap();
+
+    for id in jobs.iter().chain([&blocker]) {
+        assert_eq!(scheduler.wait(*id), Some(JobStatus::Finished));
+    }
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["verify", "build", "build again", "mirror"]
+    );
+    assert!(matches!(scheduler.wait(failed), Some(JobStatus::Failed(_))));
+    assert_eq!(
+        scheduler.wait(panicked),
+        Some(JobStatus::Failed("oops".to_owned()))
+    );
+
+    scheduler.forget_done();
+    assert!(scheduler.jobs().is_empty());
+    assert_eq!(scheduler.status(blocker), None);
+}
+
+#[test]
+fn test_scheduler_cancel() {
+    let scheduler = Scheduler::new(SchedulerOptions::default().workers(1));
+
+    let (started, running) = mpsc::channel::<()>();
+    let long = scheduler.submit("long", JobPriority::Normal, move |context| {
+        started.send(()).unwrap();
+        while !context.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    });
+    let queued = scheduler.submit("queued", JobPriority::Normal, |_| panic!("never started"));
+
+    running.recv().unwrap();
+    assert_eq!(scheduler.status(long), Some(JobStatus::Running));
+    assert!(scheduler.cancel(queued));
+    assert_eq!(scheduler.status(queued), Some(JobStatus::Cancelled));
+    assert!(scheduler.cancel(long));
+    assert_eq!(scheduler.wait(long), Some(JobStatus::Cancelled));
+
+    // nothing left to cancel
+    assert!(!scheduler.cancel(long));
+    assert!(!scheduler.cancel(1000));
+}
+
+#[test]
+fn test_scheduler_background_interval() {
+    let interval = Duration::from_millis(50);
+    let scheduler = Scheduler::new(
+        SchedulerOptions::default()
+            .workers(2)
+            .background_interval(interval),
+    );
+
+    let start = Instant::now();
+    let jobs: Vec<_> = (0..3)
+        .map(|i| scheduler.submit(format!("mirror {}", i), JobPriority::Background, |_| Ok(())))
+        .collect();
+    for id in jobs {
+        assert_eq!(scheduler.wait(id), Some(JobStatus::Finished));
+    }
+    assert!(start.elapsed() >= interval * 2);
+}
+
+#[test]
+fn test_scheduler_runs_on_runtime() {
+    let scheduler = Scheduler::new(SchedulerOptions::default());
+
+    // the jobs run on the threads of the runtime rather than on threads of the scheduler's
+    let job = scheduler.submit("count", JobPriority::Normal, |_| {
+        assert!(current_runtime().busy_threads() >= 1);
+        Ok(())
+    });
+    assert_eq!(scheduler.wait(job), Some(JobStatus::Finished));
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    scrub_repository, MetadataError, Repository, RepositoryOptions, RepositoryWriter, ScrubAction,
+    ScrubOptions, UpdateRecordBuilder,
+};
+use tempdir::TempDir;
+
+mod common;
+
+#[test]
+fn test_scrub_package() -> Result<(), MetadataError> {
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_packager("Michael Bluth <michael@bluthcompany.com>");
+
+    let mut stripped = package.clone();
+    ScrubOptions::all(ScrubAction::Strip).scrub_package(&mut stripped)?;
+    assert_eq!(stripped.packager(), "Michael Bluth");
+    assert_eq!(stripped.rpm_buildhost(), "");
+    assert_eq!(stripped.changelogs()[0].author, "Lucille Bluth - 1.1.1-1");
+    assert_eq!(
+        stripped.changelogs()[0].description,
+        package.changelogs()[0].description
+    );
+    assert_eq!(stripped.name(), package.name());
+
+    let options = ScrubOptions::all(ScrubAction::Hash).salt("secret");
+    let mut hashed = package.clone();
+    options.scrub_package(&mut hashed)?;
+    let packager = hashed.packager().to_owned();
+    assert!(packager.starts_with("Michael Bluth <"), "{}", packager);
+    assert!(packager.ends_with("@scrubbed.invalid>"), "{}", packager);
+    assert!(!packager.contains("bluthcompany"));
+    assert_eq!(hashed.rpm_buildhost().len(), 16);
+    assert_ne!(hashed.rpm_buildhost(), "localhost");
+
+    // the same address is hashed the same way, as long as the salt is the same
+    let mut again = package.clone();
+    options.scrub_package(&mut again)?;
+    assert_eq!(again.packager(), packager);
+    let mut other_salt = package.clone();
+    ScrubOptions::all(ScrubAction::Hash)
+        .salt("other")
+        .scrub_package(&mut other_salt)?;
+    assert_ne!(other_salt.packager(), packager);
+
+    // only what's asked for is scrubbed
+    let mut buildhost_only = package.clone();
+    ScrubOptions::default()
+        .buildhosts(ScrubAction::Strip)
+        .scrub_package(&mut buildhost_only)?;
+    assert_eq!(buildhost_only.packager(), package.packager());
+    assert_eq!(buildhost_only.changelogs(), package.changelogs());
+    assert_eq!(buildhost_only.rpm_buildhost(), "");
+
+    Ok(())
+}
+
+#[test]
+fn test_scrub_repository() -> Result<(), MetadataError> {
+    let source = TempDir::new("test_scrub_repository_source")?;
+    let destination = TempDir::new("test_scrub_repository_destination")?;
+
+    let mut writer = RepositoryWriter::new(source.path(), 1)?;
+    writer.add_package(&common::COMPLEX_PACKAGE)?;
+    let advisory = UpdateRecordBuilder::new()
+        .id("HOTFIX-2023:0001")
+        .title("a hotfix")
+        .from("release-engineering@example.com")
+        .update_type("bugfix")
+        .issued_date("1683023400")
+        .build()?;
+    writer.add_advisory(&advisory)?;
+    writer.finish()?;
+
+    let scrub = ScrubOptions::all(ScrubAction::Strip);
+    let report = scrub_repository(
+        source.path(),
+        destination.path(),
+        &scrub,
+        RepositoryOptions::default(),
+    )?;
+    assert!(!report.changed.is_empty());
+
+    let repo = Repository::load_from_directory(destination.path())?;
+    assert_eq!(repo.packages().len(), 1);
+    let package = repo.packages().values().next().unwrap();
+    assert_eq!(package.rpm_buildhost(), "");
+    assert!(package
+        .changelogs()
+        .iter()
+        .all(|changelog| !changelog.author.contains('@')));
+    assert_eq!(package.name(), common::COMPLEX_PACKAGE.name());
+    assert_eq!(package.files(), common::COMPLEX_PACKAGE.files());
+    let advisory = repo.advisories().values().next().unwrap();
+    assert_eq!(advisory.from, "");
+    assert_eq!(advisory.id, "HOTFIX-2023:0001");
+
+    Ok(())
+}
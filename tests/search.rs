@@ -0,0 +1,100 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::search::{SearchField, SearchHit};
+use rpmrepo_metadata::Repository;
+
+mod common;
+
+fn fixture_repo() -> Repository {
+    common::repository(
+        [
+            (
+                "python3-requests",
+                "HTTP library, written in Python, for human beings",
+                "Most existing Python modules for sending HTTP requests are extremely verbose.",
+            ),
+            (
+                "python3",
+                "Python 3 interpreter",
+                "Python is an accessible, high-level, dynamically typed, interpreted language.",
+            ),
+            (
+                "curl",
+                "A utility for getting files from remote servers (FTP, HTTP, and others)",
+                "curl is a command line tool for transferring data with URL syntax.",
+            ),
+            (
+                "httpie",
+                "A Curl-like tool for humans",
+                "HTTPie is a CLI HTTP utility built out of frustration with existing tools.",
+            ),
+        ]
+        .map(|(name, summary, description)| {
+            common::package_builder(name, "1.0", "noarch")
+                .summary(summary)
+                .description(description)
+                .build()
+                .unwrap()
+        }),
+    )
+}
+
+fn names(hits: &[SearchHit]) -> Vec<String> {
+    hits.iter()
+        .map(|hit| hit.package.name().to_owned())
+        .collect()
+}
+
+#[test]
+fn test_search() {
+    let repo = fixture_repo();
+    let index = repo.search_index();
+    assert_eq!(index.len(), 4);
+
+    // the package named like the query first, then matches in names, then summaries
+    let hits = index.search("python3");
+    assert_eq!(names(&hits), ["python3", "python3-requests"]);
+    assert_eq!(hits[1].fields, [SearchField::Name]);
+
+    let hits = index.search("curl");
+    assert_eq!(names(&hits), ["curl", "httpie"]);
+    assert_eq!(
+        hits[0].fields,
+        [SearchField::Name, SearchField::Description]
+    );
+    assert_eq!(hits[1].fields, [SearchField::Summary]);
+
+    // words of the query are prefixes, matched regardless of case, and must all match
+    assert_eq!(
+        names(&index.search("HTTP human")),
+        ["httpie", "python3-requests"]
+    );
+    assert_eq!(names(&index.search("interp")), ["python3"]);
+    assert!(index.search("python curl").is_empty());
+    assert!(index.search("  --- ").is_empty());
+    assert!(index.search("zsh").is_empty());
+}
+
+#[test]
+fn test_search_fields() {
+    let repo = fixture_repo();
+    let index = repo.search_index();
+
+    // like `dnf search` without `--all`
+    let fields = [SearchField::Name, SearchField::Summary];
+    assert_eq!(
+        names(&index.search_fields("verbose", &fields)),
+        Vec::<String>::new()
+    );
+    assert_eq!(names(&index.search("verbose")), ["python3-requests"]);
+    assert_eq!(names(&index.search_fields("utility", &fields)), ["curl"]);
+    assert_eq!(
+        names(&index.search_fields("utility", &[SearchField::Description])),
+        ["httpie"]
+    );
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{AdvisorySeverity, Package, Repository, UpdateRecord, UpdateReference, EVR};
+
+mod common;
+
+use common::package;
+
+fn advisory(id: &str, update_type: &str, severity: &str, packages: &[&Package]) -> UpdateRecord {
+    UpdateRecord::builder()
+        .id(id)
+        .title(format!("{} update", id))
+        .from("updates@fedoraproject.org")
+        .update_type(update_type)
+        .severity(severity)
+        .issued_date("2023-04-18 00:00:00")
+        .updated_date("2023-04-18 00:00:00")
+        .reference(UpdateReference {
+            href: format!("https://bugzilla.example.com/{}", id),
+            id: format!("BZ-{}", id),
+            title: "A bug".to_owned(),
+            reftype: "bugzilla".to_owned(),
+        })
+        .reference(UpdateReference {
+            href: format!("https://cve.example.com/{}", id),
+            id: format!("CVE-{}", id),
+            title: "A vulnerability".to_owned(),
+            reftype: "cve".to_owned(),
+        })
+        .packages("F38", "Fedora 38", packages.iter().copied())
+        .build()
+        .unwrap()
+}
+
+fn fixture_repo() -> Repository {
+    let openssl = package("openssl", "3.0.9", "x86_64");
+    let curl = package("curl", "8.0.1", "x86_64");
+    let vim = package("vim", "9.0.1", "x86_64");
+    let advisories = [
+        advisory("2023-0001", "security", "Important", &[&openssl]),
+        advisory("2023-0002", "security", "critical", &[&curl]),
+        advisory("2023-0003", "security", "Low", &[&vim]),
+        advisory("2023-0004", "bugfix", "Critical", &[&vim]),
+        // fixed in a version the repository doesn't have
+        advisory(
+            "2023-0005",
+            "security",
+            "Critical",
+            &[&package("bash", "5.2.21", "x86_64")],
+        ),
+    ];
+    let mut repo = common::repository([
+        openssl,
+        package("curl", "8.1.0", "x86_64"),
+        vim,
+        package("bash", "5.2.15", "x86_64"),
+    ]);
+    for advisory in advisories {
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+    repo
+}
+
+#[test]
+fn test_advisory_severity() {
+    assert_eq!(
+        AdvisorySeverity::parse("important"),
+        Some(AdvisorySeverity::Important)
+    );
+    assert_eq!(AdvisorySeverity::parse(""), Some(AdvisorySeverity::None));
+    assert_eq!(AdvisorySeverity::parse("urgent"), None);
+    assert!(AdvisorySeverity::Critical > AdvisorySeverity::Moderate);
+}
+
+#[test]
+fn test_security_updates() {
+    let repo = fixture_repo();
+    let installed = [
+        package("openssl", "3.0.8", "x86_64"),
+        package("curl", "7.88.1", "x86_64"),
+        package("vim", "9.0.0", "x86_64"),
+        package("bash", "5.2.15", "x86_64"),
+    ];
+
+    let feed = repo.security_updates(&installed, AdvisorySeverity::None);
+    let updates: Vec<(&str, AdvisorySeverity)> = feed
+        .updates
+        .iter()
+        .map(|update| (update.id.as_str(), update.severity))
+        .collect();
+    assert_eq!(
+        updates,
+        [
+            ("2023-0002", AdvisorySeverity::Critical),
+            ("2023-0001", AdvisorySeverity::Important),
+            ("2023-0003", AdvisorySeverity::Low),
+        ]
+    );
+    let curl = &feed.updates[0];
+    assert_eq!(curl.cves, ["CVE-2023-0002"]);
+    assert_eq!(curl.packages.len(), 1);
+    assert_eq!(curl.packages[0].installed, EVR::new("0", "7.88.1", "1"));
+    assert_eq!(curl.packages[0].fixed, EVR::new("0", "8.0.1", "1"));
+    assert_eq!(curl.packages[0].available, EVR::new("0", "8.1.0", "1"));
+    assert_eq!(feed.counts().get(&AdvisorySeverity::Important), Some(&1));
+
+    let feed = repo.security_updates(&installed, AdvisorySeverity::Important);
+    let ids: Vec<&str> = feed
+        .updates
+        .iter()
+        .map(|update| update.id.as_str())
+        .collect();
+    assert_eq!(ids, ["2023-0002", "2023-0001"]);
+
+    // packages which are already fixed aren't updated
+    let installed = [
+        package("openssl", "3.0.9", "x86_64"),
+        package("curl", "8.1.0", "x86_64"),
+    ];
+    assert!(repo
+        .security_updates(&installed, AdvisorySeverity::None)
+        .is_empty());
+}
+
+#[cfg(feature = "errata")]
+#[test]
+fn test_security_feed_json() {
+    let repo = fixture_repo();
+    let installed = [package("openssl", "3.0.8", "x86_64")];
+    let feed = repo.security_updates(&installed, AdvisorySeverity::Moderate);
+    assert_eq!(
+        feed.to_json(),
+        serde_json::json!({
+            "updates": [{
+                "id": "2023-0001",
+                "title": "2023-0001 update",
+                "severity": "Important",
+                "issued_date": "2023-04-18 00:00:00",
+                "cves": ["CVE-2023-0001"],
+                "packages": [{
+                    "name": "openssl",
+                    "arch": "x86_64",
+                    "installed": "0:3.0.8-1",
+                    "fixed": "0:3.0.9-1",
+                    "available": "0:3.0.9-1",
+                }],
+            }],
+        })
+    );
+}
@@ -0,0 +1,388 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Tests for the createrepo_c sqlite databases. The XML they're converted from and back to, and the schema
+//! they're compared against, are createrepo_c output in `tests/assets/createrepo_c/`, see `generate.py`
+//! there.
+
+extern crate rpmrepo_metadata;
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::*;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use tempdir::TempDir;
+
+static CREATEREPO_C_PRIMARY: &str = include_str!("assets/createrepo_c/primary.xml");
+static CREATEREPO_C_FILELISTS: &str = include_str!("assets/createrepo_c/filelists.xml");
+static CREATEREPO_C_OTHER: &str = include_str!("assets/createrepo_c/other.xml");
+
+/// The schema of the databases createrepo_c writes, as sqlite stores it in `sqlite_master`.
+static CREATEREPO_C_PRIMARY_SCHEMA: &str = include_str!("assets/createrepo_c/primary.sqlite.sql");
+static CREATEREPO_C_FILELISTS_SCHEMA: &str =
+    include_str!("assets/createrepo_c/filelists.sqlite.sql");
+static CREATEREPO_C_OTHER_SCHEMA: &str = include_str!("assets/createrepo_c/other.sqlite.sql");
+
+/// Write the createrepo_c XML metadata to `dir`, and convert it to databases compressed with
+/// `compression`. Returns the paths of the primary, filelists and other databases.
+fn write_databases(
+    dir: &Path,
+    compression: CompressionType,
+) -> Result<[PathBuf; 3], MetadataError> {
+    let mut paths = Vec::new();
+    for (metadata_type, contents) in [
+        (MetadataType::Primary, CREATEREPO_C_PRIMARY),
+        (MetadataType::Filelists, CREATEREPO_C_FILELISTS),
+        (MetadataType::Other, CREATEREPO_C_OTHER),
+    ] {
+        let source = dir.join(metadata_type.file_name(CompressionType::None));
+        std::fs::write(&source, contents)?;
+        let record = transcode_metadata_file(
+            &source,
+            &metadata_type,
+            MetadataFormat::Sqlite(compression),
+            ChecksumType::Sha256,
+        )?;
+        assert_eq!(record.database_version, Some(DATABASE_VERSION));
+        paths.push(dir.join(record.location_href.file_name().unwrap()));
+    }
+    Ok(paths.try_into().unwrap())
+}
+
+/// The metadata of `repo` written the way createrepo_c writes it.
+fn write_xml(repo: &Repository) -> Result<[String; 3], MetadataError> {
+    let packages = repo.packages();
+
+    let mut writer = PrimaryXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(XmlStyle::CreaterepoC);
+    writer.write_header(packages.len())?;
+    for package in packages.values() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    let primary = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+
+    let mut writer = FilelistsXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(XmlStyle::CreaterepoC);
+    writer.write_header(packages.len())?;
+    for package in packages.values() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    let filelists = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+
+    let mut writer = OtherXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.set_style(XmlStyle::CreaterepoC);
+    writer.write_header(packages.len())?;
+    for package in packages.values() {
+        writer.write_package(package)?;
+    }
+    writer.finish()?;
+    let other = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+
+    Ok([primary, filelists, other])
+}
+
+fn open(path: &Path) -> Result<Connection, MetadataError> {
+    Ok(Connection::open(path)?)
+}
+
+/// Every row of every table of the database at `path`.
+fn rows(path: &Path) -> Result<Vec<(String, Vec<Value>)>, MetadataError> {
+    let connection = open(path)?;
+    let tables: Vec<String> = connection
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let mut rows = Vec::new();
+    for table in tables {
+        let mut statement =
+            connection.prepare(&format!("SELECT * FROM {} ORDER BY rowid", table))?;
+        let columns = statement.column_count();
+        let mut query = statement.query([])?;
+        while let Some(row) = query.next()? {
+            let values = (0..columns)
+                .map(|column| row.get(column))
+                .collect::<Result<_, _>>()?;
+            rows.push((table.clone(), values));
+        }
+    }
+    Ok(rows)
+}
+
+#[test]
+fn test_sqlite_schema() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_sqlite_schema")?;
+    let paths = write_databases(tmp_dir.path(), CompressionType::None)?;
+
+    for (path, expected) in paths.iter().zip([
+        CREATEREPO_C_PRIMARY_SCHEMA,
+        CREATEREPO_C_FILELISTS_SCHEMA,
+        CREATEREPO_C_OTHER_SCHEMA,
+    ]) {
+        let connection = open(path)?;
+        let schema: Vec<String> = connection
+            .prepare("SELECT sql FROM sqlite_master ORDER BY rowid")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let schema: String = schema.iter().map(|sql| format!("{};\n", sql)).collect();
+        assert_eq!(schema, expected, "{}", path.display());
+
+        let (version, checksum): (u32, String) =
+            connection.query_row("SELECT dbversion, checksum FROM db_info", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        assert_eq!(version, 10);
+        // the checksum of the XML the database was converted from
+        let xml = path.with_extension("xml");
+        assert_eq!(
+            Checksum::Sha256(checksum),
+            utils::checksum_file(&xml, ChecksumType::Sha256)?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_rows() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_sqlite_rows")?;
+    let [primary, filelists, other] = write_databases(tmp_dir.path(), CompressionType::None)?;
+
+    let connection = open(&primary)?;
+    let package: (i64, String, String, String, String, Option<String>, String) = connection
+        .query_row(
+            "SELECT pkgKey, pkgId, name, epoch, checksum_type, location_base, rpm_packager FROM packages WHERE name = 'rpm-with-special-chars'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)),
+        )?;
+    assert_eq!(
+        package,
+        (
+            2,
+            "0a8a2b3d5f8c3a0ba6b3d42e74ab83e0c2f9c71a44342ba3fc38b2d5f24f22ab".to_owned(),
+            "rpm-with-special-chars".to_owned(),
+            "0".to_owned(),
+            "sha256".to_owned(),
+            Some("https://example.com/packages/".to_owned()),
+            "Zoë <zoe@example.com>".to_owned(),
+        )
+    );
+    let requires: Vec<(String, Option<String>, String)> = connection
+        .prepare("SELECT name, flags, pre FROM requires WHERE pkgKey = 1 ORDER BY rowid")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        requires[1],
+        ("/usr/sbin/useradd".to_owned(), None, "TRUE".to_owned())
+    );
+    assert_eq!(requires[2].2, "FALSE");
+    // only the files which primary.xml lists
+    let files: Vec<(String, String)> = connection
+        .prepare("SELECT name, type FROM files ORDER BY rowid")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        files,
+        vec![
+            ("/etc/complex/pkg.cfg".to_owned(), "file".to_owned()),
+            ("/usr/bin/complex_a".to_owned(), "file".to_owned()),
+        ]
+    );
+
+    let connection = open(&filelists)?;
+    let dirs: Vec<(i64, String, String, String)> = connection
+        .prepare("SELECT pkgKey, dirname, filenames, filetypes FROM filelist ORDER BY rowid")?
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        dirs[0],
+        (
+            1,
+            "/etc/complex".to_owned(),
+            "pkg.cfg".to_owned(),
+            "f".to_owned()
+        )
+    );
+    assert_eq!(
+        dirs[2],
+        (
+            1,
+            "/usr/share/doc".to_owned(),
+            "complex-package".to_owned(),
+            "d".to_owned()
+        )
+    );
+    assert_eq!(
+        dirs[dirs.len() - 2],
+        (
+            2,
+            "/usr/share".to_owned(),
+            "spëcial".to_owned(),
+            "d".to_owned()
+        )
+    );
+
+    let connection = open(&other)?;
+    let changelogs: i64 =
+        connection.query_row("SELECT count(*) FROM changelog", [], |row| row.get(0))?;
+    assert_eq!(
+        changelogs,
+        CREATEREPO_C_OTHER.matches("<changelog ").count() as i64
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_to_xml() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_sqlite_to_xml")?;
+    // compressed databases are decompressed to be read
+    let [primary, filelists, other] = write_databases(tmp_dir.path(), CompressionType::Bz2)?;
+    assert_eq!(
+        primary.file_name().unwrap().to_str(),
+        Some("primary.sqlite.bz2")
+    );
+
+    let repo = Repository::load_from_sqlite_databases(&primary, Some(&filelists), Some(&other))?;
+    let [primary_xml, filelists_xml, other_xml] = write_xml(&repo)?;
+    assert_eq!(primary_xml, CREATEREPO_C_PRIMARY);
+    assert_eq!(filelists_xml, CREATEREPO_C_FILELISTS);
+    assert_eq!(other_xml, CREATEREPO_C_OTHER);
+
+    // without filelists.sqlite, packages only have the files primary.sqlite lists
+    let repo = Repository::load_from_sqlite_databases(&primary, None, None)?;
+    let [primary_xml, _, other_xml] = write_xml(&repo)?;
+    assert_eq!(primary_xml, CREATEREPO_C_PRIMARY);
+    assert!(!other_xml.contains("<changelog"));
+    assert_eq!(
+        repo.packages()
+            .values()
+            .map(|p| p.files().len())
+            .collect::<Vec<_>>(),
+        vec![2, 0]
+    );
+
+    assert_eq!(
+        std::fs::read_dir(std::env::temp_dir())?
+            .filter_map(Result::ok)
+            .filter(|entry| entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&format!("rpmrepo-sqlite-{}-", std::process::id())))
+            .count(),
+        0,
+        "the decompressed databases are removed"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_xml_to_sqlite_roundtrip() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_xml_to_sqlite_roundtrip")?;
+    let [primary, filelists, other] = write_databases(tmp_dir.path(), CompressionType::None)?;
+    let repo = Repository::load_from_sqlite_databases(&primary, Some(&filelists), Some(&other))?;
+
+    // the databases converted from the XML written from the databases are the same
+    let out_dir = TempDir::new("test_xml_to_sqlite_roundtrip_out")?;
+    for (metadata_type, xml) in [
+        MetadataType::Primary,
+        MetadataType::Filelists,
+        MetadataType::Other,
+    ]
+    .into_iter()
+    .zip(write_xml(&repo)?)
+    {
+        let source = out_dir
+            .path()
+            .join(metadata_type.file_name(CompressionType::None));
+        std::fs::write(&source, xml)?;
+        transcode_metadata_file(
+            &source,
+            &metadata_type,
+            MetadataFormat::Sqlite(CompressionType::None),
+            ChecksumType::Sha256,
+        )?;
+    }
+    for path in [primary, filelists, other] {
+        let roundtrip = out_dir.path().join(path.file_name().unwrap());
+        assert_eq!(rows(&roundtrip)?, rows(&path)?, "{}", path.display());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_transcode() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_sqlite_transcode")?;
+    let [primary, ..] = write_databases(tmp_dir.path(), CompressionType::None)?;
+
+    // databases are recompressed as they are
+    let record = transcode_metadata_file(
+        &primary,
+        &MetadataType::PrimaryDb,
+        MetadataFormat::Sqlite(CompressionType::Xz),
+        ChecksumType::Sha256,
+    )?;
+    assert_eq!(record.metadata_type, MetadataType::PrimaryDb);
+    assert_eq!(record.database_version, Some(DATABASE_VERSION));
+    let mut decompressed = Vec::new();
+    utils::reader_from_file(&tmp_dir.path().join("primary.sqlite.xz"))?
+        .read_to_end(&mut decompressed)?;
+    assert_eq!(decompressed, std::fs::read(&primary)?);
+
+    // but can't be converted to XML on their own
+    assert!(matches!(
+        transcode_metadata_file(
+            &primary,
+            &MetadataType::PrimaryDb,
+            MetadataFormat::Xml(CompressionType::Gzip),
+            ChecksumType::Sha256
+        ),
+        Err(MetadataError::InvalidFieldError("xml metadata type", name)) if name == "primary_db"
+    ));
+    assert!(matches!(
+        transcode_metadata_file(
+            &tmp_dir.path().join("primary.xml"),
+            &MetadataType::Updateinfo,
+            MetadataFormat::Sqlite(CompressionType::None),
+            ChecksumType::Sha256
+        ),
+        Err(MetadataError::InvalidFieldError("sqlite metadata type", name)) if name == "updateinfo"
+    ));
+    assert!(matches!(
+        SqliteWriter::new(&tmp_dir.path().join("group.sqlite"), &MetadataType::Group),
+        Err(MetadataError::InvalidFieldError("sqlite metadata type", _))
+    ));
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs the databases written by tests/assets/createrepo_c/generate.py"]
+fn test_createrepo_c_databases() -> Result<(), MetadataError> {
+    let databases =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/assets/createrepo_c/databases");
+    let [primary, filelists, other] =
+        ["primary", "filelists", "other"].map(|name| databases.join(format!("{}.sqlite", name)));
+
+    // the databases createrepo_c writes are read back to the XML it writes
+    let repo = Repository::load_from_sqlite_databases(&primary, Some(&filelists), Some(&other))?;
+    let [primary_xml, filelists_xml, other_xml] = write_xml(&repo)?;
+    assert_eq!(primary_xml, CREATEREPO_C_PRIMARY);
+    assert_eq!(filelists_xml, CREATEREPO_C_FILELISTS);
+    assert_eq!(other_xml, CREATEREPO_C_OTHER);
+
+    // and have the same rows as the ones converted from the XML
+    let tmp_dir = TempDir::new("test_createrepo_c_databases")?;
+    for (path, createrepo_c) in write_databases(tmp_dir.path(), CompressionType::None)?
+        .iter()
+        .zip([primary, filelists, other])
+    {
+        assert_eq!(rows(path)?, rows(&createrepo_c)?, "{}", path.display());
+    }
+    Ok(())
+}
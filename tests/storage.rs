@@ -0,0 +1,395 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    export_bundle, import_bundle, open_archive, utils, BundleOptions, ChecksumType, MetadataError,
+    Package, Repository, RepositoryOptions, RepositoryReader, Storage, TarStorage, VerifyOptions,
+};
+use tempdir::TempDir;
+
+mod common;
+
+/// The directory of the repository inside the archives, long enough for GNU long names in tar
+const ARCHIVE_DIR: &str = "offline-media/repositories/BaseOS/x86_64";
+
+/// Write a repository with package files to `path`.
+fn write_repository(path: &Path) -> Result<Repository, MetadataError> {
+    write_packages(path, &[&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY])
+}
+
+/// Write a repository of `packages` to `path`, with package files named after their index.
+fn write_packages(path: &Path, packages: &[&Package]) -> Result<Repository, MetadataError> {
+    let mut repo = Repository::new();
+    for (index, package) in packages.iter().enumerate() {
+        let mut package = (*package).clone();
+        let href = format!("Packages/{}.rpm", index);
+        let contents = vec![index as u8; 5000 + index];
+        fs::create_dir_all(path.join("Packages"))?;
+        fs::write(path.join(&href), &contents)?;
+        package
+            .set_location_href(href)
+            .set_checksum(utils::checksum_bytes(&contents, ChecksumType::Sha256)?)
+            .set_size_package(contents.len() as u64);
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+    let options = RepositoryOptions::default().simple_metadata_filenames(false);
+    repo.write_to_directory_with_options(path, options)?;
+    Ok(repo)
+}
+
+/// The files under `dir`, by their `/`-separated paths relative to it.
+fn list_files(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap()).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().is_dir() {
+            for (path, full) in list_files(&entry.path()) {
+                files.push((format!("{}/{}", name, path), full));
+            }
+        } else {
+            files.push((name, entry.path()));
+        }
+    }
+    files
+}
+
+/// A tar archive of the files under `dir`, in `ARCHIVE_DIR`.
+fn tar_archive(dir: &Path) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, path) in list_files(dir) {
+        let name = format!("{}/{}", ARCHIVE_DIR, name);
+        if name.len() > 100 {
+            let long_name = format!("{}\0", name);
+            let header = common::tar_header("././@LongLink", long_name.len(), b'L');
+            common::tar_entry(&mut archive, header, long_name.as_bytes());
+        }
+        let data = fs::read(path).unwrap();
+        common::tar_entry(
+            &mut archive,
+            common::tar_header(&name, data.len(), b'0'),
+            &data,
+        );
+    }
+    archive.extend([0u8; 1024]);
+    archive
+}
+
+const SECTOR: usize = 2048;
+
+enum IsoEntry {
+    Dir(usize),
+    File(Vec<u8>),
+}
+
+struct IsoDir {
+    parent: usize,
+    entries: Vec<(String, IsoEntry)>,
+}
+
+fn add_iso_dir(dirs: &mut Vec<IsoDir>, parent: usize, path: &Path) -> usize {
+    let index = dirs.len();
+    dirs.push(IsoDir {
+        parent,
+        entries: Vec::new(),
+    });
+    let mut children: Vec<_> = fs::read_dir(path).unwrap().map(|e| e.unwrap()).collect();
+    children.sort_by_key(|entry| entry.file_name());
+    for child in children {
+        let name = child.file_name().to_string_lossy().into_owned();
+        let entry = match child.path().is_dir() {
+            true => IsoEntry::Dir(add_iso_dir(dirs, index, &child.path())),
+            false => IsoEntry::File(fs::read(child.path()).unwrap()),
+        };
+        dirs[index].entries.push((name, entry));
+    }
+    index
+}
+
+/// A directory record, with a Rock Ridge name if `rock_ridge` is given.
+fn iso_record(name: &[u8], sector: usize, size: usize, dir: bool, rock_ridge: &[u8]) -> Vec<u8> {
+    let mut record = vec![0u8; 33];
+    record[2..6].copy_from_slice(&(sector as u32).to_le_bytes());
+    record[6..10].copy_from_slice(&(sector as u32).to_be_bytes());
+    record[10..14].copy_from_slice(&(size as u32).to_le_bytes());
+    record[14..18].copy_from_slice(&(size as u32).to_be_bytes());
+    record[25] = if dir { 2 } else { 0 };
+    record[28] = 1;
+    record[31] = 1;
+    record[32] = name.len() as u8;
+    record.extend(name);
+    if name.len().is_multiple_of(2) {
+        record.push(0);
+    }
+    record.extend(rock_ridge);
+    if record.len() % 2 == 1 {
+        record.push(0);
+    }
+    record[0] = record.len() as u8;
+    record
+}
+
+/// The contents of directory `index`, given the sectors each directory and file starts at.
+fn iso_dir_contents(
+    dirs: &[IsoDir],
+    index: usize,
+    dir_sectors: &[(usize, usize)],
+    file_sectors: &[Vec<usize>],
+) -> Vec<u8> {
+    let (sector, size) = dir_sectors[index];
+    let (parent_sector, parent_size) = dir_sectors[dirs[index].parent];
+    // the root directory tells readers that the image has Rock Ridge entries
+    let sp: &[u8] = if index == 0 {
+        &[b'S', b'P', 7, 1, 0xbe, 0xef, 0]
+    } else {
+        &[]
+    };
+    let mut records = vec![
+        iso_record(&[0], sector, size, true, sp),
+        iso_record(&[1], parent_sector, parent_size, true, &[]),
+    ];
+    for (i, (name, entry)) in dirs[index].entries.iter().enumerate() {
+        let mut nm = vec![b'N', b'M', 5 + name.len() as u8, 1, 0];
+        nm.extend(name.as_bytes());
+        let plain = format!("F{}.;1", i);
+        records.push(match entry {
+            IsoEntry::Dir(child) => {
+                let (sector, size) = dir_sectors[*child];
+                iso_record(format!("D{}", i).as_bytes(), sector, size, true, &nm)
+            }
+            IsoEntry::File(data) => iso_record(
+                plain.as_bytes(),
+                file_sectors[index][i],
+                data.len(),
+                false,
+                &nm,
+            ),
+        });
+    }
+
+    let mut contents = Vec::new();
+    for record in records {
+        // records don't cross sectors
+        if contents.len() % SECTOR + record.len() > SECTOR {
+            contents.resize(contents.len().div_ceil(SECTOR) * SECTOR, 0);
+        }
+        contents.extend(record);
+    }
+    contents.resize(contents.len().div_ceil(SECTOR) * SECTOR, 0);
+    contents
+}
+
+/// An ISO 9660 image with Rock Ridge names of the files under `dir`, in `ARCHIVE_DIR`.
+fn iso_image(dir: &Path) -> Vec<u8> {
+    let staging = TempDir::new("iso_image").unwrap();
+    let root = staging.path().join(ARCHIVE_DIR);
+    fs::create_dir_all(&root).unwrap();
+    for (name, path) in list_files(dir) {
+        let target = root.join(name);
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::copy(path, target).unwrap();
+    }
+    let mut dirs = Vec::new();
+    add_iso_dir(&mut dirs, 0, staging.path());
+
+    // the directory sizes don't depend on where things are
+    let unplaced: Vec<Vec<usize>> = dirs.iter().map(|dir| vec![0; dir.entries.len()]).collect();
+    let sizes: Vec<usize> = (0..dirs.len())
+        .map(|index| iso_dir_contents(&dirs, index, &vec![(0, 0); dirs.len()], &unplaced).len())
+        .collect();
+    let mut next = 18;
+    let mut dir_sectors = Vec::new();
+    for size in sizes {
+        dir_sectors.push((next, size));
+        next += size / SECTOR;
+    }
+    let mut file_sectors = unplaced;
+    for (index, dir) in dirs.iter().enumerate() {
+        for (i, (_, entry)) in dir.entries.iter().enumerate() {
+            if let IsoEntry::File(data) = entry {
+                file_sectors[index][i] = next;
+                next += data.len().div_ceil(SECTOR).max(1);
+            }
+        }
+    }
+
+    let mut image = vec![0u8; next * SECTOR];
+    let primary = &mut image[16 * SECTOR..17 * SECTOR];
+    primary[0] = 1;
+    primary[1..6].copy_from_slice(b"CD001");
+    primary[6] = 1;
+    let (root_sector, root_size) = dir_sectors[0];
+    primary[156..190].copy_from_slice(&iso_record(&[0], root_sector, root_size, true, &[]));
+    let terminator = &mut image[17 * SECTOR..18 * SECTOR];
+    terminator[0] = 255;
+    terminator[1..6].copy_from_slice(b"CD001");
+    terminator[6] = 1;
+    for (index, dir) in dirs.iter().enumerate() {
+        let (sector, _) = dir_sectors[index];
+        let contents = iso_dir_contents(&dirs, index, &dir_sectors, &file_sectors);
+        image[sector * SECTOR..sector * SECTOR + contents.len()].copy_from_slice(&contents);
+        for (i, (_, entry)) in dir.entries.iter().enumerate() {
+            if let IsoEntry::File(data) = entry {
+                let start = file_sectors[index][i] * SECTOR;
+                image[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+    }
+    image
+}
+
+/// Check that the repository in `archive` reads, validates and verifies like the one it was made of.
+fn check_archive(archive: &Path, dir: &Path, repo: &Repository) -> Result<(), MetadataError> {
+    let local = RepositoryReader::new_from_directory(dir)?;
+    let reader =
+        RepositoryReader::new_from_storage(open_archive(archive)?, Path::new(ARCHIVE_DIR))?;
+    assert_eq!(reader.repomd(), local.repomd());
+    assert_eq!(reader.validate()?, local.validate()?);
+
+    let options = VerifyOptions::default().workers(2).readahead(4096);
+    let report = reader.verify_files(options)?;
+    assert!(report.is_ok());
+    assert_eq!(report.files.len(), local.verify_files(options)?.files.len());
+    assert_eq!(
+        report.bytes_hashed,
+        local.verify_files(options)?.bytes_hashed
+    );
+
+    assert_eq!(reader.iter_advisories()?.count(), 0);
+    assert_eq!(reader.into_repo()?.packages(), repo.packages());
+    Ok(())
+}
+
+#[test]
+fn test_read_tar_archive() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_read_tar_archive")?;
+    let dir = tmp_dir.path().join("repo");
+    let repo = write_repository(&dir)?;
+
+    let tar_path = tmp_dir.path().join("repo.tar");
+    fs::write(&tar_path, tar_archive(&dir))?;
+    check_archive(&tar_path, &dir, &repo)?;
+
+    let tar_gz_path = tmp_dir.path().join("repo.tar.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        fs::File::create(&tar_gz_path)?,
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&tar_archive(&dir))?;
+    encoder.finish()?;
+    check_archive(&tar_gz_path, &dir, &repo)?;
+
+    let storage = TarStorage::open(&tar_path)?;
+    let mut names = storage.list(&Path::new(ARCHIVE_DIR).join("Packages"))?;
+    names.sort();
+    assert_eq!(names, vec!["0.rpm", "1.rpm"]);
+    assert_eq!(
+        storage.size(&Path::new(ARCHIVE_DIR).join("Packages/1.rpm"))?,
+        5001
+    );
+    assert!(!storage.is_file(Path::new(ARCHIVE_DIR)));
+
+    fs::write(&tar_path, b"not a tar archive")?;
+    assert!(TarStorage::open(&tar_path).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_read_iso_image() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_read_iso_image")?;
+    let dir = tmp_dir.path().join("repo");
+    let repo = write_repository(&dir)?;
+
+    let iso_path = tmp_dir.path().join("repo.iso");
+    fs::write(&iso_path, iso_image(&dir))?;
+    check_archive(&iso_path, &dir, &repo)?;
+
+    let storage = open_archive(&iso_path)?;
+    let mut names = storage.list(&Path::new(ARCHIVE_DIR).join("Packages"))?;
+    names.sort();
+    assert_eq!(names, vec!["0.rpm", "1.rpm"]);
+    assert!(storage.is_file(&Path::new(ARCHIVE_DIR).join("repodata/repomd.xml")));
+    assert!(!storage.is_file(&Path::new(ARCHIVE_DIR).join("repodata")));
+    assert!(!storage.is_file(&Path::new(ARCHIVE_DIR).join("Packages/2.rpm")));
+
+    // a root directory which claims to be larger than the image
+    let mut image = iso_image(&dir);
+    let size = 16 * SECTOR + 156 + 10;
+    image[size..size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&iso_path, image)?;
+    assert!(open_archive(&iso_path).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_export_import_bundle() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_export_import_bundle")?;
+    let source = tmp_dir.path().join("source");
+    let dest = tmp_dir.path().join("dest");
+    let snapshot = write_repository(&source)?;
+    let options = BundleOptions::default().verification(VerifyOptions::default().workers(2));
+
+    let full = tmp_dir.path().join("full.tar");
+    let manifest = export_bundle(&source, &full, None, options)?;
+    assert!(!manifest.incremental);
+    assert!(manifest
+        .files
+        .iter()
+        .any(|file| file.path == Path::new("Packages/1.rpm")));
+    assert_eq!(import_bundle(&full, &dest, options)?, manifest);
+    let reader = RepositoryReader::new_from_directory(&dest)?;
+    assert!(reader.verify_files(VerifyOptions::default())?.is_ok());
+    assert_eq!(reader.into_repo()?.packages(), snapshot.packages());
+
+    // a new package, with the old ones left out of the bundle
+    let mut new_package = common::COMPLEX_PACKAGE.clone();
+    new_package.set_name("complex-package-ng");
+    let packages = [&*common::COMPLEX_PACKAGE, &*common::RPM_EMPTY, &new_package];
+    fs::remove_dir_all(&source)?;
+    let repo = write_packages(&source, &packages)?;
+    let incremental = tmp_dir.path().join("incremental.tar");
+    let manifest = export_bundle(&source, &incremental, Some(&snapshot), options)?;
+    assert!(manifest.incremental);
+    let bundled: Vec<_> = manifest
+        .files
+        .iter()
+        .filter(|file| file.path.starts_with("Packages"))
+        .map(|file| file.path.clone())
+        .collect();
+    assert_eq!(bundled, vec![PathBuf::from("Packages/2.rpm")]);
+
+    // the packages it relies on have to be in the destination
+    let elsewhere = tmp_dir.path().join("elsewhere");
+    assert!(import_bundle(&incremental, &elsewhere, options).is_err());
+    assert!(!elsewhere.join("repodata").exists());
+
+    import_bundle(&incremental, &dest, options)?;
+    let reader = RepositoryReader::new_from_directory(&dest)?;
+    assert!(reader.verify_files(VerifyOptions::default())?.is_ok());
+    assert_eq!(reader.into_repo()?.packages(), repo.packages());
+
+    // a corrupted bundle isn't imported
+    let mut contents = fs::read(&incremental)?;
+    let offset = contents
+        .windows(5000)
+        .position(|w| w == [2u8; 5000])
+        .unwrap();
+    contents[offset] = 3;
+    fs::write(&incremental, contents)?;
+    let result = import_bundle(&incremental, &elsewhere, options);
+    assert!(matches!(
+        result,
+        Err(MetadataError::ChecksumMismatchError(..))
+    ));
+    Ok(())
+}
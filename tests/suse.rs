@@ -0,0 +1,159 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    LoadOptions, LocalizedText, MetadataError, MetadataSelection, Pattern, PatternsXml, Product,
+    ProductsXml, Repository, Requirement, EVR,
+};
+use tempdir::TempDir;
+
+const PRODUCTS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<products>
+  <product>
+    <vendor>SUSE</vendor>
+    <name>SLES</name>
+    <version epoch="0" ver="15.4" rel="0"/>
+    <arch>x86_64</arch>
+    <summary>SUSE Linux Enterprise Server 15 SP4</summary>
+    <description>SUSE Linux Enterprise offers a comprehensive suite of products.</description>
+  </product>
+</products>
+"#;
+
+const PATTERNS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<patterns xmlns="http://novell.com/package/metadata/suse/pattern" xmlns:rpm="http://linux.duke.edu/metadata/rpm" count="1">
+  <pattern>
+    <name>apparmor</name>
+    <version epoch="0" ver="20170319" rel="89.1"/>
+    <arch>x86_64</arch>
+    <summary>AppArmor</summary>
+    <summary lang="de">AppArmor-Sicherheit</summary>
+    <description>AppArmor is an application security framework.</description>
+    <uservisible/>
+    <category lang="en">Base Technologies</category>
+    <icon>pattern-apparmor</icon>
+    <order>1140</order>
+    <rpm:provides>
+      <rpm:entry name="pattern()" flags="EQ" ver="apparmor"/>
+    </rpm:provides>
+    <rpm:requires>
+      <rpm:entry name="apparmor-parser"/>
+      <rpm:entry name="pattern()" flags="EQ" ver="minimal_base"/>
+    </rpm:requires>
+    <rpm:recommends>
+      <rpm:entry name="apparmor-utils"/>
+    </rpm:recommends>
+  </pattern>
+</patterns>
+"#;
+
+fn product() -> Product {
+    Product {
+        vendor: "SUSE".to_owned(),
+        name: "SLES".to_owned(),
+        evr: EVR::new("0", "15.4", "0"),
+        arch: "x86_64".to_owned(),
+        summary: "SUSE Linux Enterprise Server 15 SP4".to_owned(),
+        description: "SUSE Linux Enterprise offers a comprehensive suite of products.".to_owned(),
+    }
+}
+
+fn requirement(name: &str, version: Option<&str>) -> Requirement {
+    Requirement {
+        name: name.to_owned(),
+        flags: version.map(|_| "EQ".to_owned()),
+        version: version.map(str::to_owned),
+        ..Requirement::default()
+    }
+}
+
+fn pattern() -> Pattern {
+    Pattern {
+        name: "apparmor".to_owned(),
+        evr: EVR::new("0", "20170319", "89.1"),
+        arch: "x86_64".to_owned(),
+        summary: vec![
+            LocalizedText::new(None, "AppArmor"),
+            LocalizedText::new(Some("de"), "AppArmor-Sicherheit"),
+        ],
+        description: vec![LocalizedText::new(
+            None,
+            "AppArmor is an application security framework.",
+        )],
+        category: vec![LocalizedText::new(Some("en"), "Base Technologies")],
+        uservisible: true,
+        icon: Some("pattern-apparmor".to_owned()),
+        order: Some("1140".to_owned()),
+        rpm_provides: vec![requirement("pattern()", Some("apparmor"))],
+        rpm_requires: vec![
+            requirement("apparmor-parser", None),
+            requirement("pattern()", Some("minimal_base")),
+        ],
+        rpm_recommends: vec![requirement("apparmor-utils", None)],
+        ..Pattern::default()
+    }
+}
+
+#[test]
+fn test_products_xml() -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    repo.load_metadata_str::<ProductsXml>(PRODUCTS_XML)?;
+    assert_eq!(repo.products(), &[product()]);
+
+    let mut reloaded = Repository::new();
+    reloaded.load_metadata_str::<ProductsXml>(&repo.write_metadata_string::<ProductsXml>()?)?;
+    assert_eq!(reloaded.products(), repo.products());
+
+    Ok(())
+}
+
+#[test]
+fn test_patterns_xml() -> Result<(), MetadataError> {
+    let mut repo = Repository::new();
+    repo.load_metadata_str::<PatternsXml>(PATTERNS_XML)?;
+    assert_eq!(repo.patterns(), &[pattern()]);
+    assert_eq!(repo.patterns()[0].summary(), "AppArmor");
+    assert_eq!(repo.patterns()[0].category(), "Base Technologies");
+
+    let written = repo.write_metadata_string::<PatternsXml>()?;
+    assert!(written.contains(r#"count="1""#));
+    let mut reloaded = Repository::new();
+    reloaded.load_metadata_str::<PatternsXml>(&written)?;
+    assert_eq!(reloaded.patterns(), repo.patterns());
+
+    Ok(())
+}
+
+#[test]
+fn test_suse_repository() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_suse_repository")?;
+    let mut repo = Repository::new();
+    repo.products_mut().push(product());
+    repo.patterns_mut().push(pattern());
+    repo.write_to_directory(tmp_dir.path())?;
+
+    let loaded = Repository::load_from_directory(tmp_dir.path())?;
+    assert!(loaded.repomd().get_record("products").is_some());
+    assert!(loaded.repomd().get_record("patterns").is_some());
+    assert_eq!(loaded.products(), repo.products());
+    assert_eq!(loaded.patterns(), repo.patterns());
+
+    let options =
+        LoadOptions::default().metadata(MetadataSelection::PRIMARY | MetadataSelection::PRODUCTS);
+    let (loaded, _) = Repository::load_from_directory_with_options(tmp_dir.path(), options)?;
+    assert_eq!(loaded.products(), repo.products());
+    assert!(loaded.patterns().is_empty());
+
+    // repositories without them don't get the files
+    let tmp_dir = TempDir::new("test_suse_repository")?;
+    Repository::new().write_to_directory(tmp_dir.path())?;
+    let loaded = Repository::load_from_directory(tmp_dir.path())?;
+    assert!(loaded.repomd().get_record("products").is_none());
+    assert!(loaded.products().is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+
+use pretty_assertions::assert_eq;
+#[cfg(feature = "proptest")]
+use proptest::prelude::*;
+use rpmrepo_metadata::testing::RepositoryGenerator;
+use rpmrepo_metadata::*;
+use tempdir::TempDir;
+
+#[test]
+fn test_generate_repository() -> Result<(), MetadataError> {
+    let generator = RepositoryGenerator::default()
+        .packages(60)
+        .files_per_package(4)
+        .changelogs_per_package(2)
+        .advisories(5);
+    let repo = generator.generate();
+
+    assert_eq!(repo.packages().len(), 60);
+    assert_eq!(repo.advisories().len(), 5);
+    assert_eq!(generator.generate(), repo);
+    assert_ne!(generator.seed(1).generate(), repo);
+
+    let nevras: HashSet<String> = repo.packages().values().map(|p| p.nevra()).collect();
+    assert_eq!(nevras.len(), 60);
+    for package in repo.packages().values() {
+        assert!(package.files().len() >= 4, "{}", package.nevra());
+        assert_eq!(package.changelogs().len(), 2);
+        assert!(package.evr().validate().is_ok());
+    }
+    assert!(repo
+        .packages()
+        .values()
+        .any(|p| p.name().ends_with("-devel")));
+
+    // every advisory is for packages of the repository
+    for advisory in repo.advisories().values() {
+        assert!(advisory.packages().count() > 0);
+        for package in advisory.packages() {
+            assert!(
+                repo.packages()
+                    .values()
+                    .any(|p| p.location_href().ends_with(&package.filename)),
+                "{}",
+                package.filename
+            );
+        }
+    }
+
+    // the dependencies between packages resolve within the repository
+    let graph = DependencyGraph::from_repository(&repo, DependencyGraphOptions::default());
+    assert!(graph
+        .edges()
+        .iter()
+        .any(|edge| edge.capability.contains(".so.")));
+
+    let report = repo.validate()?;
+    assert!(report.is_valid(), "{}", report);
+
+    // and the repository survives being written out
+    let tmp_dir = TempDir::new("test_generate_repository")?;
+    repo.write_to_directory(tmp_dir.path())?;
+    let loaded = Repository::load_from_directory(tmp_dir.path())?;
+    assert_eq!(loaded.packages(), repo.packages());
+    assert_eq!(loaded.advisories(), repo.advisories());
+
+    Ok(())
+}
+
+#[cfg(feature = "proptest")]
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_proptest_evr(evr: EVR) {
+        prop_assert!(evr.validate().is_ok(), "{}", evr);
+        prop_assert_eq!(EVR::parse(&evr.to_string()), evr);
+    }
+
+    #[test]
+    fn test_proptest_roundtrip(
+        packages in prop::collection::vec(any::<Package>(), 0..20),
+        advisories in prop::collection::vec(any::<UpdateRecord>(), 0..5),
+    ) {
+        let repo = repository_of(packages, advisories);
+        let tmp_dir = TempDir::new("test_proptest_roundtrip")?;
+        repo.write_to_directory(tmp_dir.path())?;
+        let loaded = Repository::load_from_directory(tmp_dir.path())?;
+        prop_assert_eq!(loaded.packages(), repo.packages());
+        prop_assert_eq!(loaded.advisories(), repo.advisories());
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_arbitrary_roundtrip() -> Result<(), MetadataError> {
+    use arbitrary::Unstructured;
+
+    // a fuzzer's input, from which as many values are taken as it's long enough for
+    let mut state = 0u32;
+    let data: Vec<u8> = (0..64 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 24) as u8
+        })
+        .collect();
+    let mut u = Unstructured::new(&data);
+    let packages: Vec<Package> = (0..30).map(|_| u.arbitrary::<Package>().unwrap()).collect();
+    let advisories = (0..10)
+        .map(|_| u.arbitrary::<UpdateRecord>().unwrap())
+        .collect();
+    assert!(packages.iter().any(|p| !p.files().is_empty()));
+
+    // values can be made up even once the data has run out
+    let evr: EVR = Unstructured::new(&[]).arbitrary().unwrap();
+    assert!(evr.validate().is_ok());
+
+    let repo = repository_of(packages, advisories);
+    let tmp_dir = TempDir::new("test_arbitrary_roundtrip")?;
+    repo.write_to_directory(tmp_dir.path())?;
+    let loaded = Repository::load_from_directory(tmp_dir.path())?;
+    assert_eq!(loaded.packages(), repo.packages());
+    assert_eq!(loaded.advisories(), repo.advisories());
+
+    Ok(())
+}
+
+#[cfg(any(feature = "proptest", feature = "arbitrary"))]
+fn repository_of(packages: Vec<Package>, advisories: Vec<UpdateRecord>) -> Repository {
+    let mut repo = Repository::new();
+    for package in packages {
+        repo.packages_mut()
+            .insert(package.pkgid().to_owned(), package);
+    }
+    for advisory in advisories {
+        repo.advisories_mut().insert(advisory.id.clone(), advisory);
+    }
+    repo
+}
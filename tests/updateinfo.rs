@@ -9,7 +9,7 @@ extern crate rpmrepo_metadata;
 use pretty_assertions::assert_eq;
 use rpmrepo_metadata::UpdateRecord;
 use rpmrepo_metadata::*;
-use std::fs::{Metadata, OpenOptions};
+use std::fs::OpenOptions;
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use tempdir::TempDir;
 
@@ -24,13 +24,6 @@ static EMPTY_UPDATEINFO_NO_FOOTER: &str = r#"<?xml version="1.0" encoding="UTF-8
 <updates>
 "#;
 
-static EMPTY_UPDATEINFO_NO_DECL: &str = r#"
-<updates>
-</updates>
-"#;
-
-static COMPLEX_UPDATEINFO: &str = r#""#;
-
 #[test]
 fn test_updateinfo_xml_writer_empty() -> Result<(), MetadataError> {
     let mut writer = UpdateinfoXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
@@ -75,6 +68,7 @@ fn test_updateinfo_xml_writer_file() -> Result<(), MetadataError> {
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(other_name)
         .unwrap();
 
@@ -143,14 +137,14 @@ fn test_updateinfo_xml_read_updaterecord() -> Result<(), MetadataError> {
     let mut updateinfo_xml =
         UpdateinfoXml::new_reader(utils::create_xml_reader(EMPTY_UPDATEINFO.as_bytes()));
     // assert_eq!(updateinfo_xml.read_header()?, ());
-    assert!(matches!(updateinfo_xml.read_update()?, None));
+    assert!(updateinfo_xml.read_update()?.is_none());
 
     // Test that no updaterecords are parsed when there are no packages and the footer element doesn't exist (EOF)
     let mut updateinfo_xml = UpdateinfoXml::new_reader(utils::create_xml_reader(
         EMPTY_UPDATEINFO_NO_FOOTER.as_bytes(),
     ));
     // assert_eq!(updateinfo_xml.read_header()?, ());
-    assert!(matches!(updateinfo_xml.read_update()?, None));
+    assert!(updateinfo_xml.read_update()?.is_none());
 
     // // Test that an updaterecord is parsed correctly when there are updaterecords
     // let mut updateinfo_xml =
@@ -161,3 +155,265 @@ fn test_updateinfo_xml_read_updaterecord() -> Result<(), MetadataError> {
 
     Ok(())
 }
+
+/// The same advisory as written by different producers, which spell the flags of packages differently
+#[test]
+fn test_updateinfo_xml_read_flags() -> Result<(), MetadataError> {
+    let update = |id: &str, update_flags: &str, attributes: &str, package_flags: &str| {
+        format!(
+            r#"<update from="errata@example.com" status="final" type="security" version="1">
+    <id>{id}</id>
+    {update_flags}
+    <pkglist>
+      <collection short="el">
+        <name>EL</name>
+        <package name="kernel" version="5.14.0" release="1.el9" epoch="0" arch="x86_64" src="kernel-5.14.0-1.el9.src.rpm"{attributes}>
+          <filename>kernel-5.14.0-1.el9.x86_64.rpm</filename>
+          {package_flags}
+        </package>
+      </collection>
+    </pkglist>
+  </update>"#
+        )
+    };
+    let updates = [
+        update(
+            "createrepo_c",
+            "",
+            "",
+            "<reboot_suggested>True</reboot_suggested>",
+        ),
+        update(
+            "this-library",
+            "",
+            "",
+            "<reboot_suggested>1</reboot_suggested>",
+        ),
+        update("empty-element", "", "", "<reboot_suggested/>"),
+        update("attribute", "", r#" reboot_suggested="yes""#, ""),
+        update("yum", "<reboot_suggested>true</reboot_suggested>", "", ""),
+        update(
+            "unset",
+            "",
+            "",
+            "<reboot_suggested>False</reboot_suggested>",
+        ),
+    ];
+    let updateinfo = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<updates>\n  {}\n</updates>\n",
+        updates.join("\n  ")
+    );
+
+    let mut updateinfo_xml =
+        UpdateinfoXml::new_reader(utils::create_xml_reader(updateinfo.as_bytes()));
+    let mut flags = Vec::new();
+    while let Some(record) = updateinfo_xml.read_update()? {
+        let package = &record.pkglist[0].packages[0];
+        assert_eq!(package.filename, "kernel-5.14.0-1.el9.x86_64.rpm");
+        assert!(!package.restart_suggested && !package.relogin_suggested);
+        flags.push((record.id, package.reboot_suggested));
+    }
+    assert_eq!(
+        flags,
+        vec![
+            ("createrepo_c".to_owned(), true),
+            ("this-library".to_owned(), true),
+            ("empty-element".to_owned(), true),
+            ("attribute".to_owned(), true),
+            ("yum".to_owned(), true),
+            ("unset".to_owned(), false),
+        ]
+    );
+
+    Ok(())
+}
+
+/// An advisory shipped to several products, one of them as part of a module and one from a producer which
+/// doesn't name its collections
+static MULTI_COLLECTION_UPDATEINFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<updates>
+  <update status="final" from="errata@example.com" type="bugfix" version="1">
+    <id>EXAMPLE-2022:0001</id>
+    <title>nginx bug fix update</title>
+    <copyright></copyright>
+    <release></release>
+    <severity></severity>
+    <summary></summary>
+    <description>Fixes the thing</description>
+    <solution></solution>
+    <references/>
+    <pkglist>
+      <collection short="EL-9">
+        <name>Example Linux 9</name>
+        <package name="nginx" version="1.20.1" release="10.el9" epoch="1" arch="x86_64" src="nginx-1.20.1-10.el9.src.rpm">
+          <filename>nginx-1.20.1-10.el9.x86_64.rpm</filename>
+        </package>
+      </collection>
+      <collection short="EL-8-nginx-1.20">
+        <name>Example Linux 8 AppStream</name>
+        <module name="nginx" stream="1.20" version="8060020220301000000" context="a5c3afed" arch="x86_64"/>
+        <package name="nginx" version="1.20.1" release="1.module_el8" epoch="1" arch="x86_64" src="nginx-1.20.1-1.module_el8.src.rpm">
+          <filename>nginx-1.20.1-1.module_el8.x86_64.rpm</filename>
+          <restart_suggested>1</restart_suggested>
+        </package>
+        <package name="nginx-mod-mail" version="1.20.1" release="1.module_el8" epoch="1" arch="x86_64" src="nginx-1.20.1-1.module_el8.src.rpm">
+          <filename>nginx-mod-mail-1.20.1-1.module_el8.x86_64.rpm</filename>
+        </package>
+      </collection>
+      <collection>
+        <package name="nginx" version="1.20.1" release="10.el9" epoch="1" arch="aarch64" src="nginx-1.20.1-10.el9.src.rpm">
+          <filename>nginx-1.20.1-10.el9.aarch64.rpm</filename>
+        </package>
+      </collection>
+    </pkglist>
+  </update>
+</updates>
+"#;
+
+#[test]
+fn test_updateinfo_xml_multiple_collections() -> Result<(), MetadataError> {
+    let mut updateinfo_xml = UpdateinfoXml::new_reader(utils::create_xml_reader(
+        MULTI_COLLECTION_UPDATEINFO.as_bytes(),
+    ));
+    let record = updateinfo_xml.read_update()?.unwrap();
+    assert!(updateinfo_xml.read_update()?.is_none());
+
+    let collections: Vec<_> = record
+        .pkglist
+        .iter()
+        .map(|c| (c.shortname.as_deref(), c.name.as_str(), c.module.is_some()))
+        .collect();
+    assert_eq!(
+        collections,
+        vec![
+            (Some("EL-9"), "Example Linux 9", false),
+            (Some("EL-8-nginx-1.20"), "Example Linux 8 AppStream", true),
+            (None, "", false),
+        ]
+    );
+    let filenames: Vec<_> = record.packages().map(|p| p.filename.as_str()).collect();
+    assert_eq!(
+        filenames,
+        vec![
+            "nginx-1.20.1-10.el9.x86_64.rpm",
+            "nginx-1.20.1-1.module_el8.x86_64.rpm",
+            "nginx-mod-mail-1.20.1-1.module_el8.x86_64.rpm",
+            "nginx-1.20.1-10.el9.aarch64.rpm",
+        ]
+    );
+
+    // the collections are written back exactly as they were read
+    let mut writer = UpdateinfoXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header()?;
+    writer.write_updaterecord(&record)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, MULTI_COLLECTION_UPDATEINFO);
+
+    Ok(())
+}
+
+static BUILT_UPDATEINFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<updates>
+  <update status="final" from="release-engineering@example.com" type="bugfix" version="1">
+    <id>HOTFIX-2023:0001</id>
+    <title>rpm-empty hotfix</title>
+    <issued>2023-05-02</issued>
+    <updated>2023-05-02 10:30:00</updated>
+    <copyright></copyright>
+    <release></release>
+    <severity>moderate</severity>
+    <summary></summary>
+    <description>Fixes nothing at all</description>
+    <solution></solution>
+    <references>
+      <reference href="https://issues.example.com/1234" id="1234" type="other" title="rpm-empty is too empty"/>
+    </references>
+    <pkglist>
+      <collection short="HF">
+        <name>Hotfixes</name>
+        <package name="rpm-empty" version="0" release="0" epoch="0" arch="x86_64" src="rpm-empty-0-0.src.rpm">
+          <filename>rpm-empty-0-0.x86_64.rpm</filename>
+          <sum type="sha256">90fbba546300f507473547f33e229ee7bad94bbbe6e84b21d485e8e43b5f1132</sum>
+        </package>
+      </collection>
+    </pkglist>
+  </update>
+</updates>
+"#;
+
+#[test]
+fn test_update_record_builder() -> Result<(), MetadataError> {
+    let record = UpdateRecord::builder()
+        .id("HOTFIX-2023:0001")
+        .title("rpm-empty hotfix")
+        .from("release-engineering@example.com")
+        .update_type("bugfix")
+        .severity("moderate")
+        .issued_date("2023-05-02")
+        .updated_date("2023-05-02 10:30:00")
+        .description("Fixes nothing at all")
+        .reference(UpdateReference {
+            href: "https://issues.example.com/1234".to_owned(),
+            id: "1234".to_owned(),
+            title: "rpm-empty is too empty".to_owned(),
+            reftype: "other".to_owned(),
+        })
+        .packages("HF", "Hotfixes", [&*common::RPM_EMPTY])
+        .build()?;
+    assert_eq!(record.status, "final");
+    assert_eq!(record.version, "1");
+
+    let mut writer = UpdateinfoXml::new_writer(utils::create_xml_writer(Cursor::new(Vec::new())));
+    writer.write_header()?;
+    writer.write_updaterecord(&record)?;
+    writer.finish()?;
+    let buffer = writer.into_inner().into_inner();
+    assert_eq!(std::str::from_utf8(&buffer)?, BUILT_UPDATEINFO);
+
+    // the updated date defaults to the issued date
+    let record = UpdateRecordBuilder::new()
+        .id("HOTFIX-2023:0002")
+        .title("another hotfix")
+        .from("release-engineering@example.com")
+        .update_type("security")
+        .issued_date("1683023400")
+        .build()?;
+    assert_eq!(record.updated_date.as_deref(), Some("1683023400"));
+
+    let error = UpdateRecordBuilder::new()
+        .id("HOTFIX-2023:0003")
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Missing metadata fields: title, from, update_type, issued_date"
+    );
+
+    let builder = UpdateRecordBuilder::new()
+        .id("HOTFIX-2023:0003")
+        .title("a broken hotfix")
+        .from("release-engineering@example.com")
+        .update_type("bugfix");
+    for (builder, message) in [
+        (
+            builder.clone().issued_date("05/02/2023"),
+            "\"05/02/2023\" is not a valid issued_date",
+        ),
+        (
+            builder
+                .clone()
+                .issued_date("2023-05-02")
+                .updated_date("2023-13-02 10:30:00"),
+            "\"2023-13-02 10:30:00\" is not a valid updated_date",
+        ),
+        (
+            builder.issued_date("2023-05-02").severity("Urgent"),
+            "\"Urgent\" is not a valid severity",
+        ),
+    ] {
+        assert_eq!(builder.build().unwrap_err().to_string(), message);
+    }
+
+    Ok(())
+}
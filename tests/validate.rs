@@ -0,0 +1,145 @@
+// Copyright (c) 2022 Daniel Alley
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pretty_assertions::assert_eq;
+use rpmrepo_metadata::{
+    Changelog, CompressionType, MetadataError, Package, Repository, RepositoryOptions,
+    RepositoryReader, RepositoryWriter, Severity, ValidationCheck, EVR,
+};
+use tempdir::TempDir;
+mod common;
+
+fn write_repo(path: &std::path::Path, packages: &[&Package]) -> Result<(), MetadataError> {
+    let options = RepositoryOptions::default().metadata_compression_type(CompressionType::None);
+    let mut writer = RepositoryWriter::new_with_options(path, packages.len(), options)?;
+    for package in packages {
+        writer.add_package(package)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn checks(report: &rpmrepo_metadata::ValidationReport) -> Vec<(Severity, ValidationCheck)> {
+    report
+        .issues
+        .iter()
+        .map(|issue| (issue.severity, issue.check))
+        .collect()
+}
+
+#[test]
+fn test_validate_valid_repo() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_validate_valid_repo")?;
+    write_repo(
+        tmp_dir.path(),
+        &[&common::COMPLEX_PACKAGE, &common::RPM_EMPTY],
+    )?;
+
+    let report = RepositoryReader::new_from_directory(tmp_dir.path())?.validate()?;
+    assert!(report.is_valid());
+    assert!(report.is_empty(), "{}", report);
+    assert_eq!(report.to_string(), "0 errors, 0 warnings, 0 info");
+
+    let repo = Repository::load_from_directory(tmp_dir.path())?;
+    assert!(repo.validate()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_broken_repo() -> Result<(), MetadataError> {
+    let tmp_dir = TempDir::new("test_validate_broken_repo")?;
+    write_repo(
+        tmp_dir.path(),
+        &[&common::COMPLEX_PACKAGE, &common::RPM_EMPTY],
+    )?;
+    let reader = RepositoryReader::new_from_directory(tmp_dir.path())?;
+    let path_of = |metadata| {
+        tmp_dir
+            .path()
+            .join(&reader.repomd().get_record(metadata).unwrap().location_href)
+    };
+
+    // rpm-empty is missing from filelists.xml, whose header still counts it
+    let filelists = std::fs::read_to_string(path_of("filelists"))?;
+    let start = filelists.find("<package pkgid=\"90fbba").unwrap();
+    let end = start + filelists[start..].find("</package>").unwrap() + "</package>".len();
+    std::fs::write(
+        path_of("filelists"),
+        format!("{}{}", &filelists[..start], &filelists[end..]),
+    )?;
+    // other.xml is gone
+    std::fs::remove_file(path_of("other"))?;
+    // a leftover from an earlier version of the repository
+    std::fs::write(tmp_dir.path().join("repodata/old-primary.xml"), "")?;
+
+    let report = reader.validate()?;
+    assert!(!report.is_valid());
+    assert_eq!(
+        checks(&report),
+        vec![
+            (Severity::Error, ValidationCheck::DanglingRecord),
+            (Severity::Info, ValidationCheck::UnreferencedFile),
+            (Severity::Error, ValidationCheck::CountMismatch),
+            (Severity::Error, ValidationCheck::CountMismatch),
+            (Severity::Error, ValidationCheck::MissingPackage),
+        ]
+    );
+    let missing = report.errors().last().unwrap();
+    assert_eq!(missing.metadata.as_deref(), Some("filelists"));
+    assert_eq!(missing.entry.as_deref(), Some("rpm-empty-0:0-0.x86_64"));
+    assert_eq!(report.errors().count(), 4);
+    assert_eq!(report.infos().count(), 1);
+    assert!(report.to_string().contains(
+        "info[unreferenced-file]: repodata/old-primary.xml isn't referenced by repomd.xml\n"
+    ));
+    assert!(report.to_string().ends_with("4 errors, 0 warnings, 1 info"));
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_packages() {
+    let mut repo = Repository::new();
+    let mut package = common::COMPLEX_PACKAGE.clone();
+    package.set_evr(EVR::new("x", "1.0-2", "3"));
+    package.set_time_build(0);
+    package.set_changelogs(vec![Changelog {
+        author: "Lucille Bluth".to_owned(),
+        timestamp: i64::MAX as u64,
+        description: "- From the future".to_owned(),
+    }]);
+    repo.packages_mut()
+        .insert(package.pkgid().to_owned(), package.clone());
+    repo.packages_mut().insert(
+        common::RPM_EMPTY.pkgid().to_owned(),
+        common::RPM_EMPTY.clone(),
+    );
+
+    let report = repo.validate().unwrap();
+    assert_eq!(
+        checks(&report),
+        vec![
+            (Severity::Error, ValidationCheck::InvalidEvr),
+            (Severity::Warning, ValidationCheck::TimestampOutOfRange),
+            (Severity::Warning, ValidationCheck::TimestampOutOfRange),
+        ]
+    );
+    assert!(report
+        .issues
+        .iter()
+        .all(|issue| issue.entry.as_deref() == Some(package.nevra().as_str())));
+    assert_eq!(
+        report.issues[0].message,
+        "invalid EVR \"x:1.0-2-3\": the epoch isn't a number"
+    );
+    assert_eq!(report.issues[1].message, "the build time isn't set");
+
+    assert!(EVR::new("", "1.0", "1.fc38").validate().is_ok());
+    assert!(EVR::new("2", "1.0~rc1^git", "1.fc38").validate().is_ok());
+    assert!(EVR::new("0", "1 0", "1").validate().is_err());
+    assert!(EVR::new("0", "1.0", "").validate().is_err());
+}